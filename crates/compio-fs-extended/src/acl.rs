@@ -0,0 +1,246 @@
+//! POSIX ACL operations using the `system.posix_acl_*` extended attributes
+//!
+//! Linux stores a file's POSIX ACLs as two extended attributes in a small
+//! binary format:
+//!
+//! - `system.posix_acl_access`: the **access ACL**, checked on every access
+//!   to the object itself. Only present when the ACL has entries beyond the
+//!   owner/group/other triple that the mode bits already express.
+//! - `system.posix_acl_default`: the **default ACL** of a directory,
+//!   inherited by entries created inside it.
+//!
+//! Copying these attributes verbatim copies the ACLs exactly, without
+//! linking libacl. Named user and group entries refer to numeric IDs, so
+//! (like `rsync -A`) a copy between systems with different user databases
+//! grants access to whoever has those IDs there.
+//!
+//! # Ordering
+//!
+//! `fchmod` rewrites the mask entry of an access ACL, so the access ACL must
+//! be copied **after** permissions. A directory's default ACL is best copied
+//! **before** any children are created, so they inherit it directly.
+//!
+//! ## Platform Support
+//!
+//! **Linux**: Supported
+//!
+//! **Other platforms**: Copying is a no-op (their ACL models differ)
+
+#[cfg(target_os = "linux")]
+use crate::error::acl_error;
+use crate::error::Result;
+use std::os::unix::io::RawFd;
+
+/// Which POSIX ACL of a file or directory to operate on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclKind {
+    /// Access ACL (permissions of the object itself)
+    Access,
+    /// Default ACL (inherited by children created in a directory)
+    Default,
+}
+
+impl AclKind {
+    /// Name of the xattr that stores this ACL on Linux
+    #[must_use]
+    pub const fn xattr_name(self) -> &'static std::ffi::CStr {
+        match self {
+            Self::Access => c"system.posix_acl_access",
+            Self::Default => c"system.posix_acl_default",
+        }
+    }
+}
+
+/// Whether `name` is one of the xattrs holding a POSIX ACL
+///
+/// Generic xattr copies should skip these: ACLs are copied (or not) by
+/// `--acls`, in the order described in the module documentation.
+#[must_use]
+pub fn is_acl_xattr(name: &str) -> bool {
+    name.starts_with("system.posix_acl_")
+}
+
+/// Read one POSIX ACL in its xattr encoding
+///
+/// Returns `None` if the object has no such ACL, or the filesystem does not
+/// support ACLs.
+///
+/// # Errors
+///
+/// This function will return an error if the attribute cannot be read.
+#[cfg(target_os = "linux")]
+pub fn get_acl_fd(fd: RawFd, kind: AclKind) -> Result<Option<Vec<u8>>> {
+    let name = kind.xattr_name();
+    loop {
+        // SAFETY: size query with a null buffer; name is NUL-terminated
+        let size = unsafe { libc::fgetxattr(fd, name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return absent_or_error("read", kind).map(|()| None);
+        }
+
+        let mut value = vec![0u8; size.unsigned_abs()];
+        // SAFETY: value has room for `value.len()` bytes
+        let read =
+            unsafe { libc::fgetxattr(fd, name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if read >= 0 {
+            value.truncate(read.unsigned_abs());
+            return Ok(Some(value));
+        }
+        // The ACL grew between the two calls; size it again
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+            return absent_or_error("read", kind).map(|()| None);
+        }
+    }
+}
+
+/// Set one POSIX ACL from its xattr encoding, or remove it (`None`)
+///
+/// Removing an ACL the object does not have is not an error.
+///
+/// # Errors
+///
+/// This function will return an error if the attribute cannot be written or
+/// removed, including when the filesystem does not support ACLs but `value`
+/// is `Some`.
+#[cfg(target_os = "linux")]
+pub fn set_acl_fd(fd: RawFd, kind: AclKind, value: Option<&[u8]>) -> Result<()> {
+    let name = kind.xattr_name();
+    match value {
+        Some(value) => {
+            // SAFETY: value holds `value.len()` initialized bytes
+            let ret = unsafe {
+                libc::fsetxattr(fd, name.as_ptr(), value.as_ptr().cast(), value.len(), 0)
+            };
+            if ret != 0 {
+                return Err(last_acl_error("write", kind));
+            }
+            Ok(())
+        }
+        None => {
+            // SAFETY: name is NUL-terminated
+            if unsafe { libc::fremovexattr(fd, name.as_ptr()) } != 0 {
+                return absent_or_error("remove", kind);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Copy one POSIX ACL between file descriptors
+///
+/// If the source has no such ACL, any ACL the destination inherited (e.g. a
+/// default ACL from its new parent) is removed so the copy matches the
+/// source. Filesystems without ACL support are treated as having no ACLs.
+///
+/// # Errors
+///
+/// This function will return an error if the ACL cannot be read from the
+/// source or written to the destination.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use compio_fs_extended::acl::{copy_acl_fd, AclKind};
+/// use std::os::unix::io::AsRawFd;
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let src = std::fs::File::open("source.txt")?;
+/// let dst = std::fs::File::open("destination.txt")?;
+/// copy_acl_fd(src.as_raw_fd(), dst.as_raw_fd(), AclKind::Access)?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn copy_acl_fd(src_fd: RawFd, dst_fd: RawFd, kind: AclKind) -> Result<()> {
+    let value = get_acl_fd(src_fd, kind)?;
+    set_acl_fd(dst_fd, kind, value.as_deref())
+}
+
+/// Copy one POSIX ACL between file descriptors (no-op on this platform)
+///
+/// # Errors
+///
+/// Never fails on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn copy_acl_fd(_src_fd: RawFd, _dst_fd: RawFd, _kind: AclKind) -> Result<()> {
+    Ok(())
+}
+
+/// `Ok` if the last error means "no such ACL" (or no ACL support)
+#[cfg(target_os = "linux")]
+fn absent_or_error(op: &str, kind: AclKind) -> Result<()> {
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ENODATA | libc::EOPNOTSUPP) => Ok(()),
+        _ => Err(last_acl_error(op, kind)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn last_acl_error(op: &str, kind: AclKind) -> crate::error::ExtendedError {
+    acl_error(&format!(
+        "failed to {op} {}: {}",
+        kind.xattr_name().to_string_lossy(),
+        std::io::Error::last_os_error()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use std::os::unix::io::AsRawFd;
+    #[cfg(target_os = "linux")]
+    use tempfile::TempDir;
+
+    /// Encode a POSIX ACL with a named user entry (and the mask it requires)
+    #[cfg(target_os = "linux")]
+    fn acl_with_named_user(uid: u32) -> Vec<u8> {
+        const ACL_UNDEFINED_ID: u32 = u32::MAX;
+        let mut value = 2u32.to_le_bytes().to_vec(); // POSIX_ACL_XATTR_VERSION
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, ACL_UNDEFINED_ID), // ACL_USER_OBJ
+            (0x02, 4, uid),                    // ACL_USER
+            (0x04, 4, ACL_UNDEFINED_ID),       // ACL_GROUP_OBJ
+            (0x10, 4, ACL_UNDEFINED_ID),       // ACL_MASK
+            (0x20, 0, ACL_UNDEFINED_ID),       // ACL_OTHER
+        ] {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn test_acl_xattr_names() {
+        assert!(is_acl_xattr("system.posix_acl_access"));
+        assert!(is_acl_xattr("system.posix_acl_default"));
+        assert!(!is_acl_xattr("user.comment"));
+        assert!(!is_acl_xattr("security.selinux"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_copy_access_acl() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = std::fs::File::create(temp_dir.path().join("src")).unwrap();
+        let dst = std::fs::File::create(temp_dir.path().join("dst")).unwrap();
+
+        let acl = acl_with_named_user(4242);
+        if let Err(e) = set_acl_fd(src.as_raw_fd(), AclKind::Access, Some(&acl)) {
+            eprintln!("Skipping: filesystem does not support POSIX ACLs ({e})");
+            return;
+        }
+
+        copy_acl_fd(src.as_raw_fd(), dst.as_raw_fd(), AclKind::Access).unwrap();
+        assert_eq!(
+            get_acl_fd(dst.as_raw_fd(), AclKind::Access).unwrap(),
+            Some(acl)
+        );
+
+        // A source without an ACL removes the destination's
+        let plain = std::fs::File::create(temp_dir.path().join("plain")).unwrap();
+        copy_acl_fd(plain.as_raw_fd(), dst.as_raw_fd(), AclKind::Access).unwrap();
+        assert_eq!(get_acl_fd(dst.as_raw_fd(), AclKind::Access).unwrap(), None);
+    }
+}
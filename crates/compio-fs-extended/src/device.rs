@@ -11,6 +11,14 @@
 //! - **Block Devices**: Hard drives, SSDs, etc.
 //! - **Sockets**: Network and Unix domain sockets
 //!
+//! # Recreating Special Files
+//!
+//! [`DirectoryFd::mknodat`](crate::DirectoryFd::mknodat) creates any of these
+//! relative to a directory file descriptor (TOCTOU-safe), given the source's
+//! `st_mode` and `st_rdev`. Since `mknod` applies the umask, permissions are
+//! set afterwards with [`DirectoryFd::fchmodat`](crate::DirectoryFd::fchmodat).
+//! Devices need `CAP_MKNOD`; fifos and sockets do not.
+//!
 //! # Usage
 //!
 //! ```rust,no_run
@@ -29,11 +37,17 @@
 //! # }
 //! ```
 
+#[cfg(unix)]
+use crate::directory::DirectoryFd;
 use crate::error::{ExtendedError, Result};
 #[cfg(unix)]
 use nix::sys::stat;
 #[cfg(unix)]
 use nix::unistd;
+#[cfg(unix)]
+use std::ffi::{CString, OsStr};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
 /// Create a special file at the given path using async spawn
@@ -216,6 +230,83 @@ pub async fn create_socket_at_path(path: &Path, mode: u32) -> Result<()> {
 
 // Windows: create_socket_at_path not defined - compile-time error
 
+/// Create a special file relative to a directory - Unix (`mknodat(2)`)
+///
+/// `mode` carries the file type (`S_IFIFO`, `S_IFSOCK`, `S_IFCHR` or
+/// `S_IFBLK`) and permission bits; `rdev` is the device number of a device
+/// (`makedev` encoding) and ignored for other types.
+#[cfg(unix)]
+pub(crate) async fn mknodat_impl(
+    dir: &DirectoryFd,
+    name: &OsStr,
+    mode: u32,
+    rdev: u64,
+) -> Result<()> {
+    let c_name = CString::new(name.as_bytes())
+        .map_err(|e| device_error(&format!("invalid file name: {e}")))?;
+    let dir_fd = dir.as_raw_fd();
+
+    compio::runtime::spawn_blocking(move || {
+        // SAFETY: dir_fd stays open while the DirectoryFd is borrowed; the
+        // name is NUL-terminated
+        // Note: mode_t is u16 on macOS, u32 on Linux - cast to platform's type
+        let ret = unsafe {
+            libc::mknodat(
+                dir_fd,
+                c_name.as_ptr(),
+                mode as libc::mode_t,
+                rdev as libc::dev_t,
+            )
+        };
+        if ret != 0 {
+            return Err(device_error(&format!(
+                "mknodat failed: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| device_error(&format!("spawn_blocking failed: {e:?}")))?
+}
+
+/// Set the permissions of a special file relative to a directory - Unix
+/// (`fchmodat(2)`)
+///
+/// Unlike `lfchmodat`, this follows the name, so it must not be used on
+/// symlinks. Special files cannot be opened for `fchmod` without side
+/// effects (opening a fifo blocks, a device may be acted on).
+#[cfg(unix)]
+pub(crate) async fn fchmodat_impl(dir: &DirectoryFd, name: &OsStr, mode: u32) -> Result<()> {
+    let c_name = CString::new(name.as_bytes())
+        .map_err(|e| device_error(&format!("invalid file name: {e}")))?;
+    let dir_fd = dir.as_raw_fd();
+
+    let operation = move || {
+        // SAFETY: as in mknodat_impl
+        let ret = unsafe { libc::fchmodat(dir_fd, c_name.as_ptr(), mode as libc::mode_t, 0) };
+        if ret != 0 {
+            return Err(device_error(&format!(
+                "fchmodat failed: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    };
+
+    #[cfg(feature = "cheap_calls_sync")]
+    {
+        operation()
+    }
+
+    #[cfg(not(feature = "cheap_calls_sync"))]
+    {
+        compio::runtime::spawn_blocking(operation)
+            .await
+            .map_err(|e| device_error(&format!("spawn_blocking failed: {e:?}")))?
+    }
+}
+
 /// Error helper for device operations
 fn device_error(msg: &str) -> ExtendedError {
     crate::error::device_error(msg)
@@ -306,6 +397,32 @@ mod tests {
         }
     }
 
+    #[compio::test]
+    #[cfg(unix)]
+    #[allow(clippy::unnecessary_cast)] // S_IFIFO is u16 on macOS
+    async fn test_mknodat_fifo_with_permissions() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let temp_dir = TempDir::new().unwrap();
+        let dir = DirectoryFd::open(temp_dir.path()).await.unwrap();
+        let name = OsStr::new("fifo");
+
+        dir.mknodat(name, libc::S_IFIFO as u32 | 0o600, 0)
+            .await
+            .unwrap();
+        dir.fchmodat(name, 0o4640).await.unwrap();
+
+        let metadata = std::fs::symlink_metadata(temp_dir.path().join("fifo")).unwrap();
+        assert!(metadata.file_type().is_fifo());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o4640);
+
+        // An existing name is not replaced
+        assert!(dir
+            .mknodat(name, libc::S_IFIFO as u32 | 0o600, 0)
+            .await
+            .is_err());
+    }
+
     /// **Proof that mknod(S_IFSOCK) works on Linux**
     ///
     /// This test definitively proves that mknod() CAN create socket inodes on Linux.
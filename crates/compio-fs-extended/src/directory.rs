@@ -279,7 +279,41 @@ impl DirectoryFd {
         &self,
         pathname: &std::ffi::OsStr,
     ) -> crate::Result<crate::FileMetadata> {
-        crate::metadata::statx_impl(self, pathname).await
+        crate::metadata::statx_impl(self, pathname, crate::metadata::StatxMask::BASIC_STATS).await
+    }
+
+    /// Get metadata for a child, requesting only the fields in `mask`
+    ///
+    /// Like `statx_full`, but lets callers that need only some fields (e.g.
+    /// type and size when permissions and times are not preserved) avoid the
+    /// cost of the others. Fields outside `mask` may be zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the statx operation fails
+    #[cfg(unix)]
+    pub async fn statx_with_mask(
+        &self,
+        pathname: &std::ffi::OsStr,
+        mask: crate::metadata::StatxMask,
+    ) -> crate::Result<crate::FileMetadata> {
+        crate::metadata::statx_impl(self, pathname, mask).await
+    }
+
+    /// Get metadata for several children in one submission
+    ///
+    /// Like `statx_full` for each of `pathnames`, but on Linux all the
+    /// `IORING_OP_STATX` requests go to the kernel in a single io_uring
+    /// submission, so listing a large directory does not pay one submission
+    /// per entry. Results are in the order of `pathnames`, and each entry
+    /// succeeds or fails on its own.
+    #[cfg(unix)]
+    pub async fn statx_batch(
+        &self,
+        pathnames: &[std::ffi::OsString],
+    ) -> Vec<crate::Result<crate::FileMetadata>> {
+        crate::metadata::statx_batch_impl(self, pathnames, crate::metadata::StatxMask::BASIC_STATS)
+            .await
     }
 
     /// Open a subdirectory relative to this DirectoryFd (TOCTOU-safe)
@@ -523,7 +557,9 @@ impl DirectoryFd {
         &self,
         pathname: &std::ffi::OsStr,
     ) -> Result<(std::time::SystemTime, std::time::SystemTime)> {
-        let full = crate::metadata::statx_impl(self, pathname).await?;
+        use crate::metadata::StatxMask;
+        let full = crate::metadata::statx_impl(self, pathname, StatxMask::ATIME | StatxMask::MTIME)
+            .await?;
         Ok((full.accessed, full.modified))
     }
 
@@ -586,6 +622,40 @@ impl DirectoryFd {
         crate::metadata::lfchownat_impl(self, pathname, uid, gid).await
     }
 
+    /// Create a fifo, socket or device file for a child
+    ///
+    /// Uses `mknodat(2)` with directory FD and relative path (TOCTOU-safe).
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the new file (relative to this directory)
+    /// * `mode` - File type (`S_IFIFO`, `S_IFSOCK`, `S_IFCHR`, `S_IFBLK`) and
+    ///   permissions; the umask applies
+    /// * `rdev` - Device number for device files (`makedev` encoding), as in
+    ///   `FileMetadata::rdev`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name already exists, or permission is denied
+    /// (devices require `CAP_MKNOD`).
+    #[cfg(unix)]
+    pub async fn mknodat(&self, name: &std::ffi::OsStr, mode: u32, rdev: u64) -> Result<()> {
+        crate::device::mknodat_impl(self, name, mode, rdev).await
+    }
+
+    /// Change the permissions of a child that is not a symlink
+    ///
+    /// Uses `fchmodat(2)`, for entries that cannot be opened to `fchmod`
+    /// them, such as fifos and device files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation fails.
+    #[cfg(unix)]
+    pub async fn fchmodat(&self, name: &std::ffi::OsStr, mode: u32) -> Result<()> {
+        crate::device::fchmodat_impl(self, name, mode).await
+    }
+
     // ========================================================================
     // Symlink operations on children (relative paths) - Unix only
     // ========================================================================
@@ -622,32 +692,355 @@ impl DirectoryFd {
     pub async fn readlinkat(&self, link_name: &str) -> Result<std::path::PathBuf> {
         crate::symlink::readlinkat_impl(self, link_name).await
     }
+
+    // ========================================================================
+    // Xattr operations on children (relative names) - Linux only
+    // ========================================================================
+
+    /// Get an extended attribute of a child, without following symlinks
+    ///
+    /// The child is opened with `O_PATH | O_NOFOLLOW` (no data access, works
+    /// on symlinks) and the attribute read through `/proc/self/fd`, so the
+    /// operation is TOCTOU-safe like the other `*at` calls and needs no full
+    /// open of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the child (relative to this directory)
+    /// * `attr` - Name of the extended attribute
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child doesn't exist, the attribute doesn't
+    /// exist or permission is denied.
+    #[cfg(target_os = "linux")]
+    pub async fn lget_xattr_at(&self, name: &std::ffi::OsStr, attr: &str) -> Result<Vec<u8>> {
+        crate::xattr::lget_xattr_at_impl(self, name, attr).await
+    }
+
+    /// Set an extended attribute of a child, without following symlinks
+    ///
+    /// See [`DirectoryFd::lget_xattr_at`] for how the child is addressed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child doesn't exist, permission is denied or
+    /// the filesystem rejects the attribute (e.g. `user.*` on a symlink).
+    #[cfg(target_os = "linux")]
+    pub async fn lset_xattr_at(
+        &self,
+        name: &std::ffi::OsStr,
+        attr: &str,
+        value: &[u8],
+    ) -> Result<()> {
+        crate::xattr::lset_xattr_at_impl(self, name, attr, value).await
+    }
+
+    /// List the extended attributes of a child, without following symlinks
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child doesn't exist or permission is denied.
+    #[cfg(target_os = "linux")]
+    pub async fn llist_xattr_at(&self, name: &std::ffi::OsStr) -> Result<Vec<String>> {
+        crate::xattr::llist_xattr_at_impl(self, name).await
+    }
+
+    /// Read every extended attribute of a child with a single `O_PATH` open
+    ///
+    /// Each attribute comes with its own result, so one unreadable attribute
+    /// does not hide the others.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child doesn't exist or its attributes cannot
+    /// be listed.
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::type_complexity)]
+    pub async fn lget_all_xattrs_at(
+        &self,
+        name: &std::ffi::OsStr,
+    ) -> Result<Vec<(String, Result<Vec<u8>>)>> {
+        crate::xattr::lget_all_xattrs_at_impl(self, name).await
+    }
+
+    /// Read the entries of this directory through its file descriptor
+    ///
+    /// Unlike [`read_dir`], which opens the directory again by path, this
+    /// lists exactly the directory this `DirectoryFd` pinned: renaming it or
+    /// swapping a symlink into its path after it was opened cannot redirect
+    /// the listing. The entries come from `getdents64(2)` on a descriptor
+    /// opened from this one (`openat(fd, ".")`), so clones listing the same
+    /// directory do not share a read offset.
+    ///
+    /// Entry types come from `d_type`. Filesystems that leave it unknown are
+    /// asked with `fstatat(AT_SYMLINK_NOFOLLOW)` relative to the same
+    /// descriptor; entries removed in the meantime are left out.
+    ///
+    /// `.` and `..` are not returned; the order is the directory's own.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory cannot be read or
+    /// the type of an entry cannot be determined.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use compio_fs_extended::DirectoryFd;
+    /// use std::path::Path;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let dir = DirectoryFd::open(Path::new("/tmp")).await?;
+    /// for entry in dir.read_dir_at().await? {
+    ///     let kind = if entry.file_type().is_dir() { "dir" } else { "other" };
+    ///     println!("{:?} ({kind})", entry.file_name());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub async fn read_dir_at(&self) -> Result<Vec<DirEntry>> {
+        let dir_fd = self.as_raw_fd();
+        compio::runtime::spawn_blocking(move || read_dir_fd(dir_fd))
+            .await
+            .map_err(|e| {
+                crate::error::ExtendedError::SpawnJoin(format!("spawn_blocking failed: {:?}", e))
+            })?
+    }
 }
 
-/// Read directory entries
-///
-/// This function provides a consistent API for directory reading that abstracts
-/// whether the operation is blocking or uses io_uring.
-///
-/// CURRENT STATUS: Uses std::fs::read_dir (synchronous) because:
-/// - Linux kernel 6.14 does NOT have IORING_OP_GETDENTS64
-/// - Patches proposed in 2021 were never merged
-/// - See: https://lwn.net/Articles/878873/
-///
-/// FUTURE: If kernel adds GETDENTS64 support, this function can be updated
-/// to use io_uring without changing the calling code.
+/// Type of a directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryType {
+    /// Regular file
+    File,
+    /// Directory
+    Directory,
+    /// Symbolic link
+    Symlink,
+    /// Block device
+    BlockDevice,
+    /// Character device
+    CharDevice,
+    /// Named pipe
+    Fifo,
+    /// Unix domain socket
+    Socket,
+}
+
+impl EntryType {
+    /// Whether this is a directory
+    #[must_use]
+    pub const fn is_dir(self) -> bool {
+        matches!(self, Self::Directory)
+    }
+
+    /// Whether this is a regular file
+    #[must_use]
+    pub const fn is_file(self) -> bool {
+        matches!(self, Self::File)
+    }
+
+    /// Whether this is a symbolic link
+    #[must_use]
+    pub const fn is_symlink(self) -> bool {
+        matches!(self, Self::Symlink)
+    }
+
+    /// Type from a `d_type` value (`None` for `DT_UNKNOWN`)
+    #[cfg(unix)]
+    const fn from_d_type(d_type: u8) -> Option<Self> {
+        match d_type {
+            libc::DT_REG => Some(Self::File),
+            libc::DT_DIR => Some(Self::Directory),
+            libc::DT_LNK => Some(Self::Symlink),
+            libc::DT_BLK => Some(Self::BlockDevice),
+            libc::DT_CHR => Some(Self::CharDevice),
+            libc::DT_FIFO => Some(Self::Fifo),
+            libc::DT_SOCK => Some(Self::Socket),
+            _ => None,
+        }
+    }
+
+    /// Type from the `S_IFMT` bits of a mode
+    #[cfg(unix)]
+    const fn from_mode(mode: libc::mode_t) -> Option<Self> {
+        match mode & libc::S_IFMT {
+            libc::S_IFREG => Some(Self::File),
+            libc::S_IFDIR => Some(Self::Directory),
+            libc::S_IFLNK => Some(Self::Symlink),
+            libc::S_IFBLK => Some(Self::BlockDevice),
+            libc::S_IFCHR => Some(Self::CharDevice),
+            libc::S_IFIFO => Some(Self::Fifo),
+            libc::S_IFSOCK => Some(Self::Socket),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a directory listed with [`DirectoryFd::read_dir_at`]
 ///
-/// # Arguments
+/// The name is relative to the listed directory; pass it to the `*_at`
+/// methods of the same `DirectoryFd`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name within the directory
+    name: std::ffi::OsString,
+    /// Inode number
+    ino: u64,
+    /// Type, as of the listing
+    file_type: EntryType,
+}
+
+impl DirEntry {
+    /// Name of the entry within its directory
+    #[must_use]
+    pub fn file_name(&self) -> &std::ffi::OsStr {
+        &self.name
+    }
+
+    /// Inode number of the entry
+    #[must_use]
+    pub const fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Type of the entry (symlinks are not followed)
+    #[must_use]
+    pub const fn file_type(&self) -> EntryType {
+        self.file_type
+    }
+}
+
+/// List the directory open as `dir_fd` with `getdents64(2)`
+#[cfg(target_os = "linux")]
+fn read_dir_fd(dir_fd: std::os::unix::io::RawFd) -> Result<Vec<DirEntry>> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+
+    // A descriptor of our own, so the read offset is not shared with clones
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    // SAFETY: dir_fd is valid for the duration of this call; "." is NUL-terminated
+    let fd = unsafe { libc::openat(dir_fd, c".".as_ptr(), flags) };
+    if fd < 0 {
+        let err = std::io::Error::last_os_error();
+        return Err(directory_error(&format!("openat(\".\") failed: {err}")));
+    }
+    // SAFETY: We just opened this fd and own it
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut entries = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        // SAFETY: buffer has room for `buffer.len()` bytes
+        let read = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                fd.as_raw_fd(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(directory_error(&format!("getdents64 failed: {err}")));
+        }
+        if read == 0 {
+            return Ok(entries);
+        }
+        let records = &buffer[..read.unsigned_abs() as usize];
+        for (ino, d_type, name) in dirents(records) {
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let file_type = match EntryType::from_d_type(d_type) {
+                Some(file_type) => file_type,
+                None => match entry_type_at(fd.as_raw_fd(), name)? {
+                    Some(file_type) => file_type,
+                    None => continue,
+                },
+            };
+            entries.push(DirEntry {
+                name: std::ffi::OsStr::from_bytes(name).to_os_string(),
+                ino,
+                file_type,
+            });
+        }
+    }
+}
+
+/// The `(d_ino, d_type, d_name)` of each `linux_dirent64` record in `records`
+#[cfg(target_os = "linux")]
+fn dirents(records: &[u8]) -> impl Iterator<Item = (u64, u8, &[u8])> {
+    // struct linux_dirent64 { u64 d_ino; i64 d_off; u16 d_reclen; u8 d_type; char d_name[]; }
+    const NAME: usize = 19;
+    let mut rest = records;
+    std::iter::from_fn(move || {
+        let ino = u64::from_ne_bytes(*rest.first_chunk::<8>()?);
+        let reclen = usize::from(u16::from_ne_bytes([*rest.get(16)?, *rest.get(17)?]));
+        let d_type = *rest.get(18)?;
+        let name = rest.get(NAME..reclen)?;
+        let name = &name[..name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(name.len())];
+        rest = &rest[reclen..];
+        Some((ino, d_type, name))
+    })
+}
+
+/// Type of the entry `name` of the directory `dir_fd`, without following
+/// symlinks; `None` if it no longer exists
+#[cfg(target_os = "linux")]
+fn entry_type_at(dir_fd: std::os::unix::io::RawFd, name: &[u8]) -> Result<Option<EntryType>> {
+    let name_cstr = std::ffi::CString::new(name)
+        .map_err(|e| directory_error(&format!("Invalid entry name: {e}")))?;
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: name is NUL-terminated and stat has room for a struct stat
+    let ret = unsafe {
+        libc::fstatat(
+            dir_fd,
+            name_cstr.as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if ret != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::NotFound {
+            return Ok(None);
+        }
+        return Err(directory_error(&format!("fstatat failed: {err}")));
+    }
+    // SAFETY: fstatat succeeded and filled in stat
+    let stat = unsafe { stat.assume_init() };
+    Ok(EntryType::from_mode(stat.st_mode))
+}
+
+/// Read the entries of the directory at `path`
 ///
-/// * `path` - Directory path to read
+/// The directory is opened and listed with `getdents64(2)` on a blocking
+/// thread, the same way as [`DirectoryFd::read_dir_at`], and the listing is
+/// collected there: iterating the result never makes a syscall on the
+/// runtime thread, however large the directory.
 ///
-/// # Returns
+/// io_uring has no getdents operation to use instead: `IORING_OP_GETDENTS`
+/// was proposed (<https://lwn.net/Articles/878873/>) but never merged, so
+/// there is no opcode a kernel could report in its probe. Should one land,
+/// this function and [`DirectoryFd::read_dir_at`] are where it would go,
+/// with the blocking path as the fallback for older kernels.
 ///
-/// Iterator over directory entries
+/// Callers holding a [`DirectoryFd`] should use [`DirectoryFd::read_dir_at`]
+/// instead, which does not resolve the path again.
 ///
 /// # Errors
 ///
-/// Returns an error if the directory cannot be read
+/// Returns an error if the directory cannot be opened or read, or the type
+/// of an entry cannot be determined.
 ///
 /// # Example
 ///
@@ -656,30 +1049,85 @@ impl DirectoryFd {
 /// use std::path::Path;
 ///
 /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-/// let entries = read_dir(Path::new("/tmp")).await?;
-/// for entry in entries {
-///     let entry = entry?;
+/// for entry in read_dir(Path::new("/tmp")).await? {
 ///     println!("Entry: {:?}", entry.file_name());
 /// }
 /// # Ok(())
 /// # }
 /// ```
-pub async fn read_dir(path: &Path) -> Result<std::fs::ReadDir> {
-    // NOTE: Kernel limitation - must use synchronous std::fs::read_dir
-    // Wrapping in spawn to avoid blocking the async runtime
-    // This function exists to:
-    // 1. Provide a consistent API in compio-fs-extended
-    // 2. Allow future swap to io_uring if/when kernel adds GETDENTS64
-    // 3. Keep app code (src/directory.rs) abstracted from implementation details
+#[cfg(target_os = "linux")]
+pub async fn read_dir(path: &Path) -> Result<Vec<DirEntry>> {
     let path_owned = path.to_path_buf();
     compio::runtime::spawn_blocking(move || {
-        std::fs::read_dir(path_owned)
-            .map_err(|e| directory_error(&format!("Failed to read directory: {}", e)))
+        let dir = std::fs::File::open(&path_owned)
+            .map_err(|e| directory_error(&format!("Failed to open directory: {e}")))?;
+        read_dir_fd(dir.as_raw_fd())
     })
     .await
     .map_err(|e| directory_error(&format!("spawn_blocking failed: {:?}", e)))?
 }
 
+/// Read the entries of the directory at `path`
+///
+/// Without `getdents64(2)` the directory is listed with `std::fs::read_dir`,
+/// still on a blocking thread and collected there, so callers see the same
+/// entries as on Linux.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be opened or read, or the type
+/// of an entry cannot be determined.
+#[cfg(not(target_os = "linux"))]
+pub async fn read_dir(path: &Path) -> Result<Vec<DirEntry>> {
+    let path_owned = path.to_path_buf();
+    compio::runtime::spawn_blocking(move || read_dir_std(&path_owned))
+        .await
+        .map_err(|e| directory_error(&format!("spawn_blocking failed: {:?}", e)))?
+}
+
+/// List the directory at `path` with `std::fs::read_dir`
+#[cfg(any(not(target_os = "linux"), test))]
+fn read_dir_std(path: &Path) -> Result<Vec<DirEntry>> {
+    use std::os::unix::fs::{DirEntryExt, FileTypeExt};
+
+    let listing = std::fs::read_dir(path)
+        .map_err(|e| directory_error(&format!("Failed to open directory: {e}")))?;
+    listing
+        .map(|entry| {
+            let entry =
+                entry.map_err(|e| directory_error(&format!("Failed to read directory: {e}")))?;
+            let kind = entry
+                .file_type()
+                .map_err(|e| directory_error(&format!("Failed to get entry type: {e}")))?;
+            let file_type = if kind.is_file() {
+                EntryType::File
+            } else if kind.is_dir() {
+                EntryType::Directory
+            } else if kind.is_symlink() {
+                EntryType::Symlink
+            } else if kind.is_block_device() {
+                EntryType::BlockDevice
+            } else if kind.is_char_device() {
+                EntryType::CharDevice
+            } else if kind.is_fifo() {
+                EntryType::Fifo
+            } else if kind.is_socket() {
+                EntryType::Socket
+            } else {
+                return Err(directory_error(&format!(
+                    "Unknown type of entry {:?}",
+                    entry.file_name()
+                )));
+            };
+            Ok(DirEntry {
+                name: entry.file_name(),
+                ino: entry.ino(),
+                file_type,
+            })
+        })
+        .collect()
+}
+
 #[cfg(unix)]
 impl AsFd for DirectoryFd {
     fn as_fd(&self) -> BorrowedFd<'_> {
@@ -874,4 +1322,149 @@ mod tests {
             assert!(created_path.is_dir());
         }
     }
+
+    #[cfg(target_os = "linux")]
+    #[compio::test]
+    async fn test_read_dir_at_names_and_types() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let non_utf8 = std::ffi::OsStr::from_bytes(b"caf\xe9");
+        fs::write(temp_dir.path().join("file"), "test").unwrap();
+        fs::write(temp_dir.path().join(non_utf8), "test").unwrap();
+        fs::create_dir(temp_dir.path().join("subdir")).unwrap();
+        std::os::unix::fs::symlink("subdir", temp_dir.path().join("link")).unwrap();
+        for i in 0..2000 {
+            fs::write(temp_dir.path().join(format!("many-{i}")), "").unwrap();
+        }
+
+        let dir_fd = DirectoryFd::open(temp_dir.path()).await.unwrap();
+        let entries = dir_fd.read_dir_at().await.unwrap();
+        // Large directories take several getdents64 calls; . and .. are skipped
+        assert_eq!(entries.len(), 2004);
+
+        let mut named: Vec<_> = entries
+            .iter()
+            .filter(|entry| !entry.file_name().as_bytes().starts_with(b"many-"))
+            .map(|entry| (entry.file_name().to_os_string(), entry.file_type()))
+            .collect();
+        named.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            named,
+            [
+                (non_utf8.to_os_string(), EntryType::File),
+                ("file".into(), EntryType::File),
+                ("link".into(), EntryType::Symlink),
+                ("subdir".into(), EntryType::Directory),
+            ]
+        );
+        let file = entries
+            .iter()
+            .find(|entry| entry.file_name() == "file")
+            .unwrap();
+        let ino = fs::metadata(temp_dir.path().join("file")).unwrap().ino();
+        assert_eq!(file.ino(), ino);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[compio::test]
+    async fn test_read_dir_at_lists_the_pinned_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let original = temp_dir.path().join("dir");
+        fs::create_dir(&original).unwrap();
+        fs::write(original.join("kept"), "test").unwrap();
+        let dir_fd = DirectoryFd::open(&original).await.unwrap();
+
+        // The path now names a different directory
+        fs::rename(&original, temp_dir.path().join("moved")).unwrap();
+        fs::create_dir(&original).unwrap();
+        fs::write(original.join("swapped"), "test").unwrap();
+
+        // Listing twice (or from a clone) starts from the beginning each time
+        for dir in [&dir_fd, &dir_fd.clone()] {
+            let names: Vec<_> = dir
+                .read_dir_at()
+                .await
+                .unwrap()
+                .iter()
+                .map(|entry| entry.file_name().to_os_string())
+                .collect();
+            assert_eq!(names, ["kept"]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[compio::test]
+    async fn test_read_dir_std_matches_getdents() {
+        // The portable listing of other systems sees what getdents64 sees
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file"), "test").unwrap();
+        fs::create_dir(temp_dir.path().join("subdir")).unwrap();
+        std::os::unix::fs::symlink("subdir", temp_dir.path().join("link")).unwrap();
+        for i in 0..100 {
+            fs::write(temp_dir.path().join(format!("many-{i}")), "").unwrap();
+        }
+
+        let mut portable = read_dir_std(temp_dir.path()).unwrap();
+        let mut native = read_dir(temp_dir.path()).await.unwrap();
+        portable.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        native.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        assert_eq!(portable.len(), 103);
+        assert_eq!(portable, native);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[compio::test]
+    async fn test_read_dir_collects_off_the_runtime() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file"), "test").unwrap();
+        fs::create_dir(temp_dir.path().join("subdir")).unwrap();
+        for i in 0..3000 {
+            fs::write(temp_dir.path().join(format!("many-{i}")), "").unwrap();
+        }
+
+        // The whole listing is returned; nothing is left to read lazily
+        let entries = read_dir(temp_dir.path()).await.unwrap();
+        assert_eq!(entries.len(), 3002);
+        let subdir = entries
+            .iter()
+            .find(|entry| entry.file_name() == "subdir")
+            .unwrap();
+        assert_eq!(subdir.file_type(), EntryType::Directory);
+
+        assert!(read_dir(&temp_dir.path().join("missing")).await.is_err());
+        assert!(read_dir(&temp_dir.path().join("file")).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[compio::test]
+    async fn test_statx_batch_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("small"), "test").unwrap();
+        fs::write(temp_dir.path().join("large"), vec![0u8; 4096]).unwrap();
+        fs::create_dir(temp_dir.path().join("subdir")).unwrap();
+        let dir_fd = DirectoryFd::open(temp_dir.path()).await.unwrap();
+
+        let names: Vec<std::ffi::OsString> = ["large", "missing", "small", "subdir"]
+            .iter()
+            .map(std::ffi::OsString::from)
+            .collect();
+        let results = dir_fd.statx_batch(&names).await;
+        assert_eq!(results.len(), 4);
+
+        // One result per name, in order; a missing entry fails on its own
+        assert_eq!(results[0].as_ref().unwrap().size, 4096);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().size, 4);
+        assert!(results[3].as_ref().unwrap().is_dir());
+        for (name, result) in names.iter().zip(&results) {
+            if let Ok(metadata) = result {
+                let single = dir_fd.statx_full(name).await.unwrap();
+                assert_eq!(metadata.ino, single.ino);
+            }
+        }
+
+        assert!(dir_fd.statx_batch(&[]).await.is_empty());
+    }
 }
@@ -44,6 +44,10 @@ pub enum ExtendedError {
     #[error("xattr operation failed: {0}")]
     Xattr(String),
 
+    /// POSIX ACL operation error
+    #[error("ACL operation failed: {0}")]
+    Acl(String),
+
     /// Device operation error
     #[error("device operation failed: {0}")]
     Device(String),
@@ -151,6 +155,12 @@ pub fn xattr_error(msg: &str) -> ExtendedError {
     ExtendedError::Xattr(msg.to_string())
 }
 
+/// Helper for creating POSIX ACL specific errors
+#[must_use]
+pub fn acl_error(msg: &str) -> ExtendedError {
+    ExtendedError::Acl(msg.to_string())
+}
+
 /// Helper for creating device specific errors
 #[must_use]
 pub fn device_error(msg: &str) -> ExtendedError {
@@ -0,0 +1,191 @@
+//! File descriptor hygiene: close-on-exec helpers and leak auditing
+//!
+//! Every descriptor this crate opens carries `O_CLOEXEC`, so none of them leak
+//! into processes spawned by the caller (hooks, remote shells). The helpers
+//! here cover the cases `std` and `compio` do not handle themselves (`dup`,
+//! `pipe`), and `FdSnapshot` lets long-running callers check, between runs,
+//! that no descriptor was leaked or opened without close-on-exec.
+//!
+//! Auditing reads `/proc/self/fd` and is therefore Linux-only.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+/// Duplicate `fd` with close-on-exec set (`F_DUPFD_CLOEXEC`)
+///
+/// # Errors
+///
+/// Returns the OS error if `fcntl` fails.
+pub fn dup_cloexec(fd: RawFd) -> io::Result<RawFd> {
+    // SAFETY: fcntl only inspects `fd`; an invalid descriptor yields EBADF
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if new_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(new_fd)
+}
+
+/// Create a pipe with close-on-exec set on both ends; returns `(read, write)`
+///
+/// # Errors
+///
+/// Returns the OS error if `pipe2` fails.
+#[cfg(target_os = "linux")]
+pub fn pipe_cloexec() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0 as RawFd; 2];
+    // SAFETY: fds is a valid two-element out-array
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Whether `fd` has close-on-exec set
+///
+/// # Errors
+///
+/// Returns the OS error if `fcntl` fails (e.g. `fd` is not open).
+pub fn is_cloexec(fd: RawFd) -> io::Result<bool> {
+    // SAFETY: F_GETFD only reads descriptor flags
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags & libc::FD_CLOEXEC != 0)
+}
+
+/// One open descriptor of the current process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdInfo {
+    /// Descriptor number
+    pub fd: RawFd,
+    /// What it refers to (`/proc/self/fd/N` link target)
+    pub target: PathBuf,
+    /// Whether close-on-exec is set
+    pub cloexec: bool,
+}
+
+impl FdInfo {
+    /// Descriptors owned by async runtimes (`io_uring` rings, eventfds, epoll)
+    ///
+    /// Runtimes and dispatchers keep these for their whole lifetime, so they
+    /// are expected to appear between runs and are not reported as leaks.
+    #[must_use]
+    pub fn is_runtime_internal(&self) -> bool {
+        self.target
+            .as_os_str()
+            .as_encoded_bytes()
+            .starts_with(b"anon_inode:")
+    }
+}
+
+/// Set of descriptors open at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FdSnapshot {
+    /// Open descriptors, sorted by number
+    pub fds: Vec<FdInfo>,
+}
+
+#[cfg(target_os = "linux")]
+impl FdSnapshot {
+    /// Capture the descriptors currently open in this process
+    ///
+    /// The descriptor used to read `/proc/self/fd` itself is left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `/proc/self/fd` cannot be read.
+    pub fn capture() -> io::Result<Self> {
+        let mut fds = Vec::new();
+        for entry in std::fs::read_dir("/proc/self/fd")? {
+            let entry = entry?;
+            let Some(fd) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            // The directory iterator's own descriptor is gone by now
+            let Ok(target) = std::fs::read_link(entry.path()) else {
+                continue;
+            };
+            let Ok(cloexec) = is_cloexec(fd) else {
+                continue;
+            };
+            fds.push(FdInfo {
+                fd,
+                target,
+                cloexec,
+            });
+        }
+        fds.sort_by_key(|info| info.fd);
+        Ok(Self { fds })
+    }
+
+    /// Descriptors open now that were not open in `baseline`
+    ///
+    /// Runtime-internal descriptors (see `FdInfo::is_runtime_internal`) are
+    /// ignored. A descriptor number reused for a different target counts as new.
+    #[must_use]
+    pub fn leaked_since(&self, baseline: &Self) -> Vec<FdInfo> {
+        self.fds
+            .iter()
+            .filter(|info| !info.is_runtime_internal() && !baseline.fds.contains(info))
+            .cloned()
+            .collect()
+    }
+
+    /// Descriptors other than stdin/stdout/stderr without close-on-exec
+    #[must_use]
+    pub fn inheritable(&self) -> Vec<FdInfo> {
+        self.fds
+            .iter()
+            .filter(|info| info.fd > 2 && !info.cloexec)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::DirectoryFd;
+    use std::os::unix::io::AsRawFd;
+
+    #[compio::test]
+    async fn test_crate_descriptors_are_cloexec() {
+        // Test: Every way this crate opens a descriptor sets O_CLOEXEC
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        let dir = DirectoryFd::open(temp_dir.path()).await.unwrap();
+        let sub = dir.open_directory_at("sub".as_ref()).await.unwrap();
+        let file = dir
+            .open_file_at("f".as_ref(), false, true, true, false)
+            .await
+            .unwrap();
+
+        for fd in [dir.as_raw_fd(), sub.as_raw_fd(), file.as_raw_fd()] {
+            assert!(is_cloexec(fd).unwrap(), "fd {fd} lacks O_CLOEXEC");
+        }
+
+        let (read_fd, write_fd) = pipe_cloexec().unwrap();
+        let dup_fd = dup_cloexec(read_fd).unwrap();
+        for fd in [read_fd, write_fd, dup_fd] {
+            assert!(is_cloexec(fd).unwrap());
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    #[test]
+    fn test_snapshot_reports_leaks_and_inheritable() {
+        // Test: New descriptors show up as leaks; non-CLOEXEC ones as inheritable
+        let baseline = FdSnapshot::capture().unwrap();
+        let leaked = std::fs::File::open("/proc/self/status").unwrap();
+        let inheritable = unsafe { libc::dup(leaked.as_raw_fd()) };
+
+        let now = FdSnapshot::capture().unwrap();
+        let leaks: Vec<RawFd> = now.leaked_since(&baseline).iter().map(|i| i.fd).collect();
+        assert!(leaks.contains(&leaked.as_raw_fd()), "{leaks:?}");
+        assert!(now.inheritable().iter().any(|i| i.fd == inheritable));
+
+        unsafe { libc::close(inheritable) };
+    }
+}
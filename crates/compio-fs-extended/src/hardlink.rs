@@ -7,7 +7,9 @@ use compio::fs::File;
 #[cfg(target_os = "linux")]
 use io_uring::{opcode, types};
 #[cfg(target_os = "linux")]
-use std::ffi::CString;
+use std::ffi::{CString, OsStr};
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 #[cfg(target_os = "linux")]
 use std::pin::Pin;
@@ -108,14 +110,74 @@ pub async fn create_hardlink_at_path(original_path: &Path, link_path: &Path) ->
     .map_err(|e| hardlink_error(&format!("spawn_blocking failed: {:?}", e)))?
 }
 
+/// Create a hard link named `link_name` in `dir` to the file at `original_path`
+///
+/// Uses io_uring `LINKAT` relative to the directory FD, so many links can be
+/// submitted at once and the link lands in the directory that was opened.
+/// Unlike [`create_hardlink_at_path`], errors keep their `errno`, so callers
+/// can tell an existing name (`ErrorKind::AlreadyExists`) apart.
+///
+/// # Errors
+///
+/// Returns an error if `link_name` already exists, the original does not
+/// exist or is on another filesystem, or permission is denied.
+#[cfg(target_os = "linux")]
+pub async fn linkat(
+    original_path: &Path,
+    dir: &crate::directory::DirectoryFd,
+    link_name: &OsStr,
+) -> std::io::Result<()> {
+    let invalid = |e: std::ffi::NulError| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+    let original = CString::new(original_path.as_os_str().as_bytes()).map_err(invalid)?;
+    let name = CString::new(link_name.as_bytes()).map_err(invalid)?;
+    let op = HardlinkOp {
+        olddirfd: libc::AT_FDCWD,
+        oldpath: original,
+        newdirfd: dir.as_raw_fd(),
+        newpath: name,
+    };
+    compio::runtime::submit(op).await.0.map(|_| ())
+}
+
+/// Create a hard link named `link_name` in `dir` to `original_name` in
+/// `original_dir`
+///
+/// Like [`linkat`], but the original is resolved from a directory FD too,
+/// so no path component outside the two directories is looked up. A
+/// symlink named `original_name` is linked itself, not followed.
+///
+/// # Errors
+///
+/// As for [`linkat`].
+#[cfg(target_os = "linux")]
+pub async fn linkat_at(
+    original_dir: &crate::directory::DirectoryFd,
+    original_name: &OsStr,
+    dir: &crate::directory::DirectoryFd,
+    link_name: &OsStr,
+) -> std::io::Result<()> {
+    let invalid = |e: std::ffi::NulError| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+    let op = HardlinkOp {
+        olddirfd: original_dir.as_raw_fd(),
+        oldpath: CString::new(original_name.as_bytes()).map_err(invalid)?,
+        newdirfd: dir.as_raw_fd(),
+        newpath: CString::new(link_name.as_bytes()).map_err(invalid)?,
+    };
+    compio::runtime::submit(op).await.0.map(|_| ())
+}
+
 // Windows: create_hardlink_at_path not defined
 // Compile-time error if you try to use it on Windows
 
 /// io_uring hardlink (linkat) operation (Linux-only)
 #[cfg(target_os = "linux")]
 struct HardlinkOp {
+    /// Directory `oldpath` is relative to (`AT_FDCWD`: the working directory)
+    olddirfd: std::os::unix::io::RawFd,
     /// Source path to link from
     oldpath: CString,
+    /// Directory `newpath` is relative to (`AT_FDCWD`: the working directory)
+    newdirfd: std::os::unix::io::RawFd,
     /// Destination path for the new hardlink
     newpath: CString,
 }
@@ -125,22 +187,47 @@ impl HardlinkOp {
     /// Create a new hardlink operation for submission to io_uring
     #[must_use]
     fn new(oldpath: CString, newpath: CString) -> Self {
-        Self { oldpath, newpath }
+        Self {
+            olddirfd: libc::AT_FDCWD,
+            oldpath,
+            newdirfd: libc::AT_FDCWD,
+            newpath,
+        }
     }
 }
 
 #[cfg(target_os = "linux")]
 impl OpCode for HardlinkOp {
     fn create_entry(self: Pin<&mut Self>) -> compio::driver::OpEntry {
-        // Use AT_FDCWD for both paths
+        // Kernels before 5.15 have no IORING_OP_LINKAT
+        if !crate::uring_probe::is_supported(opcode::LinkAt::CODE) {
+            return compio::driver::OpEntry::Blocking;
+        }
         compio::driver::OpEntry::Submission(
             opcode::LinkAt::new(
-                types::Fd(libc::AT_FDCWD),
+                types::Fd(self.olddirfd),
                 self.oldpath.as_ptr(),
-                types::Fd(libc::AT_FDCWD),
+                types::Fd(self.newdirfd),
                 self.newpath.as_ptr(),
             )
             .build(),
         )
     }
+
+    fn call_blocking(self: Pin<&mut Self>) -> std::io::Result<usize> {
+        // SAFETY: both paths are NUL-terminated and outlive the call
+        let ret = unsafe {
+            libc::linkat(
+                self.olddirfd,
+                self.oldpath.as_ptr(),
+                self.newdirfd,
+                self.newpath.as_ptr(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(0)
+    }
 }
@@ -6,8 +6,16 @@
 //! - Symlink operations (create, read, metadata)
 //! - Hardlink operations
 //! - Extended attributes (xattr) using io_uring opcodes
+//! - POSIX ACLs (`system.posix_acl_*` attributes)
 //! - Directory operations with secure *at syscalls
 //! - File ownership operations
+//! - Filesystem-wide durability barrier (`syncfs`)
+//! - Close-on-exec helpers and file descriptor leak auditing
+//! - Signed timestamps (`Timestamp`) for pre-epoch and post-2038 file times
+//! - io_uring registered (fixed) buffers for copy loops (`write_managed`)
+//! - Extent maps (`FIEMAP`) for splitting copies and skipping holes
+//! - Runtime detection of io_uring opcodes, with blocking fallbacks on older
+//!   kernels (`uring_probe`)
 //!
 //! This crate extends `compio::fs::File` with additional operations that are not
 //! available in the base compio-fs crate, using direct syscalls integrated with
@@ -36,16 +44,25 @@
 //!
 //! Note: `fadvise` operations are only available on Linux.
 //!
+pub mod acl;
 pub mod device;
 pub mod directory;
 pub mod error;
 pub mod extended_file;
+pub mod extents;
 pub mod fadvise;
 pub mod fallocate;
+pub mod fd_hygiene;
 pub mod hardlink;
 pub mod metadata;
 pub mod ownership;
 pub mod symlink;
+pub mod syncfs;
+pub mod timestamp;
+#[cfg(target_os = "linux")]
+pub mod uring_probe;
+#[cfg(target_os = "linux")]
+pub mod write_managed;
 pub mod xattr;
 
 // Platform-specific shims (none required at module level yet)
@@ -54,7 +71,8 @@ pub mod xattr;
 pub use directory::DirectoryFd;
 pub use error::{ExtendedError, Result};
 pub use extended_file::ExtendedFile;
-pub use metadata::FileMetadata;
+pub use metadata::{FileMetadata, StatxMask};
+pub use timestamp::Timestamp;
 
 // Re-export specific operation modules
 #[cfg(target_os = "linux")]
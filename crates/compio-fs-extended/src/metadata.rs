@@ -38,6 +38,8 @@
 use crate::directory::DirectoryFd;
 #[cfg(unix)]
 use crate::error::{metadata_error, ExtendedError, Result};
+#[cfg(unix)]
+use crate::timestamp::Timestamp;
 #[cfg(target_os = "linux")]
 use compio::driver::OpCode;
 #[cfg(unix)]
@@ -88,6 +90,12 @@ pub struct FileMetadata {
     pub ino: u64,
     /// Device ID
     pub dev: u64,
+    /// Device number of a character or block device file (`makedev`
+    /// encoding, as `mknod` takes it); 0 for other files
+    pub rdev: u64,
+    /// Allocated space in 512-byte units (`st_blocks`), whatever the
+    /// filesystem's block size
+    pub blocks: u64,
     /// Last access time
     pub accessed: SystemTime,
     /// Last modification time
@@ -146,6 +154,78 @@ impl FileMetadata {
     }
 }
 
+/// Set of metadata fields requested from `statx(2)` (`STATX_*` bits)
+///
+/// Requesting fewer fields lets some filesystems (network and FUSE ones in
+/// particular) skip work such as revalidating attributes or fetching the
+/// birth time. Fields outside the mask may still be filled in by the kernel,
+/// but callers must not rely on them: they may be zero.
+///
+/// The file type, device, and statx attributes are always returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatxMask(u32);
+
+impl StatxMask {
+    /// File type (`S_IFMT` bits of `mode`)
+    pub const TYPE: Self = Self(0x0001);
+    /// Permission bits of `mode`
+    pub const MODE: Self = Self(0x0002);
+    /// `nlink`
+    pub const NLINK: Self = Self(0x0004);
+    /// `uid`
+    pub const UID: Self = Self(0x0008);
+    /// `gid`
+    pub const GID: Self = Self(0x0010);
+    /// `accessed`
+    pub const ATIME: Self = Self(0x0020);
+    /// `modified`
+    pub const MTIME: Self = Self(0x0040);
+    /// Status change time (not stored in `FileMetadata`)
+    pub const CTIME: Self = Self(0x0080);
+    /// `ino`
+    pub const INO: Self = Self(0x0100);
+    /// `size`
+    pub const SIZE: Self = Self(0x0200);
+    /// `blocks`
+    pub const BLOCKS: Self = Self(0x0400);
+    /// Everything `stat(2)` returns (`STATX_BASIC_STATS`)
+    pub const BASIC_STATS: Self = Self(0x07ff);
+    /// `created` (birth time, where the filesystem records it)
+    pub const BTIME: Self = Self(0x0800);
+
+    /// Raw `STATX_*` bits
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Fields in either mask
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Whether every field of `other` is requested
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for StatxMask {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+impl std::ops::BitOrAssign for StatxMask {
+    fn bitor_assign(&mut self, other: Self) {
+        *self = self.union(other);
+    }
+}
+
 /// io_uring statx operation for getting file metadata with nanosecond timestamps
 #[cfg(target_os = "linux")]
 pub struct StatxOp {
@@ -229,14 +309,9 @@ pub async fn statx_at(path: &Path) -> Result<(SystemTime, SystemTime)> {
         Ok(_) => {
             let statx_buf = result.1.statxbuf;
 
-            // Extract nanosecond timestamps
-            let atime_secs = u64::try_from(statx_buf.stx_atime.tv_sec).unwrap_or(0);
-            let atime_nanos = statx_buf.stx_atime.tv_nsec;
-            let mtime_secs = u64::try_from(statx_buf.stx_mtime.tv_sec).unwrap_or(0);
-            let mtime_nanos = statx_buf.stx_mtime.tv_nsec;
-
-            let atime = SystemTime::UNIX_EPOCH + std::time::Duration::new(atime_secs, atime_nanos);
-            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::new(mtime_secs, mtime_nanos);
+            // Nanosecond timestamps, possibly before the epoch
+            let atime = Timestamp::from_statx(&statx_buf.stx_atime).to_system_time();
+            let mtime = Timestamp::from_statx(&statx_buf.stx_mtime).to_system_time();
 
             Ok((atime, mtime))
         }
@@ -244,17 +319,11 @@ pub async fn statx_at(path: &Path) -> Result<(SystemTime, SystemTime)> {
     }
 }
 
-/// Helper to convert SystemTime to nix TimeSpec
+/// Helper to convert SystemTime to nix TimeSpec (signed, so pre-epoch
+/// times are set exactly)
 #[cfg(unix)]
-fn system_time_to_timespec(time: SystemTime) -> Result<TimeSpec> {
-    let duration = time
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map_err(|e| metadata_error(&format!("Invalid time: {}", e)))?;
-
-    Ok(TimeSpec::new(
-        duration.as_secs() as i64,
-        duration.subsec_nanos() as i64,
-    ))
+fn system_time_to_timespec(time: SystemTime) -> TimeSpec {
+    Timestamp::from(time).to_timespec()
 }
 
 /// Change file timestamps using file descriptor (FD-based, more efficient)
@@ -284,8 +353,8 @@ pub async fn futimens_fd(file: &File, accessed: SystemTime, modified: SystemTime
     // futimens is FD-based, better than path-based utimensat (no TOCTOU)
     let fd = file.as_raw_fd();
     let inner = compio::runtime::spawn_blocking(move || {
-        let atime = system_time_to_timespec(accessed)?;
-        let mtime = system_time_to_timespec(modified)?;
+        let atime = system_time_to_timespec(accessed);
+        let mtime = system_time_to_timespec(modified);
 
         // SAFETY: Caller guarantees fd is valid and won't be closed during this operation.
         nix::sys::stat::futimens(fd, &atime, &mtime)
@@ -309,7 +378,8 @@ pub async fn futimens_fd(file: &File, accessed: SystemTime, modified: SystemTime
 ///
 /// # Returns
 ///
-/// Returns full `FileMetadata` with nanosecond precision timestamps
+/// Returns `FileMetadata` with nanosecond precision timestamps; only the
+/// fields in `mask` are guaranteed to be valid
 ///
 /// # Errors
 ///
@@ -318,7 +388,40 @@ pub async fn futimens_fd(file: &File, accessed: SystemTime, modified: SystemTime
 pub(crate) async fn statx_impl(
     dir: &DirectoryFd,
     pathname: &std::ffi::OsStr,
+    mask: StatxMask,
 ) -> Result<FileMetadata> {
+    let op = statx_op(dir, pathname, mask)?;
+    let result = submit(op).await;
+
+    match result.0 {
+        Ok(_) => Ok(metadata_from_statx(&result.1.statxbuf)),
+        Err(e) => Err(metadata_error(&format!("statx failed: {}", e))),
+    }
+}
+
+/// Get metadata for several children of `dir` in one io_uring submission
+///
+/// Every `IORING_OP_STATX` is queued before the runtime next submits, so a
+/// directory's entries cost one `io_uring_enter` instead of one each. The
+/// results are in the order of `pathnames`; one failing does not affect
+/// the others.
+#[cfg(target_os = "linux")]
+pub(crate) async fn statx_batch_impl(
+    dir: &DirectoryFd,
+    pathnames: &[std::ffi::OsString],
+    mask: StatxMask,
+) -> Vec<Result<FileMetadata>> {
+    futures::future::join_all(
+        pathnames
+            .iter()
+            .map(|pathname| statx_impl(dir, pathname, mask)),
+    )
+    .await
+}
+
+/// The `IORING_OP_STATX` for `pathname` relative to `dir`
+#[cfg(target_os = "linux")]
+fn statx_op(dir: &DirectoryFd, pathname: &std::ffi::OsStr, mask: StatxMask) -> Result<StatxOp> {
     use std::os::unix::ffi::OsStrExt;
 
     let dir_fd = dir.as_raw_fd();
@@ -327,70 +430,76 @@ pub(crate) async fn statx_impl(
 
     // Use directory FD with relative path
     // AT_SYMLINK_NOFOLLOW = don't dereference symlinks (CRITICAL for symlink preservation!)
-    // STATX_BASIC_STATS = 0x7ff (all basic fields)
-    let op = StatxOp::new(dir_fd, path_cstr, libc::AT_SYMLINK_NOFOLLOW, 0x0000_07ff);
-    let result = submit(op).await;
+    // The file type is always needed to interpret the result
+    let mask = mask | StatxMask::TYPE;
+    Ok(StatxOp::new(
+        dir_fd,
+        path_cstr,
+        libc::AT_SYMLINK_NOFOLLOW,
+        mask.bits(),
+    ))
+}
 
-    match result.0 {
-        Ok(_) => {
-            let statx_buf = result.1.statxbuf;
+/// Convert a filled-in `statx` buffer to `FileMetadata`
+#[cfg(target_os = "linux")]
+fn metadata_from_statx(statx_buf: &libc::statx) -> FileMetadata {
+    // Extract all metadata fields
+    let size = statx_buf.stx_size;
+    let mode = statx_buf.stx_mode as u32;
+    let uid = statx_buf.stx_uid;
+    let gid = statx_buf.stx_gid;
+    let nlink = statx_buf.stx_nlink as u64;
+    let ino = statx_buf.stx_ino;
+    let blocks = statx_buf.stx_blocks;
+
+    // Combine device major/minor into single dev ID
+    #[allow(clippy::cast_lossless)]
+    let dev = (statx_buf.stx_dev_major as u64) << 32 | (statx_buf.stx_dev_minor as u64);
+    let rdev = libc::makedev(statx_buf.stx_rdev_major, statx_buf.stx_rdev_minor);
+
+    // Convert timestamps, which may be before the epoch
+    let accessed = Timestamp::from_statx(&statx_buf.stx_atime).to_system_time();
+    let modified = Timestamp::from_statx(&statx_buf.stx_mtime).to_system_time();
+
+    // Birth time (creation time) - may not be available on all filesystems
+    let created = if statx_buf.stx_mask & libc::STATX_BTIME != 0 {
+        Some(Timestamp::from_statx(&statx_buf.stx_btime).to_system_time())
+    } else {
+        None
+    };
 
-            // Extract all metadata fields
-            let size = statx_buf.stx_size;
-            let mode = statx_buf.stx_mode as u32;
-            let uid = statx_buf.stx_uid;
-            let gid = statx_buf.stx_gid;
-            let nlink = statx_buf.stx_nlink as u64;
-            let ino = statx_buf.stx_ino;
-
-            // Combine device major/minor into single dev ID
-            #[allow(clippy::cast_lossless)]
-            let dev = (statx_buf.stx_dev_major as u64) << 32 | (statx_buf.stx_dev_minor as u64);
-
-            // Convert timestamps with pre-epoch handling
-            let accessed = statx_ts_to_system_time(&statx_buf.stx_atime);
-            let modified = statx_ts_to_system_time(&statx_buf.stx_mtime);
-
-            // Birth time (creation time) - may not be available on all filesystems
-            let created = if statx_buf.stx_mask & libc::STATX_BTIME != 0 {
-                Some(statx_ts_to_system_time(&statx_buf.stx_btime))
-            } else {
-                None
-            };
-
-            // Platform-specific fields
-            #[cfg(target_os = "linux")]
-            let attributes = if statx_buf.stx_attributes_mask != 0 {
-                Some(statx_buf.stx_attributes)
-            } else {
-                None
-            };
-
-            #[cfg(target_os = "linux")]
-            let attributes_mask = if statx_buf.stx_attributes_mask != 0 {
-                Some(statx_buf.stx_attributes_mask)
-            } else {
-                None
-            };
-
-            Ok(FileMetadata {
-                size,
-                mode,
-                uid,
-                gid,
-                nlink,
-                ino,
-                dev,
-                accessed,
-                modified,
-                created,
-                #[cfg(target_os = "linux")]
-                attributes,
-                #[cfg(target_os = "linux")]
-                attributes_mask,
-            })
-        }
-        Err(e) => Err(metadata_error(&format!("statx failed: {}", e))),
+    // Platform-specific fields
+    #[cfg(target_os = "linux")]
+    let attributes = if statx_buf.stx_attributes_mask != 0 {
+        Some(statx_buf.stx_attributes)
+    } else {
+        None
+    };
+
+    #[cfg(target_os = "linux")]
+    let attributes_mask = if statx_buf.stx_attributes_mask != 0 {
+        Some(statx_buf.stx_attributes_mask)
+    } else {
+        None
+    };
+
+    FileMetadata {
+        size,
+        mode,
+        uid,
+        gid,
+        nlink,
+        ino,
+        dev,
+        rdev,
+        blocks,
+        accessed,
+        modified,
+        created,
+        #[cfg(target_os = "linux")]
+        attributes,
+        #[cfg(target_os = "linux")]
+        attributes_mask,
     }
 }
 
@@ -398,10 +507,13 @@ pub(crate) async fn statx_impl(
 ///
 /// Uses `fstatat(2)` with directory FD for TOCTOU-safe metadata retrieval.
 /// This is the macOS equivalent of Linux `statx_impl`.
+///
+/// `fstatat` has no field mask; `_mask` is accepted for API parity.
 #[cfg(target_os = "macos")]
 pub(crate) async fn statx_impl(
     dir: &DirectoryFd,
     pathname: &std::ffi::OsStr,
+    _mask: StatxMask,
 ) -> Result<FileMetadata> {
     use std::os::unix::ffi::OsStrExt;
 
@@ -428,6 +540,8 @@ pub(crate) async fn statx_impl(
         let nlink = stat_result.st_nlink as u64;
         let ino = stat_result.st_ino;
         let dev = stat_result.st_dev as u64;
+        let rdev = stat_result.st_rdev as u64;
+        let blocks = stat_result.st_blocks as u64;
 
         // Convert timestamps (macOS has nanosecond precision)
         let accessed = unix_ts_to_system_time(stat_result.st_atime, stat_result.st_atime_nsec);
@@ -449,6 +563,8 @@ pub(crate) async fn statx_impl(
             nlink,
             ino,
             dev,
+            rdev,
+            blocks,
             accessed,
             modified,
             created,
@@ -460,35 +576,26 @@ pub(crate) async fn statx_impl(
     .map_err(|e| ExtendedError::SpawnJoin(format!("spawn_blocking failed: {:?}", e)))?
 }
 
-/// Convert Unix timestamp to SystemTime (macOS)
+/// Get metadata for several children of `dir` (macOS)
+///
+/// There is no io_uring to batch into, so each `fstatat` runs in turn.
 #[cfg(target_os = "macos")]
-fn unix_ts_to_system_time(secs: i64, nsec: i64) -> SystemTime {
-    let nsec = nsec as u32;
-    if secs >= 0 {
-        SystemTime::UNIX_EPOCH + std::time::Duration::new(secs as u64, nsec)
-    } else {
-        let abs_secs = (-secs) as u64;
-        SystemTime::UNIX_EPOCH
-            .checked_sub(std::time::Duration::new(abs_secs, nsec))
-            .unwrap_or(SystemTime::UNIX_EPOCH)
+pub(crate) async fn statx_batch_impl(
+    dir: &DirectoryFd,
+    pathnames: &[std::ffi::OsString],
+    mask: StatxMask,
+) -> Vec<Result<FileMetadata>> {
+    let mut results = Vec::with_capacity(pathnames.len());
+    for pathname in pathnames {
+        results.push(statx_impl(dir, pathname, mask).await);
     }
+    results
 }
 
-/// Convert statx timestamp to SystemTime with pre-epoch support
-///
-/// Handles timestamps before 1970 (negative tv_sec) correctly.
-#[cfg(target_os = "linux")]
-fn statx_ts_to_system_time(ts: &libc::statx_timestamp) -> SystemTime {
-    let nsec = ts.tv_nsec;
-    if ts.tv_sec >= 0 {
-        SystemTime::UNIX_EPOCH + std::time::Duration::new(ts.tv_sec as u64, nsec)
-    } else {
-        let secs = (-ts.tv_sec) as u64;
-        // Saturate: if subtraction underflows, clamp to UNIX_EPOCH
-        SystemTime::UNIX_EPOCH
-            .checked_sub(std::time::Duration::new(secs, nsec))
-            .unwrap_or(SystemTime::UNIX_EPOCH)
-    }
+/// Convert Unix timestamp to SystemTime (macOS)
+#[cfg(target_os = "macos")]
+fn unix_ts_to_system_time(secs: i64, nsec: i64) -> SystemTime {
+    Timestamp::new(secs, nsec).to_system_time()
 }
 
 /// Change file permissions using DirectoryFd
@@ -546,8 +653,8 @@ pub(crate) async fn lutimensat_impl(
     let dir_fd = dir.as_raw_fd();
 
     let operation = move || {
-        let atime = system_time_to_timespec(accessed)?;
-        let mtime = system_time_to_timespec(modified)?;
+        let atime = system_time_to_timespec(accessed);
+        let mtime = system_time_to_timespec(modified);
 
         nix::sys::stat::utimensat(
             Some(dir_fd),
@@ -70,6 +70,10 @@ impl SymlinkOp {
 #[cfg(target_os = "linux")]
 impl OpCode for SymlinkOp {
     fn create_entry(self: Pin<&mut Self>) -> compio::driver::OpEntry {
+        // Kernels before 5.15 have no IORING_OP_SYMLINKAT
+        if !crate::uring_probe::is_supported(opcode::SymlinkAt::CODE) {
+            return compio::driver::OpEntry::Blocking;
+        }
         compio::driver::OpEntry::Submission(
             opcode::SymlinkAt::new(
                 types::Fd(self.dir_fd.unwrap_or(libc::AT_FDCWD)),
@@ -79,6 +83,21 @@ impl OpCode for SymlinkOp {
             .build(),
         )
     }
+
+    fn call_blocking(self: Pin<&mut Self>) -> std::io::Result<usize> {
+        // SAFETY: both paths are NUL-terminated and outlive the call
+        let ret = unsafe {
+            libc::symlinkat(
+                self.target.as_ptr(),
+                self.dir_fd.unwrap_or(libc::AT_FDCWD),
+                self.link_path.as_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(0)
+    }
 }
 
 /// Trait for symlink operations
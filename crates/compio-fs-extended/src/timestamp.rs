@@ -0,0 +1,176 @@
+//! Signed file timestamps
+//!
+//! File times are signed: `struct timespec` and `statx_timestamp` hold
+//! seconds since the Unix epoch as a signed 64-bit value plus a non-negative
+//! nanosecond part, so files can be dated before 1970 (e.g. extracted from old
+//! archives) or far past 2038. `Timestamp` is that pair, and the conversions
+//! here never go through `u64` seconds or `SystemTime::duration_since`, which
+//! clamp or fail on pre-epoch times (or panic on `UNIX_EPOCH + Duration` of a
+//! negative value cast to `u64`).
+//!
+//! The nanoseconds always count forward from `secs`, as in `timespec`: 1.5
+//! seconds before the epoch is `{ secs: -2, nsecs: 500_000_000 }`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Nanoseconds per second
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// A point in time as signed seconds and nanoseconds from the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    /// Seconds from the Unix epoch, negative before it
+    pub secs: i64,
+    /// Nanoseconds after `secs` (always `0..1_000_000_000`)
+    pub nsecs: u32,
+}
+
+impl Timestamp {
+    /// The Unix epoch
+    pub const EPOCH: Self = Self { secs: 0, nsecs: 0 };
+
+    /// Timestamp from seconds and nanoseconds, as found in `timespec`
+    ///
+    /// Nanoseconds outside `0..1_000_000_000` (including negative ones) are
+    /// carried into the seconds; the result saturates at the `i64` range.
+    #[must_use]
+    pub fn new(secs: i64, nsecs: i64) -> Self {
+        let secs = secs.saturating_add(nsecs.div_euclid(NANOS_PER_SEC));
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // In 0..10^9
+        let nsecs = nsecs.rem_euclid(NANOS_PER_SEC) as u32;
+        Self { secs, nsecs }
+    }
+
+    /// Timestamp of a `SystemTime`, exact for any time it can hold
+    #[must_use]
+    pub fn from_system_time(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Self::new(
+                i64::try_from(after.as_secs()).unwrap_or(i64::MAX),
+                i64::from(after.subsec_nanos()),
+            ),
+            Err(before) => {
+                let before = before.duration();
+                Self::new(
+                    i64::try_from(before.as_secs()).map_or(i64::MIN, |secs| -secs),
+                    -i64::from(before.subsec_nanos()),
+                )
+            }
+        }
+    }
+
+    /// The `SystemTime` of this timestamp
+    ///
+    /// On Unix `SystemTime` itself is a signed `timespec`, so every timestamp
+    /// converts exactly. Elsewhere, times outside its range saturate to the
+    /// epoch.
+    #[must_use]
+    pub fn to_system_time(self) -> SystemTime {
+        let nanos = Duration::from_nanos(u64::from(self.nsecs));
+        let time = if self.secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(self.secs.unsigned_abs()))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(self.secs.unsigned_abs()))
+        };
+        time.and_then(|time| time.checked_add(nanos))
+            .unwrap_or(UNIX_EPOCH)
+    }
+
+    /// Timestamp of a `statx` time field
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub fn from_statx(ts: &libc::statx_timestamp) -> Self {
+        Self::new(ts.tv_sec, i64::from(ts.tv_nsec))
+    }
+
+    /// The `timespec` for `utimensat`/`futimens`
+    #[cfg(unix)]
+    #[must_use]
+    pub fn to_timespec(self) -> nix::sys::time::TimeSpec {
+        // Note: time_t is i64 on 64-bit platforms (truncates on 32-bit ones,
+        // like the kernel would); nanoseconds always fit
+        #[allow(clippy::unnecessary_cast, clippy::cast_possible_truncation)]
+        nix::sys::time::TimeSpec::new(self.secs as libc::time_t, self.nsecs as _)
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        Self::from_system_time(time)
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.to_system_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_normalizes_nanoseconds() {
+        assert_eq!(
+            Timestamp::new(-2, 500_000_000),
+            Timestamp {
+                secs: -2,
+                nsecs: 500_000_000
+            }
+        );
+        // 1.5 s before the epoch written as (-1, -0.5 s)
+        assert_eq!(
+            Timestamp::new(-1, -500_000_000),
+            Timestamp::new(-2, 500_000_000)
+        );
+        assert_eq!(
+            Timestamp::new(1, 1_500_000_000),
+            Timestamp::new(2, 500_000_000)
+        );
+        assert_eq!(Timestamp::new(i64::MAX, 2_000_000_000).secs, i64::MAX);
+    }
+
+    #[test]
+    fn test_system_time_round_trip() {
+        for (secs, nsecs) in [
+            (0, 0),
+            (0, 1),
+            (-1, 999_999_999),
+            (-2, 500_000_000),
+            (-2_208_988_800, 0),              // 1900-01-01
+            (-62_135_596_800, 0),             // 0001-01-01
+            (2_147_483_647, 999_999_999),     // 2038-01-19, the 32-bit limit
+            (2_147_483_648, 0),               // one second past it
+            (253_402_300_799, 123_456_789),   // 9999-12-31T23:59:59
+            (i64::from(u32::MAX) * 1000, 7),  // far future
+            (-i64::from(u32::MAX) * 1000, 7), // far past
+        ] {
+            let timestamp = Timestamp::new(secs, nsecs);
+            let time = timestamp.to_system_time();
+            assert_eq!(Timestamp::from(time), timestamp, "{secs}.{nsecs}");
+        }
+
+        let before = UNIX_EPOCH - Duration::from_millis(1500);
+        assert_eq!(Timestamp::from(before), Timestamp::new(-2, 500_000_000));
+        assert!(Timestamp::from(before) < Timestamp::EPOCH);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_statx_and_timespec() {
+        // SAFETY: statx_timestamp is plain old data
+        let mut ts: libc::statx_timestamp = unsafe { std::mem::zeroed() };
+        ts.tv_sec = -2;
+        ts.tv_nsec = 250_000_000;
+        let timestamp = Timestamp::from_statx(&ts);
+        assert_eq!(
+            timestamp.to_system_time(),
+            UNIX_EPOCH - Duration::from_millis(1750)
+        );
+
+        let timespec = timestamp.to_timespec();
+        assert_eq!(timespec.tv_sec(), -2);
+        assert_eq!(timespec.tv_nsec(), 250_000_000);
+    }
+}
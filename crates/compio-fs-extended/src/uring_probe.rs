@@ -0,0 +1,132 @@
+//! Runtime detection of io_uring support
+//!
+//! One binary has to run on every kernel from 5.10 on, but io_uring gained
+//! opcodes over that range: `IORING_OP_LINKAT` and `IORING_OP_SYMLINKAT` in
+//! 5.15, `IORING_OP_FGETXATTR` and `IORING_OP_FSETXATTR` in 5.19. Instead of
+//! choosing at build time, the kernel's opcodes are probed once per process
+//! (`IORING_REGISTER_PROBE` on a throwaway ring), and an operation whose
+//! opcode is missing runs the equivalent syscall on compio's blocking pool
+//! (`OpEntry::Blocking`).
+//!
+//! io_uring itself may be missing too: a kernel built without it, the
+//! `kernel.io_uring_disabled` sysctl, or a container whose seccomp profile
+//! blocks `io_uring_setup` (Docker's default profile does since 25.0).
+//! [`check_available`] says which, so a program can fail with advice before
+//! starting its runtime.
+//!
+//! # Usage
+//!
+//! ```rust,no_run
+//! use compio_fs_extended::uring_probe;
+//! use io_uring::opcode;
+//!
+//! if let Err(e) = uring_probe::check_available() {
+//!     eprintln!("{e}");
+//! }
+//! for (name, kernel) in uring_probe::missing_opcodes() {
+//!     println!("{name} needs Linux {kernel}: using a blocking syscall");
+//! }
+//! if uring_probe::is_supported(opcode::FGetXattr::CODE) {
+//!     println!("xattrs are read through io_uring");
+//! }
+//! ```
+
+use io_uring::{opcode, IoUring, Probe};
+use std::io;
+use std::sync::OnceLock;
+
+/// Opcodes submitted by this crate that kernels since 5.10 may lack, with
+/// their syscall and the kernel adding them
+pub const LATE_OPCODES: &[(u8, &str, &str)] = &[
+    (opcode::LinkAt::CODE, "linkat", "5.15"),
+    (opcode::SymlinkAt::CODE, "symlinkat", "5.15"),
+    (opcode::FGetXattr::CODE, "fgetxattr", "5.19"),
+    (opcode::FSetXattr::CODE, "fsetxattr", "5.19"),
+];
+
+/// Supported opcodes, one bit each; `None` if the kernel cannot be probed
+static SUPPORTED: OnceLock<Option<[u64; 4]>> = OnceLock::new();
+
+/// Probe the kernel's opcodes on a throwaway ring
+fn probe() -> Option<[u64; 4]> {
+    let ring = IoUring::new(2).ok()?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe).ok()?;
+    let mut bits = [0u64; 4];
+    for code in 0..=u8::MAX {
+        if probe.is_supported(code) {
+            bits[usize::from(code / 64)] |= 1 << (code % 64);
+        }
+    }
+    Some(bits)
+}
+
+/// Whether the running kernel supports the io_uring opcode `code`
+/// (`opcode::*::CODE`); `false` if io_uring cannot be probed
+#[must_use]
+pub fn is_supported(code: u8) -> bool {
+    SUPPORTED
+        .get_or_init(probe)
+        .is_some_and(|bits| bits[usize::from(code / 64)] & (1 << (code % 64)) != 0)
+}
+
+/// The [`LATE_OPCODES`] the running kernel lacks, as (syscall, kernel adding
+/// the opcode)
+#[must_use]
+pub fn missing_opcodes() -> Vec<(&'static str, &'static str)> {
+    LATE_OPCODES
+        .iter()
+        .filter(|(code, _, _)| !is_supported(*code))
+        .map(|&(_, name, kernel)| (name, kernel))
+        .collect()
+}
+
+/// Check that io_uring can be used at all
+///
+/// # Errors
+///
+/// Returns the `io_uring_setup` error, with a message explaining the likely
+/// cause: no io_uring in the kernel, the `kernel.io_uring_disabled` sysctl,
+/// or a seccomp profile blocking it.
+pub fn check_available() -> io::Result<()> {
+    let Err(e) = IoUring::new(2) else {
+        return Ok(());
+    };
+    let disabled = std::fs::read_to_string("/proc/sys/kernel/io_uring_disabled")
+        .is_ok_and(|text| text.trim() != "0");
+    let cause = match e.raw_os_error() {
+        Some(libc::ENOSYS) => "the kernel has no io_uring (Linux 5.10 or later is needed)",
+        Some(libc::EPERM) if disabled => {
+            "io_uring is disabled by the kernel.io_uring_disabled sysctl"
+        }
+        Some(libc::EPERM) => {
+            "io_uring is blocked, usually by a container's seccomp profile (allow \
+             io_uring_setup, io_uring_enter and io_uring_register, e.g. with \
+             --security-opt seccomp=unconfined)"
+        }
+        Some(libc::ENOMEM) => "io_uring could not lock memory (raise RLIMIT_MEMLOCK)",
+        _ => "io_uring is unavailable",
+    };
+    Err(io::Error::new(e.kind(), format!("{cause}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_agrees_with_availability() {
+        // Where io_uring works, the opcodes every supported kernel has are
+        // found, and the late ones are either supported or reported missing
+        if check_available().is_err() {
+            assert!(!is_supported(opcode::Read::CODE));
+            return;
+        }
+        assert!(is_supported(opcode::Read::CODE));
+        assert!(is_supported(opcode::Statx::CODE));
+        let missing = missing_opcodes();
+        for (code, name, _) in LATE_OPCODES {
+            assert_eq!(is_supported(*code), !missing.iter().any(|(n, _)| n == name));
+        }
+    }
+}
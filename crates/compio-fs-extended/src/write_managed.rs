@@ -0,0 +1,324 @@
+//! Copy I/O through io_uring registered (fixed) buffers
+//!
+//! An ordinary `read_at`/`write_at` makes the kernel pin the pages of its
+//! buffer for the duration of the request and unpin them afterwards. Buffers
+//! registered once with `IORING_REGISTER_BUFFERS` stay pinned, and requests
+//! name them by index with `IORING_OP_READ_FIXED` / `IORING_OP_WRITE_FIXED`,
+//! so a copy loop neither allocates nor pins per chunk.
+//!
+//! A ring has a single buffer table and compio runs one ring per thread, so
+//! each thread registers its own [`REGISTERED_BUFFERS`] buffers the first
+//! time [`take`] is called on it; their size is the size asked for then.
+//! Registration can fail (the `RLIMIT_MEMLOCK` limit, a kernel older than
+//! 5.1, a table registered by someone else): [`take`] then returns `None`
+//! for the rest of the thread and callers fall back to ordinary buffers, as
+//! they do when every buffer is leased or a larger one is needed.
+//!
+//! # Usage
+//!
+//! ```rust,no_run
+//! use compio::fs::File;
+//! use compio_fs_extended::write_managed::{self, read_fixed_at, write_fixed_at};
+//!
+//! # async fn example(src: &File, dst: &File) -> std::io::Result<()> {
+//! if let Some(buffer) = write_managed::take(1024 * 1024) {
+//!     let compio::buf::BufResult(read, buffer) = read_fixed_at(src, buffer, 0).await;
+//!     read?;
+//!     let compio::buf::BufResult(written, _buffer) = write_fixed_at(dst, buffer, 0).await;
+//!     written?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use compio::buf::BufResult;
+use compio::driver::OpCode;
+use compio::fs::File;
+use compio::runtime::submit;
+use io_uring::{opcode, types};
+use std::cell::RefCell;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// Buffers registered per thread
+pub const REGISTERED_BUFFERS: usize = 8;
+
+/// `io_uring_register(2)` opcode registering fixed buffers
+const IORING_REGISTER_BUFFERS: libc::c_uint = 0;
+
+thread_local! {
+    /// This thread's buffer table
+    static TABLE: RefCell<TableState> = const { RefCell::new(TableState::Unregistered) };
+}
+
+/// Whether this thread's ring has our buffers
+enum TableState {
+    /// Not tried yet
+    Unregistered,
+    /// Registered
+    Registered(Rc<Table>),
+    /// Registration failed; do not try again
+    Unavailable,
+}
+
+/// Buffers registered with this thread's ring
+struct Table {
+    /// Start of `REGISTERED_BUFFERS * size` bytes
+    base: *mut u8,
+    /// Length of each buffer
+    size: usize,
+    /// Indices of the buffers not leased
+    free: RefCell<Vec<u16>>,
+}
+
+impl Table {
+    /// Allocate the buffers and register them with the current ring
+    fn register(size: usize) -> std::io::Result<Self> {
+        let memory = vec![0u8; REGISTERED_BUFFERS * size].into_boxed_slice();
+        let base = Box::into_raw(memory).cast::<u8>();
+        let table = Self {
+            base,
+            size,
+            free: RefCell::new((0..REGISTERED_BUFFERS as u16).rev().collect()),
+        };
+        let iovecs: Vec<libc::iovec> = (0..REGISTERED_BUFFERS)
+            .map(|index| libc::iovec {
+                iov_base: table.buffer(index as u16).cast(),
+                iov_len: size,
+            })
+            .collect();
+        let ring = compio::runtime::Runtime::with_current(|runtime| runtime.as_raw_fd());
+        // SAFETY: the iovecs describe memory owned by `table`, which is
+        // never freed while the thread (and so its ring) is alive
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                ring,
+                IORING_REGISTER_BUFFERS,
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_uint,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(table)
+    }
+
+    /// Start of buffer `index`
+    fn buffer(&self, index: u16) -> *mut u8 {
+        // SAFETY: index < REGISTERED_BUFFERS, so this stays in the allocation
+        unsafe { self.base.add(usize::from(index) * self.size) }
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        let memory = std::ptr::slice_from_raw_parts_mut(self.base, REGISTERED_BUFFERS * self.size);
+        // SAFETY: `base` came from Box::into_raw of a slice of this length
+        drop(unsafe { Box::from_raw(memory) });
+    }
+}
+
+/// A registered buffer leased from this thread's table
+///
+/// Holds `len()` bytes of data (up to `capacity()`); returned to the table
+/// when dropped. Only usable on the thread that leased it.
+pub struct FixedBuf {
+    /// Table the buffer belongs to
+    table: Rc<Table>,
+    /// Index in the table
+    index: u16,
+    /// Bytes of data
+    len: usize,
+}
+
+impl FixedBuf {
+    /// Size of the buffer
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.table.size
+    }
+
+    /// Set the number of bytes of data (at most `capacity()`); the contents
+    /// of any bytes added are unspecified
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.capacity());
+    }
+
+    /// Start of the buffer
+    fn as_mut_ptr(&self) -> *mut u8 {
+        self.table.buffer(self.index)
+    }
+}
+
+impl std::ops::Deref for FixedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the lease gives this FixedBuf sole use of the buffer, and
+        // len <= capacity
+        unsafe { std::slice::from_raw_parts(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for FixedBuf {
+    fn drop(&mut self) {
+        self.table.free.borrow_mut().push(self.index);
+    }
+}
+
+/// A registered buffer of at least `size` bytes, holding `size` bytes of
+/// unspecified data, or `None` if there is none to lease on this thread
+///
+/// The first call on a thread registers the thread's buffers with `size`
+/// bytes each.
+#[must_use]
+pub fn take(size: usize) -> Option<FixedBuf> {
+    if size == 0 || size > u32::MAX as usize {
+        return None;
+    }
+    TABLE.with(|state| {
+        let mut state = state.borrow_mut();
+        if matches!(*state, TableState::Unregistered) {
+            *state = match Table::register(size) {
+                Ok(table) => TableState::Registered(Rc::new(table)),
+                Err(_) => TableState::Unavailable,
+            };
+        }
+        let TableState::Registered(table) = &*state else {
+            return None;
+        };
+        if size > table.size {
+            return None;
+        }
+        let index = table.free.borrow_mut().pop()?;
+        Some(FixedBuf {
+            table: Rc::clone(table),
+            index,
+            len: size,
+        })
+    })
+}
+
+/// `IORING_OP_READ_FIXED` into a registered buffer
+struct ReadFixedOp {
+    /// File to read
+    fd: RawFd,
+    /// Buffer read into (its `len()` bytes at most)
+    buf: FixedBuf,
+    /// File offset
+    offset: u64,
+}
+
+impl OpCode for ReadFixedOp {
+    fn create_entry(self: Pin<&mut Self>) -> compio::driver::OpEntry {
+        compio::driver::OpEntry::Submission(
+            opcode::ReadFixed::new(
+                types::Fd(self.fd),
+                self.buf.as_mut_ptr(),
+                self.buf.len as u32,
+                self.buf.index,
+            )
+            .offset(self.offset)
+            .build(),
+        )
+    }
+}
+
+/// `IORING_OP_WRITE_FIXED` from a registered buffer
+struct WriteFixedOp {
+    /// File to write
+    fd: RawFd,
+    /// Buffer written (its `len()` bytes)
+    buf: FixedBuf,
+    /// File offset
+    offset: u64,
+}
+
+impl OpCode for WriteFixedOp {
+    fn create_entry(self: Pin<&mut Self>) -> compio::driver::OpEntry {
+        compio::driver::OpEntry::Submission(
+            opcode::WriteFixed::new(
+                types::Fd(self.fd),
+                self.buf.as_mut_ptr().cast_const(),
+                self.buf.len as u32,
+                self.buf.index,
+            )
+            .offset(self.offset)
+            .build(),
+        )
+    }
+}
+
+/// Read up to `buf.len()` bytes at `offset` into `buf`
+///
+/// Like `read_at`: returns the bytes read, and the buffer with its length
+/// set to them.
+pub async fn read_fixed_at(file: &File, buf: FixedBuf, offset: u64) -> BufResult<usize, FixedBuf> {
+    let op = ReadFixedOp {
+        fd: file.as_raw_fd(),
+        buf,
+        offset,
+    };
+    let BufResult(result, op) = submit(op).await;
+    let mut buf = op.buf;
+    if let Ok(read) = result {
+        buf.set_len(read);
+    }
+    BufResult(result, buf)
+}
+
+/// Write the `buf.len()` bytes of `buf` at `offset`
+///
+/// Like `write_at`: returns the bytes written (possibly fewer) and the
+/// buffer unchanged.
+pub async fn write_fixed_at(file: &File, buf: FixedBuf, offset: u64) -> BufResult<usize, FixedBuf> {
+    let op = WriteFixedOp {
+        fd: file.as_raw_fd(),
+        buf,
+        offset,
+    };
+    let BufResult(result, op) = submit(op).await;
+    BufResult(result, op.buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_fixed_buffers_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("data");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        // Registration may be refused (memlock limit); callers fall back
+        let Some(buffer) = take(4096) else {
+            assert!(take(4096).is_none());
+            return;
+        };
+        assert_eq!(buffer.capacity(), 4096);
+        assert!(take(8192).is_none(), "larger than the registered size");
+
+        let src = File::open(&path).await.unwrap();
+        let BufResult(read, buffer) = read_fixed_at(&src, buffer, 8192).await;
+        assert_eq!(read.unwrap(), 10_000 - 8192);
+        assert_eq!(&buffer[..], &data[8192..]);
+
+        let dst_path = temp_dir.path().join("copy");
+        let dst = File::create(&dst_path).await.unwrap();
+        let BufResult(written, buffer) = write_fixed_at(&dst, buffer, 0).await;
+        assert_eq!(written.unwrap(), 10_000 - 8192);
+        assert_eq!(std::fs::read(&dst_path).unwrap(), &data[8192..]);
+
+        // Leases go back to the table when dropped
+        let leased: Vec<FixedBuf> = std::iter::from_fn(|| take(4096)).collect();
+        assert_eq!(leased.len(), REGISTERED_BUFFERS - 1);
+        drop(buffer);
+        assert!(take(4096).is_some());
+    }
+}
@@ -24,6 +24,8 @@
 //! Our implementation uses the same behavior on both platforms by passing 0 for macOS's
 //! extra parameters, making it functionally equivalent to Linux's simpler API.
 
+#[cfg(target_os = "linux")]
+use crate::directory::DirectoryFd;
 use crate::error::{xattr_error, Result};
 
 /// macOS-specific constant for xattr operations on symlinks
@@ -167,6 +169,10 @@ impl GetXattrOp {
 #[cfg(target_os = "linux")]
 impl OpCode for GetXattrOp {
     fn create_entry(mut self: Pin<&mut Self>) -> compio::driver::OpEntry {
+        // Kernels before 5.19 have no IORING_OP_FGETXATTR
+        if !crate::uring_probe::is_supported(opcode::FGetXattr::CODE) {
+            return compio::driver::OpEntry::Blocking;
+        }
         compio::driver::OpEntry::Submission(
             opcode::FGetXattr::new(
                 types::Fd(self.fd),
@@ -177,6 +183,22 @@ impl OpCode for GetXattrOp {
             .build(),
         )
     }
+
+    fn call_blocking(mut self: Pin<&mut Self>) -> std::io::Result<usize> {
+        // SAFETY: the name is NUL-terminated and the buffer outlives the call
+        let size = unsafe {
+            libc::fgetxattr(
+                self.fd,
+                self.name.as_ptr(),
+                self.buffer.as_mut_ptr() as *mut libc::c_void,
+                self.buffer.len(),
+            )
+        };
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
 }
 
 /// io_uring setxattr operation
@@ -201,6 +223,10 @@ impl SetXattrOp {
 #[cfg(target_os = "linux")]
 impl OpCode for SetXattrOp {
     fn create_entry(self: Pin<&mut Self>) -> compio::driver::OpEntry {
+        // Kernels before 5.19 have no IORING_OP_FSETXATTR
+        if !crate::uring_probe::is_supported(opcode::FSetXattr::CODE) {
+            return compio::driver::OpEntry::Blocking;
+        }
         compio::driver::OpEntry::Submission(
             opcode::FSetXattr::new(
                 types::Fd(self.fd),
@@ -212,6 +238,23 @@ impl OpCode for SetXattrOp {
             .build(),
         )
     }
+
+    fn call_blocking(self: Pin<&mut Self>) -> std::io::Result<usize> {
+        // SAFETY: the name is NUL-terminated and the value outlives the call
+        let ret = unsafe {
+            libc::fsetxattr(
+                self.fd,
+                self.name.as_ptr(),
+                self.value.as_ptr() as *const libc::c_void,
+                self.value.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(0)
+    }
 }
 
 /// Implementation of xattr operations using io_uring opcodes
@@ -1002,6 +1045,238 @@ pub async fn lremove_xattr_at_path(path: &Path, name: &str) -> Result<()> {
 
 // Windows: lremove_xattr_at_path not defined - compile-time error
 
+// ============================================================================
+// Name-relative operations (DirectoryFd children)
+// ============================================================================
+//
+// Linux has no `getxattrat`-style calls before 6.13, and `fgetxattr` refuses
+// `O_PATH` descriptors. The child is therefore opened with
+// `openat(dirfd, name, O_PATH | O_NOFOLLOW)` - which reads no data, needs no
+// read permission and works on symlinks - and the path-based calls are made
+// on its `/proc/self/fd/N` magic link. Following that link lands on the
+// opened inode itself (a symlink is not followed any further), so the whole
+// operation refers to the entry found by the `openat`, whatever is renamed
+// into place afterwards.
+
+/// `O_PATH` handle to one child of a directory, for xattr calls
+#[cfg(target_os = "linux")]
+struct EntryHandle {
+    /// The `O_PATH` descriptor (closed on drop)
+    _fd: std::os::fd::OwnedFd,
+    /// `/proc/self/fd/N` for `_fd`
+    proc_path: CString,
+}
+
+#[cfg(target_os = "linux")]
+impl EntryHandle {
+    /// Open `name` relative to `dir_fd` without following a final symlink
+    fn open(dir_fd: std::os::unix::io::RawFd, name: &std::ffi::OsStr) -> Result<Self> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        use std::os::unix::ffi::OsStrExt;
+
+        let name_cstr = CString::new(name.as_bytes())
+            .map_err(|e| xattr_error(&format!("Invalid name: {}", e)))?;
+        // SAFETY: name_cstr is NUL-terminated; dir_fd is an open directory
+        let fd = unsafe {
+            libc::openat(
+                dir_fd,
+                name_cstr.as_ptr(),
+                libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            let errno = std::io::Error::last_os_error();
+            return Err(xattr_error(&format!("openat O_PATH failed: {}", errno)));
+        }
+        // SAFETY: fd was just returned by openat and is owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let proc_path = CString::new(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+            .map_err(|e| xattr_error(&format!("Invalid proc path: {}", e)))?;
+        Ok(Self { _fd: fd, proc_path })
+    }
+
+    /// Value of `name`
+    fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let name_cstr =
+            CString::new(name).map_err(|e| xattr_error(&format!("Invalid name: {}", e)))?;
+        loop {
+            // SAFETY: both strings are NUL-terminated; a null buffer asks for the size
+            let size = unsafe {
+                libc::getxattr(
+                    self.proc_path.as_ptr(),
+                    name_cstr.as_ptr(),
+                    std::ptr::null_mut(),
+                    0,
+                )
+            };
+            if size < 0 {
+                let errno = std::io::Error::last_os_error();
+                return Err(xattr_error(&format!("getxattr failed: {}", errno)));
+            }
+            let mut buffer = vec![0u8; size as usize];
+            // SAFETY: buffer is valid for buffer.len() bytes
+            let actual_size = unsafe {
+                libc::getxattr(
+                    self.proc_path.as_ptr(),
+                    name_cstr.as_ptr(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if actual_size >= 0 {
+                buffer.truncate(actual_size as usize);
+                return Ok(buffer);
+            }
+            let errno = std::io::Error::last_os_error();
+            // The value grew between the two calls: ask for the size again
+            if errno.raw_os_error() != Some(libc::ERANGE) {
+                return Err(xattr_error(&format!("getxattr failed: {}", errno)));
+            }
+        }
+    }
+
+    /// Set `name` to `value`
+    fn set(&self, name: &str, value: &[u8]) -> Result<()> {
+        let name_cstr =
+            CString::new(name).map_err(|e| xattr_error(&format!("Invalid name: {}", e)))?;
+        // SAFETY: both strings are NUL-terminated; value is valid for its length
+        let ret = unsafe {
+            libc::setxattr(
+                self.proc_path.as_ptr(),
+                name_cstr.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let errno = std::io::Error::last_os_error();
+            return Err(xattr_error(&format!("setxattr failed: {}", errno)));
+        }
+        Ok(())
+    }
+
+    /// Names of all attributes
+    fn list(&self) -> Result<Vec<String>> {
+        loop {
+            // SAFETY: proc_path is NUL-terminated; a null buffer asks for the size
+            let size = unsafe { libc::listxattr(self.proc_path.as_ptr(), std::ptr::null_mut(), 0) };
+            if size < 0 {
+                let errno = std::io::Error::last_os_error();
+                return Err(xattr_error(&format!("listxattr failed: {}", errno)));
+            }
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+            let mut buffer = vec![0u8; size as usize];
+            // SAFETY: buffer is valid for buffer.len() bytes
+            let actual_size = unsafe {
+                libc::listxattr(
+                    self.proc_path.as_ptr(),
+                    buffer.as_mut_ptr() as *mut libc::c_char,
+                    buffer.len(),
+                )
+            };
+            if actual_size >= 0 {
+                buffer.truncate(actual_size as usize);
+                return Ok(buffer
+                    .split(|&byte| byte == 0)
+                    .filter(|name| !name.is_empty())
+                    .filter_map(|name| String::from_utf8(name.to_vec()).ok())
+                    .collect());
+            }
+            let errno = std::io::Error::last_os_error();
+            if errno.raw_os_error() != Some(libc::ERANGE) {
+                return Err(xattr_error(&format!("listxattr failed: {}", errno)));
+            }
+        }
+    }
+}
+
+/// Run a name-relative xattr operation on the child `name` of `dir`
+#[cfg(target_os = "linux")]
+async fn with_entry<T, F>(dir: &DirectoryFd, name: &std::ffi::OsStr, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&EntryHandle) -> Result<T> + Send + 'static,
+{
+    use std::os::unix::io::AsRawFd;
+
+    let dir_fd = dir.as_raw_fd();
+    let name = name.to_owned();
+    let operation = move || f(&EntryHandle::open(dir_fd, &name)?);
+
+    #[cfg(feature = "cheap_calls_sync")]
+    {
+        operation()
+    }
+
+    #[cfg(not(feature = "cheap_calls_sync"))]
+    {
+        compio::runtime::spawn_blocking(operation)
+            .await
+            .map_err(|e| {
+                crate::error::ExtendedError::SpawnJoin(format!("spawn_blocking failed: {:?}", e))
+            })?
+    }
+}
+
+/// Get an extended attribute of a child of `dir` (not following symlinks)
+#[cfg(target_os = "linux")]
+pub(crate) async fn lget_xattr_at_impl(
+    dir: &DirectoryFd,
+    name: &std::ffi::OsStr,
+    attr: &str,
+) -> Result<Vec<u8>> {
+    let attr = attr.to_string();
+    with_entry(dir, name, move |entry| entry.get(&attr)).await
+}
+
+/// Set an extended attribute of a child of `dir` (not following symlinks)
+#[cfg(target_os = "linux")]
+pub(crate) async fn lset_xattr_at_impl(
+    dir: &DirectoryFd,
+    name: &std::ffi::OsStr,
+    attr: &str,
+    value: &[u8],
+) -> Result<()> {
+    let (attr, value) = (attr.to_string(), value.to_vec());
+    with_entry(dir, name, move |entry| entry.set(&attr, &value)).await
+}
+
+/// List the extended attributes of a child of `dir` (not following symlinks)
+#[cfg(target_os = "linux")]
+pub(crate) async fn llist_xattr_at_impl(
+    dir: &DirectoryFd,
+    name: &std::ffi::OsStr,
+) -> Result<Vec<String>> {
+    with_entry(dir, name, EntryHandle::list).await
+}
+
+/// Read every extended attribute of a child of `dir` with a single open
+///
+/// Attributes that vanish or cannot be read between listing and reading are
+/// returned as errors next to their names, so the caller decides how to report
+/// them.
+#[cfg(target_os = "linux")]
+#[allow(clippy::type_complexity)]
+pub(crate) async fn lget_all_xattrs_at_impl(
+    dir: &DirectoryFd,
+    name: &std::ffi::OsStr,
+) -> Result<Vec<(String, Result<Vec<u8>>)>> {
+    with_entry(dir, name, |entry| {
+        Ok(entry
+            .list()?
+            .into_iter()
+            .map(|attr| {
+                let value = entry.get(&attr);
+                (attr, value)
+            })
+            .collect())
+    })
+    .await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
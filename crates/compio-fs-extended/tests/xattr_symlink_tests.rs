@@ -141,3 +141,55 @@ async fn test_xattr_api_differences() {
         println!("Filesystem supports symlink xattrs");
     }
 }
+
+/// Test that DirectoryFd name-relative xattr calls address the child itself
+///
+/// A regular child gets and lists its own attributes; a symlink child is not
+/// followed to its target.
+#[compio::test]
+#[cfg(target_os = "linux")]
+async fn test_directoryfd_xattr_at_does_not_follow_symlinks() {
+    use compio_fs_extended::DirectoryFd;
+    use std::ffi::OsStr;
+
+    let temp_dir = TempDir::new().unwrap();
+    let target_path = temp_dir.path().join("target.txt");
+    std::fs::write(&target_path, "target content").unwrap();
+    std::os::unix::fs::symlink(&target_path, temp_dir.path().join("link.txt")).unwrap();
+    let dir = DirectoryFd::open(temp_dir.path()).await.unwrap();
+
+    if dir
+        .lset_xattr_at(OsStr::new("target.txt"), "user.type", b"target_file")
+        .await
+        .is_err()
+    {
+        println!("Extended attributes not supported on this filesystem - test skipped");
+        return;
+    }
+    assert_eq!(
+        dir.lget_xattr_at(OsStr::new("target.txt"), "user.type")
+            .await
+            .unwrap(),
+        b"target_file"
+    );
+    assert_eq!(
+        get_xattr_at_path(&target_path, "user.type").await.unwrap(),
+        b"target_file"
+    );
+    let all = dir
+        .lget_all_xattrs_at(OsStr::new("target.txt"))
+        .await
+        .unwrap();
+    assert!(all
+        .iter()
+        .any(|(name, value)| name == "user.type"
+            && value.as_deref().ok() == Some(&b"target_file"[..])));
+
+    // The symlink itself has no user.type, whatever its target has
+    let link_names = dir.llist_xattr_at(OsStr::new("link.txt")).await.unwrap();
+    assert!(!link_names.contains(&"user.type".to_string()));
+    assert!(dir
+        .lget_xattr_at(OsStr::new("link.txt"), "user.type")
+        .await
+        .is_err());
+}
@@ -0,0 +1,587 @@
+//! Atomic creation of destination files (`--atomic-create`)
+//!
+//! By default a destination file is opened at its final name and written in
+//! place, so readers of the destination can see it half-written. With
+//! `--atomic-create` the file only appears at its name once its data and
+//! metadata are complete:
+//!
+//! - `tmpfile`: the file is created anonymously with `O_TMPFILE` and given its
+//!   name with `linkat(2)` at the end. No temporary name is ever visible, and
+//!   an interrupted copy leaves nothing behind. Filesystems without
+//!   `O_TMPFILE` fall back to `rename`.
+//! - `rename`: the file is written under a run-stamped temporary name (see
+//!   `temp_files`) and renamed over the final name at the end. Leftovers of a
+//!   crashed run are removed by `arsync cleanup`.
+//!
+//! Replacing an existing destination is atomic in both modes: `linkat` cannot
+//! overwrite, so a tmpfile is linked at a temporary name and renamed.
+//!
+//! `--partial` also writes under another name and renames at the end, but the
+//! name (`.arsync.partial.<name>`) is stable and the file is kept when the copy
+//! fails or the run is killed. It is stamped with the source's size and
+//! modification time in a `user.arsync.partial` xattr, and the next run
+//! continues it from its current length if the stamp still matches; otherwise
+//! (or without user xattr support) it starts over.
+//!
+//! `--delay-updates` writes under a temporary name too, but leaves the
+//! rename to the end of the run (see `delay_updates`).
+
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::metadata::MetadataConfig;
+use crate::run_state::RunState;
+use crate::temp_files::{partial_name, temp_name};
+use compio::fs::File;
+use compio_fs_extended::{DirectoryFd, FileMetadata, Timestamp};
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How destination files are created atomically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AtomicCreate {
+    /// Anonymous `O_TMPFILE` file, named with `linkat` when complete
+    Tmpfile,
+    /// Temporary name, renamed over the final name when complete
+    Rename,
+}
+
+/// Set once the fallback from `tmpfile` to `rename` has been reported
+static TMPFILE_FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Xattr recording which version of the source a partial file holds
+const PARTIAL_XATTR: &std::ffi::CStr = c"user.arsync.partial";
+
+/// Where a destination file is being written until `commit()`
+#[derive(Debug)]
+enum Staging {
+    /// At its final name
+    InPlace,
+    /// Anonymous `O_TMPFILE` file
+    Tmpfile,
+    /// Under this temporary name
+    Renamed(OsString),
+    /// Under this partial file name, kept if the copy does not complete
+    Partial(OsString),
+    /// Under this temporary name, renamed at the end of this run
+    /// (`--delay-updates`)
+    Delayed(OsString, Arc<RunState>),
+}
+
+/// Destination file being written, not yet visible at its final name
+///
+/// Dropping it without `commit()` removes the temporary name, if any.
+#[derive(Debug)]
+pub struct StagedFile {
+    /// Directory the file is created in
+    dir: DirectoryFd,
+    /// Final name
+    name: OsString,
+    /// Current location
+    staging: Staging,
+}
+
+impl StagedFile {
+    /// Create the destination file `name` in `dir`
+    ///
+    /// With `mode == None` the file is opened (and truncated) at its final name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the file cannot be created.
+    pub async fn create(
+        dir: &DirectoryFd,
+        name: &OsStr,
+        mode: Option<AtomicCreate>,
+        dst: &Path,
+    ) -> Result<(File, Self)> {
+        let staged = |staging| Self {
+            dir: dir.clone(),
+            name: name.to_os_string(),
+            staging,
+        };
+
+        match mode {
+            None => {
+                let file = dir
+                    .open_file_at(name, false, true, true, true)
+                    .await
+                    .map_err(|e| create_error("openat destination", dst, dir, &e))?;
+                return Ok((file, staged(Staging::InPlace)));
+            }
+            Some(AtomicCreate::Tmpfile) => match open_tmpfile(dir.as_raw_fd()).await {
+                Ok(file) => return Ok((file, staged(Staging::Tmpfile))),
+                Err(e) if tmpfile_unsupported(&e) => {
+                    if !TMPFILE_FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                        tracing::warn!(
+                            "O_TMPFILE is not supported at {} ({e}); using temporary names instead",
+                            dir.path().display()
+                        );
+                    }
+                }
+                Err(e) => return Err(create_error("open O_TMPFILE destination", dst, dir, &e)),
+            },
+            Some(AtomicCreate::Rename) => {}
+        }
+
+        let temp = temp_name(name);
+        let file = dir
+            .open_file_at(&temp, true, true, true, true)
+            .await
+            .map_err(|e| create_error("openat temporary destination", dst, dir, &e))?;
+        Ok((file, staged(Staging::Renamed(temp))))
+    }
+
+    /// Create the destination file `name` in `dir` as `config` asks: staged
+    /// until the end of the run with `--delay-updates`, otherwise as
+    /// `--atomic-create` says
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the file cannot be created.
+    pub async fn create_for(
+        dir: &DirectoryFd,
+        name: &OsStr,
+        config: &MetadataConfig,
+        dst: &Path,
+    ) -> Result<(File, Self)> {
+        if !config.delay_updates {
+            return Self::create(dir, name, config.atomic_create, dst).await;
+        }
+        let temp = temp_name(name);
+        let file = dir
+            .open_file_at(&temp, true, true, true, true)
+            .await
+            .map_err(|e| create_error("openat delayed destination", dst, dir, &e))?;
+        let staged = Self {
+            dir: dir.clone(),
+            name: name.to_os_string(),
+            staging: Staging::Delayed(temp, Arc::clone(&config.run)),
+        };
+        Ok((file, staged))
+    }
+
+    /// Create or continue the partial file for `name` in `dir` (`--partial`)
+    ///
+    /// Returns the file with the offset to continue copying from: the length
+    /// of a partial file left by an earlier run for the same `source`, or 0
+    /// after starting it over.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the partial file cannot be opened or
+    /// truncated.
+    pub async fn create_partial(
+        dir: &DirectoryFd,
+        name: &OsStr,
+        source: &FileMetadata,
+        dst: &Path,
+    ) -> Result<(File, Self, u64)> {
+        let partial = partial_name(name);
+        let file = dir
+            .open_file_at(&partial, true, true, true, false)
+            .await
+            .map_err(|e| create_error("openat partial destination", dst, dir, &e))?;
+
+        let modified = Timestamp::from(source.modified);
+        let stamp = format!("{} {}.{:09}", source.size, modified.secs, modified.nsecs);
+        let (fd, size) = (file.as_raw_fd(), source.size);
+        // `file` outlives the blocking call, keeping the fd open
+        let offset =
+            compio::runtime::spawn_blocking(move || resume_offset(fd, stamp.as_bytes(), size))
+                .await
+                .map_err(|_| SyncError::Internal("Partial file worker panicked".to_string()))?
+                .map_err(|e| create_error("prepare partial destination", dst, dir, &e))?;
+
+        let staged = Self {
+            dir: dir.clone(),
+            name: name.to_os_string(),
+            staging: Staging::Partial(partial),
+        };
+        Ok((file, staged, offset))
+    }
+
+    /// Open the written data for reading (`--verify-direct`, `--emit-checksums`)
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the file cannot be reopened.
+    pub async fn reopen_for_read(&self, file: &File, dst: &Path) -> Result<File> {
+        let reader = match &self.staging {
+            Staging::InPlace => self.dir.open_file_at(&self.name, true, false, false, false),
+            Staging::Renamed(temp) | Staging::Partial(temp) | Staging::Delayed(temp, _) => {
+                self.dir.open_file_at(temp, true, false, false, false)
+            }
+            Staging::Tmpfile => {
+                // An O_TMPFILE file has no name; reopen it through /proc
+                let proc_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+                return File::open(&proc_path).await.map_err(|e| {
+                    ErrorContext::new("reopen O_TMPFILE destination for reading")
+                        .destination(dst)
+                        .io_cause(&e)
+                        .file_system()
+                });
+            }
+        };
+        reader.await.map_err(|e| {
+            ErrorContext::new("openat destination for reading")
+                .destination(dst)
+                .dirfd(self.dir.path())
+                .cause(&e)
+                .file_system()
+        })
+    }
+
+    /// Make the completely written `file` visible at its final name
+    ///
+    /// With `--delay-updates` that is left to the end of the run (see
+    /// `delay_updates`).
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the file cannot be linked or renamed
+    /// into place.
+    pub async fn commit(mut self, file: &File, dst: &Path) -> Result<()> {
+        let staging = std::mem::replace(&mut self.staging, Staging::InPlace);
+        if let Staging::Delayed(temp, run) = staging {
+            run.delayed.defer(self.dir.path().join(&self.name), temp);
+            return Ok(());
+        }
+        let dir_fd = self.dir.as_raw_fd();
+        let file_fd = file.as_raw_fd();
+        let name = self.name.clone();
+        // `self.dir` and `file` outlive the blocking call, keeping both fds open
+        let result = compio::runtime::spawn_blocking(move || match staging {
+            Staging::InPlace | Staging::Delayed(..) => Ok(()),
+            Staging::Renamed(temp) => rename_at(dir_fd, &temp, &name).inspect_err(|_| {
+                let _ = unlink_at(dir_fd, &temp);
+            }),
+            Staging::Tmpfile => materialize_tmpfile(file_fd, dir_fd, &name),
+            Staging::Partial(partial) => {
+                // The stamp must not survive on the finished file; if the
+                // rename fails the partial file is kept for the next run
+                remove_partial_stamp(file_fd)?;
+                rename_at(dir_fd, &partial, &name)
+            }
+        })
+        .await
+        .map_err(|_| SyncError::Internal("Destination commit worker panicked".to_string()))?;
+
+        result.map_err(|e| {
+            ErrorContext::new("move destination into place")
+                .destination(dst)
+                .dirfd(self.dir.path())
+                .io_cause(&e)
+                .file_system()
+        })
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        if let Staging::Renamed(temp) | Staging::Delayed(temp, _) = &self.staging {
+            let _ = unlink_at(self.dir.as_raw_fd(), temp);
+        }
+    }
+}
+
+/// Offset from which the partial file `fd` can be continued
+///
+/// A partial file stamped with `stamp` (the same source version) that is no
+/// longer than the source keeps its data; anything else is truncated and
+/// stamped. If the stamp cannot be written (no user xattrs on this
+/// filesystem) the file is still written, but a later run starts it over.
+fn resume_offset(fd: RawFd, stamp: &[u8], source_size: u64) -> io::Result<u64> {
+    // SAFETY: the caller keeps fd open; stat is plain old data
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // SAFETY: stat points to a valid buffer
+    if unsafe { libc::fstat(fd, &raw mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let length = u64::try_from(stat.st_size).unwrap_or(0);
+
+    let mut current = [0u8; 64];
+    // SAFETY: current has room for `current.len()` bytes; the name is NUL-terminated
+    let read = unsafe {
+        libc::fgetxattr(
+            fd,
+            PARTIAL_XATTR.as_ptr(),
+            current.as_mut_ptr().cast(),
+            current.len(),
+        )
+    };
+    if usize::try_from(read).is_ok_and(|read| &current[..read] == stamp) && length <= source_size {
+        return Ok(length);
+    }
+
+    // SAFETY: fd is open for writing
+    if unsafe { libc::ftruncate(fd, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: stamp holds `stamp.len()` initialized bytes
+    if unsafe {
+        libc::fsetxattr(
+            fd,
+            PARTIAL_XATTR.as_ptr(),
+            stamp.as_ptr().cast(),
+            stamp.len(),
+            0,
+        )
+    } != 0
+    {
+        tracing::debug!(
+            "Cannot stamp partial file ({}); it will not be resumable",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(0)
+}
+
+/// Remove the `--partial` stamp from a completed file
+fn remove_partial_stamp(fd: RawFd) -> io::Result<()> {
+    // SAFETY: the caller keeps fd open; the name is NUL-terminated
+    if unsafe { libc::fremovexattr(fd, PARTIAL_XATTR.as_ptr()) } == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENODATA | libc::EOPNOTSUPP) => Ok(()),
+        _ => Err(error),
+    }
+}
+
+/// Error for a destination file that cannot be created
+fn create_error(
+    operation: &'static str,
+    dst: &Path,
+    dir: &DirectoryFd,
+    cause: &impl std::fmt::Display,
+) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(dst)
+        .dirfd(dir.path())
+        .cause(cause)
+        .file_system()
+}
+
+/// Whether an `O_TMPFILE` open failed because the filesystem (or kernel) lacks it
+fn tmpfile_unsupported(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EOPNOTSUPP | libc::EISDIR | libc::EINVAL)
+    )
+}
+
+/// Open an anonymous file in `dir_fd` with `O_TMPFILE`
+async fn open_tmpfile(dir_fd: RawFd) -> io::Result<File> {
+    compio::runtime::spawn_blocking(move || {
+        // SAFETY: the caller's DirectoryFd keeps dir_fd open; "." is a valid C string
+        let fd = unsafe {
+            libc::openat(
+                dir_fd,
+                c".".as_ptr(),
+                libc::O_TMPFILE | libc::O_RDWR | libc::O_CLOEXEC,
+                0o644,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: we just created this fd and own it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    })
+    .await
+    .map_err(|_| io::Error::other("O_TMPFILE open worker panicked"))?
+}
+
+/// Give the `O_TMPFILE` file `file_fd` the name `name` in `dir_fd`
+///
+/// An existing file at `name` is replaced atomically (link at a temporary
+/// name, then rename).
+fn materialize_tmpfile(file_fd: RawFd, dir_fd: RawFd, name: &OsStr) -> io::Result<()> {
+    match link_tmpfile(file_fd, dir_fd, name) {
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+            let temp = temp_name(name);
+            link_tmpfile(file_fd, dir_fd, &temp)?;
+            rename_at(dir_fd, &temp, name).inspect_err(|_| {
+                let _ = unlink_at(dir_fd, &temp);
+            })
+        }
+        result => result,
+    }
+}
+
+/// `linkat` an `O_TMPFILE` file to `name` in `dir_fd`
+///
+/// `AT_EMPTY_PATH` needs `CAP_DAC_READ_SEARCH`; without it the kernel reports
+/// `ENOENT` and the `/proc/self/fd` link is used instead.
+fn link_tmpfile(file_fd: RawFd, dir_fd: RawFd, name: &OsStr) -> io::Result<()> {
+    let name = c_name(name)?;
+    // SAFETY: both fds are open; the paths are valid C strings
+    if unsafe {
+        libc::linkat(
+            file_fd,
+            c"".as_ptr(),
+            dir_fd,
+            name.as_ptr(),
+            libc::AT_EMPTY_PATH,
+        )
+    } == 0
+    {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    if !matches!(error.raw_os_error(), Some(libc::ENOENT | libc::EPERM)) {
+        return Err(error);
+    }
+    let proc_path = CString::new(format!("/proc/self/fd/{file_fd}"))?;
+    // SAFETY: as above
+    if unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            proc_path.as_ptr(),
+            dir_fd,
+            name.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    } == 0
+    {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// `renameat` within one directory
+fn rename_at(dir_fd: RawFd, from: &OsStr, to: &OsStr) -> io::Result<()> {
+    let (from, to) = (c_name(from)?, c_name(to)?);
+    // SAFETY: dir_fd is open; the names are valid C strings
+    if unsafe { libc::renameat(dir_fd, from.as_ptr(), dir_fd, to.as_ptr()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// `unlinkat` a file in `dir_fd`
+pub(crate) fn unlink_at(dir_fd: RawFd, name: &OsStr) -> io::Result<()> {
+    let name = c_name(name)?;
+    // SAFETY: dir_fd is open; the name is a valid C string
+    if unsafe { libc::unlinkat(dir_fd, name.as_ptr(), 0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// File name as a C string
+fn c_name(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use compio::io::AsyncWriteAtExt;
+    use tempfile::TempDir;
+
+    async fn write_staged(dir: &Path, name: &str, mode: AtomicCreate, data: &'static [u8]) {
+        let dir_fd = DirectoryFd::open(dir).await.unwrap();
+        let dst = dir.join(name);
+        let (mut file, staged) = StagedFile::create(&dir_fd, OsStr::new(name), Some(mode), &dst)
+            .await
+            .unwrap();
+        file.write_all_at(data, 0).await.0.unwrap();
+
+        // Nothing is visible at the final name before the commit
+        if !dst.exists() {
+            assert_eq!(
+                std::fs::read_dir(dir).unwrap().count(),
+                usize::from(mode == AtomicCreate::Rename)
+            );
+        }
+        staged.commit(&file, &dst).await.unwrap();
+    }
+
+    #[compio::test]
+    async fn test_atomic_create_modes_create_and_replace() {
+        // Requirement: Files appear only when complete and replace existing ones
+        for mode in [AtomicCreate::Tmpfile, AtomicCreate::Rename] {
+            let temp_dir = TempDir::new().unwrap();
+            write_staged(temp_dir.path(), "f", mode, b"first").await;
+            assert_eq!(std::fs::read(temp_dir.path().join("f")).unwrap(), b"first");
+
+            write_staged(temp_dir.path(), "f", mode, b"second").await;
+            assert_eq!(std::fs::read(temp_dir.path().join("f")).unwrap(), b"second");
+            assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        }
+    }
+
+    #[compio::test]
+    async fn test_uncommitted_rename_leaves_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_fd = DirectoryFd::open(temp_dir.path()).await.unwrap();
+        let dst = temp_dir.path().join("f");
+        let (_file, staged) =
+            StagedFile::create(&dir_fd, OsStr::new("f"), Some(AtomicCreate::Rename), &dst)
+                .await
+                .unwrap();
+        drop(staged);
+
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[compio::test]
+    async fn test_partial_file_kept_and_resumed() {
+        // Requirement: An uncommitted partial file survives and is continued
+        // from its length while the source is unchanged, then renamed into
+        // place without its stamp; a changed source starts it over
+        let temp_dir = TempDir::new().unwrap();
+        let dir_fd = DirectoryFd::open(temp_dir.path()).await.unwrap();
+        let dst = temp_dir.path().join("f");
+        std::fs::write(temp_dir.path().join("src"), b"0123456789").unwrap();
+        let source = dir_fd.statx_full(OsStr::new("src")).await.unwrap();
+
+        let (mut file, staged, offset) =
+            StagedFile::create_partial(&dir_fd, OsStr::new("f"), &source, &dst)
+                .await
+                .unwrap();
+        assert_eq!(offset, 0);
+        file.write_all_at(&b"0123"[..], 0).await.0.unwrap();
+        drop((file, staged));
+        let partial = temp_dir.path().join(".arsync.partial.f");
+        assert_eq!(std::fs::read(&partial).unwrap(), b"0123");
+
+        let (mut file, staged, offset) =
+            StagedFile::create_partial(&dir_fd, OsStr::new("f"), &source, &dst)
+                .await
+                .unwrap();
+        if offset == 0 {
+            eprintln!("Skipping resume checks: no user xattrs on this filesystem");
+            return;
+        }
+        assert_eq!(offset, 4);
+        file.write_all_at(&b"456789"[..], 4).await.0.unwrap();
+        staged.commit(&file, &dst).await.unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"0123456789");
+        assert!(!partial.exists());
+        let ours = compio_fs_extended::xattr::list_xattr_at_path(&dst)
+            .await
+            .unwrap_or_default();
+        assert!(!ours.iter().any(|name| name == "user.arsync.partial"));
+
+        // A partial file of another source version is truncated
+        std::fs::write(&partial, b"stale").unwrap();
+        let (_file, _staged, offset) =
+            StagedFile::create_partial(&dir_fd, OsStr::new("f"), &source, &dst)
+                .await
+                .unwrap();
+        assert_eq!(offset, 0);
+        assert_eq!(std::fs::metadata(&partial).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,933 @@
+//! Experimental bidirectional sync (`arsync bisync A B`)
+//!
+//! Keeps two local directory trees (which may be network mounts of different
+//! machines) in sync in both directions. A state database records, for every
+//! file seen in sync on both sides, its size, content hash and the mtime it
+//! had on each side. On the next run each side is compared with that record:
+//!
+//! - changed on one side only: the change (including a deletion) is copied to
+//!   the other side;
+//! - changed on both sides: a conflict, resolved by `ConflictPolicy`;
+//! - new on both sides with identical content: simply recorded.
+//!
+//! An mtime change whose content hash still matches the record (a `touch`) is
+//! not a change. A modification always wins over a deletion on the other side,
+//! whatever the policy, so a conflict never loses data.
+//!
+//! Only regular files are synced; directories are created as needed (but not
+//! removed), and symlinks and special files are skipped with a warning. Both
+//! roots are locked (see `dest_lock`) for the duration of the run.
+
+use crate::dest_lock::{DestinationLock, LOCK_FILE_NAME};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::temp_files::{parse_temp_name, temp_name};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::Read;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Default name of the state database, created in the root of side A
+pub const STATE_FILE_NAME: &str = ".arsync-bisync.state";
+
+/// First line of the state database
+const STATE_HEADER: &str = "arsync-bisync-state v1";
+
+/// Bytes hashed per chunk (see `content_hash`)
+const HASH_CHUNK: usize = 1024 * 1024;
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+/// How to resolve a file modified on both sides since the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// The version with the newer mtime wins (A on a tie)
+    #[default]
+    NewestWins,
+    /// The version on side A wins
+    PreferA,
+    /// Keep both: A's version at the original name, B's version renamed with
+    /// the conflict suffix on both sides
+    KeepBoth,
+}
+
+/// Options for `bisync`
+#[derive(Debug, Clone)]
+pub struct BisyncOptions {
+    /// Conflict resolution policy
+    pub policy: ConflictPolicy,
+    /// Inserted before the extension of B's version with `KeepBoth`
+    /// (`notes.txt` becomes `notes.<suffix>.txt`)
+    pub conflict_suffix: String,
+    /// State database location (default: `A/.arsync-bisync.state`)
+    pub state_file: Option<PathBuf>,
+    /// Only report what would be done
+    pub dry_run: bool,
+}
+
+impl Default for BisyncOptions {
+    fn default() -> Self {
+        Self {
+            policy: ConflictPolicy::default(),
+            conflict_suffix: "conflict".to_string(),
+            state_file: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// One of the two synced trees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The first tree given on the command line
+    A,
+    /// The second tree given on the command line
+    B,
+}
+
+impl Side {
+    const fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::B => "B",
+        })
+    }
+}
+
+// ============================================================================
+// STATE DATABASE
+// ============================================================================
+
+/// What was last seen in sync for one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateEntry {
+    /// Size in bytes
+    pub size: u64,
+    /// Content hash (see `content_hash`)
+    pub hash: [u8; 16],
+    /// Modification time on side A (nanoseconds since the epoch)
+    pub mtime_a: i128,
+    /// Modification time on side B (nanoseconds since the epoch)
+    pub mtime_b: i128,
+}
+
+/// State database: relative path to last-synced state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncState {
+    /// Entries by path relative to the roots
+    pub entries: BTreeMap<PathBuf, StateEntry>,
+}
+
+impl SyncState {
+    /// Load the database; a missing file is an empty state (first run)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is malformed.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(contents) => Self::decode(&contents).ok_or_else(|| {
+                SyncError::InvalidConfig(format!(
+                    "Corrupt bisync state database {}; remove it to start over",
+                    path.display()
+                ))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(state_error("read bisync state", path, &e)),
+        }
+    }
+
+    /// Write the database atomically (temporary file and rename)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let staging = path.with_file_name(temp_name(path.file_name().unwrap_or_default()));
+        std::fs::write(&staging, self.encode())
+            .and_then(|()| std::fs::rename(&staging, path))
+            .map_err(|e| state_error("write bisync state", path, &e))
+    }
+
+    /// One line per entry: `hash size mtime_a mtime_b path` (tab-separated)
+    fn encode(&self) -> Vec<u8> {
+        let mut out = format!("{STATE_HEADER}\n").into_bytes();
+        for (path, entry) in &self.entries {
+            let hex: String = entry.hash.iter().map(|b| format!("{b:02x}")).collect();
+            out.extend_from_slice(
+                format!(
+                    "{hex}\t{}\t{}\t{}\t",
+                    entry.size, entry.mtime_a, entry.mtime_b
+                )
+                .as_bytes(),
+            );
+            escape_path(path.as_os_str(), &mut out);
+            out.push(b'\n');
+        }
+        out
+    }
+
+    fn decode(contents: &[u8]) -> Option<Self> {
+        let mut lines = contents.split(|&b| b == b'\n');
+        if lines.next()? != STATE_HEADER.as_bytes() {
+            return None;
+        }
+        let mut entries = BTreeMap::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let mut fields = line.splitn(5, |&b| b == b'\t');
+            let mut hash = [0u8; 16];
+            let hex = std::str::from_utf8(fields.next()?).ok()?;
+            if hex.len() != 32 {
+                return None;
+            }
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+            }
+            let mut number = || {
+                std::str::from_utf8(fields.next()?)
+                    .ok()?
+                    .parse::<i128>()
+                    .ok()
+            };
+            let entry = StateEntry {
+                size: u64::try_from(number()?).ok()?,
+                hash,
+                mtime_a: number()?,
+                mtime_b: number()?,
+            };
+            entries.insert(PathBuf::from(unescape_path(fields.next()?)?), entry);
+        }
+        Some(Self { entries })
+    }
+}
+
+/// Escape `\`, tab and newline so any filename fits on one line
+pub(crate) fn escape_path(path: &OsStr, out: &mut Vec<u8>) {
+    for &b in path.as_bytes() {
+        match b {
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b => out.push(b),
+        }
+    }
+}
+
+/// Inverse of `escape_path`; `None` on an invalid escape
+pub(crate) fn unescape_path(escaped: &[u8]) -> Option<OsString> {
+    let mut out = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&b) = bytes.next() {
+        if b == b'\\' {
+            out.push(match bytes.next()? {
+                b'\\' => b'\\',
+                b't' => b'\t',
+                b'n' => b'\n',
+                _ => return None,
+            });
+        } else {
+            out.push(b);
+        }
+    }
+    Some(OsString::from_vec(out))
+}
+
+// ============================================================================
+// SCANNING AND CHANGE DETECTION
+// ============================================================================
+
+/// Size and mtime of a file as currently seen on one side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seen {
+    size: u64,
+    mtime: i128,
+}
+
+impl Seen {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec()),
+        }
+    }
+}
+
+/// Change on one side relative to the state database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Not present now and not recorded
+    Absent,
+    /// Present now, not recorded
+    Created,
+    /// Recorded, content unchanged
+    Unchanged,
+    /// Recorded, content changed
+    Modified,
+    /// Recorded, no longer present
+    Deleted,
+}
+
+impl Change {
+    const fn has_content(self) -> bool {
+        matches!(self, Self::Created | Self::Modified)
+    }
+}
+
+/// Collect the regular files under `root` by relative path
+fn scan(root: &Path, state_file: &Path) -> Result<BTreeMap<PathBuf, Seen>> {
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(root).follow_links(false).min_depth(1) {
+        let entry = entry.map_err(|e| {
+            ErrorContext::new("scan bisync tree")
+                .source(e.path().unwrap_or(root))
+                .cause(&e)
+                .file_system()
+        })?;
+        let path = entry.path();
+        if path == state_file
+            || (entry.depth() == 1 && entry.file_name() == LOCK_FILE_NAME)
+            || parse_temp_name(entry.file_name()).is_some()
+        {
+            continue;
+        }
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+        if !file_type.is_file() {
+            warn!("bisync: skipping non-regular file {}", path.display());
+            continue;
+        }
+        let metadata = entry.metadata().map_err(|e| {
+            ErrorContext::new("stat bisync file")
+                .source(path)
+                .cause(&e)
+                .file_system()
+        })?;
+        let relative = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+        files.insert(relative, Seen::of(&metadata));
+    }
+    Ok(files)
+}
+
+/// Content hash: MD5 over the MD5s of consecutive 1 MiB chunks
+///
+/// Chunking keeps memory bounded for large files.
+fn content_hash(path: &Path) -> Result<[u8; 16]> {
+    let hash_error = |e: &std::io::Error| {
+        ErrorContext::new("hash bisync file")
+            .source(path)
+            .io_cause(e)
+            .file_system()
+    };
+    let mut file = std::fs::File::open(path).map_err(|e| hash_error(&e))?;
+    let mut chunk = vec![0u8; HASH_CHUNK];
+    let mut digests = Vec::new();
+    loop {
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match file.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(hash_error(&e)),
+            }
+        }
+        if filled == 0 && !digests.is_empty() {
+            break;
+        }
+        digests.extend_from_slice(&md5::compute(&chunk[..filled]).0);
+        if filled < chunk.len() {
+            break;
+        }
+    }
+    Ok(md5::compute(&digests).0)
+}
+
+// ============================================================================
+// PLANNING
+// ============================================================================
+
+/// One step of a bisync run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Copy the file from `from` to the other side
+    Copy {
+        /// Relative path
+        path: PathBuf,
+        /// Side whose version is copied
+        from: Side,
+        /// Whether this resolves a conflict
+        conflict: bool,
+    },
+    /// Delete the file on `side` (it was deleted on the other side)
+    Delete {
+        /// Relative path
+        path: PathBuf,
+        /// Side the file is removed from
+        side: Side,
+    },
+    /// Conflict kept both ways: B's version moves to `renamed` on both sides
+    /// and A's version is copied to B
+    KeepBoth {
+        /// Relative path
+        path: PathBuf,
+        /// Relative path B's version is kept under
+        renamed: PathBuf,
+    },
+    /// Both sides already agree; only the state database is updated
+    Record {
+        /// Relative path
+        path: PathBuf,
+    },
+    /// Deleted on both sides; dropped from the state database
+    Forget {
+        /// Relative path
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Copy {
+                path,
+                from,
+                conflict,
+            } => {
+                let note = if *conflict { " (conflict)" } else { "" };
+                write!(f, "{from} -> {}{note}: {}", from.other(), path.display())
+            }
+            Self::Delete { path, side } => write!(f, "delete on {side}: {}", path.display()),
+            Self::KeepBoth { path, renamed } => write!(
+                f,
+                "conflict, keeping both: {} (B's version as {})",
+                path.display(),
+                renamed.display()
+            ),
+            Self::Record { path } => write!(f, "in sync: {}", path.display()),
+            Self::Forget { path } => write!(f, "deleted on both sides: {}", path.display()),
+        }
+    }
+}
+
+/// Inputs shared by the planning steps
+struct Planner<'a> {
+    a: &'a Path,
+    b: &'a Path,
+    options: &'a BisyncOptions,
+    files_a: &'a BTreeMap<PathBuf, Seen>,
+    files_b: &'a BTreeMap<PathBuf, Seen>,
+}
+
+impl Planner<'_> {
+    fn root(&self, side: Side) -> &Path {
+        match side {
+            Side::A => self.a,
+            Side::B => self.b,
+        }
+    }
+
+    fn classify(&self, side: Side, path: &Path, entry: Option<&StateEntry>) -> Result<Change> {
+        let (files, recorded_mtime) = match side {
+            Side::A => (self.files_a, entry.map(|e| e.mtime_a)),
+            Side::B => (self.files_b, entry.map(|e| e.mtime_b)),
+        };
+        Ok(match (files.get(path), entry) {
+            (None, None) => Change::Absent,
+            (Some(_), None) => Change::Created,
+            (None, Some(_)) => Change::Deleted,
+            (Some(seen), Some(entry)) => {
+                if seen.size == entry.size && Some(seen.mtime) == recorded_mtime {
+                    Change::Unchanged
+                } else if seen.size == entry.size
+                    && content_hash(&self.root(side).join(path))? == entry.hash
+                {
+                    // Touched but not modified
+                    Change::Unchanged
+                } else {
+                    Change::Modified
+                }
+            }
+        })
+    }
+
+    fn plan_path(&self, path: &Path, entry: Option<&StateEntry>) -> Result<Action> {
+        let change_a = self.classify(Side::A, path, entry)?;
+        let change_b = self.classify(Side::B, path, entry)?;
+        let copy = |from, conflict| Action::Copy {
+            path: path.to_path_buf(),
+            from,
+            conflict,
+        };
+
+        Ok(match (change_a, change_b) {
+            (Change::Deleted, Change::Deleted) | (Change::Absent, Change::Absent) => {
+                Action::Forget {
+                    path: path.to_path_buf(),
+                }
+            }
+            (Change::Unchanged, Change::Unchanged) => Action::Record {
+                path: path.to_path_buf(),
+            },
+            (a, Change::Unchanged | Change::Absent) if a.has_content() => copy(Side::A, false),
+            (Change::Unchanged | Change::Absent, b) if b.has_content() => copy(Side::B, false),
+            (Change::Deleted, Change::Unchanged) => Action::Delete {
+                path: path.to_path_buf(),
+                side: Side::B,
+            },
+            (Change::Unchanged, Change::Deleted) => Action::Delete {
+                path: path.to_path_buf(),
+                side: Side::A,
+            },
+            // Modification beats deletion under every policy
+            (a, Change::Deleted) if a.has_content() => copy(Side::A, true),
+            (Change::Deleted, _) => copy(Side::B, true),
+            _ => {
+                // Content on both sides: a conflict unless they agree
+                if content_hash(&self.a.join(path))? == content_hash(&self.b.join(path))? {
+                    Action::Record {
+                        path: path.to_path_buf(),
+                    }
+                } else {
+                    self.resolve_conflict(path.to_path_buf())?
+                }
+            }
+        })
+    }
+
+    fn resolve_conflict(&self, path: PathBuf) -> Result<Action> {
+        Ok(match self.options.policy {
+            ConflictPolicy::PreferA => Action::Copy {
+                path,
+                from: Side::A,
+                conflict: true,
+            },
+            ConflictPolicy::NewestWins => {
+                let newer_b = match (self.files_a.get(&path), self.files_b.get(&path)) {
+                    (Some(a), Some(b)) => b.mtime > a.mtime,
+                    _ => false,
+                };
+                Action::Copy {
+                    path,
+                    from: if newer_b { Side::B } else { Side::A },
+                    conflict: true,
+                }
+            }
+            ConflictPolicy::KeepBoth => {
+                let renamed = self.conflict_name(&path)?;
+                Action::KeepBoth { path, renamed }
+            }
+        })
+    }
+
+    /// `dir/name.<suffix>.ext`, numbered if that name is taken on either side
+    fn conflict_name(&self, path: &Path) -> Result<PathBuf> {
+        let stem = path.file_stem().unwrap_or_default();
+        let ext = path.extension();
+        for n in 1u32.. {
+            let mut name = stem.to_os_string();
+            name.push(".");
+            name.push(&self.options.conflict_suffix);
+            if n > 1 {
+                name.push(format!("-{n}"));
+            }
+            if let Some(ext) = ext {
+                name.push(".");
+                name.push(ext);
+            }
+            let candidate = path.with_file_name(name);
+            let taken = |files: &BTreeMap<PathBuf, Seen>, root: &Path| {
+                files.contains_key(&candidate) || root.join(&candidate).exists()
+            };
+            if !taken(self.files_a, self.a) && !taken(self.files_b, self.b) {
+                return Ok(candidate);
+            }
+        }
+        Err(SyncError::Internal(format!(
+            "No free conflict name for {}",
+            path.display()
+        )))
+    }
+}
+
+/// Compute the actions that bring `a` and `b` in sync
+///
+/// # Errors
+///
+/// Returns an error if a tree cannot be scanned or a file cannot be hashed.
+pub fn plan(a: &Path, b: &Path, state: &SyncState, options: &BisyncOptions) -> Result<Vec<Action>> {
+    let state_file = state_file(a, options);
+    let files_a = scan(a, &state_file)?;
+    let files_b = scan(b, &state_file)?;
+    let planner = Planner {
+        a,
+        b,
+        options,
+        files_a: &files_a,
+        files_b: &files_b,
+    };
+
+    let paths: BTreeSet<&PathBuf> = files_a
+        .keys()
+        .chain(files_b.keys())
+        .chain(state.entries.keys())
+        .collect();
+    paths
+        .into_iter()
+        .map(|path| planner.plan_path(path, state.entries.get(path)))
+        .collect()
+}
+
+// ============================================================================
+// EXECUTION
+// ============================================================================
+
+/// Result of a bisync run
+#[derive(Debug, Default)]
+pub struct BisyncReport {
+    /// Actions performed (or that would be performed in dry-run mode),
+    /// excluding `Record`
+    pub actions: Vec<Action>,
+    /// Files copied from A to B
+    pub copied_to_b: u64,
+    /// Files copied from B to A
+    pub copied_to_a: u64,
+    /// Files deleted on either side
+    pub deleted: u64,
+    /// Conflicts resolved
+    pub conflicts: u64,
+}
+
+/// Synchronize `a` and `b` in both directions
+///
+/// # Errors
+///
+/// Returns an error if either root is locked by another run, or if scanning,
+/// copying, deleting or saving the state database fails. State for actions
+/// completed before the failure is saved, so a rerun picks up where this one
+/// stopped.
+pub fn bisync(a: &Path, b: &Path, options: &BisyncOptions) -> Result<BisyncReport> {
+    for root in [a, b] {
+        if !root.is_dir() {
+            return Err(SyncError::InvalidConfig(format!(
+                "bisync root {} is not a directory",
+                root.display()
+            )));
+        }
+    }
+    let _locks = if options.dry_run {
+        None
+    } else {
+        Some((
+            DestinationLock::acquire(a, b)?,
+            DestinationLock::acquire(b, a)?,
+        ))
+    };
+
+    let state_path = state_file(a, options);
+    let mut state = SyncState::load(&state_path)?;
+    let actions = plan(a, b, &state, options)?;
+
+    let mut report = BisyncReport::default();
+    let mut result = Ok(());
+    for action in actions {
+        if !options.dry_run {
+            result = apply(a, b, &action, &mut state);
+            if result.is_err() {
+                break;
+            }
+        }
+        match &action {
+            Action::Copy { from, conflict, .. } => {
+                match from {
+                    Side::A => report.copied_to_b += 1,
+                    Side::B => report.copied_to_a += 1,
+                }
+                report.conflicts += u64::from(*conflict);
+            }
+            Action::Delete { .. } => report.deleted += 1,
+            Action::KeepBoth { .. } => {
+                report.copied_to_a += 1;
+                report.copied_to_b += 1;
+                report.conflicts += 1;
+            }
+            Action::Record { .. } => continue,
+            Action::Forget { .. } => {}
+        }
+        report.actions.push(action);
+    }
+
+    if !options.dry_run {
+        state.save(&state_path)?;
+    }
+    result.map(|()| report)
+}
+
+fn state_file(a: &Path, options: &BisyncOptions) -> PathBuf {
+    options
+        .state_file
+        .clone()
+        .unwrap_or_else(|| a.join(STATE_FILE_NAME))
+}
+
+/// Perform one action and update `state` to match
+fn apply(a: &Path, b: &Path, action: &Action, state: &mut SyncState) -> Result<()> {
+    let root = |side| match side {
+        Side::A => a,
+        Side::B => b,
+    };
+    match action {
+        Action::Copy { path, from, .. } => {
+            copy_file(&root(*from).join(path), &root(from.other()).join(path))?;
+            record(a, b, path, state)
+        }
+        Action::Delete { path, side } => {
+            let target = root(*side).join(path);
+            std::fs::remove_file(&target).or_else(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Ok(())
+                } else {
+                    Err(state_error("delete bisync file", &target, &e))
+                }
+            })?;
+            state.entries.remove(path);
+            Ok(())
+        }
+        Action::KeepBoth { path, renamed } => {
+            copy_file(&b.join(path), &a.join(renamed))?;
+            std::fs::rename(b.join(path), b.join(renamed))
+                .map_err(|e| state_error("rename conflicting file", &b.join(renamed), &e))?;
+            copy_file(&a.join(path), &b.join(path))?;
+            record(a, b, renamed, state)?;
+            record(a, b, path, state)
+        }
+        Action::Record { path } => record(a, b, path, state),
+        Action::Forget { path } => {
+            state.entries.remove(path);
+            Ok(())
+        }
+    }
+}
+
+/// Record `path` as in sync, from the files now on both sides
+fn record(a: &Path, b: &Path, path: &Path, state: &mut SyncState) -> Result<()> {
+    let stat = |root: &Path| {
+        let full = root.join(path);
+        std::fs::metadata(&full)
+            .map(|m| Seen::of(&m))
+            .map_err(|e| state_error("stat bisync file", &full, &e))
+    };
+    let (seen_a, seen_b) = (stat(a)?, stat(b)?);
+    state.entries.insert(
+        path.to_path_buf(),
+        StateEntry {
+            size: seen_a.size,
+            hash: content_hash(&a.join(path))?,
+            mtime_a: seen_a.mtime,
+            mtime_b: seen_b.mtime,
+        },
+    );
+    Ok(())
+}
+
+/// Copy `src` over `dst` atomically, keeping permissions and mtime
+fn copy_file(src: &Path, dst: &Path) -> Result<()> {
+    let copy_error = |operation, e: &std::io::Error| {
+        ErrorContext::new(operation)
+            .source(src)
+            .destination(dst)
+            .io_cause(e)
+            .file_system()
+    };
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| copy_error("create bisync directory", &e))?;
+    }
+    let staging = dst.with_file_name(temp_name(dst.file_name().unwrap_or_default()));
+    let copied = std::fs::copy(src, &staging)
+        .and_then(|_| std::fs::metadata(src))
+        .and_then(|metadata| {
+            filetime::set_file_mtime(
+                &staging,
+                filetime::FileTime::from_last_modification_time(&metadata),
+            )
+        })
+        .and_then(|()| std::fs::rename(&staging, dst));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(&staging);
+        return Err(copy_error("copy bisync file", &e));
+    }
+    Ok(())
+}
+
+fn state_error(operation: &str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    struct Trees {
+        _temp_dir: TempDir,
+        a: PathBuf,
+        b: PathBuf,
+    }
+
+    fn trees() -> Trees {
+        let temp_dir = TempDir::new().unwrap();
+        let a = temp_dir.path().join("a");
+        let b = temp_dir.path().join("b");
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        Trees {
+            _temp_dir: temp_dir,
+            a,
+            b,
+        }
+    }
+
+    fn write_aged(path: &Path, contents: &str, age_secs: u64) {
+        std::fs::write(path, contents).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(age_secs);
+        filetime::set_file_mtime(path, filetime::FileTime::from_system_time(mtime)).unwrap();
+    }
+
+    fn run(t: &Trees, policy: ConflictPolicy) -> BisyncReport {
+        let options = BisyncOptions {
+            policy,
+            ..BisyncOptions::default()
+        };
+        bisync(&t.a, &t.b, &options).unwrap()
+    }
+
+    #[test]
+    fn test_changes_propagate_both_ways() {
+        // Test: Creations, modifications and deletions flow to the other side
+        let t = trees();
+        std::fs::create_dir(t.a.join("sub")).unwrap();
+        std::fs::write(t.a.join("sub/from_a.txt"), "a").unwrap();
+        std::fs::write(t.b.join("from_b.txt"), "b").unwrap();
+        std::fs::write(t.b.join("same.txt"), "same").unwrap();
+        std::fs::write(t.a.join("same.txt"), "same").unwrap();
+
+        let report = run(&t, ConflictPolicy::default());
+        assert_eq!((report.copied_to_a, report.copied_to_b), (1, 1));
+        assert_eq!(
+            std::fs::read_to_string(t.b.join("sub/from_a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            std::fs::read_to_string(t.a.join("from_b.txt")).unwrap(),
+            "b"
+        );
+
+        // Second run with no changes does nothing
+        assert!(run(&t, ConflictPolicy::default()).actions.is_empty());
+
+        // A touch is not a change; a deletion on one side propagates
+        filetime::set_file_mtime(
+            t.b.join("same.txt"),
+            filetime::FileTime::from_unix_time(1, 0),
+        )
+        .unwrap();
+        std::fs::remove_file(t.a.join("from_b.txt")).unwrap();
+        std::fs::write(t.b.join("sub/from_a.txt"), "edited on b").unwrap();
+        let report = run(&t, ConflictPolicy::default());
+        assert_eq!((report.copied_to_a, report.deleted), (1, 1));
+        assert!(!t.b.join("from_b.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(t.a.join("sub/from_a.txt")).unwrap(),
+            "edited on b"
+        );
+        assert!(run(&t, ConflictPolicy::default()).actions.is_empty());
+        assert!(!t.a.join(LOCK_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_conflict_policies() {
+        // Test: Files changed on both sides are resolved by the chosen policy
+        for (policy, a_wins) in [
+            (ConflictPolicy::NewestWins, false),
+            (ConflictPolicy::PreferA, true),
+        ] {
+            let t = trees();
+            write_aged(&t.a.join("doc.txt"), "base", 100);
+            run(&t, policy);
+            write_aged(&t.a.join("doc.txt"), "older edit on a", 50);
+            write_aged(&t.b.join("doc.txt"), "newer edit on b", 10);
+
+            let report = run(&t, policy);
+            assert_eq!(report.conflicts, 1, "{policy:?}");
+            let expected = if a_wins {
+                "older edit on a"
+            } else {
+                "newer edit on b"
+            };
+            for root in [&t.a, &t.b] {
+                assert_eq!(
+                    std::fs::read_to_string(root.join("doc.txt")).unwrap(),
+                    expected
+                );
+            }
+        }
+
+        let t = trees();
+        write_aged(&t.a.join("doc.txt"), "from a", 10);
+        write_aged(&t.b.join("doc.txt"), "from b", 10);
+        let report = run(&t, ConflictPolicy::KeepBoth);
+        assert_eq!(report.conflicts, 1);
+        for root in [&t.a, &t.b] {
+            assert_eq!(
+                std::fs::read_to_string(root.join("doc.txt")).unwrap(),
+                "from a"
+            );
+            assert_eq!(
+                std::fs::read_to_string(root.join("doc.conflict.txt")).unwrap(),
+                "from b"
+            );
+        }
+        assert!(run(&t, ConflictPolicy::KeepBoth).actions.is_empty());
+    }
+
+    #[test]
+    fn test_modification_beats_deletion_and_state_round_trip() {
+        let t = trees();
+        std::fs::write(t.a.join("keep\tme\n.txt"), "v1").unwrap();
+        run(&t, ConflictPolicy::PreferA);
+        std::fs::remove_file(t.a.join("keep\tme\n.txt")).unwrap();
+        std::fs::write(t.b.join("keep\tme\n.txt"), "v2").unwrap();
+
+        let report = run(&t, ConflictPolicy::PreferA);
+        assert_eq!(report.conflicts, 1);
+        assert_eq!(
+            std::fs::read_to_string(t.a.join("keep\tme\n.txt")).unwrap(),
+            "v2"
+        );
+
+        let state = SyncState::load(&t.a.join(STATE_FILE_NAME)).unwrap();
+        assert_eq!(SyncState::decode(&state.encode()).unwrap(), state);
+        assert!(state.entries.contains_key(Path::new("keep\tme\n.txt")));
+    }
+}
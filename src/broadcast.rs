@@ -0,0 +1,204 @@
+//! One source read feeding several consumers
+//!
+//! Some copies hand the same source data to more than one consumer: fan-out
+//! writes every chunk to each destination, and `--verify-direct` checksums the
+//! chunks that were written. Rather than reading the source once per consumer,
+//! or copying each chunk for each of them, a [`Broadcast`] reads the source
+//! once into shared [`Chunk`]s that every subscriber receives.
+//!
+//! Each subscriber has a bounded queue of chunks, so the read waits for the
+//! slowest live consumer instead of buffering the file in memory. A consumer
+//! that stops receiving (its destination failed) is dropped and no longer
+//! holds the others back. Chunk buffers come from the copy buffer pool and are
+//! reused once every consumer has released them.
+
+use crate::pipelines::BufferPool;
+use crate::write_verify::ChunkChecksums;
+use compio::fs::File;
+use compio::io::{AsyncReadAt, AsyncWriteAtExt};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+/// Chunks queued per subscriber before the read waits for it
+pub const DEFAULT_QUEUE_DEPTH: usize = 4;
+
+/// A piece of the source, shared by every subscriber
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// Offset of the chunk in the source
+    pub offset: u64,
+    /// Chunk data
+    pub data: Rc<Vec<u8>>,
+}
+
+/// Receiving end of a [`Broadcast`]; ends once the whole source was sent
+pub type Subscriber = mpsc::Receiver<Chunk>;
+
+/// Single reader, multiple consumers with backpressure
+pub struct Broadcast {
+    /// Queue length of each subscriber
+    depth: usize,
+    /// Subscribers still receiving
+    subscribers: Vec<mpsc::Sender<Chunk>>,
+    /// Chunks sent, oldest first, until their buffers can be reused
+    sent: VecDeque<Rc<Vec<u8>>>,
+}
+
+impl Broadcast {
+    /// Broadcast queueing up to `depth` chunks per subscriber
+    #[must_use]
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            subscribers: Vec::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Add a consumer; it receives every chunk read after this call
+    pub fn subscribe(&mut self) -> Subscriber {
+        // The channel holds one slot per sender on top of its buffer
+        let (sender, receiver) = mpsc::channel(self.depth - 1);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Read the first `size` bytes of `src` and send them to every subscriber
+    ///
+    /// Must run concurrently with the subscribers (e.g. in `futures::join!`).
+    /// Reading stops early once every subscriber has gone. Returns the number
+    /// of bytes read; the subscribers' streams end when this returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of a failed read. The subscribers' streams end
+    /// early, so they cannot tell a failed read from the end of the source.
+    #[allow(clippy::future_not_send)]
+    pub async fn pump(mut self, src: &File, size: u64, pool: &BufferPool) -> io::Result<u64> {
+        let mut offset = 0u64;
+        while offset < size && !self.subscribers.is_empty() {
+            let buffer = self.reusable_buffer(pool);
+            let read_result = src.read_at(buffer, offset).await;
+            let mut buffer = read_result.1;
+            let bytes_read = match read_result.0 {
+                Ok(0) => {
+                    pool.put(buffer);
+                    break;
+                }
+                Ok(bytes_read) => bytes_read,
+                Err(e) => {
+                    pool.put(buffer);
+                    return Err(e);
+                }
+            };
+            buffer.truncate(bytes_read);
+
+            let data = Rc::new(buffer);
+            self.send(Chunk {
+                offset,
+                data: Rc::clone(&data),
+            })
+            .await;
+            self.sent.push_back(data);
+            offset += bytes_read as u64;
+        }
+        for data in self.sent.drain(..) {
+            if let Ok(buffer) = Rc::try_unwrap(data) {
+                pool.put(buffer);
+            }
+        }
+        Ok(offset)
+    }
+
+    /// Send `chunk` to every subscriber, dropping those that have gone
+    #[allow(clippy::future_not_send)]
+    async fn send(&mut self, chunk: Chunk) {
+        let sends = self.subscribers.iter_mut().map(|subscriber| {
+            let chunk = chunk.clone();
+            async move { subscriber.send(chunk).await.is_ok() }
+        });
+        let mut live = futures::future::join_all(sends).await.into_iter();
+        self.subscribers.retain(|_| live.next().unwrap_or_default());
+    }
+
+    /// The oldest sent buffer if every subscriber is done with it, else a new one
+    fn reusable_buffer(&mut self, pool: &BufferPool) -> Vec<u8> {
+        if self
+            .sent
+            .front()
+            .is_some_and(|data| Rc::strong_count(data) == 1)
+        {
+            if let Some(Ok(mut buffer)) = self.sent.pop_front().map(Rc::try_unwrap) {
+                buffer.resize(pool.buffer_size(), 0);
+                return buffer;
+            }
+        }
+        pool.take()
+    }
+}
+
+/// Consumer writing every chunk to `file` at its source offset
+///
+/// # Errors
+///
+/// Returns the first write error; the remaining chunks are not received.
+#[allow(clippy::future_not_send)]
+pub async fn write_chunks(file: &mut File, mut chunks: Subscriber) -> io::Result<()> {
+    while let Some(chunk) = chunks.next().await {
+        file.write_all_at(chunk.data, chunk.offset).await.0?;
+    }
+    Ok(())
+}
+
+/// Consumer recording the sampled chunks for `--verify-direct`
+#[allow(clippy::future_not_send)]
+pub async fn record_checksums(checksums: &mut ChunkChecksums, mut chunks: Subscriber) {
+    while let Some(chunk) = chunks.next().await {
+        checksums.record(chunk.offset, &chunk.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_broadcast_feeds_every_subscriber() {
+        // Requirement: Every subscriber sees the whole source from one read,
+        // and one that gives up does not stall the others
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("src");
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let src = File::open(&path).await.unwrap();
+        let pool = BufferPool::new(4096, 8);
+
+        let mut broadcast = Broadcast::new(2);
+        let mut all = broadcast.subscribe();
+        let mut first_only = broadcast.subscribe();
+        let collect_all = async {
+            let mut received = vec![0u8; data.len()];
+            while let Some(chunk) = all.next().await {
+                let start = usize::try_from(chunk.offset).unwrap();
+                received[start..start + chunk.data.len()].copy_from_slice(&chunk.data);
+            }
+            received
+        };
+        let take_first = async move { first_only.next().await.map(|chunk| chunk.offset) };
+
+        let (read, received, first) = futures::join!(
+            broadcast.pump(&src, data.len() as u64, &pool),
+            collect_all,
+            take_first
+        );
+        assert_eq!(read.unwrap(), data.len() as u64);
+        assert_eq!(received, data);
+        assert_eq!(first, Some(0));
+    }
+}
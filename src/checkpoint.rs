@@ -0,0 +1,375 @@
+//! Checkpointed statistics and completed entries (`--state-journal`)
+//!
+//! A crashed or killed run otherwise loses everything it knew: how much it had
+//! copied and which entries were done. With `--state-journal PATH`, a
+//! background thread appends a snapshot of the run's statistics and the paths
+//! completed since the previous checkpoint to the journal every
+//! `--checkpoint-secs` seconds, or sooner once `--checkpoint-files` entries
+//! have completed. Each checkpoint is flushed with `fdatasync`, so after a
+//! crash the journal is accurate up to the last checkpoint.
+//!
+//! Format: a header line, then records of one line each:
+//!
+//! - `S <unix-ms> <files> <dirs> <bytes> <symlinks> <errors>`: stats snapshot
+//! - `+ <path>`: an entry completed (relative to the source root, escaped like
+//!   the deletion journal so any name fits on one line)
+//! - `E <unix-ms>`: the run finished successfully
+
+use crate::bisync::{escape_path, unescape_path};
+use crate::directory::DirectoryStats;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::stats::SharedStats;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// First line of the journal
+const JOURNAL_HEADER: &[u8] = b"arsync-state-journal v1";
+
+/// How often and after how many completed entries to checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// Checkpoint at least this often (`None`: only on completed entries)
+    pub interval: Option<Duration>,
+    /// Checkpoint once this many entries completed (`None`: only on time)
+    pub files: Option<usize>,
+}
+
+/// Entries completed since the last checkpoint, guarded by `Checkpointer::state`
+#[derive(Default)]
+struct State {
+    /// Completed entries not yet written
+    completed: Vec<PathBuf>,
+    /// Set when the run is over; the thread writes a last checkpoint and exits
+    closed: bool,
+}
+
+/// Writes periodic checkpoints of a run to the state journal
+pub struct Checkpointer {
+    /// Journal path (for error messages)
+    path: PathBuf,
+    /// Journal, open for appending
+    file: Mutex<File>,
+    /// Source root completed paths are recorded relative to
+    root: PathBuf,
+    /// Statistics of the run
+    stats: Arc<SharedStats>,
+    /// When to checkpoint
+    policy: CheckpointPolicy,
+    /// Completed entries and shutdown flag
+    state: Mutex<State>,
+    /// Signalled when enough entries completed or on close
+    wake: Condvar,
+    /// Checkpoint thread, joined by `finish()`
+    thread: Mutex<Option<JoinHandle<Result<()>>>>,
+}
+
+impl Checkpointer {
+    /// Start a journal at `path` for a run copying from `root`
+    ///
+    /// An existing journal is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if the journal cannot be created, or
+    /// `SyncError::Internal` if the thread cannot be spawned.
+    pub fn start(
+        path: &Path,
+        root: &Path,
+        stats: Arc<SharedStats>,
+        policy: CheckpointPolicy,
+    ) -> Result<Arc<Self>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| journal_error("create state journal", path, &e))?;
+        file.write_all(JOURNAL_HEADER)
+            .and_then(|()| file.write_all(b"\n"))
+            .map_err(|e| journal_error("write state journal", path, &e))?;
+
+        let checkpointer = Arc::new(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            root: root.to_path_buf(),
+            stats,
+            policy,
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+            thread: Mutex::new(None),
+        });
+        let worker = Arc::clone(&checkpointer);
+        let handle = std::thread::Builder::new()
+            .name("arsync-checkpoint".to_string())
+            .spawn(move || worker.run())
+            .map_err(|e| SyncError::Internal(format!("Failed to start checkpoint thread: {e}")))?;
+        *checkpointer
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(handle);
+        Ok(checkpointer)
+    }
+
+    /// Record that the entry at source path `path` has been copied
+    pub fn completed(&self, path: &Path) {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let mut state = self.lock();
+        state.completed.push(relative.to_path_buf());
+        if self
+            .policy
+            .files
+            .is_some_and(|files| state.completed.len() >= files)
+        {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Write the last checkpoint and stop the thread
+    ///
+    /// With `success`, the journal is marked as belonging to a finished run.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if a checkpoint could not be written.
+    pub fn finish(&self, success: bool) -> Result<()> {
+        self.lock().closed = true;
+        self.wake.notify_all();
+        let handle = self
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(handle) = handle {
+            handle
+                .join()
+                .map_err(|_| SyncError::Internal("Checkpoint thread panicked".to_string()))??;
+        }
+        if success {
+            let line = format!("E {}\n", unix_millis());
+            self.append(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint thread: write a checkpoint per interval or batch of entries
+    fn run(&self) -> Result<()> {
+        loop {
+            let (completed, closed) = {
+                let mut state = self.lock();
+                loop {
+                    let batch_full = self
+                        .policy
+                        .files
+                        .is_some_and(|files| state.completed.len() >= files);
+                    if state.closed || batch_full {
+                        break;
+                    }
+                    if let Some(interval) = self.policy.interval {
+                        let (guard, timeout) = self
+                            .wake
+                            .wait_timeout(state, interval)
+                            .unwrap_or_else(PoisonError::into_inner);
+                        state = guard;
+                        if timeout.timed_out() {
+                            break;
+                        }
+                    } else {
+                        state = self
+                            .wake
+                            .wait(state)
+                            .unwrap_or_else(PoisonError::into_inner);
+                    }
+                }
+                (std::mem::take(&mut state.completed), state.closed)
+            };
+            self.checkpoint(&completed)?;
+            if closed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Append the entries and a stats snapshot, then flush to disk
+    fn checkpoint(&self, completed: &[PathBuf]) -> Result<()> {
+        let mut record = Vec::new();
+        for path in completed {
+            record.extend_from_slice(b"+ ");
+            escape_path(path.as_os_str(), &mut record);
+            record.push(b'\n');
+        }
+        let stats = self.stats.snapshot();
+        record.extend_from_slice(
+            format!(
+                "S {} {} {} {} {} {}\n",
+                unix_millis(),
+                stats.files_copied,
+                stats.directories_created,
+                stats.bytes_copied,
+                stats.symlinks_processed,
+                stats.errors
+            )
+            .as_bytes(),
+        );
+        self.append(&record)
+    }
+
+    /// Append `record` to the journal and sync it
+    fn append(&self, record: &[u8]) -> Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(record)
+            .and_then(|()| file.sync_data())
+            .map_err(|e| journal_error("write state journal", &self.path, &e))
+    }
+
+    /// Lock the state, recovering from a poisoned lock
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a state journal records about a (possibly crashed) run
+#[derive(Debug, Default)]
+pub struct JournalSummary {
+    /// Last statistics snapshot
+    pub stats: Option<DirectoryStats>,
+    /// Entries completed, relative to the source root
+    pub completed: Vec<PathBuf>,
+    /// Whether the run finished successfully
+    pub finished: bool,
+}
+
+/// Read the state journal at `path`
+///
+/// A torn last line (crash during a write) is ignored.
+///
+/// # Errors
+///
+/// Returns `SyncError::FileSystem` if the journal cannot be read or is not a
+/// state journal.
+#[allow(dead_code)] // For post-mortem analysis and resume tooling
+pub fn read_journal(path: &Path) -> Result<JournalSummary> {
+    let contents =
+        std::fs::read(path).map_err(|e| journal_error("read state journal", path, &e))?;
+    let mut lines = contents.split(|&b| b == b'\n');
+    if lines.next() != Some(JOURNAL_HEADER) {
+        return Err(SyncError::FileSystem(format!(
+            "{} is not an arsync state journal",
+            path.display()
+        )));
+    }
+
+    let mut summary = JournalSummary::default();
+    // Only lines terminated by a newline were written completely
+    let complete = contents
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        .saturating_sub(1);
+    for line in lines.take(complete) {
+        match line {
+            [b'+', b' ', escaped @ ..] => {
+                if let Some(entry) = unescape_path(escaped) {
+                    summary.completed.push(PathBuf::from(entry));
+                }
+            }
+            [b'S', b' ', fields @ ..] => {
+                let fields: Vec<u64> = String::from_utf8_lossy(fields)
+                    .split(' ')
+                    .filter_map(|field| field.parse().ok())
+                    .collect();
+                if let [_, files, dirs, bytes, symlinks, errors] = fields[..] {
+                    summary.stats = Some(DirectoryStats {
+                        files_copied: files,
+                        directories_created: dirs,
+                        bytes_copied: bytes,
+                        symlinks_processed: symlinks,
+                        errors,
+                        ..DirectoryStats::default()
+                    });
+                }
+            }
+            [b'E', b' ', ..] => summary.finished = true,
+            _ => {}
+        }
+    }
+    Ok(summary)
+}
+
+/// Milliseconds since the Unix epoch
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn journal_error(operation: &str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checkpoints_after_batch_of_files() {
+        // Requirement: A batch of completed entries is flushed before the run ends
+        let temp_dir = TempDir::new().unwrap();
+        let journal = temp_dir.path().join("state");
+        let stats = Arc::new(SharedStats::new(&DirectoryStats::default()));
+        let policy = CheckpointPolicy {
+            interval: None,
+            files: Some(2),
+        };
+        let checkpointer =
+            Checkpointer::start(&journal, Path::new("/src"), Arc::clone(&stats), policy).unwrap();
+
+        stats.increment_files_copied();
+        stats.increment_bytes_copied(100);
+        checkpointer.completed(Path::new("/src/a"));
+        checkpointer.completed(Path::new("/src/dir/b\nc"));
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let summary = loop {
+            let summary = read_journal(&journal).unwrap();
+            if summary.stats.is_some() || std::time::Instant::now() > deadline {
+                break summary;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        // A crash now would leave these entries and stats behind
+        assert_eq!(
+            summary.completed,
+            [PathBuf::from("a"), PathBuf::from("dir/b\nc")]
+        );
+        assert_eq!(summary.stats.unwrap().bytes_copied, 100);
+        assert!(!summary.finished);
+
+        checkpointer.finish(true).unwrap();
+        assert!(read_journal(&journal).unwrap().finished);
+    }
+
+    #[test]
+    fn test_torn_last_line_is_ignored() {
+        let temp_dir = TempDir::new().unwrap();
+        let journal = temp_dir.path().join("state");
+        std::fs::write(
+            &journal,
+            "arsync-state-journal v1\n+ a\nS 1 1 0 10 0 0\n+ partial-na",
+        )
+        .unwrap();
+        let summary = read_journal(&journal).unwrap();
+        assert_eq!(summary.completed, [PathBuf::from("a")]);
+        assert_eq!(summary.stats.unwrap().files_copied, 1);
+    }
+}
@@ -0,0 +1,361 @@
+//! Checksum list of the copied files (`--emit-checksums FILE`)
+//!
+//! Every file the run writes is hashed on its way through the copy: the
+//! read/write loop feeds each chunk to the file's [`Digester`] in file order,
+//! as it takes the chunk's buffer back. Hashing needs the data in userspace,
+//! so `copy_file_range`, reflinks, server-side copies and parallel copies are
+//! not used while a list is being made; the files written by a `--transform`
+//! command or encrypted for `--encrypt-to` are read back once written, and
+//! the prefix a `--partial` copy resumes from is read from the partial file.
+//!
+//! At the end of a successful run the list is written to FILE in the format of
+//! `b3sum` (BLAKE3, the default) or `sha256sum` (`--checksum-algorithm
+//! sha256`): one `HASH  PATH` line per file, sorted by path, with paths
+//! relative to the destination directory (the destination's parent for a
+//! single file), so `cd DST && b3sum -c FILE` checks the copy without arsync.
+//! Names containing a backslash or a newline are escaped as both tools do.
+//!
+//! Hard links to a copied file are listed with its hash. Files left alone
+//! because they were up to date, or linked from `--link-dest`, are read once
+//! to be hashed, so the list covers the whole destination tree whatever the
+//! run had to copy.
+
+use crate::error::{Result, SyncError};
+use compio::fs::File;
+use sha2::Digest as _;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::mem::ManuallyDrop;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Bytes read at a time when a written file is read back
+const READ_BACK_SIZE: usize = 1024 * 1024;
+
+/// Hash of the checksum list (`--checksum-algorithm`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChecksumAlgorithm {
+    /// BLAKE3, checked with `b3sum -c`
+    #[default]
+    Blake3,
+    /// SHA-256, checked with `sha256sum -c`
+    Sha256,
+}
+
+/// Hash of one file's contents, fed in file order
+pub enum Digester {
+    /// BLAKE3 state (large, so boxed)
+    Blake3(Box<blake3::Hasher>),
+    /// SHA-256 state
+    Sha256(sha2::Sha256),
+}
+
+impl Digester {
+    /// Empty hash for `algorithm`
+    #[must_use]
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    /// Add the next `data` of the file
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Add `len` zero bytes (a hole skipped by `--sparse`)
+    pub fn update_zeros(&mut self, mut len: u64) {
+        const ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+        while len > 0 {
+            #[allow(clippy::cast_possible_truncation)] // At most ZEROS.len()
+            let n = len.min(ZEROS.len() as u64) as usize;
+            self.update(&ZEROS[..n]);
+            len -= n as u64;
+        }
+    }
+
+    /// The hash, in lowercase hex
+    #[must_use]
+    pub fn finish(self) -> String {
+        match self {
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Sha256(hasher) => {
+                hasher
+                    .finalize()
+                    .iter()
+                    .fold(String::new(), |mut hex, byte| {
+                        let _ = write!(hex, "{byte:02x}");
+                        hex
+                    })
+            }
+        }
+    }
+}
+
+/// Hashes of the files copied so far
+#[derive(Debug, Default)]
+struct Hashes {
+    /// Copied files by destination path
+    files: BTreeMap<PathBuf, String>,
+    /// Hard links by destination path, with the copied file they link to
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Checksum list of one run (see `run_state`), if it makes one
+#[derive(Debug, Default)]
+pub struct ChecksumList {
+    /// Hash of the list, `None` if the run makes no list
+    algorithm: Option<ChecksumAlgorithm>,
+    /// Hashes recorded so far
+    hashes: Mutex<Hashes>,
+}
+
+impl ChecksumList {
+    /// List hashed with `algorithm`, or none
+    #[must_use]
+    pub fn new(algorithm: Option<ChecksumAlgorithm>) -> Self {
+        Self {
+            algorithm,
+            hashes: Mutex::default(),
+        }
+    }
+
+    /// A digester for the next copied file, if this run makes a list
+    #[must_use]
+    pub fn digester(&self) -> Option<Digester> {
+        self.algorithm.map(Digester::new)
+    }
+
+    /// Whether this run makes a list, so copies must pass through userspace
+    #[must_use]
+    pub const fn active(&self) -> bool {
+        self.algorithm.is_some()
+    }
+
+    /// Record the hash of the file copied to `dst`
+    pub fn record(&self, dst: &Path, digester: Digester) {
+        let digest = digester.finish();
+        if self.active() {
+            self.hashes
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .files
+                .insert(dst.to_path_buf(), digest);
+        }
+    }
+
+    /// Hash the file at `path`, which the run did not copy, and record it as
+    /// the contents of `dst` (the up-to-date `dst` itself, or the
+    /// `--link-dest` file it links to)
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if `path` cannot be read.
+    pub async fn record_existing(&self, dst: &Path, path: &Path) -> Result<()> {
+        let Some(digester) = self.digester() else {
+            return Ok(());
+        };
+        let reader = File::open(path).await.map_err(|e| {
+            SyncError::FileSystem(format!(
+                "Failed to open {} for its checksum: {e}",
+                path.display()
+            ))
+        })?;
+        let digester = read_back(&reader, None, digester, path).await?;
+        self.record(dst, digester);
+        Ok(())
+    }
+
+    /// Record that `dst` is a hard link to the copied file `original`
+    pub fn link(&self, dst: &Path, original: &Path) {
+        if self.active() {
+            self.hashes
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .links
+                .push((dst.to_path_buf(), original.to_path_buf()));
+        }
+    }
+
+    /// Write the list to `file`, with paths relative to `root`, and empty it
+    ///
+    /// Returns the number of files listed. Does nothing (and returns 0) if
+    /// the run makes no list.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` if `file` cannot be written.
+    pub fn finish(&self, file: &Path, root: &Path) -> Result<usize> {
+        if !self.active() {
+            return Ok(0);
+        }
+        let Hashes { mut files, links } =
+            std::mem::take(&mut *self.hashes.lock().unwrap_or_else(PoisonError::into_inner));
+        // A link whose original failed to copy is left out with it
+        for (dst, original) in links {
+            if let Some(digest) = files.get(&original).cloned() {
+                files.insert(dst, digest);
+            }
+        }
+
+        let mut contents = Vec::new();
+        for (path, digest) in &files {
+            contents.extend(line(digest, path.strip_prefix(root).unwrap_or(path)));
+        }
+        std::fs::write(file, contents).map_err(|e| {
+            SyncError::FileSystem(format!(
+                "Failed to write the checksum list {}: {e}",
+                file.display()
+            ))
+        })?;
+        Ok(files.len())
+    }
+}
+
+/// `HASH  PATH` line as `b3sum` and `sha256sum` write it
+///
+/// A path with a backslash or newline is escaped (`\\`, `\n`) and the line
+/// starts with a backslash.
+fn line(digest: &str, path: &Path) -> Vec<u8> {
+    let name = path.as_os_str().as_bytes();
+    let escaped = name.iter().any(|&byte| byte == b'\\' || byte == b'\n');
+    let mut line = Vec::with_capacity(digest.len() + name.len() + 4);
+    if escaped {
+        line.push(b'\\');
+    }
+    line.extend_from_slice(digest.as_bytes());
+    line.extend_from_slice(b"  ");
+    for &byte in name {
+        match byte {
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            _ => line.push(byte),
+        }
+    }
+    line.push(b'\n');
+    line
+}
+
+/// Feed the first `len` bytes of `reader` (all of it if `None`) to `digester`
+///
+/// For data that did not pass through the copy loop: a `--partial` prefix,
+/// or what a content filter wrote. `reader` must be open for reading.
+///
+/// # Errors
+///
+/// Returns `SyncError::FileSystem` if the file cannot be read.
+pub async fn read_back(
+    reader: &File,
+    len: Option<u64>,
+    mut digester: Digester,
+    dst: &Path,
+) -> Result<Digester> {
+    let fd = reader.as_raw_fd();
+    // `reader` outlives the await, so the raw fd stays valid
+    compio::runtime::spawn_blocking(move || {
+        // SAFETY: fd is open for the duration; ManuallyDrop keeps it from being closed
+        let file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+        let mut buffer = vec![0; READ_BACK_SIZE];
+        let mut offset = 0;
+        while len.is_none_or(|len| offset < len) {
+            #[allow(clippy::cast_possible_truncation)] // At most READ_BACK_SIZE
+            let want = len.map_or(READ_BACK_SIZE, |len| {
+                (len - offset).min(READ_BACK_SIZE as u64) as usize
+            });
+            let read = file.read_at(&mut buffer[..want], offset)?;
+            if read == 0 {
+                break;
+            }
+            digester.update(&buffer[..read]);
+            offset += read as u64;
+        }
+        Ok::<_, std::io::Error>(digester)
+    })
+    .await
+    .map_err(|_| SyncError::Internal("Checksum worker panicked".to_string()))?
+    .map_err(|e| {
+        SyncError::FileSystem(format!(
+            "Failed to read back {} for its checksum: {e}",
+            dst.display()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn digest(algorithm: ChecksumAlgorithm, parts: &[&[u8]]) -> String {
+        let mut digester = Digester::new(algorithm);
+        for part in parts {
+            digester.update(part);
+        }
+        digester.finish()
+    }
+
+    #[test]
+    fn test_digests_match_b3sum_and_sha256sum() {
+        // Requirement: The hashes are those the standard tools print,
+        // however the data is split into chunks
+        assert_eq!(
+            digest(ChecksumAlgorithm::Sha256, &[b"ab", b"c"]),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(ChecksumAlgorithm::Blake3, &[b""]),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        let mut zeros = Digester::new(ChecksumAlgorithm::Blake3);
+        zeros.update(b"x");
+        zeros.update_zeros(100_000);
+        assert_eq!(
+            zeros.finish(),
+            digest(ChecksumAlgorithm::Blake3, &[b"x", &[0; 100_000]])
+        );
+    }
+
+    #[test]
+    fn test_lines_are_escaped_like_coreutils() {
+        // Requirement: `sha256sum -c` and `b3sum -c` read the names back
+        assert_eq!(line("ab", Path::new("dir/file")), b"ab  dir/file\n");
+        assert_eq!(line("ab", Path::new("a\nb\\c")), b"\\ab  a\\nb\\\\c\n");
+    }
+
+    #[test]
+    fn test_lists_of_runs_are_separate() {
+        // Requirement: Runs going on at once each list only their own files
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let (first, second) = (
+            ChecksumList::new(Some(ChecksumAlgorithm::Sha256)),
+            ChecksumList::new(Some(ChecksumAlgorithm::Sha256)),
+        );
+        let mut digester = first.digester().unwrap();
+        digester.update(b"abc");
+        first.record(&root.join("a"), digester);
+        first.link(&root.join("b"), &root.join("a"));
+        second.record(&root.join("c"), second.digester().unwrap());
+
+        let list = root.join("first.sha256");
+        assert_eq!(first.finish(&list, root).unwrap(), 2);
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            std::fs::read_to_string(&list).unwrap(),
+            format!("{digest}  a\n{digest}  b\n")
+        );
+        assert_eq!(second.finish(&root.join("second.sha256"), root).unwrap(), 1);
+        assert!(ChecksumList::default().digester().is_none());
+    }
+}
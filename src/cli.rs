@@ -3,17 +3,34 @@
 //! This module organizes CLI arguments by **functional usage** - each group
 //! contains the options needed by a specific component or subsystem.
 
+use crate::bisync::ConflictPolicy;
 use anyhow::Result;
 use clap::Parser;
-use std::num::NonZeroUsize;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 
 // Import MetadataConfig from metadata module
 pub use crate::metadata::MetadataConfig;
 
+/// Subcommands, listed after the options in `arsync --help`
+///
+/// Each is recognized as the first argument and has its own `--help`.
+const SUBCOMMANDS_HELP: &str = "\
+Subcommands:
+  cleanup DESTINATION         Remove stale temporary artifacts of interrupted runs
+  restore ENCRYPTED DEST      Decrypt a copy made with --encrypt-to
+  verify SOURCE DESTINATION   Check that a copy matches its source, by content
+  plan SOURCE DESTINATION...  Report which copy mechanisms a copy would use
+  bisync A B                  Two-way sync of two directory trees (experimental)
+  simulate MANIFEST           Benchmark the scheduler on a synthetic tree
+  usage LEDGER                Show transfer accounting recorded by a daemon
+  serve                       Run as a service controlled over a Unix socket
+  probe [USER@]HOST[:PATH]    Show what a remote arsync supports
+  --daemon                    Receive pushes from rsync:// clients";
+
 /// High-performance bulk file copying utility using `io_uring`
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = SUBCOMMANDS_HELP)]
 pub struct Args {
     /// Source and destination paths
     #[command(flatten)]
@@ -31,11 +48,448 @@ pub struct Args {
     #[command(flatten)]
     pub metadata: MetadataConfig,
 
+    /// Directory traversal behavior
+    #[command(flatten)]
+    pub traversal: TraversalConfig,
+
+    /// Remote shell and remote command configuration
+    #[command(flatten)]
+    pub remote: RemoteConfig,
+
     /// Output and logging configuration
     #[command(flatten)]
     pub output: OutputConfig,
 }
 
+/// Remove stale temporary artifacts left behind by interrupted runs
+///
+/// Invoked as `arsync cleanup DESTINATION`. (To copy from a source directory
+/// literally named `cleanup`, write it as `./cleanup`.)
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync cleanup", version, long_about = None)]
+pub struct CleanupArgs {
+    /// Destination directory to scan for `.arsync.tmp.*` artifacts
+    #[arg(value_name = "DESTINATION")]
+    pub destination: PathBuf,
+
+    /// List stale artifacts without removing them
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Also remove artifacts whose owning process still appears to be running
+    ///
+    /// Process IDs can be reused, so a live-looking owner may be unrelated.
+    /// Only use this when no other arsync run targets the destination.
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl CleanupArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "cleanup";
+
+    /// Cleanup options for `cleanup_stale_artifacts()`
+    #[must_use]
+    pub const fn options(&self) -> crate::temp_files::CleanupOptions {
+        crate::temp_files::CleanupOptions {
+            dry_run: self.dry_run,
+            force: self.force,
+        }
+    }
+}
+
+/// Decrypt a copy made with --encrypt-to
+///
+/// Invoked as `arsync restore --identity FILE ENCRYPTED DESTINATION`. Files
+/// are decrypted, and with --encrypt-names entries get their names back,
+/// along with their permissions and times.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync restore", version, long_about = None)]
+pub struct RestoreArgs {
+    /// Encrypted copy (the destination of the --encrypt-to run)
+    #[arg(value_name = "ENCRYPTED")]
+    pub source: PathBuf,
+
+    /// Directory to restore into
+    #[arg(value_name = "DESTINATION")]
+    pub destination: PathBuf,
+
+    /// age identity file holding the key of a recipient
+    #[arg(long, value_name = "FILE")]
+    pub identity: PathBuf,
+}
+
+impl RestoreArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "restore";
+}
+
+/// Check that a copy matches its source, by content
+///
+/// Invoked as `arsync verify SOURCE DESTINATION`. Every entry of SOURCE must
+/// be in DESTINATION with the same type, symlinks with the same target and
+/// files with the same BLAKE3 hash; entries only in DESTINATION are reported
+/// too. Metadata is not compared. Exits with status 1 if anything differs.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync verify", version, long_about = None)]
+pub struct VerifyArgs {
+    /// Original tree (or file)
+    #[arg(value_name = "SOURCE")]
+    pub source: PathBuf,
+
+    /// Copy to check
+    #[arg(value_name = "DESTINATION")]
+    pub destination: PathBuf,
+
+    /// Hash on N threads (default: one per CPU), large files region by region
+    ///
+    /// Without this option a single thread hashes everything.
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "0")]
+    pub parallel: Option<usize>,
+
+    /// Size of the regions large files are hashed in, in MB
+    #[arg(long, default_value = "64", value_parser = clap::value_parser!(u64).range(1..))]
+    pub region_size_mb: u64,
+
+    /// Do not show progress
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+impl VerifyArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "verify";
+
+    /// Options for `verify()`
+    #[must_use]
+    pub fn options(&self) -> crate::verify::VerifyOptions {
+        crate::verify::VerifyOptions {
+            threads: match self.parallel {
+                None => 1,
+                Some(0) => num_cpus::get(),
+                Some(threads) => threads,
+            },
+            region_size: self.region_size_mb * 1024 * 1024,
+            progress: !self.quiet,
+        }
+    }
+}
+
+/// Report which copy mechanisms a copy would use, without copying
+///
+/// Invoked as `arsync plan [--explain] SOURCE DESTINATION...`. For every
+/// destination, prints how many files and bytes would be copied by reflink,
+/// `copy_file_range`, server-side copy or read/write, and the expected copy
+/// buffer memory and page cache traffic. The mechanisms are tried on a
+/// sample of each source filesystem's data, written to unnamed scratch files
+/// in the destination. Takes the copy's options that affect the choice.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync plan", version, long_about = None)]
+pub struct PlanArgs {
+    /// Tree (or file) to be copied
+    #[arg(value_name = "SOURCE")]
+    pub source: PathBuf,
+
+    /// Where it would be copied to (several for a fan-out copy)
+    #[arg(value_name = "DESTINATION", required = true)]
+    pub destinations: Vec<PathBuf>,
+
+    /// Also show, per source filesystem, which mechanisms are usable and why
+    /// one is chosen, and how the footprint is estimated
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Copy method of the copy
+    #[arg(long, default_value = "auto")]
+    pub copy_method: CopyMethod,
+
+    /// The copy preserves holes (--sparse)
+    #[arg(short = 'S', long)]
+    pub sparse: bool,
+
+    /// Queue depth of the copy
+    #[arg(long, default_value = "4096")]
+    pub queue_depth: usize,
+
+    /// Buffer size of the copy in KB (default: from the tuning profile)
+    #[arg(long)]
+    pub buffer_size_kb: Option<NonZeroUsize>,
+
+    /// Tuning profile of the copy (default: detected for each destination)
+    #[arg(long, value_enum, value_name = "PROFILE")]
+    pub tune: Option<crate::tuning::TuneProfile>,
+
+    /// Maximum files in flight of the copy
+    #[arg(long, default_value = "1024")]
+    pub max_files_in_flight: usize,
+
+    /// Large-file pipeline threshold of the copy, in MB (0: none)
+    #[arg(long, value_name = "MB", default_value = "64")]
+    pub large_file_threshold_mb: u64,
+
+    /// Maximum large files in flight of the copy
+    #[arg(long, value_name = "N", default_value = "8")]
+    pub large_files_in_flight: usize,
+}
+
+impl PlanArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "plan";
+
+    /// Options for `plan()`
+    #[must_use]
+    pub fn options(&self) -> crate::plan::PlanOptions {
+        crate::plan::PlanOptions {
+            copy_method: self.copy_method.clone(),
+            sparse: self.sparse,
+            queue_depth: self.queue_depth,
+            buffer_size: self.buffer_size_kb.map(|kb| kb.get() * 1024),
+            tune: self.tune,
+            max_files_in_flight: self.max_files_in_flight,
+            large_file_threshold: self.large_file_threshold_mb * 1024 * 1024,
+            large_files_in_flight: self.large_files_in_flight,
+        }
+    }
+}
+
+/// Two-way sync of two directory trees (experimental)
+///
+/// Invoked as `arsync bisync A B`. Changes made on either side since the last
+/// run are copied to the other; files changed on both sides are resolved by
+/// --conflict.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync bisync", version, long_about = None)]
+pub struct BisyncArgs {
+    /// First directory
+    #[arg(value_name = "A")]
+    pub a: PathBuf,
+
+    /// Second directory
+    #[arg(value_name = "B")]
+    pub b: PathBuf,
+
+    /// How to resolve files modified on both sides
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::NewestWins)]
+    pub conflict: ConflictPolicy,
+
+    /// Suffix for B's version with --conflict=keep-both (notes.SUFFIX.txt)
+    #[arg(long, value_name = "SUFFIX", default_value = "conflict")]
+    pub conflict_suffix: String,
+
+    /// State database (default: A/.arsync-bisync.state)
+    #[arg(long, value_name = "FILE")]
+    pub state: Option<PathBuf>,
+
+    /// Show what would be done without changing either side
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+impl BisyncArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "bisync";
+
+    /// Options for `bisync()`
+    #[must_use]
+    pub fn options(&self) -> crate::bisync::BisyncOptions {
+        crate::bisync::BisyncOptions {
+            policy: self.conflict,
+            conflict_suffix: self.conflict_suffix.clone(),
+            state_file: self.state.clone(),
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+/// Benchmark the scheduler on a synthetic tree without touching disks
+///
+/// Invoked as `arsync simulate MANIFEST`. Every copy operation is replaced by
+/// a sleep proportional to the file's size; see `simulate` for the manifest
+/// format.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync simulate", version, long_about = None)]
+pub struct SimulateArgs {
+    /// Manifest listing `SIZE PATH` per line
+    #[arg(value_name = "MANIFEST")]
+    pub manifest: PathBuf,
+
+    /// Simulated copy throughput of one file (or parallel region), in MB/s
+    #[arg(long, default_value = "500", value_parser = clap::value_parser!(u64).range(1..))]
+    pub throughput_mb: u64,
+
+    /// Simulated fixed cost of each file, in microseconds
+    #[arg(long, default_value = "50")]
+    pub file_latency_us: u64,
+
+    /// Simulated cost of each directory, in microseconds
+    #[arg(long, default_value = "20")]
+    pub dir_latency_us: u64,
+
+    /// Concurrency control (as for a real copy)
+    #[command(flatten)]
+    pub concurrency: ConcurrencyConfig,
+
+    /// Parallel large-file copy (as for a real copy)
+    #[command(flatten)]
+    pub parallel: ParallelCopyConfig,
+}
+
+impl SimulateArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "simulate";
+
+    /// Operation costs for `simulate()`
+    #[must_use]
+    pub const fn cost(&self) -> crate::simulate::SimulationCost {
+        crate::simulate::SimulationCost {
+            bytes_per_sec: self.throughput_mb * 1024 * 1024,
+            file_latency: std::time::Duration::from_micros(self.file_latency_us),
+            dir_latency: std::time::Duration::from_micros(self.dir_latency_us),
+        }
+    }
+}
+
+/// Show transfer accounting recorded by a daemon
+///
+/// Invoked as `arsync usage LEDGER`. Prints bytes in and out per day, module
+/// and client, with a total per module.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync usage", version, long_about = None)]
+pub struct UsageArgs {
+    /// Usage ledger written by the daemon
+    #[arg(value_name = "LEDGER")]
+    pub ledger: PathBuf,
+
+    /// Only show this module
+    #[arg(long)]
+    pub module: Option<String>,
+
+    /// Only show this client
+    #[arg(long)]
+    pub client: Option<String>,
+
+    /// Only show days from this one on (YYYY-MM-DD; default: the last 30 days)
+    #[arg(long, value_name = "DAY", value_parser = parse_day)]
+    pub since: Option<crate::protocol::accounting::Day>,
+}
+
+impl UsageArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "usage";
+}
+
+fn parse_day(text: &str) -> std::result::Result<crate::protocol::accounting::Day, String> {
+    crate::protocol::accounting::Day::parse(text)
+        .ok_or_else(|| format!("invalid day {text:?} (expected YYYY-MM-DD)"))
+}
+
+/// Run as a long-running service for orchestration tools
+///
+/// Invoked as `arsync serve --control-socket PATH`. Accepts JSON-RPC 2.0
+/// requests, one per line, on the Unix socket PATH to submit sync jobs,
+/// query their progress, cancel them and list them (see `control`).
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync serve", version, long_about = None)]
+pub struct ServeArgs {
+    /// Unix socket to accept control connections on (created owner-only)
+    #[arg(long, value_name = "PATH")]
+    pub control_socket: PathBuf,
+
+    /// Jobs of a priority class (or a more urgent one) running at once;
+    /// later jobs wait, most urgent class first
+    #[arg(long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..=1024))]
+    pub max_jobs: u64,
+
+    /// Files in flight shared by all running jobs, weighted by their
+    /// priority classes
+    #[arg(long, default_value = "1024", value_parser = clap::value_parser!(u64).range(1..=10_000))]
+    pub max_files_in_flight: u64,
+
+    /// Log job starts and ends (-vv for debug output)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl ServeArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "serve";
+}
+
+/// Show what a remote arsync and the filesystem at its path support
+///
+/// Invoked as `arsync probe [USER@]HOST[:PATH]`. Connects like a transfer
+/// would (over --rsh), performs the protocol handshake and capability
+/// negotiation, and prints the remote version, protocol versions,
+/// capabilities, compression codecs, checksums and filesystem features of
+/// PATH (default: the remote home directory). Nothing is transferred.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync probe", version, long_about = None)]
+pub struct ProbeArgs {
+    /// Remote host, optionally with user and target path
+    #[arg(value_name = "[USER@]HOST[:PATH]")]
+    pub target: String,
+
+    /// Remote shell configuration
+    #[command(flatten)]
+    pub remote: RemoteConfig,
+
+    /// Answer a probe on stdin/stdout (run by the probing client)
+    #[arg(long, hide = true)]
+    pub server: bool,
+}
+
+impl ProbeArgs {
+    /// Subcommand name recognized as the first argument
+    pub const SUBCOMMAND: &'static str = "probe";
+
+    /// `(user, host, path)` of the target; an empty user lets ssh choose
+    #[must_use]
+    pub fn split_target(&self) -> (&str, &str, &str) {
+        let (login, path) = self
+            .target
+            .split_once(':')
+            .unwrap_or((self.target.as_str(), ""));
+        let (user, host) = login.split_once('@').unwrap_or(("", login));
+        (user, host, path)
+    }
+}
+
+/// Receive pushes from rsync clients over TCP, without ssh
+///
+/// Invoked as `arsync --daemon --config FILE`. Listens for `rsync://`
+/// clients (rsync's daemon protocol) and receives into the modules defined
+/// in FILE, a subset of rsyncd.conf (see `protocol::daemon`). Clients are
+/// not authenticated.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "arsync --daemon", version, long_about = None)]
+pub struct DaemonArgs {
+    /// Module definitions (rsyncd.conf subset)
+    #[arg(long, value_name = "FILE", default_value = "/etc/arsyncd.conf")]
+    pub config: PathBuf,
+
+    /// Port to listen on, instead of the config's `port` (default 873)
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Address to listen on, instead of the config's `address` (default: all
+    /// IPv4 addresses)
+    #[arg(long, value_name = "ADDR")]
+    pub address: Option<std::net::IpAddr>,
+
+    /// Seconds running sessions get to finish after SIGTERM
+    #[arg(long, value_name = "SECS", default_value = "30")]
+    pub drain_timeout: u64,
+
+    /// Log sessions (-vv for debug output)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl DaemonArgs {
+    /// First argument that starts the daemon
+    pub const FLAG: &'static str = "--daemon";
+}
+
 // ============================================================================
 // FUNCTIONAL GROUPS: Organized by what component consumes them
 // ============================================================================
@@ -52,6 +506,14 @@ pub struct PathConfig {
     /// Destination directory or file
     #[arg(value_name = "DESTINATION")]
     pub destination: PathBuf,
+
+    /// Further destinations, each getting a full copy (fan-out)
+    ///
+    /// Every source file is read once and written to all destinations
+    /// concurrently. Each destination is reported separately; one that fails
+    /// is dropped and the others are completed.
+    #[arg(value_name = "DESTINATION")]
+    pub extra_destinations: Vec<PathBuf>,
 }
 
 /// I/O and `FileOperations` configuration
@@ -61,6 +523,9 @@ pub struct PathConfig {
 #[command(next_help_heading = "I/O Performance Options")]
 pub struct IoConfig {
     /// Queue depth for `io_uring` operations
+    ///
+    /// Every 1024 also lets one more chunk of each file be in flight (read
+    /// while earlier chunks are written), up to 16.
     #[arg(long, default_value = "4096")]
     pub queue_depth: usize,
 
@@ -70,6 +535,19 @@ pub struct IoConfig {
     #[arg(long)]
     pub buffer_size_kb: Option<NonZeroUsize>,
 
+    /// Tuning profile to use instead of the one detected for the destination
+    ///
+    /// Profiles set the buffer size, whether files are preallocated, whether
+    /// large files may be written in parallel and whether per-file fsync is
+    /// considered expensive. FUSE destinations (s3fs, gcsfuse) get `fuse`:
+    /// no preallocation, sequential whole-file writes, 8 MB buffers.
+    #[arg(long, value_enum, value_name = "PROFILE")]
+    pub tune: Option<crate::tuning::TuneProfile>,
+
+    /// Print the tuning profile chosen for the destination and exit
+    #[arg(long)]
+    pub show_tuning: bool,
+
     /// Copy method to use
     #[arg(long, default_value = "auto")]
     pub copy_method: CopyMethod,
@@ -91,7 +569,8 @@ pub struct IoConfig {
 pub struct ParallelCopyConfig {
     /// Maximum recursion depth for parallel file copying
     ///
-    /// Creates up to 2^depth parallel tasks for large files.
+    /// Creates up to 2^depth parallel tasks for large files, splitting the
+    /// file along its extents (FIEMAP) and skipping its holes.
     /// - Depth 0 (default): Disabled - use sequential copy
     /// - Depth 1: 2 parallel tasks
     /// - Depth 2: 4 parallel tasks
@@ -118,6 +597,15 @@ pub struct ParallelCopyConfig {
     /// Default: 2 MB
     #[arg(long = "parallel-chunk-size-mb", default_value = "2")]
     pub chunk_size_mb: usize,
+
+    /// Choose the parallel depth per device
+    ///
+    /// Files on rotational disks are copied sequentially, and the depth is
+    /// capped between slow devices, timed from the first chunks copied
+    /// between them. --parallel-max-depth is the most used.
+    /// Only applies when --parallel-max-depth > 0.
+    #[arg(long = "parallel-adaptive")]
+    pub adaptive: bool,
 }
 
 impl ParallelCopyConfig {
@@ -219,6 +707,42 @@ pub struct ConcurrencyConfig {
     /// where you want to catch configuration issues early.
     #[arg(long)]
     pub no_adaptive_concurrency: bool,
+
+    /// Files at least this large (in MB) go through the large-file pipeline
+    ///
+    /// Large files get their own worker threads (and `io_uring` rings), their
+    /// own --large-files-in-flight limit and buffers of at least 1 MB, so a
+    /// few huge files neither starve nor are starved by many small ones.
+    /// 0 copies every file through one pipeline.
+    #[arg(long, value_name = "MB", default_value = "64")]
+    pub large_file_threshold_mb: u64,
+
+    /// Maximum large files in flight (see --large-file-threshold-mb)
+    #[arg(long, value_name = "N", default_value = "8")]
+    pub large_files_in_flight: usize,
+
+    /// Spill completed hardlink inodes to files in this directory
+    ///
+    /// For trees with hundreds of millions of hardlinked inodes, tracking them
+    /// all in memory can take many GB. With this option, completed inodes are
+    /// kept in a compact table and, beyond --hardlink-memory-entries, spilled
+    /// to (immediately unlinked) files in DIR.
+    #[arg(long, value_name = "DIR")]
+    pub hardlink_spill_dir: Option<PathBuf>,
+
+    /// Completed hardlink inodes kept in memory before spilling to disk
+    ///
+    /// Only used with --hardlink-spill-dir.
+    #[arg(long, default_value_t = crate::hardlink_store::InodeStoreConfig::DEFAULT_MEMORY_ENTRIES)]
+    pub hardlink_memory_entries: usize,
+
+    /// Do not take the destination lock (`.arsync.lock`)
+    ///
+    /// By default arsync holds an exclusive lock on the destination for the
+    /// whole run and refuses to start if another arsync is syncing into it.
+    /// Only use this if the destination's filesystem does not support locks.
+    #[arg(long)]
+    pub no_lock: bool,
 }
 
 impl ConcurrencyConfig {
@@ -232,6 +756,446 @@ impl ConcurrencyConfig {
             self.no_adaptive_concurrency,
         )
     }
+
+    /// Compact hardlink store configuration, if spilling is enabled
+    #[must_use]
+    pub fn hardlink_store_config(&self) -> Option<crate::hardlink_store::InodeStoreConfig> {
+        self.hardlink_spill_dir.as_deref().map(|dir| {
+            crate::hardlink_store::InodeStoreConfig::with_spill(self.hardlink_memory_entries, dir)
+        })
+    }
+}
+
+/// Directory traversal configuration
+///
+/// Used by: `traverse_and_copy_directory_iterative()`
+#[derive(clap::Args, Debug, Clone, Default)]
+#[command(next_help_heading = "Traversal Options")]
+pub struct TraversalConfig {
+    /// Descend into directories already seen at another path (bind mounts)
+    ///
+    /// Bind mounts can make the same directory appear at several paths, or even
+    /// inside itself. By default arsync copies each directory (by device and
+    /// inode) only once and skips later occurrences with a warning. With this
+    /// flag, duplicates are copied at every path; true loops (a directory
+    /// inside itself) are still always skipped.
+    #[arg(long)]
+    pub follow_bind_mounts: bool,
+
+    /// Copy even though the destination is inside the source
+    ///
+    /// Such a copy is refused because it would copy into itself. With this
+    /// flag it runs as long as an exclude rule covers the destination (e.g.
+    /// `--exclude /backup/` for `arsync /data /data/backup`); a destination
+    /// that is the source is always refused.
+    #[arg(long)]
+    pub allow_overlap: bool,
+
+    /// Shorten names longer than the destination filesystem's NAME_MAX
+    ///
+    /// Without this option such files are reported and the copy fails. With
+    /// `hash`, each long name is cut down and suffixed with a hash of the full
+    /// name (keeping the extension); every mapping is recorded in
+    /// `.arsync-long-names` in the destination.
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub truncate_long_names: Option<crate::long_names::LongNamePolicy>,
+
+    /// Warm the source cache up to N files ahead of the copy
+    ///
+    /// For high-latency sources (object-store FUSE, NFS over a WAN): a
+    /// background thread looks up the metadata of upcoming files and asks the
+    /// kernel to read ahead the first MB of each, staying at most N files and
+    /// --preread-budget-mb ahead of the copy.
+    #[arg(long, value_name = "N")]
+    pub preread: Option<NonZeroUsize>,
+
+    /// Data --preread may cache ahead of the copy, in MB
+    #[arg(long, value_name = "MB", default_value = "256")]
+    pub preread_budget_mb: u64,
+
+    /// Record statistics and completed entries in a journal as the copy runs
+    ///
+    /// Checkpoints are appended and synced every --checkpoint-secs seconds or
+    /// --checkpoint-files completed files, so the journal stays accurate if
+    /// arsync crashes. A successful run ends the journal with an `E` record.
+    #[arg(long, value_name = "PATH")]
+    pub state_journal: Option<PathBuf>,
+
+    /// Seconds between --state-journal checkpoints (0: only per file batch)
+    #[arg(long, value_name = "SECS", default_value = "10")]
+    pub checkpoint_secs: u64,
+
+    /// Completed files per --state-journal checkpoint (0: only on time)
+    #[arg(long, value_name = "N", default_value = "1000")]
+    pub checkpoint_files: usize,
+
+    /// Delete destination entries that are not in the source
+    ///
+    /// Each directory's extraneous entries are removed when the traversal
+    /// reaches it (like --delete-during). arsync's own files in the
+    /// destination (lock, long-name manifest, journals) are kept.
+    #[arg(long)]
+    pub delete: bool,
+
+    /// Delete extraneous entries before copying anything (implies --delete)
+    #[arg(long, conflicts_with_all = ["delete_during", "delete_after"])]
+    pub delete_before: bool,
+
+    /// Delete extraneous entries per directory as it is copied (implies --delete)
+    #[arg(long, conflicts_with = "delete_after")]
+    pub delete_during: bool,
+
+    /// Delete extraneous entries after a successful copy (implies --delete)
+    #[arg(long)]
+    pub delete_after: bool,
+
+    /// Hard link unchanged files from DIR instead of copying them (repeatable)
+    ///
+    /// A file whose counterpart at the same path in DIR matches the source
+    /// (size and modification time, or contents with --checksum) and has the
+    /// permissions and ownership being preserved is hard linked into the
+    /// destination, so snapshot backups only store what changed. DIRs are
+    /// tried in order; a relative DIR is relative to the destination. Files a
+    /// link cannot reach (DIR on another filesystem) are copied.
+    #[arg(long, value_name = "DIR")]
+    pub link_dest: Vec<PathBuf>,
+
+    /// Pipe files matching GLOB through the shell command CMD (GLOB:CMD)
+    ///
+    /// The source is CMD's standard input and its standard output becomes
+    /// the destination's contents, e.g. --transform='*.txt:unix2dos'. GLOB
+    /// is an --exclude pattern; may be repeated, and the first matching rule
+    /// wins. A transformed file is up to date when its modification time
+    /// matches, whatever its size or contents, and a failing CMD leaves the
+    /// destination as it was.
+    #[arg(long, value_name = "GLOB:CMD", value_parser = crate::transform::TransformRule::parse)]
+    pub transform: Vec<crate::transform::TransformRule>,
+
+    /// Encrypt file contents for the age recipient RECIPIENT (age1...)
+    ///
+    /// For backups to untrusted storage: each file is stored as an age file
+    /// that only the matching identity can decrypt, with `arsync restore
+    /// --identity FILE`. May be repeated. An encrypted file is up to date when
+    /// its modification time matches; tree shape, sizes, metadata and symlink
+    /// targets stay visible.
+    #[arg(
+        long,
+        value_name = "RECIPIENT",
+        value_parser = crate::encryption::parse_recipient,
+        conflicts_with_all = ["transform", "link_dest", "shadow_rsync"]
+    )]
+    pub encrypt_to: Vec<String>,
+
+    /// Also encrypt for the identities in the age identity file FILE
+    ///
+    /// Needed by --encrypt-names, to read the names given by earlier runs.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["transform", "link_dest", "shadow_rsync"]
+    )]
+    pub encrypt_identity: Option<PathBuf>,
+
+    /// Store entries under random names, listed in the encrypted manifest
+    /// `.arsync-encryption` at the destination root
+    #[arg(long, requires = "encrypt_identity")]
+    pub encrypt_names: bool,
+
+    /// Skip files whose contents match, instead of size and modification time
+    ///
+    /// An existing destination file of the same size is read and compared with
+    /// the source; it is only copied again if the contents differ.
+    #[arg(short = 'c', long)]
+    pub checksum: bool,
+
+    /// Copy every file, even if the destination's size and mtime match
+    #[arg(short = 'I', long, conflicts_with = "checksum")]
+    pub ignore_times: bool,
+
+    /// Exit with status 0 despite failed entries, and still --delete-after
+    ///
+    /// An entry that fails (unreadable file, refused write) is skipped and
+    /// the copy goes on; every failed path is listed with its errno at the
+    /// end. Without this flag such a run exits with status 23, like rsync,
+    /// and --delete-after deletes nothing.
+    #[arg(long)]
+    pub ignore_errors: bool,
+
+    /// Stop after N entries have failed (the run then fails)
+    #[arg(long, value_name = "N")]
+    pub max_errors: Option<NonZeroU64>,
+
+    /// Include/exclude rules (kept last: it sets its own help heading)
+    #[command(flatten)]
+    pub filter: FilterConfig,
+}
+
+impl TraversalConfig {
+    /// When extraneous destination entries are deleted, if at all
+    #[must_use]
+    pub const fn delete_timing(&self) -> Option<crate::directory::DeleteTiming> {
+        use crate::directory::DeleteTiming;
+        if self.delete_before {
+            Some(DeleteTiming::Before)
+        } else if self.delete_after {
+            Some(DeleteTiming::After)
+        } else if self.delete || self.delete_during {
+            Some(DeleteTiming::During)
+        } else {
+            None
+        }
+    }
+
+    /// Whether file contents are encrypted (`--encrypt-to`, `--encrypt-identity`)
+    #[must_use]
+    pub const fn encrypts(&self) -> bool {
+        !self.encrypt_to.is_empty() || self.encrypt_identity.is_some()
+    }
+
+    /// How existing destination files are found up to date, if at all
+    #[must_use]
+    pub const fn update_check(&self) -> Option<crate::directory::UpdateCheck> {
+        use crate::directory::UpdateCheck;
+        if self.ignore_times {
+            None
+        } else if self.checksum {
+            Some(UpdateCheck::Checksum)
+        } else {
+            Some(UpdateCheck::SizeAndMtime)
+        }
+    }
+}
+
+/// One `--exclude`, `--include`, `--exclude-from` or `--filter` option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterOption {
+    /// `--exclude PATTERN`
+    Exclude(String),
+    /// `--include PATTERN`
+    Include(String),
+    /// `--exclude-from FILE`
+    ExcludeFrom(PathBuf),
+    /// `--filter RULE`
+    Filter(String),
+}
+
+/// Include/exclude filter options, in command-line order
+///
+/// Used by: `FilterRules::from_config()`
+///
+/// rsync checks the rules of all four options in the order they were given,
+/// which derived fields (one `Vec` per option) would lose, so `clap::Args` is
+/// implemented by hand using the argument indices.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterConfig {
+    /// Options in command-line order
+    pub rules: Vec<FilterOption>,
+}
+
+impl FilterConfig {
+    const EXCLUDE: &'static str = "exclude";
+    const INCLUDE: &'static str = "include";
+    const EXCLUDE_FROM: &'static str = "exclude_from";
+    const FILTER: &'static str = "filter";
+}
+
+impl clap::FromArgMatches for FilterConfig {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let mut config = Self::default();
+        config.update_from_arg_matches(matches)?;
+        Ok(config)
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        fn indexed<T: Clone + Send + Sync + 'static>(
+            matches: &clap::ArgMatches,
+            id: &str,
+            option: impl Fn(T) -> FilterOption,
+        ) -> Vec<(usize, FilterOption)> {
+            match (matches.indices_of(id), matches.get_many::<T>(id)) {
+                (Some(indices), Some(values)) => indices.zip(values.cloned().map(option)).collect(),
+                _ => Vec::new(),
+            }
+        }
+
+        let mut rules = indexed(matches, Self::EXCLUDE, FilterOption::Exclude);
+        rules.extend(indexed(matches, Self::INCLUDE, FilterOption::Include));
+        rules.extend(indexed(
+            matches,
+            Self::EXCLUDE_FROM,
+            FilterOption::ExcludeFrom,
+        ));
+        rules.extend(indexed(matches, Self::FILTER, FilterOption::Filter));
+        if !rules.is_empty() {
+            rules.sort_by_key(|&(index, _)| index);
+            self.rules = rules.into_iter().map(|(_, rule)| rule).collect();
+        }
+        Ok(())
+    }
+}
+
+impl clap::Args for FilterConfig {
+    fn augment_args(cmd: clap::Command) -> clap::Command {
+        use clap::{value_parser, Arg, ArgAction};
+        let option = |id: &'static str, long: &'static str, value_name: &'static str| {
+            Arg::new(id)
+                .long(long)
+                .value_name(value_name)
+                .action(ArgAction::Append)
+        };
+        cmd.next_help_heading("Filter Options")
+            .arg(
+                option(Self::EXCLUDE, "exclude", "PATTERN")
+                    .value_parser(value_parser!(String))
+                    .help("Skip files and directories matching PATTERN (rsync syntax)"),
+            )
+            .arg(
+                option(Self::INCLUDE, "include", "PATTERN")
+                    .value_parser(value_parser!(String))
+                    .help("Copy files matching PATTERN even if a later rule excludes them"),
+            )
+            .arg(
+                option(Self::EXCLUDE_FROM, "exclude-from", "FILE")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Read exclude patterns from FILE, one per line"),
+            )
+            .arg(
+                option(Self::FILTER, "filter", "RULE")
+                    .short('f')
+                    .value_parser(value_parser!(String))
+                    .help("Add a filter rule: `- PATTERN`, `+ PATTERN` or `!` to clear")
+                    .long_help(
+                        "Add a filter rule: `- PATTERN` (exclude), `+ PATTERN` (include) \
+                         or `!` (clear the rules so far)\n\n\
+                         All --exclude, --include, --exclude-from and --filter rules are \
+                         checked in command-line order; the first match decides.",
+                    ),
+            )
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        Self::augment_args(cmd)
+    }
+}
+
+/// Remote shell configuration
+///
+/// Used by: `SshConnection`, `SshPool` (via `RemoteShell`)
+#[derive(clap::Args, Debug, Clone, Default)]
+#[command(next_help_heading = "Remote Options")]
+pub struct RemoteConfig {
+    /// Remote shell command, split like a shell would (default: ssh)
+    ///
+    /// Example: --rsh='ssh -p 2222 -i "/keys/my key"'
+    #[arg(short = 'e', long, value_name = "COMMAND")]
+    pub rsh: Option<String>,
+
+    /// Path or command for arsync on the remote host (default: arsync; rsync
+    /// when pushing to a remote destination)
+    ///
+    /// Inserted verbatim into the remote command line, like rsync's
+    /// --rsync-path (e.g. "sudo /opt/arsync/bin/arsync").
+    #[arg(long, value_name = "COMMAND", alias = "rsync-path")]
+    pub remote_cmd: Option<String>,
+
+    /// Keep the file list of a sending server in DIR between runs
+    ///
+    /// Repeated pulls of the same tree reuse the cached list instead of
+    /// reading every directory again. Any change to a directory invalidates
+    /// the cache; file metadata is always re-read.
+    #[arg(long, value_name = "DIR")]
+    pub flist_cache: Option<PathBuf>,
+
+    /// Connect again up to N times when the remote shell fails to connect
+    /// for a transient reason (default: 2)
+    ///
+    /// Refused or timed-out connections, unreachable networks and failed
+    /// name lookups are retried after 1s, 2s, 4s, ... (at most 30s). Only
+    /// connections that failed before the remote command started are retried.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub connect_retries: u32,
+
+    /// Reuse one SSH connection per host between runs (OpenSSH ControlMaster)
+    ///
+    /// The master connection stays open SECS seconds after its last session
+    /// (default: 60), so runs within that time skip the SSH handshake and
+    /// authentication. The remote shell must be OpenSSH.
+    #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "60")]
+    pub ssh_multiplex: Option<u64>,
+
+    /// Compress file data sent over the wire (remote transfers only)
+    ///
+    /// Between arsync hosts literal data is compressed with zstd; pushes to
+    /// rsync use rsync's zlib token compression.
+    #[arg(short = 'z', long)]
+    pub compress: bool,
+
+    /// Compression level (1-22 for zstd, 1-9 for zlib; 0 disables, default
+    /// picks the codec's default). Implies --compress.
+    #[arg(long, value_name = "NUM", value_parser = clap::value_parser!(u8).range(0..=22))]
+    pub compress_level: Option<u8>,
+}
+
+impl RemoteConfig {
+    /// Parse into a `RemoteShell`, applying defaults
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `--rsh` is empty or has unbalanced quotes, or if
+    /// `--remote-cmd` is empty.
+    pub fn remote_shell(&self) -> Result<crate::protocol::shell::RemoteShell> {
+        use crate::protocol::shell::{RemoteShell, DEFAULT_REMOTE_CMD, DEFAULT_RSH};
+        RemoteShell::parse(
+            self.rsh.as_deref().unwrap_or(DEFAULT_RSH),
+            self.remote_cmd.as_deref().unwrap_or(DEFAULT_REMOTE_CMD),
+        )
+    }
+
+    /// Connector opening sessions with `shell`, multiplexed and retried as
+    /// `--ssh-multiplex` and `--connect-retries` ask
+    #[cfg(feature = "remote-sync")]
+    #[must_use]
+    pub fn connector(
+        &self,
+        shell: crate::protocol::shell::RemoteShell,
+    ) -> crate::protocol::ssh::SshConnector {
+        use crate::protocol::ssh::{RetryPolicy, SshConnector};
+        let connector = SshConnector::new(shell).with_retry(RetryPolicy {
+            retries: self.connect_retries,
+            ..RetryPolicy::default()
+        });
+        match self.ssh_multiplex {
+            Some(secs) => connector.multiplexed(std::time::Duration::from_secs(secs)),
+            None => connector,
+        }
+    }
+
+    /// Compression asked for with `--compress`/`--compress-level`
+    #[cfg(feature = "remote-sync")]
+    #[must_use]
+    pub fn compress_level(&self) -> Option<crate::protocol::compress::CompressLevel> {
+        use crate::protocol::compress::CompressLevel;
+        match self.compress_level {
+            Some(0) => None,
+            Some(level) => Some(CompressLevel::new(level)),
+            None if self.compress => Some(CompressLevel::DEFAULT),
+            None => None,
+        }
+    }
+
+    /// Parse into a `RemoteShell` that starts rsync, for pushing to a
+    /// remote destination
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Self::remote_shell`].
+    pub fn rsync_shell(&self) -> Result<crate::protocol::shell::RemoteShell> {
+        use crate::protocol::shell::{RemoteShell, DEFAULT_RSH, DEFAULT_RSYNC_CMD};
+        RemoteShell::parse(
+            self.rsh.as_deref().unwrap_or(DEFAULT_RSH),
+            self.remote_cmd.as_deref().unwrap_or(DEFAULT_RSYNC_CMD),
+        )
+    }
 }
 
 /// Output and logging configuration
@@ -241,14 +1205,23 @@ impl ConcurrencyConfig {
 #[command(next_help_heading = "Output Options")]
 #[allow(clippy::struct_excessive_bools)]
 pub struct OutputConfig {
-    /// Show what would be copied without actually copying
-    #[arg(long)]
+    /// Show what would change without changing the destination
+    ///
+    /// Runs the whole traversal (filters, update checks, --delete) and
+    /// prints one itemized line per change, like rsync -n -i.
+    #[arg(short = 'n', long)]
     pub dry_run: bool,
 
     /// Show progress information
     #[arg(long)]
     pub progress: bool,
 
+    /// Ask before overwriting files that differ and before deleting entries
+    ///
+    /// Only active when stdin is a terminal.
+    #[arg(long)]
+    pub interactive: bool,
+
     /// Verbose output (-v, -vv, -vvv)
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
@@ -257,18 +1230,102 @@ pub struct OutputConfig {
     #[arg(short, long)]
     pub quiet: bool,
 
+    /// Report as line-delimited JSON events on stdout, for scripts and CI
+    ///
+    /// Every line is one object with an `event` field: `file_start`,
+    /// `progress`, `file_done`, `error`, `itemize` (with --dry-run) and a
+    /// final `summary`. Log messages go to stderr.
+    #[arg(
+        long,
+        conflicts_with_all = ["progress", "to_tar", "from_tar", "shadow_rsync"]
+    )]
+    pub json: bool,
+
+    /// After the run, check the destination with `rsync -n -i`
+    ///
+    /// rsync is run in dry-run itemize mode with the options matching
+    /// arsync's; any change it would still make is reported, grouped by what
+    /// it concerns (contents, times, permissions, ownership, ...), and fails
+    /// the run. For validating arsync against rsync when migrating.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub shadow_rsync: bool,
+
+    /// Write the source as a tar archive to DESTINATION instead of copying it
+    ///
+    /// DESTINATION is the archive file, or `-` for standard output. The
+    /// archive is in pax format; the metadata options choose what it carries
+    /// beyond mode, ownership and times (`-X` xattrs, `-A` ACLs, `-l` links,
+    /// `-H` hard links, `-D` devices and FIFOs). Filter rules apply.
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "shadow_rsync", "extra_destinations"]
+    )]
+    pub to_tar: bool,
+
+    /// Extract the tar archive SOURCE into DESTINATION instead of copying
+    ///
+    /// SOURCE is the archive file, or `-` for standard input. Members are
+    /// written like copied files (`--atomic-create` applies); the metadata
+    /// options choose what is restored from the archive. Filter rules apply
+    /// to member names.
+    #[arg(
+        long,
+        conflicts_with_all = ["to_tar", "dry_run", "shadow_rsync", "extra_destinations"]
+    )]
+    pub from_tar: bool,
+
+    /// Write a checksum list of every copied file to FILE
+    ///
+    /// The list is in `b3sum` format (`sha256sum` with --checksum-algorithm
+    /// sha256), with paths relative to the destination, so
+    /// `cd DESTINATION && b3sum -c FILE` verifies the copy without arsync.
+    /// Files are hashed as they are copied, which keeps them on the
+    /// read/write path (no copy_file_range, reflink or parallel copy).
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["dry_run", "to_tar", "from_tar", "extra_destinations"]
+    )]
+    pub emit_checksums: Option<PathBuf>,
+
+    /// Hash of the --emit-checksums list
+    #[arg(long, value_enum, default_value = "blake3")]
+    pub checksum_algorithm: crate::checksum_list::ChecksumAlgorithm,
+
+    /// Serve Prometheus metrics on http://ADDR/metrics while the run lasts
+    ///
+    /// io_uring submissions and reads/writes in flight, bytes and files
+    /// copied with their rates, failures and retries. ADDR is IP:PORT, e.g.
+    /// 127.0.0.1:9187.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["to_tar", "from_tar"])]
+    pub metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Write the metrics to FILE when the run ends, for node_exporter
+    ///
+    /// In the Prometheus text format, with `arsync_last_run_success` and
+    /// `arsync_last_run_timestamp_seconds`; name it `*.prom` in the textfile
+    /// collector's directory. FILE is replaced atomically.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["to_tar", "from_tar"])]
+    pub metrics_textfile: Option<PathBuf>,
+
     /// Enable pirate speak (arrr! 🏴‍☠️)
     #[arg(long, default_value = "false")]
     pub pirate: bool,
+
+    /// Debug: fail the run if it leaks file descriptors (scans /proc/self/fd)
+    #[arg(long, hide = true)]
+    pub debug_fd_audit: bool,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
 pub enum CopyMethod {
-    /// Automatically choose the best method
+    /// Automatically choose the best method (`copy_file_range` within a filesystem)
     Auto,
-    /// Use `copy_file_range` for same-filesystem copies
+    /// Use `copy_file_range`, falling back to splice, then read/write where unsupported
     CopyFileRange,
-    /// Use splice for zero-copy operations
+    /// Clone files with `FICLONE` (btrfs, XFS), falling back to read/write
+    Reflink,
+    /// Copy through a pipe with `splice`, in the kernel, falling back to read/write
     Splice,
     /// Use traditional read/write operations
     ReadWrite,
@@ -297,61 +1354,18 @@ impl Args {
     /// - Buffer size is too large (>1GB)
     /// - No CPU cores are available
     /// - Both --quiet and --verbose options are used
+    /// - --rsh or --remote-cmd cannot be parsed
     pub fn validate(&self) -> Result<()> {
-        // Check if source exists
-        if !self.paths.source.exists() {
-            anyhow::bail!(
-                "Source path does not exist: {}",
-                self.paths.source.display()
-            );
-        }
-
-        // Check if source is readable
-        if !self.paths.source.is_dir() && !self.paths.source.is_file() {
-            anyhow::bail!(
-                "Source path must be a file or directory: {}",
-                self.paths.source.display()
-            );
-        }
-
-        // Check queue depth bounds
-        if self.io.queue_depth < 1024 || self.io.queue_depth > 65_536 {
-            anyhow::bail!(
-                "Queue depth must be between 1024 and 65536, got: {}",
-                self.io.queue_depth
-            );
-        }
-
-        // Check max files in flight bounds
-        if self.concurrency.max_files_in_flight < 1 || self.concurrency.max_files_in_flight > 10_000
-        {
-            anyhow::bail!(
-                "Max files in flight must be between 1 and 10000, got: {}",
-                self.concurrency.max_files_in_flight
-            );
-        }
-
-        // Validate buffer size
-        if let Some(kb) = self.io.buffer_size_kb {
-            let kb_value = kb.get();
-            if kb_value > 1024 * 1024 {
-                anyhow::bail!("Buffer size too large (max 1GB): {kb_value} KB");
+        // Settings shared with library users are checked by SyncConfig
+        if let Err(e) = crate::config::SyncConfig::from(self).validate() {
+            match e {
+                crate::error::SyncError::InvalidConfig(message) => anyhow::bail!(message),
+                e => return Err(e.into()),
             }
         }
 
-        // Check CPU count bounds
-        let effective_cpu_count = self.effective_cpu_count();
-        if effective_cpu_count == 0 {
-            anyhow::bail!("No CPU cores available");
-        }
-
-        // Validate conflicting options
-        if self.output.quiet && self.output.verbose > 0 {
-            anyhow::bail!("Cannot use both --quiet and --verbose options");
-        }
-
-        // Validate parallel copy configuration
-        self.io.parallel.validate()?;
+        // Validate remote shell command line
+        self.remote.remote_shell()?;
 
         Ok(())
     }
@@ -367,7 +1381,10 @@ impl Args {
     }
 
     /// Get the actual buffer size in bytes (handles None → default)
+    ///
+    /// Note: the copy path uses the tuning profile's buffer size instead
     #[must_use]
+    #[allow(dead_code)] // Kept for API compatibility
     pub const fn effective_buffer_size(&self) -> usize {
         match self.io.buffer_size_kb {
             None => 64 * 1024, // Default: 64KB
@@ -377,12 +1394,14 @@ impl Args {
 
     /// Check if the source is a directory
     #[must_use]
+    #[allow(dead_code)] // Kept for API compatibility; the sync uses SyncConfig
     pub fn is_directory_copy(&self) -> bool {
         self.paths.source.is_dir()
     }
 
     /// Check if the source is a single file
     #[must_use]
+    #[allow(dead_code)] // Kept for API compatibility; the sync uses SyncConfig
     pub fn is_file_copy(&self) -> bool {
         self.paths.source.is_file()
     }
@@ -503,6 +1522,7 @@ mod tests {
     #![allow(clippy::expect_used)]
     use super::*;
     use crate::error::SyncError;
+    use crate::metadata::{SpecialFilePolicy, UnprivilegedOwnership};
     use compio::fs::File;
     use tempfile::TempDir;
 
@@ -512,21 +1532,30 @@ mod tests {
             paths: PathConfig {
                 source,
                 destination,
+                extra_destinations: Vec::new(),
             },
             io: IoConfig {
                 queue_depth: 4096,
                 buffer_size_kb: NonZeroUsize::new(1024),
                 copy_method: CopyMethod::Auto,
+                tune: None,
+                show_tuning: false,
                 cpu_count: 2,
                 parallel: ParallelCopyConfig {
                     max_depth: 0,
                     min_file_size_mb: 128,
                     chunk_size_mb: 2,
+                    adaptive: false,
                 },
             },
             concurrency: ConcurrencyConfig {
                 max_files_in_flight: 100,
                 no_adaptive_concurrency: false,
+                large_file_threshold_mb: 64,
+                large_files_in_flight: 8,
+                hardlink_spill_dir: None,
+                hardlink_memory_entries: 4_000_000,
+                no_lock: false,
             },
             metadata: MetadataConfig {
                 archive: false,
@@ -536,8 +1565,19 @@ mod tests {
                 times: false,
                 group: false,
                 owner: false,
+                no_owner: false,
+                no_group: false,
                 devices: false,
+                specials: false,
+                devices_and_specials: false,
                 fsync: false,
+                syncfs: false,
+                verify_direct: None,
+                atomic_create: None,
+                partial: false,
+                delay_updates: false,
+                special_files: SpecialFilePolicy::Skip,
+                unprivileged_ownership: UnprivilegedOwnership::Permitted,
                 xattrs: true,
                 acls: false,
                 hard_links: false,
@@ -545,13 +1585,33 @@ mod tests {
                 crtimes: false,
                 preserve_xattr: false,
                 preserve_acl: false,
+                omit_dir_times: false,
+                omit_link_times: false,
+                symlink_rewrite: Vec::new(),
+                sparse: false,
+                preserve_flags: false,
+                update_immutable: false,
+                overlay_layer: false,
+                run: Default::default(),
             },
+            traversal: TraversalConfig::default(),
+            remote: RemoteConfig::default(),
             output: OutputConfig {
                 dry_run: false,
                 progress: false,
+                interactive: false,
                 verbose: 0,
                 quiet: false,
+                json: false,
+                shadow_rsync: false,
+                to_tar: false,
+                from_tar: false,
                 pirate: false,
+                emit_checksums: None,
+                checksum_algorithm: crate::checksum_list::ChecksumAlgorithm::Blake3,
+                metrics_listen: None,
+                metrics_textfile: None,
+                debug_fd_audit: false,
             },
         }
     }
@@ -599,6 +1659,19 @@ mod tests {
         assert!(args.validate().is_err());
     }
 
+    #[compio::test]
+    async fn test_validate_update_immutable_needs_preserve_flags() {
+        // Requirement: Immutable destinations are only rewritten when flags
+        // are preserved, so they are locked again afterwards
+        let (temp_dir, file_path) = create_temp_file().await.unwrap();
+        let mut args = create_test_args(file_path, temp_dir.path().join("dest"));
+        args.metadata.update_immutable = true;
+        let message = args.validate().unwrap_err().to_string();
+        assert!(message.contains("--preserve-flags"), "{message}");
+        args.metadata.preserve_flags = true;
+        assert!(args.validate().is_ok());
+    }
+
     #[test]
     fn test_convenience_accessors() {
         let args = create_test_args(PathBuf::from("/test/src"), PathBuf::from("/test/dst"));
@@ -611,4 +1684,122 @@ mod tests {
         assert_eq!(args.verbose(), 0);
         assert!(!args.quiet());
     }
+
+    #[test]
+    fn test_remote_options_parse() {
+        // Test: --rsh is split into argv and --remote-cmd overrides the remote binary
+        let args = Args::try_parse_from([
+            "arsync",
+            "--rsh=ssh -p 2222 -i '/keys/my key'",
+            "--remote-cmd=/opt/arsync/bin/arsync",
+            "src",
+            "dst",
+        ])
+        .unwrap();
+        let shell = args.remote.remote_shell().unwrap();
+        assert_eq!(shell.program(), "ssh");
+        assert_eq!(shell.options(), ["-p", "2222", "-i", "/keys/my key"]);
+
+        let mut args = create_test_args(PathBuf::from("/test/src"), PathBuf::from("/test/dst"));
+        args.remote.rsh = Some("ssh 'unterminated".to_string());
+        assert!(args.remote.remote_shell().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "remote-sync")]
+    fn test_compress_options() {
+        use crate::protocol::compress::CompressLevel;
+        let level = |options: &[&str]| {
+            let argv = ["arsync"].iter().chain(options).chain(&["src", "dst"]);
+            Args::try_parse_from(argv).map(|args| args.remote.compress_level())
+        };
+        assert_eq!(level(&[]).unwrap(), None);
+        assert_eq!(level(&["-z"]).unwrap(), Some(CompressLevel::DEFAULT));
+        assert_eq!(
+            level(&["--compress-level=19"]).unwrap(),
+            Some(CompressLevel::new(19))
+        );
+        assert_eq!(level(&["-z", "--compress-level=0"]).unwrap(), None);
+        assert!(level(&["--compress-level=23"]).is_err());
+    }
+
+    #[test]
+    fn test_delete_timing() {
+        use crate::directory::DeleteTiming;
+        let timing = |options: &[&str]| {
+            let argv = ["arsync"].iter().chain(options).chain(&["src", "dst"]);
+            Args::try_parse_from(argv).map(|args| args.traversal.delete_timing())
+        };
+        assert_eq!(timing(&[]).unwrap(), None);
+        assert_eq!(timing(&["--delete"]).unwrap(), Some(DeleteTiming::During));
+        assert_eq!(
+            timing(&["--delete", "--delete-before"]).unwrap(),
+            Some(DeleteTiming::Before)
+        );
+        assert_eq!(
+            timing(&["--delete-after"]).unwrap(),
+            Some(DeleteTiming::After)
+        );
+        assert!(timing(&["--delete-before", "--delete-after"]).is_err());
+    }
+
+    #[test]
+    fn test_filter_options_keep_command_line_order() {
+        let args = Args::try_parse_from([
+            "arsync",
+            "--include",
+            "*.rs",
+            "--exclude-from",
+            "list",
+            "-f",
+            "- target/",
+            "--exclude",
+            "*",
+            "src",
+            "dst",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.traversal.filter.rules,
+            [
+                FilterOption::Include("*.rs".to_string()),
+                FilterOption::ExcludeFrom(PathBuf::from("list")),
+                FilterOption::Filter("- target/".to_string()),
+                FilterOption::Exclude("*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_probe_target() {
+        // Requirement: `arsync probe` takes [USER@]HOST[:PATH]
+        let split = |target: &str| {
+            let args = ProbeArgs::try_parse_from(["arsync probe", target]).unwrap();
+            let (user, host, path) = args.split_target();
+            (user.to_string(), host.to_string(), path.to_string())
+        };
+        let owned = |user: &str, host: &str, path: &str| {
+            (user.to_string(), host.to_string(), path.to_string())
+        };
+        assert_eq!(split("backup"), owned("", "backup", ""));
+        assert_eq!(split("alice@backup"), owned("alice", "backup", ""));
+        assert_eq!(
+            split("alice@backup:/srv/data"),
+            owned("alice", "backup", "/srv/data")
+        );
+        assert_eq!(split("backup:rel/dir"), owned("", "backup", "rel/dir"));
+    }
+
+    #[test]
+    fn test_update_check() {
+        use crate::directory::UpdateCheck;
+        let check = |options: &[&str]| {
+            let argv = ["arsync"].iter().chain(options).chain(&["src", "dst"]);
+            Args::try_parse_from(argv).map(|args| args.traversal.update_check())
+        };
+        assert_eq!(check(&[]).unwrap(), Some(UpdateCheck::SizeAndMtime));
+        assert_eq!(check(&["-c"]).unwrap(), Some(UpdateCheck::Checksum));
+        assert_eq!(check(&["--ignore-times"]).unwrap(), None);
+        assert!(check(&["--checksum", "-I"]).is_err());
+    }
 }
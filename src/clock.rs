@@ -0,0 +1,151 @@
+//! Wall-clock time source, replaceable in tests
+//!
+//! Code that compares timestamps against "now" (lock ages, quota days, cache
+//! staleness) takes a `&dyn Clock` instead of calling `SystemTime::now()`, so
+//! tests can pin the time with a `MockClock` and cover edge cases such as
+//! pre-epoch clocks, a clock stepped backwards, or the last second of a day,
+//! without sleeping or depending on when they run.
+//!
+//! `SystemTime` is UTC-based, so local-time effects like DST transitions never
+//! show up in it; leap seconds are not counted either (a day is always 86 400
+//! seconds, as in Unix time).
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current wall-clock time
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// The current time
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's real-time clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+#[allow(dead_code)] // Test double; the CLI always uses `SystemClock`
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+#[allow(dead_code)] // Test double; the CLI always uses `SystemClock`
+impl MockClock {
+    /// Clock stopped at `time`
+    #[must_use]
+    pub const fn at(time: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(time),
+        }
+    }
+
+    /// Move the clock to `time`, possibly backwards
+    pub fn set(&self, time: SystemTime) {
+        *self.lock() = time;
+    }
+
+    /// Move the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        *self.lock() += by;
+    }
+
+    /// Move the clock backward by `by`, like an NTP step correction
+    pub fn rewind(&self, by: Duration) {
+        *self.lock() -= by;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SystemTime> {
+        self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.lock()
+    }
+}
+
+/// Nanoseconds from the Unix epoch to `time`, negative before it
+#[must_use]
+pub fn unix_nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => i128::try_from(after.as_nanos()).unwrap_or(i128::MAX),
+        Err(before) => i128::try_from(before.duration().as_nanos()).map_or(i128::MIN, |n| -n),
+    }
+}
+
+/// Time `nanos` nanoseconds from the Unix epoch (negative for before it)
+///
+/// Returns `None` if `SystemTime` cannot represent it.
+#[must_use]
+#[allow(dead_code)] // Counterpart of `unix_nanos`; only tests need it so far
+pub fn from_unix_nanos(nanos: i128) -> Option<SystemTime> {
+    let magnitude = nanos.unsigned_abs();
+    let secs = u64::try_from(magnitude / 1_000_000_000).ok()?;
+    #[allow(clippy::cast_possible_truncation)] // Remainder is below 10^9
+    let offset = Duration::new(secs, (magnitude % 1_000_000_000) as u32);
+    if nanos < 0 {
+        UNIX_EPOCH.checked_sub(offset)
+    } else {
+        UNIX_EPOCH.checked_add(offset)
+    }
+}
+
+/// Whole seconds from the Unix epoch to `time`, rounded down (so
+/// 0.5 seconds before the epoch is -1)
+#[must_use]
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    let secs = unix_nanos(time).div_euclid(1_000_000_000);
+    i64::try_from(secs).unwrap_or(if secs < 0 { i64::MIN } else { i64::MAX })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_told() {
+        // Requirement: A mock clock returns a fixed time, and can be moved
+        // forwards and backwards
+        let clock = MockClock::at(from_unix_nanos(1_700_000_000_000_000_000).unwrap());
+        assert_eq!(clock.now(), clock.now());
+        assert_eq!(unix_seconds(clock.now()), 1_700_000_000);
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(unix_nanos(clock.now()), 1_700_000_001_500_000_000);
+        clock.rewind(Duration::from_secs(10));
+        assert_eq!(unix_seconds(clock.now()), 1_699_999_991);
+        clock.set(UNIX_EPOCH);
+        assert_eq!(unix_nanos(clock.now()), 0);
+    }
+
+    #[test]
+    fn test_pre_epoch_times() {
+        // Requirement: Times before 1970 convert to negative offsets and
+        // round down to whole seconds, as in `struct timespec`
+        let clock = MockClock::at(from_unix_nanos(-1_500_000_000).unwrap());
+        assert_eq!(clock.now(), UNIX_EPOCH - Duration::from_millis(1500));
+        assert_eq!(unix_nanos(clock.now()), -1_500_000_000);
+        assert_eq!(unix_seconds(clock.now()), -2);
+
+        // 1969-12-31T23:59:59.999999999
+        assert_eq!(unix_seconds(from_unix_nanos(-1).unwrap()), -1);
+        // 1900-01-01T00:00:00, a common "unknown date" sentinel
+        let sentinel = from_unix_nanos(-2_208_988_800 * 1_000_000_000).unwrap();
+        assert_eq!(unix_seconds(sentinel), -2_208_988_800);
+
+        for nanos in [i128::from(i64::MIN), -1, 0, 1, i128::from(i64::MAX)] {
+            assert_eq!(unix_nanos(from_unix_nanos(nanos).unwrap()), nanos);
+        }
+        assert_eq!(from_unix_nanos(i128::MAX), None);
+    }
+}
@@ -0,0 +1,562 @@
+//! Typed sync configuration shared by the CLI and library users
+//!
+//! [`Args`] mirrors the command line: besides the sync's settings it holds
+//! remote shell options and actions such as `--show-tuning`. [`SyncConfig`] is
+//! what a sync actually runs on: the source and destination plus the
+//! copy-semantics groups (metadata, I/O, concurrency, traversal, output). The
+//! CLI builds one from `Args`; library users and frontends build one with
+//! [`SyncConfig::new`] and adjust its fields. [`SyncConfig::validate`] checks
+//! it the same way for all of them.
+//!
+//! A config can be saved and loaded with [`SyncConfig::to_config_file`] and
+//! [`SyncConfig::from_config_file`]. The format is one `option = value` line
+//! per setting, keyed by the long command-line option (`archive = true`,
+//! `buffer-size-kb = 128`, `source = /data`), with `#` comments. Loading feeds
+//! the options through the command-line parser, so a config file accepts
+//! exactly what the command line does and round-trips to an identical config.
+//! Each fan-out destination is another `destination = ...` line, and filter
+//! rules are written in the order they are checked.
+//! Output options are not saved: they describe a run, not a copy.
+
+use crate::cli::{
+    Args, ConcurrencyConfig, FilterOption, IoConfig, MetadataConfig, OutputConfig, TraversalConfig,
+};
+use crate::error::{Result, SyncError};
+use crate::priority::JobShare;
+use clap::{Parser, ValueEnum};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Everything a sync needs to know, independent of how it was specified
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Source directory or file
+    pub source: PathBuf,
+    /// Destination directory or file
+    pub destination: PathBuf,
+    /// Further destinations of a fan-out (see `fanout`)
+    pub extra_destinations: Vec<PathBuf>,
+    /// I/O sizes, tuning and parallel copy
+    pub io: IoConfig,
+    /// Files in flight, hardlink tracking and destination locking
+    pub concurrency: ConcurrencyConfig,
+    /// What metadata is preserved and how files are created
+    pub metadata: MetadataConfig,
+    /// Directory traversal behavior
+    pub traversal: TraversalConfig,
+    /// Progress, prompting and diagnostics
+    pub output: OutputConfig,
+    /// Share of a budget of files in flight this sync competes for with
+    /// others in the same process (set by `arsync serve`)
+    pub priority: Option<Arc<JobShare>>,
+}
+
+impl SyncConfig {
+    /// Config copying `source` to `destination` with every option at its default
+    #[must_use]
+    #[allow(dead_code)] // Library API
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        let (source, destination) = (source.into(), destination.into());
+        // The defaults live in the option definitions; parse an empty command
+        // line to get them
+        let args = Args::parse_from([
+            OsString::from("arsync"),
+            OsString::from("--"),
+            OsString::from("."),
+            OsString::from("."),
+        ]);
+        Self {
+            source,
+            destination,
+            ..Self::from(args)
+        }
+    }
+
+    /// Validate the configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` if:
+    /// - The source does not exist or is not a file or directory
+    /// - Queue depth is outside 1024-65536
+    /// - Max files in flight (or large files in flight) is outside 1-10000
+    /// - Buffer size is too large (>1GB)
+    /// - No CPU cores are available
+    /// - Both quiet and verbose output are requested
+    /// - `--delete` is combined with several destinations
+    /// - A destination is inside the source without `--allow-overlap` and an
+    ///   exclude covering it
+    /// - The parallel copy settings are inconsistent
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(SyncError::InvalidConfig(message));
+
+        if self.output.from_tar {
+            // The source is an archive (or standard input)
+            if self.source.as_os_str() != crate::tar_import::STDIN && !self.source.is_file() {
+                return invalid(format!(
+                    "Archive does not exist or is not a file: {}",
+                    self.source.display()
+                ));
+            }
+        } else if !self.source.exists() {
+            return invalid(format!(
+                "Source path does not exist: {}",
+                self.source.display()
+            ));
+        } else if !self.source.is_dir() && !self.source.is_file() {
+            return invalid(format!(
+                "Source path must be a file or directory: {}",
+                self.source.display()
+            ));
+        }
+        if self.io.queue_depth < 1024 || self.io.queue_depth > 65_536 {
+            return invalid(format!(
+                "Queue depth must be between 1024 and 65536, got: {}",
+                self.io.queue_depth
+            ));
+        }
+        if self.concurrency.max_files_in_flight < 1 || self.concurrency.max_files_in_flight > 10_000
+        {
+            return invalid(format!(
+                "Max files in flight must be between 1 and 10000, got: {}",
+                self.concurrency.max_files_in_flight
+            ));
+        }
+        if self.concurrency.large_file_threshold_mb > 0
+            && !(1..=10_000).contains(&self.concurrency.large_files_in_flight)
+        {
+            return invalid(format!(
+                "Large files in flight must be between 1 and 10000, got: {}",
+                self.concurrency.large_files_in_flight
+            ));
+        }
+        if let Some(kb) = self.io.buffer_size_kb {
+            let kb_value = kb.get();
+            if kb_value > 1024 * 1024 {
+                return invalid(format!("Buffer size too large (max 1GB): {kb_value} KB"));
+            }
+        }
+        if self.effective_cpu_count() == 0 {
+            return invalid("No CPU cores available".to_string());
+        }
+        if self.output.quiet && self.output.verbose > 0 {
+            return invalid("Cannot use both --quiet and --verbose options".to_string());
+        }
+        if self.metadata.update_immutable && !self.metadata.preserve_flags {
+            return invalid("--update-immutable requires --preserve-flags".to_string());
+        }
+        if !self.extra_destinations.is_empty() && self.traversal.delete_timing().is_some() {
+            return invalid("--delete cannot be used with several destinations".to_string());
+        }
+        if self.traversal.encrypts()
+            && (self.output.to_tar
+                || self.output.from_tar
+                || !self.extra_destinations.is_empty()
+                || !self.source.is_dir())
+        {
+            return invalid(
+                "--encrypt-to only applies to copying a directory to one destination".to_string(),
+            );
+        }
+        if !self.extra_destinations.is_empty() && self.output.dry_run {
+            return invalid("--dry-run cannot be used with several destinations".to_string());
+        }
+        let rules = crate::filter::FilterRules::from_config(&self.traversal.filter)?;
+        if !self.output.from_tar && !self.output.to_tar && self.source.is_dir() {
+            for destination in self.destinations() {
+                crate::overlap::check(
+                    &self.source,
+                    destination,
+                    self.traversal.allow_overlap,
+                    &rules,
+                    self.traversal.delete_timing().is_some(),
+                )?;
+            }
+        }
+        self.io
+            .parallel
+            .validate()
+            .map_err(|e| SyncError::InvalidConfig(e.to_string()))
+    }
+
+    /// Get the actual CPU count to use
+    #[must_use]
+    pub fn effective_cpu_count(&self) -> usize {
+        if self.io.cpu_count == 0 {
+            num_cpus::get()
+        } else {
+            self.io.cpu_count
+        }
+    }
+
+    /// The destination followed by any further fan-out destinations
+    pub fn destinations(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.destination).chain(&self.extra_destinations)
+    }
+
+    /// Check if the source is a directory
+    #[must_use]
+    pub fn is_directory_copy(&self) -> bool {
+        self.source.is_dir()
+    }
+
+    /// Check if the source is a single file
+    #[must_use]
+    pub fn is_file_copy(&self) -> bool {
+        self.source.is_file()
+    }
+
+    /// Serialize the paths and copy-semantics options as a config file
+    #[must_use]
+    #[allow(dead_code)] // Library API
+    pub fn to_config_file(&self) -> Vec<u8> {
+        let mut out = ConfigWriter::default();
+        out.comment("arsync sync configuration");
+        out.path("source", &self.source);
+        for destination in self.destinations() {
+            out.path("destination", destination);
+        }
+
+        let io = &self.io;
+        out.value("queue-depth", io.queue_depth);
+        out.optional("buffer-size-kb", io.buffer_size_kb);
+        out.optional_choice("tune", io.tune.as_ref());
+        out.choice("copy-method", &io.copy_method);
+        out.value("cpu-count", io.cpu_count);
+        out.value("parallel-max-depth", io.parallel.max_depth);
+        out.value("parallel-min-size-mb", io.parallel.min_file_size_mb);
+        out.value("parallel-chunk-size-mb", io.parallel.chunk_size_mb);
+        out.value("parallel-adaptive", io.parallel.adaptive);
+
+        let concurrency = &self.concurrency;
+        out.value("max-files-in-flight", concurrency.max_files_in_flight);
+        out.value(
+            "no-adaptive-concurrency",
+            concurrency.no_adaptive_concurrency,
+        );
+        out.value(
+            "large-file-threshold-mb",
+            concurrency.large_file_threshold_mb,
+        );
+        out.value("large-files-in-flight", concurrency.large_files_in_flight);
+        out.optional_path(
+            "hardlink-spill-dir",
+            concurrency.hardlink_spill_dir.as_deref(),
+        );
+        out.value(
+            "hardlink-memory-entries",
+            concurrency.hardlink_memory_entries,
+        );
+        out.value("no-lock", concurrency.no_lock);
+
+        let metadata = &self.metadata;
+        out.value("archive", metadata.archive);
+        out.value("recursive", metadata.recursive);
+        out.value("links", metadata.links);
+        out.value("perms", metadata.perms);
+        out.value("times", metadata.times);
+        out.value("group", metadata.group);
+        out.value("owner", metadata.owner);
+        out.value("no-owner", metadata.no_owner);
+        out.value("no-group", metadata.no_group);
+        // -D has no long name; it is saved as the two options it stands for
+        out.value("devices", metadata.devices || metadata.devices_and_specials);
+        out.value(
+            "specials",
+            metadata.specials || metadata.devices_and_specials,
+        );
+        out.value("fsync", metadata.fsync);
+        out.value("syncfs", metadata.syncfs);
+        out.value("sparse", metadata.sparse);
+        out.optional("verify-direct", metadata.verify_direct);
+        out.optional_choice("atomic-create", metadata.atomic_create.as_ref());
+        out.value("partial", metadata.partial);
+        out.value("delay-updates", metadata.delay_updates);
+        out.choice("special-files", &metadata.special_files);
+        out.choice("unprivileged-ownership", &metadata.unprivileged_ownership);
+        out.value("xattrs", metadata.xattrs);
+        out.value("acls", metadata.acls);
+        out.value("hard-links", metadata.hard_links);
+        out.value("atimes", metadata.atimes);
+        out.value("crtimes", metadata.crtimes);
+        out.value("omit-dir-times", metadata.omit_dir_times);
+        out.value("omit-link-times", metadata.omit_link_times);
+        for rule in &metadata.symlink_rewrite {
+            out.path("symlink-rewrite", Path::new(&rule.to_string()));
+        }
+        out.value("preserve-flags", metadata.preserve_flags);
+        out.value("update-immutable", metadata.update_immutable);
+        out.value("overlay-layer", metadata.overlay_layer);
+        out.value("preserve-xattr", metadata.preserve_xattr);
+        out.value("preserve-acl", metadata.preserve_acl);
+
+        let traversal = &self.traversal;
+        out.value("follow-bind-mounts", traversal.follow_bind_mounts);
+        out.value("allow-overlap", traversal.allow_overlap);
+        out.optional_choice(
+            "truncate-long-names",
+            traversal.truncate_long_names.as_ref(),
+        );
+        out.optional("preread", traversal.preread);
+        out.value("preread-budget-mb", traversal.preread_budget_mb);
+        out.optional_path("state-journal", traversal.state_journal.as_deref());
+        out.value("checkpoint-secs", traversal.checkpoint_secs);
+        out.value("checkpoint-files", traversal.checkpoint_files);
+        out.value("delete", traversal.delete);
+        out.value("delete-before", traversal.delete_before);
+        out.value("delete-during", traversal.delete_during);
+        out.value("delete-after", traversal.delete_after);
+        for dir in &traversal.link_dest {
+            out.path("link-dest", dir);
+        }
+        for rule in &traversal.transform {
+            out.path("transform", Path::new(&rule.to_string()));
+        }
+        for recipient in &traversal.encrypt_to {
+            out.value("encrypt-to", recipient);
+        }
+        out.optional_path("encrypt-identity", traversal.encrypt_identity.as_deref());
+        out.value("encrypt-names", traversal.encrypt_names);
+        out.value("checksum", traversal.checksum);
+        out.value("ignore-times", traversal.ignore_times);
+        out.value("ignore-errors", traversal.ignore_errors);
+        out.optional("max-errors", traversal.max_errors);
+        // Patterns are escaped like paths; the order of the rules matters
+        for rule in &traversal.filter.rules {
+            match rule {
+                FilterOption::Exclude(pattern) => out.path("exclude", Path::new(pattern)),
+                FilterOption::Include(pattern) => out.path("include", Path::new(pattern)),
+                FilterOption::ExcludeFrom(path) => out.path("exclude-from", path),
+                FilterOption::Filter(rule) => out.path("filter", Path::new(rule)),
+            }
+        }
+
+        out.0
+    }
+
+    /// Parse a config file written by `to_config_file` (or by hand)
+    ///
+    /// Options that are missing take their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` if a line is malformed, the source or
+    /// destination is missing, or an option or value is not accepted by the
+    /// command line.
+    #[allow(dead_code)] // Library API
+    pub fn from_config_file(contents: &[u8]) -> Result<Self> {
+        let mut argv = vec![OsString::from("arsync")];
+        let (mut source, mut destinations) = (None, Vec::new());
+        for (number, line) in contents.split(|&b| b == b'\n').enumerate() {
+            // Values are taken verbatim up to the end of the line, so paths
+            // may end in spaces; only a CRLF line ending is stripped
+            let line = line.trim_ascii_start();
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            let malformed = || {
+                SyncError::InvalidConfig(format!(
+                    "Config line {}: expected `option = value`, got: {}",
+                    number + 1,
+                    String::from_utf8_lossy(line)
+                ))
+            };
+            let split = line.iter().position(|&b| b == b'=').ok_or_else(malformed)?;
+            let key = line[..split].trim_ascii();
+            let value = crate::bisync::unescape_path(line[split + 1..].trim_ascii_start())
+                .ok_or_else(malformed)?;
+            if key.is_empty() || key.starts_with(b"-") {
+                return Err(malformed());
+            }
+            match key {
+                b"source" => source = Some(PathBuf::from(value)),
+                b"destination" => destinations.push(PathBuf::from(value)),
+                _ if value == "false" => {}
+                _ => {
+                    let mut option = b"--".to_vec();
+                    option.extend_from_slice(key);
+                    if value != "true" {
+                        option.push(b'=');
+                        option.extend_from_slice(value.as_bytes());
+                    }
+                    argv.push(OsString::from_vec(option));
+                }
+            }
+        }
+        let missing =
+            |key: &str| SyncError::InvalidConfig(format!("Config has no `{key} = ...` line"));
+        argv.push(OsString::from("--"));
+        argv.push(source.ok_or_else(|| missing("source"))?.into_os_string());
+        if destinations.is_empty() {
+            return Err(missing("destination"));
+        }
+        argv.extend(destinations.into_iter().map(PathBuf::into_os_string));
+
+        let args = Args::try_parse_from(argv).map_err(|e| {
+            SyncError::InvalidConfig(format!("Config: {}", e.render().to_string().trim()))
+        })?;
+        Ok(Self::from(args))
+    }
+}
+
+impl From<Args> for SyncConfig {
+    fn from(args: Args) -> Self {
+        Self {
+            source: args.paths.source,
+            destination: args.paths.destination,
+            extra_destinations: args.paths.extra_destinations,
+            io: args.io,
+            concurrency: args.concurrency,
+            metadata: args.metadata,
+            traversal: args.traversal,
+            output: args.output,
+            priority: None,
+        }
+    }
+}
+
+impl From<&Args> for SyncConfig {
+    fn from(args: &Args) -> Self {
+        Self::from(args.clone())
+    }
+}
+
+/// Builds the `option = value` lines of a config file
+#[derive(Default)]
+struct ConfigWriter(Vec<u8>);
+
+impl ConfigWriter {
+    fn comment(&mut self, text: &str) {
+        self.line(b"#", text.as_bytes());
+    }
+
+    fn value(&mut self, key: &str, value: impl Display) {
+        self.line(key.as_bytes(), value.to_string().as_bytes());
+    }
+
+    fn optional(&mut self, key: &str, value: Option<impl Display>) {
+        if let Some(value) = value {
+            self.value(key, value);
+        }
+    }
+
+    fn choice(&mut self, key: &str, value: &impl ValueEnum) {
+        if let Some(name) = value.to_possible_value() {
+            self.value(key, name.get_name());
+        }
+    }
+
+    fn optional_choice(&mut self, key: &str, value: Option<&impl ValueEnum>) {
+        if let Some(value) = value {
+            self.choice(key, value);
+        }
+    }
+
+    fn path(&mut self, key: &str, path: &Path) {
+        let mut escaped = Vec::new();
+        crate::bisync::escape_path(path.as_os_str(), &mut escaped);
+        self.line(key.as_bytes(), &escaped);
+    }
+
+    fn optional_path(&mut self, key: &str, path: Option<&Path>) {
+        if let Some(path) = path {
+            self.path(key, path);
+        }
+    }
+
+    fn line(&mut self, key: &[u8], value: &[u8]) {
+        self.0.extend_from_slice(key);
+        self.0
+            .extend_from_slice(if key == b"#" { b" " } else { b" = " });
+        self.0.extend_from_slice(value);
+        self.0.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::metadata::{SpecialFilePolicy, UnprivilegedOwnership};
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_config_file_round_trip() {
+        // Requirement: A saved config loads back identical, paths included
+        let mut config = SyncConfig::new("/data/src dir", "/backup/tab\there");
+        config.metadata.archive = true;
+        config.metadata.verify_direct = Some(10);
+        config.metadata.special_files = SpecialFilePolicy::Placeholder;
+        config.metadata.no_group = true;
+        config.metadata.unprivileged_ownership = UnprivilegedOwnership::Fail;
+        config.metadata.preserve_flags = true;
+        config.metadata.update_immutable = true;
+        config.metadata.delay_updates = true;
+        config.io.buffer_size_kb = NonZeroUsize::new(256);
+        config.io.tune = Some(crate::tuning::TuneProfile::Fuse);
+        config.io.parallel.adaptive = true;
+        config.traversal.state_journal = Some(PathBuf::from("/var/lib/arsync/state\njournal"));
+        config.traversal.allow_overlap = true;
+        config.traversal.link_dest = vec![PathBuf::from("../daily.1"), PathBuf::from("/snap/x")];
+        config.traversal.transform =
+            vec![crate::transform::TransformRule::parse("*.bat:unix2dos | tr a: b").unwrap()];
+        config.traversal.filter.rules = vec![
+            FilterOption::Include("*.rs".to_string()),
+            FilterOption::Exclude("odd\nname".to_string()),
+            FilterOption::Filter("- target/".to_string()),
+        ];
+        config.traversal.ignore_errors = true;
+        config.traversal.max_errors = std::num::NonZeroU64::new(100);
+        config.concurrency.max_files_in_flight = 64;
+        config.extra_destinations =
+            vec![PathBuf::from("/mirror/one"), PathBuf::from("/mirror/two")];
+
+        let file = config.to_config_file();
+        let loaded = SyncConfig::from_config_file(&file).unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{config:?}"));
+        assert!(String::from_utf8_lossy(&file).contains("\narchive = true\n"));
+
+        config.metadata.devices_and_specials = true;
+        let loaded = SyncConfig::from_config_file(&config.to_config_file()).unwrap();
+        assert!(loaded.metadata.devices && loaded.metadata.specials);
+
+        // Encryption excludes --link-dest and --transform
+        config.traversal.link_dest.clear();
+        config.traversal.transform.clear();
+        config.traversal.encrypt_to =
+            vec!["age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p".to_string()];
+        config.traversal.encrypt_identity = Some(PathBuf::from("/etc/arsync/backup key.txt"));
+        config.traversal.encrypt_names = true;
+        let loaded = SyncConfig::from_config_file(&config.to_config_file()).unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{config:?}"));
+    }
+
+    #[test]
+    fn test_config_file_errors() {
+        let defaults = SyncConfig::from_config_file(b"source = /a\ndestination = /b\n").unwrap();
+        assert_eq!(defaults.io.queue_depth, 4096);
+
+        for (contents, expected) in [
+            (&b"destination = /b\n"[..], "no `source"),
+            (b"source = /a\ndestination = /b\narchive\n", "line 3"),
+            (
+                b"source = /a\ndestination = /b\nno-such-option = 1\n",
+                "no-such-option",
+            ),
+            (
+                b"source = /a\ndestination = /b\nqueue-depth = lots\n",
+                "lots",
+            ),
+        ] {
+            let message = SyncConfig::from_config_file(contents)
+                .unwrap_err()
+                .to_string();
+            assert!(message.contains(expected), "{message}");
+        }
+    }
+}
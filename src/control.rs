@@ -0,0 +1,979 @@
+//! Control service for orchestration tools (`arsync serve`)
+//!
+//! `arsync serve --control-socket PATH` keeps running and accepts sync jobs
+//! on a Unix socket. Clients speak JSON-RPC 2.0, one message per line:
+//!
+//! ```text
+//! → {"jsonrpc":"2.0","id":1,"method":"submit","params":{"args":["-a","/data/","/backup/data"]}}
+//! ← {"jsonrpc":"2.0","id":1,"result":{"job":1}}
+//! → {"jsonrpc":"2.0","id":2,"method":"status","params":{"job":1}}
+//! ← {"jsonrpc":"2.0","id":2,"result":{"job":1,"state":"running","files_copied":120,...}}
+//! ```
+//!
+//! | Method   | Params                                            | Result                          |
+//! |----------|---------------------------------------------------|---------------------------------|
+//! | `submit` | `{"args": [...], "priority": P, "paths": {...}}`  | `{"job": ID}`                   |
+//! | `status` | `{"job": ID}`                                     | the job                         |
+//! | `cancel` | `{"job": ID}`                                     | `{"job": ID, "cancelled": BOOL}`|
+//! | `list`   | none                                              | `{"jobs": [...]}`               |
+//!
+//! A job's `args` are an arsync command line without the program name; they
+//! are parsed and validated like one, so a bad option fails the `submit`.
+//! A job is `queued`, `running`, `completed`, `failed` (with an `error`) or
+//! `cancelled`, and reports the entries discovered and the files and bytes
+//! copied so far.
+//!
+//! `priority` is the job's class: `interactive`, `normal` (the default) or
+//! `background`. `paths` optionally maps source subtrees to other classes
+//! (`{"/data/db": "interactive"}`; relative paths are taken within the
+//! source), the deepest matching path winning.
+//!
+//! Jobs run through [`crate::sync::sync`] on the service's runtime. Queued
+//! jobs start most urgent class first, then in submission order. At most
+//! `--max-jobs` jobs of a class or a more urgent one run at once, so an
+//! interactive job does not wait for background jobs to finish. Running jobs
+//! share one budget of files in flight (`--max-files-in-flight`), weighted
+//! by class (see [`crate::priority`]): a background job keeps running next to
+//! an interactive one but starts files only within its small share. Each job
+//! tunes for its own destination and has its own buffer pools, warnings and
+//! metrics (see [`crate::run_state`]). Progress comes from the [`EVENTS`]
+//! bus, where every run tags its events with its job: jobs with the same or
+//! nested sources are told apart. Cancelling a running job stops it at its
+//! next I/O; its temporary files are left for `arsync cleanup`.
+
+use crate::cli::{Args, ServeArgs};
+use crate::config::SyncConfig;
+use crate::events::{FileId, SyncEvent, EVENTS};
+use crate::priority::{JobShare, Priority, PriorityBudget, PriorityRules};
+use crate::run_state::RunState;
+use crate::sync::SyncStats;
+use anyhow::{Context, Result};
+use clap::Parser;
+use compio::buf::BufResult;
+use compio::io::{AsyncRead, AsyncWriteExt};
+use compio::net::{UnixListener, UnixStream};
+use futures::FutureExt;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::OsString;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Events queued for the progress tracker before they are dropped
+const EVENT_QUEUE_CAPACITY: usize = 65_536;
+
+/// Longest request line accepted
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// JSON-RPC error codes
+mod code {
+    /// The request is not valid JSON
+    pub const PARSE_ERROR: i64 = -32_700;
+    /// The request is not a JSON-RPC request
+    pub const INVALID_REQUEST: i64 = -32_600;
+    /// No such method
+    pub const METHOD_NOT_FOUND: i64 = -32_601;
+    /// The params are missing or wrong
+    pub const INVALID_PARAMS: i64 = -32_602;
+    /// No job has the given id
+    pub const UNKNOWN_JOB: i64 = -32_001;
+}
+
+/// Error answer to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    /// JSON-RPC error code
+    pub code: i64,
+    /// Description for the client
+    pub message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: code::INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+}
+
+// ============================================================================
+// Jobs
+// ============================================================================
+
+/// Where a job is in its life
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    /// Waiting for a job slot
+    Queued,
+    /// Syncing
+    Running,
+    /// Finished successfully
+    Completed,
+    /// Finished with an error
+    Failed(String),
+    /// Cancelled before it finished
+    Cancelled,
+}
+
+impl JobState {
+    /// Name used on the wire
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Failed(_) => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// What a job has done so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JobProgress {
+    /// Files the traversal found
+    pub files_discovered: u64,
+    /// Size of the files found
+    pub bytes_discovered: u64,
+    /// Files copied completely
+    pub files_copied: u64,
+    /// Bytes written
+    pub bytes_copied: u64,
+    /// Entries that failed
+    pub errors: u64,
+}
+
+/// A submitted job
+struct Job {
+    config: SyncConfig,
+    rules: PriorityRules,
+    state: JobState,
+    progress: JobProgress,
+    submitted: Instant,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    /// The running sync; dropping it cancels the job
+    task: Option<compio::runtime::JoinHandle<()>>,
+}
+
+impl Job {
+    /// The job as `status` and `list` return it
+    fn to_json(&self, id: u64) -> Value {
+        let elapsed = self
+            .started
+            .map(|started| self.finished.unwrap_or_else(Instant::now) - started);
+        let mut job = json!({
+            "job": id,
+            "state": self.state.name(),
+            "priority": self.rules.class.name(),
+            "source": self.config.source.display().to_string(),
+            "destination": self.config.destination.display().to_string(),
+            "files_discovered": self.progress.files_discovered,
+            "bytes_discovered": self.progress.bytes_discovered,
+            "files_copied": self.progress.files_copied,
+            "bytes_copied": self.progress.bytes_copied,
+            "errors": self.progress.errors,
+            "queued_ms": millis(self.started.unwrap_or_else(Instant::now) - self.submitted),
+            "elapsed_ms": elapsed.map(millis),
+        });
+        if let JobState::Failed(message) = &self.state {
+            job["error"] = Value::from(message.as_str());
+        }
+        job
+    }
+}
+
+fn millis(duration: std::time::Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// The job table and its queue
+#[derive(Default)]
+struct Scheduler {
+    jobs: BTreeMap<u64, Job>,
+    /// Queued jobs, oldest first
+    queue: VecDeque<u64>,
+    max_jobs: usize,
+    next_id: u64,
+    /// Which job each file copy in progress belongs to
+    files: HashMap<FileId, u64>,
+}
+
+impl Scheduler {
+    fn new(max_jobs: usize) -> Self {
+        Self {
+            max_jobs: max_jobs.max(1),
+            next_id: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Queue a job; returns its id
+    fn submit(&mut self, config: SyncConfig, rules: PriorityRules) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.insert(
+            id,
+            Job {
+                config,
+                rules,
+                state: JobState::Queued,
+                progress: JobProgress::default(),
+                submitted: Instant::now(),
+                started: None,
+                finished: None,
+                task: None,
+            },
+        );
+        self.queue.push_back(id);
+        id
+    }
+
+    /// Mark the next queued job running if a slot is free
+    ///
+    /// The next job is the oldest of the most urgent class. It needs a slot
+    /// among the jobs of its class or a more urgent one: jobs of a lower
+    /// class keep running next to it with a smaller share of the budget.
+    fn start_next(&mut self) -> Option<(u64, SyncConfig, PriorityRules)> {
+        let (index, class) = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter_map(|(index, id)| Some((index, self.jobs.get(id)?.rules.class)))
+            .max_by_key(|&(index, class)| (class, std::cmp::Reverse(index)))?;
+        let running = self
+            .jobs
+            .values()
+            .filter(|job| job.state == JobState::Running && job.rules.class >= class)
+            .count();
+        if running >= self.max_jobs {
+            return None;
+        }
+        let id = self.queue.remove(index)?;
+        let job = self.jobs.get_mut(&id)?;
+        job.state = JobState::Running;
+        job.started = Some(Instant::now());
+        Some((id, job.config.clone(), job.rules.clone()))
+    }
+
+    /// Record how a running job ended
+    fn finish(&mut self, id: u64, result: std::result::Result<SyncStats, String>) {
+        let Some(job) = self.jobs.get_mut(&id) else {
+            return;
+        };
+        if job.state != JobState::Running {
+            return;
+        }
+        if let Some(task) = job.task.take() {
+            task.detach();
+        }
+        job.state = match result {
+            Ok(stats) => {
+                job.progress.files_copied = job.progress.files_copied.max(stats.files_copied);
+                job.progress.bytes_copied = job.progress.bytes_copied.max(stats.bytes_copied);
+                JobState::Completed
+            }
+            Err(message) => JobState::Failed(message),
+        };
+        job.finished = Some(Instant::now());
+        self.files.retain(|_, job| *job != id);
+    }
+
+    /// Cancel a queued or running job
+    ///
+    /// Returns whether it was cancelled (`false` if it had already ended)
+    /// and the task to drop, which stops a running job.
+    fn cancel(
+        &mut self,
+        id: u64,
+    ) -> std::result::Result<(bool, Option<compio::runtime::JoinHandle<()>>), RpcError> {
+        let job = self.jobs.get_mut(&id).ok_or_else(|| unknown_job(id))?;
+        let task = match job.state {
+            JobState::Queued => {
+                self.queue.retain(|&queued| queued != id);
+                None
+            }
+            JobState::Running => job.task.take(),
+            _ => return Ok((false, None)),
+        };
+        job.state = JobState::Cancelled;
+        job.finished = Some(Instant::now());
+        self.files.retain(|_, job| *job != id);
+        Ok((true, task))
+    }
+
+    /// `job` if it is running
+    fn running(&self, job: u64) -> Option<u64> {
+        self.jobs
+            .get(&job)
+            .filter(|job| job.state == JobState::Running)
+            .map(|_| job)
+    }
+
+    /// Count an event towards the job it belongs to
+    fn apply(&mut self, event: &SyncEvent) {
+        let id = match event {
+            SyncEvent::EntryDiscovered { job, .. } | SyncEvent::Error { job, .. } => {
+                self.running(*job)
+            }
+            SyncEvent::FileStarted { job, id, .. } => {
+                if let Some(job) = self.running(*job) {
+                    self.files.insert(*id, job);
+                }
+                return;
+            }
+            SyncEvent::ChunkCopied { id, .. } => self.files.get(id).copied(),
+            SyncEvent::FileDone { id, .. } | SyncEvent::FileFailed { id } => self.files.remove(id),
+        };
+        let Some(progress) = id
+            .and_then(|id| self.jobs.get_mut(&id))
+            .map(|job| &mut job.progress)
+        else {
+            return;
+        };
+        match *event {
+            SyncEvent::EntryDiscovered {
+                size,
+                is_dir: false,
+                ..
+            } => {
+                progress.files_discovered += 1;
+                progress.bytes_discovered += size;
+            }
+            SyncEvent::ChunkCopied { bytes, .. } => progress.bytes_copied += bytes,
+            SyncEvent::FileDone { .. } => progress.files_copied += 1,
+            SyncEvent::Error { .. } => progress.errors += 1,
+            _ => {}
+        }
+    }
+}
+
+fn unknown_job(id: u64) -> RpcError {
+    RpcError {
+        code: code::UNKNOWN_JOB,
+        message: format!("No job {id}"),
+    }
+}
+
+// ============================================================================
+// Service
+// ============================================================================
+
+/// Jobs of a control service and the budget they share
+pub struct ControlService {
+    scheduler: RefCell<Scheduler>,
+    /// Files in flight of all running jobs, weighted by priority
+    budget: Arc<PriorityBudget>,
+    /// Size of the budget; no job may use more on its own
+    files_in_flight: usize,
+}
+
+impl ControlService {
+    /// Service running up to `max_jobs` jobs of a class at once, sharing
+    /// `files_in_flight` files in flight between them
+    #[must_use]
+    pub fn new(max_jobs: usize, files_in_flight: usize) -> Rc<Self> {
+        let files_in_flight = files_in_flight.max(1);
+        Rc::new(Self {
+            scheduler: RefCell::new(Scheduler::new(max_jobs)),
+            budget: PriorityBudget::new(files_in_flight),
+            files_in_flight,
+        })
+    }
+
+    /// Attribute sync events to jobs until the service is dropped
+    pub fn track_progress(self: &Rc<Self>) -> compio::runtime::JoinHandle<()> {
+        let mut events = EVENTS.subscribe(EVENT_QUEUE_CAPACITY);
+        let service = Rc::downgrade(self);
+        compio::runtime::spawn(async move {
+            while let Some(event) = events.next().await {
+                let Some(service) = service.upgrade() else {
+                    break;
+                };
+                service.scheduler.borrow_mut().apply(&event);
+            }
+        })
+    }
+
+    /// Answer one request line; `None` for notifications (no `id`)
+    pub fn handle(self: &Rc<Self>, line: &[u8]) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(line) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    &RpcError {
+                        code: code::PARSE_ERROR,
+                        message: e.to_string(),
+                    },
+                ))
+            }
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let error = RpcError {
+                code: code::INVALID_REQUEST,
+                message: "Request has no method".to_string(),
+            };
+            return Some(error_response(id.unwrap_or(Value::Null), &error));
+        };
+        let params = request.get("params").unwrap_or(&Value::Null);
+        let result = self.call(method, params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(error) => error_response(id, &error),
+        })
+    }
+
+    fn call(self: &Rc<Self>, method: &str, params: &Value) -> std::result::Result<Value, RpcError> {
+        match method {
+            "submit" => {
+                let args = params
+                    .get("args")
+                    .and_then(Value::as_array)
+                    .and_then(|args| {
+                        args.iter()
+                            .map(|arg| arg.as_str().map(OsString::from))
+                            .collect::<Option<Vec<_>>>()
+                    })
+                    .ok_or_else(|| {
+                        RpcError::invalid_params("\"args\" must be a list of strings")
+                    })?;
+                let id = self.submit(&args, priority_params(params)?)?;
+                Ok(json!({ "job": id }))
+            }
+            "status" => {
+                let id = job_param(params)?;
+                let scheduler = self.scheduler.borrow();
+                let job = scheduler.jobs.get(&id).ok_or_else(|| unknown_job(id))?;
+                Ok(job.to_json(id))
+            }
+            "cancel" => {
+                let id = job_param(params)?;
+                let (cancelled, task) = self.scheduler.borrow_mut().cancel(id)?;
+                // Dropping the task stops the sync at its next await
+                drop(task);
+                if cancelled {
+                    info!("Cancelled job {id}");
+                    self.start_jobs();
+                }
+                Ok(json!({ "job": id, "cancelled": cancelled }))
+            }
+            "list" => {
+                let scheduler = self.scheduler.borrow();
+                let jobs: Vec<Value> = scheduler
+                    .jobs
+                    .iter()
+                    .map(|(&id, job)| job.to_json(id))
+                    .collect();
+                Ok(json!({ "jobs": jobs }))
+            }
+            _ => Err(RpcError {
+                code: code::METHOD_NOT_FOUND,
+                message: format!("Unknown method {method:?}"),
+            }),
+        }
+    }
+
+    /// Parse and validate a job's command line, queue it with its priority
+    /// classes and start it if a slot is free
+    ///
+    /// # Errors
+    ///
+    /// Returns an invalid-params error if the command line is not accepted
+    /// or the sync it describes is invalid.
+    pub fn submit(
+        self: &Rc<Self>,
+        args: &[OsString],
+        mut rules: PriorityRules,
+    ) -> std::result::Result<u64, RpcError> {
+        let args = Args::try_parse_from(
+            std::iter::once(OsString::from("arsync")).chain(args.iter().cloned()),
+        )
+        .map_err(|e| RpcError::invalid_params(e.to_string().trim_end()))?;
+        if let Ok(crate::protocol::Location::Remote { .. }) =
+            crate::protocol::Location::parse(&args.destination().to_string_lossy())
+        {
+            return Err(RpcError::invalid_params(
+                "Remote destinations are not supported by the control service",
+            ));
+        }
+        let mut config = SyncConfig::from(&args);
+        // Nobody watches a terminal
+        config.output.progress = false;
+        config.output.interactive = false;
+        let concurrency = &mut config.concurrency;
+        concurrency.max_files_in_flight = concurrency.max_files_in_flight.min(self.files_in_flight);
+        concurrency.large_files_in_flight =
+            concurrency.large_files_in_flight.min(self.files_in_flight);
+        config
+            .validate()
+            .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+        for (path, _) in &mut rules.paths {
+            *path = config.source.join(&*path);
+        }
+
+        let class = rules.class;
+        let id = self.scheduler.borrow_mut().submit(config, rules);
+        info!("Queued job {id} ({})", class.name());
+        self.start_jobs();
+        Ok(id)
+    }
+
+    /// Start queued jobs while slots are free
+    fn start_jobs(self: &Rc<Self>) {
+        loop {
+            let Some((id, mut config, rules)) = self.scheduler.borrow_mut().start_next() else {
+                return;
+            };
+            config.priority = Some(Arc::new(JobShare::new(Arc::clone(&self.budget), id, rules)));
+            // The run tags its events with the job
+            config.metadata.run = Arc::new(RunState {
+                job: id,
+                ..RunState::default()
+            });
+            info!(
+                "Starting job {id}: {} -> {}",
+                config.source.display(),
+                config.destination.display()
+            );
+            let service = Rc::clone(self);
+            let task = compio::runtime::spawn(async move {
+                let result = AssertUnwindSafe(crate::sync::sync(&config))
+                    .catch_unwind()
+                    .await;
+                let result = match result {
+                    Ok(Ok(stats)) => Ok(stats),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("Job panicked".to_string()),
+                };
+                match &result {
+                    Ok(stats) => info!(
+                        "Job {id} completed: {} files, {} bytes in {:?}",
+                        stats.files_copied, stats.bytes_copied, stats.duration
+                    ),
+                    Err(e) => warn!("Job {id} failed: {e}"),
+                }
+                service.scheduler.borrow_mut().finish(id, result);
+                service.start_jobs();
+            });
+            // The task has not run yet, so the job is still running
+            if let Some(job) = self.scheduler.borrow_mut().jobs.get_mut(&id) {
+                job.task = Some(task);
+            }
+        }
+    }
+}
+
+/// The `job` parameter of `status` and `cancel`
+fn job_param(params: &Value) -> std::result::Result<u64, RpcError> {
+    params
+        .get("job")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::invalid_params("\"job\" must be a job id"))
+}
+
+/// The `priority` and `paths` parameters of `submit`
+fn priority_params(params: &Value) -> std::result::Result<PriorityRules, RpcError> {
+    let class = |value: &Value| {
+        value
+            .as_str()
+            .ok_or_else(|| RpcError::invalid_params("A priority must be a string"))?
+            .parse::<Priority>()
+            .map_err(RpcError::invalid_params)
+    };
+    let mut rules = PriorityRules::default();
+    if let Some(value) = params.get("priority") {
+        rules.class = class(value)?;
+    }
+    if let Some(paths) = params.get("paths") {
+        let paths = paths
+            .as_object()
+            .ok_or_else(|| RpcError::invalid_params("\"paths\" must map paths to priorities"))?;
+        for (path, value) in paths {
+            rules.paths.push((PathBuf::from(path), class(value)?));
+        }
+    }
+    Ok(rules)
+}
+
+fn error_response(id: Value, error: &RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+// ============================================================================
+// Socket
+// ============================================================================
+
+/// Run `arsync serve`: accept control connections until the process ends
+///
+/// # Errors
+///
+/// Returns an error if the control socket cannot be created (or another
+/// service is already listening on it) or accepting connections fails.
+#[allow(clippy::future_not_send)] // compio buffers are not Send by design
+pub async fn serve(args: &ServeArgs) -> Result<()> {
+    let path = &args.control_socket;
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!("Another arsync is serving on {}", path.display());
+        }
+        // Left behind by a service that did not exit cleanly
+        std::fs::remove_file(path)
+            .with_context(|| format!("Cannot remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .await
+        .with_context(|| format!("Cannot listen on {}", path.display()))?;
+    // Whoever can connect can copy files as this user
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Cannot restrict {}", path.display()))?;
+
+    let max_jobs = usize::try_from(args.max_jobs).unwrap_or(usize::MAX);
+    let files_in_flight = usize::try_from(args.max_files_in_flight).unwrap_or(usize::MAX);
+    let service = ControlService::new(max_jobs, files_in_flight);
+    let _progress = service.track_progress();
+    info!(
+        "Serving on {} ({max_jobs} jobs at once, {files_in_flight} files in flight)",
+        path.display()
+    );
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept a control connection")?;
+        let service = Rc::clone(&service);
+        compio::runtime::spawn(async move {
+            if let Err(e) = serve_connection(&service, stream).await {
+                debug!("Control connection ended: {e:#}");
+            }
+        })
+        .detach();
+    }
+}
+
+/// Answer requests on one connection until the client closes it
+#[allow(clippy::future_not_send)]
+async fn serve_connection(service: &Rc<ControlService>, mut stream: UnixStream) -> Result<()> {
+    let mut pending = Vec::new();
+    let mut buf = Vec::with_capacity(64 * 1024);
+    loop {
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            if let Some(response) = service.handle(&line) {
+                let mut text = response.to_string();
+                text.push('\n');
+                let BufResult(result, _) = stream.write_all(text.into_bytes()).await;
+                result.context("Failed to answer a control request")?;
+            }
+        }
+        if pending.len() > MAX_REQUEST_LEN {
+            anyhow::bail!("Control request longer than {MAX_REQUEST_LEN} bytes");
+        }
+        buf.clear();
+        let BufResult(result, returned) = stream.read(buf).await;
+        buf = returned;
+        if result.context("Failed to read a control request")? == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::path::PathBuf;
+
+    fn config(source: &str) -> SyncConfig {
+        SyncConfig::new(source, "/backup")
+    }
+
+    #[test]
+    fn test_jobs_wait_for_a_free_slot() {
+        // Requirement: No more than max_jobs run at once; queued jobs start
+        // in submission order as slots free up, and cancelled ones never do
+        let mut scheduler = Scheduler::new(2);
+        let ids: Vec<u64> = ["/a", "/b", "/c", "/d"]
+            .into_iter()
+            .map(|source| scheduler.submit(config(source), PriorityRules::default()))
+            .collect();
+        assert_eq!(scheduler.start_next().unwrap().0, ids[0]);
+        assert_eq!(scheduler.start_next().unwrap().0, ids[1]);
+        assert!(scheduler.start_next().is_none());
+
+        assert!(scheduler.cancel(ids[2]).unwrap().0);
+        let stats = SyncStats {
+            files_copied: 3,
+            bytes_copied: 30,
+            errors: 0,
+            duration: std::time::Duration::ZERO,
+        };
+        scheduler.finish(ids[0], Ok(stats));
+        assert_eq!(scheduler.start_next().unwrap().0, ids[3]);
+        assert!(scheduler.start_next().is_none());
+
+        let state = |id: u64| scheduler.jobs[&id].state.clone();
+        assert_eq!(state(ids[0]), JobState::Completed);
+        assert_eq!(scheduler.jobs[&ids[0]].progress.files_copied, 3);
+        assert_eq!(state(ids[1]), JobState::Running);
+        assert_eq!(state(ids[2]), JobState::Cancelled);
+        assert!(!scheduler.cancel(ids[2]).unwrap().0);
+        assert_eq!(scheduler.cancel(99).unwrap_err().code, code::UNKNOWN_JOB);
+    }
+
+    #[test]
+    fn test_urgent_jobs_start_first_and_beside_background_ones() {
+        // Requirement: Queued jobs start most urgent class first; a job only
+        // needs a slot among jobs of its class or a more urgent one, so an
+        // interactive job starts while background jobs fill every slot
+        let class = |class| PriorityRules {
+            class,
+            paths: Vec::new(),
+        };
+        let mut scheduler = Scheduler::new(1);
+        let mirror = scheduler.submit(config("/a"), class(Priority::Background));
+        assert_eq!(scheduler.start_next().unwrap().0, mirror);
+        let later_mirror = scheduler.submit(config("/b"), class(Priority::Background));
+        let normal = scheduler.submit(config("/c"), class(Priority::Normal));
+        let restore = scheduler.submit(config("/d"), class(Priority::Interactive));
+
+        assert_eq!(scheduler.start_next().unwrap().0, restore);
+        // The normal job needs the slot the interactive one holds
+        assert!(scheduler.start_next().is_none());
+        scheduler.finish(restore, Err("done".to_string()));
+        assert_eq!(scheduler.start_next().unwrap().0, normal);
+        assert!(scheduler.start_next().is_none());
+        assert_eq!(scheduler.jobs[&later_mirror].state, JobState::Queued);
+        assert_eq!(
+            scheduler.jobs[&mirror].to_json(mirror)["priority"],
+            "background"
+        );
+    }
+
+    #[test]
+    fn test_events_count_towards_their_job() {
+        // Requirement: Progress events are attributed to the running job
+        // that published them, even among jobs with the same source
+        let mut scheduler = Scheduler::new(2);
+        let outer = scheduler.submit(config("/data"), PriorityRules::default());
+        let inner = scheduler.submit(config("/data"), PriorityRules::default());
+        scheduler.start_next();
+        scheduler.start_next();
+
+        let file = FileId(7);
+        for event in [
+            SyncEvent::EntryDiscovered {
+                job: inner,
+                path: PathBuf::from("/data/photos/a.jpg"),
+                size: 100,
+                is_dir: false,
+            },
+            SyncEvent::FileStarted {
+                job: inner,
+                id: file,
+                path: PathBuf::from("/data/photos/a.jpg"),
+                size: 100,
+            },
+            SyncEvent::ChunkCopied {
+                id: file,
+                offset: 0,
+                bytes: 100,
+            },
+            SyncEvent::FileDone {
+                id: file,
+                bytes: 100,
+            },
+            SyncEvent::Error {
+                job: outer,
+                path: PathBuf::from("/data/photos/a.jpg"),
+                message: "EIO".to_string(),
+            },
+            SyncEvent::EntryDiscovered {
+                job: 99,
+                path: PathBuf::from("/data/x"),
+                size: 1,
+                is_dir: false,
+            },
+        ] {
+            scheduler.apply(&event);
+        }
+
+        assert_eq!(
+            scheduler.jobs[&inner].progress,
+            JobProgress {
+                files_discovered: 1,
+                bytes_discovered: 100,
+                files_copied: 1,
+                bytes_copied: 100,
+                errors: 0,
+            }
+        );
+        assert_eq!(
+            scheduler.jobs[&outer].progress,
+            JobProgress {
+                errors: 1,
+                ..JobProgress::default()
+            }
+        );
+        assert!(scheduler.files.is_empty());
+    }
+
+    #[compio::test]
+    async fn test_requests_and_errors() {
+        // Requirement: Requests are answered as JSON-RPC 2.0, with the
+        // standard error codes for malformed requests and bad params
+        let service = ControlService::new(1, 64);
+        let call = |line: &str| service.handle(line.as_bytes()).unwrap();
+
+        let response = call(r#"{"jsonrpc":"2.0","id":1,"method":"list"}"#);
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["jobs"], json!([]));
+
+        assert_eq!(call("{not json")["error"]["code"], code::PARSE_ERROR);
+        let response = call(r#"{"jsonrpc":"2.0","id":2,"method":"nope"}"#);
+        assert_eq!(response["error"]["code"], code::METHOD_NOT_FOUND);
+        let response = call(r#"{"jsonrpc":"2.0","id":3,"method":"status","params":{"job":5}}"#);
+        assert_eq!(response["error"]["code"], code::UNKNOWN_JOB);
+        let response = call(
+            r#"{"jsonrpc":"2.0","id":4,"method":"submit","params":{"args":["--no-such-option","/a","/b"]}}"#,
+        );
+        assert_eq!(response["error"]["code"], code::INVALID_PARAMS);
+        let response = call(
+            r#"{"jsonrpc":"2.0","id":5,"method":"submit","params":{"args":["-a","/nonexistent/source","/b"]}}"#,
+        );
+        assert_eq!(response["error"]["code"], code::INVALID_PARAMS);
+        let response = call(
+            r#"{"jsonrpc":"2.0","id":6,"method":"submit","params":{"args":["/a","/b"],"priority":"urgent"}}"#,
+        );
+        assert_eq!(response["error"]["code"], code::INVALID_PARAMS);
+        let response = call(
+            r#"{"jsonrpc":"2.0","id":7,"method":"submit","params":{"args":["/a","/b"],"paths":["/a/x"]}}"#,
+        );
+        assert_eq!(response["error"]["code"], code::INVALID_PARAMS);
+
+        // Notifications get no answer
+        assert!(service
+            .handle(br#"{"jsonrpc":"2.0","method":"list"}"#)
+            .is_none());
+    }
+
+    #[compio::test]
+    async fn test_submitted_job_runs_to_completion() {
+        // Requirement: A submitted job syncs its source and reports
+        // completion through status
+        let temp = tempfile::TempDir::new().unwrap();
+        let source = temp.path().join("src");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        let dest = temp.path().join("dst");
+
+        let service = ControlService::new(2, 64);
+        let id = service
+            .submit(
+                &[
+                    OsString::from("-a"),
+                    source.clone().into_os_string(),
+                    dest.clone().into_os_string(),
+                ],
+                PriorityRules::default(),
+            )
+            .unwrap();
+        let status = |service: &Rc<ControlService>| {
+            let request =
+                format!(r#"{{"jsonrpc":"2.0","id":1,"method":"status","params":{{"job":{id}}}}}"#);
+            service.handle(request.as_bytes()).unwrap()["result"].clone()
+        };
+        for _ in 0..500 {
+            if status(&service)["state"] != "running" {
+                break;
+            }
+            compio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let job = status(&service);
+        assert_eq!(job["state"], "completed", "{job}");
+        assert_eq!(job["files_copied"], 1);
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"hello");
+    }
+
+    #[compio::test]
+    async fn test_jobs_use_their_own_tuning_profiles() {
+        // Requirement: Jobs running at once copy with their own tuning
+        // profile (here seen in their chunk sizes), not the first job's
+        let temp = tempfile::TempDir::new().unwrap();
+        let mut events = EVENTS.subscribe(EVENT_QUEUE_CAPACITY);
+        let service = ControlService::new(2, 64);
+        let mut jobs = Vec::new();
+        for (name, tune) in [("nfs", "nfs"), ("generic", "generic")] {
+            let source = temp.path().join(name);
+            std::fs::create_dir(&source).unwrap();
+            std::fs::write(source.join("data.bin"), vec![7u8; 4 * 1024 * 1024]).unwrap();
+            let args = [
+                "-a",
+                "--tune",
+                tune,
+                "--copy-method",
+                "read-write",
+                source.to_str().unwrap(),
+                temp.path().join(format!("{name}-copy")).to_str().unwrap(),
+            ]
+            .map(OsString::from);
+            jobs.push((
+                source,
+                service.submit(&args, PriorityRules::default()).unwrap(),
+            ));
+        }
+        for _ in 0..500 {
+            let scheduler = service.scheduler.borrow();
+            if jobs
+                .iter()
+                .all(|(_, id)| scheduler.jobs[id].state != JobState::Running)
+            {
+                break;
+            }
+            drop(scheduler);
+            compio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let mut sources = HashMap::new();
+        let mut largest_chunk: HashMap<PathBuf, u64> = HashMap::new();
+        while let Some(Some(event)) = events.next().now_or_never() {
+            match event {
+                SyncEvent::FileStarted { id, path, .. } => {
+                    sources.insert(id, path);
+                }
+                SyncEvent::ChunkCopied { id, bytes, .. } => {
+                    if let Some(path) = sources.get(&id) {
+                        let largest = largest_chunk.entry(path.clone()).or_default();
+                        *largest = (*largest).max(bytes);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for ((source, id), buffer_size) in jobs.iter().zip([1024 * 1024, 64 * 1024]) {
+            let state = service.scheduler.borrow().jobs[id].state.clone();
+            assert_eq!(state, JobState::Completed);
+            assert_eq!(largest_chunk[&source.join("data.bin")], buffer_size);
+        }
+    }
+}
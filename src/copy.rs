@@ -10,6 +10,8 @@
 //! - **`copy_file_range`**: In-kernel copying, most efficient for large files
 //! - **`splice`**: Zero-copy operations using pipes
 //! - **`read_write`**: Traditional fallback method
+//! - **server-side copy**: NFS 4.2 / SMB3 offload, tried first on network mounts
+//!   (see `crate::offload`)
 //! - **auto**: Automatically selects the best method available
 //!
 //! # Performance Characteristics
@@ -39,25 +41,38 @@
 //! }
 //! ```
 
-use crate::cli::ParallelCopyConfig;
-use crate::error::{Result, SyncError};
+use crate::atomic_create::StagedFile;
+use crate::checksum_list::Digester;
+use crate::cli::{CopyMethod, ParallelCopyConfig};
+use crate::device::{dir_device, DevicePair, DeviceTimings};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::events::{FileEvents, SyncEvent, EVENTS};
 use crate::metadata::{preserve_file_metadata, MetadataConfig};
+use crate::metrics::{IoOp, Metrics};
+use crate::write_verify::ChunkChecksums;
+use compio::buf::IoBuf;
 use compio::dispatcher::Dispatcher;
 use compio::fs::File;
 use compio::io::{AsyncReadAt, AsyncWriteAt};
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::stream::{FuturesOrdered, FuturesUnordered, StreamExt};
+use std::ops::Range;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::LazyLock;
+use std::time::Instant;
 
-/// Default I/O buffer size (in bytes) used for chunked read/write operations.
-///
-/// Chosen to balance syscall overhead and memory usage. Adjust if profiling
-/// indicates different optimal sizes for specific workloads.
-const BUFFER_SIZE: usize = 64 * 1024; // 64KB buffer
+/// Pipe size requested for `splice` copies (the default unprivileged maximum)
+const SPLICE_PIPE_SIZE: libc::c_int = 1024 * 1024;
 
 /// 2MB huge page size for alignment in parallel copies
 const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
 
+/// `FICLONE`: `_IOW(0x94, 9, int)`, issued on the destination fd
+pub(crate) const FICLONE: libc::c_ulong = 0x4004_9409;
+
+/// Whether the "reflinks not supported" warning was already logged
+static REFLINK_WARNED: AtomicBool = AtomicBool::new(false);
+
 /// Global static dispatcher for parallel copy operations.
 ///
 /// The dispatcher is shared across all threads and initialized lazily on first use.
@@ -104,21 +119,48 @@ pub async fn copy_file(
         src.parent().unwrap_or_else(|| std::path::Path::new(".")),
     )
     .await
-    .map_err(|e| SyncError::FileSystem(format!("Failed to open source parent: {e}")))?;
+    .map_err(|e| {
+        ErrorContext::new("open source parent")
+            .source(src)
+            .cause(&e)
+            .file_system()
+    })?;
 
     let src_filename = src
         .file_name()
         .ok_or_else(|| SyncError::FileSystem("Source has no filename".to_string()))?;
 
-    // Get metadata
-    let src_metadata = src_parent_dir.statx_full(src_filename).await?;
+    // Get metadata (only the fields the metadata flags need)
+    let src_metadata = src_parent_dir
+        .statx_with_mask(src_filename, metadata_config.statx_mask())
+        .await
+        .map_err(|e| {
+            ErrorContext::new("statx")
+                .source(src)
+                .dirfd(src_parent_dir.path())
+                .cause(&e)
+                .file_system()
+        })?;
+
+    EVENTS.emit_with(|| SyncEvent::EntryDiscovered {
+        job: metadata_config.run.job,
+        path: src.to_path_buf(),
+        size: src_metadata.size,
+        is_dir: false,
+    });
 
     // Set up DirectoryFd for destination
     let dst_parent_dir = compio_fs_extended::DirectoryFd::open(
         dst.parent().unwrap_or_else(|| std::path::Path::new(".")),
     )
     .await
-    .map_err(|e| SyncError::FileSystem(format!("Failed to open dest parent: {e}")))?;
+    .map_err(|e| {
+        ErrorContext::new("open destination parent")
+            .source(src)
+            .destination(dst)
+            .cause(&e)
+            .file_system()
+    })?;
 
     let dst_filename = dst
         .file_name()
@@ -132,6 +174,7 @@ pub async fn copy_file(
         dst,
         metadata_config,
         parallel_config,
+        &CopyMethod::Auto,
         dispatcher,
         &src_metadata,
         &src_parent_dir,
@@ -162,6 +205,7 @@ pub async fn copy_file(
 /// - `src_metadata`: Pre-fetched metadata via `DirectoryFd::statx_full()`
 /// - `dst_parent_dir`: Destination parent `DirectoryFd` for TOCTOU-safe creation
 /// - `dst_filename`: Destination **basename only** (no path separators) relative to `dst_parent_dir`
+/// - `copy_method`: `--copy-method`; see [`kernel_copy`]
 /// - `dispatcher`: For parallel copy operations
 ///
 /// **Why `&str` not `&Path`?** Filenames must be simple basenames without `/` for TOCTOU safety.
@@ -181,6 +225,8 @@ pub async fn copy_file(
 /// - Destination file cannot be created or opened for writing
 /// - File copying operation fails (I/O errors, permission issues)
 /// - Metadata preservation fails
+/// - An immutable destination cannot be unlocked (`--update-immutable`) or
+///   the source's inode flags cannot be set (`--preserve-flags`)
 #[allow(clippy::future_not_send)]
 #[allow(clippy::too_many_arguments)]
 pub async fn copy_file_internal(
@@ -188,6 +234,7 @@ pub async fn copy_file_internal(
     dst: &Path, // Only for error messages
     metadata_config: &MetadataConfig,
     parallel_config: &ParallelCopyConfig,
+    copy_method: &CopyMethod,
     dispatcher: &'static Dispatcher,
     src_metadata: &compio_fs_extended::FileMetadata,
     src_parent_dir: &compio_fs_extended::DirectoryFd,
@@ -197,14 +244,63 @@ pub async fn copy_file_internal(
 ) -> Result<()> {
     // Get file size from pre-fetched metadata (no syscall needed!)
     let file_size = src_metadata.size;
+    let events = EVENTS.file_started(&metadata_config.run, src, file_size);
+    let kernel_copy = kernel_copy(copy_method, metadata_config, src_metadata, dst_parent_dir);
+
+    // --update-immutable: an immutable or append-only destination cannot be
+    // opened for writing or renamed over, so unlock it first
+    let unlocked = if metadata_config.update_immutable {
+        crate::inode_flags::unlock_at(dst_parent_dir.as_raw_fd(), dst_filename).map_err(|e| {
+            ErrorContext::new("FS_IOC_SETFLAGS")
+                .destination(dst)
+                .dirfd(dst_parent_dir.path())
+                .cause(&e)
+                .file_system()
+        })?
+    } else {
+        None
+    };
+
+    // --parallel-adaptive: the depth suits the devices copied between
+    let devices = if parallel_config.adaptive {
+        dir_device(dst_parent_dir).map(|destination| DevicePair {
+            source: src_metadata.dev,
+            destination,
+        })
+    } else {
+        None
+    };
 
-    // Decide whether to use parallel copy
-    if parallel_config.should_use_parallel(file_size) {
+    // Decide whether to use parallel copy (not needed when the kernel copies,
+    // --sparse needs the sequential loop to skip holes, --partial needs data
+    // written in order so a partial file's length is its valid prefix,
+    // --emit-checksums hashes the data in file order, and rotational disks
+    // are copied sequentially with --parallel-adaptive)
+    let result = if file_size == 0 && empty_fast_path(metadata_config) {
+        create_empty_file(
+            dst,
+            metadata_config,
+            src_metadata,
+            dst_parent_dir,
+            dst_filename,
+        )
+        .await
+    } else if kernel_copy.is_none()
+        && !metadata_config.sparse
+        && !metadata_config.partial
+        && !metadata_config.run.checksums.active()
+        && parallel_config.should_use_parallel(file_size)
+        && metadata_config.run.tuning.parallel_writes
+        && devices.is_none_or(|devices| {
+            devices.depth(&metadata_config.run.devices, parallel_config.max_depth) != Some(0)
+        })
+    {
         copy_read_write_parallel(
             src,
             dst,
             metadata_config,
             parallel_config,
+            devices,
             file_size,
             dispatcher,
             src_metadata,
@@ -212,6 +308,7 @@ pub async fn copy_file_internal(
             src_filename,
             dst_parent_dir,
             dst_filename,
+            &events,
         )
         .await
     } else {
@@ -220,13 +317,633 @@ pub async fn copy_file_internal(
             dst,
             metadata_config,
             file_size,
+            kernel_copy,
             src_metadata,
             src_parent_dir,
             src_filename,
             dst_parent_dir,
             dst_filename,
+            &events,
+        )
+        .await
+    };
+    let result = match result {
+        Ok(()) if metadata_config.preserve_flags => preserve_flags(
+            src,
+            dst,
+            src_parent_dir,
+            src_filename,
+            dst_parent_dir,
+            dst_filename,
+        ),
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // Lock the destination again if the failed copy left it in place
+            if let Some(flags) = unlocked {
+                if let Err(restore) =
+                    crate::inode_flags::apply_at(dst_parent_dir.as_raw_fd(), dst_filename, flags)
+                {
+                    tracing::warn!(
+                        "Failed to restore the inode flags of {}: {restore}",
+                        dst.display()
+                    );
+                }
+            }
+            Err(e)
+        }
+    };
+
+    match &result {
+        Ok(()) => events.done(file_size),
+        Err(_) => events.failed(),
+    }
+    result
+}
+
+/// Give the copied `dst` the source's inode flags (--preserve-flags), last
+/// because immutable and append-only flags forbid any further change
+///
+/// Sources on filesystems without inode flags leave the destination's alone.
+fn preserve_flags(
+    src: &Path,
+    dst: &Path,
+    src_parent_dir: &compio_fs_extended::DirectoryFd,
+    src_filename: &std::ffi::OsStr,
+    dst_parent_dir: &compio_fs_extended::DirectoryFd,
+    dst_filename: &std::ffi::OsStr,
+) -> Result<()> {
+    let Ok(flags) = crate::inode_flags::get_at(src_parent_dir.as_raw_fd(), src_filename) else {
+        return Ok(());
+    };
+    crate::inode_flags::apply_at(dst_parent_dir.as_raw_fd(), dst_filename, flags).map_err(|e| {
+        ErrorContext::new("FS_IOC_SETFLAGS")
+            .source(src)
+            .destination(dst)
+            .dirfd(dst_parent_dir.path())
+            .cause(&e)
+            .file_system()
+    })
+}
+
+/// Whether a zero-byte file can be copied by [`create_empty_file`]
+///
+/// Nothing may need the source open (`--xattrs`, `--acls`) or the
+/// destination written elsewhere first (`--atomic-create`, `--partial`,
+/// `--delay-updates`).
+const fn empty_fast_path(metadata_config: &MetadataConfig) -> bool {
+    !metadata_config.should_preserve_xattrs()
+        && !metadata_config.should_preserve_acls()
+        && metadata_config.atomic_create.is_none()
+        && !metadata_config.partial
+        && !metadata_config.delay_updates
+}
+
+/// Copy a zero-byte file by creating `dst` and applying its metadata
+///
+/// Trees of lock and flag files would otherwise pay for opening the source,
+/// the buffer pool and a round trip per piece of metadata, all for no data.
+/// Here the source is never opened: the destination is created (or
+/// truncated), given the preserved ownership, permissions and timestamps
+/// from the metadata the traversal already has, synced with `--fsync` and
+/// closed in one job on the blocking pool. io_uring has no fchown, fchmod
+/// or futimens opcode to link behind its openat, so one job is the shortest
+/// chain there is.
+#[allow(clippy::future_not_send)]
+async fn create_empty_file(
+    dst: &Path,
+    metadata_config: &MetadataConfig,
+    src_metadata: &compio_fs_extended::FileMetadata,
+    dst_parent_dir: &compio_fs_extended::DirectoryFd,
+    dst_filename: &std::ffi::OsStr,
+) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(dst_filename.as_bytes()).map_err(|e| {
+        SyncError::FileSystem(format!("Invalid destination name {}: {e}", dst.display()))
+    })?;
+    let owner = metadata_config.ownership(src_metadata.uid, src_metadata.gid);
+    let mode = metadata_config
+        .should_preserve_permissions()
+        .then(|| src_metadata.permissions());
+    let times = metadata_config.should_preserve_timestamps().then(|| {
+        [src_metadata.accessed, src_metadata.modified].map(|time| {
+            let time = compio_fs_extended::Timestamp::from(time);
+            libc::timespec {
+                tv_sec: time.secs,
+                tv_nsec: i64::from(time.nsecs),
+            }
+        })
+    });
+    let fsync = metadata_config.fsync;
+    let dir_fd = dst_parent_dir.as_raw_fd();
+
+    // `dst_parent_dir` outlives the blocking call, keeping its fd open
+    let result = compio::runtime::spawn_blocking(move || {
+        let flags =
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC | libc::O_NOFOLLOW;
+        // SAFETY: dir_fd is open and name is NUL-terminated
+        let fd = unsafe { libc::openat(dir_fd, name.as_ptr(), flags, 0o644) };
+        if fd < 0 {
+            return Err(("openat destination", std::io::Error::last_os_error()));
+        }
+        // SAFETY: fd was just opened and is owned here, closed on drop
+        let file = unsafe { <std::fs::File as std::os::unix::io::FromRawFd>::from_raw_fd(fd) };
+        let check = |op: &'static str, ret: libc::c_int| {
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err((op, std::io::Error::last_os_error()))
+            }
+        };
+        // Ownership before permissions: fchown clears setuid and setgid bits
+        if let Some((uid, gid)) = owner {
+            // SAFETY: fchown on an open fd
+            check("fchown", unsafe { libc::fchown(fd, uid, gid) })?;
+        }
+        if let Some(mode) = mode {
+            // SAFETY: fchmod on an open fd
+            check("fchmod", unsafe { libc::fchmod(fd, mode) })?;
+        }
+        if let Some(times) = times {
+            // SAFETY: times points to two timespecs
+            check("futimens", unsafe { libc::futimens(fd, times.as_ptr()) })?;
+        }
+        if fsync {
+            file.sync_all().map_err(|e| ("fsync", e))?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|_| SyncError::Internal("Empty file worker panicked".to_string()))?;
+
+    result.map_err(|(op, e)| {
+        ErrorContext::new(op)
+            .destination(dst)
+            .dirfd(dst_parent_dir.path())
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let checksums = &metadata_config.run.checksums;
+    if let Some(digester) = checksums.digester() {
+        checksums.record(dst, digester);
+    }
+    Ok(())
+}
+
+/// In-kernel copy tried before the read/write loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KernelCopy {
+    /// `copy_file_range(2)`
+    CopyFileRange,
+    /// `FICLONE`: share the source's extents (copy-on-write filesystems)
+    Reflink,
+    /// `splice(2)` through a pipe
+    Splice,
+}
+
+/// Which in-kernel copy to try before the read/write loop, if any
+///
+/// - `copy-file-range`: always tried; the kernel copies across filesystems
+///   where it can and refuses (`EXDEV`) where it cannot
+/// - `reflink`: always tried; refused unless both files are on the same
+///   btrfs/XFS (or other reflink-capable) filesystem
+/// - `splice`: always tried; the data moves from the source's page cache
+///   through a pipe into the destination, across filesystems too
+/// - `auto`: `copy_file_range` when source and destination are on the same
+///   filesystem, where the data then never passes through userspace (and
+///   btrfs/XFS may share extents instead of copying them); not with
+///   `--sparse`, since filesystems without shared extents write the holes
+/// - `read-write`: none
+///
+/// Where `copy_file_range` is refused (filesystems without it, old kernels),
+/// `splice` is tried before falling back to read/write.
+///
+/// `--verify-direct` and `--emit-checksums` need the written data in
+/// userspace to checksum it, so they always copy with read/write.
+fn kernel_copy(
+    copy_method: &CopyMethod,
+    metadata_config: &MetadataConfig,
+    src_metadata: &compio_fs_extended::FileMetadata,
+    dst_parent_dir: &compio_fs_extended::DirectoryFd,
+) -> Option<KernelCopy> {
+    if metadata_config.verify_direct.is_some() || metadata_config.run.checksums.active() {
+        return None;
+    }
+    match copy_method {
+        CopyMethod::CopyFileRange => Some(KernelCopy::CopyFileRange),
+        CopyMethod::Reflink => Some(KernelCopy::Reflink),
+        CopyMethod::Auto if metadata_config.sparse => None,
+        CopyMethod::Auto => {
+            same_device(src_metadata.dev, dst_parent_dir).then_some(KernelCopy::CopyFileRange)
+        }
+        CopyMethod::Splice => Some(KernelCopy::Splice),
+        CopyMethod::ReadWrite => None,
+    }
+}
+
+/// `fallocate` mode for preallocating destination data
+///
+/// With `--partial` preallocation must not extend the file, whose length
+/// marks how much of it an interrupted run wrote.
+fn fallocate_mode(metadata_config: &MetadataConfig) -> u32 {
+    if metadata_config.partial {
+        compio_fs_extended::fallocate::mode::KEEP_SIZE
+    } else {
+        0
+    }
+}
+
+/// Whether the directory `dir` is on device `dev`
+fn same_device(dev: u64, dir: &compio_fs_extended::DirectoryFd) -> bool {
+    dir_device(dir) == Some(dev)
+}
+
+/// Copy the whole file with `copy_file_range(2)`
+///
+/// There is no `io_uring` opcode for `copy_file_range`, so the syscall runs on
+/// compio's blocking pool, keeping the runtime thread free while the kernel
+/// copies. Returns `Ok(false)` if the kernel or filesystem refuses before any
+/// data was copied, so the caller can fall back to read/write.
+///
+/// # Errors
+///
+/// Returns an error if the copy fails part-way or the blocking worker could
+/// not be run.
+async fn try_copy_file_range(src: &File, dst: &File, len: u64) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if len == 0 {
+        return Ok(false);
+    }
+    // Both files outlive the await, so the raw fds stay valid
+    let (src_fd, dst_fd) = (src.as_raw_fd(), dst.as_raw_fd());
+    compio::runtime::spawn_blocking(move || {
+        crate::offload::copy_file_range_all(src_fd, dst_fd, len)
+    })
+    .await
+    .map_err(|_| SyncError::CopyFailed("copy_file_range worker panicked".to_string()))?
+    .map_err(|e| SyncError::CopyFailed(format!("copy_file_range failed part-way: {e}")))
+}
+
+/// Copy the whole file through a pipe with `splice(2)`
+///
+/// The data goes from the source's page cache into a pipe and from the pipe
+/// into the destination without being copied to userspace, between any two
+/// filesystems that implement splicing. Like `copy_file_range`, it runs on
+/// compio's blocking pool. Returns `Ok(false)` if either file refuses before
+/// any data was written, so the caller can fall back to read/write.
+///
+/// # Errors
+///
+/// Returns an error if the copy fails part-way, the pipe cannot be created
+/// or the blocking worker could not be run.
+async fn try_splice(src: &File, dst: &File, len: u64) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if len == 0 {
+        return Ok(false);
+    }
+    // Both files outlive the await, so the raw fds stay valid
+    let (src_fd, dst_fd) = (src.as_raw_fd(), dst.as_raw_fd());
+    compio::runtime::spawn_blocking(move || splice_all(src_fd, dst_fd, len))
+        .await
+        .map_err(|_| SyncError::CopyFailed("splice worker panicked".to_string()))?
+        .map_err(|e| SyncError::CopyFailed(format!("splice failed part-way: {e}")))
+}
+
+/// Splice `len` bytes from the start of `src_fd` to the start of `dst_fd`
+/// through a pipe; `Ok(false)` if splicing is refused before any data moved
+fn splice_all(
+    src_fd: std::os::unix::io::RawFd,
+    dst_fd: std::os::unix::io::RawFd,
+    len: u64,
+) -> std::io::Result<bool> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for both ends of the pipe
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both fds are open and owned by nobody else
+    let (read_end, write_end) =
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    // SAFETY: F_SETPIPE_SZ on an open pipe
+    let resized =
+        unsafe { libc::fcntl(write_end.as_raw_fd(), libc::F_SETPIPE_SZ, SPLICE_PIPE_SIZE) };
+    // A larger pipe moves more per call; the default 64 KB works too
+    let pipe_size = usize::try_from(resized).unwrap_or(64 * 1024);
+
+    let mut src_off: libc::loff_t = 0;
+    let mut dst_off: libc::loff_t = 0;
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = usize::try_from(remaining)
+            .unwrap_or(pipe_size)
+            .min(pipe_size);
+        // SAFETY: the offset is a valid pointer; all fds are open
+        let filled = unsafe {
+            libc::splice(
+                src_fd,
+                &raw mut src_off,
+                write_end.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk,
+                libc::SPLICE_F_MOVE,
+            )
+        };
+        if filled < 0 {
+            let err = std::io::Error::last_os_error();
+            if dst_off == 0 && splice_unsupported(&err) {
+                return Ok(false);
+            }
+            return Err(err);
+        }
+        if filled == 0 {
+            // Source shrank under us
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("source ended {remaining} bytes early"),
+            ));
+        }
+        let mut in_pipe = filled.unsigned_abs();
+        while in_pipe > 0 {
+            // SAFETY: the offset is a valid pointer; all fds are open
+            let drained = unsafe {
+                libc::splice(
+                    read_end.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    dst_fd,
+                    &raw mut dst_off,
+                    in_pipe,
+                    libc::SPLICE_F_MOVE,
+                )
+            };
+            if drained < 0 {
+                let err = std::io::Error::last_os_error();
+                if dst_off == 0 && splice_unsupported(&err) {
+                    return Ok(false);
+                }
+                return Err(err);
+            }
+            if drained == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            in_pipe -= drained.unsigned_abs();
+        }
+        remaining -= filled.unsigned_abs() as u64;
+    }
+    Ok(true)
+}
+
+/// Whether a `splice` error means "not possible for these files"
+fn splice_unsupported(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EINVAL | libc::EOPNOTSUPP | libc::ENOSYS | libc::EBADF | libc::EXDEV)
+    )
+}
+
+/// Clone the whole file with the `FICLONE` ioctl
+///
+/// Support is only known by trying: the ioctl is refused (`EOPNOTSUPP`,
+/// `EXDEV`, `EINVAL`, `ENOTTY`, ...) when the filesystem cannot share extents
+/// or the files are on different filesystems. Returns `Ok(false)` then, and
+/// warns once per run, so the caller can fall back to read/write.
+///
+/// # Errors
+///
+/// Returns an error if the clone fails for another reason (`ENOSPC`, `EIO`)
+/// or the blocking worker could not be run.
+async fn try_reflink(src: &File, dst: &File, len: u64) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if len == 0 {
+        return Ok(false);
+    }
+    // Cloning walks every extent; keep it off the runtime thread. Both files
+    // outlive the await, so the raw fds stay valid.
+    let (src_fd, dst_fd) = (src.as_raw_fd(), dst.as_raw_fd());
+    let cloned = compio::runtime::spawn_blocking(move || {
+        // SAFETY: FICLONE takes the source fd as its argument; both fds are open
+        if unsafe { libc::ioctl(dst_fd, FICLONE as _, src_fd) } == 0 {
+            Ok(true)
+        } else {
+            let err = std::io::Error::last_os_error();
+            if reflink_unsupported(&err) {
+                Ok(false)
+            } else {
+                Err(err)
+            }
+        }
+    })
+    .await
+    .map_err(|_| SyncError::CopyFailed("Reflink worker panicked".to_string()))?
+    .map_err(|e| SyncError::CopyFailed(format!("FICLONE failed: {e}")))?;
+
+    if !cloned && !REFLINK_WARNED.swap(true, Ordering::Relaxed) {
+        tracing::warn!(
+            "Reflinks are not supported between source and destination; copying file data instead"
+        );
+    }
+    Ok(cloned)
+}
+
+/// Whether a `FICLONE` error means "not possible here" rather than a failure
+fn reflink_unsupported(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(
+            libc::EOPNOTSUPP
+                | libc::EXDEV
+                | libc::EINVAL
+                | libc::ENOTTY
+                | libc::ENOSYS
+                | libc::EBADF
+                | libc::EPERM
         )
+    )
+}
+
+/// Next data segment of `file` at or after `offset`, as `(start, end)`
+///
+/// Returns `None` if only a hole remains before `file_size`. Filesystems
+/// without `SEEK_DATA` support report the whole rest of the file as data.
+fn next_data_segment(file: &File, offset: u64, file_size: u64) -> Option<(u64, u64)> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let pos = libc::off_t::try_from(offset).ok()?;
+    // SAFETY: lseek on an open fd; reads use explicit offsets, so moving the
+    // file position is harmless
+    let start = unsafe { libc::lseek(fd, pos, libc::SEEK_DATA) };
+    if start < 0 {
+        return match std::io::Error::last_os_error().raw_os_error() {
+            // No data after `offset`
+            Some(libc::ENXIO) => None,
+            _ => Some((offset, file_size)),
+        };
+    }
+    // SAFETY: as above
+    let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+    let start = u64::try_from(start).ok()?;
+    if start >= file_size {
+        return None;
+    }
+    let end = u64::try_from(end).map_or(file_size, |end| end.min(file_size));
+    Some((start, end.max(start + 1)))
+}
+
+/// Deallocate `len` bytes at `offset` of `file`, keeping its size
+///
+/// # Errors
+///
+/// Returns an error if the filesystem cannot punch holes.
+async fn punch_hole(file: &File, offset: u64, len: u64) -> Result<()> {
+    use compio_fs_extended::fallocate::mode::{KEEP_SIZE, PUNCH_HOLE};
+    use compio_fs_extended::{ExtendedFile, Fallocate};
+
+    ExtendedFile::from_ref(file)
+        .fallocate(offset, len, PUNCH_HOLE | KEEP_SIZE)
         .await
+        .map_err(|e| SyncError::FileSystem(format!("Failed to punch hole in destination: {e}")))
+}
+
+/// Set the length of `file` to `len`, leaving a hole after its data
+///
+/// # Errors
+///
+/// Returns an error if `ftruncate` fails.
+pub(crate) fn set_file_len(file: &File, len: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = libc::off_t::try_from(len)
+        .map_err(|_| SyncError::FileSystem(format!("File size {len} out of range")))?;
+    // SAFETY: ftruncate on an open, writable fd
+    if unsafe { libc::ftruncate(file.as_raw_fd(), len) } != 0 {
+        return Err(SyncError::FileSystem(format!(
+            "Failed to set destination file size: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+/// Buffer of a read/write copy loop
+///
+/// A buffer registered with the thread's ring when one is free (see
+/// `compio_fs_extended::write_managed`), so chunks are neither allocated
+/// nor pinned per request; otherwise an ordinary heap buffer.
+enum CopyBuffer {
+    /// Registered with the ring (`IORING_OP_READ_FIXED`/`WRITE_FIXED`)
+    #[cfg(target_os = "linux")]
+    Fixed(compio_fs_extended::write_managed::FixedBuf),
+    /// Heap buffer
+    Heap(Vec<u8>),
+}
+
+impl CopyBuffer {
+    /// A buffer of the size of `pool`'s, taken from the pool if none is
+    /// registered
+    fn from_pool(pool: &crate::pipelines::BufferPool) -> Self {
+        Self::fixed(pool.buffer_size()).unwrap_or_else(|| Self::Heap(pool.take()))
+    }
+
+    /// A buffer of `size` bytes
+    fn with_size(size: usize) -> Self {
+        Self::fixed(size).unwrap_or_else(|| Self::Heap(vec![0; size]))
+    }
+
+    /// A registered buffer of `size` bytes, if one is free
+    #[cfg(target_os = "linux")]
+    fn fixed(size: usize) -> Option<Self> {
+        compio_fs_extended::write_managed::take(size).map(Self::Fixed)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fixed(_size: usize) -> Option<Self> {
+        None
+    }
+
+    /// Set the bytes of data (for a read: how many to read at most)
+    fn set_len(&mut self, len: usize) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Fixed(buffer) => buffer.set_len(len),
+            Self::Heap(buffer) => buffer.resize(len, 0),
+        }
+    }
+
+    /// Read up to `len()` bytes of `file` at `offset`, counted in `metrics`;
+    /// `len()` is then the bytes read
+    async fn read_at(
+        self,
+        file: &File,
+        offset: u64,
+        metrics: &Metrics,
+    ) -> compio::buf::BufResult<usize, Self> {
+        let _in_flight = metrics.submit(IoOp::Read);
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Fixed(buffer) => {
+                let compio::buf::BufResult(result, buffer) =
+                    compio_fs_extended::write_managed::read_fixed_at(file, buffer, offset).await;
+                compio::buf::BufResult(result, Self::Fixed(buffer))
+            }
+            Self::Heap(buffer) => {
+                // Reads fill the whole capacity unless sliced to the length
+                let len = buffer.len();
+                let compio::buf::BufResult(result, slice) =
+                    file.read_at(buffer.slice(..len), offset).await;
+                let mut buffer = slice.into_inner();
+                if let Ok(read) = result {
+                    buffer.truncate(read);
+                }
+                compio::buf::BufResult(result, Self::Heap(buffer))
+            }
+        }
+    }
+
+    /// Write the data to `file` at `offset`, counted in `metrics`
+    async fn write_at(
+        self,
+        file: &mut File,
+        offset: u64,
+        metrics: &Metrics,
+    ) -> compio::buf::BufResult<usize, Self> {
+        let _in_flight = metrics.submit(IoOp::Write);
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Fixed(buffer) => {
+                let compio::buf::BufResult(result, buffer) =
+                    compio_fs_extended::write_managed::write_fixed_at(file, buffer, offset).await;
+                compio::buf::BufResult(result, Self::Fixed(buffer))
+            }
+            Self::Heap(buffer) => {
+                let compio::buf::BufResult(result, buffer) = file.write_at(buffer, offset).await;
+                compio::buf::BufResult(result, Self::Heap(buffer))
+            }
+        }
+    }
+
+    /// Give a heap buffer back to `pool` (a registered one goes back to its
+    /// table when dropped)
+    fn put(self, pool: &crate::pipelines::BufferPool) {
+        if let Self::Heap(buffer) = self {
+            pool.put(buffer);
+        }
+    }
+}
+
+impl std::ops::Deref for CopyBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Fixed(buffer) => buffer,
+            Self::Heap(buffer) => buffer,
+        }
     }
 }
 
@@ -275,11 +992,13 @@ async fn copy_read_write(
     dst: &Path, // Only for error messages
     metadata_config: &MetadataConfig,
     file_size: u64,
+    kernel_copy: Option<KernelCopy>,
     src_metadata: &compio_fs_extended::FileMetadata,
     src_parent_dir: &compio_fs_extended::DirectoryFd,
     src_filename: &std::ffi::OsStr,
     dst_parent_dir: &compio_fs_extended::DirectoryFd,
     dst_filename: &std::ffi::OsStr,
+    events: &FileEvents,
 ) -> Result<()> {
     // Extract timestamps from pre-fetched metadata (no syscall needed!)
     let (src_accessed, src_modified) = (src_metadata.accessed, src_metadata.modified);
@@ -289,30 +1008,84 @@ async fn copy_read_write(
         .open_file_at(src_filename, true, false, false, false)
         .await
         .map_err(|e| {
-            SyncError::FileSystem(format!(
-                "Failed to open source file {} via dirfd: {e}",
-                src.display()
-            ))
+            ErrorContext::new("openat source")
+                .source(src)
+                .destination(dst)
+                .dirfd(src_parent_dir.path())
+                .cause(&e)
+                .file_system()
         })?;
 
-    // Open destination file via DirectoryFd (TOCTOU-safe, io_uring-ready)
-    let mut dst_file = dst_parent_dir
-        .open_file_at(dst_filename, false, true, true, true)
-        .await
-        .map_err(|e| {
-            SyncError::FileSystem(format!(
-                "Failed to create destination file {} via dirfd: {e}",
-                dst.display()
-            ))
-        })?;
+    // Open destination file via DirectoryFd (TOCTOU-safe, io_uring-ready),
+    // anonymously or under a temporary name with --atomic-create or
+    // --delay-updates, or as the partial file an interrupted run left behind
+    // with --partial
+    let (dst_file, staged, resume_offset) = if metadata_config.partial {
+        StagedFile::create_partial(dst_parent_dir, dst_filename, src_metadata, dst).await?
+    } else {
+        let (file, staged) =
+            StagedFile::create_for(dst_parent_dir, dst_filename, metadata_config, dst).await?;
+        (file, staged, 0)
+    };
+    if resume_offset > 0 {
+        tracing::debug!(
+            "Resuming {} from its partial file at {resume_offset}/{file_size} bytes",
+            dst.display()
+        );
+    }
 
     // file_size already passed as parameter (from pre-fetched metadata or initial check)
     // ✅ NO redundant src_file.metadata() call!
 
+    // --emit-checksums: hash the data as it is copied, starting with the
+    // prefix a resumed copy keeps
+    let checksum_list = &metadata_config.run.checksums;
+    let mut digester = checksum_list.digester();
+    let hashing = digester.is_some();
+    if let Some(prefix) = digester.take_if(|_| resume_offset > 0) {
+        let reader = staged.reopen_for_read(&dst_file, dst).await?;
+        let prefix = crate::checksum_list::read_back(&reader, Some(resume_offset), prefix, dst);
+        digester = Some(prefix.await?);
+    }
+
+    // Let the server copy the data when both files are on NFS 4.2 / SMB3
+    // (whole files only, so not when resuming)
+    let mut offloaded = resume_offset == 0
+        && !hashing
+        && crate::offload::try_server_side_copy(
+            &src_file,
+            &dst_file,
+            file_size,
+            &metadata_config.run.offload,
+        )
+        .await?;
+
+    // Otherwise let the kernel copy (or clone) locally, falling back to
+    // read/write below if it cannot (old kernel, unsupported filesystem,
+    // cross-device)
+    if let Some(method) = kernel_copy.filter(|_| !offloaded && resume_offset == 0) {
+        offloaded = match method {
+            KernelCopy::CopyFileRange => {
+                try_copy_file_range(&src_file, &dst_file, file_size).await?
+                    || try_splice(&src_file, &dst_file, file_size).await?
+            }
+            KernelCopy::Reflink => try_reflink(&src_file, &dst_file, file_size).await?,
+            KernelCopy::Splice => try_splice(&src_file, &dst_file, file_size).await?,
+        };
+        if offloaded {
+            tracing::debug!("{method:?}: copied {file_size} bytes");
+        } else {
+            tracing::debug!(
+                "{method:?} unsupported for {}, using read/write",
+                dst.display()
+            );
+        }
+    }
+
     // Preallocate destination file space to the final size to reduce fragmentation
     // and improve write performance using io_uring fallocate.
     // Skip preallocation for empty files as fallocate fails with EINVAL for zero length.
-    if file_size > 0 {
+    if file_size > 0 && !offloaded {
         use compio_fs_extended::{ExtendedFile, Fallocate};
 
         let extended_dst = ExtendedFile::from_ref(&dst_file);
@@ -338,10 +1111,18 @@ async fn copy_read_write(
                 })?;
         }
 
-        // Preallocate destination file space
-        extended_dst.fallocate(0, file_size, 0).await.map_err(|e| {
-            SyncError::FileSystem(format!("Failed to preallocate destination file: {e}"))
-        })?;
+        // Preallocate destination file space, unless the tuning profile says
+        // preallocation is wasted on this filesystem (with --sparse only the
+        // data segments are preallocated, in the loop below)
+        if metadata_config.run.tuning.fallocate && !metadata_config.sparse {
+            let mode = fallocate_mode(metadata_config);
+            extended_dst
+                .fallocate(0, file_size, mode)
+                .await
+                .map_err(|e| {
+                    SyncError::FileSystem(format!("Failed to preallocate destination file: {e}"))
+                })?;
+        }
 
         // Hint that destination data won't be accessed again after this copy (Linux only)
         #[cfg(target_os = "linux")]
@@ -362,61 +1143,53 @@ async fn copy_read_write(
         }
     }
 
-    // Use compio's async read_at/write_at operations with buffer reuse
-    // Create buffer once and reuse it throughout the copy (no allocations!)
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-    let mut offset = 0u64;
-    let mut total_copied = 0u64;
-
-    while total_copied < file_size {
-        // Read data from source file - buffer ownership transferred to compio
-        let read_result = src_file.read_at(buffer, offset).await;
-
-        let bytes_read = read_result
-            .0
-            .map_err(|e| SyncError::IoUring(format!("compio read_at operation failed: {e}")))?;
-
-        // Get buffer back from read operation
-        buffer = read_result.1;
-
-        if bytes_read == 0 {
-            // End of file
-            break;
+    // Copy through the file's pipeline pool, several chunks at a time (see
+    // `CopyChunks`); small files use the tuning profile's buffer size,
+    // large files at least 1 MB
+    let pool = metadata_config.run.buffers.pool(file_size);
+    let mut checksums = metadata_config.verify_direct.map(ChunkChecksums::new);
+
+    // --sparse: copy only the source's data segments (SEEK_DATA/SEEK_HOLE)
+    // and skip all-zero chunks, leaving holes in the destination
+    let sparse = metadata_config.sparse && !offloaded;
+    let total_copied = if offloaded {
+        events.chunk(0, file_size);
+        file_size
+    } else {
+        // A resumed prefix counts as copied
+        if resume_offset > 0 {
+            events.chunk(0, resume_offset);
         }
-
-        // Truncate buffer to only the bytes read (avoids writing garbage)
-        // This doesn't allocate, just changes the length
-        buffer.truncate(bytes_read);
-
-        // Write data to destination file - write_at takes ownership and returns the buffer
-        // This way we reuse the same allocation for both read and write
-        let write_result = dst_file.write_at(buffer, offset).await;
-
-        let bytes_written = write_result
-            .0
-            .map_err(|e| SyncError::IoUring(format!("compio write_at operation failed: {e}")))?;
-
-        // Get the buffer back from write operation and resize it for the next read
-        // resize() reuses the existing capacity when possible (no new allocation!)
-        buffer = write_result.1;
-        buffer.resize(BUFFER_SIZE, 0);
-
-        // Ensure we wrote the expected number of bytes
-        if bytes_written != bytes_read {
-            return Err(SyncError::CopyFailed(format!(
-                "Write size mismatch: expected {bytes_read}, got {bytes_written}"
-            )));
+        let chunks = CopyChunks {
+            src: &src_file,
+            dst: &dst_file,
+            pool,
+            sparse,
+            preallocate_segments: sparse && metadata_config.run.tuning.fallocate,
+            fallocate_mode: fallocate_mode(metadata_config),
+        };
+        resume_offset
+            + chunks
+                .copy(
+                    resume_offset,
+                    file_size,
+                    &mut checksums,
+                    &mut digester,
+                    events,
+                )
+                .await?
+    };
+    // A short read put chunks out of file order: hash what was written
+    if hashing && digester.is_none() {
+        if let Some(fresh) = checksum_list.digester() {
+            let reader = staged.reopen_for_read(&dst_file, dst).await?;
+            digester = Some(crate::checksum_list::read_back(&reader, None, fresh, dst).await?);
         }
+    }
 
-        total_copied += bytes_written as u64;
-        offset += bytes_written as u64;
-
-        tracing::debug!(
-            "compio read_at/write_at: copied {} bytes, total: {}/{} (buffer reused)",
-            bytes_written,
-            total_copied,
-            file_size
-        );
+    // Holes at the end are not written: extend the file to its full size
+    if sparse {
+        set_file_len(&dst_file, file_size)?;
     }
 
     // Sync the destination file to disk if requested (matches rsync --fsync)
@@ -427,6 +1200,10 @@ async fn copy_read_write(
             .map_err(|e| SyncError::FileSystem(format!("Failed to sync destination file: {e}")))?;
     }
 
+    if let Some(checksums) = checksums {
+        verify_written(checksums, &staged, &dst_file, dst).await?;
+    }
+
     // Preserve file metadata using the metadata module
     preserve_file_metadata(
         &src_file,
@@ -437,6 +1214,10 @@ async fn copy_read_write(
         metadata_config,
     )
     .await?;
+    staged.commit(&dst_file, dst).await?;
+    if let Some(digester) = digester {
+        checksum_list.record(dst, digester);
+    }
 
     tracing::debug!(
         "compio read_at/write_at: successfully copied {} bytes",
@@ -445,6 +1226,210 @@ async fn copy_read_write(
     Ok(())
 }
 
+/// A read/write copy of one file, a window of chunks at a time
+///
+/// Reading and writing one buffer in turn leaves the device idle half the
+/// time. Each chunk is instead read and then written by its own future, and
+/// the pool's `chunks_in_flight()` of them run at once, so chunk k+2 is
+/// being read while chunk k is written. Finished chunks are taken in file
+/// order, and their buffers reused for the next ones.
+struct CopyChunks<'a> {
+    /// Source file
+    src: &'a File,
+    /// Destination file
+    dst: &'a File,
+    /// Buffers, and how many chunks to keep in flight
+    pool: &'a crate::pipelines::BufferPool,
+    /// Copy only data segments and leave all-zero chunks as holes
+    sparse: bool,
+    /// Preallocate each data segment (and punch the all-zero chunks)
+    preallocate_segments: bool,
+    /// `fallocate` mode for the data segments
+    fallocate_mode: u32,
+}
+
+/// One chunk of a `CopyChunks` copy, done
+struct CopiedChunk {
+    /// File offset
+    offset: u64,
+    /// Bytes asked for
+    requested: usize,
+    /// Bytes written (0 for an all-zero chunk left as a hole)
+    written: usize,
+    /// The data read
+    buffer: CopyBuffer,
+}
+
+impl CopyChunks<'_> {
+    /// Copy the bytes from `start` to `file_size`
+    ///
+    /// Returns the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a read, write or preallocation fails, or a write
+    /// is short.
+    #[allow(clippy::future_not_send)]
+    async fn copy(
+        &self,
+        start: u64,
+        file_size: u64,
+        checksums: &mut Option<ChunkChecksums>,
+        digester: &mut Option<Digester>,
+        events: &FileEvents,
+    ) -> Result<u64> {
+        let buffer_size = self.pool.buffer_size() as u64;
+        let mut in_flight = FuturesOrdered::new();
+        let mut spare = Vec::new();
+        let mut offset = start;
+        let mut segment_end = if self.sparse { 0 } else { file_size };
+        let mut end_of_file = false;
+        let mut written = 0;
+
+        loop {
+            while !end_of_file
+                && offset < file_size
+                && in_flight.len() < self.pool.chunks_in_flight()
+            {
+                if offset >= segment_end {
+                    // Only with --sparse: jump over the hole to the next data segment
+                    let Some((data, end)) = next_data_segment(self.src, offset, file_size) else {
+                        events.chunk(offset, file_size - offset);
+                        if let Some(digester) = digester.as_mut() {
+                            digester.update_zeros(file_size - offset);
+                        }
+                        offset = file_size;
+                        break;
+                    };
+                    events.chunk(offset, data - offset);
+                    if let Some(digester) = digester.as_mut() {
+                        digester.update_zeros(data - offset);
+                    }
+                    (offset, segment_end) = (data, end);
+                    if self.preallocate_segments {
+                        self.preallocate(data, end - data).await?;
+                    }
+                }
+                #[allow(clippy::cast_possible_truncation)] // At most buffer_size
+                let len = (segment_end - offset).min(buffer_size) as usize;
+                let buffer = spare
+                    .pop()
+                    .unwrap_or_else(|| CopyBuffer::from_pool(self.pool));
+                in_flight.push_back(self.chunk(buffer, offset, len, events));
+                offset += len as u64;
+            }
+
+            let Some(chunk) = in_flight.next().await else {
+                break;
+            };
+            let chunk = chunk?;
+            let read = chunk.buffer.len();
+            if let Some(checksums) = checksums.as_mut() {
+                checksums.record(chunk.offset, &chunk.buffer);
+            }
+            if let Some(hasher) = digester.as_mut() {
+                hasher.update(&chunk.buffer);
+            }
+            events.chunk(chunk.offset, read as u64);
+            written += chunk.written as u64;
+
+            if read == 0 {
+                // The source is shorter than it was: stop at its end
+                end_of_file = true;
+            } else if read < chunk.requested {
+                // A short read: the rest of the chunk goes after those queued,
+                // out of file order for the digester (the caller reads back)
+                *digester = None;
+                let rest = chunk.requested - read;
+                in_flight.push_back(self.chunk(
+                    chunk.buffer,
+                    chunk.offset + read as u64,
+                    rest,
+                    events,
+                ));
+                continue;
+            }
+            spare.push(chunk.buffer);
+        }
+
+        for buffer in spare {
+            buffer.put(self.pool);
+        }
+        tracing::debug!("compio read_at/write_at: copied {written} bytes");
+        Ok(written)
+    }
+
+    /// Read `len` bytes at `offset` into `buffer`, and write them unless
+    /// they are all zeros (--sparse)
+    #[allow(clippy::future_not_send)]
+    fn chunk(
+        &self,
+        mut buffer: CopyBuffer,
+        offset: u64,
+        len: usize,
+        events: &FileEvents,
+    ) -> impl std::future::Future<Output = Result<CopiedChunk>> {
+        // Clones share the descriptors, so the future borrows nothing
+        let src = self.src.clone();
+        let mut dst = self.dst.clone();
+        let events = events.clone();
+        let (sparse, punch) = (self.sparse, self.preallocate_segments);
+        async move {
+            buffer.set_len(len);
+            let read_result = buffer.read_at(&src, offset, events.metrics()).await;
+            let read = read_result
+                .0
+                .map_err(|e| SyncError::IoUring(format!("compio read_at operation failed: {e}")))?;
+            let buffer = read_result.1;
+            let mut chunk = CopiedChunk {
+                offset,
+                requested: len,
+                written: 0,
+                buffer,
+            };
+            if read == 0 {
+                return Ok(chunk);
+            }
+
+            if sparse && chunk.buffer.iter().all(|&byte| byte == 0) {
+                // Leave a hole instead of writing zeros; punch it if preallocated
+                if punch {
+                    punch_hole(&dst, offset, read as u64).await?;
+                }
+                return Ok(chunk);
+            }
+
+            let write_result = chunk
+                .buffer
+                .write_at(&mut dst, offset, events.metrics())
+                .await;
+            let written = write_result.0.map_err(|e| {
+                SyncError::IoUring(format!("compio write_at operation failed: {e}"))
+            })?;
+            chunk.buffer = write_result.1;
+            if written != read {
+                return Err(SyncError::CopyFailed(format!(
+                    "Write size mismatch at offset {offset}: expected {read}, got {written}"
+                )));
+            }
+            chunk.written = written;
+            Ok(chunk)
+        }
+    }
+
+    /// Preallocate `len` bytes of the destination at `offset`
+    #[allow(clippy::future_not_send)]
+    async fn preallocate(&self, offset: u64, len: u64) -> Result<()> {
+        use compio_fs_extended::{ExtendedFile, Fallocate};
+        ExtendedFile::from_ref(self.dst)
+            .fallocate(offset, len, self.fallocate_mode)
+            .await
+            .map_err(|e| {
+                SyncError::FileSystem(format!("Failed to preallocate destination file: {e}"))
+            })
+    }
+}
+
 /// Copy a file using parallel recursive binary splitting
 ///
 /// This function splits large files into regions recursively and copies them
@@ -470,6 +1455,7 @@ async fn copy_read_write_parallel(
     dst: &Path, // Only for error messages
     metadata_config: &MetadataConfig,
     parallel_config: &ParallelCopyConfig,
+    devices: Option<DevicePair>,
     file_size: u64,
     dispatcher: &'static Dispatcher,
     src_metadata: &compio_fs_extended::FileMetadata,
@@ -477,6 +1463,7 @@ async fn copy_read_write_parallel(
     src_filename: &std::ffi::OsStr,
     dst_parent_dir: &compio_fs_extended::DirectoryFd,
     dst_filename: &std::ffi::OsStr,
+    events: &FileEvents,
 ) -> Result<()> {
     let max_depth = parallel_config.max_depth;
     let max_tasks = 1 << max_depth; // 2^max_depth
@@ -497,34 +1484,63 @@ async fn copy_read_write_parallel(
         .open_file_at(src_filename, true, false, false, false)
         .await
         .map_err(|e| {
-            SyncError::FileSystem(format!(
-                "Failed to open source file {} via dirfd: {e}",
-                src.display()
-            ))
+            ErrorContext::new("openat source")
+                .source(src)
+                .destination(dst)
+                .dirfd(src_parent_dir.path())
+                .cause(&e)
+                .file_system()
         })?;
 
-    // 3. Open destination file via DirectoryFd (TOCTOU-safe, io_uring-ready)
-    let dst_file = dst_parent_dir
-        .open_file_at(dst_filename, false, true, true, true)
-        .await
-        .map_err(|e| {
-            SyncError::FileSystem(format!(
-                "Failed to create destination file {} via dirfd: {e}",
-                dst.display()
-            ))
-        })?;
+    // 3. Open destination file via DirectoryFd (TOCTOU-safe, io_uring-ready),
+    // anonymously or under a temporary name with --atomic-create or
+    // --delay-updates
+    let (dst_file, staged) =
+        StagedFile::create_for(dst_parent_dir, dst_filename, metadata_config, dst).await?;
+
+    // 4. Server-side copy (NFS 4.2 / SMB3) makes splitting the file pointless
+    let offloaded = crate::offload::try_server_side_copy(
+        &src_file,
+        &dst_file,
+        file_size,
+        &metadata_config.run.offload,
+    )
+    .await?;
+
+    // 5. Where the source's data is, so regions follow its extents and its
+    // holes are neither read nor written
+    let mut data = if offloaded {
+        Vec::new()
+    } else {
+        data_ranges(&src_file, file_size).await
+    };
 
-    // 4. CRITICAL: fallocate the entire file first to prevent fragmentation
-    // and allow parallel writes without conflicts
-    if file_size > 0 {
+    // 6. CRITICAL: fallocate the data first to prevent fragmentation and
+    // allow parallel writes without conflicts
+    if file_size > 0 && !offloaded {
         use compio_fs_extended::{ExtendedFile, Fallocate};
 
         let extended_dst = ExtendedFile::from_ref(&dst_file);
 
-        // Preallocate destination file space
-        extended_dst.fallocate(0, file_size, 0).await.map_err(|e| {
-            SyncError::FileSystem(format!("Failed to preallocate destination file: {e}"))
-        })?;
+        // Preallocate destination file space, unless the tuning profile says
+        // preallocation is wasted on this filesystem
+        if metadata_config.run.tuning.fallocate {
+            for range in &data {
+                extended_dst
+                    .fallocate(range.start, range.end - range.start, 0)
+                    .await
+                    .map_err(|e| {
+                        SyncError::FileSystem(format!(
+                            "Failed to preallocate destination file: {e}"
+                        ))
+                    })?;
+            }
+        }
+
+        // A hole at the end is not written: give the file its full size
+        if data.last().is_none_or(|last| last.end < file_size) {
+            set_file_len(&dst_file, file_size)?;
+        }
 
         // Apply fadvise hints (Linux only - io_uring optimization)
         #[cfg(target_os = "linux")]
@@ -563,47 +1579,77 @@ async fn copy_read_write_parallel(
         }
     }
 
-    // 5. Calculate all regions upfront (iterative, not recursive)
-    let chunk_size = parallel_config.chunk_size_bytes();
-    let num_tasks = 1 << max_depth; // 2^max_depth
-    let region_size = file_size / num_tasks as u64;
-
-    tracing::info!(
-        "PARALLEL COPY: {} tasks, {} MB per region, chunk={} KB, thread={:?}, multi-threaded={}",
-        num_tasks,
-        region_size / 1_048_576,
-        chunk_size / 1024,
-        std::thread::current().id(),
-        true // Dispatcher is always required now
-    );
+    // 7. Multi-threaded: dispatch to worker threads via dispatcher
+    let mut checksums = metadata_config.verify_direct.map(ChunkChecksums::new);
+    if offloaded {
+        events.chunk(0, file_size);
+    } else {
+        let chunk_size = parallel_config.chunk_size_bytes();
+
+        // Holes are skipped, and count as copied
+        let mut copied_to = 0;
+        for range in data.iter().chain(std::iter::once(&(file_size..file_size))) {
+            if range.start > copied_to {
+                events.chunk(copied_to, range.start - copied_to);
+            }
+            copied_to = range.end;
+        }
 
-    // Multi-threaded: dispatch to worker threads via dispatcher
-    {
-        let mut receivers = Vec::with_capacity(num_tasks);
+        // --parallel-adaptive: until the devices have been timed, the first
+        // chunks are copied one at a time to time them
+        let mut depth = max_depth;
+        if let Some(devices) = devices {
+            let timings = &metadata_config.run.devices;
+            if devices.depth(timings, max_depth).is_none() {
+                checksums = time_first_chunks(
+                    &src_file, &dst_file, &mut data, chunk_size, devices, timings, checksums,
+                    events,
+                )
+                .await?;
+            }
+            depth = devices.depth(timings, max_depth).unwrap_or(max_depth);
+        }
 
-        for task_id in 0..num_tasks {
-            let start = task_id as u64 * region_size;
-            let end = if task_id == num_tasks - 1 {
-                file_size // Last task handles remainder
-            } else {
-                (task_id as u64 + 1) * region_size
-            };
+        // Calculate all regions upfront (iterative, not recursive)
+        let num_tasks = 1 << depth; // 2^depth
+        let regions = plan_regions(&data, num_tasks);
+        let data_size: u64 = data.iter().map(|range| range.end - range.start).sum();
+
+        tracing::info!(
+            "PARALLEL COPY: {} tasks, {} MB of data in {} extents, chunk={} KB, thread={:?}, multi-threaded={}",
+            regions.len(),
+            data_size / 1_048_576,
+            data.len(),
+            chunk_size / 1024,
+            std::thread::current().id(),
+            true // Dispatcher is always required now
+        );
 
-            // Align to page boundaries (except first and last)
-            let start_aligned = if task_id > 0 {
-                align_to_page(start, HUGE_PAGE_SIZE)
-            } else {
-                start
-            };
+        let mut receivers = Vec::with_capacity(regions.len());
 
+        for ranges in regions {
             // Clone file handles for this task
             let src = src_file.clone();
             let mut dst = dst_file.clone();
+            let mut checksums = metadata_config.verify_direct.map(ChunkChecksums::new);
+            let events = events.clone();
 
             // Dispatch to worker thread - each gets its own io_uring instance
             let receiver = dispatcher
                 .dispatch(move || async move {
-                    copy_region_sequential(&src, &mut dst, start_aligned, end, chunk_size).await
+                    for range in ranges {
+                        checksums = copy_region_sequential(
+                            &src,
+                            &mut dst,
+                            range.start,
+                            range.end,
+                            chunk_size,
+                            checksums,
+                            &events,
+                        )
+                        .await?;
+                    }
+                    Ok(checksums)
                 })
                 .map_err(|e| {
                     SyncError::CopyFailed(format!("Failed to dispatch parallel copy task: {e:?}"))
@@ -629,19 +1675,25 @@ async fn copy_read_write_parallel(
             .collect();
 
         while let Some(result) = futures.next().await {
-            result?; // Fail fast on first error
+            // Fail fast on first error
+            if let (Some(all), Some(region)) = (checksums.as_mut(), result?) {
+                all.extend(region);
+            }
         }
     }
 
-    // 7. Sync all data to disk if requested (matches rsync --fsync)
+    // 8. Sync all data to disk if requested (matches rsync --fsync)
     if metadata_config.fsync {
         dst_file
             .sync_all()
             .await
             .map_err(|e| SyncError::FileSystem(format!("Failed to sync destination file: {e}")))?;
     }
+    if let Some(checksums) = checksums {
+        verify_written(checksums, &staged, &dst_file, dst).await?;
+    }
 
-    // 8. Preserve file metadata
+    // 9. Preserve file metadata
     preserve_file_metadata(
         &src_file,
         &dst_file,
@@ -651,11 +1703,145 @@ async fn copy_read_write_parallel(
         metadata_config,
     )
     .await?;
+    staged.commit(&dst_file, dst).await?;
 
     tracing::info!("Parallel copy completed: {} bytes", file_size);
     Ok(())
 }
 
+/// Copy the first `SAMPLES` chunks of `data` one at a time, timing each for
+/// `devices` in `timings` (`--parallel-adaptive`), and drop them from `data`
+///
+/// # Errors
+///
+/// Returns an error if a chunk cannot be read or written.
+#[allow(clippy::future_not_send)]
+#[allow(clippy::too_many_arguments)]
+async fn time_first_chunks(
+    src: &File,
+    dst: &File,
+    data: &mut Vec<Range<u64>>,
+    chunk_size: usize,
+    devices: DevicePair,
+    timings: &DeviceTimings,
+    mut checksums: Option<ChunkChecksums>,
+    events: &FileEvents,
+) -> Result<Option<ChunkChecksums>> {
+    let mut dst = dst.clone();
+    for _ in 0..crate::device::SAMPLES {
+        let Some(first) = data.first_mut() else {
+            break;
+        };
+        let end = first.end.min(first.start + chunk_size as u64);
+        let started = Instant::now();
+        checksums = copy_region_sequential(
+            src,
+            &mut dst,
+            first.start,
+            end,
+            chunk_size,
+            checksums,
+            events,
+        )
+        .await?;
+        devices.record(timings, started.elapsed(), end - first.start);
+        first.start = end;
+        if first.is_empty() {
+            data.remove(0);
+        }
+    }
+    Ok(checksums)
+}
+
+/// The ranges of the source holding data, in file order, for splitting a
+/// parallel copy
+///
+/// Taken from its extent map (`compio_fs_extended::extents`); the whole file
+/// where the filesystem has none.
+#[allow(clippy::future_not_send)]
+async fn data_ranges(src: &File, file_size: u64) -> Vec<Range<u64>> {
+    match compio_fs_extended::extents::extent_map(src, file_size).await {
+        Ok(extents) => data_ranges_from(&extents, file_size),
+        Err(e) => {
+            tracing::debug!("No extent map ({e}), splitting the whole file");
+            vec![0..file_size]
+        }
+    }
+}
+
+/// The data ranges of a `file_size`-byte file with these `extents`: merged
+/// where they touch, clipped to the file, without unwritten extents (which
+/// read as zeros)
+///
+/// An empty map of a non-empty file is not trusted (the whole file is
+/// copied): a filesystem may answer `FIEMAP` without tracking extents.
+fn data_ranges_from(
+    extents: &[compio_fs_extended::extents::Extent],
+    file_size: u64,
+) -> Vec<Range<u64>> {
+    if extents.is_empty() {
+        return if file_size > 0 {
+            vec![0..file_size]
+        } else {
+            Vec::new()
+        };
+    }
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for extent in extents.iter().filter(|extent| !extent.is_unwritten()) {
+        let (start, end) = (extent.offset.min(file_size), extent.end().min(file_size));
+        match ranges.last_mut() {
+            _ if start >= end => {}
+            Some(last) if start <= last.end => last.end = last.end.max(end),
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+/// Split `data` into at most `tasks` regions of about equal size, each a
+/// list of ranges copied in turn by one task
+///
+/// Regions end where a range ends when they can; a range split between two
+/// regions is cut at a huge page boundary when it holds one, and the next
+/// region makes up what the cut left short.
+pub(crate) fn plan_regions(data: &[Range<u64>], tasks: usize) -> Vec<Vec<Range<u64>>> {
+    let total: u64 = data.iter().map(|range| range.end - range.start).sum();
+    let target = total.div_ceil(tasks.max(1) as u64).max(1);
+    let mut regions: Vec<Vec<Range<u64>>> = vec![Vec::new()];
+    // Data bytes in the regions so far
+    let mut planned = 0;
+    for range in data {
+        let mut start = range.start;
+        while start < range.end {
+            // Region k (counting from 1) ends after k * target data bytes
+            let wanted = (target * regions.len() as u64).saturating_sub(planned);
+            if wanted == 0 {
+                regions.push(Vec::new());
+                continue;
+            }
+            let mut end = range.end;
+            let cut = end - start > wanted;
+            if cut {
+                let aligned = align_to_page(start + wanted, HUGE_PAGE_SIZE);
+                end = if aligned > start {
+                    aligned
+                } else {
+                    start + wanted
+                };
+            }
+            if let Some(region) = regions.last_mut() {
+                region.push(start..end);
+            }
+            planned += end - start;
+            start = end;
+            if cut {
+                regions.push(Vec::new());
+            }
+        }
+    }
+    regions
+}
+
 /// Copy a region sequentially
 ///
 /// This function copies a contiguous region of a file using sequential
@@ -668,14 +1854,21 @@ async fn copy_read_write_parallel(
 /// * `start` - Starting byte offset
 /// * `end` - Ending byte offset (exclusive)
 /// * `chunk_size` - Size of chunks for read/write operations
+/// * `checksums` - Sampled chunk checksums to record (`--verify-direct`)
+/// * `events` - Publishes a `ChunkCopied` event per chunk
+///
+/// Returns the recorded checksums for the final read-back.
 #[allow(clippy::future_not_send)]
+#[allow(clippy::too_many_arguments)]
 async fn copy_region_sequential(
     src: &File,
     dst: &mut File,
     start: u64,
     end: u64,
     chunk_size: usize,
-) -> Result<()> {
+    mut checksums: Option<ChunkChecksums>,
+    events: &FileEvents,
+) -> Result<Option<ChunkChecksums>> {
     tracing::debug!(
         "copy_region_sequential: start={} MB, end={} MB, thread={:?}",
         start / 1_048_576,
@@ -684,46 +1877,69 @@ async fn copy_region_sequential(
     );
 
     let mut offset = start;
+    // One buffer for the whole region, registered with this worker's ring
+    // when possible
+    let mut buffer = CopyBuffer::with_size(chunk_size);
 
     while offset < end {
         let remaining = end - offset;
         #[allow(clippy::cast_possible_truncation)]
         let to_read = remaining.min(chunk_size as u64) as usize;
 
-        // Allocate buffer sized to what we actually need to read
+        // Read only what the region still needs
         // This prevents reading past the region boundary in parallel execution
-        let buffer = vec![0u8; to_read];
+        buffer.set_len(to_read);
 
         // Read from source at this offset
-        let read_result = src.read_at(buffer, offset).await;
+        let read_result = buffer.read_at(src, offset, events.metrics()).await;
         let bytes_read = read_result
             .0
             .map_err(|e| SyncError::IoUring(format!("read_at failed at offset {offset}: {e}")))?;
-        let mut buffer = read_result.1;
+        buffer = read_result.1;
 
         if bytes_read == 0 {
             break;
         }
 
         // Write to destination at same offset
-        buffer.truncate(bytes_read);
-        let write_result = dst.write_at(buffer, offset).await;
+        buffer.set_len(bytes_read);
+        let write_result = buffer.write_at(dst, offset, events.metrics()).await;
         let bytes_written = write_result
             .0
             .map_err(|e| SyncError::IoUring(format!("write_at failed at offset {offset}: {e}")))?;
-        // Note: We intentionally don't reuse the buffer here (write_result.1)
-        // A new buffer is allocated on each iteration to ensure correct sizing
+        buffer = write_result.1;
 
         if bytes_written != bytes_read {
             return Err(SyncError::CopyFailed(format!(
                 "Write size mismatch at offset {offset}: read {bytes_read}, wrote {bytes_written}"
             )));
         }
+        if let Some(checksums) = checksums.as_mut() {
+            checksums.record(offset, &buffer);
+        }
+        events.chunk(offset, bytes_written as u64);
 
         offset += bytes_written as u64;
     }
 
-    Ok(())
+    Ok(checksums)
+}
+
+/// Read back the sampled chunks of a just-written file (`--verify-direct`)
+///
+/// # Errors
+///
+/// Returns an error if the destination cannot be reopened for reading or any
+/// sampled chunk differs from what was written.
+#[allow(clippy::future_not_send)]
+async fn verify_written(
+    checksums: ChunkChecksums,
+    staged: &StagedFile,
+    dst_file: &File,
+    dst: &Path,
+) -> Result<()> {
+    let reader = staged.reopen_for_read(dst_file, dst).await?;
+    checksums.verify(&reader, dst).await
 }
 
 /// Align offset to page boundary (round down)
@@ -744,9 +1960,11 @@ const fn align_to_page(offset: u64, page_size: u64) -> u64 {
 mod tests {
     use super::*;
     use crate::cli::{
-        Args, ConcurrencyConfig, CopyMethod, IoConfig, OutputConfig, ParallelCopyConfig, PathConfig,
+        Args, ConcurrencyConfig, CopyMethod, IoConfig, OutputConfig, ParallelCopyConfig,
+        PathConfig, RemoteConfig, TraversalConfig,
     };
-    use crate::metadata::MetadataConfig;
+    use crate::metadata::{MetadataConfig, SpecialFilePolicy, UnprivilegedOwnership};
+    use crate::run_state::RunState;
     use std::fs;
     use std::num::NonZeroUsize;
     use std::os::unix::fs::PermissionsExt;
@@ -760,6 +1978,7 @@ mod tests {
             max_depth: 0, // 0 = disabled
             min_file_size_mb: 128,
             chunk_size_mb: 2,
+            adaptive: false,
         }
     }
 
@@ -793,6 +2012,7 @@ mod tests {
             dst,
             metadata_config,
             parallel_config,
+            &CopyMethod::Auto,
             dispatcher_static,
             &src_metadata,
             &src_parent_dir,
@@ -809,17 +2029,25 @@ mod tests {
             paths: PathConfig {
                 source: PathBuf::from("/test/source"),
                 destination: PathBuf::from("/test/dest"),
+                extra_destinations: Vec::new(),
             },
             io: IoConfig {
                 queue_depth: 4096,
                 buffer_size_kb: NonZeroUsize::new(64),
                 copy_method: CopyMethod::Auto,
+                tune: None,
+                show_tuning: false,
                 cpu_count: 1,
                 parallel: disabled_parallel_config(),
             },
             concurrency: ConcurrencyConfig {
                 max_files_in_flight: 1024,
                 no_adaptive_concurrency: false,
+                large_file_threshold_mb: 64,
+                large_files_in_flight: 8,
+                hardlink_spill_dir: None,
+                hardlink_memory_entries: 4_000_000,
+                no_lock: false,
             },
             metadata: MetadataConfig {
                 archive: true, // Enable archive mode for full metadata preservation
@@ -829,8 +2057,19 @@ mod tests {
                 times: false,
                 group: false,
                 owner: false,
+                no_owner: false,
+                no_group: false,
                 devices: false,
+                specials: false,
+                devices_and_specials: false,
                 fsync: false,
+                syncfs: false,
+                verify_direct: None,
+                atomic_create: None,
+                partial: false,
+                delay_updates: false,
+                special_files: SpecialFilePolicy::Skip,
+                unprivileged_ownership: UnprivilegedOwnership::Permitted,
                 xattrs: false,
                 acls: false,
                 hard_links: false,
@@ -838,13 +2077,33 @@ mod tests {
                 crtimes: false,
                 preserve_xattr: false,
                 preserve_acl: false,
+                omit_dir_times: false,
+                omit_link_times: false,
+                symlink_rewrite: Vec::new(),
+                sparse: false,
+                preserve_flags: false,
+                update_immutable: false,
+                overlay_layer: false,
+                run: Default::default(),
             },
+            traversal: TraversalConfig::default(),
+            remote: RemoteConfig::default(),
             output: OutputConfig {
                 dry_run: false,
                 progress: false,
+                interactive: false,
                 verbose: 0,
                 quiet: false,
+                json: false,
+                shadow_rsync: false,
+                to_tar: false,
+                from_tar: false,
                 pirate: false,
+                emit_checksums: None,
+                checksum_algorithm: crate::checksum_list::ChecksumAlgorithm::Blake3,
+                metrics_listen: None,
+                metrics_textfile: None,
+                debug_fd_audit: false,
             },
         }
     }
@@ -1049,6 +2308,105 @@ mod tests {
         );
     }
 
+    #[compio::test]
+    async fn test_preserve_metadata_pre_epoch_and_post_2038() {
+        // Requirement: Timestamps before 1970 and after 2038 are preserved
+        // exactly instead of being clamped or panicking
+        use std::time::UNIX_EPOCH;
+
+        let temp_dir = TempDir::new().unwrap();
+        let src_path = temp_dir.path().join("old.txt");
+        let dst_path = temp_dir.path().join("old_copy.txt");
+        fs::write(&src_path, "from an old archive").unwrap();
+
+        let modified = UNIX_EPOCH - Duration::from_millis(1500);
+        let accessed = UNIX_EPOCH + Duration::new(4_102_444_800, 250_000_000); // 2100-01-01
+        fs::File::options()
+            .write(true)
+            .open(&src_path)
+            .unwrap()
+            .set_times(
+                fs::FileTimes::new()
+                    .set_accessed(accessed)
+                    .set_modified(modified),
+            )
+            .unwrap();
+
+        let args = create_test_args_with_archive();
+        copy_file_test_helper(
+            &src_path,
+            &dst_path,
+            &args.metadata,
+            &disabled_parallel_config(),
+        )
+        .await
+        .unwrap();
+
+        let dst_metadata = fs::metadata(&dst_path).unwrap();
+        assert_eq!(dst_metadata.modified().unwrap(), modified);
+        assert_eq!(dst_metadata.accessed().unwrap(), accessed);
+    }
+
+    #[compio::test]
+    async fn test_partial_copy_resumes() {
+        // Requirement: With --partial, a partial file left by an interrupted
+        // run is continued from its length instead of copied again
+        let temp_dir = TempDir::new().unwrap();
+        let src_path = temp_dir.path().join("big.bin");
+        let dst_path = temp_dir.path().join("big_copy.bin");
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        fs::write(&src_path, &data).unwrap();
+
+        // Simulate the interrupted run: the first 100 000 bytes are there
+        // (marked with a different byte, to see they are not copied again)
+        let dir_fd = compio_fs_extended::DirectoryFd::open(temp_dir.path())
+            .await
+            .unwrap();
+        let source = dir_fd
+            .statx_full(std::ffi::OsStr::new("big.bin"))
+            .await
+            .unwrap();
+        let (mut partial, staged, _) = StagedFile::create_partial(
+            &dir_fd,
+            std::ffi::OsStr::new("big_copy.bin"),
+            &source,
+            &dst_path,
+        )
+        .await
+        .unwrap();
+        let prefix = vec![0xAAu8; 100_000];
+        compio::io::AsyncWriteAtExt::write_all_at(&mut partial, prefix, 0)
+            .await
+            .0
+            .unwrap();
+        drop((partial, staged));
+
+        let mut args = create_test_args_with_archive();
+        args.metadata.partial = true;
+        copy_file_test_helper(
+            &src_path,
+            &dst_path,
+            &args.metadata,
+            &disabled_parallel_config(),
+        )
+        .await
+        .unwrap();
+
+        let copied = fs::read(&dst_path).unwrap();
+        assert_eq!(copied.len(), data.len());
+        assert_eq!(copied[100_000..], data[100_000..]);
+        assert!(!temp_dir
+            .path()
+            .join(".arsync.partial.big_copy.bin")
+            .exists());
+        if copied[..100_000] != data[..100_000] {
+            // Resumed (filesystems without user xattrs start over instead)
+            assert!(copied[..100_000].iter().all(|&byte| byte == 0xAA));
+        }
+    }
+
     #[compio::test]
     async fn test_preserve_metadata_large_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -1229,4 +2587,133 @@ mod tests {
             "Large file sizes should match"
         );
     }
+
+    #[compio::test]
+    async fn test_copy_chunks_in_flight() {
+        // Requirement: With several chunks in flight every byte is copied
+        // once and in place, and --sparse still leaves zero chunks as holes
+        let temp_dir = TempDir::new().unwrap();
+        let src_path = temp_dir.path().join("source.bin");
+        let mut data: Vec<u8> = (0..100_000u32)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        data[40_960..49_152].fill(0);
+        fs::write(&src_path, &data).unwrap();
+        let pool = crate::pipelines::BufferPool::new(4096, 8).in_flight(3);
+
+        for sparse in [false, true] {
+            let dst_path = temp_dir.path().join(format!("copy-{sparse}.bin"));
+            let src = File::open(&src_path).await.unwrap();
+            let dst = compio::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(&dst_path)
+                .await
+                .unwrap();
+            let chunks = CopyChunks {
+                src: &src,
+                dst: &dst,
+                pool: &pool,
+                sparse,
+                preallocate_segments: false,
+                fallocate_mode: 0,
+            };
+            let mut checksums = Some(ChunkChecksums::new(100));
+            let mut digester = Some(Digester::new(
+                crate::checksum_list::ChecksumAlgorithm::Blake3,
+            ));
+            let events = EVENTS.file_started(&RunState::default(), &src_path, data.len() as u64);
+            let written = chunks
+                .copy(0, data.len() as u64, &mut checksums, &mut digester, &events)
+                .await
+                .unwrap();
+            set_file_len(&dst, data.len() as u64).unwrap();
+            // All-zero chunks left as holes are hashed like the others
+            let mut whole = Digester::new(crate::checksum_list::ChecksumAlgorithm::Blake3);
+            whole.update(&data);
+            assert_eq!(digester.unwrap().finish(), whole.finish());
+
+            let expected = if sparse {
+                data.len() - 8192
+            } else {
+                data.len()
+            };
+            assert_eq!(written, expected as u64);
+            assert_eq!(fs::read(&dst_path).unwrap(), data);
+            checksums.unwrap().verify(&dst, &dst_path).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_splice_copies_whole_file() {
+        // Requirement: A splice copy moves every byte, in several pipe loads
+        use std::os::unix::io::AsRawFd;
+
+        let temp_dir = TempDir::new().unwrap();
+        let data: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| (i % 251) as u8).collect();
+        let src_path = temp_dir.path().join("source.bin");
+        let dst_path = temp_dir.path().join("destination.bin");
+        fs::write(&src_path, &data).unwrap();
+        let src = fs::File::open(&src_path).unwrap();
+        let dst = fs::File::create(&dst_path).unwrap();
+
+        let len = data.len() as u64;
+        assert!(splice_all(src.as_raw_fd(), dst.as_raw_fd(), len).unwrap());
+        assert_eq!(fs::read(&dst_path).unwrap(), data);
+    }
+
+    #[test]
+    fn test_data_ranges_from_extents() {
+        // Requirement: Touching extents merge, unwritten extents are holes,
+        // extents are clipped to the file, and an empty map means the whole
+        // file
+        use compio_fs_extended::extents::{Extent, FIEMAP_EXTENT_LAST, FIEMAP_EXTENT_UNWRITTEN};
+        let extent = |offset, length, flags| Extent {
+            offset,
+            length,
+            flags,
+        };
+        let extents = [
+            extent(0, 4096, 0),
+            extent(4096, 4096, 0),
+            extent(16_384, 4096, FIEMAP_EXTENT_UNWRITTEN),
+            extent(32_768, 8192, FIEMAP_EXTENT_LAST),
+        ];
+        assert_eq!(
+            data_ranges_from(&extents, 36_000),
+            vec![0..8192, 32_768..36_000]
+        );
+        assert_eq!(data_ranges_from(&[], 10), vec![0..10]);
+        assert!(data_ranges_from(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn test_plan_regions_balanced_and_aligned() {
+        // Requirement: Regions cover every data byte once, in order, number
+        // at most the tasks, and are cut inside a range at huge page
+        // boundaries
+        const MB: u64 = 1 << 20;
+        let data = [0..3 * MB, 5 * MB..6 * MB, 10 * MB..20 * MB + 777];
+        for tasks in [1, 2, 4, 8, 64] {
+            let regions = plan_regions(&data, tasks);
+            assert!(regions.len() <= tasks, "{regions:?}");
+            let covered: Vec<Range<u64>> = regions.iter().flatten().cloned().collect();
+            let mut merged: Vec<Range<u64>> = Vec::new();
+            for range in covered {
+                match merged.last_mut() {
+                    Some(last) if last.end == range.start => last.end = range.end,
+                    _ => merged.push(range),
+                }
+            }
+            assert_eq!(merged, data);
+        }
+
+        let regions = plan_regions(&[0..64 * MB], 4);
+        assert_eq!(regions.len(), 4);
+        for region in &regions[1..] {
+            assert_eq!(region[0].start % HUGE_PAGE_SIZE, 0, "{regions:?}");
+        }
+        assert_eq!(plan_regions(&[], 4), vec![Vec::<Range<u64>>::new()]);
+    }
 }
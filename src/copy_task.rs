@@ -0,0 +1,502 @@
+//! Cancellable, resumable single-file copy with progress reporting
+//!
+//! `CopyTask` is the library-facing counterpart of `copy::copy_file_internal()`
+//! for callers that need control over an individual file while it is being
+//! copied (GUI front-ends, watch mode):
+//!
+//! - **Progress**: a callback receives the number of bytes copied so far after
+//!   every chunk
+//! - **Cancellation**: a cloneable [`CancellationHandle`] stops the copy at the
+//!   next chunk boundary without affecting any other file in the run
+//! - **Resume**: a cancelled copy reports the offset it reached; a new task
+//!   started with [`CopyTask::resume_from`] continues from there without
+//!   truncating the destination
+//!
+//! Like the rest of the copy path, files are opened via `DirectoryFd` relative
+//! to their parent directories (TOCTOU-safe).
+//!
+//! # Usage
+//!
+//! ```rust,ignore
+//! use arsync::copy_task::{CopyOutcome, CopyTask};
+//!
+//! let task = CopyTask::new(src, dst, &metadata_config)
+//!     .with_progress(|p| println!("{}/{} bytes", p.bytes_copied, p.total_bytes));
+//! let cancel = task.cancellation_handle();
+//!
+//! match task.run().await? {
+//!     CopyOutcome::Completed { bytes_copied } => println!("done: {bytes_copied}"),
+//!     CopyOutcome::Cancelled { resume_offset } => {
+//!         // Later: CopyTask::new(src, dst, &metadata_config).resume_from(resume_offset)
+//!     }
+//! }
+//! ```
+
+#![allow(dead_code)] // Library-facing API; the CLI binary does not drive CopyTask directly
+
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::metadata::{preserve_file_metadata, MetadataConfig};
+use compio::io::{AsyncReadAt, AsyncWriteAt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Chunk size for the read/write loop (matches `copy::copy_read_write`)
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Progress snapshot reported after each chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyProgress {
+    /// Bytes of the file that are now present in the destination
+    /// (includes the resumed prefix, if any)
+    pub bytes_copied: u64,
+    /// Total size of the source file
+    pub total_bytes: u64,
+}
+
+/// Callback invoked with progress updates
+pub type ProgressCallback = Arc<dyn Fn(CopyProgress) + Send + Sync>;
+
+/// Cloneable handle used to cancel a running `CopyTask` or directory copy
+///
+/// Cancellation is cooperative: a `CopyTask` checks the flag between chunks,
+/// so at most one chunk is written after `cancel()` is called; a directory
+/// copy checks it before starting each entry (see `interrupt`).
+#[derive(Debug, Clone, Default)]
+pub struct CancellationHandle {
+    /// Shared cancellation flag
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationHandle {
+    /// Create a new, not-yet-cancelled handle
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Check whether cancellation was requested
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Result of running a `CopyTask`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyOutcome {
+    /// The whole file was copied and metadata preserved
+    Completed {
+        /// Total bytes present in the destination
+        bytes_copied: u64,
+    },
+    /// The copy was cancelled; the destination is valid up to `resume_offset`
+    Cancelled {
+        /// Offset to pass to [`CopyTask::resume_from`] to continue
+        resume_offset: u64,
+    },
+}
+
+/// A single-file copy that can report progress, be cancelled and be resumed
+pub struct CopyTask {
+    /// Source file path
+    src: PathBuf,
+    /// Destination file path
+    dst: PathBuf,
+    /// Metadata preservation configuration (applied on completion)
+    metadata_config: MetadataConfig,
+    /// Offset to resume from (destination is not truncated when set)
+    resume_from: Option<u64>,
+    /// Optional progress callback
+    progress: Option<ProgressCallback>,
+    /// Cancellation flag shared with handles
+    cancel: CancellationHandle,
+}
+
+impl CopyTask {
+    /// Create a task copying `src` to `dst`
+    #[must_use]
+    pub fn new(src: &Path, dst: &Path, metadata_config: &MetadataConfig) -> Self {
+        Self {
+            src: src.to_path_buf(),
+            dst: dst.to_path_buf(),
+            metadata_config: metadata_config.clone(),
+            resume_from: None,
+            progress: None,
+            cancel: CancellationHandle::new(),
+        }
+    }
+
+    /// Report progress to `callback` after every chunk
+    #[must_use]
+    pub fn with_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(CopyProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Use an existing cancellation handle (e.g. one shared by a batch of tasks)
+    #[must_use]
+    pub fn with_cancellation(mut self, handle: CancellationHandle) -> Self {
+        self.cancel = handle;
+        self
+    }
+
+    /// Continue a previous copy from `offset`
+    ///
+    /// The destination is opened without truncation and bytes before `offset`
+    /// are assumed to already match the source (typically `resume_offset`
+    /// from a previous [`CopyOutcome::Cancelled`]).
+    #[must_use]
+    pub const fn resume_from(mut self, offset: u64) -> Self {
+        self.resume_from = Some(offset);
+        self
+    }
+
+    /// Get a handle that can cancel this task from elsewhere
+    #[must_use]
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.cancel.clone()
+    }
+
+    /// Run the copy to completion or cancellation
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - Either parent directory cannot be opened
+    /// - The source cannot be read or the destination cannot be written
+    /// - The resume offset is beyond the source size or the destination size
+    /// - Metadata preservation fails
+    #[allow(clippy::future_not_send)]
+    pub async fn run(self) -> Result<CopyOutcome> {
+        let src_parent = self.src.parent().unwrap_or_else(|| Path::new("."));
+        let dst_parent = self.dst.parent().unwrap_or_else(|| Path::new("."));
+        let src_filename = self
+            .src
+            .file_name()
+            .ok_or_else(|| SyncError::FileSystem("Source has no filename".to_string()))?;
+        let dst_filename = self
+            .dst
+            .file_name()
+            .ok_or_else(|| SyncError::FileSystem("Destination has no filename".to_string()))?;
+
+        let src_dir = compio_fs_extended::DirectoryFd::open(src_parent)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("open source parent")
+                    .source(&self.src)
+                    .cause(&e)
+                    .file_system()
+            })?;
+        let dst_dir = compio_fs_extended::DirectoryFd::open(dst_parent)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("open destination parent")
+                    .source(&self.src)
+                    .destination(&self.dst)
+                    .cause(&e)
+                    .file_system()
+            })?;
+
+        let src_metadata = src_dir.statx_full(src_filename).await.map_err(|e| {
+            ErrorContext::new("statx")
+                .source(&self.src)
+                .dirfd(src_dir.path())
+                .cause(&e)
+                .file_system()
+        })?;
+        let total_bytes = src_metadata.size;
+
+        let src_file = src_dir
+            .open_file_at(src_filename, true, false, false, false)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("openat source")
+                    .source(&self.src)
+                    .dirfd(src_dir.path())
+                    .cause(&e)
+                    .file_system()
+            })?;
+
+        // Resuming must not truncate what was already written
+        let truncate = self.resume_from.is_none();
+        let mut dst_file = dst_dir
+            .open_file_at(dst_filename, false, true, true, truncate)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("openat destination")
+                    .source(&self.src)
+                    .destination(&self.dst)
+                    .dirfd(dst_dir.path())
+                    .cause(&e)
+                    .file_system()
+            })?;
+
+        let mut offset = self.resume_from.unwrap_or(0);
+        if offset > 0 {
+            let dst_len = dst_file
+                .metadata()
+                .await
+                .map_err(|e| {
+                    ErrorContext::new("fstat destination")
+                        .destination(&self.dst)
+                        .io_cause(&e)
+                        .file_system()
+                })?
+                .len();
+            if offset > total_bytes || offset > dst_len {
+                return Err(SyncError::CopyFailed(format!(
+                    "Cannot resume {} at offset {offset}: source is {total_bytes} bytes, destination is {dst_len} bytes",
+                    self.dst.display()
+                )));
+            }
+        }
+
+        self.report(offset, total_bytes);
+
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        while offset < total_bytes {
+            if self.cancel.is_cancelled() {
+                tracing::debug!(
+                    "Copy of {} cancelled at offset {}",
+                    self.src.display(),
+                    offset
+                );
+                return Ok(CopyOutcome::Cancelled {
+                    resume_offset: offset,
+                });
+            }
+
+            let read_result = src_file.read_at(buffer, offset).await;
+            let bytes_read = read_result.0.map_err(|e| {
+                SyncError::IoUring(format!("read_at failed at offset {offset}: {e}"))
+            })?;
+            buffer = read_result.1;
+            if bytes_read == 0 {
+                break;
+            }
+
+            buffer.truncate(bytes_read);
+            let write_result = dst_file.write_at(buffer, offset).await;
+            let bytes_written = write_result.0.map_err(|e| {
+                SyncError::IoUring(format!("write_at failed at offset {offset}: {e}"))
+            })?;
+            buffer = write_result.1;
+            buffer.resize(CHUNK_SIZE, 0);
+
+            if bytes_written != bytes_read {
+                return Err(SyncError::CopyFailed(format!(
+                    "Write size mismatch: expected {bytes_read}, got {bytes_written}"
+                )));
+            }
+
+            offset += bytes_written as u64;
+            self.report(offset, total_bytes);
+        }
+
+        // A resumed destination may be longer than the source
+        if self.resume_from.is_some() {
+            crate::copy::set_file_len(&dst_file, offset)?;
+        }
+
+        if self.metadata_config.fsync {
+            dst_file.sync_all().await.map_err(|e| {
+                SyncError::FileSystem(format!("Failed to sync destination file: {e}"))
+            })?;
+        }
+
+        preserve_file_metadata(
+            &src_file,
+            &dst_file,
+            &self.dst,
+            src_metadata.accessed,
+            src_metadata.modified,
+            &self.metadata_config,
+        )
+        .await?;
+
+        Ok(CopyOutcome::Completed {
+            bytes_copied: offset,
+        })
+    }
+
+    /// Invoke the progress callback, if any
+    fn report(&self, bytes_copied: u64, total_bytes: u64) {
+        if let Some(callback) = &self.progress {
+            callback(CopyProgress {
+                bytes_copied,
+                total_bytes,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::metadata::{SpecialFilePolicy, UnprivilegedOwnership};
+    use std::sync::atomic::AtomicU64;
+    use tempfile::TempDir;
+
+    fn test_metadata_config() -> MetadataConfig {
+        MetadataConfig {
+            archive: false,
+            recursive: false,
+            links: false,
+            perms: false,
+            times: false,
+            group: false,
+            owner: false,
+            no_owner: false,
+            no_group: false,
+            devices: false,
+            specials: false,
+            devices_and_specials: false,
+            fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
+            xattrs: false,
+            acls: false,
+            hard_links: false,
+            atimes: false,
+            crtimes: false,
+            preserve_xattr: false,
+            preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[compio::test]
+    async fn test_copy_task_completes_with_progress() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dst = temp_dir.path().join("dst.bin");
+        let data = test_data(CHUNK_SIZE * 3 + 17);
+        std::fs::write(&src, &data).unwrap();
+
+        let last = Arc::new(AtomicU64::new(0));
+        let last_clone = Arc::clone(&last);
+        let outcome = CopyTask::new(&src, &dst, &test_metadata_config())
+            .with_progress(move |p| last_clone.store(p.bytes_copied, Ordering::Relaxed))
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome,
+            CopyOutcome::Completed {
+                bytes_copied: data.len() as u64
+            }
+        );
+        assert_eq!(last.load(Ordering::Relaxed), data.len() as u64);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+    }
+
+    #[compio::test]
+    #[allow(clippy::panic)] // panic! is the correct way to fail tests
+    async fn test_copy_task_cancel_then_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dst = temp_dir.path().join("dst.bin");
+        let data = test_data(CHUNK_SIZE * 8);
+        std::fs::write(&src, &data).unwrap();
+
+        // Cancel from the progress callback once two chunks are written
+        let handle = CancellationHandle::new();
+        let cancel = handle.clone();
+        let outcome = CopyTask::new(&src, &dst, &test_metadata_config())
+            .with_cancellation(handle)
+            .with_progress(move |p| {
+                if p.bytes_copied >= (CHUNK_SIZE * 2) as u64 {
+                    cancel.cancel();
+                }
+            })
+            .run()
+            .await
+            .unwrap();
+
+        let CopyOutcome::Cancelled { resume_offset } = outcome else {
+            panic!("expected cancellation, got {outcome:?}");
+        };
+        assert_eq!(resume_offset, (CHUNK_SIZE * 2) as u64);
+
+        let outcome = CopyTask::new(&src, &dst, &test_metadata_config())
+            .resume_from(resume_offset)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            CopyOutcome::Completed {
+                bytes_copied: data.len() as u64
+            }
+        );
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+    }
+
+    #[compio::test]
+    async fn test_copy_task_resume_truncates_longer_destination() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dst = temp_dir.path().join("dst.bin");
+        let data = test_data(CHUNK_SIZE + 100);
+        std::fs::write(&src, &data).unwrap();
+        let mut stale = data[..CHUNK_SIZE].to_vec();
+        stale.extend(vec![0xAA; CHUNK_SIZE * 2]);
+        std::fs::write(&dst, &stale).unwrap();
+
+        let outcome = CopyTask::new(&src, &dst, &test_metadata_config())
+            .resume_from(CHUNK_SIZE as u64)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            CopyOutcome::Completed {
+                bytes_copied: data.len() as u64
+            }
+        );
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+    }
+
+    #[compio::test]
+    async fn test_copy_task_rejects_resume_past_destination() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src.bin");
+        let dst = temp_dir.path().join("dst.bin");
+        std::fs::write(&src, test_data(4096)).unwrap();
+        std::fs::write(&dst, test_data(100)).unwrap();
+
+        let result = CopyTask::new(&src, &dst, &test_metadata_config())
+            .resume_from(2048)
+            .run()
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Updated files put in place together at the end of the run (`--delay-updates`)
+//!
+//! Normally each file appears at its destination as soon as it is copied, so
+//! a reader of the destination during a sync sees some files updated and
+//! others not. With `--delay-updates` every file is written under a
+//! run-stamped temporary name next to its destination (see `temp_files`), on
+//! the destination's filesystem, and its rename is deferred: committing the
+//! file only records it in the run's [`DelayedUpdates`]. Once the traversal
+//! has finished, [`DelayedUpdates::finish`] renames all of them into place in
+//! one pass; if the run failed it removes
+//! them instead, leaving the destination as it was. The staged files of a
+//! crashed run are left for `arsync cleanup`.
+//!
+//! Renaming into a directory changes its modification time, so the times of
+//! the directories renamed into are put back afterwards: the traversal has
+//! already given them the source's.
+//!
+//! Hard links to a staged file are made to its temporary name and deferred
+//! with it. Directories, symlinks and special files are still created as the
+//! traversal reaches them.
+
+use crate::error::{Result, SyncError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs::FileTimes;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tracing::{debug, info, warn};
+
+/// Files staged by one run (see `run_state`), put in place at its end
+#[derive(Debug, Default)]
+pub struct DelayedUpdates {
+    /// Staged files by final path, with their temporary name in the same
+    /// directory
+    pending: Mutex<BTreeMap<PathBuf, OsString>>,
+}
+
+impl DelayedUpdates {
+    /// Take the pending updates, leaving none
+    fn take(&self) -> BTreeMap<PathBuf, OsString> {
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Record that the file for `dst` is written under `temp` in its directory
+    pub fn defer(&self, dst: PathBuf, temp: OsString) {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(dst, temp);
+    }
+
+    /// Where the file for `dst` is staged until the end of the run, if it is
+    #[must_use]
+    pub fn staged(&self, dst: &Path) -> Option<PathBuf> {
+        let pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let temp = pending.get(dst)?;
+        Some(dst.with_file_name(temp))
+    }
+
+    /// Put the staged files in place if `result` is a success, or remove them
+    ///
+    /// Does nothing without staged files, so it can end every run.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `result`, or `SyncError::FileSystem` if some staged
+    /// files cannot be renamed into place (those are removed).
+    pub async fn finish<T>(&self, result: Result<T>) -> Result<T> {
+        let pending = self.take();
+        if pending.is_empty() {
+            return result;
+        }
+        let commit = result.is_ok();
+        let outcome = compio::runtime::spawn_blocking(move || {
+            if commit {
+                rename_all(pending)
+            } else {
+                remove_all(pending);
+                Ok(())
+            }
+        })
+        .await
+        .map_err(|_| SyncError::Internal("Delayed update worker panicked".to_string()))?;
+        outcome?;
+        result
+    }
+}
+
+/// Rename every staged file into place, then restore the times of their
+/// directories
+fn rename_all(pending: BTreeMap<PathBuf, OsString>) -> Result<()> {
+    let dirs: BTreeSet<&Path> = pending.keys().filter_map(|dst| dst.parent()).collect();
+    let times: Vec<(&Path, FileTimes)> = dirs
+        .into_iter()
+        .filter_map(|dir| {
+            let metadata = std::fs::metadata(dir).ok()?;
+            let times = FileTimes::new()
+                .set_accessed(metadata.accessed().ok()?)
+                .set_modified(metadata.modified().ok()?);
+            Some((dir, times))
+        })
+        .collect();
+
+    let mut failed = Vec::new();
+    for (dst, temp) in &pending {
+        let staged = dst.with_file_name(temp);
+        if let Err(e) = std::fs::rename(&staged, dst) {
+            warn!("Cannot put {} in place: {e}", dst.display());
+            let _ = std::fs::remove_file(&staged);
+            failed.push((dst, e));
+        }
+    }
+    for (dir, times) in times {
+        if let Err(e) = std::fs::File::open(dir).and_then(|dir| dir.set_times(times)) {
+            debug!("Cannot restore the times of {}: {e}", dir.display());
+        }
+    }
+
+    info!(
+        "Put {} delayed updates in place",
+        pending.len() - failed.len()
+    );
+    match failed.first() {
+        None => Ok(()),
+        Some((dst, e)) => Err(SyncError::FileSystem(format!(
+            "Failed to put {} of {} delayed updates in place ({}: {e})",
+            failed.len(),
+            pending.len(),
+            dst.display()
+        ))),
+    }
+}
+
+/// Remove every staged file after a failed run
+fn remove_all(pending: BTreeMap<PathBuf, OsString>) {
+    for (dst, temp) in &pending {
+        if let Err(e) = std::fs::remove_file(dst.with_file_name(temp)) {
+            debug!("Cannot remove the staged file for {}: {e}", dst.display());
+        }
+    }
+    info!(
+        "Discarded {} delayed updates after the failed run",
+        pending.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::temp_files::temp_name;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    /// Stage `content` for `dst` in `updates`, as a delayed copy does
+    fn stage(updates: &DelayedUpdates, dst: &Path, content: &str) {
+        let temp = temp_name(dst.file_name().unwrap());
+        std::fs::write(dst.with_file_name(&temp), content).unwrap();
+        updates.defer(dst.to_path_buf(), temp);
+    }
+
+    /// Directory holding `file` with the content "old"
+    fn destination(temp_dir: &TempDir) -> (PathBuf, PathBuf) {
+        let dir = temp_dir.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        let dst = dir.join("file");
+        std::fs::write(&dst, "old").unwrap();
+        (dir, dst)
+    }
+
+    #[compio::test]
+    async fn test_finish_discards_after_failure() {
+        // Requirement: Staged files are removed when the run fails, leaving
+        // the destination as it was
+        let temp_dir = TempDir::new().unwrap();
+        let (dir, dst) = destination(&temp_dir);
+        let updates = DelayedUpdates::default();
+
+        stage(&updates, &dst, "failed");
+        assert!(updates.staged(&dst).unwrap().exists());
+        let failure: Result<()> = Err(SyncError::CopyFailed("run failed".to_string()));
+        assert!(updates.finish(failure).await.is_err());
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "old");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[compio::test]
+    async fn test_finish_commits_after_success() {
+        // Requirement: Staged files replace their destinations when the run
+        // succeeds, and directory times survive the renames
+        let temp_dir = TempDir::new().unwrap();
+        let (dir, dst) = destination(&temp_dir);
+        let updates = DelayedUpdates::default();
+
+        stage(&updates, &dst, "new");
+        let old_time = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000);
+        std::fs::File::open(&dir)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(old_time))
+            .unwrap();
+        assert_eq!(updates.finish(Ok(7)).await.unwrap(), 7);
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "new");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(std::fs::metadata(&dir).unwrap().mtime(), 1_000);
+        assert!(updates.staged(&dst).is_none());
+    }
+
+    #[compio::test]
+    async fn test_runs_finish_only_their_own_updates() {
+        // Requirement: A run that fails discards only its own staged files,
+        // not those of a run going on next to it
+        let temp_dir = TempDir::new().unwrap();
+        let (_, dst) = destination(&temp_dir);
+        let other_dst = temp_dir.path().join("other");
+        let (failed, succeeding) = (DelayedUpdates::default(), DelayedUpdates::default());
+
+        stage(&failed, &dst, "failed");
+        stage(&succeeding, &other_dst, "new");
+        let failure: Result<()> = Err(SyncError::CopyFailed("run failed".to_string()));
+        assert!(failed.finish(failure).await.is_err());
+        assert!(succeeding.staged(&other_dst).unwrap().exists());
+        succeeding.finish(Ok(())).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "old");
+        assert_eq!(std::fs::read_to_string(&other_dst).unwrap(), "new");
+    }
+}
@@ -0,0 +1,437 @@
+//! Parallel, restartable deletion of destination entries
+//!
+//! Removing millions of extraneous destination files one at a time takes
+//! hours, so deletion runs over its own dispatcher:
+//!
+//! - Every directory's children are dispatched concurrently; a directory is
+//!   removed only after all of its children are (children before parents).
+//! - Unlinks and directory reads are bounded by a dedicated semaphore, separate
+//!   from the copy pipeline's `--max-files-in-flight` permits, so a large
+//!   deletion cannot starve copies (or the other way around).
+//! - The entries to delete are recorded in a journal (`.arsync-delete.journal`
+//!   in the destination root) before anything is removed, and each completed
+//!   entry is marked done. An interrupted run's pending entries are picked up
+//!   by the next call; removing an already-removed path is not an error, so
+//!   replaying a partially completed entry is safe.
+//!
+//! A [`Deleter`] owns the dispatcher and permits, so one can serve many
+//! calls (`--delete-during` deletes per directory, journaling each in that
+//! directory). With `--dry-run` it only reports the entries it would delete.
+
+use crate::bisync::{escape_path, unescape_path};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::itemize::Itemizer;
+use compio::dispatcher::Dispatcher;
+use compio_sync::Semaphore;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Name of the deletion journal in the destination root
+pub const JOURNAL_FILE_NAME: &str = ".arsync-delete.journal";
+
+/// Default number of concurrent unlink/rmdir/readdir operations
+pub const DEFAULT_MAX_DELETES_IN_FLIGHT: usize = 256;
+
+/// First line of the journal
+const JOURNAL_HEADER: &[u8] = b"arsync-delete-journal v1";
+
+/// Counters for a deletion run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeletionStats {
+    /// Files, symlinks and special files removed
+    pub files_removed: u64,
+    /// Directories removed
+    pub dirs_removed: u64,
+}
+
+// ============================================================================
+// JOURNAL
+// ============================================================================
+
+/// On-disk record of pending deletions
+///
+/// Format: a header line, then `+ <path>` for each entry to delete and
+/// `- <path>` once it is gone. Paths are relative to the destination root and
+/// escaped so that each fits on one line.
+#[derive(Debug)]
+pub struct DeletionJournal {
+    /// Journal file path
+    path: PathBuf,
+    /// Open for appending `-` records
+    file: File,
+}
+
+impl DeletionJournal {
+    /// Entries still pending in the journal under `dest_root`, if one exists
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal exists but cannot be read or is malformed.
+    pub fn pending(dest_root: &Path) -> Result<Vec<PathBuf>> {
+        let path = dest_root.join(JOURNAL_FILE_NAME);
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(journal_error("read deletion journal", &path, &e)),
+        };
+
+        let corrupt = || {
+            SyncError::FileSystem(format!(
+                "Corrupt deletion journal {}; remove it to start over",
+                path.display()
+            ))
+        };
+        let mut lines = contents.split(|&b| b == b'\n');
+        if lines.next() != Some(JOURNAL_HEADER) {
+            return Err(corrupt());
+        }
+        let mut pending = BTreeSet::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            // A torn final line (crash mid-append) is simply ignored
+            let Some(entry) = line.get(2..).and_then(unescape_path).map(PathBuf::from) else {
+                continue;
+            };
+            match line.first() {
+                Some(b'+') => pending.insert(entry),
+                Some(b'-') => pending.remove(&entry),
+                _ => return Err(corrupt()),
+            };
+        }
+        Ok(pending.into_iter().collect())
+    }
+
+    /// Write a fresh journal listing `entries` as pending
+    ///
+    /// The journal is written to a temporary name, synced and renamed into
+    /// place, so a crash never leaves a truncated list behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be written.
+    pub fn create(dest_root: &Path, entries: &[PathBuf]) -> Result<Self> {
+        let path = dest_root.join(JOURNAL_FILE_NAME);
+        let mut contents = JOURNAL_HEADER.to_vec();
+        contents.push(b'\n');
+        for entry in entries {
+            contents.extend_from_slice(b"+ ");
+            escape_path(entry.as_os_str(), &mut contents);
+            contents.push(b'\n');
+        }
+
+        let staging = crate::temp_files::temp_path(dest_root, JOURNAL_FILE_NAME);
+        std::fs::write(&staging, &contents)
+            .and_then(|()| File::open(&staging)?.sync_all())
+            .and_then(|()| std::fs::rename(&staging, &path))
+            .map_err(|e| journal_error("write deletion journal", &path, &e))?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| journal_error("open deletion journal", &path, &e))?;
+        Ok(Self { path, file })
+    }
+
+    /// Record that `entry` has been fully removed
+    fn mark_done(&mut self, entry: &Path) -> Result<()> {
+        let mut line = b"- ".to_vec();
+        escape_path(entry.as_os_str(), &mut line);
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .map_err(|e| journal_error("update deletion journal", &self.path, &e))
+    }
+
+    /// Remove the journal after every entry is done
+    fn finish(self) -> Result<()> {
+        std::fs::remove_file(&self.path)
+            .map_err(|e| journal_error("remove deletion journal", &self.path, &e))
+    }
+}
+
+fn journal_error(operation: &str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+// ============================================================================
+// PARALLEL DELETION
+// ============================================================================
+
+/// State shared by all deletion tasks
+struct DeletionContext {
+    /// Dispatcher running the deletion tasks
+    dispatcher: &'static Dispatcher,
+    /// Bounds concurrent filesystem operations
+    permits: Semaphore,
+    /// Files removed so far
+    files_removed: AtomicU64,
+    /// Directories removed so far
+    dirs_removed: AtomicU64,
+}
+
+/// Parallel deletion with its own dispatcher and in-flight limit
+///
+/// Counters accumulate over every call.
+pub struct Deleter {
+    /// Shared with the dispatched tasks
+    ctx: Arc<DeletionContext>,
+    /// Reports deletions instead of making them (`--dry-run`)
+    itemizer: Option<Arc<Itemizer>>,
+}
+
+impl Deleter {
+    /// Deleter running at most `max_in_flight` filesystem operations at once
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the dispatcher's worker threads cannot be started.
+    pub fn new(max_in_flight: usize) -> Result<Self> {
+        // Same lifetime arrangement as the copy traversal: the dispatcher owns
+        // worker threads and lives for the rest of the program
+        let dispatcher = Box::leak(Box::new(Dispatcher::new()?));
+        Ok(Self {
+            ctx: Arc::new(DeletionContext {
+                dispatcher,
+                permits: Semaphore::new(max_in_flight.max(1)),
+                files_removed: AtomicU64::new(0),
+                dirs_removed: AtomicU64::new(0),
+            }),
+            itemizer: None,
+        })
+    }
+
+    /// Report entries to `itemizer` instead of deleting them (`--dry-run`)
+    #[must_use]
+    pub fn with_itemizer(mut self, itemizer: Option<Arc<Itemizer>>) -> Self {
+        self.itemizer = itemizer;
+        self
+    }
+
+    /// Entries removed by this deleter so far
+    #[must_use]
+    pub fn stats(&self) -> DeletionStats {
+        DeletionStats {
+            files_removed: self.ctx.files_removed.load(Ordering::Relaxed),
+            dirs_removed: self.ctx.dirs_removed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Delete `entries` (relative to `dest_root`) and everything below them
+    ///
+    /// Entries left pending by an interrupted earlier run are deleted as well.
+    /// Symlinks are removed, never followed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be written or an entry cannot be
+    /// removed. Completed entries stay marked in the journal, so calling again
+    /// resumes with the rest.
+    #[allow(clippy::future_not_send)]
+    pub async fn delete_entries(&self, dest_root: &Path, entries: &[PathBuf]) -> Result<()> {
+        let mut all: BTreeSet<PathBuf> = DeletionJournal::pending(dest_root)?.into_iter().collect();
+        if let Some(itemizer) = &self.itemizer {
+            all.extend(entries.iter().cloned());
+            for entry in &all {
+                let path = dest_root.join(entry);
+                let is_dir = std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+                itemizer.delete(&path, is_dir);
+            }
+            return Ok(());
+        }
+        if !all.is_empty() {
+            info!(
+                "Resuming {} pending deletion(s) from {}",
+                all.len(),
+                dest_root.join(JOURNAL_FILE_NAME).display()
+            );
+        }
+        // Entries pending from an earlier run were already confirmed there
+        all.extend(
+            entries
+                .iter()
+                .filter(|entry| {
+                    crate::interactive::prompter()
+                        .is_none_or(|prompter| prompter.confirm_delete(&dest_root.join(entry)))
+                })
+                .cloned(),
+        );
+        let all: Vec<PathBuf> = all.into_iter().collect();
+        if all.is_empty() {
+            return Ok(());
+        }
+        let mut journal = DeletionJournal::create(dest_root, &all)?;
+
+        let mut pending: FuturesUnordered<_> = all
+            .into_iter()
+            .map(|entry| {
+                let ctx = Arc::clone(&self.ctx);
+                let full = dest_root.join(&entry);
+                async move { (delete_tree(ctx, full).await, entry) }
+            })
+            .collect();
+        while let Some((result, entry)) = pending.next().await {
+            result?;
+            journal.mark_done(&entry)?;
+        }
+        journal.finish()
+    }
+}
+
+/// Delete `entries` (relative to `dest_root`) and everything below them
+///
+/// Runs a one-off [`Deleter`]; see [`Deleter::delete_entries`].
+///
+/// # Errors
+///
+/// Returns an error if the journal cannot be written or an entry cannot be
+/// removed. Completed entries stay marked in the journal, so calling again
+/// resumes with the rest.
+#[allow(clippy::future_not_send)]
+pub async fn delete_entries(
+    dest_root: &Path,
+    entries: &[PathBuf],
+    max_in_flight: usize,
+) -> Result<DeletionStats> {
+    let deleter = Deleter::new(max_in_flight)?;
+    deleter.delete_entries(dest_root, entries).await?;
+    Ok(deleter.stats())
+}
+
+/// Run `delete_entry` for `path` on the dispatcher
+async fn delete_tree(ctx: Arc<DeletionContext>, path: PathBuf) -> Result<()> {
+    let dispatcher = ctx.dispatcher;
+    dispatcher
+        .dispatch(move || delete_entry(ctx, path))
+        .map_err(|e| SyncError::FileSystem(format!("Failed to dispatch deletion: {e:?}")))?
+        .await
+        .map_err(|e| SyncError::FileSystem(format!("Failed to receive deletion result: {e:?}")))?
+}
+
+/// Remove `path`; directories are emptied concurrently first
+async fn delete_entry(ctx: Arc<DeletionContext>, path: PathBuf) -> Result<()> {
+    let metadata = match compio::fs::symlink_metadata(&path).await {
+        Ok(metadata) => metadata,
+        // Already removed (e.g. by the run this one resumes)
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(delete_error("stat", &path, &e)),
+    };
+
+    if !metadata.is_dir() {
+        let _permit = ctx.permits.acquire().await;
+        return match compio::fs::remove_file(&path).await {
+            Ok(()) => {
+                ctx.files_removed.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(delete_error("remove file", &path, &e)),
+        };
+    }
+
+    let children: Vec<PathBuf> = {
+        let _permit = ctx.permits.acquire().await;
+        compio_fs_extended::directory::read_dir(&path)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("read directory for deletion")
+                    .destination(&path)
+                    .cause(&e)
+                    .file_system()
+            })?
+            .iter()
+            .map(|entry| path.join(entry.file_name()))
+            .collect()
+    };
+
+    // Children are dispatched as separate tasks; permits are only held around
+    // individual operations so that parents waiting on children never block
+    // the children themselves
+    futures::future::try_join_all(
+        children
+            .into_iter()
+            .map(|child| delete_tree(Arc::clone(&ctx), child)),
+    )
+    .await?;
+
+    let _permit = ctx.permits.acquire().await;
+    match compio::fs::remove_dir(&path).await {
+        Ok(()) => {
+            ctx.dirs_removed.fetch_add(1, Ordering::Relaxed);
+            debug!("Removed directory {}", path.display());
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(delete_error("remove directory", &path, &e)),
+    }
+}
+
+fn delete_error(operation: &str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_delete_entries_resumes_journal() {
+        // Test: Deep trees are removed children-first, and pending entries of
+        // an interrupted run are completed
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let deep = root.join("old/a/b/c");
+        std::fs::create_dir_all(&deep).unwrap();
+        for i in 0..50 {
+            std::fs::write(root.join(format!("old/a/f{i}")), b"x").unwrap();
+        }
+        std::fs::write(deep.join("leaf"), b"x").unwrap();
+        std::os::unix::fs::symlink("/nonexistent", root.join("old/link")).unwrap();
+        std::fs::create_dir(root.join("interrupted")).unwrap();
+        std::fs::write(root.join("interrupted/rest"), b"x").unwrap();
+        std::fs::write(root.join("keep"), b"x").unwrap();
+
+        // Simulate a crash: one entry done, one still pending
+        drop(
+            DeletionJournal::create(root, &[PathBuf::from("gone"), PathBuf::from("interrupted")])
+                .unwrap(),
+        );
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(root.join(JOURNAL_FILE_NAME))
+            .unwrap();
+        journal.write_all(b"- gone\n").unwrap();
+        assert_eq!(
+            DeletionJournal::pending(root).unwrap(),
+            vec![PathBuf::from("interrupted")]
+        );
+
+        let stats = delete_entries(root, &[PathBuf::from("old")], 4)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            DeletionStats {
+                files_removed: 53,
+                dirs_removed: 5,
+            }
+        );
+        assert!(!root.join("old").exists());
+        assert!(!root.join("interrupted").exists());
+        assert!(root.join("keep").exists());
+        assert!(!root.join(JOURNAL_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,268 @@
+//! Destination locking so concurrent runs cannot interleave
+//!
+//! Each run takes an exclusive `flock(2)` on `.arsync.lock` in the destination
+//! directory for its whole duration. The lock file records who holds it
+//! (`pid`, start time, source), so a second run into the same destination
+//! fails immediately with a message naming the running sync instead of
+//! silently interleaving writes with it.
+//!
+//! `flock` locks are released by the kernel when the holder exits, so a crashed
+//! run never leaves a stale lock behind: a leftover `.arsync.lock` file without
+//! a holder is simply taken over. The file is removed when the lock is dropped.
+
+use crate::clock::{unix_seconds, Clock, SystemClock};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::temp_files::RunId;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file created in the destination directory
+pub const LOCK_FILE_NAME: &str = ".arsync.lock";
+
+/// Metadata written into the lock file by its holder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    /// Run holding the lock (PID and start time)
+    pub run_id: RunId,
+    /// Source of the holding run
+    pub source: PathBuf,
+}
+
+impl LockHolder {
+    /// Serialize as `key=value` lines
+    fn encode(&self) -> String {
+        format!(
+            "pid={}\nstarted={}\nrun_id={}\nsource={}\n",
+            self.run_id.pid,
+            self.run_id.started,
+            self.run_id,
+            self.source.display()
+        )
+    }
+
+    /// Parse the lock file contents; `None` if incomplete (e.g. mid-write)
+    fn decode(contents: &str) -> Option<Self> {
+        let mut pid = None;
+        let mut started = None;
+        let mut source = PathBuf::new();
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("pid", value)) => pid = value.parse().ok(),
+                Some(("started", value)) => started = value.parse().ok(),
+                Some(("source", value)) => source = PathBuf::from(value),
+                _ => {}
+            }
+        }
+        Some(Self {
+            run_id: RunId {
+                pid: pid?,
+                started: started?,
+            },
+            source,
+        })
+    }
+
+    /// Human-readable description for the "already running" diagnostic
+    ///
+    /// A start time after `clock`'s now (the clock was stepped back) shows as
+    /// 0s ago.
+    fn describe(&self, clock: &dyn Clock) -> String {
+        let age = u64::try_from(unix_seconds(clock.now()))
+            .unwrap_or(0)
+            .saturating_sub(self.run_id.started);
+        format!(
+            "pid {}, started at {} (unix time, {age}s ago), syncing from {}",
+            self.run_id.pid,
+            self.run_id.started,
+            self.source.display()
+        )
+    }
+}
+
+/// Exclusive lock on a destination, held until dropped
+#[derive(Debug)]
+pub struct DestinationLock {
+    /// Open lock file; closing it releases the `flock`
+    file: File,
+    /// Path of the lock file
+    path: PathBuf,
+}
+
+impl DestinationLock {
+    /// Take the lock for `destination_dir`, or fail if another run holds it
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` naming the holder if another sync is
+    /// running into the same destination, or `SyncError::FileSystem` if the
+    /// lock file cannot be created or locked.
+    pub fn acquire(destination_dir: &Path, source: &Path) -> Result<Self> {
+        let path = destination_dir.join(LOCK_FILE_NAME);
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o644)
+                .custom_flags(libc::O_CLOEXEC)
+                .open(&path)
+                .map_err(|e| lock_error("open destination lock", &path, &e))?;
+
+            // SAFETY: flock on an fd we own
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = std::io::Error::last_os_error();
+                if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                    return Err(already_running(destination_dir, file));
+                }
+                return Err(lock_error("lock destination", &path, &err));
+            }
+
+            // The previous holder unlinks the file before releasing the lock;
+            // if we locked such an orphaned inode, start over on the new file.
+            if !is_same_file(&file, &path) {
+                continue;
+            }
+
+            let mut lock = Self { file, path };
+            lock.write_holder(source)?;
+            return Ok(lock);
+        }
+    }
+
+    /// Path of the lock file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record the current run as the holder
+    fn write_holder(&mut self, source: &Path) -> Result<()> {
+        let holder = LockHolder {
+            run_id: RunId::current(),
+            source: source.to_path_buf(),
+        };
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.write_all(holder.encode().as_bytes()))
+            .map_err(|e| lock_error("write destination lock", &self.path, &e))
+    }
+}
+
+impl Drop for DestinationLock {
+    fn drop(&mut self) {
+        // Unlink while still holding the lock so a waiter never locks a file
+        // that is about to disappear (see the inode check in `acquire`).
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(
+                "Failed to remove destination lock {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), std::fs::symlink_metadata(path)) {
+        (Ok(open), Ok(named)) => open.dev() == named.dev() && open.ino() == named.ino(),
+        _ => false,
+    }
+}
+
+/// Build the "another sync is running" error from the holder's lock file
+fn already_running(destination_dir: &Path, mut file: File) -> SyncError {
+    let mut contents = String::new();
+    let holder = file
+        .read_to_string(&mut contents)
+        .ok()
+        .and_then(|_| LockHolder::decode(&contents));
+    let who = holder.map_or_else(
+        || "holder details unavailable".to_string(),
+        |holder| holder.describe(&SystemClock),
+    );
+    SyncError::InvalidConfig(format!(
+        "another sync is running into {} ({who}); wait for it to finish or pass --no-lock to override",
+        destination_dir.display()
+    ))
+}
+
+fn lock_error(operation: &str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_second_lock_reports_holder() {
+        // Test: A second run into the same destination is refused
+        // Requirement: The diagnostic names the running sync (pid, start time)
+        let temp_dir = TempDir::new().unwrap();
+        let lock = DestinationLock::acquire(temp_dir.path(), Path::new("/src/a")).unwrap();
+
+        let err = DestinationLock::acquire(temp_dir.path(), Path::new("/src/b"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("another sync is running"), "{err}");
+        assert!(
+            err.contains(&format!("pid {}", std::process::id())),
+            "{err}"
+        );
+        assert!(err.contains("/src/a"), "{err}");
+
+        drop(lock);
+        assert!(!temp_dir.path().join(LOCK_FILE_NAME).exists());
+        DestinationLock::acquire(temp_dir.path(), Path::new("/src/b")).unwrap();
+    }
+
+    #[test]
+    fn test_leftover_lock_file_is_taken_over() {
+        // Test: A lock file without a holder (crashed run) does not block
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join(LOCK_FILE_NAME),
+            "pid=1\nstarted=0\nsource=/old\n",
+        )
+        .unwrap();
+
+        let lock = DestinationLock::acquire(temp_dir.path(), Path::new("/new")).unwrap();
+        let contents = std::fs::read_to_string(lock.path()).unwrap();
+        let holder = LockHolder::decode(&contents).unwrap();
+        assert_eq!(holder.run_id, RunId::current());
+        assert_eq!(holder.source, Path::new("/new"));
+    }
+
+    #[test]
+    fn test_holder_age() {
+        // Requirement: The holder's age is measured against the clock, and
+        // a start time in the future (clock stepped back) is not negative
+        use crate::clock::MockClock;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let holder = LockHolder {
+            run_id: RunId {
+                pid: 42,
+                started: 1_000_000,
+            },
+            source: PathBuf::from("/src"),
+        };
+        let clock = MockClock::at(UNIX_EPOCH + Duration::from_secs(1_000_090));
+        assert!(holder.describe(&clock).contains("90s ago"));
+
+        clock.rewind(Duration::from_secs(120));
+        assert!(holder.describe(&clock).contains("0s ago"));
+        clock.set(UNIX_EPOCH - Duration::from_secs(5));
+        assert!(holder.describe(&clock).contains("0s ago"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Parallel copy depth chosen per device (`--parallel-adaptive`)
+//!
+//! `--parallel-max-depth` splits every large file the same way, but what
+//! helps depends on the disks: an NVMe drive serves many requests at once and
+//! copies faster with more tasks, while a spinning disk seeks back and forth
+//! between the tasks' regions and copies slower. With `--parallel-adaptive`
+//! the depth is chosen per file, for the pair of devices it is copied between:
+//!
+//! - If either device is rotational (`queue/rotational` in sysfs), the file
+//!   is copied sequentially
+//! - Otherwise the first [`SAMPLES`] chunks copied between the pair are
+//!   copied one at a time and timed. With a median over [`FAST`] per MiB the
+//!   depth is capped at 2, over [`SLOW`] per MiB at 1; faster pairs get the
+//!   full `--parallel-max-depth`
+//!
+//! Devices sysfs does not describe (network filesystems, tmpfs, Btrfs'
+//! anonymous devices) are judged by their timings alone. What is learned
+//! about a pair is kept for the rest of the run, in its [`DeviceTimings`]
+//! like the tuning profile.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use tracing::info;
+
+/// Chunks timed per device pair before its depth is chosen
+pub const SAMPLES: usize = 8;
+
+/// Chunk latency per MiB above which the depth is capped at 2 (500 MiB/s)
+pub const FAST: Duration = Duration::from_millis(2);
+
+/// Chunk latency per MiB above which the depth is capped at 1 (100 MiB/s)
+pub const SLOW: Duration = Duration::from_millis(10);
+
+/// What a run has learned about each (source, destination) device pair
+#[derive(Debug, Default)]
+pub struct DeviceTimings(Mutex<HashMap<DevicePair, PairState>>);
+
+/// Timings and sysfs facts of a device pair
+#[derive(Debug)]
+struct PairState {
+    /// Either device is rotational
+    rotational: bool,
+    /// Chunk latencies per MiB, up to `SAMPLES`
+    latencies: Vec<Duration>,
+}
+
+impl PairState {
+    /// Median chunk latency per MiB once `SAMPLES` chunks have been timed
+    fn latency(&self) -> Option<Duration> {
+        if self.latencies.len() < SAMPLES {
+            return None;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+}
+
+/// The devices a file is copied between (`st_dev` numbers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DevicePair {
+    /// Device of the source
+    pub source: u64,
+    /// Device of the destination
+    pub destination: u64,
+}
+
+impl DevicePair {
+    /// Depth for a file copied between the pair, or `None` while its chunks
+    /// still need timing in `timings`
+    #[must_use]
+    pub fn depth(self, timings: &DeviceTimings, max_depth: usize) -> Option<usize> {
+        self.with_state(timings, |state| {
+            choose_depth(max_depth, state.rotational, state.latency())
+        })
+    }
+
+    /// Record in `timings` that a chunk of `bytes` took `elapsed` to copy
+    pub fn record(self, timings: &DeviceTimings, elapsed: Duration, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let per_mib = elapsed.mul_f64(1_048_576.0 / bytes as f64);
+        self.with_state(timings, |state| {
+            if state.latencies.len() < SAMPLES {
+                state.latencies.push(per_mib);
+                if let Some(latency) = state.latency() {
+                    info!(
+                        "Devices {} -> {}: {:?} per MiB copied (--parallel-adaptive)",
+                        device_name(self.source),
+                        device_name(self.destination),
+                        latency
+                    );
+                }
+            }
+        });
+    }
+
+    /// Run `f` on the pair's state, reading sysfs the first time
+    fn with_state<T>(self, timings: &DeviceTimings, f: impl FnOnce(&mut PairState) -> T) -> T {
+        let mut pairs = timings.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = pairs.entry(self).or_insert_with(|| {
+            let rotational = [self.source, self.destination]
+                .into_iter()
+                .find(|&dev| is_rotational(dev));
+            if let Some(dev) = rotational {
+                info!(
+                    "Device {} is rotational: files on it are copied sequentially \
+                     (--parallel-adaptive)",
+                    device_name(dev)
+                );
+            }
+            PairState {
+                rotational: rotational.is_some(),
+                latencies: Vec::with_capacity(SAMPLES),
+            }
+        });
+        f(state)
+    }
+}
+
+/// Depth for a pair of devices: sequential if `rotational`, otherwise
+/// `max_depth` capped by the median chunk `latency` per MiB; `None` until
+/// the latency is known
+#[must_use]
+pub fn choose_depth(
+    max_depth: usize,
+    rotational: bool,
+    latency: Option<Duration>,
+) -> Option<usize> {
+    if rotational {
+        return Some(0);
+    }
+    let latency = latency?;
+    Some(if latency > SLOW {
+        max_depth.min(1)
+    } else if latency > FAST {
+        max_depth.min(2)
+    } else {
+        max_depth
+    })
+}
+
+/// Device of the open directory `dir`
+#[must_use]
+pub fn dir_device(dir: &compio_fs_extended::DirectoryFd) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut st = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: st is a valid out-pointer for fstat on an open fd
+    if unsafe { libc::fstat(dir.as_raw_fd(), st.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: fstat succeeded and initialized st
+    #[allow(clippy::useless_conversion)] // st_dev width is platform-dependent
+    Some(u64::from(unsafe { st.assume_init() }.st_dev))
+}
+
+/// Major and minor numbers of `dev` (glibc's encoding)
+const fn major_minor(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 32) & 0xFFFF_F000) | ((dev >> 8) & 0xFFF);
+    let minor = ((dev >> 12) & 0xFFFF_FF00) | (dev & 0xFF);
+    (major, minor)
+}
+
+/// `major:minor` of `dev`, as sysfs names it
+fn device_name(dev: u64) -> String {
+    let (major, minor) = major_minor(dev);
+    format!("{major}:{minor}")
+}
+
+/// Whether sysfs says the block device `dev` is rotational; a partition
+/// has its disk's queue
+fn is_rotational(dev: u64) -> bool {
+    let base = format!("/sys/dev/block/{}", device_name(dev));
+    ["queue/rotational", "../queue/rotational"]
+        .iter()
+        .find_map(|queue| std::fs::read_to_string(format!("{base}/{queue}")).ok())
+        .is_some_and(|text| text.trim() == "1")
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_choose_depth() {
+        // Requirement: Rotational devices copy sequentially; others get the
+        // full depth only once their chunks are known to be fast
+        let ms = Duration::from_millis;
+        assert_eq!(choose_depth(4, true, None), Some(0));
+        assert_eq!(choose_depth(4, true, Some(ms(1))), Some(0));
+        assert_eq!(choose_depth(4, false, None), None);
+        assert_eq!(choose_depth(4, false, Some(ms(1))), Some(4));
+        assert_eq!(choose_depth(4, false, Some(ms(5))), Some(2));
+        assert_eq!(choose_depth(1, false, Some(ms(5))), Some(1));
+        assert_eq!(choose_depth(4, false, Some(ms(50))), Some(1));
+    }
+
+    #[test]
+    fn test_latency_after_samples() {
+        // Requirement: The depth is chosen from the median of SAMPLES chunks,
+        // normalized per MiB
+        // Device numbers no sysfs entry has, so the pair is not rotational
+        let pair = DevicePair {
+            source: u64::MAX - 1,
+            destination: u64::MAX,
+        };
+        let timings = DeviceTimings::default();
+        for _ in 0..SAMPLES - 1 {
+            pair.record(&timings, Duration::from_millis(2), 2 * 1_048_576);
+            assert_eq!(pair.depth(&timings, 3), None);
+        }
+        pair.record(&timings, Duration::from_millis(100), 2 * 1_048_576);
+        assert_eq!(pair.depth(&timings, 3), Some(3));
+        pair.record(&timings, Duration::from_millis(100), 2 * 1_048_576);
+        assert_eq!(
+            pair.depth(&timings, 3),
+            Some(3),
+            "later chunks are not sampled"
+        );
+        assert_eq!(
+            pair.depth(&DeviceTimings::default(), 3),
+            None,
+            "another run times the pair again"
+        );
+    }
+
+    #[test]
+    fn test_major_minor() {
+        // Requirement: Device numbers decode like glibc's major()/minor()
+        assert_eq!(major_minor(0x0803), (8, 3));
+        assert_eq!(major_minor(0x1_0300), (259, 0));
+        assert_eq!(device_name(0xFD01), "253:1");
+    }
+}
@@ -0,0 +1,204 @@
+//! Removal of destination entries not present in the source (`--delete`)
+//!
+//! As with rsync, extraneous entries can be removed at three points:
+//!
+//! - `--delete-before`: the whole destination is compared against the source
+//!   and extraneous entries are removed before anything is copied.
+//! - `--delete-during` (what `--delete` alone means): each directory's
+//!   extraneous entries are removed when the traversal reaches it, before its
+//!   children are copied.
+//! - `--delete-after`: the comparison runs once the copy has succeeded; a
+//!   failed copy deletes nothing.
+//!
+//! Removal goes through [`crate::deletion::Deleter`], so it is parallel,
+//! journaled and confirmed with `--interactive`. An entry is extraneous if no
+//! source entry maps to its name (after `--truncate-long-names`). arsync's own
+//! files (lock, long-name manifest, deletion journal, temporary files) and
+//! entries matching an exclude rule are never extraneous, and excluded
+//! directories are not looked into.
+
+use crate::deletion::{Deleter, JOURNAL_FILE_NAME};
+use crate::dest_lock::LOCK_FILE_NAME;
+use crate::error::{ErrorContext, Result};
+use crate::filter::FilterRules;
+use crate::long_names::{name_max, LongNameMapper, DEFAULT_NAME_MAX, MANIFEST_FILE_NAME};
+use crate::path_builder::PathBuilder;
+use crate::temp_files::{PARTIAL_PREFIX, TEMP_PREFIX};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// When extraneous destination entries are removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteTiming {
+    /// Whole tree, before copying (`--delete-before`)
+    Before,
+    /// Per directory, as the traversal reaches it (`--delete`, `--delete-during`)
+    During,
+    /// Whole tree, after a successful copy (`--delete-after`)
+    After,
+}
+
+/// Whether `name` is one of arsync's own files, kept regardless of the source
+///
+/// This includes `--partial` files, which a later run resumes.
+fn is_protected(name: &OsStr) -> bool {
+    name == LOCK_FILE_NAME
+        || name == MANIFEST_FILE_NAME
+        || name == crate::encryption::MANIFEST_FILE_NAME
+        || name == JOURNAL_FILE_NAME
+        || name.as_bytes().starts_with(TEMP_PREFIX.as_bytes())
+        || name.as_bytes().starts_with(PARTIAL_PREFIX.as_bytes())
+}
+
+/// Remove the entries of `dst_dir` whose names are not in `kept`
+///
+/// `kept` holds the destination names of the source directory's entries.
+/// `filter` comes with the path of `dst_dir` relative to the destination root;
+/// entries it excludes are kept.
+///
+/// # Errors
+///
+/// Returns an error if `dst_dir` cannot be read or an entry cannot be removed.
+#[allow(clippy::future_not_send)]
+pub(super) async fn delete_extraneous_in(
+    deleter: &Deleter,
+    dst_dir: &Path,
+    kept: &HashSet<OsString>,
+    filter: Option<(&FilterRules, &Path)>,
+) -> Result<()> {
+    let mut filter = filter.map(|(filter, relative)| (filter, PathBuilder::new(relative)));
+    let extraneous: Vec<PathBuf> = read_names(dst_dir)
+        .await?
+        .into_iter()
+        .filter(|(name, is_dir)| {
+            !kept.contains(name)
+                && !is_protected(name)
+                && !filter.as_mut().is_some_and(|(filter, relative_entry)| {
+                    filter.is_excluded(relative_entry.entry(name), *is_dir)
+                })
+        })
+        .map(|(name, _)| PathBuf::from(name))
+        .collect();
+    deleter.delete_entries(dst_dir, &extraneous).await
+}
+
+/// Remove every entry under `dst_root` that has no counterpart under `src_root`
+///
+/// Used for `--delete-before` and `--delete-after`. Only directories present
+/// on both sides are descended into; an extraneous directory is removed
+/// with everything below it.
+///
+/// # Errors
+///
+/// Returns an error if a directory on either side cannot be read or an
+/// extraneous entry cannot be removed.
+#[allow(clippy::future_not_send)]
+pub(super) async fn delete_extraneous_tree(
+    deleter: &Deleter,
+    src_root: &Path,
+    dst_root: &Path,
+    long_names: &LongNameMapper,
+    filter: Option<&FilterRules>,
+) -> Result<()> {
+    let excluded =
+        |path: &Path, is_dir| filter.is_some_and(|filter| filter.is_excluded(path, is_dir));
+    let mut extraneous = Vec::new();
+    // Source and destination paths differ where names are mapped
+    let mut pending = vec![(PathBuf::new(), PathBuf::new())];
+    while let Some((src_relative, relative)) = pending.pop() {
+        let (src_dir, dst_dir) = (src_root.join(&src_relative), dst_root.join(&relative));
+        let dst_names = read_names(&dst_dir).await?;
+        let dst_name_max =
+            std::fs::File::open(&dst_dir).map_or(DEFAULT_NAME_MAX, |dir| name_max(dir.as_raw_fd()));
+
+        let mut kept = HashSet::with_capacity(dst_names.len());
+        let mut relative_entry = PathBuilder::new(&src_relative);
+        for (name, is_dir) in read_names(&src_dir).await? {
+            let descend = is_dir && !excluded(relative_entry.entry(&name), true);
+            // A name too long for the destination cannot be there to keep
+            let mapped = long_names
+                .map_name(&src_dir, &dst_dir, &name, dst_name_max)
+                .unwrap_or_else(|_| name.clone());
+            if descend && dst_names.get(&mapped) == Some(&true) {
+                pending.push((src_relative.join(&name), relative.join(&mapped)));
+            }
+            kept.insert(mapped);
+        }
+        extraneous.extend(
+            dst_names
+                .into_iter()
+                .filter(|(name, is_dir)| {
+                    !kept.contains(name)
+                        && !is_protected(name)
+                        && !excluded(relative_entry.entry(name), *is_dir)
+                })
+                .map(|(name, _)| relative.join(name)),
+        );
+    }
+    deleter.delete_entries(dst_root, &extraneous).await
+}
+
+/// Names in `dir`, each with whether it is a directory (symlinks are not)
+async fn read_names(dir: &Path) -> Result<HashMap<OsString, bool>> {
+    let entries = compio_fs_extended::directory::read_dir(dir)
+        .await
+        .map_err(|e| {
+            ErrorContext::new("read directory for --delete")
+                .destination(dir)
+                .cause(&e)
+                .traversal()
+        })?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.file_name().to_os_string(), entry.file_type().is_dir()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_delete_extraneous_tree() {
+        // Requirement: Entries missing from the source are removed at any
+        // depth; matching entries and arsync's own files are kept
+        let temp_dir = TempDir::new().unwrap();
+        let (src, dst) = (temp_dir.path().join("src"), temp_dir.path().join("dst"));
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("keep"), b"x").unwrap();
+        std::fs::write(src.join("sub/keep"), b"x").unwrap();
+        std::fs::create_dir_all(dst.join("sub")).unwrap();
+        std::fs::create_dir_all(dst.join("gone/deeper")).unwrap();
+        for name in ["keep", "extra", "sub/keep", "sub/extra", "gone/deeper/f"] {
+            std::fs::write(dst.join(name), b"x").unwrap();
+        }
+        std::os::unix::fs::symlink("keep", dst.join("sub/link")).unwrap();
+        std::fs::write(dst.join(LOCK_FILE_NAME), b"").unwrap();
+        std::fs::write(dst.join(format!("{TEMP_PREFIX}1.keep")), b"").unwrap();
+        std::fs::write(dst.join(format!("{PARTIAL_PREFIX}keep")), b"").unwrap();
+
+        let deleter = Deleter::new(4).unwrap();
+        let long_names = LongNameMapper::new(&dst, None);
+        delete_extraneous_tree(&deleter, &src, &dst, &long_names, None)
+            .await
+            .unwrap();
+
+        assert!(dst.join("keep").exists());
+        assert!(dst.join("sub/keep").exists());
+        assert!(dst.join(LOCK_FILE_NAME).exists());
+        assert!(dst.join(format!("{TEMP_PREFIX}1.keep")).exists());
+        assert!(dst.join(format!("{PARTIAL_PREFIX}keep")).exists());
+        assert!(!dst.join("extra").exists());
+        assert!(!dst.join("sub/extra").exists());
+        assert!(!dst.join("sub/link").is_symlink());
+        assert!(!dst.join("gone").exists());
+        assert_eq!(deleter.stats().files_removed, 4);
+        assert_eq!(deleter.stats().dirs_removed, 2);
+    }
+}
@@ -0,0 +1,203 @@
+//! Hard linking unchanged files from earlier snapshots (`--link-dest`)
+//!
+//! Snapshot backups copy into a fresh directory each time, passing the
+//! previous snapshot as `--link-dest=DIR`. Before a regular file is copied,
+//! its counterpart at the same relative path in each DIR (in order; relative
+//! DIRs are relative to the destination, as in rsync) is compared with the
+//! source the way an existing destination would be (size and modification
+//! time, or contents with `--checksum`), and must also have the permissions
+//! and ownership being preserved, and with `-X`/`-A` the same extended
+//! attributes and ACLs, since a hard link shares them. Only a regular file
+//! qualifies: a symlink in DIR is not followed. The first match is hard
+//! linked into the destination instead of copying the file, so each snapshot
+//! only stores what changed.
+//!
+//! A hard link cannot cross filesystems: a DIR on another filesystem than the
+//! destination (`EXDEV`), or a file with too many links already (`EMLINK`),
+//! is copied as usual, with a warning grouped per directory.
+
+use super::traversal::open_parent_dirfd;
+use super::types::{metadata_from_path, FileLocation, TraversalContext};
+use super::update::{same_contents, UpdateCheck};
+use crate::interned_path::InternedPath;
+use crate::metadata::MetadataConfig;
+use crate::warnings::WarningLog;
+use compio_fs_extended::{ExtendedFile, FileMetadata, XattrOps};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// The file in the first `--link-dest` directory that `dst` can be a hard
+/// link to, if any
+#[allow(clippy::future_not_send)]
+pub(super) async fn find_link_target(
+    src: &FileLocation,
+    metadata: &FileMetadata,
+    dst: &FileLocation,
+    ctx: &TraversalContext,
+) -> Option<PathBuf> {
+    let check = ctx.traversal_config.update_check()?;
+    let dst_path = dst.path.to_path_buf();
+    let relative = dst_path.strip_prefix(&*ctx.dst_root).ok()?;
+    let config = &ctx.metadata_config;
+    let compare_xattrs = config.should_preserve_xattrs() || config.should_preserve_acls();
+    for dir in &ctx.traversal_config.link_dest {
+        let candidate = ctx.dst_root.join(dir).join(relative);
+        // symlink_metadata: a symlink is not followed, and is not a file
+        let Ok(existing) = metadata_from_path(&candidate).await else {
+            continue;
+        };
+        if !existing.is_file() || !same_attributes(metadata, &existing, ctx) {
+            continue;
+        }
+        if check != UpdateCheck::Checksum && !compare_xattrs {
+            return Some(candidate);
+        }
+        let Ok(parent_dir) = open_parent_dirfd(&candidate).await else {
+            continue;
+        };
+        let location = FileLocation {
+            path: InternedPath::from(candidate.as_path()),
+            parent_dir,
+        };
+        if check == UpdateCheck::Checksum
+            && !same_contents(src, &location, metadata.size)
+                .await
+                .unwrap_or(false)
+        {
+            continue;
+        }
+        if compare_xattrs && !same_xattrs(src, &location, config).await {
+            continue;
+        }
+        return Some(candidate);
+    }
+    None
+}
+
+/// Whether the regular file `existing` can stand in for the source: same
+/// size and modification time, and the permissions and ownership the copy
+/// would get
+fn same_attributes(
+    metadata: &FileMetadata,
+    existing: &FileMetadata,
+    ctx: &TraversalContext,
+) -> bool {
+    let config = &ctx.metadata_config;
+    existing.size == metadata.size
+        && (ctx.traversal_config.checksum || existing.modified == metadata.modified)
+        && (!config.should_preserve_permissions()
+            || existing.permissions() == metadata.permissions())
+        && (!config.should_preserve_owner() || existing.uid == metadata.uid)
+        && (!config.should_preserve_group() || existing.gid == metadata.gid)
+}
+
+/// Whether `candidate` has the extended attributes of `src` that the copy
+/// would carry: ACLs with `--acls`, the others with `--xattrs`
+#[allow(clippy::future_not_send)]
+async fn same_xattrs(
+    src: &FileLocation,
+    candidate: &FileLocation,
+    config: &MetadataConfig,
+) -> bool {
+    let (Ok(src_file), Ok(candidate_file)) = futures::join!(
+        src.parent_dir
+            .open_file_at(src.filename(), true, false, false, false),
+        candidate
+            .parent_dir
+            .open_file_at(candidate.filename(), true, false, false, false),
+    ) else {
+        return false;
+    };
+    match (
+        carried_xattrs(&src_file, config).await,
+        carried_xattrs(&candidate_file, config).await,
+    ) {
+        (Some(src_xattrs), Some(candidate_xattrs)) => src_xattrs == candidate_xattrs,
+        _ => false,
+    }
+}
+
+/// The extended attributes of `file` a copy would carry, by name; `None` if
+/// one cannot be read
+///
+/// A filesystem without extended attributes has none.
+#[allow(clippy::future_not_send)]
+async fn carried_xattrs(
+    file: &compio::fs::File,
+    config: &MetadataConfig,
+) -> Option<BTreeMap<String, Vec<u8>>> {
+    let file = ExtendedFile::from_ref(file);
+    let mut carried = BTreeMap::new();
+    for name in file.list_xattr().await.unwrap_or_default() {
+        let carries = if compio_fs_extended::acl::is_acl_xattr(&name) {
+            config.should_preserve_acls()
+        } else {
+            config.should_preserve_xattrs()
+        };
+        if carries {
+            let value = file.get_xattr(&name).await.ok()?;
+            carried.insert(name, value);
+        }
+    }
+    Some(carried)
+}
+
+/// Hard link `dst` to `target`, replacing an outdated destination file
+///
+/// Returns `false` (after a warning in `warnings` or a debug message) if the
+/// link cannot be made and the file should be copied instead.
+#[allow(clippy::future_not_send)]
+pub(super) async fn link_from(target: &Path, dst: &FileLocation, warnings: &WarningLog) -> bool {
+    let dst_path = dst.path.to_path_buf();
+    let mut result =
+        compio_fs_extended::hardlink::linkat(target, &dst.parent_dir, dst.filename()).await;
+    if matches!(&result, Err(e) if e.kind() == ErrorKind::AlreadyExists) {
+        // The destination has an older copy: link in its place
+        if let Err(e) = compio::fs::remove_file(&dst_path).await {
+            debug!("Cannot replace {}, copying it: {e}", dst_path.display());
+            return false;
+        }
+        result =
+            compio_fs_extended::hardlink::linkat(target, &dst.parent_dir, dst.filename()).await;
+    }
+    match result {
+        Ok(()) => {
+            debug!("Linked {} from {}", dst_path.display(), target.display());
+            true
+        }
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            warnings.warn(
+                "--link-dest on another filesystem",
+                &dst_path,
+                format_args!(
+                    "Cannot hard link {} from {} on another filesystem, copying it",
+                    dst_path.display(),
+                    target.display()
+                ),
+            );
+            false
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EMLINK) => {
+            warnings.warn(
+                "--link-dest file has too many links",
+                &dst_path,
+                format_args!(
+                    "{} has too many hard links, copying {}",
+                    target.display(),
+                    dst_path.display()
+                ),
+            );
+            false
+        }
+        Err(e) => {
+            debug!(
+                "Cannot link {} from {}, copying it: {e}",
+                dst_path.display(),
+                target.display()
+            );
+            false
+        }
+    }
+}
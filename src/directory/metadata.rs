@@ -2,8 +2,10 @@
 
 use crate::error::{Result, SyncError};
 use crate::io_uring::FileOperations;
-use crate::metadata::MetadataConfig;
+use crate::metadata::{copy_acl_fd, AclKind, MetadataConfig};
+use crate::warnings::WarningLog;
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use tracing::debug;
 
@@ -48,6 +50,7 @@ pub(super) async fn preserve_file_metadata(
 ///
 /// * `src_path` - Source directory path
 /// * `dst_path` - Destination directory path
+/// * `warnings` - Warning log of the run, for attributes that cannot be copied
 ///
 /// # Returns
 ///
@@ -60,7 +63,11 @@ pub(super) async fn preserve_file_metadata(
 /// - Extended attributes cannot be written to destination
 /// - Permission is denied for xattr operations
 #[allow(clippy::future_not_send)]
-pub async fn preserve_directory_xattr(src_path: &Path, dst_path: &Path) -> Result<()> {
+pub async fn preserve_directory_xattr(
+    src_path: &Path,
+    dst_path: &Path,
+    warnings: &WarningLog,
+) -> Result<()> {
     use compio_fs_extended::{ExtendedFile, XattrOps};
 
     // Open source and destination directories for xattr operations
@@ -83,24 +90,34 @@ pub async fn preserve_directory_xattr(src_path: &Path, dst_path: &Path) -> Resul
         return Ok(());
     };
 
-    // Copy each extended attribute
-    for name in xattr_names {
+    // Copy each extended attribute; ACLs are copied by --acls, after
+    // permissions (see `copy_acl_fd`)
+    for name in xattr_names
+        .into_iter()
+        .filter(|name| !compio_fs_extended::acl::is_acl_xattr(name))
+    {
         match extended_src.get_xattr(&name).await {
             Ok(value) => {
                 if let Err(e) = extended_dst.set_xattr(&name, &value).await {
                     // Log warning but continue with other xattrs
-                    tracing::warn!(
-                        "Failed to preserve directory extended attribute '{}': {}",
-                        name,
-                        e
+                    warnings.warn(
+                        "directory xattr not preserved",
+                        dst_path,
+                        format_args!(
+                            "Failed to preserve directory extended attribute '{name}' on {}: {e}",
+                            dst_path.display()
+                        ),
                     );
                 }
             }
             Err(e) => {
-                tracing::warn!(
-                    "Failed to read directory extended attribute '{}': {}",
-                    name,
-                    e
+                warnings.warn(
+                    "directory xattr not readable",
+                    src_path,
+                    format_args!(
+                        "Failed to read directory extended attribute '{name}' of {}: {e}",
+                        src_path.display()
+                    ),
                 );
             }
         }
@@ -138,6 +155,18 @@ pub async fn preserve_directory_metadata_fd(
     // Get underlying File from DirectoryFd for metadata operations
     let dst_file = dst_dir_fd.as_file();
 
+    // Default ACL first: children created after this returns inherit it
+    // directly instead of being fixed up afterwards
+    let acl_src = if metadata_config.should_preserve_acls() {
+        let src_dir = compio::fs::File::open(src_path).await.map_err(|e| {
+            SyncError::FileSystem(format!("Failed to open source directory for ACLs: {e}"))
+        })?;
+        copy_acl_fd(src_dir.as_raw_fd(), dst_file.as_raw_fd(), AclKind::Default)?;
+        Some(src_dir)
+    } else {
+        None
+    };
+
     // Preserve directory permissions if requested
     if metadata_config.should_preserve_permissions() {
         let mode = extended_metadata.permissions();
@@ -158,11 +187,16 @@ pub async fn preserve_directory_metadata_fd(
         );
     }
 
-    // Preserve directory ownership if requested
-    if metadata_config.should_preserve_ownership() {
-        let source_uid = extended_metadata.uid;
-        let source_gid = extended_metadata.gid;
+    // Access ACL after chmod, which would otherwise rewrite its mask entry
+    if let Some(src_dir) = &acl_src {
+        copy_acl_fd(src_dir.as_raw_fd(), dst_file.as_raw_fd(), AclKind::Access)?;
+        debug!("Preserved directory ACLs for {}", dst_path.display());
+    }
 
+    // Preserve directory ownership if requested
+    if let Some((source_uid, source_gid)) =
+        metadata_config.ownership(extended_metadata.uid, extended_metadata.gid)
+    {
         // Use FD-based fchown (TOCTOU-safe!)
         dst_file.fchown(source_uid, source_gid).await.map_err(|e| {
             SyncError::FileSystem(format!("Failed to preserve directory ownership: {e}"))
@@ -176,8 +210,8 @@ pub async fn preserve_directory_metadata_fd(
         );
     }
 
-    // Preserve directory timestamps if requested
-    if metadata_config.should_preserve_timestamps() {
+    // Preserve directory timestamps if requested (and not --omit-dir-times)
+    if metadata_config.should_preserve_dir_timestamps() {
         let src_accessed = extended_metadata.accessed;
         let src_modified = extended_metadata.modified;
 
@@ -194,10 +228,15 @@ pub async fn preserve_directory_metadata_fd(
 
     // Preserve directory extended attributes if requested
     if metadata_config.should_preserve_xattrs() {
-        preserve_directory_xattr(src_path, dst_path).await?;
+        preserve_directory_xattr(src_path, dst_path, &metadata_config.run.warnings).await?;
         debug!("Preserved directory xattrs for {}", dst_path.display());
     }
 
+    // --overlay-layer: opaque and redirect markers, with or without -X
+    if metadata_config.overlay_layer {
+        crate::overlay::copy_directory_markers(src_path, dst_file, dst_path).await?;
+    }
+
     Ok(())
 }
 
@@ -7,19 +7,36 @@
 //! # Module Organization
 //!
 //! - `types`: Core data structures (`FileLocation`, `TraversalContext`, etc.)
+//! - `delete`: Removal of extraneous destination entries (`--delete`)
+//! - `link_dest`: Hard linking unchanged files from earlier snapshots (`--link-dest`)
+//! - `special`: Handling of fifos, sockets and devices (`--special-files`)
+//! - `stale`: Reopen-and-retry of stale (NFS `ESTALE`) directory handles
 //! - `symlink`: Symlink copying and metadata preservation
 //! - `metadata`: Directory metadata preservation operations
 //! - `traversal`: Recursive directory traversal logic
+//! - `update`: Skipping files the destination already has
 //! - `mod`: Public API and module coordination (this file)
 
+mod delete;
+mod link_dest;
 mod metadata;
+mod special;
+mod stale;
 mod symlink;
 mod traversal;
 mod types;
+mod update;
 
 // Re-export public types
 #[allow(unused_imports)] // Used by external modules
-pub use types::{metadata_from_path, DirectoryStats, FileLocation, TraversalContext};
+pub use delete::DeleteTiming;
+#[allow(unused_imports)] // Used by external modules
+pub use types::{
+    metadata_from_path, CrtimeCounts, CrtimeStatus, DirectoryStats, FileAllocation, FileLocation,
+    SpecialFileCounts, SpecialKind, TraversalContext, WriteAmplification,
+};
+#[allow(unused_imports)] // Used by external modules
+pub use update::UpdateCheck;
 
 // Re-export public functions
 #[allow(unused_imports)] // Used by external modules
@@ -27,17 +44,24 @@ pub use metadata::{
     preserve_directory_metadata, preserve_directory_metadata_fd, preserve_directory_xattr,
 };
 
-use crate::cli::{Args, CopyMethod};
+use crate::cli::CopyMethod;
+use crate::config::SyncConfig;
+use crate::deletion::{Deleter, DEFAULT_MAX_DELETES_IN_FLIGHT};
+use crate::encryption::Encryption;
 use crate::error::{Result, SyncError};
+use crate::filter::FilterRules;
 use crate::hardlink_tracker::FilesystemTracker;
 use crate::io_uring::FileOperations;
+use crate::itemize::Itemizer;
+use crate::long_names::LongNameMapper;
 use std::path::Path;
-use tracing::{debug, info};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
 
 /// Copy an entire directory tree from source to destination
 ///
 /// Recursively copies all files, directories, and symlinks from `src` to `dst`,
-/// preserving metadata according to the configuration in `args`. Uses async
+/// preserving metadata according to the configuration in `config`. Uses async
 /// `io_uring` operations for optimal performance and TOCTOU-safe DirectoryFd-based
 /// operations for security.
 ///
@@ -47,7 +71,7 @@ use tracing::{debug, info};
 /// * `dst` - Destination directory path to copy to
 /// * `file_ops` - File operations handler containing copy configuration
 /// * `_copy_method` - Copy method (e.g., auto, `copy_file_range`, splice)
-/// * `args` - Command-line arguments containing metadata and concurrency config
+/// * `config` - Sync configuration (metadata, concurrency, traversal settings)
 ///
 /// # Returns
 ///
@@ -68,10 +92,13 @@ pub async fn copy_directory(
     dst: &Path,
     file_ops: &FileOperations,
     _copy_method: CopyMethod,
-    args: &Args,
+    config: &SyncConfig,
 ) -> Result<DirectoryStats> {
     let mut stats = DirectoryStats::default();
-    let mut hardlink_tracker = FilesystemTracker::new();
+    let mut hardlink_tracker = config.concurrency.hardlink_store_config().map_or_else(
+        FilesystemTracker::new,
+        FilesystemTracker::with_compact_store,
+    );
 
     info!(
         "Starting directory copy from {} to {}",
@@ -79,9 +106,19 @@ pub async fn copy_directory(
         dst.display()
     );
 
+    // --dry-run: changes are itemized on stdout instead of made
+    let itemizer = config.output.dry_run.then(|| {
+        Arc::new(
+            Itemizer::stdout(dst)
+                .with_crtimes(config.metadata.crtimes)
+                .with_json(config.output.json),
+        )
+    });
+
     // Create destination directory if it doesn't exist
-    if dst.exists() {
-        // Set source filesystem from root directory (destination already exists)
+    if dst.exists() || itemizer.is_some() {
+        // Set source filesystem from root directory (destination already
+        // exists, or a dry run reports it missing during the traversal)
         let root_metadata = types::metadata_from_path(src).await?;
         hardlink_tracker.set_source_filesystem(root_metadata.dev);
     } else {
@@ -97,25 +134,98 @@ pub async fn copy_directory(
 
         // Preserve root directory metadata (permissions, ownership, timestamps) if requested
         let root_metadata = types::metadata_from_path(src).await?;
-        metadata::preserve_directory_metadata(src, dst, &root_metadata, &args.metadata).await?;
+        metadata::preserve_directory_metadata(src, dst, &root_metadata, &config.metadata).await?;
 
         // Set source filesystem from root directory
         hardlink_tracker.set_source_filesystem(root_metadata.dev);
     }
 
-    // Traverse source directory iteratively using compio's dispatcher
-    traversal::traverse_and_copy_directory_iterative(
+    // --exclude/--include/--filter: excluded subtrees are pruned by the
+    // traversal and kept by --delete
+    let filter = Some(FilterRules::from_config(&config.traversal.filter)?)
+        .filter(|rules| !rules.is_empty())
+        .map(Arc::new);
+
+    // --delete: extraneous destination entries are removed before, during or
+    // after the traversal
+    let delete_timing = config.traversal.delete_timing();
+    let deleter = delete_timing
+        .map(|_| Deleter::new(DEFAULT_MAX_DELETES_IN_FLIGHT))
+        .transpose()?
+        .map(|deleter| Arc::new(deleter.with_itemizer(itemizer.clone())));
+    // --encrypt-to: file contents, and with --encrypt-names entry names, are
+    // encrypted (a dry run records no names)
+    let encryption = Encryption::load(&config.traversal, dst)?.map(|encryption| {
+        Arc::new(if itemizer.is_some() {
+            encryption.without_manifest()
+        } else {
+            encryption
+        })
+    });
+    // Maps source names like the traversal does (recording none in a dry run)
+    let delete_names = || {
+        let long_names = LongNameMapper::new(dst, config.traversal.truncate_long_names)
+            .with_encryption(encryption.clone());
+        if itemizer.is_some() {
+            long_names.without_manifest()
+        } else {
+            long_names
+        }
+    };
+    // A destination missing in a dry run has nothing to delete
+    if let (Some(DeleteTiming::Before), Some(deleter), true) =
+        (delete_timing, &deleter, dst.exists())
+    {
+        delete::delete_extraneous_tree(deleter, src, dst, &delete_names(), filter.as_deref())
+            .await?;
+    }
+
+    // Traverse source directory iteratively using compio's dispatcher; with
+    // --delay-updates the copied files are only put in place once it is done
+    let traversed = traversal::traverse_and_copy_directory_iterative(
         src.to_path_buf(),
         dst.to_path_buf(),
         file_ops,
         _copy_method,
         &mut stats,
         &mut hardlink_tracker,
-        &args.metadata,
-        &args.concurrency,
-        &args.io.parallel,
+        &config.metadata,
+        &config.concurrency,
+        &config.io.parallel,
+        &config.traversal,
+        deleter
+            .as_ref()
+            .filter(|_| delete_timing == Some(DeleteTiming::During))
+            .cloned(),
+        filter.clone(),
+        itemizer.clone(),
+        config.priority.clone(),
+        encryption.clone(),
     )
-    .await?;
+    .await;
+    // Entries already stored under new names are recorded even if it failed
+    let recorded = encryption
+        .as_ref()
+        .map_or(Ok(()), |encryption| encryption.finish());
+    config.metadata.run.delayed.finish(traversed).await?;
+    recorded?;
+
+    // Like rsync, failed entries keep --delete-after from deleting anything
+    // (an unreadable source directory would look empty) unless --ignore-errors
+    if delete_timing == Some(DeleteTiming::After)
+        && stats.errors > 0
+        && !config.traversal.ignore_errors
+    {
+        warn!(
+            "{} entries failed; --delete-after deletes nothing (--ignore-errors to delete anyway)",
+            stats.errors
+        );
+    } else if let (Some(DeleteTiming::After), Some(deleter), true) =
+        (delete_timing, &deleter, dst.exists())
+    {
+        delete::delete_extraneous_tree(deleter, src, dst, &delete_names(), filter.as_deref())
+            .await?;
+    }
 
     // Log hardlink detection results
     let hardlink_stats = hardlink_tracker.get_stats();
@@ -123,6 +233,81 @@ pub async fn copy_directory(
         "Directory copy completed: {} files, {} directories, {} bytes, {} symlinks",
         stats.files_copied, stats.directories_created, stats.bytes_copied, stats.symlinks_processed
     );
+    if stats.specials_created.total() > 0 {
+        info!("Special files recreated: {}", stats.specials_created);
+    }
+    if stats.specials_skipped.total() > 0 {
+        warn!(
+            "Special files skipped: {} (use --devices/--specials to recreate them, or --special-files=placeholder to keep their names)",
+            stats.specials_skipped
+        );
+    }
+    if stats.specials_placeholders.total() > 0 {
+        info!(
+            "Special files replaced by empty placeholders: {}",
+            stats.specials_placeholders
+        );
+    }
+    if let (Some(deleter), false) = (&deleter, config.output.dry_run) {
+        let deleted = deleter.stats();
+        info!(
+            "Deleted {} files and {} directories not present in the source",
+            deleted.files_removed, deleted.dirs_removed
+        );
+    }
+    if stats.files_unchanged > 0 {
+        info!("Skipped {} files already up to date", stats.files_unchanged);
+    }
+    if stats.empty_files > 0 {
+        info!("Created {} zero-byte files", stats.empty_files);
+    }
+    if stats.files_linked > 0 {
+        info!(
+            "Hard linked {} unchanged files from --link-dest",
+            stats.files_linked
+        );
+    }
+    if stats.write_amplification.files > 0 {
+        info!("Write amplification: {}", stats.write_amplification);
+        for file in &stats.write_amplification.worst {
+            info!("  {file}");
+        }
+    }
+    if stats.crtimes.not_preserved > 0 || stats.crtimes.unavailable > 0 {
+        warn!(
+            "Creation times (--crtimes): {} (Linux cannot set a file's creation time; \
+             copies get the time they were created)",
+            stats.crtimes
+        );
+    } else if stats.crtimes.preserved > 0 {
+        info!("Creation times (--crtimes): {}", stats.crtimes);
+    }
+    if stats.stale_recoveries > 0 {
+        info!(
+            "Recovered from stale directory handles (ESTALE) {} times",
+            stats.stale_recoveries
+        );
+    }
+    if stats.symlink_rewrites.rewritten > 0 {
+        let rewrites = &stats.symlink_rewrites;
+        let dangling: Vec<_> = rewrites.still_dangling().collect();
+        info!(
+            "Rewrote the targets of {} symlinks ({} dangling)",
+            rewrites.rewritten,
+            dangling.len()
+        );
+        for (link, target) in dangling {
+            config.metadata.run.warnings.warn(
+                "rewritten symlink dangling",
+                link,
+                format_args!(
+                    "Rewritten symlink {} -> {} points to nothing",
+                    link.display(),
+                    target.display()
+                ),
+            );
+        }
+    }
     if hardlink_stats.hardlink_groups > 0 {
         info!(
             "Hardlink detection: {} unique files, {} hardlink groups, {} total hardlinks",
@@ -131,6 +316,13 @@ pub async fn copy_directory(
             hardlink_stats.total_hardlinks
         );
     }
+    if hardlink_stats.spilled_files > 0 {
+        info!(
+            "Hardlink store: {} completed inodes spilled to disk, {} KiB kept in memory",
+            hardlink_stats.spilled_files,
+            hardlink_stats.store_memory_bytes / 1024
+        );
+    }
 
     Ok(stats)
 }
@@ -139,6 +331,8 @@ mod tests {
     #![allow(clippy::unwrap_used)]
     #![allow(clippy::expect_used)]
     use super::*;
+    use crate::metadata::{SpecialFilePolicy, UnprivilegedOwnership};
+    use crate::run_state::RunState;
     use crate::stats::SharedStats;
     use std::os::unix::fs::MetadataExt;
     use std::sync::Arc;
@@ -237,8 +431,19 @@ mod tests {
                 times: false,
                 group: false,
                 owner: false,
+                no_owner: false,
+                no_group: false,
                 devices: false,
+                specials: false,
+                devices_and_specials: false,
                 fsync: false,
+                syncfs: false,
+                verify_direct: None,
+                atomic_create: None,
+                partial: false,
+                delay_updates: false,
+                special_files: SpecialFilePolicy::Skip,
+                unprivileged_ownership: UnprivilegedOwnership::Permitted,
                 xattrs: false,
                 acls: false,
                 hard_links: false,
@@ -246,6 +451,14 @@ mod tests {
                 crtimes: false,
                 preserve_xattr: false,
                 preserve_acl: false,
+                omit_dir_times: false,
+                omit_link_times: false,
+                symlink_rewrite: Vec::new(),
+                sparse: false,
+                preserve_flags: false,
+                update_immutable: false,
+                overlay_layer: false,
+                run: Default::default(),
             },
             Arc::new(SharedStats::new(&stats)),
         )
@@ -287,8 +500,19 @@ mod tests {
                 times: false,
                 group: false,
                 owner: false,
+                no_owner: false,
+                no_group: false,
                 devices: false,
+                specials: false,
+                devices_and_specials: false,
                 fsync: false,
+                syncfs: false,
+                verify_direct: None,
+                atomic_create: None,
+                partial: false,
+                delay_updates: false,
+                special_files: SpecialFilePolicy::Skip,
+                unprivileged_ownership: UnprivilegedOwnership::Permitted,
                 xattrs: false,
                 acls: false,
                 hard_links: false,
@@ -296,6 +520,14 @@ mod tests {
                 crtimes: false,
                 preserve_xattr: false,
                 preserve_acl: false,
+                omit_dir_times: false,
+                omit_link_times: false,
+                symlink_rewrite: Vec::new(),
+                sparse: false,
+                preserve_flags: false,
+                update_immutable: false,
+                overlay_layer: false,
+                run: Default::default(),
             },
             Arc::new(SharedStats::new(&stats)),
         )
@@ -332,8 +564,14 @@ mod tests {
         // Test handle_existing_hardlink
         let stats = Arc::new(SharedStats::new(&DirectoryStats::default()));
 
-        let result =
-            traversal::handle_existing_hardlink(&link_file, &dst_file, 12345, &stats).await;
+        let result = traversal::handle_existing_hardlink(
+            &link_file,
+            &dst_file,
+            12345,
+            &stats,
+            &RunState::default(),
+        )
+        .await;
 
         assert!(result.is_ok(), "handle_existing_hardlink should succeed");
 
@@ -367,8 +605,14 @@ mod tests {
         let stats = Arc::new(SharedStats::new(&DirectoryStats::default()));
 
         // Try to create hardlink to nonexistent file - should fail
-        let result =
-            traversal::handle_existing_hardlink(&link_file, &nonexistent, 12345, &stats).await;
+        let result = traversal::handle_existing_hardlink(
+            &link_file,
+            &nonexistent,
+            12345,
+            &stats,
+            &RunState::default(),
+        )
+        .await;
 
         assert!(
             result.is_err(),
@@ -413,8 +657,14 @@ mod tests {
         let stats = Arc::new(SharedStats::new(&DirectoryStats::default()));
 
         // Try to create hardlink in read-only directory - should fail
-        let result =
-            traversal::handle_existing_hardlink(&link_file, &dst_file, 12345, &stats).await;
+        let result = traversal::handle_existing_hardlink(
+            &link_file,
+            &dst_file,
+            12345,
+            &stats,
+            &RunState::default(),
+        )
+        .await;
 
         assert!(
             result.is_err(),
@@ -0,0 +1,236 @@
+//! Handling of special files: fifos, sockets and devices
+//!
+//! With `--specials` (fifos and sockets) and `--devices` (block and character
+//! devices; both implied by `-D` and `-a`), special files are recreated at the
+//! destination with `mknodat`, keeping their type and device number, and get
+//! the source's permissions, ownership and timestamps as configured. Creating
+//! devices needs root, so without it they are treated as not copied; with
+//! `--overlay-layer` whiteouts are always recreated (see `overlay`).
+//!
+//! Special files that are not copied are not left out silently: each one is
+//! either skipped with a warning or replaced by an empty regular file of the
+//! same name (`--special-files`), and counted per type in the stats.
+
+use super::types::{FileLocation, SpecialKind};
+use crate::error::{ErrorContext, Result};
+use crate::itemize::Itemizer;
+use crate::metadata::{preserve_timestamps_from_fd, MetadataConfig, SpecialFilePolicy};
+use crate::stats::SharedStats;
+use compio_fs_extended::FileMetadata;
+use tracing::debug;
+
+/// Recreate a special file, or apply the `--special-files` policy to it
+///
+/// With an `itemizer` (`--dry-run`), the file or placeholder is reported
+/// instead of created; `dst_missing` means the destination's directory does
+/// not exist.
+///
+/// # Errors
+///
+/// Returns an error if the special file or placeholder cannot be created or
+/// its permissions or timestamps cannot be set.
+#[allow(clippy::future_not_send)]
+#[allow(clippy::too_many_arguments)] // Mirrors the traversal context it is called from
+pub(super) async fn process_special_file(
+    src: &FileLocation,
+    dst: &FileLocation,
+    kind: SpecialKind,
+    metadata: &FileMetadata,
+    metadata_config: &MetadataConfig,
+    stats: &SharedStats,
+    itemizer: Option<&Itemizer>,
+    dst_missing: bool,
+) -> Result<()> {
+    let src_path = src.path.to_path_buf();
+    // --overlay-layer: whiteouts are part of the layer, and creating one
+    // needs no privileges
+    let whiteout = metadata_config.overlay_layer && crate::overlay::is_whiteout(metadata);
+    let requested = if kind.is_device() {
+        whiteout || metadata_config.should_preserve_devices()
+    } else {
+        metadata_config.should_preserve_specials()
+    };
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let permitted = !kind.is_device() || whiteout || unsafe { libc::geteuid() } == 0;
+    if requested && permitted {
+        let existing = if dst_missing {
+            None
+        } else {
+            dst.parent_dir.statx_full(dst.filename()).await.ok()
+        };
+        return recreate_special_file(
+            src,
+            dst,
+            kind,
+            metadata,
+            existing.as_ref(),
+            metadata_config,
+            stats,
+            itemizer,
+        )
+        .await;
+    }
+
+    match metadata_config.special_files {
+        SpecialFilePolicy::Skip => {
+            let reason = if requested {
+                "devices can only be created as root"
+            } else {
+                "special files are not copied"
+            };
+            metadata_config.run.warnings.warn(
+                "special file skipped",
+                &src_path,
+                format_args!("Skipping {} {}: {reason}", kind.name(), src_path.display()),
+            );
+            stats.increment_special_skipped(kind);
+            Ok(())
+        }
+        SpecialFilePolicy::Placeholder => {
+            let dst_path = dst.path.to_path_buf();
+            if let Some(itemizer) = itemizer {
+                itemizer.placeholder(&dst_path, kind.name());
+                stats.increment_special_placeholder(kind);
+                return Ok(());
+            }
+            let placeholder = dst
+                .parent_dir
+                .open_file_at(dst.filename(), false, true, true, true)
+                .await
+                .map_err(|e| {
+                    ErrorContext::new("create special file placeholder")
+                        .source(&src_path)
+                        .destination(&dst_path)
+                        .dirfd(dst.parent_dir.path())
+                        .cause(&e)
+                        .file_system()
+                })?;
+
+            if metadata_config.should_preserve_permissions() {
+                use std::os::unix::fs::PermissionsExt;
+                placeholder
+                    .set_permissions(compio::fs::Permissions::from_mode(metadata.mode & 0o7777))
+                    .await
+                    .map_err(|e| {
+                        ErrorContext::new("fchmod special file placeholder")
+                            .destination(&dst_path)
+                            .io_cause(&e)
+                            .file_system()
+                    })?;
+            }
+            if metadata_config.should_preserve_timestamps() {
+                preserve_timestamps_from_fd(&placeholder, metadata.accessed, metadata.modified)
+                    .await?;
+            }
+
+            debug!(
+                "Created empty placeholder {} for {} {}",
+                dst_path.display(),
+                kind.name(),
+                src_path.display()
+            );
+            stats.increment_special_placeholder(kind);
+            Ok(())
+        }
+    }
+}
+
+/// Create `dst` as a special file of the same type (and device number) as
+/// `src`, with its metadata as configured
+///
+/// An `existing` destination of the same type and device number is kept and
+/// only has its metadata updated; anything else in the way is replaced.
+#[allow(clippy::future_not_send)]
+#[allow(clippy::too_many_arguments)]
+async fn recreate_special_file(
+    src: &FileLocation,
+    dst: &FileLocation,
+    kind: SpecialKind,
+    metadata: &FileMetadata,
+    existing: Option<&FileMetadata>,
+    metadata_config: &MetadataConfig,
+    stats: &SharedStats,
+    itemizer: Option<&Itemizer>,
+) -> Result<()> {
+    let src_path = src.path.to_path_buf();
+    let dst_path = dst.path.to_path_buf();
+    let name = dst.filename();
+    let file_type = metadata.mode & libc::S_IFMT;
+
+    let up_to_date = existing.is_some_and(|existing| {
+        existing.mode & libc::S_IFMT == file_type && existing.rdev == metadata.rdev
+    });
+    if let Some(itemizer) = itemizer {
+        if !up_to_date {
+            itemizer.special(&dst_path, kind.is_device());
+            stats.increment_special_created(kind);
+        }
+        return Ok(());
+    }
+
+    if !up_to_date {
+        if existing.is_some() {
+            compio::fs::remove_file(&dst_path).await.map_err(|e| {
+                ErrorContext::new("remove existing destination")
+                    .destination(&dst_path)
+                    .io_cause(&e)
+                    .file_system()
+            })?;
+        }
+        dst.parent_dir
+            .mknodat(name, file_type | (metadata.mode & 0o777), metadata.rdev)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("mknodat special file")
+                    .source(&src_path)
+                    .destination(&dst_path)
+                    .dirfd(dst.parent_dir.path())
+                    .cause(&e)
+                    .file_system()
+            })?;
+    }
+
+    // Ownership before permissions: chown clears the setuid and setgid bits
+    let name_str = name.to_string_lossy();
+    if let Some((uid, gid)) = metadata_config.ownership(metadata.uid, metadata.gid) {
+        if let Err(e) = dst.parent_dir.lfchownat(&name_str, uid, gid).await {
+            // Like files and symlinks, not fatal without the privileges
+            debug!(
+                "Could not preserve ownership of {} (may need root): {}",
+                dst_path.display(),
+                e
+            );
+        }
+    }
+    if metadata_config.should_preserve_permissions() {
+        dst.parent_dir
+            .fchmodat(name, metadata.mode & 0o7777)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("fchmodat special file")
+                    .destination(&dst_path)
+                    .cause(&e)
+                    .file_system()
+            })?;
+    }
+    if metadata_config.should_preserve_timestamps() {
+        dst.parent_dir
+            .lutimensat(&name_str, metadata.accessed, metadata.modified)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("set special file timestamps")
+                    .destination(&dst_path)
+                    .cause(&e)
+                    .file_system()
+            })?;
+    }
+
+    debug!(
+        "Recreated {} {} as {}",
+        kind.name(),
+        src_path.display(),
+        dst_path.display()
+    );
+    stats.increment_special_created(kind);
+    Ok(())
+}
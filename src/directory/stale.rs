@@ -0,0 +1,140 @@
+//! Recovery from stale directory handles (NFS `ESTALE`)
+//!
+//! On NFS, a directory file descriptor held open across server-side changes
+//! (the export re-exported, the server restarted without stable file handles,
+//! the directory replaced) fails every later call with `ESTALE`, even though
+//! the directory is still reachable by path. Operations relative to such a
+//! `DirectoryFd` are retried through [`retry_stale`]: the handles are reopened
+//! by path and the operation re-run, up to [`MAX_STALE_RETRIES`] times, and
+//! each recovery is counted in the statistics instead of failing the subtree.
+//! Every retry is counted in the run's metrics as well.
+
+use crate::error::{ErrorContext, Result};
+use crate::metrics::Metrics;
+use crate::stats::SharedStats;
+use compio_fs_extended::DirectoryFd;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Reopen-and-retry attempts per operation before its `ESTALE` is returned
+pub(super) const MAX_STALE_RETRIES: u32 = 3;
+
+/// Run `op` on `dirs`, reopening them and retrying while it fails with `ESTALE`
+///
+/// Which of the handles went stale is not known, so all of them are reopened
+/// by the path they were opened on. The caller's handles are left as they
+/// are; only `op` sees the fresh ones.
+///
+/// # Errors
+///
+/// Returns the error of `op` once it fails with anything but `ESTALE` or
+/// retries are exhausted, or an error if a directory cannot be reopened.
+#[allow(clippy::future_not_send)]
+pub(super) async fn retry_stale<const N: usize, T, F, Fut>(
+    dirs: [&Arc<DirectoryFd>; N],
+    stats: &SharedStats,
+    metrics: &Metrics,
+    mut op: F,
+) -> Result<T>
+where
+    F: FnMut([Arc<DirectoryFd>; N]) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut dirs = dirs.map(Arc::clone);
+    let mut attempt = 0;
+    loop {
+        match op(dirs.clone()).await {
+            Err(e) if e.is_stale() && attempt < MAX_STALE_RETRIES => {
+                attempt += 1;
+                metrics.retried();
+                warn!("Stale directory handle ({e}); reopening, attempt {attempt}/{MAX_STALE_RETRIES}");
+                for dir in &mut dirs {
+                    *dir = reopen(dir).await?;
+                }
+            }
+            result => {
+                if attempt > 0 && result.is_ok() {
+                    stats.increment_stale_recoveries();
+                }
+                return result;
+            }
+        }
+    }
+}
+
+/// Open the directory `dir` was opened on again
+async fn reopen(dir: &DirectoryFd) -> Result<Arc<DirectoryFd>> {
+    let fresh = DirectoryFd::open(dir.path()).await.map_err(|e| {
+        ErrorContext::new("reopen stale directory")
+            .dirfd(dir.path())
+            .cause(&e)
+            .file_system()
+    })?;
+    Ok(Arc::new(fresh))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::directory::DirectoryStats;
+    use crate::error::SyncError;
+    use std::cell::Cell;
+    use tempfile::TempDir;
+
+    fn stale() -> SyncError {
+        ErrorContext::new("statx")
+            .io_cause(&std::io::Error::from_raw_os_error(libc::ESTALE))
+            .file_system()
+    }
+
+    #[compio::test]
+    async fn test_retry_stale_reopens_and_counts_recovery() {
+        let dir_path = TempDir::new().unwrap();
+        let dir = Arc::new(DirectoryFd::open(dir_path.path()).await.unwrap());
+        let stats = SharedStats::new(&DirectoryStats::default());
+        let calls = Cell::new(0);
+        let original = &dir;
+
+        let metrics = Metrics::new();
+
+        let result = retry_stale([&dir], &stats, &metrics, |[fresh]| {
+            calls.set(calls.get() + 1);
+            let first = calls.get() == 1;
+            async move {
+                if first {
+                    assert!(Arc::ptr_eq(&fresh, original));
+                    return Err(stale());
+                }
+                assert!(!Arc::ptr_eq(&fresh, original));
+                Ok(fresh.path().to_path_buf())
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), dir_path.path());
+        assert_eq!(stats.snapshot().stale_recoveries, 1);
+        assert!(metrics
+            .render()
+            .lines()
+            .any(|l| l == "arsync_retries_total 1"));
+    }
+
+    #[compio::test]
+    async fn test_retry_stale_is_bounded() {
+        let dir_path = TempDir::new().unwrap();
+        let dir = Arc::new(DirectoryFd::open(dir_path.path()).await.unwrap());
+        let stats = SharedStats::new(&DirectoryStats::default());
+        let calls = Cell::new(0);
+
+        let result: Result<()> = retry_stale([&dir], &stats, &Metrics::new(), |_| {
+            calls.set(calls.get() + 1);
+            async { Err(stale()) }
+        })
+        .await;
+        assert!(result.unwrap_err().is_stale());
+        assert_eq!(calls.get(), MAX_STALE_RETRIES + 1);
+        assert_eq!(stats.snapshot().stale_recoveries, 0);
+    }
+}
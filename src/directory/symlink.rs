@@ -1,11 +1,15 @@
 //! Symlink copying and metadata preservation
 
-use crate::error::{Result, SyncError};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::itemize::Itemizer;
 use crate::metadata::MetadataConfig;
 use crate::stats::SharedStats;
+use crate::symlink_rewrite::rewrite;
+use crate::warnings::WarningLog;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing::{debug, error};
+use tracing::debug;
 
 /// Process a symlink by copying it
 ///
@@ -16,7 +20,8 @@ use tracing::{debug, error};
 ///
 /// - **Valid Symlinks**: Copies the symlink with its target preserved
 /// - **Broken Symlinks**: Copies the symlink with its broken target preserved
-/// - **Target Preservation**: The symlink target is read and recreated exactly
+/// - **Target Preservation**: The symlink target is read and recreated exactly,
+///   unless a `--symlink-rewrite` rule matches it
 ///
 /// # Parameters
 ///
@@ -43,32 +48,115 @@ pub(super) async fn process_symlink(
 ) -> Result<()> {
     debug!("Processing symlink: {}", src_path.display());
 
-    match copy_symlink(&src_path, &dst_path, metadata_config).await {
+    match copy_symlink(&src_path, &dst_path, metadata_config, &stats).await {
         Ok(()) => {
             stats.increment_symlinks_processed();
             Ok(())
         }
         Err(e) => {
+            // Logged (deduplicated) by the directory that contains it
             stats.increment_errors();
-            error!("Failed to copy symlink {}: {}", src_path.display(), e);
             Err(e)
         }
     }
 }
 
+/// Report the copy of the symlink `src` to `dst` (`--dry-run`)
+///
+/// A destination symlink that already points to the same (possibly
+/// rewritten) target is left alone and not reported. `dst_missing` means the
+/// destination's directory does not exist.
+///
+/// # Errors
+///
+/// Returns an error if the source symlink cannot be read.
+pub(super) fn itemize_symlink(
+    src: &Path,
+    dst: &Path,
+    dst_missing: bool,
+    metadata_config: &MetadataConfig,
+    itemizer: &Itemizer,
+    stats: &SharedStats,
+) -> Result<()> {
+    let target = std::fs::read_link(src).map_err(|e| {
+        ErrorContext::new("readlink")
+            .source(src)
+            .destination(dst)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let target = rewrite(&metadata_config.symlink_rewrite, &target).unwrap_or(target);
+    let existing = if dst_missing {
+        None
+    } else {
+        std::fs::symlink_metadata(dst)
+            .ok()
+            .map(|_| std::fs::read_link(dst).ok())
+    };
+    if existing
+        .as_ref()
+        .is_some_and(|current| current.as_ref() == Some(&target))
+    {
+        return Ok(());
+    }
+    itemizer.symlink(dst, &target, existing.is_some());
+    stats.increment_symlinks_processed();
+    Ok(())
+}
+
+/// Copy the extended attributes of the symlink `src` to the symlink `dst`
+///
+/// Failures are warned about per attribute and do not fail the symlink, like
+/// xattr failures on files and directories.
+#[allow(clippy::future_not_send)]
+async fn preserve_symlink_xattrs(
+    src: &Path,
+    dst: &Path,
+    src_dir_fd: &compio_fs_extended::DirectoryFd,
+    dst_dir_fd: &compio_fs_extended::DirectoryFd,
+    warnings: &WarningLog,
+) {
+    let (Some(src_name), Some(dst_name)) = (src.file_name(), dst.file_name()) else {
+        return;
+    };
+    // No xattr support, or no attributes: nothing to copy
+    let Ok(attrs) = src_dir_fd.lget_all_xattrs_at(src_name).await else {
+        return;
+    };
+    for (attr, value) in attrs {
+        let result = match value {
+            Ok(value) => dst_dir_fd.lset_xattr_at(dst_name, &attr, &value).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warnings.warn(
+                "symlink xattr not preserved",
+                dst,
+                format_args!(
+                    "Failed to preserve symlink extended attribute '{attr}' on {}: {e}",
+                    dst.display()
+                ),
+            );
+        }
+    }
+}
+
 /// Copy a symlink preserving its target and metadata
 ///
+/// A target matching a `--symlink-rewrite` rule is rewritten, and the link
+/// recorded in `stats`.
+///
 /// Note: Symlinks cannot be opened as file descriptors, so metadata preservation
 /// uses path-based operations (fchmodat with `AT_SYMLINK_NOFOLLOW`, etc.).
 /// This is the lowest-common-denominator for symlinks, but acceptable since
 /// the symlink itself is atomic.
 #[allow(clippy::future_not_send)]
 #[allow(clippy::too_many_lines)] // Metadata preservation adds lines
-#[allow(clippy::cast_sign_loss)] // Timestamps are i64 but Duration needs u64
 pub(super) async fn copy_symlink(
     src: &Path,
     dst: &Path,
     metadata_config: &MetadataConfig,
+    stats: &SharedStats,
 ) -> Result<()> {
     use compio_fs_extended::directory::DirectoryFd;
 
@@ -121,6 +209,17 @@ pub(super) async fn copy_symlink(
             e
         ))
     })?;
+    let rewritten = rewrite(&metadata_config.symlink_rewrite, &target);
+    if let Some(new_target) = &rewritten {
+        debug!(
+            "Rewriting symlink target of {}: {} -> {}",
+            src.display(),
+            target.display(),
+            new_target.display()
+        );
+    }
+    let is_rewritten = rewritten.is_some();
+    let target = rewritten.unwrap_or(target);
 
     // Remove destination if it exists
     if dst.exists() {
@@ -148,6 +247,9 @@ pub(super) async fn copy_symlink(
         })?;
 
     debug!("Copied symlink {} -> {}", dst.display(), target.display());
+    if is_rewritten {
+        stats.record_symlink_rewrite(dst, &target);
+    }
 
     // Preserve symlink metadata using DirectoryFd operations with AT_SYMLINK_NOFOLLOW
     // These operate on the symlink itself, not its target
@@ -161,12 +263,8 @@ pub(super) async fn copy_symlink(
         ))
     })?;
 
-    // Preserve ownership (if requested and we have permissions)
-    if metadata_config.archive || metadata_config.owner || metadata_config.group {
-        use std::os::unix::fs::MetadataExt;
-        let uid = src_metadata.uid();
-        let gid = src_metadata.gid();
-
+    // Preserve ownership (as far as requested and permitted)
+    if let Some((uid, gid)) = metadata_config.ownership(src_metadata.uid(), src_metadata.gid()) {
         // Use lfchownat which doesn't follow symlinks
         if let Err(e) = dst_dir_fd.lfchownat(&dst_name, uid, gid).await {
             // Don't fail if we can't change ownership (common for non-root)
@@ -177,17 +275,29 @@ pub(super) async fn copy_symlink(
         }
     }
 
-    // Preserve timestamps (if requested)
-    if metadata_config.archive || metadata_config.times {
-        use std::os::unix::fs::MetadataExt;
+    // Preserve extended attributes of the symlink itself (security.*,
+    // trusted.*; user.* is not allowed on symlinks), by name relative to the
+    // parent DirectoryFds rather than by path
+    if metadata_config.should_preserve_xattrs() {
+        preserve_symlink_xattrs(
+            src,
+            dst,
+            &src_dir_fd,
+            &dst_dir_fd,
+            &metadata_config.run.warnings,
+        )
+        .await;
+    }
 
+    // Preserve timestamps (if requested and not --omit-link-times)
+    if metadata_config.should_preserve_link_timestamps() {
         // Include nanoseconds for full precision
-        let atime = std::time::UNIX_EPOCH
-            + std::time::Duration::from_secs(src_metadata.atime() as u64)
-            + std::time::Duration::from_nanos(src_metadata.atime_nsec() as u64);
-        let mtime = std::time::UNIX_EPOCH
-            + std::time::Duration::from_secs(src_metadata.mtime() as u64)
-            + std::time::Duration::from_nanos(src_metadata.mtime_nsec() as u64);
+        let atime =
+            compio_fs_extended::Timestamp::new(src_metadata.atime(), src_metadata.atime_nsec())
+                .to_system_time();
+        let mtime =
+            compio_fs_extended::Timestamp::new(src_metadata.mtime(), src_metadata.mtime_nsec())
+                .to_system_time();
 
         // Use lutimensat which doesn't follow symlinks
         dst_dir_fd
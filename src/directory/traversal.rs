@@ -3,21 +3,46 @@
 //! Core recursive directory traversal logic using compio's dispatcher pattern.
 
 use crate::adaptive_concurrency::{check_fd_limits, AdaptiveConcurrencyController};
+use crate::checkpoint::{CheckpointPolicy, Checkpointer};
 use crate::cli::CopyMethod;
 use crate::copy::copy_file_internal;
-use crate::error::{Result, SyncError};
-use crate::hardlink_tracker::FilesystemTracker;
+use crate::deletion::Deleter;
+use crate::encryption::Encryption;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::error_policy::ErrorPolicy;
+use crate::events::{SyncEvent, EVENTS};
+use crate::filter::FilterRules;
+use crate::hardlink_tracker::{FilesystemTracker, InodeInfo};
 use crate::io_uring::FileOperations;
+use crate::itemize::Itemizer;
+use crate::long_names::{name_max, LongNameMapper};
 use crate::metadata::MetadataConfig;
+use crate::path_builder::PathBuilder;
+use crate::pipelines::Pipelines;
+use crate::preread::Prefetcher;
+use crate::priority::JobShare;
+use crate::run_state::RunState;
 use crate::stats::SharedStats;
-use compio::dispatcher::Dispatcher;
+use crate::supervisor::Supervisor;
+use compio_fs_extended::StatxMask;
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::collections::HashSet;
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing::{debug, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
+use super::delete::delete_extraneous_in;
+use super::link_dest::{find_link_target, link_from};
 use super::metadata::preserve_directory_metadata_fd;
-use super::symlink::process_symlink;
-use super::types::{DirectoryStats, FileLocation, TraversalContext};
+use super::special::process_special_file;
+use super::stale::retry_stale;
+use super::symlink::{itemize_symlink, process_symlink};
+use super::types::{
+    CrtimeStatus, DirectoryStats, FileAllocation, FileLocation, SpecialKind, TraversalContext,
+};
+use super::update::{is_up_to_date, UpdateCheck};
 
 /// Directory traversal using compio's dispatcher for iterative processing
 ///
@@ -37,12 +62,13 @@ pub(super) async fn traverse_and_copy_directory_iterative(
     metadata_config: &MetadataConfig,
     concurrency_config: &crate::cli::ConcurrencyConfig,
     parallel_config: &crate::cli::ParallelCopyConfig,
+    traversal_config: &crate::cli::TraversalConfig,
+    deleter: Option<Arc<Deleter>>,
+    filter: Option<Arc<FilterRules>>,
+    itemizer: Option<Arc<Itemizer>>,
+    priority: Option<Arc<JobShare>>,
+    encryption: Option<Arc<Encryption>>,
 ) -> Result<()> {
-    // Create a dispatcher for async operations
-    // Using Box::leak for &'static lifetime - dispatcher lives for program duration
-    // This is intentional: the dispatcher manages worker threads and should not be dropped
-    let dispatcher = Box::leak(Box::new(Dispatcher::new()?));
-
     // Create Arc-wrapped FileOperations and configs for safe sharing across async tasks
     // No more unsafe transmute needed!
     let file_ops_arc = Arc::new(file_ops.clone());
@@ -54,6 +80,18 @@ pub(super) async fn traverse_and_copy_directory_iterative(
     let shared_stats = Arc::new(SharedStats::new(&stats_value));
     let shared_hardlink_tracker = Arc::new(std::mem::take(hardlink_tracker));
 
+    // Worker threads for async operations; a panic fails only its own entry
+    let supervisor = Arc::new(Supervisor::new(Arc::clone(&shared_stats))?);
+    let errors = Arc::new(ErrorPolicy::new(
+        traversal_config,
+        Arc::clone(&metadata_config.run),
+    ));
+    // Large files get their own workers, in-flight limit and buffers
+    let pipelines = Arc::new(Pipelines::new(
+        concurrency_config,
+        Arc::clone(&shared_stats),
+    )?);
+
     // Check FD limits and warn if too low
     if let Ok(fd_limit) = check_fd_limits() {
         if fd_limit < concurrency_config.max_files_in_flight as u64 {
@@ -76,7 +114,43 @@ pub(super) async fn traverse_and_copy_directory_iterative(
     // unwrap them later to return the final stats. The clone increments ref count,
     // but all child operations complete before we unwrap, so it's just +1/-1.
     // Delegate to root wrapper which handles DirectoryFd setup
-    // Build traversal context
+    // Build traversal context; a dry run reads no file data and records
+    // no progress
+    let dry_run = itemizer.is_some();
+    let preread = traversal_config
+        .preread
+        .filter(|_| !dry_run)
+        .map(|files_ahead| {
+            Prefetcher::start(
+                files_ahead.get(),
+                traversal_config.preread_budget_mb * 1024 * 1024,
+            )
+        })
+        .transpose()?;
+    let checkpoint = traversal_config
+        .state_journal
+        .as_deref()
+        .filter(|_| !dry_run)
+        .map(|journal| {
+            Checkpointer::start(
+                journal,
+                &initial_src,
+                Arc::clone(&shared_stats),
+                CheckpointPolicy {
+                    interval: (traversal_config.checkpoint_secs > 0)
+                        .then(|| Duration::from_secs(traversal_config.checkpoint_secs)),
+                    files: (traversal_config.checkpoint_files > 0)
+                        .then_some(traversal_config.checkpoint_files),
+                },
+            )
+        })
+        .transpose()?;
+
+    let mut long_names = LongNameMapper::new(&initial_dst, traversal_config.truncate_long_names)
+        .with_encryption(encryption.clone());
+    if dry_run {
+        long_names = long_names.without_manifest();
+    }
     let ctx = TraversalContext {
         file_ops: file_ops_arc,
         copy_method: _copy_method,
@@ -85,10 +159,66 @@ pub(super) async fn traverse_and_copy_directory_iterative(
         concurrency_controller,
         metadata_config: metadata_config_arc,
         parallel_config: parallel_config_arc,
-        dispatcher,
+        supervisor: Arc::clone(&supervisor),
+        errors: Arc::clone(&errors),
+        cancel: crate::interrupt::token(),
+        pipelines: Arc::clone(&pipelines),
+        traversal_config: Arc::new(traversal_config.clone()),
+        visited_dirs: Arc::new(DashMap::new()),
+        dir_ancestors: Arc::new(Vec::new()),
+        long_names: Arc::new(long_names),
+        preread: preread.clone(),
+        checkpoint: checkpoint.clone(),
+        deleter,
+        filter,
+        src_root: Arc::from(initial_src.as_path()),
+        dst_root: Arc::from(initial_dst.as_path()),
+        encryption,
+        itemizer,
+        priority,
+        dst_missing: false,
+        dereferenced: false,
     };
+    let long_names = Arc::clone(&ctx.long_names);
 
-    let result = process_root_entry(initial_src, initial_dst, ctx).await;
+    let cancel = ctx.cancel.clone();
+    let result = process_root_entry(initial_src, initial_dst, ctx)
+        .await
+        .and_then(|()| errors.outcome());
+    // Stopped by a signal: whatever the entries did, the copy is incomplete
+    let result = if cancel.is_cancelled() {
+        Err(crate::interrupt::interrupted(&shared_stats))
+    } else {
+        result
+    };
+    if let Some(preread) = preread {
+        preread.finish();
+    }
+    // Must run before the stats are unwrapped: the checkpointer holds a reference
+    if let Some(checkpoint) = checkpoint {
+        if let Err(e) = checkpoint.finish(result.is_ok()) {
+            warn!("Failed to write the final checkpoint: {e}");
+        }
+    }
+
+    pipelines.report();
+    metadata_config.run.warnings.report();
+    errors.report();
+    if supervisor.panics() > 0 {
+        warn!(
+            "{} entries failed with a panic and were skipped (restarted workers {} times); please report this as a bug",
+            supervisor.panics(),
+            supervisor.restarts()
+        );
+    }
+
+    if long_names.shortened() > 0 {
+        warn!(
+            "Shortened {} names too long for the destination; original names are listed in {}",
+            long_names.shortened(),
+            long_names.manifest_path().display()
+        );
+    }
 
     // Restore the state
     // This unwraps successfully because the function and all child operations have completed,
@@ -100,6 +230,8 @@ pub(super) async fn traverse_and_copy_directory_iterative(
             )
         })?
         .into_inner();
+    // Every failed entry; the supervisor's count only has the panics among them
+    stats.errors = errors.failed();
     *hardlink_tracker = Arc::try_unwrap(shared_hardlink_tracker).map_err(|_| {
         SyncError::FileSystem(
             "Failed to unwrap Arc<FilesystemTracker> - multiple references exist".to_string(),
@@ -161,10 +293,11 @@ pub(super) async fn open_parent_dirfd(path: &Path) -> Result<Arc<compio_fs_exten
     let dir_fd = compio_fs_extended::DirectoryFd::open(parent)
         .await
         .map_err(|e| {
-            SyncError::FileSystem(format!(
-                "Failed to open parent directory {}: {e}",
-                parent.display()
-            ))
+            ErrorContext::new("open parent directory")
+                .source(path)
+                .dirfd(parent)
+                .cause(&e)
+                .file_system()
         })?;
     Ok(Arc::new(dir_fd))
 }
@@ -174,30 +307,36 @@ pub(super) async fn open_parent_dirfd(path: &Path) -> Result<Arc<compio_fs_exten
 pub(super) async fn process_root_entry(
     src_path: PathBuf,
     dst_path: PathBuf,
-    ctx: TraversalContext,
+    mut ctx: TraversalContext,
 ) -> Result<()> {
     let src_parent_dir = open_parent_dirfd(&src_path).await?;
-    let src_filename = src_path
-        .file_name()
-        .ok_or_else(|| SyncError::FileSystem("No filename".to_string()))?
-        .to_os_string();
+    if src_path.file_name().is_none() {
+        return Err(SyncError::FileSystem("No filename".to_string()));
+    }
 
-    let dst_parent_dir = open_parent_dirfd(&dst_path).await?;
-    let dst_filename = dst_path
-        .file_name()
-        .ok_or_else(|| SyncError::FileSystem("No filename".to_string()))?
-        .to_os_string();
+    // A dry run creates no directories, so the destination's parent may be
+    // missing; then the entry is new and its parent is never looked into
+    ctx.dst_missing |= ctx.itemizer.is_some()
+        && dst_path
+            .parent()
+            .is_some_and(|parent| !parent.as_os_str().is_empty() && !parent.exists());
+    let dst_parent_dir = if ctx.dst_missing {
+        Arc::clone(&src_parent_dir)
+    } else {
+        open_parent_dirfd(&dst_path).await?
+    };
+    if dst_path.file_name().is_none() {
+        return Err(SyncError::FileSystem("No filename".to_string()));
+    }
 
     let src = FileLocation {
-        path: src_path,
+        path: src_path.into(),
         parent_dir: src_parent_dir,
-        filename: src_filename,
     };
 
     let dst = FileLocation {
-        path: dst_path,
+        path: dst_path.into(),
         parent_dir: dst_parent_dir,
-        filename: dst_filename,
     };
 
     process_directory_entry_with_compio(src, dst, ctx).await
@@ -253,102 +392,133 @@ pub(super) async fn process_directory_entry_with_compio(
 
     // Acquire permit from adaptive concurrency controller
     // This prevents unbounded queue growth and adapts to resource constraints (e.g., FD exhaustion)
-    // The permit is held for the entire operation (directory, file, or symlink)
-    let _permit = controller.acquire().await;
+    // The permit is held for the entire operation (directory, file, or symlink);
+    // large files give it up when they move to the large-file pipeline
+    let permit = controller.acquire().await;
+
+    // Resolve the interned paths only now that the entry is being worked on;
+    // while queued it holds just its name and a shared parent prefix
+    let src_path = src.path.to_path_buf();
+    let dst_path = dst.path.to_path_buf();
 
-    // Get comprehensive metadata using io_uring statx via DirectoryFd
+    // Get metadata using io_uring statx via DirectoryFd, requesting only the
+    // fields the metadata flags (and the default size+mtime check) need
     // ✅ ALWAYS uses DirectoryFd - no fallback, no path-based operations!
-    let extended_metadata = src.parent_dir.statx_full(src.filename.as_ref()).await?;
+    let mut mask = ctx.metadata_config.statx_mask();
+    let check = ctx.traversal_config.update_check();
+    // Transformed and encrypted files are checked by modification time even
+    // with --checksum
+    if check == Some(UpdateCheck::SizeAndMtime)
+        || (check.is_some()
+            && (!ctx.traversal_config.transform.is_empty() || ctx.encryption.is_some()))
+    {
+        mask |= StatxMask::MTIME;
+    }
+    let (filename, path) = (src.filename(), &src_path);
+    let extended_metadata = retry_stale(
+        [&src.parent_dir],
+        &ctx.stats,
+        &ctx.metadata_config.run.metrics,
+        |[src_dir]| async move {
+            src_dir.statx_with_mask(filename, mask).await.map_err(|e| {
+                ErrorContext::new("statx")
+                    .source(path)
+                    .dirfd(src_dir.path())
+                    .cause(&e)
+                    .file_system()
+            })
+        },
+    )
+    .await?;
+    EVENTS.emit_with(|| SyncEvent::EntryDiscovered {
+        job: ctx.metadata_config.run.job,
+        path: src_path.clone(),
+        size: extended_metadata.size,
+        is_dir: extended_metadata.is_dir(),
+    });
 
     if extended_metadata.is_dir() {
         // ========================================================================
         // DIRECTORY PROCESSING: Handle directory entries
         // ========================================================================
-        debug!("Processing directory: {}", src.path.display());
-
-        // Try to create destination directory (TOCTOU-safe: no exists() check!)
-        match compio::fs::create_dir(&dst.path).await {
-            Ok(()) => {
-                ctx.stats.increment_directories_created();
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
-                // Something exists - verify it's actually a directory
-                let existing_metadata = compio::fs::metadata(&dst.path).await.map_err(|e| {
-                    SyncError::FileSystem(format!(
-                        "Failed to check existing path {}: {}",
-                        dst.path.display(),
-                        e
-                    ))
-                })?;
-
-                if !existing_metadata.is_dir() {
-                    return Err(SyncError::FileSystem(format!(
-                        "Cannot create directory {}: path exists but is not a directory (is_file: {}, is_symlink: {})",
-                        dst.path.display(),
-                        existing_metadata.is_file(),
-                        existing_metadata.is_symlink()
-                    )));
-                }
+        debug!("Processing directory: {}", src_path.display());
 
-                debug!("Directory already exists: {}", dst.path.display());
-            }
-            Err(e) => {
-                return Err(SyncError::FileSystem(format!(
-                    "Failed to create directory {}: {}",
-                    dst.path.display(),
-                    e
-                )));
-            }
+        let dir_key = InodeInfo {
+            dev: extended_metadata.dev,
+            ino: extended_metadata.ino,
+        };
+        if is_directory_revisit(&ctx, &src, dir_key) {
+            return Ok(());
         }
 
-        // Open the destination directory immediately (for metadata and children)
-        let dst_dir_fd = Arc::new(
-            compio_fs_extended::DirectoryFd::open(&dst.path)
-                .await
-                .map_err(|e| {
-                    SyncError::FileSystem(format!(
-                        "Failed to open destination directory {}: {}",
-                        dst.path.display(),
-                        e
-                    ))
-                })?,
-        );
+        // Create the destination directory, unless this is a dry run
+        let dst_missing = create_destination_dir(&src_path, &dst_path, &ctx).await?;
+
+        // Open the destination directory immediately (for metadata and children);
+        // a dry run has none to open for a missing one, and its children
+        // never look into the placeholder they get instead
+        let dst_dir_fd = if dst_missing {
+            Arc::clone(&dst.parent_dir)
+        } else {
+            Arc::new(
+                compio_fs_extended::DirectoryFd::open(&dst_path)
+                    .await
+                    .map_err(|e| {
+                        ErrorContext::new("open destination directory")
+                            .source(&src_path)
+                            .destination(&dst_path)
+                            .cause(&e)
+                            .file_system()
+                    })?,
+            )
+        };
 
         // ALWAYS preserve directory metadata (whether just created or already existed)
         // This ensures metadata is synchronized even on re-sync operations
-        preserve_directory_metadata_fd(
-            &src.path,
-            &dst.path,
-            &dst_dir_fd,
-            &extended_metadata,
-            &ctx.metadata_config,
-        )
-        .await?;
+        let (paths, metadata) = ((&src_path, &dst_path), &extended_metadata);
+        let metadata_config = &ctx.metadata_config;
+        if ctx.itemizer.is_none() {
+            retry_stale(
+                [&dst_dir_fd],
+                &ctx.stats,
+                &ctx.metadata_config.run.metrics,
+                |[dst_dir]| async move {
+                    preserve_directory_metadata_fd(
+                        paths.0,
+                        paths.1,
+                        &dst_dir,
+                        metadata,
+                        metadata_config,
+                    )
+                    .await
+                },
+            )
+            .await?;
+        }
 
         // Open source directory as DirectoryFd for TOCTOU-safe operations
         let src_dir = Arc::new(
-            compio_fs_extended::DirectoryFd::open(&src.path)
+            compio_fs_extended::DirectoryFd::open(&src_path)
                 .await
                 .map_err(|e| {
-                    SyncError::FileSystem(format!(
-                        "Failed to open source directory {}: {e}",
-                        src.path.display()
-                    ))
+                    ErrorContext::new("open source directory")
+                        .source(&src_path)
+                        .destination(&dst_path)
+                        .cause(&e)
+                        .file_system()
                 })?,
         );
 
-        // Read directory entries using compio-fs-extended wrapper
-        // This abstracts whether read_dir is blocking or uses io_uring (currently blocking due to kernel limitation)
-        // See: compio_fs_extended::directory::read_dir for implementation details and kernel status
-        let entries = compio_fs_extended::directory::read_dir(&src.path)
-            .await
-            .map_err(|e| {
-                SyncError::FileSystem(format!(
-                    "Failed to read directory {}: {}",
-                    src.path.display(),
-                    e
-                ))
-            })?;
+        // List the directory through the fd just opened, so the listing is
+        // of the directory whose children are opened relative to it, even if
+        // its path is swapped meanwhile
+        let entries = src_dir.read_dir_at().await.map_err(|e| {
+            ErrorContext::new("read_dir")
+                .source(&src_path)
+                .dirfd(src_dir.path())
+                .cause(&e)
+                .traversal()
+        })?;
 
         // ========================================================================
         // CONCURRENT PROCESSING: Dispatch all child entries concurrently
@@ -361,107 +531,184 @@ pub(super) async fn process_directory_entry_with_compio(
         // we dispatch all child entries to the same function, creating a tree
         // of concurrent operations that compio manages efficiently
         let _copy_method = ctx.copy_method.clone();
-        for entry_result in entries {
-            let entry = entry_result.map_err(|e| {
-                SyncError::FileSystem(format!("Failed to read directory entry: {e}"))
-            })?;
-            let child_src_path = entry.path();
-            let file_name = child_src_path.file_name().ok_or_else(|| {
-                SyncError::FileSystem(format!("Invalid file name in {}", child_src_path.display()))
-            })?;
-            let child_dst_path = dst.path.join(file_name);
-            let file_name_osstring = file_name.to_os_string();
+        let child_ancestors = {
+            let mut ancestors = Vec::with_capacity(ctx.dir_ancestors.len() + 1);
+            ancestors.extend_from_slice(&ctx.dir_ancestors);
+            ancestors.push(dir_key);
+            Arc::new(ancestors)
+        };
+        // One interned prefix per directory, shared by every child path
+        let src_prefix = src.path.to_prefix();
+        let dst_prefix = dst.path.to_prefix();
+        let dst_name_max = name_max(dst_dir_fd.as_raw_fd());
+        // Destination names of the source's entries, for --delete-during
+        let mut kept = HashSet::new();
+        // Filter rules match source paths relative to the root of the
+        // transfer (destination names may be shortened or encrypted)
+        let relative_dir = src_path.strip_prefix(&*ctx.src_root).unwrap_or(&src_path);
+        let mut relative_entry = PathBuilder::new(relative_dir);
+        // Hardlink farms (rsnapshot-style trees): files whose inode is
+        // already copied are linked from the tracker alone, in one batch
+        let mut links = Vec::new();
+        let mut link_probes = if links_from_tracker(&ctx, &src_dir) {
+            LINK_PROBES
+        } else {
+            0
+        };
+        for entry in &entries {
+            let file_name = entry.file_name();
+            if let Some(filter) = &ctx.filter {
+                // Excluded subtrees are pruned here, before any descent
+                let is_dir = entry.file_type().is_dir();
+                if filter.is_excluded(relative_entry.entry(file_name), is_dir) {
+                    debug!("Excluded: {}", src_path.join(file_name).display());
+                    continue;
+                }
+            }
+            let is_file = entry.file_type().is_file();
+            let copied = if is_file && link_probes > 0 {
+                let copied = ctx
+                    .hardlink_tracker
+                    .copied_dst_path(extended_metadata.dev, entry.ino());
+                // One hit makes this a link farm: look up every file
+                link_probes = if copied.is_some() {
+                    usize::MAX
+                } else {
+                    link_probes - 1
+                };
+                copied
+            } else {
+                None
+            };
+            if let (Some(preread), true, None) = (&ctx.preread, is_file, &copied) {
+                preread.offer(&src_dir, file_name);
+            }
 
             // Dispatch all entries to the same function regardless of type
             // This creates a unified processing pipeline where each entry
             // determines its own processing path (file/dir/symlink)
-            let child_src_path = child_src_path.clone();
-            let child_dst_path = child_dst_path.clone();
-            let ctx_clone = ctx.clone();
-            let src_dir_clone = Arc::clone(&src_dir);
-            let dst_dir_clone = Arc::clone(&dst_dir_fd);
-            let dst_file_name_osstring = file_name.to_os_string();
+            let mut ctx_clone = ctx.clone();
+            ctx_clone.dir_ancestors = Arc::clone(&child_ancestors);
+            ctx_clone.dst_missing = dst_missing;
 
             let child_src = FileLocation {
-                path: child_src_path.clone(),
-                parent_dir: src_dir_clone,
-                filename: file_name_osstring,
+                path: src_prefix.join(file_name),
+                parent_dir: Arc::clone(&src_dir),
             };
 
+            let dst_file_name =
+                ctx.long_names
+                    .map_name(&src_path, &dst_path, file_name, dst_name_max)?;
+            if ctx.deleter.is_some() {
+                kept.insert(dst_file_name.clone());
+            }
             let child_dst = FileLocation {
-                path: child_dst_path.clone(),
-                parent_dir: dst_dir_clone,
-                filename: dst_file_name_osstring,
+                path: dst_prefix.join(&dst_file_name),
+                parent_dir: Arc::clone(&dst_dir_fd),
             };
 
-            let receiver = ctx
-                .dispatcher
-                .dispatch(move || {
-                    process_directory_entry_with_compio(child_src, child_dst, ctx_clone)
-                })
-                .map_err(|e| {
-                    SyncError::FileSystem(format!("Failed to dispatch entry processing: {e:?}"))
-                })?;
-            futures.push(receiver);
+            if let Some(original) = copied {
+                links.push(CopiedLink {
+                    ino: entry.ino(),
+                    original,
+                    src: child_src,
+                    dst: child_dst,
+                    ctx: ctx_clone,
+                });
+            } else {
+                futures.push(run_child(child_src, child_dst, ctx_clone));
+            }
+        }
+
+        // --delete-during: the children are only dispatched (not yet run), so
+        // extraneous entries are gone before any of them is copied
+        if let (Some(deleter), false) = (&ctx.deleter, dst_missing) {
+            let filter = ctx.filter.as_deref().map(|filter| (filter, relative_dir));
+            if let Err(e) = delete_extraneous_in(deleter, &dst_path, &kept, filter).await {
+                ctx.errors.entry_failed(&dst_path, &e);
+            }
         }
 
         // ========================================================================
-        // ERROR HANDLING: Short-circuit on first error
+        // ERROR HANDLING: Failed entries are recorded, not propagated
         // ========================================================================
-        // Use try_join_all to short-circuit on first error
-        // This is crucial for performance: we don't wait for all operations
-        // to complete before checking for errors. As soon as any operation
-        // fails, we cancel the remaining operations and return the error.
-        let _ = futures::future::try_join_all(futures.into_iter().map(|receiver| async move {
-            let _ = receiver.await.map_err(|e| {
-                SyncError::FileSystem(format!(
-                    "Failed to receive result from dispatched operation: {e:?}"
-                ))
-            })?;
-            Ok::<(), SyncError>(())
-        }))
-        .await?;
+        // Wait for every child. A failed entry does not fail its directory:
+        // it is logged (deduplicated per directory) and panics are counted by
+        // the supervisor, so one bad entry cannot take its siblings down with it
+        futures::future::join(
+            futures::future::join_all(futures),
+            link_copied_inodes(links, extended_metadata.dev, &dst_dir_fd),
+        )
+        .await;
     } else if extended_metadata.is_file() {
         // ========================================================================
         // FILE PROCESSING: Handle regular files with hardlink detection
         // ========================================================================
         // Files are processed with hardlink detection to avoid copying
         // the same content multiple times when hardlinks exist
-        process_file(src, dst, extended_metadata, ctx).await?;
+        // Under `arsync serve`, wait for this job's share of the files in
+        // flight it competes for with the other jobs
+        let _share = match &ctx.priority {
+            Some(share) => Some(share.acquire(&src_path).await),
+            None => None,
+        };
+        let pipelines = Arc::clone(&ctx.pipelines);
+        if let Some(large) = pipelines.large(extended_metadata.size) {
+            // Large files wait for a large-file slot instead of holding one
+            // of the small-file pipeline's
+            drop(permit);
+            large
+                .run(&src_path, move || {
+                    process_file(src, dst, extended_metadata, ctx)
+                })
+                .await?;
+        } else {
+            process_file(src, dst, extended_metadata, ctx).await?;
+        }
     } else if extended_metadata.is_symlink() {
         // ========================================================================
         // SYMLINK PROCESSING: Handle symbolic links
         // ========================================================================
-        if ctx.metadata_config.should_preserve_links() {
+        if let (true, Some(itemizer)) = (ctx.metadata_config.should_preserve_links(), &ctx.itemizer)
+        {
+            itemize_symlink(
+                &src_path,
+                &dst_path,
+                ctx.dst_missing,
+                &ctx.metadata_config,
+                itemizer,
+                &ctx.stats,
+            )?;
+        } else if ctx.metadata_config.should_preserve_links() {
             // Copy symlink as symlink (preserve target)
-            process_symlink(src.path, dst.path, &ctx.metadata_config, ctx.stats.clone()).await?;
+            process_symlink(src_path, dst_path, &ctx.metadata_config, ctx.stats.clone()).await?;
         } else {
             // Dereference symlink: recursively process the target
             // This handles files, directories, and even chains of symlinks correctly
             debug!(
                 "Dereferencing symlink (will copy target): {}",
-                src.path.display()
+                src_path.display()
             );
 
             // Read symlink target
-            let target = std::fs::read_link(&src.path).map_err(|e| {
-                SyncError::FileSystem(format!(
-                    "Failed to read symlink {}: {}",
-                    src.path.display(),
-                    e
-                ))
+            let target = std::fs::read_link(&src_path).map_err(|e| {
+                ErrorContext::new("readlink")
+                    .source(&src_path)
+                    .destination(&dst_path)
+                    .io_cause(&e)
+                    .file_system()
             })?;
 
             // Resolve target path (handle relative symlinks)
             let target_path = if target.is_absolute() {
                 target
             } else {
-                src.path
+                src_path
                     .parent()
                     .ok_or_else(|| {
                         SyncError::FileSystem(format!(
                             "Symlink has no parent: {}",
-                            src.path.display()
+                            src_path.display()
                         ))
                     })?
                     .join(target)
@@ -469,24 +716,309 @@ pub(super) async fn process_directory_entry_with_compio(
 
             // Recursively process the target (handles files, dirs, and symlink chains)
             // Use process_root_entry since target path could be anywhere (needs own DirectoryFd setup)
-            let receiver = ctx
-                .dispatcher
-                .dispatch(move || process_root_entry(target_path, dst.path, ctx))
-                .map_err(|e| {
-                    SyncError::FileSystem(format!(
-                        "Failed to dispatch symlink target processing: {e:?}"
-                    ))
-                })?;
-
-            receiver.await.map_err(|e| {
-                SyncError::FileSystem(format!("Symlink target processing failed: {e:?}"))
-            })??;
+            let mut ctx = ctx;
+            ctx.dereferenced = true;
+            let supervisor = Arc::clone(&ctx.supervisor);
+            supervisor
+                .run(&src_path, move || {
+                    process_root_entry(target_path, dst_path, ctx)
+                })
+                .await?;
         }
+    } else if let Some(kind) = SpecialKind::from_mode(extended_metadata.mode) {
+        // ========================================================================
+        // SPECIAL FILE PROCESSING: fifos, sockets and devices (--devices,
+        // --specials, --special-files)
+        // ========================================================================
+        process_special_file(
+            &src,
+            &dst,
+            kind,
+            &extended_metadata,
+            &ctx.metadata_config,
+            &ctx.stats,
+            ctx.itemizer.as_deref(),
+            ctx.dst_missing,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
+/// Run one child entry under the supervisor, recording its failure
+///
+/// Nothing is started once `--max-errors` has been reached or the run is
+/// interrupted.
+async fn run_child(src: FileLocation, dst: FileLocation, ctx: TraversalContext) {
+    if ctx.cancel.is_cancelled() {
+        ctx.stats.increment_not_started();
+        return;
+    }
+    if ctx.errors.stopped() {
+        return;
+    }
+    let errors = Arc::clone(&ctx.errors);
+    let supervisor = Arc::clone(&ctx.supervisor);
+    let path = src.path.to_path_buf();
+    let result = supervisor
+        .run(&path, move || {
+            process_directory_entry_with_compio(src, dst, ctx)
+        })
+        .await;
+    if let Err(e) = &result {
+        errors.entry_failed(&path, e);
+    }
+}
+
+/// Files of a directory looked up in the hardlink tracker before the
+/// directory is taken not to be a hardlink farm
+const LINK_PROBES: usize = 8;
+
+/// `statfs.f_type` of overlayfs, whose `readdir` inode numbers are not the
+/// ones `statx` reports
+const OVERLAYFS_SUPER_MAGIC: i64 = 0x794C_7630;
+/// `statfs.f_type` of FUSE, whose servers may make `readdir` inodes up
+const FUSE_SUPER_MAGIC: i64 = 0x6573_5546;
+
+/// A file whose inode is already copied, to be linked to that copy
+struct CopiedLink {
+    /// Source inode, from the directory entry
+    ino: u64,
+    /// Destination of the inode's copy
+    original: PathBuf,
+    src: FileLocation,
+    dst: FileLocation,
+    ctx: TraversalContext,
+}
+
+/// Whether files in `src_dir` may be resolved from the hardlink tracker
+///
+/// The lookup uses the inode number of the directory entry instead of a
+/// `statx` of the file, so it is off where the two may differ, for dry
+/// runs and `--interactive`, which need each file's metadata, and with
+/// `--delay-updates`, whose copies are not at their names yet.
+fn links_from_tracker(ctx: &TraversalContext, src_dir: &compio_fs_extended::DirectoryFd) -> bool {
+    if ctx.itemizer.is_some()
+        || crate::interactive::prompter().is_some()
+        || ctx.metadata_config.delay_updates
+    {
+        return false;
+    }
+    let mut buf = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: buf is a valid out-pointer for fstatfs
+    if unsafe { libc::fstatfs(src_dir.as_raw_fd(), buf.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    // SAFETY: fstatfs succeeded and initialized buf
+    #[allow(clippy::useless_conversion)] // f_type width is platform-dependent
+    let fs_type = i64::from(unsafe { buf.assume_init() }.f_type) & 0xFFFF_FFFF;
+    !matches!(fs_type, OVERLAYFS_SUPER_MAGIC | FUSE_SUPER_MAGIC)
+}
+
+/// Link the files of a hardlink farm to their inodes' copies
+///
+/// All `linkat`s are submitted at once, with no `statx` of the sources. A
+/// name that already exists is left alone if it is the copy, and goes
+/// through the regular pipeline (update check, replacement) otherwise.
+async fn link_copied_inodes(
+    links: Vec<CopiedLink>,
+    dev: u64,
+    dst_dir: &compio_fs_extended::DirectoryFd,
+) {
+    if links.is_empty() {
+        return;
+    }
+    let outcomes = futures::future::join_all(links.iter().map(|link| {
+        compio_fs_extended::hardlink::linkat(&link.original, dst_dir, link.dst.filename())
+    }))
+    .await;
+    let mut fallbacks = Vec::new();
+    for (link, outcome) in links.into_iter().zip(outcomes) {
+        let src_path = link.src.path.to_path_buf();
+        match outcome {
+            Ok(()) => {
+                debug!(
+                    "Created hardlink: {} -> {}",
+                    link.dst.path.display(),
+                    link.original.display()
+                );
+                link.ctx.hardlink_tracker.count_link(dev, link.ino);
+                link.ctx
+                    .metadata_config
+                    .run
+                    .checksums
+                    .link(&link.dst.path.to_path_buf(), &link.original);
+                link.ctx.stats.increment_files_copied();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                if !is_linked_to(&link.dst, &link.original).await {
+                    fallbacks.push(run_child(link.src, link.dst, link.ctx));
+                    continue;
+                }
+                debug!("Destination is up to date: {}", link.dst.path.display());
+                link.ctx
+                    .metadata_config
+                    .run
+                    .checksums
+                    .link(&link.dst.path.to_path_buf(), &link.original);
+                link.ctx.stats.increment_files_unchanged();
+            }
+            Err(e) => {
+                let error = ErrorContext::new("linkat")
+                    .source(&link.original)
+                    .destination(link.dst.path.to_path_buf())
+                    .io_cause(&e)
+                    .file_system();
+                link.ctx.errors.entry_failed(&src_path, &error);
+                continue;
+            }
+        }
+        EVENTS.emit_with(|| SyncEvent::EntryDiscovered {
+            job: link.ctx.metadata_config.run.job,
+            path: src_path.clone(),
+            size: 0,
+            is_dir: false,
+        });
+        if let Some(checkpoint) = &link.ctx.checkpoint {
+            checkpoint.completed(&src_path);
+        }
+    }
+    futures::future::join_all(fallbacks).await;
+}
+
+/// Whether the existing `dst` is the same file as `original`
+async fn is_linked_to(dst: &FileLocation, original: &Path) -> bool {
+    let (Ok(existing), Ok(copy)) = (
+        dst.parent_dir.statx_full(dst.filename()).await,
+        compio::fs::symlink_metadata(original).await,
+    ) else {
+        return false;
+    };
+    existing.dev == copy.dev() && existing.ino == copy.ino()
+}
+
+/// Check whether a directory has already been visited and should be skipped
+///
+/// Two cases are detected, both keyed by the directory's (dev, ino):
+/// - **Loop**: the directory is one of its own ancestors (e.g. bind-mounted
+///   inside itself). Always skipped, since descending would never terminate.
+/// - **Revisit**: the directory was already copied at another path (a bind
+///   mount elsewhere in the tree). Skipped unless `--follow-bind-mounts`.
+///
+/// Returns `true` (after logging a warning) if the directory should be skipped.
+fn is_directory_revisit(ctx: &TraversalContext, src: &FileLocation, dir_key: InodeInfo) -> bool {
+    if ctx.dir_ancestors.contains(&dir_key) {
+        warn!(
+            "Skipping {}: filesystem loop detected (directory dev={} ino={} is its own ancestor)",
+            src.path.display(),
+            dir_key.dev,
+            dir_key.ino
+        );
+        return true;
+    }
+
+    if ctx.dereferenced || ctx.traversal_config.follow_bind_mounts {
+        return false;
+    }
+
+    match ctx.visited_dirs.entry(dir_key) {
+        Entry::Occupied(first) => {
+            warn!(
+                "Skipping {}: same directory as {} (bind mount?); use --follow-bind-mounts to copy it again",
+                src.path.display(),
+                first.get().display()
+            );
+            true
+        }
+        Entry::Vacant(slot) => {
+            slot.insert(src.path.clone());
+            false
+        }
+    }
+}
+
+/// Create the destination directory, or check that one already exists
+///
+/// A dry run creates nothing: a missing directory is reported and counted,
+/// and `true` returned so its entries are known to be new.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be created, or the path exists
+/// but is not a directory.
+#[allow(clippy::future_not_send)]
+async fn create_destination_dir(
+    src_path: &Path,
+    dst_path: &Path,
+    ctx: &TraversalContext,
+) -> Result<bool> {
+    let stat_error = |e: &std::io::Error| {
+        ErrorContext::new("stat existing destination")
+            .source(src_path)
+            .destination(dst_path)
+            .io_cause(e)
+            .file_system()
+    };
+
+    if let Some(itemizer) = &ctx.itemizer {
+        let existing = if ctx.dst_missing {
+            None
+        } else {
+            match compio::fs::metadata(dst_path).await {
+                Ok(metadata) => Some(metadata),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(stat_error(&e)),
+            }
+        };
+        return match existing {
+            Some(metadata) if !metadata.is_dir() => Err(not_a_directory(dst_path, &metadata)),
+            Some(_) => Ok(false),
+            None => {
+                itemizer.directory(dst_path);
+                ctx.stats.increment_directories_created();
+                Ok(true)
+            }
+        };
+    }
+
+    // Try to create destination directory (TOCTOU-safe: no exists() check!)
+    match compio::fs::create_dir(dst_path).await {
+        Ok(()) => {
+            ctx.stats.increment_directories_created();
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            // Something exists - verify it's actually a directory
+            let existing_metadata = compio::fs::metadata(dst_path)
+                .await
+                .map_err(|e| stat_error(&e))?;
+            if !existing_metadata.is_dir() {
+                return Err(not_a_directory(dst_path, &existing_metadata));
+            }
+
+            debug!("Directory already exists: {}", dst_path.display());
+        }
+        Err(e) => {
+            return Err(ErrorContext::new("mkdir")
+                .source(src_path)
+                .destination(dst_path)
+                .io_cause(&e)
+                .file_system());
+        }
+    }
+    Ok(false)
+}
+
+/// Error for a destination directory path taken by something else
+fn not_a_directory(dst_path: &Path, existing: &compio::fs::Metadata) -> SyncError {
+    SyncError::FileSystem(format!(
+        "Cannot create directory {}: path exists but is not a directory (is_file: {}, is_symlink: {})",
+        dst_path.display(),
+        existing.is_file(),
+        existing.is_symlink()
+    ))
+}
+
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::future_not_send)]
 #[allow(clippy::used_underscore_binding)]
@@ -494,14 +1026,14 @@ pub(super) async fn process_directory_entry_with_compio(
 ///
 /// This handles hardlink detection via `FilesystemTracker`, creating a
 /// hardlink when possible or copying file contents otherwise. On successful
-/// copy/link creation, it updates shared statistics and tracker state.
+/// copy/link creation, it updates shared statistics and tracker state. A file
+/// whose destination is already up to date is skipped before any of that.
 ///
 /// # Parameters
 /// - `src_path`: Source file path to process
 /// - `dst_path`: Destination file path
 /// - `metadata`: Extended source metadata used for decisions (size, inode, links)
 /// - `_file_ops`: File operations handle (reserved for future metadata work)
-/// - `_copy_method`: Copy method placeholder (currently unified to read/write)
 /// - `stats`: Shared stats accumulator updated on success/error
 /// - `hardlink_tracker`: Shared tracker for inode-based hardlink handling
 ///
@@ -514,15 +1046,79 @@ pub(super) async fn process_file(
     metadata: compio_fs_extended::FileMetadata,
     ctx: TraversalContext,
 ) -> Result<()> {
+    if let Some(preread) = &ctx.preread {
+        preread.consumed(&src.parent_dir, src.filename());
+    }
+    let started = Instant::now();
+    let src_path = src.path.to_path_buf();
+    let dst_path = dst.path.to_path_buf();
+    let transform = src_path
+        .strip_prefix(&*ctx.src_root)
+        .ok()
+        .and_then(|relative| crate::transform::find(&ctx.traversal_config.transform, relative));
+    // Transformed or encrypted copies differ from the source in size
+    let rewritten = transform.is_some() || ctx.encryption.is_some();
+    let check =
+        ctx.traversal_config
+            .update_check()
+            .map(|check| if rewritten { UpdateCheck::Mtime } else { check });
+    if let (Some(check), false) = (check, ctx.dst_missing) {
+        if is_up_to_date(check, &src, &metadata, &dst).await {
+            debug!("Destination is up to date: {}", dst_path.display());
+            ctx.metadata_config
+                .run
+                .checksums
+                .record_existing(&dst_path, &dst_path)
+                .await?;
+            ctx.stats.increment_files_unchanged();
+            if let Some(checkpoint) = &ctx.checkpoint {
+                checkpoint.completed(&src_path);
+            }
+            return Ok(());
+        }
+    }
+    if let (Some(prompter), None) = (crate::interactive::prompter(), &ctx.itemizer) {
+        // Only an existing destination needs confirming
+        if let Ok(dst_metadata) = dst.parent_dir.statx_full(dst.filename()).await {
+            if !prompter.confirm_overwrite(&src_path, &metadata, &dst_path, &dst_metadata) {
+                info!("Not overwriting {}", dst_path.display());
+                return Ok(());
+            }
+        }
+    }
+    if !ctx.traversal_config.link_dest.is_empty() && !rewritten {
+        if let Some(target) = find_link_target(&src, &metadata, &dst, &ctx).await {
+            // A dry run only counts the link
+            if ctx.itemizer.is_some()
+                || link_from(&target, &dst, &ctx.metadata_config.run.warnings).await
+            {
+                ctx.metadata_config
+                    .run
+                    .checksums
+                    .record_existing(&dst_path, &target)
+                    .await?;
+                ctx.stats.increment_files_linked();
+                if let Some(checkpoint) = &ctx.checkpoint {
+                    checkpoint.completed(&src_path);
+                }
+                return Ok(());
+            }
+        }
+    }
     debug!(
         "Processing file: {} (link_count: {})",
-        src.path.display(),
+        src_path.display(),
         metadata.nlink
     );
 
     let device_id = metadata.dev;
     let inode_number = metadata.ino;
     let link_count = metadata.nlink;
+    // Parallel chunks of a large file stay on the large-file workers
+    let dispatcher = ctx
+        .pipelines
+        .large(metadata.size)
+        .map_or_else(|| ctx.supervisor.dispatcher(), |large| large.dispatcher());
 
     // RACE-FREE HARDLINK PATTERN: Register and wait if linker
     let is_copier = ctx
@@ -534,24 +1130,12 @@ pub(super) async fn process_file(
         // We're the copier - copy the file and signal completion
         debug!(
             "Copying file content (hardlink copier): {}",
-            src.path.display()
+            src_path.display()
         );
 
         // Copy file with DirectoryFd (TOCTOU-safe, compile-time enforced)
         // CRITICAL: Capture result but don't propagate yet - must signal linkers first!
-        let copy_result = copy_file_internal(
-            &src.path,
-            &dst.path,
-            &ctx.metadata_config,
-            &ctx.parallel_config,
-            ctx.dispatcher,
-            &metadata,
-            &src.parent_dir,
-            src.filename.as_ref(),
-            &dst.parent_dir,
-            dst.filename.as_ref(),
-        )
-        .await;
+        let copy_result = copy_file_fresh(&src, &dst, &metadata, transform, &ctx, dispatcher).await;
 
         // Signal waiting linkers BEFORE propagating errors (prevents deadlock!)
         // Linkers must wake up regardless of copy success/failure
@@ -562,13 +1146,14 @@ pub(super) async fn process_file(
 
         ctx.stats.increment_files_copied();
         ctx.stats.increment_bytes_copied(metadata.size);
-        debug!("Copied file and signaled linkers: {}", dst.path.display());
+        ctx.pipelines.record(metadata.size, started);
+        debug!("Copied file and signaled linkers: {}", dst_path.display());
     } else if link_count > 1 {
         // We're a linker - waiting is already done inside register_file()
         // Get dst_path and create hardlink
         let original_dst = ctx
             .hardlink_tracker
-            .get_dst_path_for_inode(inode_number)
+            .get_dst_path(device_id, inode_number)
             .ok_or_else(|| {
                 SyncError::FileSystem(format!(
                     "BUG: dst_path not set for hardlink inode {inode_number}. \
@@ -578,43 +1163,208 @@ pub(super) async fn process_file(
 
         debug!(
             "Creating hardlink {} → {} (inode: {})",
-            dst.path.display(),
+            dst_path.display(),
             original_dst.display(),
             inode_number
         );
 
         // Create hardlink (will naturally fail if copier failed to create dst file)
-        handle_existing_hardlink(&dst.path, &original_dst, inode_number, &ctx.stats).await?;
+        if let Some(itemizer) = &ctx.itemizer {
+            itemizer.hardlink(&dst_path, &original_dst);
+            ctx.stats.increment_files_copied();
+        } else {
+            handle_existing_hardlink(
+                &dst_path,
+                &original_dst,
+                inode_number,
+                &ctx.stats,
+                &ctx.metadata_config.run,
+            )
+            .await?;
+        }
     } else {
         // Regular file (link_count == 1) - copy normally
         debug!(
             "Copying file content (non-hardlink): {}",
-            src.path.display()
+            src_path.display()
         );
 
         // Copy file with DirectoryFd (TOCTOU-safe, compile-time enforced)
-        copy_file_internal(
-            &src.path,
-            &dst.path,
-            &ctx.metadata_config,
-            &ctx.parallel_config,
-            ctx.dispatcher,
-            &metadata,
-            &src.parent_dir,
-            src.filename.as_ref(),
-            &dst.parent_dir,
-            dst.filename.as_ref(),
-        )
-        .await?;
+        copy_file_fresh(&src, &dst, &metadata, transform, &ctx, dispatcher).await?;
 
         ctx.stats.increment_files_copied();
         ctx.stats.increment_bytes_copied(metadata.size);
-        debug!("Copied file: {}", dst.path.display());
+        ctx.pipelines.record(metadata.size, started);
+        debug!("Copied file: {}", dst_path.display());
     }
 
+    if let Some(checkpoint) = &ctx.checkpoint {
+        checkpoint.completed(&src_path);
+    }
     Ok(())
 }
 
+/// Copy the file at `src` to `dst`, retrying if their directories go stale
+///
+/// With a `--transform` rule the file is piped through its command instead,
+/// and with `--encrypt-to` it is encrypted.
+/// In a dry run the copy is only reported.
+#[allow(clippy::future_not_send)]
+async fn copy_file_fresh(
+    src: &FileLocation,
+    dst: &FileLocation,
+    metadata: &compio_fs_extended::FileMetadata,
+    transform: Option<&crate::transform::TransformRule>,
+    ctx: &TraversalContext,
+    dispatcher: &'static compio::dispatcher::Dispatcher,
+) -> Result<()> {
+    if let Some(itemizer) = &ctx.itemizer {
+        itemize_file(src, dst, metadata, ctx, itemizer).await;
+        return Ok(());
+    }
+    let (src_path, dst_path) = (&src.path.to_path_buf(), &dst.path.to_path_buf());
+    let (src_name, dst_name) = (src.filename(), dst.filename());
+    retry_stale(
+        [&src.parent_dir, &dst.parent_dir],
+        &ctx.stats,
+        &ctx.metadata_config.run.metrics,
+        |[src_dir, dst_dir]| async move {
+            if let Some(rule) = transform {
+                return crate::transform::copy_transformed(
+                    rule,
+                    src_path,
+                    dst_path,
+                    &ctx.metadata_config,
+                    metadata,
+                    &src_dir,
+                    src_name,
+                    &dst_dir,
+                    dst_name,
+                )
+                .await;
+            }
+            if let Some(encryption) = &ctx.encryption {
+                return crate::encryption::copy_encrypted(
+                    encryption,
+                    src_path,
+                    dst_path,
+                    &ctx.metadata_config,
+                    metadata,
+                    &src_dir,
+                    src_name,
+                    &dst_dir,
+                    dst_name,
+                )
+                .await;
+            }
+            copy_file_internal(
+                src_path,
+                dst_path,
+                &ctx.metadata_config,
+                &ctx.parallel_config,
+                &ctx.copy_method,
+                dispatcher,
+                metadata,
+                &src_dir,
+                src_name,
+                &dst_dir,
+                dst_name,
+            )
+            .await
+        },
+    )
+    .await?;
+    if metadata.size == 0 {
+        ctx.stats.increment_empty_files();
+    }
+    // Part of the verbose report only: it costs a statx per file
+    if tracing::enabled!(tracing::Level::INFO) {
+        measure_allocation(dst, ctx).await;
+    }
+    if ctx.metadata_config.crtimes {
+        compare_crtime(dst, metadata, ctx).await;
+    }
+    Ok(())
+}
+
+/// Compare the creation time of the copy at `dst` with the source's
+/// (`--crtimes`), which Linux has no call to set
+#[allow(clippy::future_not_send)]
+async fn compare_crtime(
+    dst: &FileLocation,
+    metadata: &compio_fs_extended::FileMetadata,
+    ctx: &TraversalContext,
+) {
+    let copy = dst
+        .parent_dir
+        .statx_with_mask(dst.filename(), StatxMask::BTIME)
+        .await
+        .ok()
+        .and_then(|copy| copy.created);
+    let status = CrtimeStatus::of(metadata.created, copy);
+    let outcome = match status {
+        CrtimeStatus::Preserved => None,
+        CrtimeStatus::NotPreserved => Some("not preserved"),
+        CrtimeStatus::Unavailable => Some("unavailable"),
+    };
+    if let Some(outcome) = outcome {
+        debug!(
+            "Creation time of {} {outcome}: source {:?}, copy {copy:?}",
+            dst.path.display(),
+            metadata.created
+        );
+    }
+    ctx.stats.record_crtime(status);
+}
+
+/// Record how much space the copy at `dst` takes (`st_blocks` vs size)
+#[allow(clippy::future_not_send)]
+async fn measure_allocation(dst: &FileLocation, ctx: &TraversalContext) {
+    let mask = StatxMask::SIZE | StatxMask::BLOCKS;
+    match dst.parent_dir.statx_with_mask(dst.filename(), mask).await {
+        Ok(metadata) => {
+            let allocation = FileAllocation {
+                path: dst.path.to_path_buf(),
+                logical_bytes: metadata.size,
+                allocated_bytes: metadata.blocks.saturating_mul(512),
+            };
+            debug!("Allocated {allocation}");
+            ctx.stats.record_allocation(allocation);
+        }
+        Err(e) => debug!(
+            "Cannot measure the allocation of {}: {e}",
+            dst.path.display()
+        ),
+    }
+}
+
+/// Report the copy of `src` to `dst` (`--dry-run`)
+#[allow(clippy::future_not_send)]
+async fn itemize_file(
+    src: &FileLocation,
+    dst: &FileLocation,
+    metadata: &compio_fs_extended::FileMetadata,
+    ctx: &TraversalContext,
+    itemizer: &Itemizer,
+) {
+    let existing = if ctx.dst_missing {
+        None
+    } else {
+        dst.parent_dir.statx_full(dst.filename()).await.ok()
+    };
+    // The traversal only stats what it needs; comparing takes all of it
+    let full = match existing {
+        Some(_) => src.parent_dir.statx_full(src.filename()).await.ok(),
+        None => None,
+    };
+    itemizer.file(
+        &dst.path.to_path_buf(),
+        full.as_ref().unwrap_or(metadata),
+        existing.as_ref(),
+        ctx.traversal_config.update_check() == Some(UpdateCheck::Checksum),
+    );
+}
+
 /// Handle creation of a hardlink when the inode has already been copied
 ///
 /// This helper is invoked when a file's inode has been seen previously (i.e.,
@@ -630,6 +1380,8 @@ pub(super) async fn process_file(
 /// - `inode_number`: The inode identifier for the file being processed
 /// - `stats`: Shared statistics tracker used to record successes/errors
 /// - `hardlink_tracker`: Tracker used to look up the original path for this inode
+/// - `run`: State of the run, whose `--delay-updates` staged files the link
+///   may have to join and whose checksum list it goes into
 ///
 /// # Returns
 ///
@@ -653,6 +1405,7 @@ pub(super) async fn handle_existing_hardlink(
     original_dst: &Path,
     _inode_number: u64,
     stats: &Arc<SharedStats>,
+    run: &RunState,
 ) -> Result<()> {
     // Create destination directory if needed
     if let Some(parent) = dst_path.parent() {
@@ -667,8 +1420,19 @@ pub(super) async fn handle_existing_hardlink(
         }
     }
 
+    // With --delay-updates the original may still be staged: link to it
+    // under a temporary name, put in place along with it
+    let staged = run
+        .delayed
+        .staged(original_dst)
+        .zip(dst_path.file_name().map(crate::temp_files::temp_name));
+    let (target, link) = match &staged {
+        Some((staged, temp)) => (staged.as_path(), dst_path.with_file_name(temp)),
+        None => (original_dst, dst_path.to_path_buf()),
+    };
+
     // Create hardlink using compio-fs-extended for io_uring operations
-    compio_fs_extended::hardlink::create_hardlink_at_path(original_dst, dst_path)
+    compio_fs_extended::hardlink::create_hardlink_at_path(target, &link)
         .await
         .map_err(|e| {
             SyncError::FileSystem(format!(
@@ -678,6 +1442,10 @@ pub(super) async fn handle_existing_hardlink(
                 e
             ))
         })?;
+    if let Some((_, temp)) = staged {
+        run.delayed.defer(dst_path.to_path_buf(), temp);
+    }
+    run.checksums.link(dst_path, original_dst);
 
     stats.increment_files_copied();
     debug!(
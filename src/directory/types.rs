@@ -1,31 +1,46 @@
 //! Core types for directory traversal and copying
 //!
 //! This module contains the data structures used throughout directory operations:
-//! - `FileLocation`: Groups path, parent `DirectoryFd`, and filename
+//! - `FileLocation`: Groups interned path and parent `DirectoryFd`
 //! - `TraversalContext`: Shared state passed through recursion
-//! - `DirectoryStats`: Statistics tracking
+//! - `DirectoryStats`: Statistics tracking, with `SpecialFileCounts` per `SpecialKind`
 
 use crate::adaptive_concurrency::AdaptiveConcurrencyController;
 use crate::cli::CopyMethod;
 use crate::error::{Result, SyncError};
+use crate::hardlink_tracker::InodeInfo;
+use crate::interned_path::InternedPath;
 use crate::io_uring::FileOperations;
 use crate::metadata::MetadataConfig;
-use compio::dispatcher::Dispatcher;
+use crate::symlink_rewrite::SymlinkRewrites;
+use dashmap::DashMap;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 /// Location information for a file/directory with `DirectoryFd` context
 ///
-/// Groups the path, parent `DirectoryFd`, and filename together. This triplet appears
+/// Groups the path and parent `DirectoryFd` together. This pair appears
 /// everywhere (src and dst) and should be kept together for TOCTOU-safe operations.
+///
+/// Locations of pending entries wait in the dispatcher queue, so the path is
+/// interned: siblings share their parent's prefix instead of each holding a
+/// full `PathBuf`.
 #[derive(Clone)]
 pub struct FileLocation {
     /// Full path (used only for error messages and logging)
-    pub path: PathBuf,
+    pub path: InternedPath,
     /// Parent directory as `DirectoryFd` (for TOCTOU-safe operations)
     pub parent_dir: Arc<compio_fs_extended::DirectoryFd>,
+}
+
+impl FileLocation {
     /// Filename relative to `parent_dir` (basename only, no path separators)
-    pub filename: std::ffi::OsString,
+    #[must_use]
+    pub fn filename(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
 }
 
 /// Context passed through directory traversal operations
@@ -55,8 +70,56 @@ pub struct TraversalContext {
     pub metadata_config: Arc<MetadataConfig>,
     /// Parallel copy configuration
     pub parallel_config: Arc<crate::cli::ParallelCopyConfig>,
-    /// Global dispatcher for parallel operations
-    pub dispatcher: &'static Dispatcher,
+    /// Dispatcher workers, with panics isolated per entry
+    pub supervisor: Arc<crate::supervisor::Supervisor>,
+    /// Failed entries and what to do about them (`--ignore-errors`, `--max-errors`)
+    pub errors: Arc<crate::error_policy::ErrorPolicy>,
+    /// Stops the traversal before its next entry (Ctrl-C; see `interrupt`)
+    pub cancel: crate::copy_task::CancellationHandle,
+    /// Large-file pipeline and per-pipeline throughput (`--large-file-threshold-mb`)
+    pub pipelines: Arc<crate::pipelines::Pipelines>,
+    /// Traversal configuration (bind mount handling)
+    pub traversal_config: Arc<crate::cli::TraversalConfig>,
+    /// Directories already copied, keyed by (dev, ino), with the first source path seen
+    ///
+    /// A directory can only appear at two paths via a bind mount, so a hit here
+    /// means the tree is being revisited.
+    pub visited_dirs: Arc<DashMap<InodeInfo, InternedPath>>,
+    /// (dev, ino) of every directory between the root and the current entry
+    ///
+    /// Finding the current directory in its own ancestor chain is a true loop
+    /// (e.g. a directory bind-mounted inside itself) that would never terminate.
+    pub dir_ancestors: Arc<Vec<InodeInfo>>,
+    /// Handling of names too long for the destination (`--truncate-long-names`)
+    pub long_names: Arc<crate::long_names::LongNameMapper>,
+    /// Source cache warming ahead of the copy (`--preread`)
+    pub preread: Option<Arc<crate::preread::Prefetcher>>,
+    /// Checkpoints of stats and completed files (`--state-journal`)
+    pub checkpoint: Option<Arc<crate::checkpoint::Checkpointer>>,
+    /// Removal of extraneous entries per directory (`--delete-during`)
+    pub deleter: Option<Arc<crate::deletion::Deleter>>,
+    /// Include/exclude rules (`--exclude`, `--include`, `--filter`)
+    pub filter: Option<Arc<crate::filter::FilterRules>>,
+    /// Source root; filter and `--transform` rules match paths relative to it
+    pub src_root: Arc<Path>,
+    /// Destination root
+    pub dst_root: Arc<Path>,
+    /// Encryption of the copies (`--encrypt-to`)
+    pub encryption: Option<Arc<crate::encryption::Encryption>>,
+    /// Reports changes instead of making them (`--dry-run`)
+    pub itemizer: Option<Arc<crate::itemize::Itemizer>>,
+    /// Share of the files in flight of a service's jobs (`arsync serve`)
+    pub priority: Option<Arc<crate::priority::JobShare>>,
+    /// Whether the destination directory of this entry does not exist
+    ///
+    /// Only in a dry run, which does not create it: the entry is new, and
+    /// the destination `DirectoryFd` it carries must not be looked into.
+    pub dst_missing: bool,
+    /// Whether this entry was reached by dereferencing a symlink
+    ///
+    /// Dereferenced trees are intentionally copied again at the link's path, so
+    /// they are neither recorded in nor checked against `visited_dirs`.
+    pub dereferenced: bool,
 }
 
 /// Directory copy operation statistics
@@ -72,6 +135,265 @@ pub struct DirectoryStats {
     pub symlinks_processed: u64,
     /// Number of errors encountered
     pub errors: u64,
+    /// Special files recreated at the destination (`--devices`, `--specials`)
+    pub specials_created: SpecialFileCounts,
+    /// Special files left out of the destination (`--special-files=skip`)
+    pub specials_skipped: SpecialFileCounts,
+    /// Special files replaced by empty files (`--special-files=placeholder`)
+    pub specials_placeholders: SpecialFileCounts,
+    /// Operations that succeeded after reopening a stale (`ESTALE`) directory
+    pub stale_recoveries: u64,
+    /// Files skipped because the destination was already up to date
+    pub files_unchanged: u64,
+    /// Unchanged files hard linked from a `--link-dest` directory
+    pub files_linked: u64,
+    /// Zero-byte files among `files_copied`
+    pub empty_files: u64,
+    /// Entries not started because the run was interrupted
+    pub not_started: u64,
+    /// Space allocated for the copied files (measured in verbose runs)
+    pub write_amplification: WriteAmplification,
+    /// Symlinks whose target was rewritten (`--symlink-rewrite`)
+    pub symlink_rewrites: SymlinkRewrites,
+    /// Creation times of the copied files, compared with the source's (`--crtimes`)
+    pub crtimes: CrtimeCounts,
+}
+
+/// Type of a special file: anything but a regular file, directory or symlink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKind {
+    /// Named pipe
+    Fifo,
+    /// Unix domain socket
+    Socket,
+    /// Character device
+    CharDevice,
+    /// Block device
+    BlockDevice,
+}
+
+impl SpecialKind {
+    /// Special file type of a `st_mode`, if it is one
+    #[must_use]
+    pub const fn from_mode(mode: u32) -> Option<Self> {
+        match mode & libc::S_IFMT {
+            libc::S_IFIFO => Some(Self::Fifo),
+            libc::S_IFSOCK => Some(Self::Socket),
+            libc::S_IFCHR => Some(Self::CharDevice),
+            libc::S_IFBLK => Some(Self::BlockDevice),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a block or character device (`--devices`), rather
+    /// than a fifo or socket (`--specials`)
+    #[must_use]
+    pub const fn is_device(self) -> bool {
+        matches!(self, Self::CharDevice | Self::BlockDevice)
+    }
+
+    /// Name used in log messages
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Fifo => "fifo",
+            Self::Socket => "socket",
+            Self::CharDevice => "character device",
+            Self::BlockDevice => "block device",
+        }
+    }
+}
+
+/// Special file counts by type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpecialFileCounts {
+    /// Named pipes
+    pub fifos: u64,
+    /// Unix domain sockets
+    pub sockets: u64,
+    /// Character devices
+    pub char_devices: u64,
+    /// Block devices
+    pub block_devices: u64,
+}
+
+impl SpecialFileCounts {
+    /// Total over all types
+    #[must_use]
+    pub const fn total(&self) -> u64 {
+        self.fifos + self.sockets + self.char_devices + self.block_devices
+    }
+}
+
+impl std::fmt::Display for SpecialFileCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} fifos, {} sockets, {} character devices, {} block devices",
+            self.fifos, self.sockets, self.char_devices, self.block_devices
+        )
+    }
+}
+
+/// How the creation (birth) time of a copy compares with its source's
+///
+/// Linux has no call that sets a file's creation time, so a copy normally
+/// gets the time it was created at; `--crtimes` reports how many matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrtimeStatus {
+    /// The copy has the source's creation time
+    Preserved,
+    /// The copy has its own creation time
+    NotPreserved,
+    /// The source or the copy has no creation time (`STATX_BTIME` unsupported)
+    Unavailable,
+}
+
+impl CrtimeStatus {
+    /// Compare the creation time of a `source` with that of its `copy`
+    #[must_use]
+    pub fn of(source: Option<SystemTime>, copy: Option<SystemTime>) -> Self {
+        match (source, copy) {
+            (Some(source), Some(copy)) if source == copy => Self::Preserved,
+            (Some(_), Some(_)) => Self::NotPreserved,
+            _ => Self::Unavailable,
+        }
+    }
+}
+
+/// Copied files counted by `CrtimeStatus`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CrtimeCounts {
+    /// Copies with the source's creation time
+    pub preserved: u64,
+    /// Copies with their own creation time
+    pub not_preserved: u64,
+    /// Files without a creation time to compare
+    pub unavailable: u64,
+}
+
+impl CrtimeCounts {
+    /// Total over all outcomes
+    #[must_use]
+    pub const fn total(&self) -> u64 {
+        self.preserved + self.not_preserved + self.unavailable
+    }
+}
+
+impl std::fmt::Display for CrtimeCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} preserved, {} not preserved, {} unavailable",
+            self.preserved, self.not_preserved, self.unavailable
+        )
+    }
+}
+
+/// Files listed by name in the write amplification report
+pub const WORST_ALLOCATIONS: usize = 10;
+
+/// Space taken at the destination by one copied file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileAllocation {
+    /// Destination path
+    pub path: PathBuf,
+    /// Bytes written (the file's size)
+    pub logical_bytes: u64,
+    /// Bytes allocated by the filesystem (`st_blocks` × 512)
+    pub allocated_bytes: u64,
+}
+
+impl FileAllocation {
+    /// Allocated bytes beyond the file's size
+    #[must_use]
+    pub const fn excess(&self) -> u64 {
+        self.allocated_bytes.saturating_sub(self.logical_bytes)
+    }
+}
+
+impl std::fmt::Display for FileAllocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes written, {} allocated ({})",
+            self.path.display(),
+            self.logical_bytes,
+            self.allocated_bytes,
+            amplification(self.logical_bytes, self.allocated_bytes)
+        )
+    }
+}
+
+/// Bytes allocated at the destination compared to bytes written
+///
+/// Preallocation policy, the filesystem's block size and copy-on-write or
+/// compression make files take more (or less) space than their size; a
+/// ratio well above 1 on large files points at over-allocation.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WriteAmplification {
+    /// Files measured
+    pub files: u64,
+    /// Total bytes written
+    pub logical_bytes: u64,
+    /// Total bytes allocated
+    pub allocated_bytes: u64,
+    /// Files with the most space allocated beyond their size, worst first
+    /// (at most `WORST_ALLOCATIONS`)
+    pub worst: Vec<FileAllocation>,
+}
+
+impl WriteAmplification {
+    /// No files measured yet
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            files: 0,
+            logical_bytes: 0,
+            allocated_bytes: 0,
+            worst: Vec::new(),
+        }
+    }
+
+    /// Add the measurement of one file
+    pub fn record(&mut self, file: FileAllocation) {
+        self.files += 1;
+        self.logical_bytes += file.logical_bytes;
+        self.allocated_bytes += file.allocated_bytes;
+        if file.excess() == 0 {
+            return;
+        }
+        let position = self
+            .worst
+            .partition_point(|worse| worse.excess() >= file.excess());
+        if position < WORST_ALLOCATIONS {
+            self.worst.insert(position, file);
+            self.worst.truncate(WORST_ALLOCATIONS);
+        }
+    }
+}
+
+impl std::fmt::Display for WriteAmplification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files, {} bytes written, {} allocated ({})",
+            self.files,
+            self.logical_bytes,
+            self.allocated_bytes,
+            amplification(self.logical_bytes, self.allocated_bytes)
+        )
+    }
+}
+
+/// Allocated/written ratio for display
+#[allow(clippy::cast_precision_loss)] // Two decimals are plenty
+fn amplification(logical_bytes: u64, allocated_bytes: u64) -> String {
+    if logical_bytes == 0 {
+        "empty".to_string()
+    } else {
+        format!("{:.2}x", allocated_bytes as f64 / logical_bytes as f64)
+    }
 }
 
 // ExtendedMetadata removed - use compio_fs_extended::FileMetadata directly
@@ -105,6 +427,8 @@ pub async fn metadata_from_path(path: &Path) -> Result<compio_fs_extended::FileM
         nlink: compio_metadata.nlink(),
         ino: compio_metadata.ino(),
         dev: compio_metadata.dev(),
+        rdev: compio_metadata.rdev(),
+        blocks: compio_metadata.blocks(),
         accessed: compio_metadata.accessed().unwrap_or(std::time::UNIX_EPOCH),
         modified: compio_metadata.modified().unwrap_or(std::time::UNIX_EPOCH),
         created: compio_metadata.created().ok(),
@@ -118,3 +442,64 @@ pub async fn metadata_from_path(path: &Path) -> Result<compio_fs_extended::FileM
         generation: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(name: &str, logical_bytes: u64, allocated_bytes: u64) -> FileAllocation {
+        FileAllocation {
+            path: PathBuf::from(name),
+            logical_bytes,
+            allocated_bytes,
+        }
+    }
+
+    #[test]
+    fn test_write_amplification_keeps_worst_files() {
+        // Requirement: Totals cover every file; the report names the files
+        // with the most excess allocation, worst first, and no others
+        let mut amplification = WriteAmplification::new();
+        amplification.record(allocation("exact", 4096, 4096));
+        amplification.record(allocation("sparse", 1 << 20, 4096));
+        for i in 0..20 {
+            amplification.record(allocation(&format!("f{i}"), 100, 4096 * (i + 1)));
+        }
+
+        assert_eq!(amplification.files, 22);
+        assert_eq!(amplification.logical_bytes, 4096 + (1 << 20) + 2000);
+        assert_eq!(amplification.worst.len(), WORST_ALLOCATIONS);
+        assert_eq!(amplification.worst[0].path, PathBuf::from("f19"));
+        assert!(amplification
+            .worst
+            .windows(2)
+            .all(|pair| pair[0].excess() >= pair[1].excess()));
+        assert_eq!(
+            allocation("a", 1000, 4096).to_string(),
+            "a: 1000 bytes written, 4096 allocated (4.10x)"
+        );
+    }
+
+    #[test]
+    fn test_crtime_status() {
+        // Requirement: Creation times compare equal only when both are known
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        let later = time + std::time::Duration::from_nanos(1);
+        assert_eq!(
+            CrtimeStatus::of(Some(time), Some(time)),
+            CrtimeStatus::Preserved
+        );
+        assert_eq!(
+            CrtimeStatus::of(Some(time), Some(later)),
+            CrtimeStatus::NotPreserved
+        );
+        assert_eq!(
+            CrtimeStatus::of(None, Some(time)),
+            CrtimeStatus::Unavailable
+        );
+        assert_eq!(
+            CrtimeStatus::of(Some(time), None),
+            CrtimeStatus::Unavailable
+        );
+    }
+}
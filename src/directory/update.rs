@@ -0,0 +1,167 @@
+//! Skipping files the destination already has (update detection)
+//!
+//! Like rsync, a re-run only copies files that changed. Before a regular file
+//! is copied its destination is looked up through the parent `DirectoryFd`:
+//!
+//! - by default, a regular file with the same size and modification time is
+//!   taken to be up to date and skipped;
+//! - with `--checksum`, a file of the same size is skipped only if its
+//!   contents are identical, whatever its modification time;
+//! - with `--ignore-times`, nothing is skipped;
+//! - files piped through a `--transform` command, whose size and contents
+//!   differ from the source's by design, are skipped by modification time
+//!   alone.
+//!
+//! Modification times only match on a re-run if they were preserved
+//! (`--times`/`--archive`); without them every file is copied again.
+
+use super::types::FileLocation;
+use compio::io::AsyncReadAt;
+use compio_fs_extended::FileMetadata;
+use tracing::debug;
+
+/// Bytes compared per read with `--checksum`
+const COMPARE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How an existing destination file is judged up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateCheck {
+    /// Same size and modification time (the default)
+    SizeAndMtime,
+    /// Same size and contents (`--checksum`)
+    Checksum,
+    /// Same modification time, whatever the size (`--transform`)
+    Mtime,
+}
+
+/// Whether `dst` already holds the regular file `src` and need not be copied
+///
+/// A missing or unreadable destination is never up to date; the copy that
+/// follows reports any real problem with it.
+#[allow(clippy::future_not_send)]
+pub(super) async fn is_up_to_date(
+    check: UpdateCheck,
+    src: &FileLocation,
+    src_metadata: &FileMetadata,
+    dst: &FileLocation,
+) -> bool {
+    let Ok(dst_metadata) = dst.parent_dir.statx_full(dst.filename()).await else {
+        return false;
+    };
+    if !dst_metadata.is_file()
+        || (check != UpdateCheck::Mtime && dst_metadata.size != src_metadata.size)
+    {
+        return false;
+    }
+    match check {
+        UpdateCheck::SizeAndMtime | UpdateCheck::Mtime => {
+            dst_metadata.modified == src_metadata.modified
+        }
+        UpdateCheck::Checksum => same_contents(src, dst, src_metadata.size)
+            .await
+            .unwrap_or_else(|e| {
+                debug!(
+                    "Cannot compare {} with {}, copying it: {e}",
+                    src.path.to_path_buf().display(),
+                    dst.path.to_path_buf().display()
+                );
+                false
+            }),
+    }
+}
+
+/// Whether the first `size` bytes of `src` and `dst` are identical
+#[allow(clippy::future_not_send)]
+pub(super) async fn same_contents(
+    src: &FileLocation,
+    dst: &FileLocation,
+    size: u64,
+) -> compio_fs_extended::Result<bool> {
+    let src_file = src
+        .parent_dir
+        .open_file_at(src.filename(), true, false, false, false)
+        .await?;
+    let dst_file = dst
+        .parent_dir
+        .open_file_at(dst.filename(), true, false, false, false)
+        .await?;
+
+    let (mut src_buf, mut dst_buf) = (
+        Vec::with_capacity(COMPARE_CHUNK_SIZE),
+        Vec::with_capacity(COMPARE_CHUNK_SIZE),
+    );
+    let mut offset = 0;
+    while offset < size {
+        src_buf.clear();
+        dst_buf.clear();
+        let (src_read, dst_read) = futures::join!(
+            src_file.read_at(src_buf, offset),
+            dst_file.read_at(dst_buf, offset)
+        );
+        (src_buf, dst_buf) = (src_read.1, dst_read.1);
+        let (src_len, dst_len) = (src_read.0?, dst_read.0?);
+        if src_len == 0 || src_len != dst_len || src_buf != dst_buf {
+            return Ok(false);
+        }
+        offset += src_len as u64;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::interned_path::InternedPath;
+    use compio_fs_extended::DirectoryFd;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn location(dir: &Arc<DirectoryFd>, name: &str) -> FileLocation {
+        FileLocation {
+            path: InternedPath::from(dir.path().join(name).as_path()),
+            parent_dir: Arc::clone(dir),
+        }
+    }
+
+    #[compio::test]
+    async fn test_is_up_to_date() {
+        // Requirement: Size and mtime decide by default, contents with
+        // --checksum; a missing destination is never up to date
+        let temp_dir = TempDir::new().unwrap();
+        let dir = Arc::new(DirectoryFd::open(temp_dir.path()).await.unwrap());
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for (name, contents) in [("src", b"same"), ("same", b"same"), ("diff", b"DIFF")] {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+        let src = location(&dir, "src");
+        let metadata = dir.statx_full(src.filename()).await.unwrap();
+
+        let (same, diff) = (location(&dir, "same"), location(&dir, "diff"));
+        let missing = location(&dir, "missing");
+        assert!(is_up_to_date(UpdateCheck::SizeAndMtime, &src, &metadata, &same).await);
+        assert!(is_up_to_date(UpdateCheck::SizeAndMtime, &src, &metadata, &diff).await);
+        assert!(is_up_to_date(UpdateCheck::Checksum, &src, &metadata, &same).await);
+        assert!(!is_up_to_date(UpdateCheck::Checksum, &src, &metadata, &diff).await);
+        assert!(!is_up_to_date(UpdateCheck::SizeAndMtime, &src, &metadata, &missing).await);
+
+        // A different mtime is enough to copy by default, but not with --checksum
+        std::fs::File::options()
+            .write(true)
+            .open(temp_dir.path().join("same"))
+            .unwrap()
+            .set_modified(mtime + Duration::from_secs(1))
+            .unwrap();
+        assert!(!is_up_to_date(UpdateCheck::SizeAndMtime, &src, &metadata, &same).await);
+        assert!(is_up_to_date(UpdateCheck::Checksum, &src, &metadata, &same).await);
+    }
+}
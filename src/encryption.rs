@@ -0,0 +1,633 @@
+//! Encrypted destinations for untrusted backup targets (`--encrypt-to`)
+//!
+//! With `--encrypt-to RECIPIENT` (an age X25519 public key, `age1...`) every
+//! regular file of a directory copy is stored at the destination as an
+//! [age](https://age-encryption.org) file: X25519 key agreement per
+//! recipient, the contents in ChaCha20-Poly1305 chunks. Only the holder of a
+//! matching identity can read them back, with `arsync restore --identity`.
+//! `--encrypt-identity FILE` makes the public keys of an identity file
+//! recipients too.
+//!
+//! With `--encrypt-names` (which needs `--encrypt-identity`) every entry is
+//! also stored under a random name. The names are recorded in the manifest
+//! `.arsync-encryption` at the destination root, itself age-encrypted to the
+//! same recipients, as `<stored path>\t<original name>` lines like the
+//! long-name manifest (see `long_names`); each run decrypts it to find the
+//! names already given, so unchanged files keep theirs.
+//!
+//! The copy goes through the same staging as `--transform` (see
+//! `transform`), and for the same reason an encrypted file is up to date
+//! when its modification time matches the source's, since the sizes differ.
+//! What the storage still sees: the shape of the tree, approximate file
+//! sizes, permissions, ownership, times and xattrs as preserved, and symlink
+//! targets, which are stored as they are.
+
+use crate::bisync::{escape_path, unescape_path};
+use crate::cli::TraversalConfig;
+use crate::error::{ErrorContext, Result, SyncError};
+use compio_fs_extended::{DirectoryFd, FileMetadata};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::{debug, info, warn};
+
+/// Name of the encrypted name manifest written to the destination root
+pub const MANIFEST_FILE_NAME: &str = ".arsync-encryption";
+
+/// First line of the decrypted manifest
+const MANIFEST_HEADER: &[u8] = b"arsync-encryption v1\n";
+
+/// Random bytes in a stored name (written as hex)
+const NAME_BYTES: usize = 16;
+
+/// Check an age X25519 recipient for the command line
+///
+/// # Errors
+///
+/// Returns a message for the command line if `text` is not an `age1...`
+/// public key.
+pub fn parse_recipient(text: &str) -> std::result::Result<String, String> {
+    age::x25519::Recipient::from_str(text)
+        .map(|_| text.to_string())
+        .map_err(|e| format!("invalid age recipient {text:?}: {e}"))
+}
+
+/// Encryption of a directory copy's contents and, optionally, names
+pub struct Encryption {
+    /// Everyone who can decrypt the files
+    recipients: Vec<age::x25519::Recipient>,
+    /// Stored names, with `--encrypt-names`
+    names: Option<Mutex<NameTable>>,
+    /// Destination root; stored paths are relative to it
+    dst_root: PathBuf,
+    /// Whether the manifest is written (not with `--dry-run`)
+    keep_manifest: bool,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("recipients", &self.recipients.len())
+            .field("names", &self.names.is_some())
+            .field("dst_root", &self.dst_root)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stored names by the stored path of their directory and original name
+#[derive(Debug, Default)]
+struct NameTable {
+    names: BTreeMap<(PathBuf, OsString), OsString>,
+    /// Whether names were given since the manifest was read
+    changed: bool,
+}
+
+impl Encryption {
+    /// Encryption for a copy into `dst_root`, if the configuration asks for it
+    ///
+    /// With `--encrypt-names`, reads the names recorded by earlier runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` if a recipient or the identity file
+    /// is invalid, or an error if the manifest cannot be read or decrypted.
+    pub fn load(config: &TraversalConfig, dst_root: &Path) -> Result<Option<Self>> {
+        if !config.encrypts() {
+            return Ok(None);
+        }
+        let identities = match &config.encrypt_identity {
+            Some(path) => read_identities(path)?,
+            None => Vec::new(),
+        };
+        let mut recipients = config
+            .encrypt_to
+            .iter()
+            .map(|text| {
+                age::x25519::Recipient::from_str(text).map_err(|e| {
+                    SyncError::InvalidConfig(format!("Invalid age recipient {text:?}: {e}"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        recipients.extend(identities.iter().map(age::x25519::Identity::to_public));
+
+        let names = if config.encrypt_names {
+            let manifest = dst_root.join(MANIFEST_FILE_NAME);
+            let mut table = NameTable::default();
+            for (stored, original) in read_manifest(&manifest, &identities)? {
+                let parent = stored.parent().unwrap_or(Path::new("")).to_path_buf();
+                if let Some(name) = stored.file_name() {
+                    table.names.insert((parent, original), name.to_os_string());
+                }
+            }
+            Some(Mutex::new(table))
+        } else {
+            None
+        };
+        Ok(Some(Self {
+            recipients,
+            names,
+            dst_root: dst_root.to_path_buf(),
+            keep_manifest: true,
+        }))
+    }
+
+    /// Encryption that writes no manifest (`--dry-run`)
+    #[must_use]
+    pub fn without_manifest(mut self) -> Self {
+        self.keep_manifest = false;
+        self
+    }
+
+    /// Stored name of the source entry `name` copied into `dst_dir`
+    ///
+    /// `None` unless names are encrypted. A name not given before gets a new
+    /// random one, recorded when the run ends ([`Encryption::finish`]).
+    #[must_use]
+    pub fn map_name(&self, dst_dir: &Path, name: &OsStr) -> Option<OsString> {
+        let names = self.names.as_ref()?;
+        let parent = dst_dir
+            .strip_prefix(&self.dst_root)
+            .unwrap_or(dst_dir)
+            .to_path_buf();
+        let mut table = names.lock().unwrap_or_else(PoisonError::into_inner);
+        let key = (parent, name.to_os_string());
+        if let Some(stored) = table.names.get(&key) {
+            return Some(stored.clone());
+        }
+        let stored = random_name();
+        table.names.insert(key, stored.clone());
+        table.changed = true;
+        Some(stored)
+    }
+
+    /// Encrypt everything `src` holds into `dst`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading, encrypting or writing fails.
+    pub fn encrypt(&self, mut src: impl Read, dst: impl Write) -> std::io::Result<()> {
+        let recipients = self
+            .recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient);
+        let encryptor =
+            age::Encryptor::with_recipients(recipients).map_err(std::io::Error::other)?;
+        let mut writer = encryptor.wrap_output(dst)?;
+        std::io::copy(&mut src, &mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Record the names given during the run in the manifest
+    ///
+    /// Called once the traversal is over, whether or not it succeeded: the
+    /// entries stored under new names are already there.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest cannot be written.
+    pub fn finish(&self) -> Result<()> {
+        let Some(names) = self.names.as_ref().filter(|_| self.keep_manifest) else {
+            return Ok(());
+        };
+        let table = names.lock().unwrap_or_else(PoisonError::into_inner);
+        if !table.changed {
+            return Ok(());
+        }
+        let mut contents = MANIFEST_HEADER.to_vec();
+        for ((parent, original), stored) in &table.names {
+            escape_path(parent.join(stored).as_os_str(), &mut contents);
+            contents.push(b'\t');
+            escape_path(original, &mut contents);
+            contents.push(b'\n');
+        }
+
+        // Written beside the manifest and renamed over it: an interrupted
+        // run keeps the previous one
+        let path = self.dst_root.join(MANIFEST_FILE_NAME);
+        let temp = self
+            .dst_root
+            .join(crate::temp_files::temp_name(OsStr::new(MANIFEST_FILE_NAME)));
+        let write = || -> std::io::Result<()> {
+            let mut file = File::create(&temp)?;
+            self.encrypt(contents.as_slice(), &mut file)?;
+            file.sync_all()?;
+            std::fs::rename(&temp, &path)
+        };
+        write().map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            ErrorContext::new("write encrypted name manifest")
+                .destination(&path)
+                .io_cause(&e)
+                .file_system()
+        })?;
+        debug!(
+            "Recorded {} encrypted names in {}",
+            table.names.len(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Write `dst` by encrypting the source
+///
+/// Takes the same directory descriptors and pre-fetched metadata as
+/// `copy_file_internal`.
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be opened or read, the destination
+/// cannot be created or written, or the metadata cannot be preserved.
+#[allow(clippy::future_not_send, clippy::too_many_arguments)]
+pub async fn copy_encrypted(
+    encryption: &Arc<Encryption>,
+    src: &Path,
+    dst: &Path,
+    metadata_config: &crate::metadata::MetadataConfig,
+    src_metadata: &FileMetadata,
+    src_parent_dir: &DirectoryFd,
+    src_filename: &OsStr,
+    dst_parent_dir: &DirectoryFd,
+    dst_filename: &OsStr,
+) -> Result<()> {
+    let (encryption, source) = (Arc::clone(encryption), src.to_path_buf());
+    let encrypt = move |input: File, output: File| {
+        encryption.encrypt(input, output).map_err(|e| {
+            ErrorContext::new("encrypt")
+                .source(&source)
+                .io_cause(&e)
+                .copy_failed()
+        })
+    };
+    crate::transform::copy_filtered(
+        src,
+        dst,
+        metadata_config,
+        src_metadata,
+        src_parent_dir,
+        src_filename,
+        dst_parent_dir,
+        dst_filename,
+        encrypt,
+    )
+    .await
+}
+
+/// Read the X25519 identities (`AGE-SECRET-KEY-1...` lines) of an age
+/// identity file
+fn read_identities(path: &Path) -> Result<Vec<age::x25519::Identity>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        SyncError::InvalidConfig(format!(
+            "Cannot read the identity file {}: {e}",
+            path.display()
+        ))
+    })?;
+    let identities = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            age::x25519::Identity::from_str(line).map_err(|e| {
+                SyncError::InvalidConfig(format!("Invalid identity in {}: {e}", path.display()))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if identities.is_empty() {
+        return Err(SyncError::InvalidConfig(format!(
+            "No age identity in {}",
+            path.display()
+        )));
+    }
+    Ok(identities)
+}
+
+/// Decrypt an age file with any of `identities`
+fn decrypt(input: File, identities: &[age::x25519::Identity]) -> std::io::Result<impl Read> {
+    let decryptor =
+        age::Decryptor::new_buffered(BufReader::new(input)).map_err(std::io::Error::other)?;
+    decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        )
+        .map_err(std::io::Error::other)
+}
+
+/// `(stored path, original name)` pairs of the manifest at `path`, if any
+fn read_manifest(
+    path: &Path,
+    identities: &[age::x25519::Identity],
+) -> Result<Vec<(PathBuf, OsString)>> {
+    let manifest_error = |e: &std::io::Error| {
+        ErrorContext::new("read encrypted name manifest")
+            .source(path)
+            .io_cause(e)
+            .file_system()
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(manifest_error(&e)),
+    };
+    let mut contents = Vec::new();
+    decrypt(file, identities)
+        .and_then(|mut reader| reader.read_to_end(&mut contents))
+        .map_err(|e| manifest_error(&e))?;
+    let Some(lines) = contents.strip_prefix(MANIFEST_HEADER) else {
+        return Err(SyncError::FileSystem(format!(
+            "{} is not an arsync encrypted name manifest",
+            path.display()
+        )));
+    };
+    lines
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let parsed = line.iter().position(|&b| b == b'\t').and_then(|tab| {
+                let stored = unescape_path(&line[..tab])?;
+                Some((PathBuf::from(stored), unescape_path(&line[tab + 1..])?))
+            });
+            parsed
+                .ok_or_else(|| SyncError::FileSystem(format!("Corrupt line in {}", path.display())))
+        })
+        .collect()
+}
+
+/// A random stored name
+fn random_name() -> OsString {
+    let mut bytes = [0u8; NAME_BYTES];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        let rest = &mut bytes[filled..];
+        // SAFETY: rest is a valid buffer of rest.len() bytes
+        let read = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        // Only fails with EINTR once the entropy pool is initialized
+        if let Ok(read) = usize::try_from(read) {
+            filled += read;
+        }
+    }
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+        .into()
+}
+
+/// Entries `arsync restore` put back
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// Files decrypted
+    pub files: u64,
+    /// Directories created
+    pub directories: u64,
+    /// Symlinks recreated
+    pub symlinks: u64,
+    /// Special files and arsync's own files, not restored
+    pub skipped: u64,
+}
+
+impl fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} directories, {} symlinks restored, {} entries skipped",
+            self.files, self.directories, self.symlinks, self.skipped
+        )
+    }
+}
+
+/// Decrypt the encrypted copy at `src` into `dst` (`arsync restore`)
+///
+/// Files are decrypted with the identities in `identity`, and entries get
+/// their original names back from the manifest if names were encrypted.
+/// Permissions and times are restored from the encrypted copies, and
+/// ownership when running as root.
+///
+/// # Errors
+///
+/// Returns an error if the identity file or manifest cannot be read, or an
+/// entry cannot be read, decrypted or written.
+pub fn restore(src: &Path, dst: &Path, identity: &Path) -> Result<RestoreReport> {
+    let identities = read_identities(identity)?;
+    let names: HashMap<PathBuf, OsString> =
+        read_manifest(&src.join(MANIFEST_FILE_NAME), &identities)?
+            .into_iter()
+            .collect();
+    let mut report = RestoreReport::default();
+    restore_dir(src, dst, Path::new(""), &identities, &names, &mut report)?;
+    info!(
+        "Restored {} into {}: {report}",
+        src.display(),
+        dst.display()
+    );
+    Ok(report)
+}
+
+/// Restore the directory at `relative` (a stored path) and below into `dst`
+fn restore_dir(
+    src_root: &Path,
+    dst: &Path,
+    relative: &Path,
+    identities: &[age::x25519::Identity],
+    names: &HashMap<PathBuf, OsString>,
+    report: &mut RestoreReport,
+) -> Result<()> {
+    let src_dir = src_root.join(relative);
+    let io_error = |op: &'static str, path: &Path, e: &std::io::Error| {
+        ErrorContext::new(op).source(path).io_cause(e).file_system()
+    };
+    std::fs::create_dir_all(dst).map_err(|e| io_error("create directory", dst, &e))?;
+    let entries = std::fs::read_dir(&src_dir).map_err(|e| io_error("read_dir", &src_dir, &e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| io_error("read_dir", &src_dir, &e))?;
+        let stored = entry.file_name();
+        let src_path = entry.path();
+        if stored.as_bytes().starts_with(b".arsync") {
+            report.skipped += 1;
+            continue;
+        }
+        let stored_path = relative.join(&stored);
+        let name = match names.get(&stored_path) {
+            Some(original) => original.clone(),
+            None if names.is_empty() => stored,
+            None => {
+                warn!(
+                    "{} is not in the name manifest, restoring it under its stored name",
+                    src_path.display()
+                );
+                stored
+            }
+        };
+        let dst_path = dst.join(&name);
+        let metadata =
+            std::fs::symlink_metadata(&src_path).map_err(|e| io_error("lstat", &src_path, &e))?;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            restore_dir(src_root, &dst_path, &stored_path, identities, names, report)?;
+            report.directories += 1;
+        } else if file_type.is_file() {
+            let mut output =
+                File::create(&dst_path).map_err(|e| io_error("create", &dst_path, &e))?;
+            File::open(&src_path)
+                .and_then(|input| decrypt(input, identities))
+                .and_then(|mut reader| std::io::copy(&mut reader, &mut output))
+                .map_err(|e| {
+                    ErrorContext::new("decrypt")
+                        .source(&src_path)
+                        .destination(&dst_path)
+                        .io_cause(&e)
+                        .copy_failed()
+                })?;
+            report.files += 1;
+        } else if file_type.is_symlink() {
+            let target =
+                std::fs::read_link(&src_path).map_err(|e| io_error("readlink", &src_path, &e))?;
+            std::os::unix::fs::symlink(&target, &dst_path)
+                .map_err(|e| io_error("symlink", &dst_path, &e))?;
+            report.symlinks += 1;
+            continue;
+        } else {
+            warn!("Not restoring special file {}", src_path.display());
+            report.skipped += 1;
+            continue;
+        }
+        restore_attributes(&dst_path, &metadata)
+            .map_err(|e| io_error("restore attributes", &dst_path, &e))?;
+    }
+    Ok(())
+}
+
+/// Give `path` the ownership (as root), times and permissions in `metadata`
+///
+/// The permissions come last, as they may forbid opening the file.
+fn restore_attributes(path: &Path, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } == 0 {
+        std::os::unix::fs::chown(path, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    let times = std::fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::options()
+        .write(metadata.is_file())
+        .read(!metadata.is_file())
+        .open(path)?
+        .set_times(times)?;
+    std::fs::set_permissions(path, metadata.permissions())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Identity file holding a new identity, and its recipient
+    fn identity_file(dir: &Path) -> (PathBuf, String) {
+        let identity = age::x25519::Identity::generate();
+        let path = dir.join("key.txt");
+        std::fs::write(
+            &path,
+            format!(
+                "# test key\n{}\n",
+                age::secrecy::ExposeSecret::expose_secret(&identity.to_string())
+            ),
+        )
+        .unwrap();
+        (path, identity.to_public().to_string())
+    }
+
+    #[test]
+    fn test_parse_recipient() {
+        // Requirement: Only age X25519 public keys are accepted
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        assert_eq!(parse_recipient(&recipient).unwrap(), recipient);
+        assert!(parse_recipient("age1notakey").is_err());
+        assert!(parse_recipient("ssh-ed25519 AAAA").is_err());
+    }
+
+    #[test]
+    fn test_names_survive_runs() {
+        // Requirement: Stored names are random, recorded in the encrypted
+        // manifest and given again by the next run
+        let temp_dir = TempDir::new().unwrap();
+        let (identity, _) = identity_file(temp_dir.path());
+        let dst = temp_dir.path().join("dst");
+        std::fs::create_dir(&dst).unwrap();
+        let config = TraversalConfig {
+            encrypt_identity: Some(identity),
+            encrypt_names: true,
+            ..TraversalConfig::default()
+        };
+
+        let first = Encryption::load(&config, &dst).unwrap().unwrap();
+        let dir = first.map_name(&dst, OsStr::new("secret plans")).unwrap();
+        let file = first
+            .map_name(&dst.join(&dir), OsStr::new("q3.txt"))
+            .unwrap();
+        assert_eq!(dir.len(), 2 * NAME_BYTES);
+        assert_ne!(dir, file);
+        first.finish().unwrap();
+        let manifest = std::fs::read(dst.join(MANIFEST_FILE_NAME)).unwrap();
+        assert!(!manifest.windows(6).any(|w| w == b"q3.txt"));
+
+        let second = Encryption::load(&config, &dst).unwrap().unwrap();
+        assert_eq!(
+            second.map_name(&dst, OsStr::new("secret plans")),
+            Some(dir.clone())
+        );
+        assert_eq!(
+            second.map_name(&dst.join(&dir), OsStr::new("q3.txt")),
+            Some(file)
+        );
+    }
+
+    #[test]
+    fn test_restore_decrypts_tree() {
+        // Requirement: restore puts back contents, names and permissions
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let (identity, _) = identity_file(temp_dir.path());
+        let encrypted = temp_dir.path().join("encrypted");
+        std::fs::create_dir(&encrypted).unwrap();
+        let config = TraversalConfig {
+            encrypt_identity: Some(identity.clone()),
+            encrypt_names: true,
+            ..TraversalConfig::default()
+        };
+        let encryption = Encryption::load(&config, &encrypted).unwrap().unwrap();
+        let stored = encryption
+            .map_name(&encrypted, OsStr::new("notes.txt"))
+            .unwrap();
+        let path = encrypted.join(&stored);
+        encryption
+            .encrypt(&b"top secret"[..], File::create(&path).unwrap())
+            .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        encryption.finish().unwrap();
+        assert_ne!(std::fs::read(&path).unwrap(), b"top secret");
+
+        let restored = temp_dir.path().join("restored");
+        let report = restore(&encrypted, &restored, &identity).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(report.skipped, 1, "the manifest");
+        let notes = restored.join("notes.txt");
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "top secret");
+        assert_eq!(
+            std::fs::metadata(&notes).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+}
@@ -1,5 +1,38 @@
 //! Error handling and types
+//!
+//! # Error context
+//!
+//! Most failures in traversal and copy happen in a specific syscall on a
+//! specific entry, usually relative to a directory file descriptor. Use
+//! [`ErrorContext`] to build those errors so every message carries the same
+//! provenance: operation, source/destination path, the directory the dirfd
+//! was opened on, and the errno.
+//!
+//! ```rust,ignore
+//! use arsync::error::ErrorContext;
+//!
+//! let file = dir
+//!     .open_file_at(name, true, false, false, false)
+//!     .await
+//!     .map_err(|e| {
+//!         ErrorContext::new("openat")
+//!             .source(&src_path)
+//!             .dirfd(dir.path())
+//!             .cause(&e)
+//!             .file_system()
+//!     })?;
+//! ```
+//!
+//! # Remedies
+//!
+//! A raw errno says what the kernel refused, not what to do about it. When
+//! a context is turned into a [`SyncError`] the common refusals are
+//! diagnosed into a [`Remedy`] (read-only mount, missing capability,
+//! immutable file, SELinux denial, plain permissions), whose advice is part
+//! of the message and so shows at normal verbosity.
 
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 /// Synchronization and file operation errors
@@ -49,6 +82,10 @@ pub enum SyncError {
     #[allow(dead_code)]
     FdExhaustion(String),
 
+    /// The run was stopped by a signal (see `interrupt`)
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+
     /// Internal application error
     #[error("Internal error: {0}")]
     #[allow(dead_code)]
@@ -56,3 +93,493 @@ pub enum SyncError {
 }
 
 pub type Result<T> = std::result::Result<T, SyncError>;
+
+impl SyncError {
+    /// OS error number behind this error, if known
+    ///
+    /// Errors built with [`ErrorContext`] carry it as `errno=N`; wrapped
+    /// `std::io::Error` messages as `(os error N)`.
+    #[must_use]
+    pub fn os_error(&self) -> Option<i32> {
+        match self {
+            Self::Io(e) | Self::ExtendedFs(compio_fs_extended::ExtendedError::Io(e)) => {
+                e.raw_os_error()
+            }
+            other => {
+                let message = other.to_string();
+                parse_errno_field(&message).or_else(|| parse_os_error(&message))
+            }
+        }
+    }
+
+    /// Whether the error is a stale file handle (`ESTALE`, NFS)
+    #[must_use]
+    pub fn is_stale(&self) -> bool {
+        self.os_error() == Some(libc::ESTALE)
+    }
+
+    /// Advice for this error that its message does not already carry
+    ///
+    /// Errors built with [`ErrorContext`] include their remedy; others (a
+    /// bare `std::io::Error`, say) are diagnosed from the errno alone.
+    #[must_use]
+    pub fn remedy(&self) -> Option<Remedy> {
+        if self.to_string().contains(HINT_PREFIX) {
+            return None;
+        }
+        Remedy::diagnose("", self.os_error()?, None)
+    }
+}
+
+// ============================================================================
+// REMEDIES
+// ============================================================================
+
+/// What introduces a remedy in an error message
+const HINT_PREFIX: &str = "(hint: ";
+
+/// What to do about one of the common failure classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Remedy {
+    /// `EROFS`: the destination is mounted read-only
+    ReadOnlyFilesystem,
+    /// `EPERM` on a file or directory marked immutable or append-only
+    Immutable,
+    /// `EPERM` otherwise: a privilege such as `CAP_CHOWN` is missing
+    MissingCapability,
+    /// `EACCES` while SELinux is enforcing
+    SelinuxDenied,
+    /// `EACCES` otherwise: file or directory permissions
+    AccessDenied,
+}
+
+impl Remedy {
+    /// Diagnose `errno` from `operation` on `path` (the entry or directory
+    /// it was refused on, checked for the immutable flag)
+    #[must_use]
+    pub fn diagnose(operation: &str, errno: i32, path: Option<&Path>) -> Option<Self> {
+        classify(
+            operation,
+            errno,
+            || path.is_some_and(is_immutable),
+            selinux_enforcing,
+        )
+    }
+
+    /// What the user can do
+    #[must_use]
+    pub const fn advice(self) -> &'static str {
+        match self {
+            Self::ReadOnlyFilesystem => {
+                "the destination filesystem is mounted read-only; remount it read-write \
+                 (mount -o remount,rw) or choose another destination"
+            }
+            Self::Immutable => {
+                "the entry or its directory is immutable or append-only; check with lsattr \
+                 and clear the flag with chattr -i / chattr -a, or pass --preserve-flags \
+                 --update-immutable to rewrite such files and lock them again"
+            }
+            Self::MissingCapability => {
+                "this process lacks the privilege (CAP_CHOWN to give files away, CAP_FOWNER \
+                 for files owned by others); run as root, grant the capability, or stop \
+                 preserving it (e.g. --no-owner --no-group)"
+            }
+            Self::SelinuxDenied => {
+                "SELinux is enforcing and may have denied this; look for an AVC denial with \
+                 ausearch -m avc -ts recent and check the labels with ls -Z"
+            }
+            Self::AccessDenied => {
+                "check the permissions of the entry and of every directory above it"
+            }
+        }
+    }
+}
+
+impl Display for Remedy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.advice())
+    }
+}
+
+/// [`Remedy::diagnose`] with the environment checks passed in
+fn classify(
+    operation: &str,
+    errno: i32,
+    immutable: impl FnOnce() -> bool,
+    selinux_enforcing: impl FnOnce() -> bool,
+) -> Option<Remedy> {
+    match errno {
+        libc::EROFS => Some(Remedy::ReadOnlyFilesystem),
+        // Changing ownership never fails for an immutable flag alone
+        libc::EPERM if !operation.contains("chown") && immutable() => Some(Remedy::Immutable),
+        libc::EPERM => Some(Remedy::MissingCapability),
+        libc::EACCES if selinux_enforcing() => Some(Remedy::SelinuxDenied),
+        libc::EACCES => Some(Remedy::AccessDenied),
+        _ => None,
+    }
+}
+
+/// Whether `path` (not followed if a symlink) is immutable or append-only
+fn is_immutable(path: &Path) -> bool {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)
+        .is_ok_and(|file| {
+            crate::inode_flags::get(file.as_raw_fd())
+                .is_ok_and(|flags| flags & crate::inode_flags::LOCKING != 0)
+        })
+}
+
+/// Whether SELinux is enabled and enforcing
+fn selinux_enforcing() -> bool {
+    std::fs::read_to_string("/sys/fs/selinux/enforce").is_ok_and(|text| text.trim() == "1")
+}
+
+// ============================================================================
+// ERROR CONTEXT
+// ============================================================================
+
+/// Builder that attaches operation provenance to an error
+///
+/// Produces consistently formatted messages of the form
+/// `openat failed: <cause> [src=/a/b, dst=/x/b, dirfd=/a, errno=2]`
+/// and exposes the same data as key/value pairs via [`ErrorContext::fields`]
+/// for structured (JSON) output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Name of the operation or syscall that failed (e.g. `"openat"`)
+    operation: &'static str,
+    /// Source path being processed
+    source: Option<PathBuf>,
+    /// Destination path being processed
+    destination: Option<PathBuf>,
+    /// Path the directory file descriptor was opened on
+    dirfd: Option<PathBuf>,
+    /// OS error number, if known
+    errno: Option<i32>,
+    /// Underlying error message
+    cause: Option<String>,
+    /// Advice for the failure, once diagnosed
+    remedy: Option<Remedy>,
+}
+
+#[allow(dead_code)] // Accessors/conversions are library API (structured output); the binary uses a subset
+impl ErrorContext {
+    /// Start a context for the named operation
+    #[must_use]
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            ..Self::default()
+        }
+    }
+
+    /// Attach the source path being processed
+    #[must_use]
+    pub fn source(mut self, path: impl AsRef<Path>) -> Self {
+        self.source = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Attach the destination path being processed
+    #[must_use]
+    pub fn destination(mut self, path: impl AsRef<Path>) -> Self {
+        self.destination = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Attach the path the directory file descriptor was opened on
+    #[must_use]
+    pub fn dirfd(mut self, path: impl AsRef<Path>) -> Self {
+        self.dirfd = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Attach an explicit errno
+    #[must_use]
+    pub const fn errno(mut self, errno: i32) -> Self {
+        self.errno = Some(errno);
+        self
+    }
+
+    /// Attach the underlying error
+    ///
+    /// If no errno was set explicitly, it is recovered from the `(os error N)`
+    /// suffix that `std::io::Error` adds to its display output, which survives
+    /// wrapping in `ExtendedError`.
+    #[must_use]
+    pub fn cause(mut self, error: &impl Display) -> Self {
+        let message = error.to_string();
+        if self.errno.is_none() {
+            self.errno = parse_os_error(&message);
+        }
+        self.cause = Some(message);
+        self
+    }
+
+    /// Attach an underlying I/O error, taking the errno directly from it
+    #[must_use]
+    pub fn io_cause(mut self, error: &std::io::Error) -> Self {
+        if self.errno.is_none() {
+            self.errno = error.raw_os_error();
+        }
+        self.cause = Some(error.to_string());
+        self
+    }
+
+    /// Diagnose the errno into a [`Remedy`]
+    ///
+    /// Done by the conversions to [`SyncError`]; an `EPERM` checks the
+    /// destination (or else the dirfd's directory) for the immutable flag.
+    #[must_use]
+    pub fn diagnose(mut self) -> Self {
+        if let Some(errno) = self.errno {
+            let path = self.destination.as_deref().or(self.dirfd.as_deref());
+            self.remedy = Remedy::diagnose(self.operation, errno, path);
+        }
+        self
+    }
+
+    /// Advice for the failure, if diagnosed
+    #[must_use]
+    pub const fn remedy(&self) -> Option<Remedy> {
+        self.remedy
+    }
+
+    /// Name of the failed operation
+    #[must_use]
+    pub const fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// OS error number, if known
+    #[must_use]
+    pub const fn os_error(&self) -> Option<i32> {
+        self.errno
+    }
+
+    /// Structured key/value view of the context (for JSON output)
+    ///
+    /// Only fields that were set are included; `operation` is always first.
+    #[must_use]
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("operation", self.operation.to_string())];
+        if let Some(source) = &self.source {
+            fields.push(("source", source.display().to_string()));
+        }
+        if let Some(destination) = &self.destination {
+            fields.push(("destination", destination.display().to_string()));
+        }
+        if let Some(dirfd) = &self.dirfd {
+            fields.push(("dirfd", dirfd.display().to_string()));
+        }
+        if let Some(errno) = self.errno {
+            fields.push(("errno", errno.to_string()));
+        }
+        if let Some(cause) = &self.cause {
+            fields.push(("cause", cause.clone()));
+        }
+        if let Some(remedy) = self.remedy {
+            fields.push(("remedy", remedy.advice().to_string()));
+        }
+        fields
+    }
+
+    /// Build a `SyncError::FileSystem` from this context
+    #[must_use]
+    pub fn file_system(self) -> SyncError {
+        SyncError::FileSystem(self.diagnose().to_string())
+    }
+
+    /// Build a `SyncError::CopyFailed` from this context
+    #[must_use]
+    pub fn copy_failed(self) -> SyncError {
+        SyncError::CopyFailed(self.diagnose().to_string())
+    }
+
+    /// Build a `SyncError::DirectoryTraversal` from this context
+    #[must_use]
+    pub fn traversal(self) -> SyncError {
+        SyncError::DirectoryTraversal(self.diagnose().to_string())
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed", self.operation)?;
+        if let Some(cause) = &self.cause {
+            write!(f, ": {cause}")?;
+        }
+
+        let mut separator = " [";
+        for (key, value) in [
+            ("src", self.source.as_ref()),
+            ("dst", self.destination.as_ref()),
+            ("dirfd", self.dirfd.as_ref()),
+        ] {
+            if let Some(path) = value {
+                write!(f, "{separator}{key}={}", path.display())?;
+                separator = ", ";
+            }
+        }
+        if let Some(errno) = self.errno {
+            write!(f, "{separator}errno={errno}")?;
+            separator = ", ";
+        }
+        if separator == ", " {
+            write!(f, "]")?;
+        }
+        if let Some(remedy) = self.remedy {
+            write!(f, " {HINT_PREFIX}{remedy})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Extract `N` from the `errno=N` field that `ErrorContext` appends
+fn parse_errno_field(message: &str) -> Option<i32> {
+    let start = message.rfind("errno=")? + "errno=".len();
+    let rest = &message[start..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// Extract `N` from an `(os error N)` suffix in an error message
+fn parse_os_error(message: &str) -> Option<i32> {
+    let start = message.rfind("(os error ")? + "(os error ".len();
+    let rest = &message[start..];
+    let end = rest.find(')')?;
+    rest[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context_full_message() {
+        let io_err = std::io::Error::from_raw_os_error(libc::ENOENT);
+        let ctx = ErrorContext::new("openat")
+            .source("/src/dir/file.txt")
+            .destination("/dst/dir/file.txt")
+            .dirfd("/src/dir")
+            .io_cause(&io_err);
+
+        let message = ctx.to_string();
+        assert!(message.starts_with("openat failed: "));
+        assert!(message.contains("src=/src/dir/file.txt"));
+        assert!(message.contains("dst=/dst/dir/file.txt"));
+        assert!(message.contains("dirfd=/src/dir"));
+        assert!(message.ends_with(&format!("errno={}]", libc::ENOENT)));
+        assert_eq!(ctx.os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn test_error_context_minimal_message() {
+        let ctx = ErrorContext::new("statx");
+        assert_eq!(ctx.to_string(), "statx failed");
+        assert_eq!(ctx.fields(), vec![("operation", "statx".to_string())]);
+    }
+
+    #[test]
+    fn test_error_context_recovers_errno_from_wrapped_error() {
+        let io_err = std::io::Error::from_raw_os_error(libc::EACCES);
+        let wrapped = compio_fs_extended::ExtendedError::Directory(format!(
+            "openat(O_DIRECTORY) failed: {io_err}"
+        ));
+        let ctx = ErrorContext::new("open_directory_at").cause(&wrapped);
+        assert_eq!(ctx.os_error(), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn test_sync_error_os_error() {
+        let stale = std::io::Error::from_raw_os_error(libc::ESTALE);
+        assert!(ErrorContext::new("statx")
+            .io_cause(&stale)
+            .file_system()
+            .is_stale());
+        assert!(SyncError::CopyFailed(format!("write failed: {stale}")).is_stale());
+        assert!(SyncError::Io(std::io::Error::from_raw_os_error(libc::ESTALE)).is_stale());
+        assert_eq!(
+            SyncError::FileSystem("No filename".to_string()).os_error(),
+            None
+        );
+        assert!(!ErrorContext::new("mkdir")
+            .errno(libc::EEXIST)
+            .file_system()
+            .is_stale());
+    }
+
+    #[test]
+    fn test_error_context_into_sync_error() {
+        let err = ErrorContext::new("mkdir")
+            .destination("/dst/a")
+            .file_system();
+        assert!(matches!(err, SyncError::FileSystem(_)));
+        assert_eq!(
+            err.to_string(),
+            "File system error: mkdir failed [dst=/dst/a]"
+        );
+    }
+
+    #[test]
+    fn test_classify_remedies() {
+        // Requirement: EROFS, EPERM and EACCES map to distinct advice;
+        // other errors get none
+        let never = || false;
+        let always = || true;
+        assert_eq!(
+            classify("openat", libc::EROFS, never, never),
+            Some(Remedy::ReadOnlyFilesystem)
+        );
+        assert_eq!(
+            classify("unlinkat", libc::EPERM, always, never),
+            Some(Remedy::Immutable)
+        );
+        assert_eq!(
+            classify("unlinkat", libc::EPERM, never, never),
+            Some(Remedy::MissingCapability)
+        );
+        assert_eq!(
+            classify("fchown", libc::EPERM, always, never),
+            Some(Remedy::MissingCapability)
+        );
+        assert_eq!(
+            classify("openat", libc::EACCES, never, always),
+            Some(Remedy::SelinuxDenied)
+        );
+        assert_eq!(
+            classify("openat", libc::EACCES, never, never),
+            Some(Remedy::AccessDenied)
+        );
+        assert_eq!(classify("openat", libc::ENOENT, always, always), None);
+    }
+
+    #[test]
+    fn test_remedy_in_message() {
+        // Requirement: The advice is part of the error's message and
+        // structured fields, and the errno can still be recovered from it
+        let err = ErrorContext::new("openat")
+            .destination("/dst/a")
+            .errno(libc::EROFS)
+            .file_system();
+        let message = err.to_string();
+        assert!(message.contains("errno=30] (hint: "), "{message}");
+        assert!(message.contains("remount"), "{message}");
+        assert_eq!(err.os_error(), Some(libc::EROFS));
+        assert_eq!(err.remedy(), None);
+
+        let ctx = ErrorContext::new("openat").errno(libc::EROFS).diagnose();
+        assert_eq!(ctx.remedy(), Some(Remedy::ReadOnlyFilesystem));
+        assert!(ctx.fields().iter().any(|(key, _)| *key == "remedy"));
+
+        let bare = SyncError::Io(std::io::Error::from_raw_os_error(libc::EROFS));
+        assert_eq!(bare.remedy(), Some(Remedy::ReadOnlyFilesystem));
+    }
+}
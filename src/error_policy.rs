@@ -0,0 +1,236 @@
+//! What a directory copy does about entries that fail
+//!
+//! An entry that fails (unreadable file, refused write, a panic caught by the
+//! supervisor) is skipped and the traversal goes on with its siblings. The
+//! failure is logged as it happens (deduplicated through the run's
+//! [`WarningLog`](crate::warnings::WarningLog)), published on the event bus,
+//! and recorded here with its path and errno; at the end of the run every
+//! failed path is listed, and the run's error count tells the caller to exit
+//! with status 23 like rsync, unless `--ignore-errors` was given.
+//!
+//! `--max-errors N` caps the failures: once N entries have failed, no
+//! further entry is started and the copy fails as a whole.
+
+use crate::cli::TraversalConfig;
+use crate::error::{Result, SyncError};
+use crate::events::{SyncEvent, EVENTS};
+use crate::run_state::RunState;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+/// Exit status of a run in which some entries failed, as in rsync
+/// ("partial transfer due to error")
+pub const PARTIAL_TRANSFER_EXIT_CODE: i32 = 23;
+
+/// One entry that failed and was skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedEntry {
+    /// Source path of the entry
+    pub path: PathBuf,
+    /// OS error number, when the failure came from a system call
+    pub errno: Option<i32>,
+    /// Full error message
+    pub message: String,
+}
+
+/// Failed entries of one directory copy (`--max-errors`)
+#[derive(Debug)]
+pub struct ErrorPolicy {
+    /// Run the entries belong to, for its warnings and job
+    run: Arc<RunState>,
+    /// Failed entries after which the copy stops
+    max_errors: Option<NonZeroU64>,
+    /// Every failed entry, in the order they failed
+    failed: Mutex<Vec<FailedEntry>>,
+    /// Whether `max_errors` was reached
+    stopped: AtomicBool,
+}
+
+impl ErrorPolicy {
+    /// Policy from the command line, for the entries of `run`
+    #[must_use]
+    pub fn new(config: &TraversalConfig, run: Arc<RunState>) -> Self {
+        Self {
+            run,
+            max_errors: config.max_errors,
+            failed: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Log and record that the entry at `path` failed with `error`
+    pub fn entry_failed(&self, path: &Path, error: &SyncError) {
+        self.run.warnings.entry_failed(path, error);
+        EVENTS.emit_with(|| SyncEvent::Error {
+            job: self.run.job,
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        });
+        let count = {
+            let mut failed = self.failed.lock().unwrap_or_else(PoisonError::into_inner);
+            failed.push(FailedEntry {
+                path: path.to_path_buf(),
+                errno: error.os_error(),
+                message: error.to_string(),
+            });
+            failed.len() as u64
+        };
+        if let Some(max) = self.max_errors {
+            if count >= max.get() && !self.stopped.swap(true, Ordering::Relaxed) {
+                warn!("{count} entries failed; starting no further entries (--max-errors {max})");
+            }
+        }
+    }
+
+    /// Whether no further entry should be started
+    #[must_use]
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Number of failed entries
+    #[must_use]
+    pub fn failed(&self) -> u64 {
+        self.failed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len() as u64
+    }
+
+    /// Every failed entry, in the order they failed
+    #[must_use]
+    pub fn entries(&self) -> Vec<FailedEntry> {
+        self.failed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The copy's outcome once the traversal is done
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the copy was stopped by `--max-errors`.
+    pub fn outcome(&self) -> Result<()> {
+        if self.stopped() {
+            return Err(SyncError::CopyFailed(format!(
+                "Stopped after {} failed entries (--max-errors)",
+                self.failed()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Log every failed path with its errno, if any entry failed
+    pub fn report(&self) {
+        let entries = self.entries();
+        if entries.is_empty() {
+            return;
+        }
+        warn!("{} entries failed and were skipped:", entries.len());
+        for entry in &entries {
+            warn!("  {}", describe(entry));
+        }
+    }
+}
+
+/// One line of the end-of-run report: path, errno and message
+fn describe(entry: &FailedEntry) -> String {
+    match entry.errno {
+        Some(errno) => format!(
+            "{} (errno {errno}, {}): {}",
+            entry.path.display(),
+            errno_name(errno),
+            entry.message
+        ),
+        None => format!("{}: {}", entry.path.display(), entry.message),
+    }
+}
+
+/// Symbolic name of the common errno values, the number otherwise
+fn errno_name(errno: i32) -> String {
+    let name = match errno {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::EIO => "EIO",
+        libc::EACCES => "EACCES",
+        libc::EBUSY => "EBUSY",
+        libc::EEXIST => "EEXIST",
+        libc::EXDEV => "EXDEV",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::EMFILE => "EMFILE",
+        libc::EFBIG => "EFBIG",
+        libc::ENOSPC => "ENOSPC",
+        libc::EROFS => "EROFS",
+        libc::EMLINK => "EMLINK",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOTEMPTY => "ENOTEMPTY",
+        libc::ELOOP => "ELOOP",
+        libc::ENODATA => "ENODATA",
+        libc::EOPNOTSUPP => "EOPNOTSUPP",
+        libc::EDQUOT => "EDQUOT",
+        libc::ESTALE => "ESTALE",
+        _ => return errno.to_string(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use clap::Parser;
+
+    fn policy(extra: &[&str]) -> ErrorPolicy {
+        let args =
+            crate::cli::Args::parse_from(["arsync", "/src", "/dst"].iter().chain(extra.iter()));
+        ErrorPolicy::new(&args.traversal, Arc::default())
+    }
+
+    fn denied(path: &str) -> SyncError {
+        crate::error::ErrorContext::new("openat source")
+            .source(path)
+            .io_cause(&std::io::Error::from_raw_os_error(libc::EACCES))
+            .file_system()
+    }
+
+    #[test]
+    fn test_failed_entries_are_recorded_with_errno() {
+        // Requirement: Every failed path is kept, in order, with its errno
+        // when the failure came from a system call
+        let errors = policy(&[]);
+        errors.entry_failed(Path::new("/src/a"), &denied("/src/a"));
+        errors.entry_failed(
+            Path::new("/src/b"),
+            &SyncError::Internal("Panic while processing /src/b: boom".to_string()),
+        );
+
+        let entries = errors.entries();
+        assert_eq!(errors.failed(), 2);
+        assert_eq!(entries[0].path, Path::new("/src/a"));
+        assert_eq!(entries[0].errno, Some(libc::EACCES));
+        assert!(describe(&entries[0]).starts_with("/src/a (errno 13, EACCES): "));
+        assert_eq!(entries[1].errno, None);
+        assert!(describe(&entries[1]).starts_with("/src/b: Panic"));
+        assert!(!errors.stopped());
+        assert!(errors.outcome().is_ok());
+    }
+
+    #[test]
+    fn test_max_errors_stops_the_copy() {
+        // Requirement: --max-errors N stops starting entries at the Nth
+        // failure and fails the copy
+        let errors = policy(&["--max-errors", "2"]);
+        errors.entry_failed(Path::new("/src/a"), &denied("/src/a"));
+        assert!(!errors.stopped());
+        errors.entry_failed(Path::new("/src/b"), &denied("/src/b"));
+        assert!(errors.stopped());
+        assert!(errors.outcome().is_err());
+    }
+}
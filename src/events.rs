@@ -0,0 +1,370 @@
+//! Sync event bus for progress renderers and front-ends
+//!
+//! The copy pipeline publishes typed events as it works: entries found by the
+//! traversal, files started and finished, every chunk written, and the
+//! entries that failed. Presentation layers (the terminal `--progress` renderer, a GUI)
+//! subscribe to the process-wide [`EVENTS`] bus and receive them over an async
+//! channel as they happen, instead of polling shared counters.
+//!
+//! The bus is shared by every run in the process. Events naming an entry
+//! carry the [`RunState::job`] of the run that published them, and a file's
+//! later events carry the [`FileId`] of its `FileStarted`, so a front-end
+//! watching several runs (`arsync serve`) tells them apart.
+//!
+//! Publishing is cheap when nobody listens: [`EventBus::emit_with`] only builds
+//! the event if there is at least one subscriber. Each subscriber has its own
+//! bounded queue; a subscriber that falls behind loses events (counted in
+//! [`EventBus::dropped`]) rather than slowing the copy down.
+//!
+//! # Usage
+//!
+//! ```rust,ignore
+//! use arsync::events::{SyncEvent, EVENTS};
+//!
+//! let mut events = EVENTS.subscribe(4096);
+//! compio::runtime::spawn(async move {
+//!     while let Some(event) = events.next().await {
+//!         if let SyncEvent::ChunkCopied { bytes, .. } = event {
+//!             // update the display
+//!         }
+//!     }
+//! })
+//! .detach();
+//! ```
+
+use crate::metrics::Metrics;
+use crate::run_state::RunState;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Process-wide event bus the copy pipeline publishes to
+pub static EVENTS: EventBus = EventBus::new();
+
+/// Identifies one file copy across its `FileStarted`/`ChunkCopied`/`FileDone` events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(pub u64);
+
+/// Event published by the copy pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    /// The traversal found an entry (after `statx`)
+    EntryDiscovered {
+        /// Job of the run
+        job: u64,
+        /// Source path
+        path: PathBuf,
+        /// Size in bytes
+        size: u64,
+        /// Whether the entry is a directory
+        is_dir: bool,
+    },
+    /// Copying of a file's contents started
+    FileStarted {
+        /// Job of the run
+        job: u64,
+        /// Copy identifier
+        id: FileId,
+        /// Source path
+        path: PathBuf,
+        /// Size in bytes
+        size: u64,
+    },
+    /// A chunk of a file was written to the destination
+    ChunkCopied {
+        /// Copy identifier
+        id: FileId,
+        /// File offset of the chunk
+        offset: u64,
+        /// Chunk length in bytes
+        bytes: u64,
+    },
+    /// A file was copied completely
+    FileDone {
+        /// Copy identifier
+        id: FileId,
+        /// Bytes copied
+        bytes: u64,
+    },
+    /// Copying of a file's contents failed (the entry's `Error` follows)
+    FileFailed {
+        /// Copy identifier
+        id: FileId,
+    },
+    /// Copying an entry failed and it was skipped
+    Error {
+        /// Job of the run
+        job: u64,
+        /// Source path
+        path: PathBuf,
+        /// Error description
+        message: String,
+    },
+}
+
+/// Subscriber list of an `EventBus`
+type Subscribers = Vec<(u64, mpsc::Sender<SyncEvent>)>;
+
+/// Broadcast channel delivering every `SyncEvent` to every subscriber
+#[derive(Debug)]
+pub struct EventBus {
+    /// Subscriber ids with their sending halves
+    subscribers: Mutex<Subscribers>,
+    /// Number of subscribers (checked without taking the lock)
+    active: AtomicUsize,
+    /// Next subscriber id
+    next_subscriber: AtomicU64,
+    /// Next file copy id
+    next_file: AtomicU64,
+    /// Events not delivered because a subscriber's queue was full
+    dropped: AtomicU64,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Create a bus with no subscribers
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            active: AtomicUsize::new(0),
+            next_subscriber: AtomicU64::new(0),
+            next_file: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Subscribe to all events published from now on
+    ///
+    /// Up to `capacity` events are queued for the subscriber; further events
+    /// are dropped until it catches up. Dropping the `Subscription` unsubscribes.
+    pub fn subscribe(&'static self, capacity: usize) -> Subscription {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let id = self.next_subscriber.fetch_add(1, Ordering::Relaxed);
+        let mut subscribers = self.lock();
+        subscribers.push((id, sender));
+        self.active.store(subscribers.len(), Ordering::Release);
+        Subscription {
+            bus: self,
+            id,
+            receiver,
+        }
+    }
+
+    /// Whether anybody is listening
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire) > 0
+    }
+
+    /// Publish the event built by `make`, which only runs if there are subscribers
+    pub fn emit_with(&self, make: impl FnOnce() -> SyncEvent) {
+        if !self.is_active() {
+            return;
+        }
+        let event = make();
+        let mut subscribers = self.lock();
+        for (_, sender) in subscribers.iter_mut() {
+            if sender.try_send(event.clone()).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Start reporting the copy of `path` in `run`; publishes `FileStarted`
+    pub fn file_started(&'static self, run: &RunState, path: &Path, size: u64) -> FileEvents {
+        let id = FileId(self.next_file.fetch_add(1, Ordering::Relaxed));
+        run.metrics.file_started();
+        self.emit_with(|| SyncEvent::FileStarted {
+            job: run.job,
+            id,
+            path: path.to_path_buf(),
+            size,
+        });
+        FileEvents {
+            bus: self,
+            id,
+            metrics: Arc::clone(&run.metrics),
+        }
+    }
+
+    /// Number of events lost to full subscriber queues
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stop delivering to subscriber `id`
+    ///
+    /// Events already queued can still be read; after them the subscription's
+    /// stream ends.
+    pub fn unsubscribe(&self, id: u64) {
+        let mut subscribers = self.lock();
+        subscribers.retain(|(subscriber, _)| *subscriber != id);
+        self.active.store(subscribers.len(), Ordering::Release);
+    }
+
+    /// Lock the subscriber list, recovering from a poisoned lock
+    fn lock(&self) -> std::sync::MutexGuard<'_, Subscribers> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Receiving end of an `EventBus` subscription
+#[derive(Debug)]
+pub struct Subscription {
+    /// Bus to unsubscribe from on drop
+    bus: &'static EventBus,
+    /// Subscriber id
+    id: u64,
+    /// Queued events
+    receiver: mpsc::Receiver<SyncEvent>,
+}
+
+impl Subscription {
+    /// Next event; `None` once unsubscribed and drained
+    pub async fn next(&mut self) -> Option<SyncEvent> {
+        self.receiver.next().await
+    }
+
+    /// Subscriber id, for `EventBus::unsubscribe` from another task
+    #[must_use]
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id);
+    }
+}
+
+/// Publishes the events of one file copy, and counts it in its run's metrics
+#[derive(Debug, Clone)]
+pub struct FileEvents {
+    /// Bus the events go to
+    bus: &'static EventBus,
+    /// Copy identifier
+    id: FileId,
+    /// Metrics of the run the copy belongs to
+    metrics: Arc<Metrics>,
+}
+
+impl FileEvents {
+    /// `bytes` were written at `offset`
+    pub fn chunk(&self, offset: u64, bytes: u64) {
+        let id = self.id;
+        self.metrics.copied(bytes);
+        self.bus
+            .emit_with(|| SyncEvent::ChunkCopied { id, offset, bytes });
+    }
+
+    /// The copy completed with `bytes` copied
+    pub fn done(&self, bytes: u64) {
+        let id = self.id;
+        self.metrics.file_done();
+        self.bus.emit_with(|| SyncEvent::FileDone { id, bytes });
+    }
+
+    /// The copy failed; the error is reported for the entry as a whole
+    pub fn failed(&self) {
+        let id = self.id;
+        self.metrics.file_failed();
+        self.bus.emit_with(|| SyncEvent::FileFailed { id });
+    }
+
+    /// Metrics of the run, for counting the copy's reads and writes
+    #[must_use]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[compio::test]
+    async fn test_every_subscriber_receives_file_events() {
+        // Test: Events reach all subscribers in order, and end after unsubscribe
+        static BUS: EventBus = EventBus::new();
+        BUS.emit_with(|| unreachable!("no subscribers, event must not be built"));
+
+        let mut gui = BUS.subscribe(16);
+        let mut renderer = BUS.subscribe(16);
+        let run = RunState {
+            job: 7,
+            ..RunState::default()
+        };
+        let file = BUS.file_started(&run, Path::new("/src/a"), 10);
+        file.chunk(0, 10);
+        file.done(10);
+
+        for subscription in [&mut gui, &mut renderer] {
+            assert!(matches!(
+                subscription.next().await,
+                Some(SyncEvent::FileStarted {
+                    job: 7,
+                    size: 10,
+                    ..
+                })
+            ));
+            assert_eq!(
+                subscription.next().await,
+                Some(SyncEvent::ChunkCopied {
+                    id: FileId(0),
+                    offset: 0,
+                    bytes: 10
+                })
+            );
+            assert_eq!(
+                subscription.next().await,
+                Some(SyncEvent::FileDone {
+                    id: FileId(0),
+                    bytes: 10
+                })
+            );
+        }
+
+        // The copy is counted in its own run's metrics
+        assert!(run
+            .metrics
+            .render()
+            .lines()
+            .any(|l| l == "arsync_bytes_copied_total 10"));
+
+        BUS.unsubscribe(gui.id());
+        assert_eq!(gui.next().await, None);
+        drop(renderer);
+        assert!(!BUS.is_active());
+    }
+
+    #[compio::test]
+    async fn test_slow_subscriber_drops_events() {
+        // Requirement: A full queue loses events instead of blocking the copy
+        static BUS: EventBus = EventBus::new();
+        let mut slow = BUS.subscribe(1);
+        let file = BUS.file_started(&RunState::default(), Path::new("/src/big"), 1 << 20);
+        for chunk in 0..10 {
+            file.chunk(chunk * 65_536, 65_536);
+        }
+
+        assert!(BUS.dropped() > 0);
+        assert!(matches!(
+            slow.next().await,
+            Some(SyncEvent::FileStarted { .. })
+        ));
+    }
+}
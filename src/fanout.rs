@@ -0,0 +1,491 @@
+//! One source, several destinations (`arsync SOURCE DEST1 DEST2 ...`)
+//!
+//! Replicating a tree to N disks with N runs reads the source N times. In
+//! fan-out mode each source file is read once and every chunk is written to
+//! all destinations concurrently through a [`crate::broadcast::Broadcast`].
+//! With `--verify-direct` the sampled chunks are checksummed from the same
+//! read and read back from every destination.
+//!
+//! Each destination keeps its own statistics. A destination that fails (disk
+//! full, I/O error, a mount gone away) is dropped with its first error while
+//! the others are completed; the caller fails the run afterwards if any
+//! destination did. Source entries that cannot be read are skipped for every
+//! destination and counted once.
+//!
+//! Directories, regular files and symlinks are copied (symlinks as links with
+//! `--links`, otherwise the file they point to); special files are skipped.
+//! Hardlinked files are copied as separate files. Filter rules (`--exclude`
+//! and friends) apply as in a single-destination copy.
+
+use crate::broadcast::{record_checksums, write_chunks, Broadcast, DEFAULT_QUEUE_DEPTH};
+use crate::config::SyncConfig;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::filter::FilterRules;
+use crate::metadata::preserve_file_metadata;
+use crate::symlink_rewrite::rewrite;
+use crate::write_verify::ChunkChecksums;
+use compio::fs::File;
+use futures::stream::StreamExt;
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// What one destination received
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DestinationReport {
+    /// Destination root
+    pub path: PathBuf,
+    /// Files written
+    pub files_copied: u64,
+    /// Bytes written
+    pub bytes_copied: u64,
+    /// Directories created
+    pub directories_created: u64,
+    /// Symlinks created
+    pub symlinks_copied: u64,
+    /// Error that dropped this destination from the run, if any
+    pub error: Option<String>,
+}
+
+impl fmt::Display for DestinationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} files, {} bytes, {} directories, {} symlinks",
+            self.path.display(),
+            self.files_copied,
+            self.bytes_copied,
+            self.directories_created,
+            self.symlinks_copied
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " (failed: {error})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of a fan-out run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FanOutReport {
+    /// Files read from the source
+    pub files_read: u64,
+    /// Bytes read from the source
+    pub bytes_read: u64,
+    /// Source entries that could not be read, skipped for every destination
+    pub source_errors: u64,
+    /// One report per destination, in command-line order
+    pub destinations: Vec<DestinationReport>,
+}
+
+impl FanOutReport {
+    /// Destinations dropped because of an error
+    pub fn failed(&self) -> impl Iterator<Item = &DestinationReport> {
+        self.destinations
+            .iter()
+            .filter(|destination| destination.error.is_some())
+    }
+}
+
+/// Copy `config.source` to every destination of `config`, reading it once
+///
+/// # Errors
+///
+/// Returns an error only if the source root cannot be read; failures of
+/// individual destinations and source entries are recorded in the report.
+#[allow(clippy::future_not_send)]
+pub async fn fan_out(config: &SyncConfig) -> Result<FanOutReport> {
+    let run = FanOut {
+        config,
+        roots: config.destinations().cloned().collect(),
+        filter: FilterRules::from_config(&config.traversal.filter)?,
+        report: RefCell::new(FanOutReport {
+            destinations: config
+                .destinations()
+                .map(|path| DestinationReport {
+                    path: path.clone(),
+                    ..DestinationReport::default()
+                })
+                .collect(),
+            ..FanOutReport::default()
+        }),
+    };
+
+    if config.is_file_copy() {
+        let targets = run.targets(Path::new(""));
+        run.copy_file(&config.source, targets).await;
+    } else {
+        run.copy_tree(&config.source).await?;
+    }
+    Ok(run.report.into_inner())
+}
+
+/// State of a fan-out run (single-threaded: all I/O is on this runtime)
+struct FanOut<'a> {
+    /// Options of the run
+    config: &'a SyncConfig,
+    /// Destination roots, indexed like `report.destinations`
+    roots: Vec<PathBuf>,
+    /// Include/exclude rules
+    filter: FilterRules,
+    /// Counters and per-destination results
+    report: RefCell<FanOutReport>,
+}
+
+impl FanOut<'_> {
+    /// `relative` under every destination still in the run, with its index
+    fn targets(&self, relative: &Path) -> Vec<(usize, PathBuf)> {
+        let report = self.report.borrow();
+        self.roots
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| report.destinations[index].error.is_none())
+            .map(|(index, root)| {
+                let target = if relative.as_os_str().is_empty() {
+                    root.clone()
+                } else {
+                    root.join(relative)
+                };
+                (index, target)
+            })
+            .collect()
+    }
+
+    /// Drop destination `index` from the run because of `error`
+    fn fail(&self, index: usize, error: &SyncError) {
+        let mut report = self.report.borrow_mut();
+        let destination = &mut report.destinations[index];
+        if destination.error.is_none() {
+            warn!(
+                "Dropping destination {} from the fan-out: {error}",
+                destination.path.display()
+            );
+            destination.error = Some(error.to_string());
+        }
+    }
+
+    /// Record a source entry that could not be read
+    fn source_failed(&self, path: &Path, error: &SyncError) {
+        self.report.borrow_mut().source_errors += 1;
+        self.config.metadata.run.warnings.entry_failed(path, error);
+    }
+
+    /// Update the report of destination `index`
+    fn record(&self, index: usize, update: impl FnOnce(&mut DestinationReport)) {
+        update(&mut self.report.borrow_mut().destinations[index]);
+    }
+
+    /// Copy the tree under `src_root` to every destination
+    #[allow(clippy::future_not_send)]
+    async fn copy_tree(&self, src_root: &Path) -> Result<()> {
+        let metadata_config = &self.config.metadata;
+        let mut pending = vec![PathBuf::new()];
+        let mut copied_dirs = Vec::new();
+        while let Some(relative) = pending.pop() {
+            let src_dir = src_root.join(&relative);
+            let entries = match std::fs::read_dir(&src_dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    let error = read_error("read_dir", &src_dir, &e);
+                    if relative.as_os_str().is_empty() {
+                        return Err(error);
+                    }
+                    self.source_failed(&src_dir, &error);
+                    continue;
+                }
+            };
+
+            let mut files = Vec::new();
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        self.source_failed(
+                            &src_dir,
+                            &read_error("read directory entry", &src_dir, &e),
+                        );
+                        continue;
+                    }
+                };
+                let child = relative.join(entry.file_name());
+                let src_path = src_root.join(&child);
+                let metadata = match std::fs::symlink_metadata(&src_path) {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        self.source_failed(&src_path, &read_error("lstat", &src_path, &e));
+                        continue;
+                    }
+                };
+                if self.filter.is_excluded(&child, metadata.is_dir()) {
+                    continue;
+                }
+                if metadata.is_dir() {
+                    self.create_dirs(&child).await;
+                    pending.push(child);
+                } else if metadata.is_file() {
+                    files.push(child);
+                } else if metadata.is_symlink() && metadata_config.should_preserve_links() {
+                    self.copy_symlink(&src_path, &child);
+                } else if metadata.is_symlink() && src_path.is_file() {
+                    files.push(child);
+                } else {
+                    self.config.metadata.run.warnings.warn(
+                        "entry skipped by fan-out",
+                        &src_path,
+                        format_args!(
+                            "Skipping {}: only directories, files and symlinks are fanned out",
+                            src_path.display()
+                        ),
+                    );
+                }
+            }
+
+            futures::stream::iter(files)
+                .for_each_concurrent(
+                    self.config.concurrency.max_files_in_flight,
+                    |child| async move {
+                        let targets = self.targets(&child);
+                        self.copy_file(&src_root.join(&child), targets).await;
+                    },
+                )
+                .await;
+            copied_dirs.push(relative);
+        }
+
+        // Directory metadata last, children before parents, so that filling
+        // a directory does not change its preserved times
+        for relative in copied_dirs.iter().rev() {
+            let src_dir = src_root.join(relative);
+            let Ok(src_metadata) = crate::directory::metadata_from_path(&src_dir).await else {
+                continue;
+            };
+            for (index, dst_dir) in self.targets(relative) {
+                if let Err(e) = crate::directory::preserve_directory_metadata(
+                    &src_dir,
+                    &dst_dir,
+                    &src_metadata,
+                    metadata_config,
+                )
+                .await
+                {
+                    self.fail(index, &e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create directory `relative` in every destination
+    #[allow(clippy::future_not_send)]
+    async fn create_dirs(&self, relative: &Path) {
+        for (index, dst_dir) in self.targets(relative) {
+            match compio::fs::create_dir(&dst_dir).await {
+                Ok(()) => self.record(index, |report| report.directories_created += 1),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && dst_dir.is_dir() => {}
+                Err(e) => self.fail(index, &write_error("mkdir", &dst_dir, &e)),
+            }
+        }
+    }
+
+    /// Recreate the symlink at `src_path` as `relative` in every destination
+    /// (its target rewritten by `--symlink-rewrite`)
+    fn copy_symlink(&self, src_path: &Path, relative: &Path) {
+        let target = match std::fs::read_link(src_path) {
+            Ok(target) => target,
+            Err(e) => {
+                self.source_failed(src_path, &read_error("readlink", src_path, &e));
+                return;
+            }
+        };
+        let target = rewrite(&self.config.metadata.symlink_rewrite, &target).unwrap_or(target);
+        for (index, dst) in self.targets(relative) {
+            if dst.is_symlink() {
+                let _ = std::fs::remove_file(&dst);
+            }
+            match std::os::unix::fs::symlink(&target, &dst) {
+                Ok(()) => self.record(index, |report| report.symlinks_copied += 1),
+                Err(e) => self.fail(index, &write_error("symlink", &dst, &e)),
+            }
+        }
+    }
+
+    /// Read `src` once and write it to every target
+    #[allow(clippy::future_not_send)]
+    async fn copy_file(&self, src: &Path, targets: Vec<(usize, PathBuf)>) {
+        if targets.is_empty() {
+            return;
+        }
+        let opened = async {
+            let file = File::open(src).await?;
+            let metadata = file.metadata().await?;
+            Ok::<_, std::io::Error>((file, metadata))
+        };
+        let (src_file, src_metadata) = match opened.await {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.source_failed(src, &read_error("open source", src, &e));
+                return;
+            }
+        };
+        let file_size = src_metadata.len();
+
+        let mut outputs = Vec::with_capacity(targets.len());
+        for (index, dst) in targets {
+            match File::create(&dst).await {
+                Ok(file) => outputs.push((index, dst, file)),
+                Err(e) => self.fail(index, &write_error("create", &dst, &e)),
+            }
+        }
+
+        // Every chunk is read once and shared by all outputs (and the
+        // --verify-direct checksums); an output whose write fails is dropped
+        // and the others continue
+        let mut broadcast = Broadcast::new(DEFAULT_QUEUE_DEPTH);
+        let writers: Vec<_> = outputs
+            .into_iter()
+            .map(|(index, dst, mut file)| {
+                let chunks = broadcast.subscribe();
+                async move {
+                    let result = write_chunks(&mut file, chunks).await;
+                    (index, dst, file, result)
+                }
+            })
+            .collect();
+        let mut checksums = self.config.metadata.verify_direct.map(ChunkChecksums::new);
+        let recorder = checksums
+            .as_mut()
+            .map(|checksums| record_checksums(checksums, broadcast.subscribe()));
+        let pool = self.config.metadata.run.buffers.pool(file_size);
+        let (read, written, ()) = futures::join!(
+            broadcast.pump(&src_file, file_size, pool),
+            futures::future::join_all(writers),
+            async {
+                if let Some(recorder) = recorder {
+                    recorder.await;
+                }
+            }
+        );
+        let bytes_read = match read {
+            Ok(bytes_read) => bytes_read,
+            Err(e) => {
+                self.source_failed(src, &read_error("read source", src, &e));
+                return;
+            }
+        };
+        {
+            let mut report = self.report.borrow_mut();
+            report.files_read += 1;
+            report.bytes_read += bytes_read;
+        }
+        let outputs = written
+            .into_iter()
+            .filter_map(|(index, dst, file, result)| match result {
+                Ok(()) => Some((index, dst, file)),
+                Err(e) => {
+                    self.fail(index, &write_error("write", &dst, &e));
+                    None
+                }
+            });
+
+        let accessed = src_metadata.accessed().unwrap_or(std::time::UNIX_EPOCH);
+        let modified = src_metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+        for (index, dst, dst_file) in outputs {
+            let finished = async {
+                if self.config.metadata.fsync {
+                    dst_file
+                        .sync_all()
+                        .await
+                        .map_err(|e| write_error("fsync", &dst, &e))?;
+                }
+                if let Some(checksums) = &checksums {
+                    let reader = File::open(&dst)
+                        .await
+                        .map_err(|e| write_error("reopen for verification", &dst, &e))?;
+                    checksums.clone().verify(&reader, &dst).await?;
+                }
+                preserve_file_metadata(
+                    &src_file,
+                    &dst_file,
+                    &dst,
+                    accessed,
+                    modified,
+                    &self.config.metadata,
+                )
+                .await
+            };
+            match finished.await {
+                Ok(()) => {
+                    debug!("Fanned out {} to {}", src.display(), dst.display());
+                    self.record(index, |report| {
+                        report.files_copied += 1;
+                        report.bytes_copied += bytes_read;
+                    });
+                }
+                Err(e) => self.fail(index, &e),
+            }
+        }
+    }
+}
+
+fn read_error(operation: &'static str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .source(path)
+        .io_cause(e)
+        .file_system()
+}
+
+fn write_error(operation: &'static str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_fan_out_isolates_failed_destination() {
+        // Requirement: Every destination gets the tree; one that cannot be
+        // written is reported and does not stop the others
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("a.txt"), b"alpha").unwrap();
+        std::fs::write(src.join("sub/b.bin"), vec![7u8; 300_000]).unwrap();
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+        // A regular file where a destination root should be
+        let broken = temp_dir.path().join("not-a-dir");
+        std::fs::write(&broken, b"").unwrap();
+
+        let mut config = SyncConfig::new(&src, temp_dir.path().join("one"));
+        config.metadata.links = true;
+        config.extra_destinations = vec![broken.clone(), temp_dir.path().join("two")];
+        for destination in [&config.destination, &config.extra_destinations[1]] {
+            std::fs::create_dir(destination).unwrap();
+        }
+
+        let report = fan_out(&config).await.unwrap();
+        assert_eq!(report.files_read, 2);
+        assert_eq!(report.bytes_read, 300_005);
+        for name in ["one", "two"] {
+            let dst = temp_dir.path().join(name);
+            assert_eq!(std::fs::read(dst.join("a.txt")).unwrap(), b"alpha");
+            assert_eq!(std::fs::read(dst.join("sub/b.bin")).unwrap().len(), 300_000);
+            assert_eq!(
+                std::fs::read_link(dst.join("link")).unwrap(),
+                Path::new("a.txt")
+            );
+        }
+        assert_eq!(report.destinations[0].files_copied, 2);
+        assert_eq!(report.destinations[0].directories_created, 1);
+        assert_eq!(report.destinations[2].bytes_copied, 300_005);
+        let failed: Vec<_> = report.failed().map(|d| d.path.clone()).collect();
+        assert_eq!(failed, vec![broken]);
+    }
+}
@@ -126,6 +126,8 @@ impl AsyncFile for AsyncFileWrapper {
             nlink: m.nlink(),
             ino: m.ino(),
             dev: m.dev(),
+            rdev: m.rdev(),
+            blocks: m.blocks(),
             accessed: m.accessed().unwrap_or(SystemTime::UNIX_EPOCH),
             modified: m.modified().unwrap_or(SystemTime::UNIX_EPOCH),
             created: m.created().ok(),
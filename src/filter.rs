@@ -0,0 +1,359 @@
+//! Include/exclude filter rules (`--exclude`, `--include`, `--exclude-from`,
+//! `--filter`)
+//!
+//! Rules follow rsync's semantics:
+//!
+//! - Rules are checked in command-line order and the first match decides; an
+//!   entry no rule matches is copied.
+//! - `*` matches within one path component, `**` across components, `?` one
+//!   character other than `/`, and `[...]` a character class.
+//! - A pattern without a `/` (other than a trailing one) or `**` matches the
+//!   entry's name; otherwise it matches the end of its path relative to the
+//!   transfer root, at a component boundary. A leading `/` anchors the pattern
+//!   at the transfer root.
+//! - A trailing `/` matches directories only; `dir/***` matches `dir` and
+//!   everything below it.
+//! - `--exclude`/`--include` values may start with `- ` or `+ ` to choose the
+//!   rule type explicitly; `!` clears the rules given so far. `--filter` takes
+//!   `- PATTERN`, `+ PATTERN`, `exclude PATTERN`, `include PATTERN` or `!`.
+//! - `--exclude-from` files hold one `--exclude` value per line; blank lines
+//!   and lines starting with `#` or `;` are ignored.
+//!
+//! An excluded directory is not descended into, so nothing below it is
+//! copied. With `--delete`, destination entries that match an exclude rule are
+//! kept.
+
+use crate::cli::{FilterConfig, FilterOption};
+use crate::error::{Result, SyncError};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Compiled filter rules, in the order they are checked
+#[derive(Debug, Clone, Default)]
+pub struct FilterRules {
+    /// Rules, first match wins
+    rules: Vec<Rule>,
+}
+
+/// One include or exclude rule
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Whether a match includes (`+`) rather than excludes (`-`)
+    include: bool,
+    /// Pattern the entry's path is matched against
+    pattern: Pattern,
+}
+
+/// A parsed rsync pattern
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// Wildcard pattern, without leading `/`, trailing `/` or `/***`
+    glob: Vec<u8>,
+    /// Matched at the transfer root only (leading `/`)
+    anchored: bool,
+    /// Matched against the path rather than the name
+    full_path: bool,
+    /// Matches directories only (trailing `/`)
+    dir_only: bool,
+    /// Also matches everything below a match (trailing `/***`)
+    with_contents: bool,
+}
+
+impl FilterRules {
+    /// Compile the rules of `config`, reading `--exclude-from` files
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` if a `--filter` rule is not
+    /// understood or an `--exclude-from` file cannot be read.
+    pub fn from_config(config: &FilterConfig) -> Result<Self> {
+        let mut rules = Self::default();
+        for option in &config.rules {
+            match option {
+                FilterOption::Exclude(value) => rules.add_option(value, false),
+                FilterOption::Include(value) => rules.add_option(value, true),
+                FilterOption::Filter(rule) => rules.add_filter(rule)?,
+                FilterOption::ExcludeFrom(path) => {
+                    let contents = std::fs::read(path).map_err(|e| {
+                        SyncError::InvalidConfig(format!(
+                            "Cannot read --exclude-from file {}: {e}",
+                            path.display()
+                        ))
+                    })?;
+                    for line in contents.split(|&b| b == b'\n') {
+                        let line = line.strip_suffix(b"\r").unwrap_or(line);
+                        if line.is_empty() || line.starts_with(b"#") || line.starts_with(b";") {
+                            continue;
+                        }
+                        rules.add_option(&String::from_utf8_lossy(line), false);
+                    }
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Whether there are no rules (everything is copied)
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the entry at `relative` (from the transfer root) is excluded
+    #[must_use]
+    pub fn is_excluded(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.as_os_str().as_bytes();
+        self.rules
+            .iter()
+            .find(|rule| rule.pattern.matches(path, is_dir))
+            .is_some_and(|rule| !rule.include)
+    }
+
+    /// Add an `--exclude`/`--include` value (`include` unless prefixed)
+    fn add_option(&mut self, value: &str, include: bool) {
+        if value == "!" {
+            self.rules.clear();
+        } else if let Some(pattern) = value.strip_prefix("- ") {
+            self.push(false, pattern);
+        } else if let Some(pattern) = value.strip_prefix("+ ") {
+            self.push(true, pattern);
+        } else {
+            self.push(include, value);
+        }
+    }
+
+    /// Add a `--filter` rule
+    fn add_filter(&mut self, rule: &str) -> Result<()> {
+        let rule = rule.trim_start();
+        if rule == "!" || rule == "clear" {
+            self.rules.clear();
+            return Ok(());
+        }
+        let (kind, pattern) = rule.split_once([' ', '_']).ok_or_else(|| {
+            SyncError::InvalidConfig(format!("Filter rule has no pattern: {rule:?}"))
+        })?;
+        match kind {
+            "-" | "exclude" => self.push(false, pattern),
+            "+" | "include" => self.push(true, pattern),
+            _ => {
+                return Err(SyncError::InvalidConfig(format!(
+                    "Unsupported filter rule {rule:?} (expected -, +, exclude, include or !)"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, include: bool, pattern: &str) {
+        self.rules.push(Rule {
+            include,
+            pattern: Pattern::new(pattern),
+        });
+    }
+}
+
+impl Pattern {
+    /// Parse an rsync pattern
+    #[must_use]
+    pub fn new(text: &str) -> Self {
+        let mut glob = text.as_bytes();
+        let with_contents = glob.ends_with(b"/***");
+        if with_contents {
+            glob = &glob[..glob.len() - 4];
+        }
+        let dir_only = glob.len() > 1 && glob.ends_with(b"/");
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.starts_with(b"/");
+        if anchored {
+            glob = &glob[1..];
+        }
+        Self {
+            glob: glob.to_vec(),
+            anchored,
+            full_path: anchored || glob.contains(&b'/') || glob.windows(2).any(|w| w == b"**"),
+            dir_only,
+            with_contents,
+        }
+    }
+
+    /// Whether `path` (relative to the transfer root) matches
+    #[must_use]
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.with_contents {
+            // The match itself, or any directory above the entry
+            return self.matches_entry(path, true)
+                || path
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &b)| b == b'/')
+                    .any(|(end, _)| self.matches_entry(&path[..end], true));
+        }
+        self.matches_entry(path, is_dir)
+    }
+
+    /// Whether the entry at `path` matches, ignoring `with_contents`
+    fn matches_entry(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if !self.full_path {
+            let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+            return glob_match(&self.glob, name);
+        }
+        if self.anchored {
+            return glob_match(&self.glob, path);
+        }
+        // Unanchored: the path or any suffix starting at a component
+        glob_match(&self.glob, path)
+            || path
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'/')
+                .any(|(slash, _)| glob_match(&self.glob, &path[slash + 1..]))
+    }
+}
+
+/// Match `text` against an rsync wildcard pattern
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"*").unwrap_or(rest);
+            (0..=text.len()).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => {
+            let component = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=component).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'?', rest @ ..] => {
+            matches!(text, [first, tail @ ..] if *first != b'/' && glob_match(rest, tail))
+        }
+        [b'[', class @ ..] => match (text, class_match(class, text.first().copied())) {
+            ([_, tail @ ..], Some((true, rest))) => glob_match(rest, tail),
+            // An unterminated class is a literal `[`
+            ([b'[', tail @ ..], None) => glob_match(class, tail),
+            _ => false,
+        },
+        [b'\\', literal, rest @ ..] | [literal, rest @ ..] => {
+            matches!(text, [first, tail @ ..] if first == literal && glob_match(rest, tail))
+        }
+    }
+}
+
+/// Match `c` against the class body after `[`
+///
+/// Returns whether it matched and the pattern after the closing `]`, or
+/// `None` if the class is not terminated.
+fn class_match(class: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut body) = match class {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match body {
+            [] => return None,
+            [b']', rest @ ..] if !first => {
+                let hit = c.is_some_and(|c| c != b'/' && matched != negated);
+                return Some((hit, rest));
+            }
+            [low, b'-', high, rest @ ..] if *high != b']' => {
+                matched |= c.is_some_and(|c| (*low..=*high).contains(&c));
+                body = rest;
+            }
+            [b, rest @ ..] => {
+                matched |= c == Some(*b);
+                body = rest;
+            }
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rules(options: Vec<FilterOption>) -> FilterRules {
+        FilterRules::from_config(&FilterConfig { rules: options }).unwrap()
+    }
+
+    fn excluded(rules: &FilterRules, path: &str, is_dir: bool) -> bool {
+        rules.is_excluded(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.rs", b"main.rs"));
+        assert!(!glob_match(b"*.rs", b"src/main.rs"));
+        assert!(glob_match(b"src/**", b"src/a/b.rs"));
+        assert!(glob_match(b"**/b.rs", b"src/a/b.rs"));
+        assert!(glob_match(b"?.txt", b"a.txt"));
+        assert!(!glob_match(b"?", b"/"));
+        assert!(glob_match(b"[a-c]x", b"bx"));
+        assert!(!glob_match(b"[!a-c]x", b"bx"));
+        assert!(glob_match(b"[]]", b"]"));
+        assert!(glob_match(b"a\\*", b"a*"));
+        assert!(!glob_match(b"a\\*", b"ab"));
+        assert!(glob_match(b"[x", b"[x"));
+    }
+
+    #[test]
+    fn test_rsync_pattern_semantics() {
+        let rules = rules(vec![
+            FilterOption::Include("keep.log".to_string()),
+            FilterOption::Exclude("*.log".to_string()),
+            FilterOption::Exclude("/build".to_string()),
+            FilterOption::Exclude("cache/".to_string()),
+            FilterOption::Exclude("docs/*.tmp".to_string()),
+            FilterOption::Filter("- vendor/***".to_string()),
+        ]);
+        // First match wins
+        assert!(!excluded(&rules, "a/keep.log", false));
+        assert!(excluded(&rules, "a/other.log", false));
+        // Anchored at the root only
+        assert!(excluded(&rules, "build", true));
+        assert!(!excluded(&rules, "src/build", true));
+        // Trailing slash: directories only
+        assert!(excluded(&rules, "x/cache", true));
+        assert!(!excluded(&rules, "x/cache", false));
+        // A path pattern matches at any component boundary, `*` not across `/`
+        assert!(excluded(&rules, "docs/a.tmp", false));
+        assert!(excluded(&rules, "sub/docs/a.tmp", false));
+        assert!(!excluded(&rules, "docs/x/a.tmp", false));
+        assert!(!excluded(&rules, "mydocs/a.tmp", false));
+        // `***` covers the directory and its contents
+        assert!(excluded(&rules, "vendor", true));
+        assert!(excluded(&rules, "vendor/lib/x.c", false));
+        assert!(!excluded(&rules, "src/main.rs", false));
+    }
+
+    #[test]
+    fn test_prefixes_clear_and_exclude_from() {
+        let temp_dir = TempDir::new().unwrap();
+        let list = temp_dir.path().join("excludes");
+        std::fs::write(&list, "# comment\n; comment\n\n*.o\n+ *.c\n*\n").unwrap();
+
+        let rules = rules(vec![
+            FilterOption::Exclude("*.c".to_string()),
+            FilterOption::Exclude("!".to_string()),
+            FilterOption::Include("- secret".to_string()),
+            FilterOption::ExcludeFrom(list),
+        ]);
+        assert!(excluded(&rules, "secret", false));
+        assert!(excluded(&rules, "main.o", false));
+        assert!(!excluded(&rules, "main.c", false));
+        assert!(excluded(&rules, "README", false));
+
+        let bad = FilterConfig {
+            rules: vec![FilterOption::Filter("merge .rules".to_string())],
+        };
+        assert!(FilterRules::from_config(&bad).is_err());
+    }
+}
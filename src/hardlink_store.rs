@@ -0,0 +1,723 @@
+//! Compact, optionally disk-backed storage for completed hardlink inodes
+//!
+//! `FilesystemTracker` keeps one `HardlinkInfo` per hardlinked inode, each
+//! carrying two paths, an atomic counter and a condvar. That is fine for
+//! everyday trees but reaches many GB on trees with hundreds of millions of
+//! hardlinked inodes. Once an inode's copy is complete the only state still
+//! needed is "which destination path do later links point at", so completed
+//! inodes can move into this much denser structure.
+//!
+//! # Layout
+//!
+//! - **Records**: fixed-size 32-byte slots (`dev`, `ino`, path offset, path
+//!   length, link count) in an open-addressing table with linear probing
+//! - **Path arena**: destination paths appended back-to-back as raw bytes;
+//!   records reference them by offset, so there is no per-entry allocation
+//!
+//! Both parts are behind small storage traits with an in-memory (`Vec`) and
+//! a file-backed implementation. `InodePathStore` fills the in-memory table up
+//! to a configured number of entries and then spills further inodes into the
+//! file-backed table in the configured directory. Spill files are unlinked
+//! right after creation, so nothing is left behind if the process dies.
+//!
+//! # Performance Notes
+//!
+//! - In memory: ~43 bytes per inode at the 0.75 max load factor, plus path bytes
+//! - On disk: every probe is a `pread`; lookups are rare (only for later links
+//!   of an already-copied inode), so the spill path favours memory over speed
+//! - File-backed I/O is synchronous; it runs under the tracker's mutex
+
+use crate::hardlink_tracker::InodeInfo;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Size of a serialized record in bytes
+const RECORD_SIZE: usize = 32;
+
+/// Initial number of slots for a new table (must be a power of two)
+const INITIAL_SLOTS: usize = 1024;
+
+/// Counter used to give spill files unique names within a process
+static SPILL_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+/// Configuration for `InodePathStore`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InodeStoreConfig {
+    /// Maximum number of inodes kept in memory before spilling to disk
+    pub memory_entries: usize,
+    /// Directory for spill files (`None` = never spill, grow in memory)
+    pub spill_dir: Option<PathBuf>,
+}
+
+/// Keep everything in memory (compact representation only)
+impl Default for InodeStoreConfig {
+    fn default() -> Self {
+        Self {
+            memory_entries: usize::MAX,
+            spill_dir: None,
+        }
+    }
+}
+
+impl InodeStoreConfig {
+    /// Default in-memory capacity before spilling (~170 MB of records)
+    pub const DEFAULT_MEMORY_ENTRIES: usize = 4_000_000;
+
+    /// Spill to `dir` once `memory_entries` inodes are held in memory
+    #[must_use]
+    pub fn with_spill(memory_entries: usize, dir: &Path) -> Self {
+        Self {
+            memory_entries,
+            spill_dir: Some(dir.to_path_buf()),
+        }
+    }
+}
+
+// ============================================================================
+// RECORDS
+// ============================================================================
+
+/// Fixed-size table record
+///
+/// A record with `links == 0` is an empty slot, which makes a zero-filled
+/// file a valid empty table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Record {
+    /// Device ID
+    dev: u64,
+    /// Inode number
+    ino: u64,
+    /// Offset of the destination path in the arena
+    path_offset: u64,
+    /// Length of the destination path in bytes
+    path_len: u32,
+    /// Number of links seen for this inode (0 = empty slot)
+    links: u32,
+}
+
+impl Record {
+    /// Whether this slot is unused
+    const fn is_empty(&self) -> bool {
+        self.links == 0
+    }
+
+    /// Whether this record belongs to `key`
+    const fn matches(&self, key: InodeInfo) -> bool {
+        self.dev == key.dev && self.ino == key.ino
+    }
+
+    /// Key of this record
+    const fn key(&self) -> InodeInfo {
+        InodeInfo {
+            dev: self.dev,
+            ino: self.ino,
+        }
+    }
+
+    /// Serialize to little-endian bytes
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.dev.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.ino.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.path_offset.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.path_len.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.links.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from little-endian bytes
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let u64_at = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+        let u32_at = |i: usize| {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&bytes[i..i + 4]);
+            u32::from_le_bytes(b)
+        };
+        Self {
+            dev: u64_at(0),
+            ino: u64_at(8),
+            path_offset: u64_at(16),
+            path_len: u32_at(24),
+            links: u32_at(28),
+        }
+    }
+}
+
+/// Hash an inode key to a starting slot (splitmix64 finalizer)
+#[allow(clippy::cast_possible_truncation)] // Masked to table size below
+fn hash_key(key: InodeInfo) -> usize {
+    let mut x = key.dev.rotate_left(32) ^ key.ino;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (x ^ (x >> 31)) as usize
+}
+
+// ============================================================================
+// STORAGE BACKENDS
+// ============================================================================
+
+/// Slot array backing an open-addressing table
+trait SlotStorage: Sized {
+    /// Number of slots (always a power of two)
+    fn slot_count(&self) -> usize;
+    /// Read the record at `index`
+    fn read(&self, index: usize) -> io::Result<Record>;
+    /// Write the record at `index`
+    fn write(&mut self, index: usize, record: Record) -> io::Result<()>;
+    /// Create an empty slot array of the same kind with `count` slots
+    fn new_empty(&self, count: usize) -> io::Result<Self>;
+    /// Bytes of memory used by the slot array
+    fn memory_bytes(&self) -> usize;
+}
+
+/// Append-only byte arena holding destination paths
+trait PathArena {
+    /// Append `bytes`, returning their offset
+    fn append(&mut self, bytes: &[u8]) -> io::Result<u64>;
+    /// Read `len` bytes at `offset`
+    fn read(&self, offset: u64, len: u32) -> io::Result<Vec<u8>>;
+    /// Bytes of memory used by the arena
+    fn memory_bytes(&self) -> usize;
+}
+
+/// In-memory slot array
+struct VecSlots(Vec<Record>);
+
+impl SlotStorage for VecSlots {
+    fn slot_count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read(&self, index: usize) -> io::Result<Record> {
+        Ok(self.0[index])
+    }
+
+    fn write(&mut self, index: usize, record: Record) -> io::Result<()> {
+        self.0[index] = record;
+        Ok(())
+    }
+
+    fn new_empty(&self, count: usize) -> io::Result<Self> {
+        Ok(Self(vec![Record::default(); count]))
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.0.capacity() * std::mem::size_of::<Record>()
+    }
+}
+
+/// In-memory path arena
+struct VecArena(Vec<u8>);
+
+impl PathArena for VecArena {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<u64> {
+        let offset = self.0.len() as u64;
+        self.0.extend_from_slice(bytes);
+        Ok(offset)
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // Offsets come from this arena's own length
+    fn read(&self, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        let start = offset as usize;
+        let end = start + len as usize;
+        self.0
+            .get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "path offset out of range"))
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+/// Create an anonymous (already unlinked) read/write file in `dir`
+fn create_spill_file(dir: &Path, kind: &str) -> io::Result<File> {
+    let id = SPILL_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = crate::temp_files::temp_path(dir, &format!("hardlinks.{id}.{kind}"));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // Unlink immediately: the open handle keeps the data alive, and a crash
+    // can only leave a spill file behind in this tiny window (the run-ID
+    // temporary name lets `arsync cleanup` find it)
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// File-backed slot array (records at `index * RECORD_SIZE`)
+struct FileSlots {
+    /// Backing file (zero-filled = all slots empty)
+    file: File,
+    /// Directory used for new slot files on growth
+    dir: PathBuf,
+    /// Number of slots
+    count: usize,
+}
+
+impl FileSlots {
+    /// Create a zero-filled slot file with `count` slots
+    fn create(dir: &Path, count: usize) -> io::Result<Self> {
+        let file = create_spill_file(dir, "slots")?;
+        file.set_len((count * RECORD_SIZE) as u64)?;
+        Ok(Self {
+            file,
+            dir: dir.to_path_buf(),
+            count,
+        })
+    }
+}
+
+impl SlotStorage for FileSlots {
+    fn slot_count(&self) -> usize {
+        self.count
+    }
+
+    fn read(&self, index: usize) -> io::Result<Record> {
+        let mut bytes = [0u8; RECORD_SIZE];
+        self.file
+            .read_exact_at(&mut bytes, (index * RECORD_SIZE) as u64)?;
+        Ok(Record::from_bytes(&bytes))
+    }
+
+    fn write(&mut self, index: usize, record: Record) -> io::Result<()> {
+        self.file
+            .write_all_at(&record.to_bytes(), (index * RECORD_SIZE) as u64)
+    }
+
+    fn new_empty(&self, count: usize) -> io::Result<Self> {
+        Self::create(&self.dir, count)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// File-backed path arena
+struct FileArena {
+    /// Backing file
+    file: File,
+    /// Current length (next append offset)
+    len: u64,
+}
+
+impl PathArena for FileArena {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<u64> {
+        let offset = self.len;
+        self.file.write_all_at(bytes, offset)?;
+        self.len += bytes.len() as u64;
+        Ok(offset)
+    }
+
+    fn read(&self, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len as usize];
+        self.file.read_exact_at(&mut bytes, offset)?;
+        Ok(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        0
+    }
+}
+
+// ============================================================================
+// OPEN-ADDRESSING TABLE
+// ============================================================================
+
+/// Open-addressing `(dev, ino) → (path, links)` table over any backend
+struct CompactTable<S: SlotStorage, A: PathArena> {
+    /// Slot array
+    slots: S,
+    /// Path arena
+    arena: A,
+    /// Number of occupied slots
+    len: usize,
+}
+
+impl<S: SlotStorage, A: PathArena> CompactTable<S, A> {
+    /// Find the slot for `key`: either its record or the empty slot where it belongs
+    fn probe(slots: &S, key: InodeInfo) -> io::Result<(usize, Option<Record>)> {
+        let mask = slots.slot_count() - 1;
+        let mut index = hash_key(key) & mask;
+        loop {
+            let record = slots.read(index)?;
+            if record.is_empty() {
+                return Ok((index, None));
+            }
+            if record.matches(key) {
+                return Ok((index, Some(record)));
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    /// Double the slot array, rehashing every record (paths stay in place)
+    fn grow(&mut self) -> io::Result<()> {
+        let mut next = self.slots.new_empty(self.slots.slot_count() * 2)?;
+        for index in 0..self.slots.slot_count() {
+            let record = self.slots.read(index)?;
+            if !record.is_empty() {
+                let (slot, _) = Self::probe(&next, record.key())?;
+                next.write(slot, record)?;
+            }
+        }
+        self.slots = next;
+        Ok(())
+    }
+
+    /// Look up a key
+    fn get(&self, key: InodeInfo) -> io::Result<Option<Record>> {
+        Ok(Self::probe(&self.slots, key)?.1)
+    }
+
+    /// Insert a new key (caller guarantees it is absent)
+    fn insert(&mut self, key: InodeInfo, path: &[u8], links: u32) -> io::Result<()> {
+        // Keep load factor <= 0.75 so probe sequences stay short
+        if (self.len + 1) * 4 > self.slots.slot_count() * 3 {
+            self.grow()?;
+        }
+        let path_len = u32::try_from(path.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path too long"))?;
+        let path_offset = self.arena.append(path)?;
+        let (slot, _) = Self::probe(&self.slots, key)?;
+        self.slots.write(
+            slot,
+            Record {
+                dev: key.dev,
+                ino: key.ino,
+                path_offset,
+                path_len,
+                links: links.max(1),
+            },
+        )?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Increment the link count of an existing key, returning the new count
+    fn add_link(&mut self, key: InodeInfo) -> io::Result<Option<u32>> {
+        let (slot, record) = Self::probe(&self.slots, key)?;
+        let Some(mut record) = record else {
+            return Ok(None);
+        };
+        record.links = record.links.saturating_add(1);
+        self.slots.write(slot, record)?;
+        Ok(Some(record.links))
+    }
+
+    /// Read the destination path of a record
+    fn path_of(&self, record: &Record) -> io::Result<PathBuf> {
+        let bytes = self.arena.read(record.path_offset, record.path_len)?;
+        Ok(PathBuf::from(OsStr::from_bytes(&bytes)))
+    }
+
+    /// Bytes of memory used by this table
+    fn memory_bytes(&self) -> usize {
+        self.slots.memory_bytes() + self.arena.memory_bytes()
+    }
+}
+
+/// In-memory table
+type MemoryTable = CompactTable<VecSlots, VecArena>;
+/// File-backed table
+type SpillTable = CompactTable<FileSlots, FileArena>;
+
+// ============================================================================
+// PUBLIC STORE
+// ============================================================================
+
+/// Compact inode → destination path store with optional disk spillover
+pub struct InodePathStore {
+    /// In-memory table (always present)
+    memory: MemoryTable,
+    /// File-backed table, created on first spill
+    spill: Option<SpillTable>,
+    /// Store configuration
+    config: InodeStoreConfig,
+    /// Number of inodes with more than one link seen
+    hardlink_groups: usize,
+    /// Total links seen across all inodes
+    total_links: u64,
+}
+
+impl std::fmt::Debug for InodePathStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InodePathStore")
+            .field("memory_entries", &self.memory.len)
+            .field("spilled_entries", &self.spill.as_ref().map_or(0, |s| s.len))
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InodePathStore {
+    /// Create an empty store
+    #[must_use]
+    pub fn new(config: InodeStoreConfig) -> Self {
+        Self {
+            memory: CompactTable {
+                slots: VecSlots(vec![Record::default(); INITIAL_SLOTS]),
+                arena: VecArena(Vec::new()),
+                len: 0,
+            },
+            spill: None,
+            config,
+            hardlink_groups: 0,
+            total_links: 0,
+        }
+    }
+
+    /// Look up a record in memory, then on disk
+    fn find(&self, key: InodeInfo) -> io::Result<Option<(bool, Record)>> {
+        if let Some(record) = self.memory.get(key)? {
+            return Ok(Some((false, record)));
+        }
+        match &self.spill {
+            Some(spill) => Ok(spill.get(key)?.map(|r| (true, r))),
+            None => Ok(None),
+        }
+    }
+
+    /// Record a completed inode and the destination path later links point at
+    ///
+    /// Inserting a key that is already present only adds `links` to its count.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a spill file cannot be created or written.
+    pub fn insert(&mut self, key: InodeInfo, dst_path: &Path, links: u32) -> io::Result<()> {
+        if self.find(key)?.is_some() {
+            for _ in 0..links {
+                self.add_link(key)?;
+            }
+            return Ok(());
+        }
+
+        let path = dst_path.as_os_str().as_bytes();
+        match &self.config.spill_dir {
+            Some(dir) if self.memory.len >= self.config.memory_entries => {
+                if self.spill.is_none() {
+                    self.spill = Some(CompactTable {
+                        slots: FileSlots::create(dir, INITIAL_SLOTS)?,
+                        arena: FileArena {
+                            file: create_spill_file(dir, "paths")?,
+                            len: 0,
+                        },
+                        len: 0,
+                    });
+                    tracing::info!(
+                        "Hardlink tracker spilling to disk in {} after {} in-memory inodes",
+                        dir.display(),
+                        self.memory.len
+                    );
+                }
+                if let Some(spill) = &mut self.spill {
+                    spill.insert(key, path, links)?;
+                }
+            }
+            _ => self.memory.insert(key, path, links)?,
+        }
+
+        self.total_links += u64::from(links.max(1));
+        if links > 1 {
+            self.hardlink_groups += 1;
+        }
+        Ok(())
+    }
+
+    /// Count one more link to an already-stored inode
+    ///
+    /// Returns `false` if the inode is not in the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the spill file cannot be read or written.
+    pub fn add_link(&mut self, key: InodeInfo) -> io::Result<bool> {
+        let new_count = match self.memory.add_link(key)? {
+            Some(count) => Some(count),
+            None => match &mut self.spill {
+                Some(spill) => spill.add_link(key)?,
+                None => None,
+            },
+        };
+        match new_count {
+            Some(count) => {
+                self.total_links += 1;
+                if count == 2 {
+                    self.hardlink_groups += 1;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Check whether an inode is stored
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the spill file cannot be read.
+    pub fn contains(&self, key: InodeInfo) -> io::Result<bool> {
+        Ok(self.find(key)?.is_some())
+    }
+
+    /// Get the destination path stored for an inode
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the spill file cannot be read.
+    pub fn get_dst_path(&self, key: InodeInfo) -> io::Result<Option<PathBuf>> {
+        match self.find(key)? {
+            Some((false, record)) => self.memory.path_of(&record).map(Some),
+            Some((true, record)) => match &self.spill {
+                Some(spill) => spill.path_of(&record).map(Some),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Number of stored inodes (memory + disk)
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.memory.len + self.spill.as_ref().map_or(0, |s| s.len)
+    }
+
+    /// Whether the store is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of inodes held on disk
+    #[must_use]
+    pub fn spilled_len(&self) -> usize {
+        self.spill.as_ref().map_or(0, |s| s.len)
+    }
+
+    /// Number of stored inodes with more than one link
+    #[must_use]
+    pub const fn hardlink_groups(&self) -> usize {
+        self.hardlink_groups
+    }
+
+    /// Total links counted across all stored inodes
+    #[must_use]
+    pub const fn total_links(&self) -> u64 {
+        self.total_links
+    }
+
+    /// Approximate heap memory used by the in-memory part of the store
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        self.memory.memory_bytes() + self.spill.as_ref().map_or(0, SpillTable::memory_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(ino: u64) -> InodeInfo {
+        InodeInfo { dev: 42, ino }
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let record = Record {
+            dev: 1,
+            ino: u64::MAX,
+            path_offset: 123_456,
+            path_len: 17,
+            links: 3,
+        };
+        assert_eq!(Record::from_bytes(&record.to_bytes()), record);
+        assert!(Record::from_bytes(&[0u8; RECORD_SIZE]).is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_insert_get_and_growth() {
+        let mut store = InodePathStore::new(InodeStoreConfig::default());
+        let count = (INITIAL_SLOTS * 4) as u64;
+        for ino in 0..count {
+            store
+                .insert(key(ino), Path::new(&format!("/dst/file{ino}")), 2)
+                .unwrap();
+        }
+        assert_eq!(store.len(), count as usize);
+        assert_eq!(store.spilled_len(), 0);
+        for ino in (0..count).step_by(97) {
+            assert_eq!(
+                store.get_dst_path(key(ino)).unwrap(),
+                Some(PathBuf::from(format!("/dst/file{ino}")))
+            );
+        }
+        assert_eq!(store.get_dst_path(key(count + 1)).unwrap(), None);
+        assert_eq!(store.hardlink_groups(), count as usize);
+    }
+
+    #[test]
+    fn test_same_inode_on_different_devices() {
+        let mut store = InodePathStore::new(InodeStoreConfig::default());
+        store
+            .insert(InodeInfo { dev: 1, ino: 7 }, Path::new("/a"), 2)
+            .unwrap();
+        store
+            .insert(InodeInfo { dev: 2, ino: 7 }, Path::new("/b"), 2)
+            .unwrap();
+        assert_eq!(
+            store.get_dst_path(InodeInfo { dev: 1, ino: 7 }).unwrap(),
+            Some(PathBuf::from("/a"))
+        );
+        assert_eq!(
+            store.get_dst_path(InodeInfo { dev: 2, ino: 7 }).unwrap(),
+            Some(PathBuf::from("/b"))
+        );
+    }
+
+    #[test]
+    fn test_spill_to_disk() {
+        let spill_dir = TempDir::new().unwrap();
+        let mut store = InodePathStore::new(InodeStoreConfig::with_spill(100, spill_dir.path()));
+        for ino in 0..5000 {
+            store
+                .insert(key(ino), Path::new(&format!("/dst/{ino}")), 1)
+                .unwrap();
+        }
+        assert_eq!(store.len(), 5000);
+        assert_eq!(store.spilled_len(), 4900);
+        assert_eq!(
+            store.get_dst_path(key(4999)).unwrap(),
+            Some(PathBuf::from("/dst/4999"))
+        );
+        assert_eq!(
+            store.get_dst_path(key(3)).unwrap(),
+            Some(PathBuf::from("/dst/3"))
+        );
+
+        // Links added to spilled entries are counted
+        assert!(store.add_link(key(4000)).unwrap());
+        assert_eq!(store.hardlink_groups(), 1);
+        assert_eq!(store.total_links(), 5001);
+
+        // Spill files are unlinked immediately and never visible in the directory
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
+}
@@ -5,11 +5,19 @@
 //! - The first task to encounter a hardlinked inode becomes the "copier"
 //! - Subsequent tasks become "linkers" that wait for the copier to finish
 //! - The copier signals completion, allowing linkers to create hardlinks
-
+//!
+//! For very large trees the tracker can optionally move completed inodes out of
+//! the `DashMap` into a compact, disk-spillable `InodePathStore`
+//! (see `crate::hardlink_store`), keeping only in-flight inodes in the map.
+//! Paths in the map are `InternedPath`s that share their directory prefixes
+//! with the traversal.
+
+use crate::hardlink_store::{InodePathStore, InodeStoreConfig};
+use crate::interned_path::InternedPath;
 use dashmap::DashMap;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::debug;
 
@@ -32,17 +40,19 @@ pub struct InodeInfo {
 pub struct HardlinkInfo {
     /// Original file path (immutable after creation)
     #[allow(dead_code)]
-    pub original_path: std::path::PathBuf,
+    pub original_path: InternedPath,
     /// Inode number (immutable after creation)
     pub inode_number: u64,
     /// Number of hardlinks found (incremented atomically)
     pub link_count: AtomicU64,
     /// Destination path (set at registration time by first copier)
-    pub dst_path: std::path::PathBuf,
+    pub dst_path: InternedPath,
     /// Condition variable signaled when copy completes
     /// Linker tasks wait on this (inside `register_file`) before returning
     /// Public for testing synchronization behavior
     pub copy_complete: Arc<compio_sync::Condvar>,
+    /// Set by `signal_copy_complete`, for linkers that do not wait
+    pub copied: AtomicBool,
 }
 
 impl std::fmt::Debug for HardlinkInfo {
@@ -52,6 +62,7 @@ impl std::fmt::Debug for HardlinkInfo {
             .field("inode_number", &self.inode_number)
             .field("link_count", &self.link_count.load(Ordering::Relaxed))
             .field("dst_path", &self.dst_path)
+            .field("copied", &self.copied.load(Ordering::Relaxed))
             .finish_non_exhaustive() // Omitting copy_complete condvar
     }
 }
@@ -63,6 +74,8 @@ pub struct FilesystemTracker {
     pub hardlinks: DashMap<InodeInfo, HardlinkInfo>,
     /// Source filesystem device ID (set during scan to avoid cross-filesystem hardlinks)
     source_filesystem: std::sync::RwLock<Option<u64>>,
+    /// Compact store for completed inodes (`None` = keep everything in `hardlinks`)
+    completed: Option<std::sync::Mutex<InodePathStore>>,
 }
 
 /// Statistics about hardlink tracking
@@ -74,6 +87,10 @@ pub struct FilesystemStats {
     pub hardlink_groups: usize,
     /// Total number of hardlink references
     pub total_hardlinks: u64,
+    /// Completed inodes the compact store spilled to disk
+    pub spilled_files: usize,
+    /// Approximate heap memory held by the compact store
+    pub store_memory_bytes: usize,
 }
 
 #[allow(dead_code)]
@@ -84,6 +101,22 @@ impl FilesystemTracker {
         Self {
             hardlinks: DashMap::new(),
             source_filesystem: std::sync::RwLock::new(None),
+            completed: None,
+        }
+    }
+
+    /// Create a tracker that moves completed inodes into a compact store
+    ///
+    /// Only inodes whose copy is still in flight stay in `hardlinks`; once
+    /// `signal_copy_complete()` runs, the inode's destination path and link
+    /// count move to an `InodePathStore`, which may spill to disk according
+    /// to `config`.
+    #[must_use]
+    pub fn with_compact_store(config: InodeStoreConfig) -> Self {
+        Self {
+            hardlinks: DashMap::new(),
+            source_filesystem: std::sync::RwLock::new(None),
+            completed: Some(std::sync::Mutex::new(InodePathStore::new(config))),
         }
     }
 
@@ -93,6 +126,7 @@ impl FilesystemTracker {
         Self {
             hardlinks: DashMap::new(),
             source_filesystem: std::sync::RwLock::new(Some(dev)),
+            completed: None,
         }
     }
 
@@ -161,6 +195,9 @@ impl FilesystemTracker {
     /// # Arguments
     /// * `src_path` - Source file path
     /// * `dst_path` - Destination path (stored in `HardlinkInfo` for linkers to use)
+    ///
+    /// Paths are kept in interned form, so passing the traversal's
+    /// `InternedPath`s shares their directory prefixes instead of copying them.
     /// * `dev` - Device ID  
     /// * `ino` - Inode number
     /// * `link_count` - Number of hardlinks (from stat)
@@ -171,8 +208,8 @@ impl FilesystemTracker {
     #[allow(clippy::too_many_arguments)]
     pub async fn register_file(
         &self,
-        src_path: &Path,
-        dst_path: &Path,
+        src_path: impl Into<InternedPath>,
+        dst_path: impl Into<InternedPath>,
         dev: u64,
         ino: u64,
         link_count: u64,
@@ -181,6 +218,7 @@ impl FilesystemTracker {
         if link_count == 1 {
             return false; // Not a hardlink, caller should copy normally
         }
+        let src_path = src_path.into();
 
         let inode_info = InodeInfo { dev, ino };
 
@@ -221,14 +259,22 @@ impl FilesystemTracker {
                 false // We're a linker, waiting is done
             }
             dashmap::mapref::entry::Entry::Vacant(entry) => {
+                // Already copied and moved to the compact store: we're a linker,
+                // and the copy is complete so there is nothing to wait for.
+                // (Checked while holding the vacant entry so this can't race with
+                // the copier moving the inode out of the map.)
+                if self.completed_add_link(inode_info) {
+                    debug!(
+                        "Found hardlink for completed inode ({}, {}): {} (linker)",
+                        dev,
+                        ino,
+                        src_path.display()
+                    );
+                    return false;
+                }
+
                 // We're the first - we're the copier
-                entry.insert(HardlinkInfo {
-                    original_path: src_path.to_path_buf(),
-                    inode_number: ino,
-                    link_count: AtomicU64::new(1),
-                    dst_path: dst_path.to_path_buf(),
-                    copy_complete: Arc::new(compio_sync::Condvar::new()),
-                });
+                let dst_path = dst_path.into();
                 debug!(
                     "Registered new hardlink inode ({}, {}): {} → {} (copier)",
                     dev,
@@ -236,6 +282,14 @@ impl FilesystemTracker {
                     src_path.display(),
                     dst_path.display()
                 );
+                entry.insert(HardlinkInfo {
+                    original_path: src_path,
+                    inode_number: ino,
+                    link_count: AtomicU64::new(1),
+                    dst_path,
+                    copy_complete: Arc::new(compio_sync::Condvar::new()),
+                    copied: AtomicBool::new(false),
+                });
                 true // We're the copier
             }
         }
@@ -275,9 +329,41 @@ impl FilesystemTracker {
             .iter()
             .find(|e| e.value().inode_number == ino);
         if let Some(entry) = found {
+            let key = *entry.key();
             let condvar = Arc::clone(&entry.value().copy_complete);
+            entry.value().copied.store(true, Ordering::Release);
+            let moved = self.completed.as_ref().map(|_| {
+                let info = entry.value();
+                (
+                    info.dst_path.to_path_buf(),
+                    info.link_count.load(Ordering::Relaxed),
+                )
+            });
             drop(entry); // Release DashMap ref before signaling
 
+            // Move the completed inode into the compact store BEFORE removing it
+            // from the map, so a concurrent registration always finds it in one
+            // of the two places
+            if let (Some(store), Some((dst_path, link_count))) = (&self.completed, moved) {
+                let inserted = store.lock().is_ok_and(|mut store| {
+                    let links = u32::try_from(link_count).unwrap_or(u32::MAX);
+                    match store.insert(key, &dst_path, links) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Failed to move inode {} to compact hardlink store: {}",
+                                ino,
+                                e
+                            );
+                            false
+                        }
+                    }
+                });
+                if inserted {
+                    self.hardlinks.remove(&key);
+                }
+            }
+
             condvar.notify_all();
             debug!("Signaled copy complete for inode {}", ino);
         }
@@ -292,15 +378,79 @@ impl FilesystemTracker {
         self.hardlinks
             .iter()
             .find(|entry| entry.value().inode_number == ino)
-            .map(|entry| entry.value().dst_path.clone())
+            .map(|entry| entry.value().dst_path.to_path_buf())
+    }
+
+    /// Get the destination path for an inode on a specific device
+    ///
+    /// Like `get_dst_path_for_inode()`, but keyed by `(dev, ino)` so it is a
+    /// direct lookup and also finds inodes that were moved to the compact store.
+    #[must_use]
+    pub fn get_dst_path(&self, dev: u64, ino: u64) -> Option<PathBuf> {
+        let key = InodeInfo { dev, ino };
+        if let Some(entry) = self.hardlinks.get(&key) {
+            return Some(entry.value().dst_path.to_path_buf());
+        }
+        let store = self.completed.as_ref()?.lock().ok()?;
+        store.get_dst_path(key).ok().flatten()
+    }
+
+    /// Destination of an inode whose copy has completed
+    ///
+    /// Unlike `register_file()` this neither registers nor waits: it answers
+    /// `None` for an unknown inode or one whose copy is still in flight. For
+    /// entries linked without a `statx` of their own (hardlink farms); count
+    /// the link with `count_link()` once it exists.
+    #[must_use]
+    pub fn copied_dst_path(&self, dev: u64, ino: u64) -> Option<PathBuf> {
+        let key = InodeInfo { dev, ino };
+        if let Some(entry) = self.hardlinks.get(&key) {
+            let info = entry.value();
+            return info
+                .copied
+                .load(Ordering::Acquire)
+                .then(|| info.dst_path.to_path_buf());
+        }
+        // Completed inodes move to the store before leaving the map
+        let store = self.completed.as_ref()?.lock().ok()?;
+        store.get_dst_path(key).ok().flatten()
+    }
+
+    /// Count a link created to a copied inode (see `copied_dst_path()`)
+    pub fn count_link(&self, dev: u64, ino: u64) {
+        let key = InodeInfo { dev, ino };
+        if let Some(entry) = self.hardlinks.get(&key) {
+            entry.value().link_count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let _ = self.completed_add_link(key);
+    }
+
+    /// Count another link for an inode in the compact store, if present there
+    fn completed_add_link(&self, key: InodeInfo) -> bool {
+        self.completed
+            .as_ref()
+            .and_then(|store| store.lock().ok())
+            .is_some_and(|mut store| store.add_link(key).unwrap_or(false))
     }
 
     /// Get statistics about the filesystem tracking
     #[must_use]
     pub fn get_stats(&self) -> FilesystemStats {
-        let total_files = self.hardlinks.len();
+        let mut total_files = self.hardlinks.len();
         let mut hardlink_groups = 0;
         let mut total_hardlinks = 0;
+        let (mut spilled_files, mut store_memory_bytes) = (0, 0);
+
+        if let Some(store) = self.completed.as_ref().and_then(|s| s.lock().ok()) {
+            if !store.is_empty() {
+                total_files += store.len();
+                hardlink_groups += store.hardlink_groups();
+                total_hardlinks += store.total_links();
+                spilled_files = store.spilled_len();
+                store_memory_bytes = store.memory_bytes();
+            }
+        }
 
         for entry in &self.hardlinks {
             let count = entry.value().link_count.load(Ordering::Relaxed);
@@ -314,13 +464,21 @@ impl FilesystemTracker {
             total_files,
             hardlink_groups,
             total_hardlinks,
+            spilled_files,
+            store_memory_bytes,
         }
     }
 
     /// Check if an inode has been copied
     #[must_use]
     pub fn is_inode_copied(&self, dev: u64, ino: u64) -> bool {
-        self.hardlinks.get(&InodeInfo { dev, ino }).is_some()
+        let key = InodeInfo { dev, ino };
+        self.hardlinks.get(&key).is_some()
+            || self
+                .completed
+                .as_ref()
+                .and_then(|store| store.lock().ok())
+                .is_some_and(|store| store.contains(key).unwrap_or(false))
     }
 
     /// Get the original path for an inode if it's been registered
@@ -328,7 +486,7 @@ impl FilesystemTracker {
     pub fn get_original_path_for_inode(&self, dev: u64, ino: u64) -> Option<PathBuf> {
         self.hardlinks
             .get(&InodeInfo { dev, ino })
-            .map(|entry| entry.original_path.clone())
+            .map(|entry| entry.original_path.to_path_buf())
     }
 }
 
@@ -478,4 +636,60 @@ mod tests {
             "dst_path should be set at registration, not after copy"
         );
     }
+
+    /// Completed inodes move to the compact store; later links resolve from it
+    #[compio::test]
+    async fn test_compact_store_completed_inodes() {
+        let tracker = FilesystemTracker::with_compact_store(InodeStoreConfig::default());
+        let src = Path::new("/src/a");
+        let dst = Path::new("/dst/a");
+
+        assert!(tracker.register_file(src, dst, 7, 100, 3).await);
+        tracker.signal_copy_complete(100);
+
+        // In-flight map no longer holds the inode, but it is still known
+        assert!(tracker.hardlinks.is_empty());
+        assert!(tracker.is_inode_copied(7, 100));
+        assert_eq!(tracker.get_dst_path(7, 100), Some(dst.to_path_buf()));
+
+        // A later link is a linker and does not wait (copy already complete)
+        let is_copier = tracker
+            .register_file(Path::new("/src/b"), Path::new("/dst/b"), 7, 100, 3)
+            .await;
+        assert!(!is_copier);
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats.total_files, 1);
+        assert_eq!(stats.hardlink_groups, 1);
+        assert_eq!(stats.total_hardlinks, 2);
+    }
+
+    /// Hardlink farms resolve copied inodes without registering or waiting
+    #[compio::test]
+    async fn test_copied_dst_path_only_after_copy() {
+        // Requirement: copied_dst_path() answers only for inodes whose copy
+        // completed, from the in-flight map or the compact store, and
+        // count_link() counts links made from it
+        for tracker in [
+            FilesystemTracker::new(),
+            FilesystemTracker::with_compact_store(InodeStoreConfig::default()),
+        ] {
+            let dst = Path::new("/dst/a");
+            assert_eq!(tracker.copied_dst_path(7, 100), None);
+
+            assert!(
+                tracker
+                    .register_file(Path::new("/src/a"), dst, 7, 100, 3)
+                    .await
+            );
+            assert_eq!(tracker.copied_dst_path(7, 100), None);
+
+            tracker.signal_copy_complete(100);
+            assert_eq!(tracker.copied_dst_path(7, 100), Some(dst.to_path_buf()));
+            assert_eq!(tracker.copied_dst_path(8, 100), None);
+
+            tracker.count_link(7, 100);
+            assert_eq!(tracker.get_stats().total_hardlinks, 2);
+        }
+    }
 }
@@ -0,0 +1,243 @@
+//! Inode flags (`chattr`) and rewriting immutable destinations
+//!
+//! With `--preserve-flags` each copied file gets the source's inode flags
+//! that make sense on a copy ([`PRESERVED`]), read and set with
+//! `FS_IOC_GETFLAGS` / `FS_IOC_SETFLAGS` after its data and metadata are
+//! written. An immutable or append-only destination cannot be rewritten,
+//! renamed over or have its metadata changed, even by root; with
+//! `--update-immutable` those flags ([`LOCKING`]) are cleared before the copy
+//! ([`unlock_at`]), the file is updated, and the flags are set again: the
+//! source's when the copy succeeds, the destination's previous flags when it
+//! fails. Changing either flag needs `CAP_LINUX_IMMUTABLE`.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// `FS_IOC_GETFLAGS` (`_IOR('f', 1, long)`)
+#[cfg(target_os = "linux")]
+const FS_IOC_GETFLAGS: u64 = 0x8008_6601;
+/// `FS_IOC_SETFLAGS` (`_IOW('f', 2, long)`)
+#[cfg(target_os = "linux")]
+const FS_IOC_SETFLAGS: u64 = 0x4008_6602;
+
+/// Inode flag: writes are synchronous
+pub const SYNC: libc::c_long = 0x8;
+/// Inode flag: the file cannot be modified, renamed, linked or removed
+pub const IMMUTABLE: libc::c_long = 0x10;
+/// Inode flag: the file can only be appended to
+pub const APPEND: libc::c_long = 0x20;
+/// Inode flag: skipped by `dump(8)`
+pub const NODUMP: libc::c_long = 0x40;
+/// Inode flag: the access time is not updated
+pub const NOATIME: libc::c_long = 0x80;
+/// Inode flag: directory changes are synchronous
+pub const DIRSYNC: libc::c_long = 0x1_0000;
+
+/// Flags copied with `--preserve-flags`; the rest describe how the
+/// filesystem stores the file (compression, extents, encryption, ...)
+pub const PRESERVED: libc::c_long = SYNC | IMMUTABLE | APPEND | NODUMP | NOATIME | DIRSYNC;
+
+/// Flags that stop a file being rewritten
+pub const LOCKING: libc::c_long = IMMUTABLE | APPEND;
+
+/// Inode flags of the open file `fd`
+///
+/// # Errors
+///
+/// Fails with `ENOTTY` or `EOPNOTSUPP` where the filesystem has no inode
+/// flags.
+#[cfg(target_os = "linux")]
+pub fn get(fd: RawFd) -> io::Result<libc::c_long> {
+    let mut flags: libc::c_long = 0;
+    // SAFETY: FS_IOC_GETFLAGS writes the inode flags to `flags`, which
+    // outlives the call
+    if unsafe { libc::ioctl(fd, FS_IOC_GETFLAGS as _, &mut flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags)
+}
+
+/// Inode flags of the open file `fd` (none to read here)
+///
+/// # Errors
+///
+/// Always fails with `Unsupported`.
+#[cfg(not(target_os = "linux"))]
+pub fn get(_fd: RawFd) -> io::Result<libc::c_long> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Set the inode flags of the open file `fd`
+///
+/// # Errors
+///
+/// Fails with `EPERM` when changing [`LOCKING`] flags without
+/// `CAP_LINUX_IMMUTABLE`, or changing any flag of another user's file.
+#[cfg(target_os = "linux")]
+pub fn set(fd: RawFd, flags: libc::c_long) -> io::Result<()> {
+    // SAFETY: FS_IOC_SETFLAGS reads the inode flags from `flags`, which
+    // outlives the call
+    if unsafe { libc::ioctl(fd, FS_IOC_SETFLAGS as _, &flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the inode flags of the open file `fd` (none to set here)
+///
+/// # Errors
+///
+/// Always fails with `Unsupported`.
+#[cfg(not(target_os = "linux"))]
+pub fn set(_fd: RawFd, _flags: libc::c_long) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `current` flags with the [`PRESERVED`] ones taken from `wanted`
+#[must_use]
+pub const fn merge(current: libc::c_long, wanted: libc::c_long) -> libc::c_long {
+    (current & !PRESERVED) | (wanted & PRESERVED)
+}
+
+/// Open `name` in the directory `dir` to read or set its flags, without
+/// following a symlink (`O_NONBLOCK` so fifos and devices do not block)
+fn open_at(dir: RawFd, name: &OsStr) -> io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(name.as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: `name` is NUL-terminated and outlives the call
+    let fd = unsafe {
+        libc::openat(
+            dir,
+            name.as_ptr(),
+            libc::O_RDONLY | libc::O_NONBLOCK | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openat returned a new descriptor that nothing else owns
+    Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+}
+
+/// Inode flags of `name` in `dir` (not followed if a symlink)
+///
+/// # Errors
+///
+/// Fails if `name` cannot be opened or has no inode flags.
+pub fn get_at(dir: RawFd, name: &OsStr) -> io::Result<libc::c_long> {
+    use std::os::fd::AsRawFd;
+
+    get(open_at(dir, name)?.as_raw_fd())
+}
+
+/// Give `name` in `dir` the [`PRESERVED`] flags of `wanted`, keeping its
+/// other flags; nothing is set when they already match
+///
+/// # Errors
+///
+/// Fails if `name` cannot be opened or its flags cannot be read or set.
+pub fn apply_at(dir: RawFd, name: &OsStr, wanted: libc::c_long) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let file = open_at(dir, name)?;
+    let current = get(file.as_raw_fd())?;
+    let flags = merge(current, wanted);
+    if flags == current {
+        return Ok(());
+    }
+    set(file.as_raw_fd(), flags)
+}
+
+/// Clear the [`LOCKING`] flags of an existing `name` in `dir` so it can be
+/// rewritten, returning the flags it had; `None` if it does not exist, has
+/// no inode flags, or is not locked
+///
+/// # Errors
+///
+/// Fails if a locked file's flags cannot be cleared (`EPERM` without
+/// `CAP_LINUX_IMMUTABLE`).
+pub fn unlock_at(dir: RawFd, name: &OsStr) -> io::Result<Option<libc::c_long>> {
+    use std::os::fd::AsRawFd;
+
+    let file = match open_at(dir, name) {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Ok(flags) = get(file.as_raw_fd()) else {
+        return Ok(None);
+    };
+    if flags & LOCKING == 0 {
+        return Ok(None);
+    }
+    set(file.as_raw_fd(), flags & !LOCKING)?;
+    Ok(Some(flags))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_merge_keeps_filesystem_flags() {
+        // Requirement: Only the preserved flags come from the source; flags
+        // describing the destination's storage (here extents, 0x80000) stay
+        const EXTENTS: libc::c_long = 0x8_0000;
+        assert_eq!(
+            merge(EXTENTS, IMMUTABLE | NODUMP),
+            EXTENTS | IMMUTABLE | NODUMP
+        );
+        assert_eq!(merge(EXTENTS | APPEND, EXTENTS), EXTENTS);
+        assert_eq!(merge(0, 0x4000_0000 | NOATIME), NOATIME);
+    }
+
+    #[test]
+    fn test_unlock_missing_or_unlocked() {
+        // Requirement: A missing or unlocked destination needs no unlocking
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("plain"), b"data").unwrap();
+        let dir = std::fs::File::open(temp.path()).unwrap();
+
+        assert_eq!(
+            unlock_at(dir.as_raw_fd(), OsStr::new("missing")).unwrap(),
+            None
+        );
+        assert_eq!(
+            unlock_at(dir.as_raw_fd(), OsStr::new("plain")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_clear_update_restore() {
+        // Requirement: An immutable destination is unlocked, rewritten and
+        // locked again (needs CAP_LINUX_IMMUTABLE and a filesystem with
+        // inode flags, so skipped where either is missing)
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("locked");
+        std::fs::write(&path, b"old").unwrap();
+        let dir = std::fs::File::open(temp.path()).unwrap();
+        let name = OsStr::new("locked");
+        if apply_at(dir.as_raw_fd(), name, IMMUTABLE).is_err() {
+            return;
+        }
+        assert!(std::fs::write(&path, b"new").is_err());
+
+        let previous = unlock_at(dir.as_raw_fd(), name).unwrap().unwrap();
+        assert_ne!(previous & IMMUTABLE, 0);
+        std::fs::write(&path, b"new").unwrap();
+        apply_at(dir.as_raw_fd(), name, previous).unwrap();
+        assert_ne!(get_at(dir.as_raw_fd(), name).unwrap() & IMMUTABLE, 0);
+
+        apply_at(dir.as_raw_fd(), name, 0).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+    }
+}
@@ -0,0 +1,350 @@
+//! Interactive confirmation of overwrites and deletions (`--interactive`)
+//!
+//! For careful manual syncs, arsync can ask before it replaces a destination
+//! file that differs from the source (different type, size or modification
+//! time) and before it deletes a destination entry. Each question shows a
+//! short summary of both sides and accepts:
+//!
+//! - `y` / `n`: yes or no for this entry
+//! - `a` / `s`: yes to all / no to all (skip) remaining questions of this kind
+//! - `d`: show a `diff -u` of destination and source, then ask again
+//!
+//! Prompting is only enabled when stdin is a terminal. Copies run
+//! concurrently, so questions are serialized behind one lock; while a question
+//! is waiting for an answer, other copies that need one wait too.
+
+use compio_fs_extended::FileMetadata;
+use std::fmt::Write as _;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Prompter of this run, installed by `sync_files` with `--interactive`
+static PROMPTER: OnceLock<Prompter> = OnceLock::new();
+
+/// Answer to a confirmation question
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    /// Yes for this entry
+    Yes,
+    /// No for this entry
+    No,
+    /// Yes for this and every later entry
+    YesToAll,
+    /// No for this and every later entry
+    NoToAll,
+    /// Show the differences, then ask again
+    Diff,
+}
+
+impl Answer {
+    /// Parse a line typed by the user
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "y" | "Y" | "yes" => Some(Self::Yes),
+            "n" | "N" | "no" => Some(Self::No),
+            "a" | "A" | "all" => Some(Self::YesToAll),
+            "s" | "S" | "skip" => Some(Self::NoToAll),
+            "d" | "D" | "diff" => Some(Self::Diff),
+            _ => None,
+        }
+    }
+}
+
+/// Terminal and sticky answers of a prompter
+struct Session {
+    /// Where answers are read from
+    input: Box<dyn BufRead + Send>,
+    /// Where questions are written to
+    output: Box<dyn Write + Send>,
+    /// "Yes/no to all" given for overwrites
+    overwrite_all: Option<bool>,
+    /// "Yes/no to all" given for deletions
+    delete_all: Option<bool>,
+}
+
+/// Asks the user before overwriting or deleting destination entries
+pub struct Prompter {
+    /// Serializes questions from concurrent copies
+    session: Mutex<Session>,
+}
+
+impl Prompter {
+    /// Prompter reading answers from `input` and writing questions to `output`
+    #[must_use]
+    pub fn new(input: Box<dyn BufRead + Send>, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            session: Mutex::new(Session {
+                input,
+                output,
+                overwrite_all: None,
+                delete_all: None,
+            }),
+        }
+    }
+
+    /// Whether to replace `dst` by a copy of `src`
+    ///
+    /// Returns `true` without asking if the destination does not differ from
+    /// the source in type, size or modification time.
+    #[must_use]
+    pub fn confirm_overwrite(
+        &self,
+        src: &Path,
+        src_meta: &FileMetadata,
+        dst: &Path,
+        dst_meta: &FileMetadata,
+    ) -> bool {
+        if !differs(src_meta, dst_meta) {
+            return true;
+        }
+        let mut session = self.lock();
+        if let Some(all) = session.overwrite_all {
+            return all;
+        }
+        let question = format!(
+            "Overwrite {}?\n  source:      {}\n  destination: {}\n  {}\n",
+            dst.display(),
+            describe(src_meta),
+            describe(dst_meta),
+            compare_times(src_meta.modified, dst_meta.modified)
+        );
+        loop {
+            match session.ask(&question, "[y]es, [n]o, yes to [a]ll, [s]kip all, [d]iff") {
+                Answer::Yes => return true,
+                Answer::No => return false,
+                Answer::YesToAll => {
+                    session.overwrite_all = Some(true);
+                    return true;
+                }
+                Answer::NoToAll => {
+                    session.overwrite_all = Some(false);
+                    return false;
+                }
+                Answer::Diff => session.show_diff(dst, src),
+            }
+        }
+    }
+
+    /// Whether to delete the destination entry `path`
+    #[must_use]
+    pub fn confirm_delete(&self, path: &Path) -> bool {
+        let mut session = self.lock();
+        if let Some(all) = session.delete_all {
+            return all;
+        }
+        let question = format!("Delete {}?\n", path.display());
+        loop {
+            match session.ask(&question, "[y]es, [n]o, yes to [a]ll, [s]kip all") {
+                Answer::Yes => return true,
+                Answer::No => return false,
+                Answer::YesToAll => {
+                    session.delete_all = Some(true);
+                    return true;
+                }
+                Answer::NoToAll => {
+                    session.delete_all = Some(false);
+                    return false;
+                }
+                // Nothing to compare a deletion against
+                Answer::Diff => {}
+            }
+        }
+    }
+
+    /// Lock the session, recovering from a poisoned lock
+    fn lock(&self) -> std::sync::MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Session {
+    /// Ask until a valid answer is given; end of input counts as "no to all"
+    fn ask(&mut self, question: &str, choices: &str) -> Answer {
+        let _ = write!(self.output, "{question}{choices}? ");
+        let _ = self.output.flush();
+        loop {
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    let _ = writeln!(self.output);
+                    return Answer::NoToAll;
+                }
+                Ok(_) => {}
+            }
+            if let Some(answer) = Answer::parse(&line) {
+                return answer;
+            }
+            let _ = write!(self.output, "Please answer {choices}? ");
+            let _ = self.output.flush();
+        }
+    }
+
+    /// Print `diff -u old new` to the output
+    fn show_diff(&mut self, old: &Path, new: &Path) {
+        match std::process::Command::new("diff")
+            .arg("-u")
+            .arg("--")
+            .arg(old)
+            .arg(new)
+            .output()
+        {
+            Ok(diff) => {
+                let _ = self.output.write_all(&diff.stdout);
+                let _ = self.output.write_all(&diff.stderr);
+            }
+            Err(e) => {
+                let _ = writeln!(self.output, "Cannot run diff: {e}");
+            }
+        }
+    }
+}
+
+/// Enable prompting for this run if stdin is a terminal
+pub fn install() {
+    if !std::io::stdin().is_terminal() {
+        tracing::warn!("--interactive ignored: stdin is not a terminal");
+        return;
+    }
+    // A prompter installed by an earlier sync in this process is kept, along
+    // with its "to all" answers
+    let _ = PROMPTER.set(Prompter::new(
+        Box::new(std::io::BufReader::new(std::io::stdin())),
+        Box::new(std::io::stderr()),
+    ));
+}
+
+/// Prompter of this run, if `--interactive` is active
+#[must_use]
+pub fn prompter() -> Option<&'static Prompter> {
+    PROMPTER.get()
+}
+
+/// Whether two entries differ enough to ask before replacing one by the other
+fn differs(src: &FileMetadata, dst: &FileMetadata) -> bool {
+    src.mode & libc::S_IFMT != dst.mode & libc::S_IFMT
+        || src.size != dst.size
+        || src.modified != dst.modified
+}
+
+/// One-line summary of an entry: type, size and permissions
+fn describe(meta: &FileMetadata) -> String {
+    let kind = match meta.mode & libc::S_IFMT {
+        libc::S_IFREG => "file",
+        libc::S_IFDIR => "directory",
+        libc::S_IFLNK => "symlink",
+        _ => "special file",
+    };
+    format!(
+        "{kind}, {} bytes, mode {:04o}",
+        meta.size,
+        meta.mode & 0o7777
+    )
+}
+
+/// Which side was modified more recently, and by how much
+fn compare_times(src: SystemTime, dst: SystemTime) -> String {
+    match src.duration_since(dst) {
+        Ok(newer) if newer.is_zero() => "same modification time".to_string(),
+        Ok(newer) => format!("source is newer by {}", format_duration(newer)),
+        Err(older) => format!("source is older by {}", format_duration(older.duration())),
+    }
+}
+
+/// Coarse human-readable duration ("2d 3h", "5m 10s", "0.25s")
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let mut text = String::new();
+    let _ = match secs {
+        0 => write!(text, "{:.2}s", duration.as_secs_f64()),
+        1..=59 => write!(text, "{secs}s"),
+        60..=3599 => write!(text, "{}m {}s", secs / 60, secs % 60),
+        3600..=86_399 => write!(text, "{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => write!(text, "{}d {}h", secs / 86_400, secs % 86_400 / 3600),
+    };
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    /// Output buffer shared with the test
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn meta(size: u64, modified_secs: u64) -> FileMetadata {
+        FileMetadata {
+            size,
+            mode: libc::S_IFREG | 0o644,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            ino: 1,
+            dev: 1,
+            rdev: 0,
+            blocks: 0,
+            accessed: SystemTime::UNIX_EPOCH,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(modified_secs),
+            created: None,
+            #[cfg(target_os = "linux")]
+            attributes: None,
+            #[cfg(target_os = "linux")]
+            attributes_mask: None,
+            #[cfg(target_os = "macos")]
+            flags: None,
+            #[cfg(target_os = "macos")]
+            generation: None,
+        }
+    }
+
+    #[test]
+    fn test_overwrite_answers() {
+        // Test: Invalid input is asked again; "skip all" sticks for overwrites only
+        let output = Captured::default();
+        let prompter = Prompter::new(
+            Box::new(Cursor::new("maybe\ny\ns\ny\n")),
+            Box::new(output.clone()),
+        );
+        let (src, dst) = (Path::new("/src/a"), Path::new("/dst/a"));
+
+        assert!(prompter.confirm_overwrite(src, &meta(10, 7200), dst, &meta(5, 0)));
+        assert!(!prompter.confirm_overwrite(src, &meta(10, 1), dst, &meta(5, 0)));
+        assert!(!prompter.confirm_overwrite(src, &meta(10, 1), dst, &meta(5, 0)));
+        // Identical metadata is overwritten without asking
+        assert!(prompter.confirm_overwrite(src, &meta(5, 0), dst, &meta(5, 0)));
+        assert!(prompter.confirm_delete(Path::new("/dst/old")));
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            text.contains("source:      file, 10 bytes, mode 0644"),
+            "{text}"
+        );
+        assert!(text.contains("source is newer by 2h 0m"), "{text}");
+        assert!(text.contains("Please answer"), "{text}");
+        assert_eq!(text.matches("Overwrite /dst/a?").count(), 2, "{text}");
+    }
+
+    #[test]
+    fn test_end_of_input_declines() {
+        let prompter = Prompter::new(Box::new(Cursor::new("")), Box::new(std::io::sink()));
+        assert!(!prompter.confirm_delete(Path::new("/dst/old")));
+        assert!(!prompter.confirm_delete(Path::new("/dst/other")));
+    }
+}
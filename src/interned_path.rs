@@ -0,0 +1,230 @@
+//! Compact path storage using interned parent prefixes
+//!
+//! A large tree walk holds a path for every entry that has been discovered but
+//! not processed yet (and the hardlink tracker holds one per hardlinked inode).
+//! Stored as `PathBuf`s, every one of them repeats its full directory prefix,
+//! which for deep trees with millions of entries is most of the memory used.
+//!
+//! `InternedPath` stores only the entry's own name plus a reference to its
+//! parent's `PathPrefix`. A prefix is created once per directory and shared by
+//! all of its children (and, transitively, everything below them), so each
+//! entry costs one pointer and its name. Prefixes are reference counted and
+//! freed once the last path below them is dropped.
+//!
+//! The full path is built on demand with `to_path_buf()`: callers resolve it
+//! while actively working on an entry (bounded by the concurrency limit) and
+//! keep the compact form while it waits in a queue.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Interned directory prefix shared by all paths below it
+#[derive(Clone)]
+pub struct PathPrefix(Arc<PrefixNode>);
+
+/// One directory of an interned prefix chain
+struct PrefixNode {
+    /// Prefix of the parent directory (`None` for a root)
+    parent: Option<PathPrefix>,
+    /// Directory name (a root stores its whole path here)
+    name: Box<OsStr>,
+    /// Byte length of the resolved prefix (capacity hint for resolution)
+    len: usize,
+}
+
+impl PathPrefix {
+    /// Path of the entry `name` inside this directory
+    #[must_use]
+    pub fn join(&self, name: &OsStr) -> InternedPath {
+        InternedPath {
+            parent: Some(self.clone()),
+            name: name.into(),
+        }
+    }
+
+    /// Resolve the full directory path
+    #[must_use]
+    pub fn to_path_buf(&self) -> PathBuf {
+        resolve(Some(self), None, 0)
+    }
+}
+
+impl fmt::Debug for PathPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_path_buf(), f)
+    }
+}
+
+/// Path stored as (interned parent prefix, own name)
+#[derive(Clone)]
+pub struct InternedPath {
+    /// Prefix of the containing directory (`None` for a root path)
+    parent: Option<PathPrefix>,
+    /// Entry name (a root stores its whole path here)
+    name: Box<OsStr>,
+}
+
+impl InternedPath {
+    /// Intern a root path; it is stored whole, with no parent prefix
+    #[must_use]
+    pub fn new(path: &Path) -> Self {
+        Self {
+            parent: None,
+            name: path.as_os_str().into(),
+        }
+    }
+
+    /// Final component of the path (`None` for roots such as `/` or `..`)
+    #[must_use]
+    pub fn file_name(&self) -> Option<&OsStr> {
+        if self.parent.is_some() {
+            Some(&*self.name)
+        } else {
+            Path::new(&self.name).file_name()
+        }
+    }
+
+    /// Intern this path as a directory, for building its children's paths
+    #[must_use]
+    pub fn to_prefix(&self) -> PathPrefix {
+        let parent_len = self.parent.as_ref().map_or(0, |parent| parent.0.len + 1);
+        PathPrefix(Arc::new(PrefixNode {
+            parent: self.parent.clone(),
+            name: self.name.clone(),
+            len: parent_len + self.name.len(),
+        }))
+    }
+
+    /// Resolve the full path
+    #[must_use]
+    pub fn to_path_buf(&self) -> PathBuf {
+        resolve(self.parent.as_ref(), Some(&*self.name), self.name.len())
+    }
+
+    /// Object implementing `Display`, like `Path::display`
+    #[must_use]
+    pub const fn display(&self) -> Display<'_> {
+        Display(self)
+    }
+}
+
+impl From<&Path> for InternedPath {
+    fn from(path: &Path) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&PathBuf> for InternedPath {
+    fn from(path: &PathBuf) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<PathBuf> for InternedPath {
+    fn from(path: PathBuf) -> Self {
+        Self::new(&path)
+    }
+}
+
+impl From<&InternedPath> for InternedPath {
+    fn from(path: &InternedPath) -> Self {
+        path.clone()
+    }
+}
+
+impl PartialEq for InternedPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_path_buf() == other.to_path_buf()
+    }
+}
+
+impl Eq for InternedPath {}
+
+impl fmt::Debug for InternedPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_path_buf(), f)
+    }
+}
+
+/// Helper for printing an `InternedPath` with `format!` and `{}`
+pub struct Display<'a>(&'a InternedPath);
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.to_path_buf().display(), f)
+    }
+}
+
+/// Join a prefix chain and an optional final name into a `PathBuf`
+///
+/// Allocates only the result, sized up front from the prefix length.
+fn resolve(prefix: Option<&PathPrefix>, name: Option<&OsStr>, name_len: usize) -> PathBuf {
+    let capacity = prefix.map_or(0, |p| p.0.len + 1) + name_len;
+    let mut path = PathBuf::with_capacity(capacity);
+    if let Some(prefix) = prefix {
+        push_prefix(&mut path, prefix);
+    }
+    if let Some(name) = name {
+        path.push(name);
+    }
+    path
+}
+
+/// Append the directories of a prefix chain to `path`, outermost first
+///
+/// Recursion depth is the directory depth, which `PATH_MAX` bounds.
+fn push_prefix(path: &mut PathBuf, prefix: &PathPrefix) {
+    if let Some(parent) = &prefix.0.parent {
+        push_prefix(path, parent);
+    }
+    path.push(&prefix.0.name);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_resolves_nested_paths() {
+        // Test: Paths built from shared prefixes resolve to the full path
+        let root = InternedPath::new(Path::new("/data/src"));
+        let dir = root.to_prefix().join(OsStr::new("photos"));
+        let file = dir.to_prefix().join(OsStr::new("img.jpg"));
+
+        assert_eq!(root.to_path_buf(), Path::new("/data/src"));
+        assert_eq!(file.to_path_buf(), Path::new("/data/src/photos/img.jpg"));
+        assert_eq!(dir.to_prefix().to_path_buf(), Path::new("/data/src/photos"));
+        assert_eq!(file.display().to_string(), "/data/src/photos/img.jpg");
+    }
+
+    #[test]
+    fn test_file_name_for_roots_and_children() {
+        assert_eq!(
+            InternedPath::new(Path::new("/a/b")).file_name(),
+            Some(OsStr::new("b"))
+        );
+        assert_eq!(InternedPath::new(Path::new("/")).file_name(), None);
+        let child = InternedPath::new(Path::new("/"))
+            .to_prefix()
+            .join(OsStr::new("etc"));
+        assert_eq!(child.file_name(), Some(OsStr::new("etc")));
+        assert_eq!(child.to_path_buf(), Path::new("/etc"));
+    }
+
+    #[test]
+    fn test_siblings_share_one_prefix() {
+        // Requirement: The parent prefix is stored once, not per entry
+        let prefix = InternedPath::new(Path::new("/very/long/prefix")).to_prefix();
+        let children: Vec<InternedPath> = (0..100)
+            .map(|i| prefix.join(OsStr::new(&format!("f{i}"))))
+            .collect();
+
+        assert_eq!(Arc::strong_count(&prefix.0), 101);
+        drop(children);
+        assert_eq!(Arc::strong_count(&prefix.0), 1);
+    }
+}
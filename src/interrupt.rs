@@ -0,0 +1,106 @@
+//! Graceful stop of a directory copy on Ctrl-C (SIGINT) or SIGTERM
+//!
+//! Killed outright, a copy leaves files half-written at their final names
+//! and directories without the source's permissions and times. Once
+//! [`install`] has run, the first SIGINT or SIGTERM cancels the run's
+//! [`token`] instead, which the traversal carries in every entry's context:
+//!
+//! - no further entry is started, so nothing new is submitted to `io_uring`;
+//! - files already being copied are finished, their operations completing
+//!   normally, so none is left half-written; directories whose entries were
+//!   copied still get their metadata;
+//! - the run then fails: files staged for `--delay-updates` are removed
+//!   (the destination keeps its previous versions), `--delete-after` deletes
+//!   nothing, and `--state-journal` records a final checkpoint to
+//!   `--resume` from;
+//! - the error summarizes what was copied and how many entries were not
+//!   started, and the process exits with status 20, like rsync.
+//!
+//! A second signal kills the process as usual, for when the files in flight
+//! are too large to wait for.
+
+use crate::copy_task::CancellationHandle;
+use crate::error::SyncError;
+use crate::stats::SharedStats;
+use std::sync::OnceLock;
+
+/// Exit status of a run stopped by a signal, as in rsync
+pub const INTERRUPTED_EXIT_CODE: i32 = 20;
+
+/// Cancelled by the first SIGINT or SIGTERM after `install`
+static TOKEN: OnceLock<CancellationHandle> = OnceLock::new();
+
+/// The process's cancellation token, cancelled by SIGINT and SIGTERM once
+/// [`install`] has run
+#[must_use]
+pub fn token() -> CancellationHandle {
+    TOKEN.get_or_init(CancellationHandle::new).clone()
+}
+
+/// Make the first SIGINT or SIGTERM cancel [`token`] instead of killing
+/// the process
+///
+/// # Errors
+///
+/// Returns an error if a handler cannot be installed.
+pub fn install() -> std::io::Result<()> {
+    extern "C" fn on_signal(signal: libc::c_int) {
+        // Only async-signal-safe work here: an initialized OnceLock is read
+        // with an atomic load and the token is an atomic store
+        if let Some(token) = TOKEN.get() {
+            token.cancel();
+        }
+        // SAFETY: restoring the default disposition is async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+
+    // The handler must find the token without allocating
+    let _ = token();
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: `on_signal` only performs async-signal-safe operations
+        let previous = unsafe { libc::signal(signal, on_signal as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The error ending a run stopped by `token`, with what it got done
+#[must_use]
+pub fn interrupted(stats: &SharedStats) -> SyncError {
+    let done = stats.snapshot();
+    SyncError::Interrupted(format!(
+        "{} files ({} bytes) copied, {} unchanged and {} directories created before stopping; \
+         {} entries were not started (nor anything below them), run again to complete the copy",
+        done.files_copied,
+        done.bytes_copied,
+        done.files_unchanged,
+        done.directories_created,
+        done.not_started
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory::DirectoryStats;
+
+    #[test]
+    fn test_interrupted_summary() {
+        // Requirement: The error says what was synced and what was not
+        let stats = SharedStats::new(&DirectoryStats {
+            files_copied: 12,
+            bytes_copied: 4096,
+            directories_created: 3,
+            not_started: 7,
+            ..DirectoryStats::default()
+        });
+        let message = interrupted(&stats).to_string();
+        assert!(message.starts_with("Interrupted: 12 files (4096 bytes) copied"));
+        assert!(message.contains("3 directories created"));
+        assert!(message.contains("7 entries were not started"));
+    }
+}
@@ -42,6 +42,7 @@ use tracing::debug;
 /// # Fields
 ///
 /// * `buffer_size` - Size of buffers used for I/O operations in bytes
+/// * `run` - State of the run the copies belong to (see `run_state`)
 ///
 /// # Performance Considerations
 ///
@@ -53,6 +54,8 @@ pub struct FileOperations {
     /// Buffer size for I/O operations in bytes
     #[allow(dead_code)]
     buffer_size: usize,
+    /// State of the run the copies belong to
+    run: std::sync::Arc<crate::run_state::RunState>,
 }
 
 impl FileOperations {
@@ -87,10 +90,22 @@ impl FileOperations {
     /// - Buffer size is invalid (must be > 0)
     /// - Memory allocation fails
     #[allow(clippy::unnecessary_wraps)]
-    pub const fn new(_queue_depth: usize, buffer_size: usize) -> Result<Self> {
+    pub fn new(_queue_depth: usize, buffer_size: usize) -> Result<Self> {
         // For Phase 1.2, we'll use async I/O as a foundation
         // TODO: Implement actual io_uring integration in future phases
-        Ok(Self { buffer_size })
+        Ok(Self {
+            buffer_size,
+            run: std::sync::Arc::default(),
+        })
+    }
+
+    /// The same operations copying for the run with state `run`
+    ///
+    /// Without it, copies use a default state (generic tuning profile).
+    #[must_use]
+    pub fn with_run(mut self, run: std::sync::Arc<crate::run_state::RunState>) -> Self {
+        self.run = run;
+        self
     }
 
     /// Copy file using chunked read/write with compio buffer management
@@ -429,8 +444,19 @@ impl FileOperations {
             times: false,
             group: false,
             owner: false,
+            no_owner: false,
+            no_group: false,
             devices: false,
+            specials: false,
+            devices_and_specials: false,
             fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: crate::metadata::SpecialFilePolicy::Skip,
+            unprivileged_ownership: crate::metadata::UnprivilegedOwnership::Permitted,
             xattrs: false,
             acls: false,
             hard_links: false,
@@ -438,6 +464,14 @@ impl FileOperations {
             crtimes: false,
             preserve_xattr: false,
             preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: std::sync::Arc::clone(&self.run),
         };
 
         // Call public API - it handles DirectoryFd and Dispatcher setup internally (no leak!)
@@ -0,0 +1,355 @@
+//! Itemized list of the changes a sync makes (`--dry-run`)
+//!
+//! A dry run goes through the whole traversal (filters, update checks,
+//! hardlink detection, `--delete`) but reports each change instead of making
+//! it, one line per entry in the style of `rsync -n -i`:
+//!
+//! ```text
+//! cd+++++++++ photos/
+//! >f+++++++++ photos/new.jpg
+//! >f.st...... notes.txt
+//! cL+++++++++ latest -> photos/new.jpg
+//! hf+++++++++ photos/copy.jpg => photos/new.jpg
+//! *deleting   old.txt
+//! ```
+//!
+//! The first character is the kind of change (`>` file written, `c` entry
+//! created, `h` hardlink, `*` message), the second the entry type (`f` file,
+//! `d` directory, `L` symlink, `D` device, `S` fifo or socket). For an existing file, the rest flags what
+//! differs: `c` contents (`--checksum`), `s` size, `t` modification time, and
+//! with `--crtimes` `n` creation time. New entries show `+` throughout. Paths
+//! are relative to the destination root. With `--json` each change is an
+//! `itemize` event instead (see [`Itemizer::with_json`]).
+
+use compio_fs_extended::FileMetadata;
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Attribute columns of a new entry
+const NEW: &str = "+++++++++";
+
+/// What a line says after the path
+enum Detail<'a> {
+    /// Nothing
+    None,
+    /// Target of a symlink (`-> TARGET`)
+    Target(&'a Path),
+    /// File a hardlink shares its inode with (`=> ORIGINAL`)
+    Original(&'a Path),
+    /// Kind of special file a placeholder stands for
+    Placeholder(&'a str),
+}
+
+/// Writes one line per change, relative to the destination root
+pub struct Itemizer {
+    /// Destination root
+    root: PathBuf,
+    /// Where the lines go; lines from concurrent copies are not interleaved
+    output: Mutex<Box<dyn Write + Send>>,
+    /// Flag differing creation times (`--crtimes`)
+    crtimes: bool,
+    /// Write `itemize` events instead of text lines (`--json`)
+    json: bool,
+}
+
+impl Itemizer {
+    /// Itemizer writing lines for changes under `root` to `output`
+    #[must_use]
+    pub fn new(root: &Path, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            output: Mutex::new(output),
+            crtimes: false,
+            json: false,
+        }
+    }
+
+    /// Also compare creation times, flagging files whose differ (`--crtimes`)
+    #[must_use]
+    pub const fn with_crtimes(mut self, crtimes: bool) -> Self {
+        self.crtimes = crtimes;
+        self
+    }
+
+    /// Write each change as a JSON `itemize` event (`--json`)
+    ///
+    /// The event has the change in `item` (`>f.st......`, `*deleting`) and
+    /// the relative `path`, plus `target`, `link_to` or `placeholder_for`
+    /// where the text line has them.
+    #[must_use]
+    pub const fn with_json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Itemizer writing to standard output
+    #[must_use]
+    pub fn stdout(root: &Path) -> Self {
+        Self::new(root, Box::new(std::io::stdout()))
+    }
+
+    /// The regular file `dst` is written with the contents of a source file
+    ///
+    /// `existing` is the destination being replaced, if any. With `checksum`
+    /// its contents were compared (`--checksum`) and found to differ.
+    pub fn file(
+        &self,
+        dst: &Path,
+        src: &FileMetadata,
+        existing: Option<&FileMetadata>,
+        checksum: bool,
+    ) {
+        let Some(existing) = existing else {
+            self.emit(">f", NEW, dst, "", &Detail::None);
+            return;
+        };
+        let flag = |differs: bool, flag: char| if differs { flag } else { '.' };
+        let crtime_differs =
+            self.crtimes && matches!((src.created, existing.created), (Some(a), Some(b)) if a != b);
+        let attributes: String = [
+            flag(checksum && src.size == existing.size, 'c'),
+            flag(src.size != existing.size, 's'),
+            flag(src.modified != existing.modified, 't'),
+            '.',
+            '.',
+            '.',
+            flag(crtime_differs, 'n'),
+            '.',
+            '.',
+        ]
+        .into_iter()
+        .collect();
+        self.emit(">f", &attributes, dst, "", &Detail::None);
+    }
+
+    /// The directory `dst` is created
+    pub fn directory(&self, dst: &Path) {
+        self.emit("cd", NEW, dst, "/", &Detail::None);
+    }
+
+    /// The symlink `dst` is created pointing to `target`, replacing an
+    /// existing entry if `replaced`
+    pub fn symlink(&self, dst: &Path, target: &Path, replaced: bool) {
+        let attributes = if replaced { "c........" } else { NEW };
+        self.emit("cL", attributes, dst, "", &Detail::Target(target));
+    }
+
+    /// `dst` is created as a hardlink to the already copied `original`
+    pub fn hardlink(&self, dst: &Path, original: &Path) {
+        let original = original.strip_prefix(&self.root).unwrap_or(original);
+        self.emit("hf", NEW, dst, "", &Detail::Original(original));
+    }
+
+    /// The device (`is_device`), fifo or socket `dst` is created
+    pub fn special(&self, dst: &Path, is_device: bool) {
+        let change = if is_device { "cD" } else { "cS" };
+        self.emit(change, NEW, dst, "", &Detail::None);
+    }
+
+    /// `dst` is created as an empty placeholder for a special file
+    pub fn placeholder(&self, dst: &Path, kind: &str) {
+        self.emit(">f", NEW, dst, "", &Detail::Placeholder(kind));
+    }
+
+    /// The destination entry `dst` is deleted, with everything below it
+    pub fn delete(&self, dst: &Path, is_dir: bool) {
+        let slash = if is_dir { "/" } else { "" };
+        let path = format!("{}{slash}", self.relative(dst));
+        if self.json {
+            self.line(&json!({ "event": "itemize", "item": "*deleting", "path": path }));
+        } else {
+            self.line(&format!("*deleting   {path}"));
+        }
+    }
+
+    fn emit(&self, change: &str, attributes: &str, dst: &Path, slash: &str, detail: &Detail<'_>) {
+        let path = format!("{}{slash}", self.relative(dst));
+        if self.json {
+            let mut event = json!({
+                "event": "itemize",
+                "item": format!("{change}{attributes}"),
+                "path": path,
+            });
+            match *detail {
+                Detail::None => {}
+                Detail::Target(target) => {
+                    event["target"] = json!(target.to_string_lossy());
+                }
+                Detail::Original(original) => {
+                    event["link_to"] = json!(original.to_string_lossy());
+                }
+                Detail::Placeholder(kind) => event["placeholder_for"] = json!(kind),
+            }
+            self.line(&event);
+            return;
+        }
+        let suffix = match *detail {
+            Detail::None => String::new(),
+            Detail::Target(target) => format!(" -> {}", target.display()),
+            Detail::Original(original) => format!(" => {}", original.display()),
+            Detail::Placeholder(kind) => format!(" (placeholder for {kind})"),
+        };
+        self.line(&format!("{change}{attributes} {path}{suffix}"));
+    }
+
+    fn line(&self, line: &dyn std::fmt::Display) {
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = writeln!(output, "{line}");
+    }
+
+    /// `path` relative to the root; the root itself is `.`
+    fn relative(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path.display().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    /// Output buffer shared with the test
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn meta(size: u64, modified_secs: u64) -> FileMetadata {
+        FileMetadata {
+            size,
+            mode: libc::S_IFREG | 0o644,
+            uid: 0,
+            gid: 0,
+            nlink: 1,
+            ino: 1,
+            dev: 1,
+            rdev: 0,
+            blocks: 0,
+            accessed: SystemTime::UNIX_EPOCH,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(modified_secs),
+            created: None,
+            #[cfg(target_os = "linux")]
+            attributes: None,
+            #[cfg(target_os = "linux")]
+            attributes_mask: None,
+            #[cfg(target_os = "macos")]
+            flags: None,
+            #[cfg(target_os = "macos")]
+            generation: None,
+        }
+    }
+
+    #[test]
+    fn test_itemized_lines() {
+        // Requirement: One rsync-style line per change, relative to the root
+        let output = Captured::default();
+        let itemizer = Itemizer::new(Path::new("/dst"), Box::new(output.clone()));
+        let root = Path::new("/dst");
+
+        itemizer.directory(root);
+        itemizer.directory(&root.join("sub"));
+        itemizer.file(&root.join("new"), &meta(3, 1), None, false);
+        itemizer.file(&root.join("grown"), &meta(3, 1), Some(&meta(2, 1)), false);
+        itemizer.file(&root.join("same"), &meta(3, 1), Some(&meta(3, 1)), true);
+        itemizer.file(&root.join("touched"), &meta(3, 2), Some(&meta(3, 1)), false);
+        itemizer.symlink(&root.join("link"), Path::new("new"), false);
+        itemizer.hardlink(&root.join("sub/alias"), &root.join("new"));
+        itemizer.special(&root.join("null"), true);
+        itemizer.special(&root.join("pipe"), false);
+        itemizer.delete(&root.join("old"), true);
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "cd+++++++++ ./",
+                "cd+++++++++ sub/",
+                ">f+++++++++ new",
+                ">f.s....... grown",
+                ">fc........ same",
+                ">f..t...... touched",
+                "cL+++++++++ link -> new",
+                "hf+++++++++ sub/alias => new",
+                "cD+++++++++ null",
+                "cS+++++++++ pipe",
+                "*deleting   old/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_itemized_crtimes() {
+        // Requirement: With --crtimes a differing creation time is flagged
+        // `n` in rsync's access/creation time column; unknown ones are not
+        let output = Captured::default();
+        let itemizer =
+            Itemizer::new(Path::new("/dst"), Box::new(output.clone())).with_crtimes(true);
+        let root = Path::new("/dst");
+        let born = |secs| {
+            let mut metadata = meta(3, 1);
+            metadata.created = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            metadata
+        };
+
+        itemizer.file(&root.join("reborn"), &born(1), Some(&born(2)), false);
+        itemizer.file(&root.join("same"), &born(1), Some(&born(1)), false);
+        itemizer.file(&root.join("unknown"), &born(1), Some(&meta(3, 1)), false);
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                ">f......n.. reborn",
+                ">f......... same",
+                ">f......... unknown"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_itemized_json_events() {
+        // Requirement: With --json each change is one `itemize` event with
+        // the same change string, path and link target as the text line
+        let output = Captured::default();
+        let itemizer = Itemizer::new(Path::new("/dst"), Box::new(output.clone())).with_json(true);
+        let root = Path::new("/dst");
+
+        itemizer.directory(&root.join("sub"));
+        itemizer.symlink(&root.join("link"), Path::new("new"), true);
+        itemizer.hardlink(&root.join("sub/alias"), &root.join("new"));
+        itemizer.delete(&root.join("old"), false);
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                json!({"event": "itemize", "item": "cd+++++++++", "path": "sub/"}),
+                json!({"event": "itemize", "item": "cLc........", "path": "link", "target": "new"}),
+                json!({"event": "itemize", "item": "hf+++++++++", "path": "sub/alias", "link_to": "new"}),
+                json!({"event": "itemize", "item": "*deleting", "path": "old"}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Line-delimited JSON output for automation (`--json`)
+//!
+//! With `--json` everything arsync reports on standard output is a stream of
+//! JSON objects, one per line, each naming its kind in `event`:
+//!
+//! ```text
+//! {"event":"file_start","path":"/data/a.txt","size":1024}
+//! {"event":"progress","files_done":118,"bytes_copied":73400320}
+//! {"event":"file_done","path":"/data/a.txt","bytes":1024}
+//! {"event":"error","path":"/data/locked","message":"..."}
+//! {"event":"itemize","item":">f.st......","path":"notes.txt"}
+//! {"event":"summary","files_copied":120,"bytes_copied":73401344,"errors":1,"duration_secs":0.42,"events_dropped":0}
+//! ```
+//!
+//! - `file_start` and `file_done` bracket the copy of a file's contents
+//!   (source `path`); a file whose copy fails gets an `error` instead;
+//! - `progress` totals are printed at most once a second while data is
+//!   copied;
+//! - `error` is printed for every failed entry as it fails, and once more
+//!   without a `path` (with a `hint` when there is one) if the run fails;
+//! - `itemize` is a change of a `--dry-run` (see `Itemizer::with_json`);
+//! - `summary` is the last line of a run that completed.
+//!
+//! Log messages go to standard error so standard output stays parseable.
+//! Non-UTF-8 paths are converted lossily. Events come from the sync event bus;
+//! `events_dropped` counts any that were lost because the writer fell behind.
+
+use crate::error::SyncError;
+use crate::events::{FileId, SyncEvent, EVENTS};
+use crate::sync::SyncStats;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Events the writer can fall behind by before it starts losing them
+const EVENT_QUEUE_CAPACITY: usize = 256 * 1024;
+
+/// Minimum time between two `progress` events
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes the sync event bus to standard output as JSON lines (`--json`)
+#[derive(Debug)]
+pub struct JsonReporter {
+    /// Event bus subscriber id
+    subscriber: u64,
+    /// Task writing events as they arrive
+    task: compio::runtime::JoinHandle<()>,
+}
+
+impl JsonReporter {
+    /// Subscribe to the event bus and start writing events
+    #[must_use]
+    pub fn start() -> Self {
+        let mut events = EVENTS.subscribe(EVENT_QUEUE_CAPACITY);
+        let subscriber = events.id();
+        let task = compio::runtime::spawn(async move {
+            let mut stream = EventStream::new();
+            while let Some(event) = events.next().await {
+                if let Some(line) = stream.apply(event, Instant::now()) {
+                    print(&line);
+                }
+            }
+        });
+        Self { subscriber, task }
+    }
+
+    /// Write the events still queued and stop
+    pub async fn finish(self) {
+        EVENTS.unsubscribe(self.subscriber);
+        let _ = self.task.await;
+    }
+}
+
+/// Turns bus events into JSON events
+struct EventStream {
+    /// Source paths of the files being copied
+    files: HashMap<FileId, PathBuf>,
+    /// Files copied so far
+    files_done: u64,
+    /// Bytes written so far
+    bytes_copied: u64,
+    /// When the last `progress` event was printed
+    last_progress: Option<Instant>,
+}
+
+impl EventStream {
+    fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            files_done: 0,
+            bytes_copied: 0,
+            last_progress: None,
+        }
+    }
+
+    /// The JSON event for `event` received at `now`, if it prints one
+    fn apply(&mut self, event: SyncEvent, now: Instant) -> Option<Value> {
+        match event {
+            SyncEvent::EntryDiscovered { .. } => None,
+            SyncEvent::FileStarted { id, path, size, .. } => {
+                let line = json!({
+                    "event": "file_start",
+                    "path": path.to_string_lossy(),
+                    "size": size,
+                });
+                self.files.insert(id, path);
+                Some(line)
+            }
+            SyncEvent::ChunkCopied { bytes, .. } => {
+                self.bytes_copied += bytes;
+                if self
+                    .last_progress
+                    .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+                {
+                    return None;
+                }
+                self.last_progress = Some(now);
+                Some(json!({
+                    "event": "progress",
+                    "files_done": self.files_done,
+                    "bytes_copied": self.bytes_copied,
+                }))
+            }
+            SyncEvent::FileDone { id, bytes } => {
+                self.files_done += 1;
+                let path = self.files.remove(&id)?;
+                Some(json!({
+                    "event": "file_done",
+                    "path": path.to_string_lossy(),
+                    "bytes": bytes,
+                }))
+            }
+            SyncEvent::FileFailed { id } => {
+                self.files.remove(&id);
+                None
+            }
+            SyncEvent::Error { path, message, .. } => Some(json!({
+                "event": "error",
+                "path": path.to_string_lossy(),
+                "message": message,
+            })),
+        }
+    }
+}
+
+/// Print the `summary` event of a completed run
+pub fn summary(stats: &SyncStats) {
+    print(&json!({
+        "event": "summary",
+        "files_copied": stats.files_copied,
+        "bytes_copied": stats.bytes_copied,
+        "errors": stats.errors,
+        "duration_secs": stats.duration.as_secs_f64(),
+        "events_dropped": EVENTS.dropped(),
+    }));
+}
+
+/// Print the `error` event of a failed run
+pub fn error(error: &SyncError) {
+    let mut line = json!({ "event": "error", "message": error.to_string() });
+    if let Some(remedy) = error.remedy() {
+        line["hint"] = json!(remedy.to_string());
+    }
+    print(&line);
+}
+
+/// Write one event line to standard output
+fn print(line: &Value) {
+    let _ = writeln!(std::io::stdout(), "{line}");
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_events_become_json_lines() {
+        // Requirement: Files are reported by path from start to done,
+        // progress at most once a second, and failures as errors
+        let mut stream = EventStream::new();
+        let start = Instant::now();
+        let file = FileId(1);
+
+        assert_eq!(
+            stream.apply(
+                SyncEvent::FileStarted {
+                    job: 0,
+                    id: file,
+                    path: PathBuf::from("/src/a"),
+                    size: 20,
+                },
+                start
+            ),
+            Some(json!({"event": "file_start", "path": "/src/a", "size": 20}))
+        );
+        let chunk = |offset| SyncEvent::ChunkCopied {
+            id: file,
+            offset,
+            bytes: 10,
+        };
+        assert_eq!(
+            stream.apply(chunk(0), start),
+            Some(json!({"event": "progress", "files_done": 0, "bytes_copied": 10}))
+        );
+        assert_eq!(stream.apply(chunk(10), start), None);
+        assert_eq!(
+            stream.apply(
+                SyncEvent::FileDone {
+                    id: file,
+                    bytes: 20
+                },
+                start
+            ),
+            Some(json!({"event": "file_done", "path": "/src/a", "bytes": 20}))
+        );
+        assert_eq!(
+            stream.apply(chunk(20), start + PROGRESS_INTERVAL),
+            Some(json!({"event": "progress", "files_done": 1, "bytes_copied": 30}))
+        );
+
+        stream.apply(
+            SyncEvent::FileStarted {
+                job: 0,
+                id: FileId(2),
+                path: PathBuf::from("/src/b"),
+                size: 5,
+            },
+            start,
+        );
+        assert_eq!(
+            stream.apply(SyncEvent::FileFailed { id: FileId(2) }, start),
+            None
+        );
+        assert_eq!(
+            stream.apply(
+                SyncEvent::Error {
+                    job: 0,
+                    path: PathBuf::from("/src/b"),
+                    message: "EIO".to_string(),
+                },
+                start
+            ),
+            Some(json!({"event": "error", "path": "/src/b", "message": "EIO"}))
+        );
+        assert!(stream.files.is_empty());
+    }
+}
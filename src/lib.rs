@@ -46,21 +46,70 @@
 //! ```
 
 pub mod adaptive_concurrency;
+pub mod atomic_create;
+pub mod bisync;
+pub mod broadcast;
+pub mod checkpoint;
+pub mod checksum_list;
 pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod control;
 pub mod copy;
+pub mod copy_task;
 pub mod copy_trait;
+pub mod delay_updates;
+pub mod deletion;
+pub mod dest_lock;
+pub mod device;
 pub mod directory;
+pub mod encryption;
 pub mod error;
+pub mod error_policy;
+pub mod events;
+pub mod fanout;
 pub mod file_wrapper;
+pub mod filter;
+pub mod hardlink_store;
 pub mod hardlink_tracker;
 pub mod i18n;
+pub mod inode_flags;
+pub mod interactive;
+pub mod interned_path;
+pub mod interrupt;
 pub mod io_uring;
+pub mod itemize;
+pub mod json_output;
+pub mod long_names;
 pub mod metadata;
+pub mod metrics;
+pub mod offload;
+pub mod overlap;
+pub mod overlay;
+pub mod path_builder;
+pub mod pipelines;
+pub mod plan;
+pub mod preread;
+pub mod priority;
+pub mod privileges;
 pub mod progress;
 pub mod protocol;
+pub mod run_state;
+pub mod shadow_rsync;
+pub mod simulate;
 pub mod stats;
+pub mod supervisor;
+pub mod symlink_rewrite;
 pub mod sync;
+pub mod tar_export;
+pub mod tar_import;
+pub mod temp_files;
 pub mod traits;
+pub mod transform;
+pub mod tuning;
+pub mod verify;
+pub mod warnings;
+pub mod write_verify;
 
 // Re-export commonly used types
 pub use error::{Result, SyncError};
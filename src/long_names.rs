@@ -0,0 +1,361 @@
+//! Handling of file names longer than the destination's `NAME_MAX`
+//!
+//! Filesystems differ in how long a single path component may be: most allow
+//! 255 bytes, but some (eCryptfs: 143 bytes) allow much less. A source name
+//! that is too long for the destination would otherwise fail deep inside the
+//! copy with a bare `ENAMETOOLONG`.
+//!
+//! The limit is queried per destination directory with `fstatvfs(2)`. Names
+//! over it are reported with both lengths and the offending source path, or,
+//! with `--truncate-long-names=hash`, shortened deterministically: the name is
+//! cut down and a hash of the full name is appended (keeping the extension),
+//! so the same source name always maps to the same destination name. Every
+//! shortened name is recorded in a mapping manifest (`.arsync-long-names`) in
+//! the destination root so the original names can be recovered.
+//!
+//! A file may be written under a temporary name first (`--atomic-create`,
+//! `--delay-updates`), which is longer than its own. Temporary names shorten
+//! what they embed to fit the usual 255-byte `NAME_MAX`; where the limit is
+//! lower, names are shortened with room left for the temporary prefix
+//! (`temp_files::TEMP_NAME_RESERVE`).
+
+use crate::bisync::escape_path;
+use crate::encryption::Encryption;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::temp_files::TEMP_NAME_RESERVE;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Name of the mapping manifest written to the destination root
+pub const MANIFEST_FILE_NAME: &str = ".arsync-long-names";
+
+/// `NAME_MAX` assumed when the destination filesystem does not report one
+pub const DEFAULT_NAME_MAX: usize = 255;
+
+/// Hex digits of the name hash appended to shortened names
+const HASH_HEX_LEN: usize = 16;
+
+/// Longest extension kept when shortening (including the dot)
+const MAX_KEPT_EXTENSION: usize = 16;
+
+/// How to handle names longer than the destination's `NAME_MAX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LongNamePolicy {
+    /// Shorten to `<prefix>~<hash>[.ext]` and record the mapping
+    Hash,
+}
+
+/// Maximum file name length on the filesystem of an open directory
+///
+/// Falls back to `DEFAULT_NAME_MAX` if the filesystem does not say.
+#[must_use]
+pub fn name_max(dir_fd: RawFd) -> usize {
+    let mut buf = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: buf is a valid out-pointer for fstatvfs
+    if unsafe { libc::fstatvfs(dir_fd, buf.as_mut_ptr()) } != 0 {
+        return DEFAULT_NAME_MAX;
+    }
+    // SAFETY: fstatvfs succeeded and initialized buf
+    let namemax = unsafe { buf.assume_init() }.f_namemax;
+    usize::try_from(namemax)
+        .ok()
+        .filter(|&max| max > 0)
+        .unwrap_or(DEFAULT_NAME_MAX)
+}
+
+/// Deterministically shorten `name` to at most `max` bytes
+///
+/// The result is `<prefix>~<hash>` followed by the original extension when it
+/// is short enough to keep. The prefix is cut on a character boundary if the
+/// name is valid UTF-8.
+#[must_use]
+pub fn shorten_name(name: &OsStr, max: usize) -> OsString {
+    let bytes = name.as_bytes();
+    let digest = md5::compute(bytes);
+    let hash: String = digest.0.iter().map(|b| format!("{b:02x}")).collect();
+    let hash = &hash.as_bytes()[..HASH_HEX_LEN];
+
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| ext.as_bytes())
+        .filter(|ext| ext.len() < MAX_KEPT_EXTENSION)
+        .unwrap_or_default();
+    // Extension including its dot, or nothing
+    let ext_len = if extension.is_empty() {
+        0
+    } else {
+        extension.len() + 1
+    };
+    let suffix_len = 1 + hash.len() + ext_len;
+    if max <= suffix_len {
+        return OsString::from_vec(hash[..max.min(hash.len())].to_vec());
+    }
+
+    let stem_len = bytes.len() - ext_len;
+    let mut keep = (max - suffix_len).min(stem_len);
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        while !text.is_char_boundary(keep) {
+            keep -= 1;
+        }
+    }
+
+    let mut short = Vec::with_capacity(max);
+    short.extend_from_slice(&bytes[..keep]);
+    short.push(b'~');
+    short.extend_from_slice(hash);
+    if !extension.is_empty() {
+        short.push(b'.');
+        short.extend_from_slice(extension);
+    }
+    OsString::from_vec(short)
+}
+
+/// Applies the long-name policy during a copy and keeps the mapping manifest
+#[derive(Debug)]
+pub struct LongNameMapper {
+    /// Policy from `--truncate-long-names` (`None` = report and fail)
+    policy: Option<LongNamePolicy>,
+    /// Destination root; manifest paths are relative to it
+    dest_root: PathBuf,
+    /// Manifest file and the lines it already holds, opened on first use
+    manifest: Mutex<Option<(File, HashSet<Vec<u8>>)>>,
+    /// Number of names shortened in this run
+    shortened: AtomicU64,
+    /// Whether shortened names are recorded (not with `--dry-run`)
+    keep_manifest: bool,
+    /// Random stored names instead (`--encrypt-names`)
+    encryption: Option<Arc<Encryption>>,
+}
+
+impl LongNameMapper {
+    /// Create a mapper for a copy into `dest_root`
+    #[must_use]
+    pub fn new(dest_root: &Path, policy: Option<LongNamePolicy>) -> Self {
+        Self {
+            policy,
+            dest_root: dest_root.to_path_buf(),
+            manifest: Mutex::new(None),
+            shortened: AtomicU64::new(0),
+            keep_manifest: true,
+            encryption: None,
+        }
+    }
+
+    /// Mapper that shortens names without writing the manifest (`--dry-run`)
+    #[must_use]
+    pub fn without_manifest(mut self) -> Self {
+        self.keep_manifest = false;
+        self
+    }
+
+    /// Mapper that gives the stored names of `encryption` when it encrypts
+    /// names
+    #[must_use]
+    pub fn with_encryption(mut self, encryption: Option<Arc<Encryption>>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Destination name for the source entry `name` copied into `dst_dir`
+    ///
+    /// Returns `name` unchanged if it fits in `name_max` bytes (with room
+    /// for a temporary name when shortening), or its stored name if names
+    /// are encrypted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::FileSystem` naming the file and both lengths if the
+    /// name is too long and no policy is set, or if the manifest cannot be
+    /// written.
+    pub fn map_name(
+        &self,
+        src_dir: &Path,
+        dst_dir: &Path,
+        name: &OsStr,
+        name_max: usize,
+    ) -> Result<OsString> {
+        if let Some(stored) = self
+            .encryption
+            .as_ref()
+            .and_then(|encryption| encryption.map_name(dst_dir, name))
+        {
+            return Ok(stored);
+        }
+        // Below the usual NAME_MAX, temporary names cannot shorten what they
+        // embed enough themselves
+        let limit = if name_max < DEFAULT_NAME_MAX {
+            name_max.saturating_sub(TEMP_NAME_RESERVE)
+        } else {
+            name_max
+        };
+        if name.len() <= limit || (self.policy.is_none() && name.len() <= name_max) {
+            return Ok(name.to_os_string());
+        }
+        let Some(LongNamePolicy::Hash) = self.policy else {
+            return Err(SyncError::FileSystem(format!(
+                "Cannot copy {}: file name is {} bytes, but the destination filesystem at {} \
+                 allows at most {name_max} (NAME_MAX); pass --truncate-long-names=hash to shorten such names",
+                src_dir.join(name).display(),
+                name.len(),
+                dst_dir.display()
+            )));
+        };
+
+        let short = shorten_name(name, limit);
+        self.record(&dst_dir.join(&short), name)?;
+        self.shortened.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(
+            "Shortened {} ({} bytes) to {} for the destination's {name_max}-byte NAME_MAX",
+            src_dir.join(name).display(),
+            name.len(),
+            short.to_string_lossy()
+        );
+        Ok(short)
+    }
+
+    /// Number of names shortened so far
+    #[must_use]
+    pub fn shortened(&self) -> u64 {
+        self.shortened.load(Ordering::Relaxed)
+    }
+
+    /// Path of the mapping manifest
+    #[must_use]
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dest_root.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Append `<shortened path>\t<original name>` to the manifest, once
+    fn record(&self, short_path: &Path, original: &OsStr) -> Result<()> {
+        if !self.keep_manifest {
+            return Ok(());
+        }
+        let relative = short_path
+            .strip_prefix(&self.dest_root)
+            .unwrap_or(short_path);
+        let mut line = Vec::new();
+        escape_path(relative.as_os_str(), &mut line);
+        line.push(b'\t');
+        escape_path(original, &mut line);
+        line.push(b'\n');
+
+        let path = self.manifest_path();
+        let manifest_error = |e: &std::io::Error| {
+            ErrorContext::new("write long-name manifest")
+                .destination(&path)
+                .io_cause(e)
+                .file_system()
+        };
+        let mut guard = self
+            .manifest
+            .lock()
+            .map_err(|_| SyncError::Internal("long-name manifest lock poisoned".to_string()))?;
+        if guard.is_none() {
+            *guard = Some(open_manifest(&path).map_err(|e| manifest_error(&e))?);
+        }
+        let Some((file, lines)) = guard.as_mut() else {
+            return Ok(());
+        };
+        if lines.contains(&line) {
+            return Ok(());
+        }
+        file.write_all(&line).map_err(|e| manifest_error(&e))?;
+        lines.insert(line);
+        Ok(())
+    }
+}
+
+/// Open the manifest for appending, loading the mappings it already records
+fn open_manifest(path: &Path) -> std::io::Result<(File, HashSet<Vec<u8>>)> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    let lines = contents
+        .split_inclusive(|&b| b == b'\n')
+        .map(<[u8]>::to_vec)
+        .collect();
+    Ok((file, lines))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_shorten_name_is_deterministic_and_fits() {
+        // Test: Shortened names fit the limit, keep the extension and are stable
+        let long = OsString::from(format!("{}.jpg", "é".repeat(100)));
+        let short = shorten_name(&long, 143);
+
+        assert!(short.len() <= 143, "{}", short.len());
+        assert!(short.to_str().unwrap().ends_with(".jpg"));
+        assert!(short.to_str().unwrap().starts_with("éé"));
+        assert_eq!(short, shorten_name(&long, 143));
+        assert_ne!(
+            short,
+            shorten_name(&OsString::from(format!("{}.jpg", "é".repeat(101))), 143)
+        );
+    }
+
+    #[test]
+    fn test_long_names_fail_without_policy_and_are_mapped_with_hash() {
+        // Requirement: Report the file clearly by default; record mappings with hash
+        let temp_dir = TempDir::new().unwrap();
+        let long = OsString::from("x".repeat(200));
+
+        let strict = LongNameMapper::new(temp_dir.path(), None);
+        let err = strict
+            .map_name(Path::new("/src"), temp_dir.path(), &long, 143)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("200 bytes") && err.contains("143"), "{err}");
+        assert!(err.contains("--truncate-long-names=hash"), "{err}");
+
+        let mapper = LongNameMapper::new(temp_dir.path(), Some(LongNamePolicy::Hash));
+        let short = mapper
+            .map_name(Path::new("/src"), &temp_dir.path().join("d"), &long, 143)
+            .unwrap();
+        mapper
+            .map_name(Path::new("/src"), &temp_dir.path().join("d"), &long, 143)
+            .unwrap();
+        assert_eq!(mapper.shortened(), 2);
+
+        let manifest = std::fs::read_to_string(mapper.manifest_path()).unwrap();
+        assert_eq!(
+            manifest,
+            format!("d/{}\t{}\n", short.to_str().unwrap(), "x".repeat(200))
+        );
+
+        // Room is left for the temporary name a staged copy is written under
+        assert!(short.len() + TEMP_NAME_RESERVE <= 143, "{}", short.len());
+        let fits = OsString::from("y".repeat(120));
+        assert_eq!(
+            strict
+                .map_name(Path::new("/src"), temp_dir.path(), &fits, 143)
+                .unwrap(),
+            fits
+        );
+        assert_ne!(
+            mapper
+                .map_name(Path::new("/src"), &temp_dir.path().join("d"), &fits, 143)
+                .unwrap(),
+            fits
+        );
+        assert!(name_max(File::open(temp_dir.path()).unwrap().as_raw_fd()) > 0);
+    }
+}
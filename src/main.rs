@@ -5,30 +5,141 @@
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use tracing::{info, Level};
+use std::ffi::OsString;
+use std::sync::Arc;
+use tracing::{info, warn, Level};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 mod adaptive_concurrency;
+mod atomic_create;
+mod bisync;
+mod broadcast;
+mod checkpoint;
+mod checksum_list;
 mod cli;
+mod clock;
+mod config;
+mod control;
 mod copy;
+mod copy_task;
 mod copy_trait;
+mod delay_updates;
+mod deletion;
+mod dest_lock;
+mod device;
 mod directory;
+mod encryption;
 mod error;
+mod error_policy;
+mod events;
+mod fanout;
 mod file_wrapper;
+mod filter;
+mod hardlink_store;
 mod hardlink_tracker;
 mod i18n;
+mod inode_flags;
+mod interactive;
+mod interned_path;
+mod interrupt;
 mod io_uring;
+mod itemize;
+mod json_output;
+mod long_names;
 mod metadata;
+mod metrics;
+mod offload;
+mod overlap;
+mod overlay;
+mod path_builder;
+mod pipelines;
+mod plan;
+mod preread;
+mod priority;
+mod privileges;
 mod progress;
 mod protocol;
+mod run_state;
+mod shadow_rsync;
+mod simulate;
 mod stats;
+mod supervisor;
+mod symlink_rewrite;
 mod sync;
+mod tar_export;
+mod tar_import;
+mod temp_files;
 mod traits;
+mod transform;
+mod tuning;
+mod verify;
+mod warnings;
+mod write_verify;
 
-use cli::Args;
+use cli::{
+    Args, BisyncArgs, CleanupArgs, DaemonArgs, PlanArgs, ProbeArgs, RestoreArgs, ServeArgs,
+    SimulateArgs, UsageArgs, VerifyArgs,
+};
 use i18n::{set_language, Language, TranslationKey};
 
-#[compio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    // Without io_uring no runtime can start: say why (no io_uring in the
+    // kernel, disabled by sysctl, blocked by seccomp) instead of panicking
+    compio_fs_extended::uring_probe::check_available().context("arsync needs io_uring")?;
+    compio::runtime::Runtime::new()
+        .context("Failed to start the io_uring runtime")?
+        .block_on(run())
+}
+
+/// Parse the arguments after `arsync NAME` as `T`
+///
+/// A subcommand name is recognized before any path, so `arsync plan /dst`
+/// with a `plan` directory in the working directory is refused rather than
+/// guessed at.
+fn parse_subcommand<T: Parser>(name: &str) -> Result<T> {
+    if std::path::Path::new(name).exists() {
+        anyhow::bail!(
+            "`{name}` is both an arsync subcommand and a path in the current directory; \
+             write `./{name}` to copy it, or run `arsync {name}` from another directory"
+        );
+    }
+    Ok(T::parse_from(
+        std::iter::once(OsString::from(format!("arsync {name}")))
+            .chain(std::env::args_os().skip(2)),
+    ))
+}
+
+/// Dispatch the subcommand or run a sync
+async fn run() -> Result<()> {
+    // `arsync cleanup DST`, `arsync restore ENCRYPTED DST`, `arsync bisync A B`, `arsync simulate MANIFEST`,
+    // `arsync usage LEDGER`, `arsync serve`, `arsync probe HOST`,
+    // `arsync --server` (the receiving end of an rsync client) and
+    // `arsync --daemon` (the same for rsync:// clients) are dispatched
+    // before the main parser, which takes SOURCE and DESTINATION positionally
+    let first = std::env::args_os().nth(1);
+    match first.as_ref().and_then(|arg| arg.to_str()) {
+        Some(name @ CleanupArgs::SUBCOMMAND) => return run_cleanup(&parse_subcommand(name)?),
+        Some(name @ RestoreArgs::SUBCOMMAND) => return run_restore(&parse_subcommand(name)?),
+        Some(name @ VerifyArgs::SUBCOMMAND) => return run_verify(&parse_subcommand(name)?),
+        Some(name @ PlanArgs::SUBCOMMAND) => return run_plan(&parse_subcommand(name)?),
+        Some(name @ BisyncArgs::SUBCOMMAND) => return run_bisync(&parse_subcommand(name)?),
+        Some(name @ SimulateArgs::SUBCOMMAND) => {
+            return run_simulate(&parse_subcommand(name)?).await;
+        }
+        Some(name @ UsageArgs::SUBCOMMAND) => return run_usage(&parse_subcommand(name)?),
+        Some(name @ ServeArgs::SUBCOMMAND) => return run_serve(&parse_subcommand(name)?).await,
+        #[cfg(feature = "remote-sync")]
+        Some(name @ ProbeArgs::SUBCOMMAND) => return run_probe(&parse_subcommand(name)?).await,
+        #[cfg(feature = "remote-sync")]
+        Some(protocol::rsync_receiver::ServerArgs::FLAG) => {
+            let server_args: Vec<OsString> = std::env::args_os().skip(2).collect();
+            return run_rsync_server(&server_args).await;
+        }
+        #[cfg(feature = "remote-sync")]
+        Some(name @ DaemonArgs::FLAG) => return run_daemon(&parse_subcommand(name)?).await,
+        _ => {}
+    }
+
     // Parse command line arguments
     let args = Args::parse();
 
@@ -43,6 +154,7 @@ async fn main() -> Result<()> {
         let subscriber = tracing_subscriber::fmt()
             .with_max_level(Level::ERROR)
             .with_target(false)
+            .with_writer(log_writer(&args))
             .finish();
         tracing::subscriber::set_global_default(subscriber)?;
     } else {
@@ -56,6 +168,7 @@ async fn main() -> Result<()> {
             .with_target(false)
             .with_thread_ids(false)
             .with_thread_names(false)
+            .with_writer(log_writer(&args))
             .finish();
         tracing::subscriber::set_global_default(subscriber)?;
     }
@@ -93,13 +206,91 @@ async fn main() -> Result<()> {
                 .map_or_else(|| "auto".to_string(), |kb| kb.to_string())
         );
         info!("Max files in flight: {}", args.max_files_in_flight());
+        // One binary runs on every kernel from 5.10 on; opcodes older
+        // kernels lack fall back to blocking syscalls
+        for (syscall, kernel) in compio_fs_extended::uring_probe::missing_opcodes() {
+            info!("io_uring has no {syscall} before Linux {kernel}: using the blocking syscall");
+        }
     }
 
     // Validate arguments
     args.validate().context("Invalid arguments")?;
 
+    if args.output.to_tar {
+        return run_tar_export(&config::SyncConfig::from(&args)).await;
+    }
+
+    // One hint instead of a failed chown per entry
+    if let Some(hint) = privileges::ownership_hint(&args.metadata) {
+        if args.metadata.unprivileged_ownership == metadata::UnprivilegedOwnership::Fail {
+            anyhow::bail!(hint);
+        }
+        warn!("{hint}");
+    }
+
+    if args.output.from_tar {
+        return run_tar_import(&config::SyncConfig::from(&args)).await;
+    }
+
+    #[cfg(feature = "remote-sync")]
+    if let protocol::Location::Remote { user, host, path } =
+        protocol::Location::parse(&args.destination().to_string_lossy())?
+    {
+        if args.traversal.encrypts() {
+            anyhow::bail!("--encrypt-to only applies to a local destination");
+        }
+        if args.output.emit_checksums.is_some() {
+            anyhow::bail!("--emit-checksums only applies to a local destination");
+        }
+        if args.output.json {
+            anyhow::bail!("--json only applies to a local destination");
+        }
+        if args.output.metrics_listen.is_some() || args.output.metrics_textfile.is_some() {
+            anyhow::bail!(
+                "--metrics-listen and --metrics-textfile only apply to a local destination"
+            );
+        }
+        return run_rsync_push(&args, user.as_deref().unwrap_or(""), &host, &path).await;
+    }
+
+    if args.io.show_tuning {
+        println!(
+            "Tuning for {}:\n{}",
+            args.destination().display(),
+            tuning::select(&config::SyncConfig::from(&args))
+        );
+        return Ok(());
+    }
+
+    // Ctrl-C stops a directory copy between entries instead of mid-write;
+    // the traversal is what checks the token
+    if args.paths.extra_destinations.is_empty() && args.source().is_dir() {
+        if let Err(e) = interrupt::install() {
+            warn!("Cannot handle SIGINT/SIGTERM, a signal will stop the copy mid-write: {e}");
+        }
+    }
+
+    let metrics = Arc::new(metrics::Metrics::new());
+    if let Some(addr) = args.output.metrics_listen {
+        let addr = metrics::serve(addr, Arc::clone(&metrics))
+            .with_context(|| format!("Cannot serve metrics on {addr}"))?;
+        info!("Serving metrics on http://{addr}/metrics");
+    }
+
     // Perform the sync operation
-    let result = sync::sync_files(&args).await;
+    let json = args.output.json.then(json_output::JsonReporter::start);
+    let result = sync::sync_files(&args, Arc::clone(&metrics)).await;
+    if let Some(json) = json {
+        json.finish().await;
+    }
+    if let Some(file) = &args.output.metrics_textfile {
+        let succeeded = result
+            .as_ref()
+            .is_ok_and(|stats| stats.errors == 0 || args.traversal.ignore_errors);
+        if let Err(e) = metrics.write_textfile(file, succeeded) {
+            warn!("Cannot write metrics to {}: {e}", file.display());
+        }
+    }
 
     match result {
         Ok(stats) => {
@@ -130,16 +321,389 @@ async fn main() -> Result<()> {
                 stats.bytes_copied
             );
             info!("Duration: {:?}", stats.duration);
+            if args.output.shadow_rsync {
+                run_shadow_rsync(&config::SyncConfig::from(&args))?;
+            }
+            if args.output.json {
+                json_output::summary(&stats);
+            }
+            // Some entries were skipped: a partial transfer, as rsync reports it
+            if stats.errors > 0 && !args.traversal.ignore_errors {
+                if args.output.json {
+                    std::process::exit(error_policy::PARTIAL_TRANSFER_EXIT_CODE);
+                }
+                eprintln!(
+                    "{} entries failed and were skipped (listed above; --ignore-errors to exit with status 0)",
+                    stats.errors
+                );
+                std::process::exit(error_policy::PARTIAL_TRANSFER_EXIT_CODE);
+            }
             Ok(())
         }
         Err(e) => {
-            eprintln!(
-                "{}: {e}",
-                TranslationKey::StatusFailed
-                    .get()
-                    .unwrap_or_else(|_| "Failed".to_string())
-            );
+            if args.output.json {
+                json_output::error(&e);
+            } else {
+                eprintln!(
+                    "{}: {e}",
+                    TranslationKey::StatusFailed
+                        .get()
+                        .unwrap_or_else(|_| "Failed".to_string())
+                );
+                if let Some(remedy) = e.remedy() {
+                    eprintln!("hint: {remedy}");
+                }
+            }
+            if matches!(e, error::SyncError::Interrupted(_)) {
+                std::process::exit(interrupt::INTERRUPTED_EXIT_CODE);
+            }
             std::process::exit(1);
         }
     }
 }
+
+/// Log to stdout, unless a tar archive (`--to-tar`) or JSON events (`--json`)
+/// may be going there
+fn log_writer(args: &Args) -> BoxMakeWriter {
+    if args.output.to_tar || args.output.json {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    }
+}
+
+/// Write the source as a tar archive instead of copying it (`--to-tar`)
+async fn run_tar_export(config: &config::SyncConfig) -> Result<()> {
+    let report = tar_export::export(config).await?;
+    info!("--to-tar: {}: {report}", config.destination.display());
+    if report.source_errors > 0 {
+        anyhow::bail!(
+            "--to-tar: {} source entries could not be read and are missing from the archive",
+            report.source_errors
+        );
+    }
+    Ok(())
+}
+
+/// Extract the archive `config.source` into the destination (`--from-tar`)
+async fn run_tar_import(config: &config::SyncConfig) -> Result<()> {
+    let report = config
+        .metadata
+        .run
+        .delayed
+        .finish(tar_import::import(config).await)
+        .await?;
+    info!("--from-tar: {}: {report}", config.source.display());
+    if report.failed > 0 {
+        anyhow::bail!(
+            "--from-tar: {} archive members could not be extracted",
+            report.failed
+        );
+    }
+    Ok(())
+}
+
+/// Check every destination with rsync after the run (`--shadow-rsync`)
+///
+/// Prints what rsync would still change; any difference fails the run.
+fn run_shadow_rsync(config: &config::SyncConfig) -> Result<()> {
+    let mut changes = 0;
+    for destination in config.destinations() {
+        let report = shadow_rsync::check(config, destination)?;
+        if report.is_clean() {
+            info!("--shadow-rsync: {}: {report}", destination.display());
+        } else {
+            println!("--shadow-rsync: {}: {report}", destination.display());
+            changes += report.differences.len();
+        }
+    }
+    if changes > 0 {
+        anyhow::bail!("--shadow-rsync: rsync would still make {changes} change(s)");
+    }
+    Ok(())
+}
+
+/// Run `arsync probe`: print what a remote arsync and its filesystem support
+///
+/// With `--server`, answer the probe on stdin/stdout instead (this is what
+/// the client starts on the remote host).
+#[cfg(feature = "remote-sync")]
+async fn run_probe(args: &ProbeArgs) -> Result<()> {
+    use protocol::pipe::PipeTransport;
+    use protocol::probe;
+    use std::ffi::OsStr;
+
+    if args.server {
+        let mut transport = PipeTransport::from_stdio()?;
+        return probe::serve_probe(&mut transport, std::path::Path::new(&args.target)).await;
+    }
+
+    // What the remote shell reports on stderr is logged as warnings
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::WARN)
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    let (user, host, path) = args.split_target();
+    let connector = args.remote.connector(args.remote.remote_shell()?);
+    let remote_args = [
+        OsStr::new(ProbeArgs::SUBCOMMAND),
+        OsStr::new("--server"),
+        OsStr::new("--"),
+        OsStr::new(path),
+    ];
+    let result = connector
+        .session(user, host, &remote_args, |mut connection| async move {
+            let result = probe::probe(&mut connection).await;
+            connection.finish(result).await
+        })
+        .await
+        .with_context(|| format!("Probe of {host} failed"))?;
+    println!("{result}");
+    Ok(())
+}
+
+/// Push the source to `[user@]host:path` through a stock rsync server
+#[cfg(feature = "remote-sync")]
+async fn run_rsync_push(args: &Args, user: &str, host: &str, path: &std::path::Path) -> Result<()> {
+    let config = config::SyncConfig::from(args);
+    let connector = args.remote.connector(args.remote.rsync_shell()?);
+    let compress = args.remote.compress_level();
+    let stats = protocol::rsync_sender::push(&config, &connector, user, host, path, compress)
+        .await
+        .with_context(|| format!("Push to {host} failed"))?;
+    info!(
+        "Pushed {} files ({} bytes) in {:?}",
+        stats.files_copied, stats.bytes_copied, stats.duration
+    );
+    Ok(())
+}
+
+/// Run `arsync --server`: receive from an rsync client on stdin/stdout
+///
+/// This is what an rsync client starts on the remote host when pushing with
+/// `--rsync-path=arsync`. Stdout carries the protocol, so logging goes to
+/// stderr, which the client shows. Exits with rsync's code 23 if some files
+/// could not be updated.
+#[cfg(feature = "remote-sync")]
+async fn run_rsync_server(server_args: &[OsString]) -> Result<()> {
+    use protocol::pipe::PipeTransport;
+    use protocol::rsync_receiver::{self, ServerArgs};
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::WARN)
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    let args = ServerArgs::parse(server_args)?;
+    let mut transport = PipeTransport::from_stdio()?;
+    let stats = rsync_receiver::serve(&mut transport, &args).await?;
+    if stats.errors > 0 {
+        std::process::exit(error_policy::PARTIAL_TRANSFER_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// Run `arsync --daemon`: receive from rsync clients over TCP until SIGTERM
+#[cfg(feature = "remote-sync")]
+async fn run_daemon(args: &DaemonArgs) -> Result<()> {
+    use protocol::daemon::{self, DaemonConfig};
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(match args.verbose {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            _ => Level::DEBUG,
+        })
+        .with_target(false)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+    let config = DaemonConfig::load(&args.config)?;
+    daemon::run(
+        config,
+        args.address,
+        args.port,
+        std::time::Duration::from_secs(args.drain_timeout),
+    )
+    .await
+}
+
+/// Run `arsync serve`: accept sync jobs on a control socket until killed
+async fn run_serve(args: &ServeArgs) -> Result<()> {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(match args.verbose {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            _ => Level::DEBUG,
+        })
+        .with_target(false)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+    control::serve(args).await
+}
+
+/// Run `arsync usage`: print the transfer accounting recorded by a daemon
+fn run_usage(args: &UsageArgs) -> Result<()> {
+    use protocol::accounting::{Day, Usage, UsageLedger};
+
+    let ledger = UsageLedger::load(&args.ledger)?;
+    let since = args
+        .since
+        .unwrap_or_else(|| Day(Day::today(&clock::SystemClock).0.saturating_sub(29)));
+    let mut totals: std::collections::BTreeMap<&str, Usage> = std::collections::BTreeMap::new();
+    println!(
+        "{:<10}  {:<16}  {:<24}  {:>15}  {:>15}",
+        "DAY", "MODULE", "CLIENT", "BYTES IN", "BYTES OUT"
+    );
+    for (day, module, client, usage) in
+        ledger.query(since, args.module.as_deref(), args.client.as_deref())
+    {
+        println!(
+            "{day:<10}  {module:<16}  {client:<24}  {:>15}  {:>15}",
+            usage.bytes_in, usage.bytes_out
+        );
+        let total = totals.entry(module).or_default();
+        total.bytes_in = total.bytes_in.saturating_add(usage.bytes_in);
+        total.bytes_out = total.bytes_out.saturating_add(usage.bytes_out);
+    }
+    for (module, total) in totals {
+        println!(
+            "{:<10}  {module:<16}  {:<24}  {:>15}  {:>15}",
+            "total", "", total.bytes_in, total.bytes_out
+        );
+    }
+    Ok(())
+}
+
+/// Run `arsync cleanup`: remove stale temporary artifacts from a destination
+fn run_cleanup(args: &CleanupArgs) -> Result<()> {
+    let report = temp_files::cleanup_stale_artifacts(&args.destination, args.options())
+        .with_context(|| format!("Cleanup of {} failed", args.destination.display()))?;
+
+    let verb = if args.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for path in &report.removed {
+        println!("{verb} {}", path.display());
+    }
+    for path in &report.in_use {
+        println!("Kept (owning run may still be active) {}", path.display());
+    }
+    println!(
+        "{verb} {} stale artifact(s) ({} bytes), kept {} in use",
+        report.removed.len(),
+        report.bytes_freed,
+        report.in_use.len()
+    );
+    Ok(())
+}
+
+/// Run `arsync verify`: compare a copy with its source by content
+fn run_verify(args: &VerifyArgs) -> Result<()> {
+    let report =
+        verify::verify(&args.source, &args.destination, args.options()).with_context(|| {
+            format!(
+                "Verification of {} against {} failed",
+                args.destination.display(),
+                args.source.display()
+            )
+        })?;
+    for mismatch in &report.mismatches {
+        let path = if mismatch.path.as_os_str().is_empty() {
+            std::path::Path::new(".")
+        } else {
+            &mismatch.path
+        };
+        println!("{}: {}", path.display(), mismatch.difference);
+    }
+    println!(
+        "Compared {} entries, hashed {} files ({} bytes): {} differences",
+        report.entries,
+        report.files_hashed,
+        report.bytes_hashed,
+        report.mismatches.len()
+    );
+    if !report.mismatches.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run `arsync restore`: decrypt an encrypted copy (`--encrypt-to`)
+/// Print which copy mechanisms a copy would use (`arsync plan`)
+fn run_plan(args: &PlanArgs) -> Result<()> {
+    let plan = plan::plan(&args.source, &args.destinations, &args.options())
+        .with_context(|| format!("Cannot plan the copy of {}", args.source.display()))?;
+    println!(
+        "{}: {} files ({} bytes)",
+        args.source.display(),
+        plan.files,
+        plan.bytes
+    );
+    if plan.unreadable > 0 {
+        println!(
+            "{} entries could not be read and are not counted",
+            plan.unreadable
+        );
+    }
+    for destination in &plan.destinations {
+        if args.explain {
+            println!("{destination:#}");
+        } else {
+            println!("{destination}");
+        }
+    }
+    Ok(())
+}
+
+fn run_restore(args: &RestoreArgs) -> Result<()> {
+    let report = encryption::restore(&args.source, &args.destination, &args.identity)
+        .with_context(|| {
+            format!(
+                "Restore of {} into {} failed",
+                args.source.display(),
+                args.destination.display()
+            )
+        })?;
+    println!("Restored {report}");
+    Ok(())
+}
+
+/// Run `arsync bisync`: two-way sync of two directory trees
+fn run_bisync(args: &BisyncArgs) -> Result<()> {
+    let report = bisync::bisync(&args.a, &args.b, &args.options()).with_context(|| {
+        format!(
+            "Bisync of {} and {} failed",
+            args.a.display(),
+            args.b.display()
+        )
+    })?;
+
+    let prefix = if args.dry_run { "Would: " } else { "" };
+    for action in &report.actions {
+        println!("{prefix}{action}");
+    }
+    println!(
+        "{prefix}{} copied A -> B, {} copied B -> A, {} deleted, {} conflict(s) resolved",
+        report.copied_to_b, report.copied_to_a, report.deleted, report.conflicts
+    );
+    Ok(())
+}
+
+/// Run `arsync simulate`: benchmark the scheduler on a synthetic manifest
+async fn run_simulate(args: &SimulateArgs) -> Result<()> {
+    args.parallel.validate().context("Invalid arguments")?;
+    let tree = simulate::SimDir::load(&args.manifest)
+        .with_context(|| format!("Cannot load {}", args.manifest.display()))?;
+    let report = simulate::simulate(tree, args.cost(), &args.concurrency, &args.parallel)
+        .await
+        .context("Simulation failed")?;
+    println!("{report}");
+    Ok(())
+}
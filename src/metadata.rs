@@ -25,7 +25,11 @@
 //! ```
 
 use crate::error::{Result, SyncError};
+use crate::privileges::Privileges;
+use crate::symlink_rewrite::RewriteRule;
 use crate::traits::AsyncMetadata;
+use compio_fs_extended::{StatxMask, Timestamp};
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::time::SystemTime;
 
@@ -33,6 +37,9 @@ use std::time::SystemTime;
 // CONFIGURATION
 // ============================================================================
 
+/// `chown` ID that leaves the owner or group unchanged (-1)
+const UNCHANGED_ID: u32 = u32::MAX;
+
 /// Metadata preservation configuration
 ///
 /// Used by: `copy_file()`, `preserve_directory_metadata()`
@@ -69,10 +76,26 @@ pub struct MetadataConfig {
     #[arg(short = 'o', long)]
     pub owner: bool,
 
-    /// Preserve device files (super-user only) and special files
-    #[arg(short = 'D', long)]
+    /// Do not preserve the owner (overrides -a and -o)
+    #[arg(long)]
+    pub no_owner: bool,
+
+    /// Do not preserve the group (overrides -a and -g)
+    #[arg(long)]
+    pub no_group: bool,
+
+    /// Preserve device files (super-user only)
+    #[arg(long)]
     pub devices: bool,
 
+    /// Preserve special files (fifos and sockets)
+    #[arg(long)]
+    pub specials: bool,
+
+    /// Same as --devices --specials
+    #[arg(short = 'D')]
+    pub devices_and_specials: bool,
+
     /// Sync each file to disk after writing (like rsync --fsync)
     ///
     /// By default, arsync relies on OS page cache (like rsync).
@@ -80,6 +103,95 @@ pub struct MetadataConfig {
     #[arg(long)]
     pub fsync: bool,
 
+    /// Flush the destination filesystem once at the end of the run (syncfs)
+    ///
+    /// Cheaper alternative to --fsync for bulk migrations: a single `syncfs(2)`
+    /// on the destination filesystem after all files are written, instead of
+    /// an fsync per file. Note that this flushes the whole filesystem.
+    #[arg(long)]
+    pub syncfs: bool,
+
+    /// Preserve holes in sparse files (like rsync -S)
+    ///
+    /// Only the source's data segments are read (`SEEK_DATA`/`SEEK_HOLE`),
+    /// and chunks of zeros within them are not written, so both stay holes
+    /// in the destination. Disables parallel copies of large files and, with
+    /// `--copy-method auto`, `copy_file_range`.
+    #[arg(short = 'S', long)]
+    pub sparse: bool,
+
+    /// Read back and CRC-check written data, sampling PERCENT of chunks
+    ///
+    /// Each sampled chunk's CRC is taken from the buffer submitted to the
+    /// write; after the file is written its data is flushed, evicted from the
+    /// page cache and re-read from the device. Mismatches fail the file and
+    /// are reported with their offsets. Without a value, every chunk is
+    /// checked.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "100",
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    pub verify_direct: Option<u8>,
+
+    /// Create destination files so they only appear once complete
+    ///
+    /// `tmpfile` writes each file as an anonymous `O_TMPFILE` inode and links
+    /// it into place with `linkat` after its data and metadata are written;
+    /// filesystems without `O_TMPFILE` fall back to `rename`. `rename` writes
+    /// under a temporary name next to the destination and renames it over the
+    /// final name. Without this option files are written in place.
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub atomic_create: Option<crate::atomic_create::AtomicCreate>,
+
+    /// Keep partially copied files and resume them on the next run
+    ///
+    /// Each file is written as `.arsync.partial.<name>` next to its
+    /// destination and renamed into place when complete. A copy that fails or
+    /// is killed leaves the partial file behind, and the next run continues
+    /// it where it stopped if the source's size and modification time are
+    /// unchanged (recorded in a `user.arsync.partial` xattr; without user
+    /// xattrs it starts over). Takes precedence over --atomic-create and
+    /// disables parallel copies of large files.
+    #[arg(long)]
+    pub partial: bool,
+
+    /// Put the updated files in place together at the end of the run
+    ///
+    /// Each file is written under a hidden temporary name next to its
+    /// destination and renamed into place only once the whole tree has been
+    /// copied, so readers of the destination never see some files updated
+    /// and others not (directories, symlinks and special files still appear
+    /// as they are reached). If the run fails, the written files are removed
+    /// and the destination keeps its old contents. Takes precedence over
+    /// --atomic-create; not with --partial or --preserve-flags, whose flags
+    /// would have to be set on files not yet in place.
+    #[arg(long, conflicts_with_all = ["partial", "preserve_flags"])]
+    pub delay_updates: bool,
+
+    /// What to do with fifos, sockets and device files that are not copied
+    ///
+    /// Applies to fifos and sockets without --specials, and to devices
+    /// without --devices or when not running as root. `skip` leaves them out with a warning for each; `placeholder` creates
+    /// an empty regular file in their place, with their permissions and
+    /// timestamps when those are preserved. Either way they are counted per
+    /// type in the end-of-run statistics.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    pub special_files: SpecialFilePolicy,
+
+    /// What to do about ownership without the privileges to change it
+    ///
+    /// Applies when the owner or group is preserved but the process lacks
+    /// `CAP_CHOWN` (not running as root), which is reported once at startup.
+    /// `permitted` sets only what an ordinary user may: the group, when the
+    /// user belongs to it. `attempt` tries every `chown` anyway and fails the
+    /// entries it is refused for. `fail` refuses to start.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    pub unprivileged_ownership: UnprivilegedOwnership,
+
     /// Preserve extended attributes
     #[arg(short = 'X', long)]
     pub xattrs: bool,
@@ -96,10 +208,60 @@ pub struct MetadataConfig {
     #[arg(short = 'U', long)]
     pub atimes: bool,
 
-    /// Preserve creation times (when supported)
+    /// Compare and report creation times (Linux cannot set them, so copies
+    /// keep the time they were created)
     #[arg(long)]
     pub crtimes: bool,
 
+    /// Do not preserve directory modification times (with --times)
+    ///
+    /// For destinations that reject or noisily fail directory time updates,
+    /// such as NFS exports with root squash. File times are still preserved.
+    #[arg(short = 'O', long)]
+    pub omit_dir_times: bool,
+
+    /// Do not preserve symlink modification times (with --times)
+    #[arg(short = 'J', long)]
+    pub omit_link_times: bool,
+
+    /// Rewrite symlink targets starting with FROM to start with TO
+    ///
+    /// For relocated trees: with --symlink-rewrite=/data/old:/data/new a link
+    /// to /data/old/lib becomes a link to /data/new/lib. May be repeated; the
+    /// first rule whose FROM matches the target's leading components wins.
+    /// The rewritten links, and those whose new target does not exist at the
+    /// end of the run, are reported.
+    #[arg(long, value_name = "FROM:TO", value_parser = RewriteRule::parse)]
+    pub symlink_rewrite: Vec<RewriteRule>,
+
+    /// Preserve inode flags set with chattr (immutable, append-only, nodump, ...)
+    ///
+    /// The source's `FS_IOC_GETFLAGS` flags that make sense on a copy (sync,
+    /// immutable, append-only, nodump, noatime, dirsync) are set on each
+    /// copied file once it is written. Setting immutable or append-only
+    /// needs `CAP_LINUX_IMMUTABLE`.
+    #[arg(long)]
+    pub preserve_flags: bool,
+
+    /// Rewrite immutable and append-only destination files (with --preserve-flags)
+    ///
+    /// Such files cannot be overwritten in place, even by root, so copying
+    /// over one fails. With this option their flags are cleared, the file is
+    /// updated, and the source's flags are set again; if the copy fails the
+    /// destination's own flags are put back.
+    #[arg(long)]
+    pub update_immutable: bool,
+
+    /// Copy an overlayfs layer (upper directory), keeping its markers
+    ///
+    /// Whiteouts (0:0 character devices, which hide a lower-layer entry) are
+    /// recreated even without --devices or root, and directories keep their
+    /// overlayfs xattrs (`trusted.overlay.*`, `user.overlay.*`: opaque,
+    /// redirect) even without -X; stale ones on the destination are removed.
+    /// The `trusted.*` markers need root to read and write.
+    #[arg(long)]
+    pub overlay_layer: bool,
+
     // Deprecated flags (hidden, for backwards compatibility)
     /// Preserve extended attributes (deprecated: use -X/--xattrs)
     #[arg(long, hide = true)]
@@ -108,6 +270,32 @@ pub struct MetadataConfig {
     /// Preserve POSIX ACLs (deprecated: use -A/--acls)
     #[arg(long, hide = true)]
     pub preserve_acl: bool,
+
+    /// State of the run this config is used by (see `run_state`), not an option
+    #[arg(skip)]
+    pub run: std::sync::Arc<crate::run_state::RunState>,
+}
+
+/// Handling of ownership when the process may not change it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum UnprivilegedOwnership {
+    /// Set only the groups the user belongs to
+    #[default]
+    Permitted,
+    /// Try every change, failing the entries that are refused
+    Attempt,
+    /// Refuse to start
+    Fail,
+}
+
+/// Handling of special files (fifos, sockets, devices) that are not copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SpecialFilePolicy {
+    /// Leave them out of the destination, warning about each
+    #[default]
+    Skip,
+    /// Create an empty regular file at their destination path
+    Placeholder,
 }
 
 impl MetadataConfig {
@@ -120,7 +308,49 @@ impl MetadataConfig {
     /// Check if ownership (user and/or group) should be preserved
     #[must_use]
     pub const fn should_preserve_ownership(&self) -> bool {
-        self.owner || self.group || self.archive
+        self.should_preserve_owner() || self.should_preserve_group()
+    }
+
+    /// Check if the owner should be preserved (not `--no-owner`)
+    #[must_use]
+    pub const fn should_preserve_owner(&self) -> bool {
+        (self.owner || self.archive) && !self.no_owner
+    }
+
+    /// Check if the group should be preserved (not `--no-group`)
+    #[must_use]
+    pub const fn should_preserve_group(&self) -> bool {
+        (self.group || self.archive) && !self.no_group
+    }
+
+    /// IDs to `chown` a copy of an entry owned by `uid`:`gid` to, or `None`
+    /// if its ownership is left alone
+    ///
+    /// An ID that is not preserved is `u32::MAX` (-1, unchanged). Without
+    /// `CAP_CHOWN` and with `--unprivileged-ownership=permitted`, the owner is
+    /// left alone and the group only set if the process belongs to it.
+    #[must_use]
+    pub fn ownership(&self, uid: u32, gid: u32) -> Option<(u32, u32)> {
+        let mut uid = if self.should_preserve_owner() {
+            uid
+        } else {
+            UNCHANGED_ID
+        };
+        let mut gid = if self.should_preserve_group() {
+            gid
+        } else {
+            UNCHANGED_ID
+        };
+        if self.unprivileged_ownership == UnprivilegedOwnership::Permitted {
+            let privileges = Privileges::current();
+            if !privileges.chown {
+                uid = UNCHANGED_ID;
+                if !privileges.in_group(gid) {
+                    gid = UNCHANGED_ID;
+                }
+            }
+        }
+        (uid != UNCHANGED_ID || gid != UNCHANGED_ID).then_some((uid, gid))
     }
 
     /// Check if timestamps should be preserved
@@ -129,12 +359,42 @@ impl MetadataConfig {
         self.times || self.archive
     }
 
+    /// Check if directory timestamps should be preserved (not `--omit-dir-times`)
+    #[must_use]
+    pub const fn should_preserve_dir_timestamps(&self) -> bool {
+        self.should_preserve_timestamps() && !self.omit_dir_times
+    }
+
+    /// Check if symlink timestamps should be preserved (not `--omit-link-times`)
+    #[must_use]
+    pub const fn should_preserve_link_timestamps(&self) -> bool {
+        self.should_preserve_timestamps() && !self.omit_link_times
+    }
+
     /// Check if extended attributes should be preserved
     #[must_use]
     pub const fn should_preserve_xattrs(&self) -> bool {
         self.xattrs || self.preserve_xattr
     }
 
+    /// Check if POSIX ACLs should be preserved
+    #[must_use]
+    pub const fn should_preserve_acls(&self) -> bool {
+        self.acls || self.preserve_acl
+    }
+
+    /// Check if block and character devices should be recreated
+    #[must_use]
+    pub const fn should_preserve_devices(&self) -> bool {
+        self.devices || self.devices_and_specials || self.archive
+    }
+
+    /// Check if fifos and sockets should be recreated
+    #[must_use]
+    pub const fn should_preserve_specials(&self) -> bool {
+        self.specials || self.devices_and_specials || self.archive
+    }
+
     /// Check if symlinks should be copied as symlinks
     #[must_use]
     pub const fn should_preserve_links(&self) -> bool {
@@ -147,8 +407,31 @@ impl MetadataConfig {
         self.hard_links
     }
 
+    /// Fields the copy path needs from `statx` under this configuration
+    ///
+    /// Type, size, inode and link count are always needed (dispatch, copying,
+    /// hardlink detection); mode, ownership and times only when they are
+    /// preserved. Both times are requested with --times because they are set
+    /// together.
+    #[must_use]
+    pub fn statx_mask(&self) -> StatxMask {
+        let mut mask = StatxMask::TYPE | StatxMask::SIZE | StatxMask::INO | StatxMask::NLINK;
+        if self.should_preserve_permissions() {
+            mask |= StatxMask::MODE;
+        }
+        if self.should_preserve_ownership() {
+            mask |= StatxMask::UID | StatxMask::GID;
+        }
+        if self.should_preserve_timestamps() || self.atimes {
+            mask |= StatxMask::ATIME | StatxMask::MTIME;
+        }
+        if self.crtimes {
+            mask |= StatxMask::BTIME;
+        }
+        mask
+    }
+
     /// Check if recursive copying should be performed
-    #[allow(dead_code)] // Public API for future use
     #[must_use]
     pub const fn should_recurse(&self) -> bool {
         self.recursive || self.archive
@@ -191,13 +474,18 @@ pub async fn preserve_file_metadata(
     }
 
     if config.should_preserve_ownership() {
-        preserve_ownership_from_fd(src_file, dst_file).await?;
+        preserve_ownership_from_fd(src_file, dst_file, config).await?;
     }
 
     if config.should_preserve_xattrs() {
         preserve_xattr_from_fd(src_file, dst_file).await?;
     }
 
+    // Access ACL after fchmod, which would otherwise rewrite its mask entry
+    if config.should_preserve_acls() {
+        copy_acl_fd(src_file.as_raw_fd(), dst_file.as_raw_fd(), AclKind::Access)?;
+    }
+
     if config.should_preserve_timestamps() {
         preserve_timestamps_from_fd(dst_file, src_accessed, src_modified).await?;
     }
@@ -241,7 +529,8 @@ pub async fn preserve_permissions_from_fd(
 
 /// Preserve file ownership using file descriptors
 ///
-/// Uses fchown (file descriptor-based) to avoid TOCTOU race conditions.
+/// Uses fchown (file descriptor-based) to avoid TOCTOU race conditions. Only
+/// the IDs [`MetadataConfig::ownership`] selects are changed.
 ///
 /// # Errors
 ///
@@ -250,12 +539,22 @@ pub async fn preserve_permissions_from_fd(
 pub async fn preserve_ownership_from_fd(
     src_file: &compio::fs::File,
     dst_file: &compio::fs::File,
+    config: &MetadataConfig,
 ) -> Result<()> {
     use compio_fs_extended::OwnershipOps;
+    use std::os::unix::fs::MetadataExt;
+
+    let src_metadata = src_file
+        .metadata()
+        .await
+        .map_err(|e| SyncError::FileSystem(format!("Failed to get source file metadata: {e}")))?;
+    let Some((uid, gid)) = config.ownership(src_metadata.uid(), src_metadata.gid()) else {
+        return Ok(());
+    };
 
     // Use compio-fs-extended for ownership preservation (fchown internally)
     dst_file
-        .preserve_ownership_from(src_file)
+        .fchown(uid, gid)
         .await
         .map_err(|e| SyncError::FileSystem(format!("Failed to preserve file ownership: {e}")))?;
     Ok(())
@@ -292,8 +591,12 @@ pub async fn preserve_xattr_from_fd(
         return Ok(());
     };
 
-    // Copy each extended attribute
-    for name in xattr_names {
+    // Copy each extended attribute; ACLs are copied by --acls, after
+    // permissions (see `copy_acl_fd`)
+    for name in xattr_names
+        .into_iter()
+        .filter(|name| !compio_fs_extended::acl::is_acl_xattr(name))
+    {
         match extended_src.get_xattr(&name).await {
             Ok(value) => {
                 if let Err(e) = extended_dst.set_xattr(&name, &value).await {
@@ -328,6 +631,31 @@ pub async fn preserve_timestamps_from_fd(
         .map_err(|e| SyncError::FileSystem(format!("Failed to preserve timestamps via FD: {e}")))
 }
 
+// ============================================================================
+// POSIX ACL PRESERVATION
+// ============================================================================
+
+pub use compio_fs_extended::acl::AclKind;
+
+/// Copy one POSIX ACL between file descriptors
+///
+/// See `compio_fs_extended::acl::copy_acl_fd`: a source without the ACL
+/// removes any the destination inherited, and filesystems without ACL
+/// support are treated as having no ACLs.
+///
+/// # Errors
+///
+/// Returns error if the ACL cannot be read from the source or written to the
+/// destination.
+pub fn copy_acl_fd(
+    src_fd: std::os::unix::io::RawFd,
+    dst_fd: std::os::unix::io::RawFd,
+    kind: AclKind,
+) -> Result<()> {
+    compio_fs_extended::acl::copy_acl_fd(src_fd, dst_fd, kind)
+        .map_err(|e| SyncError::FileSystem(format!("Failed to preserve ACL: {e}")))
+}
+
 /// Get precise timestamps from a file path
 ///
 /// Uses statx when available (nanosecond precision), falls back to stat.
@@ -366,15 +694,9 @@ pub async fn get_precise_timestamps(path: &Path) -> Result<(SystemTime, SystemTi
                 )
             };
             if rc == 0 {
-                // Use stx_atime and stx_mtime with nanoseconds
-                let atime_secs = u64::try_from(buf.stx_atime.tv_sec).unwrap_or(0);
-                let atime_nanos = buf.stx_atime.tv_nsec;
-                let mtime_secs = u64::try_from(buf.stx_mtime.tv_sec).unwrap_or(0);
-                let mtime_nanos = buf.stx_mtime.tv_nsec;
-                let atime =
-                    SystemTime::UNIX_EPOCH + std::time::Duration::new(atime_secs, atime_nanos);
-                let mtime =
-                    SystemTime::UNIX_EPOCH + std::time::Duration::new(mtime_secs, mtime_nanos);
+                // Use stx_atime and stx_mtime with nanoseconds (signed)
+                let atime = Timestamp::from_statx(&buf.stx_atime).to_system_time();
+                let mtime = Timestamp::from_statx(&buf.stx_mtime).to_system_time();
                 Ok((atime, mtime))
             } else {
                 let errno = std::io::Error::last_os_error();
@@ -410,15 +732,19 @@ pub async fn get_precise_timestamps(path: &Path) -> Result<(SystemTime, SystemTi
                 errno.raw_os_error().unwrap_or(-1)
             )))
         } else {
-            // Convert timespec to SystemTime
-            let accessed_nanos: u32 = u32::try_from(stat_buf.st_atime_nsec).unwrap_or(0);
-            let modified_nanos: u32 = u32::try_from(stat_buf.st_mtime_nsec).unwrap_or(0);
-            #[allow(clippy::cast_sign_loss)]
-            let accessed = SystemTime::UNIX_EPOCH
-                + std::time::Duration::new(stat_buf.st_atime as u64, accessed_nanos);
-            #[allow(clippy::cast_sign_loss)]
-            let modified = SystemTime::UNIX_EPOCH
-                + std::time::Duration::new(stat_buf.st_mtime as u64, modified_nanos);
+            // Convert the (signed) timespecs to SystemTime
+            #[allow(clippy::useless_conversion)] // time_t and c_long are i64 on 64-bit
+            let accessed = Timestamp::new(
+                i64::from(stat_buf.st_atime),
+                i64::from(stat_buf.st_atime_nsec),
+            )
+            .to_system_time();
+            #[allow(clippy::useless_conversion)]
+            let modified = Timestamp::new(
+                i64::from(stat_buf.st_mtime),
+                i64::from(stat_buf.st_mtime_nsec),
+            )
+            .to_system_time();
             Ok((accessed, modified))
         }
     })
@@ -499,8 +825,19 @@ mod tests {
             times: false,
             group: false,
             owner: false,
+            no_owner: false,
+            no_group: false,
             devices: false,
+            specials: false,
+            devices_and_specials: false,
             fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
             xattrs: false,
             acls: false,
             hard_links: false,
@@ -508,6 +845,14 @@ mod tests {
             crtimes: false,
             preserve_xattr: false,
             preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
         };
 
         // Nothing should be preserved
@@ -527,8 +872,19 @@ mod tests {
             times: false,
             group: false,
             owner: false,
+            no_owner: false,
+            no_group: false,
             devices: false,
+            specials: false,
+            devices_and_specials: false,
             fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
             xattrs: false,
             acls: false,
             hard_links: false,
@@ -536,6 +892,14 @@ mod tests {
             crtimes: false,
             preserve_xattr: false,
             preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
         };
 
         // Archive enables most things
@@ -544,4 +908,111 @@ mod tests {
         assert!(config.should_preserve_timestamps());
         assert!(config.should_preserve_links());
     }
+
+    #[test]
+    fn test_omit_dir_and_link_times() {
+        let mut config = MetadataConfig {
+            archive: true,
+            recursive: false,
+            links: false,
+            perms: false,
+            times: false,
+            group: false,
+            owner: false,
+            no_owner: false,
+            no_group: false,
+            devices: false,
+            specials: false,
+            devices_and_specials: false,
+            fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
+            xattrs: false,
+            acls: false,
+            hard_links: false,
+            atimes: false,
+            crtimes: false,
+            preserve_xattr: false,
+            preserve_acl: false,
+            omit_dir_times: true,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
+        };
+
+        // File times stay preserved; only directory times are omitted
+        assert!(config.should_preserve_timestamps());
+        assert!(!config.should_preserve_dir_timestamps());
+        assert!(config.should_preserve_link_timestamps());
+
+        config.omit_dir_times = false;
+        config.omit_link_times = true;
+        assert!(config.should_preserve_dir_timestamps());
+        assert!(!config.should_preserve_link_timestamps());
+    }
+
+    #[test]
+    fn test_statx_mask_follows_metadata_flags() {
+        let mut config = MetadataConfig {
+            archive: false,
+            recursive: false,
+            links: false,
+            perms: false,
+            times: false,
+            group: false,
+            owner: false,
+            no_owner: false,
+            no_group: false,
+            devices: false,
+            specials: false,
+            devices_and_specials: false,
+            fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
+            xattrs: false,
+            acls: false,
+            hard_links: false,
+            atimes: false,
+            crtimes: false,
+            preserve_xattr: false,
+            preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
+        };
+
+        // --no-perms --no-times: only what copying itself needs
+        let minimal = config.statx_mask();
+        assert!(minimal.contains(StatxMask::TYPE | StatxMask::SIZE | StatxMask::NLINK));
+        assert!(!minimal.contains(StatxMask::MODE));
+        assert!(!minimal.contains(StatxMask::MTIME));
+        assert!(!minimal.contains(StatxMask::UID));
+
+        config.archive = true;
+        config.crtimes = true;
+        // Everything except CTIME and BLOCKS, which are never used
+        assert_eq!(
+            config.statx_mask() | StatxMask::CTIME | StatxMask::BLOCKS,
+            StatxMask::BASIC_STATS | StatxMask::BTIME
+        );
+    }
 }
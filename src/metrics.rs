@@ -0,0 +1,461 @@
+//! Prometheus metrics of a run (`--metrics-listen`, `--metrics-textfile`)
+//!
+//! The copy path counts its work in the run's [`Metrics`] (`RunState::metrics`)
+//! as it goes:
+//!
+//! - reads and writes of file data submitted to `io_uring`, and how many are
+//!   in flight at once (the queue depth actually reached, next to the
+//!   configured `--queue-depth`);
+//! - bytes written and files started, copied and failed, with their average
+//!   rates since the start of the run;
+//! - operations retried after an NFS directory handle went stale.
+//!
+//! With `--metrics-listen ADDR` they are served in the Prometheus text format
+//! on `http://ADDR/metrics` for as long as the run lasts. With
+//! `--metrics-textfile FILE` they are written to FILE when the run ends,
+//! together with whether it succeeded, for node_exporter's textfile collector
+//! (point it at FILE's directory and name FILE `*.prom`). The file is written
+//! under a temporary name and renamed, so the collector never reads half of
+//! it.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read as _, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Header lines read from a scrape request before it is answered anyway
+const MAX_REQUEST_LINES: usize = 100;
+
+/// Bytes read from a scrape request before it is answered anyway, so a
+/// client cannot grow the request line without end
+const MAX_REQUEST_LEN: u64 = 16 * 1024;
+
+/// How long a scraper may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name, type and help of the unlabelled metrics, in the order `render`
+/// gives their values
+const FAMILIES: [(&str, &str, &str); 11] = [
+    (
+        "arsync_io_in_flight",
+        "gauge",
+        "Reads and writes submitted and not yet completed",
+    ),
+    (
+        "arsync_io_in_flight_max",
+        "gauge",
+        "Most reads and writes in flight at once",
+    ),
+    (
+        "arsync_io_queue_depth",
+        "gauge",
+        "Configured io_uring queue depth (--queue-depth)",
+    ),
+    (
+        "arsync_bytes_copied_total",
+        "counter",
+        "Bytes of file data written",
+    ),
+    (
+        "arsync_files_copied_total",
+        "counter",
+        "Files whose contents were copied",
+    ),
+    (
+        "arsync_files_failed_total",
+        "counter",
+        "Files whose copy failed",
+    ),
+    ("arsync_files_in_flight", "gauge", "Files being copied"),
+    (
+        "arsync_files_per_second",
+        "gauge",
+        "Files copied per second since the start of the run",
+    ),
+    (
+        "arsync_bytes_per_second",
+        "gauge",
+        "Bytes written per second since the start of the run",
+    ),
+    (
+        "arsync_retries_total",
+        "counter",
+        "Operations retried after a stale NFS directory handle",
+    ),
+    (
+        "arsync_elapsed_seconds",
+        "gauge",
+        "Time since the start of the run",
+    ),
+];
+
+/// File data operation submitted to `io_uring`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOp {
+    /// Read from the source
+    Read,
+    /// Write to the destination
+    Write,
+}
+
+/// Counters and gauges of the run
+#[derive(Debug)]
+pub struct Metrics {
+    /// Start of the run, for rates
+    started: OnceLock<Instant>,
+    /// Reads of file data submitted
+    reads_submitted: AtomicU64,
+    /// Writes of file data submitted
+    writes_submitted: AtomicU64,
+    /// Reads and writes submitted and not completed
+    in_flight: AtomicU64,
+    /// Most reads and writes in flight at once
+    in_flight_max: AtomicU64,
+    /// Configured `--queue-depth`
+    queue_depth: AtomicU64,
+    /// Bytes of file data written
+    bytes_copied: AtomicU64,
+    /// Files whose contents started being copied
+    files_started: AtomicU64,
+    /// Files whose contents were copied
+    files_copied: AtomicU64,
+    /// Files whose copy failed
+    files_failed: AtomicU64,
+    /// Operations retried
+    retries: AtomicU64,
+}
+
+/// A read or write in flight, counted until dropped
+#[derive(Debug)]
+#[must_use]
+pub struct InFlight<'a>(&'a AtomicU64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Create metrics with everything at zero
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            started: OnceLock::new(),
+            reads_submitted: AtomicU64::new(0),
+            writes_submitted: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            in_flight_max: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            bytes_copied: AtomicU64::new(0),
+            files_started: AtomicU64::new(0),
+            files_copied: AtomicU64::new(0),
+            files_failed: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    /// Start the clock of the rates (the first call wins)
+    pub fn start(&self) {
+        self.started.get_or_init(Instant::now);
+    }
+
+    /// Record the configured `--queue-depth`
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Count an `op` being submitted; it is in flight until the guard drops
+    pub fn submit(&self, op: IoOp) -> InFlight<'_> {
+        match op {
+            IoOp::Read => &self.reads_submitted,
+            IoOp::Write => &self.writes_submitted,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.in_flight_max.fetch_max(in_flight, Ordering::Relaxed);
+        InFlight(&self.in_flight)
+    }
+
+    /// Count a file whose contents started being copied
+    pub fn file_started(&self) {
+        self.files_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count `bytes` of file data written
+    pub fn copied(&self, bytes: u64) {
+        self.bytes_copied.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a file whose contents were copied
+    pub fn file_done(&self) {
+        self.files_copied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a file whose copy failed
+    pub fn file_failed(&self) {
+        self.files_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an operation being retried
+    pub fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text format
+    #[must_use]
+    pub fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let elapsed = self
+            .started
+            .get()
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        #[allow(clippy::cast_precision_loss)] // Rates need no more precision
+        let rate = |count: u64| {
+            if elapsed > 0.0 {
+                count as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+        let (files_copied, bytes_copied) = (load(&self.files_copied), load(&self.bytes_copied));
+        let files_in_flight = load(&self.files_started)
+            .saturating_sub(files_copied)
+            .saturating_sub(load(&self.files_failed));
+
+        let mut out = String::new();
+        let submissions = "arsync_io_uring_submissions_total";
+        family(
+            &mut out,
+            submissions,
+            "counter",
+            "Reads and writes of file data submitted to io_uring",
+        );
+        for (op, counter) in [
+            ("read", &self.reads_submitted),
+            ("write", &self.writes_submitted),
+        ] {
+            let _ = writeln!(out, "{submissions}{{op=\"{op}\"}} {}", load(counter));
+        }
+        let values = [
+            load(&self.in_flight).to_string(),
+            load(&self.in_flight_max).to_string(),
+            load(&self.queue_depth).to_string(),
+            bytes_copied.to_string(),
+            files_copied.to_string(),
+            load(&self.files_failed).to_string(),
+            files_in_flight.to_string(),
+            rate(files_copied).to_string(),
+            rate(bytes_copied).to_string(),
+            load(&self.retries).to_string(),
+            elapsed.to_string(),
+        ];
+        for ((name, kind, help), value) in FAMILIES.iter().zip(values) {
+            sample(&mut out, name, kind, help, value);
+        }
+        out
+    }
+
+    /// Write the metrics of the finished run to `path`, for node_exporter's
+    /// textfile collector
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written or renamed into place.
+    pub fn write_textfile(&self, path: &Path, succeeded: bool) -> std::io::Result<()> {
+        let mut out = self.render();
+        sample(
+            &mut out,
+            "arsync_last_run_success",
+            "gauge",
+            "Whether the run completed without errors",
+            u8::from(succeeded),
+        );
+        sample(
+            &mut out,
+            "arsync_last_run_timestamp_seconds",
+            "gauge",
+            "When the run ended",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        std::fs::write(&temp, out)?;
+        std::fs::rename(&temp, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })
+    }
+}
+
+/// Write the `HELP` and `TYPE` lines of metric `name`
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Write metric `name` with its one unlabelled `value`
+fn sample(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    family(out, name, kind, help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Serve `metrics` on `http://addr/metrics` from a background thread
+///
+/// Returns the address listened on (with the port chosen when `addr`'s is 0).
+///
+/// # Errors
+///
+/// Returns an error if `addr` cannot be listened on.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    std::thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            // One scrape at a time: a scraper is rarely more than one client
+            for stream in listener.incoming().flatten() {
+                let _ = respond(stream, &metrics);
+            }
+        })?;
+    Ok(local)
+}
+
+/// Answer one HTTP request: the metrics for `GET /metrics`, 404 otherwise
+fn respond(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_LEN));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Read the headers, so closing does not reset the connection under the reply
+    let mut header = String::new();
+    for _ in 0..MAX_REQUEST_LINES {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut words = request_line.split_whitespace();
+    let (method, target) = (words.next(), words.next());
+    let path = target.map(|target| target.split('?').next().unwrap_or_default());
+    let (status, body) = match (method, path) {
+        (Some("GET" | "HEAD"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Metrics are at /metrics\n".to_string()),
+    };
+    let mut reply = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if method != Some("HEAD") {
+        reply.push_str(&body);
+    }
+    (&stream).write_all(reply.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_render_counts_and_queue_depth() {
+        // Requirement: Submissions are counted per operation, the in-flight
+        // gauge falls as operations complete but keeps its maximum, and files
+        // in flight are those neither copied nor failed
+        let metrics = Metrics::new();
+        metrics.set_queue_depth(4096);
+        let read = metrics.submit(IoOp::Read);
+        let write = metrics.submit(IoOp::Write);
+        drop(read);
+        metrics.file_started();
+        metrics.file_started();
+        metrics.copied(1000);
+        metrics.file_done();
+        metrics.retried();
+
+        let text = metrics.render();
+        for line in [
+            "# TYPE arsync_io_uring_submissions_total counter",
+            "arsync_io_uring_submissions_total{op=\"read\"} 1",
+            "arsync_io_uring_submissions_total{op=\"write\"} 1",
+            "arsync_io_in_flight 1",
+            "arsync_io_in_flight_max 2",
+            "arsync_io_queue_depth 4096",
+            "arsync_bytes_copied_total 1000",
+            "arsync_files_copied_total 1",
+            "arsync_files_in_flight 1",
+            "arsync_retries_total 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {line:?} in\n{text}"
+            );
+        }
+        drop(write);
+        assert!(metrics
+            .render()
+            .lines()
+            .any(|l| l == "arsync_io_in_flight 0"));
+    }
+
+    #[test]
+    fn test_textfile_is_complete_with_outcome() {
+        // Requirement: The textfile has the metrics and the run's outcome,
+        // and no temporary file is left next to it
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("arsync.prom");
+        Metrics::new().write_textfile(&path, false).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# TYPE arsync_bytes_copied_total counter\n"));
+        assert!(text.contains("\narsync_last_run_success 0\n"));
+        assert!(text.contains("\narsync_last_run_timestamp_seconds "));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_serve_answers_scrapes() {
+        // Requirement: GET /metrics returns the metrics; other paths are 404
+        let addr = serve("127.0.0.1:0".parse().unwrap(), Arc::default()).unwrap();
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        };
+        let reply = get("/metrics");
+        assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(reply.contains("\r\n\r\n# HELP arsync_io_uring_submissions_total "));
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_serve_bounds_the_request() {
+        // Requirement: A request line that never ends is answered once
+        // MAX_REQUEST_LEN bytes are read, not buffered without bound
+        let addr = serve("127.0.0.1:0".parse().unwrap(), Arc::default()).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        #[allow(clippy::cast_possible_truncation)] // A small constant
+        let line = vec![b'A'; MAX_REQUEST_LEN as usize];
+        stream.write_all(&line).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 404 Not Found\r\n"), "{reply}");
+    }
+}
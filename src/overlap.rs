@@ -0,0 +1,246 @@
+//! Refusing to copy a tree into itself
+//!
+//! `arsync /data /data/backup` copies `backup` into itself and keeps finding
+//! new entries to copy as it goes. Before a directory copy, the destination
+//! and its ancestors are compared with the source by device and inode, which
+//! also catches a destination reached through a symlink or through a bind
+//! mount of the source. An overlapping copy is refused unless
+//! `--allow-overlap` is given and an exclude rule keeps the traversal out of
+//! the destination; a destination that is the source is always refused.
+//!
+//! The other way around, `arsync --delete /data/photos /data` would delete
+//! everything in `/data` the photos do not have, the source among it. A
+//! source inside the destination is compared the same way and refused with
+//! any `--delete*`.
+
+use crate::error::{Result, SyncError};
+use crate::filter::FilterRules;
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How the destination overlaps the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overlap {
+    /// The destination is the source
+    Same,
+    /// The destination is inside the source, at this path relative to it
+    Inside(PathBuf),
+    /// The source is inside the destination, at this path relative to it
+    Encloses(PathBuf),
+}
+
+/// How `destination` overlaps the directory `source`, if it does
+///
+/// `destination` need not exist yet: its nearest existing ancestor is
+/// checked, and the missing components are added to the relative path.
+#[must_use]
+pub fn find(source: &Path, destination: &Path) -> Option<Overlap> {
+    destination_inside(source, destination).or_else(|| source_inside(source, destination))
+}
+
+/// `Same` or `Inside` if `destination` is the source or inside it
+fn destination_inside(source: &Path, destination: &Path) -> Option<Overlap> {
+    let source_id = file_id(source)?;
+    let mut missing: Vec<&OsStr> = Vec::new();
+    let mut existing = destination;
+    // Symlinks are resolved, so the ancestors below are the physical ones
+    let resolved = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(_) => {
+                missing.push(existing.file_name()?);
+                existing = match existing.parent()? {
+                    parent if parent.as_os_str().is_empty() => Path::new("."),
+                    parent => parent,
+                };
+            }
+        }
+    };
+
+    // A bind mount of the source has the source's device and inode
+    let inside = resolved
+        .ancestors()
+        .find(|ancestor| file_id(ancestor) == Some(source_id))?;
+    let mut relative = resolved.strip_prefix(inside).ok()?.to_path_buf();
+    relative.extend(missing.iter().rev());
+    Some(if relative.as_os_str().is_empty() {
+        Overlap::Same
+    } else {
+        Overlap::Inside(relative)
+    })
+}
+
+/// `Encloses` if `source` is inside the existing directory `destination`
+fn source_inside(source: &Path, destination: &Path) -> Option<Overlap> {
+    let destination_id = file_id(destination)?;
+    let source = std::fs::canonicalize(source).ok()?;
+    let enclosing = source
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| file_id(ancestor) == Some(destination_id))?;
+    Some(Overlap::Encloses(
+        source.strip_prefix(enclosing).ok()?.to_path_buf(),
+    ))
+}
+
+/// Refuse to copy `source` into `destination` if they overlap, unless
+/// `allow_overlap` and `rules` exclude the destination; a source inside the
+/// destination is only refused if the copy `deletes`
+///
+/// # Errors
+///
+/// Returns `SyncError::InvalidConfig` explaining the overlap and how to
+/// avoid it.
+pub fn check(
+    source: &Path,
+    destination: &Path,
+    allow_overlap: bool,
+    rules: &FilterRules,
+    deletes: bool,
+) -> Result<()> {
+    let invalid = |message: String| Err(SyncError::InvalidConfig(message));
+    match find(source, destination) {
+        None => Ok(()),
+        Some(Overlap::Same) => invalid(format!(
+            "Destination {} is the source directory {}",
+            destination.display(),
+            source.display()
+        )),
+        Some(Overlap::Inside(relative)) if !allow_overlap => invalid(format!(
+            "Destination {} is inside the source {} (at {}), so the copy would copy into \
+             itself; choose a destination outside the source, or pass --allow-overlap with \
+             an exclude covering it (e.g. --exclude /{}/)",
+            destination.display(),
+            source.display(),
+            relative.display(),
+            relative.display()
+        )),
+        Some(Overlap::Inside(relative)) if !is_excluded(rules, &relative) => invalid(format!(
+            "--allow-overlap needs an exclude rule covering the destination {} (at {} in \
+             the source), e.g. --exclude /{}/",
+            destination.display(),
+            relative.display(),
+            relative.display()
+        )),
+        Some(Overlap::Inside(_)) => Ok(()),
+        Some(Overlap::Encloses(relative)) if deletes => invalid(format!(
+            "Source {} is inside the destination {} (at {}), so --delete would delete \
+             the source itself from the destination; choose a destination outside it, or \
+             copy without --delete",
+            source.display(),
+            destination.display(),
+            relative.display()
+        )),
+        Some(Overlap::Encloses(_)) => Ok(()),
+    }
+}
+
+/// Whether `rules` exclude the directory `relative` or one above it, so the
+/// traversal never enters it
+fn is_excluded(rules: &FilterRules, relative: &Path) -> bool {
+    let mut prefix = PathBuf::new();
+    relative.components().any(|component| {
+        prefix.push(component);
+        rules.is_excluded(&prefix, true)
+    })
+}
+
+/// Device and inode of `path` (following symlinks)
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    std::fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::cli::{FilterConfig, FilterOption};
+    use tempfile::TempDir;
+
+    fn rules(excludes: &[&str]) -> FilterRules {
+        let config = FilterConfig {
+            rules: excludes
+                .iter()
+                .map(|pattern| FilterOption::Exclude((*pattern).to_string()))
+                .collect(),
+        };
+        FilterRules::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_find_overlap() {
+        // Requirement: A destination inside the source is found whether or
+        // not it exists yet, and through symlinks; siblings do not overlap
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("data");
+        std::fs::create_dir_all(source.join("existing")).unwrap();
+        std::os::unix::fs::symlink(&source, temp.path().join("alias")).unwrap();
+
+        assert_eq!(find(&source, &source), Some(Overlap::Same));
+        assert_eq!(
+            find(&source, &source.join("existing")),
+            Some(Overlap::Inside(PathBuf::from("existing")))
+        );
+        assert_eq!(
+            find(&source, &source.join("backup/daily")),
+            Some(Overlap::Inside(PathBuf::from("backup/daily")))
+        );
+        assert_eq!(
+            find(&source, &temp.path().join("alias/backup")),
+            Some(Overlap::Inside(PathBuf::from("backup")))
+        );
+        assert_eq!(find(&source, &temp.path().join("data-backup")), None);
+        assert_eq!(
+            find(&source.join("existing"), &source),
+            Some(Overlap::Encloses(PathBuf::from("existing")))
+        );
+        assert_eq!(
+            find(&source.join("existing"), &temp.path().join("alias")),
+            Some(Overlap::Encloses(PathBuf::from("existing")))
+        );
+        assert_eq!(find(&source, &temp.path().join("data-backup/data")), None);
+    }
+
+    #[test]
+    fn test_overlap_needs_allow_and_exclude() {
+        // Requirement: An overlap is refused unless --allow-overlap is given
+        // and an exclude covers the destination (or a directory above it);
+        // the source itself is always refused
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("data");
+        std::fs::create_dir(&source).unwrap();
+        let destination = source.join("backup/daily");
+
+        let none = rules(&[]);
+        assert!(check(&source, &destination, false, &none, false).is_err());
+        assert!(check(&source, &destination, true, &none, false).is_err());
+        assert!(check(&source, &destination, false, &rules(&["/backup/"]), false).is_err());
+        assert!(check(&source, &destination, true, &rules(&["/backup/"]), false).is_ok());
+        assert!(check(&source, &destination, true, &rules(&["daily"]), false).is_ok());
+        assert!(check(&source, &destination, true, &rules(&["*.tmp"]), false).is_err());
+        assert!(check(&source, &source, true, &rules(&["*"]), false).is_err());
+        assert!(check(&source, &temp.path().join("elsewhere"), false, &none, false).is_ok());
+    }
+
+    #[test]
+    fn test_source_inside_destination_refused_with_delete() {
+        // Requirement: Copying a directory into one that holds it is allowed,
+        // but not with --delete, which would delete the source
+        let temp = TempDir::new().unwrap();
+        let destination = temp.path().join("data");
+        let source = destination.join("photos");
+        std::fs::create_dir_all(&source).unwrap();
+
+        let none = rules(&[]);
+        assert!(check(&source, &destination, false, &none, false).is_ok());
+        let err = check(&source, &destination, false, &none, true)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--delete"), "{err}");
+        assert!(check(&source, &temp.path().join("other"), false, &none, true).is_ok());
+    }
+}
@@ -0,0 +1,124 @@
+//! Copying overlayfs layers (`--overlay-layer`)
+//!
+//! An overlayfs upper directory, such as a container image layer, records
+//! how it changes the layers below it with markers that an ordinary copy
+//! drops or refuses:
+//!
+//! - a whiteout, a character device numbered 0:0, hides the lower entry of
+//!   the same name (a deleted file or directory);
+//! - an opaque directory (`trusted.overlay.opaque`) hides everything the
+//!   lower layers have below it (a directory that was replaced);
+//! - a renamed directory names its lower origin in `trusted.overlay.redirect`.
+//!
+//! Mounts with `userxattr` (rootless containers) use `user.overlay.*`
+//! instead of `trusted.overlay.*`.
+//!
+//! With `--overlay-layer`, whiteouts are recreated with `mknodat`, which Linux
+//! allows without `CAP_MKNOD` for 0:0 since 5.8, so neither `--devices` nor
+//! root is needed. Every directory gets the source's overlay xattrs, and any
+//! the source no longer has are removed, so recopying a layer over an older
+//! replica leaves an exact replica. Other devices and xattrs follow the usual
+//! options.
+
+use crate::error::{ErrorContext, Result};
+use compio::fs::File;
+use compio_fs_extended::{ExtendedFile, FileMetadata, XattrOps};
+use std::ffi::CString;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use tracing::debug;
+
+/// Namespaces of overlayfs's own xattrs
+const MARKER_PREFIXES: [&str; 2] = ["trusted.overlay.", "user.overlay."];
+
+/// Whether `metadata` is that of a whiteout (a 0:0 character device)
+#[must_use]
+pub const fn is_whiteout(metadata: &FileMetadata) -> bool {
+    metadata.mode & libc::S_IFMT == libc::S_IFCHR && metadata.rdev == 0
+}
+
+/// Whether the xattr `name` is an overlayfs marker (opaque, redirect, ...)
+fn is_marker(name: &str) -> bool {
+    MARKER_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Give the directory `dst_dir` exactly the overlay xattrs of `src_path`
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be opened or a marker cannot be
+/// read, set or removed (`trusted.*` ones need root).
+#[allow(clippy::future_not_send)]
+pub async fn copy_directory_markers(
+    src_path: &Path,
+    dst_dir: &File,
+    dst_path: &Path,
+) -> Result<()> {
+    let src_dir = File::open(src_path).await.map_err(|e| {
+        ErrorContext::new("open source directory for overlay xattrs")
+            .source(src_path)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let extended_src = ExtendedFile::from_ref(&src_dir);
+    let extended_dst = ExtendedFile::from_ref(dst_dir);
+    // A filesystem without xattrs has no markers
+    let markers = |names: Vec<String>| -> Vec<String> {
+        names.into_iter().filter(|name| is_marker(name)).collect()
+    };
+    let src_markers = markers(extended_src.list_xattr().await.unwrap_or_default());
+    let dst_markers = markers(extended_dst.list_xattr().await.unwrap_or_default());
+
+    for name in &src_markers {
+        let value = extended_src.get_xattr(name).await.map_err(|e| {
+            ErrorContext::new("fgetxattr overlay marker")
+                .source(src_path)
+                .cause(&format!("{name}: {e}"))
+                .file_system()
+        })?;
+        extended_dst.set_xattr(name, &value).await.map_err(|e| {
+            ErrorContext::new("fsetxattr overlay marker")
+                .destination(dst_path)
+                .cause(&format!("{name}: {e}"))
+                .file_system()
+        })?;
+        debug!("Copied overlay marker {name} to {}", dst_path.display());
+    }
+
+    for name in dst_markers
+        .iter()
+        .filter(|name| !src_markers.contains(name))
+    {
+        let c_name = CString::new(name.as_str()).unwrap_or_default();
+        // SAFETY: the fd is open for the duration and c_name is NUL-terminated
+        if unsafe { libc::fremovexattr(dst_dir.as_raw_fd(), c_name.as_ptr()) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(ErrorContext::new("fremovexattr overlay marker")
+                .destination(dst_path)
+                .cause(&format!("{name}: {e}"))
+                .file_system());
+        }
+        debug!(
+            "Removed stale overlay marker {name} from {}",
+            dst_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay_markers() {
+        // Requirement: Both the privileged and the userxattr namespaces are
+        // overlay markers, and nothing else is
+        assert!(is_marker("trusted.overlay.opaque"));
+        assert!(is_marker("user.overlay.redirect"));
+        assert!(!is_marker("user.overlayish"));
+        assert!(!is_marker("security.selinux"));
+    }
+}
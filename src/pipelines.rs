@@ -0,0 +1,411 @@
+//! Separate small-file and large-file pipelines (`--large-file-threshold-mb`)
+//!
+//! Copying 4 KB files and 40 GB files through the same queue with the same
+//! settings hurts both: a handful of huge files occupy the in-flight slots and
+//! the worker threads' rings for minutes while thousands of small files wait,
+//! and small files pay for buffers sized for streaming. Regular files at least
+//! `--large-file-threshold-mb` large are therefore routed to a large-file
+//! pipeline with:
+//!
+//! - its own worker threads, and so its own `io_uring` rings
+//! - its own in-flight limit (`--large-files-in-flight`)
+//! - its own buffer pool, with buffers of at least 1 MB
+//!
+//! The small-file pipeline is the traversal itself: directories, symlinks and
+//! the remaining files share `--max-files-in-flight` and buffers of the tuning
+//! profile's size. Each pipeline's files, bytes and throughput are reported at
+//! the end of the copy. A threshold of 0 sends every file through the
+//! small-file pipeline.
+//!
+//! Buffer pools belong to a run (see `run_state`), like the tuning profile,
+//! and reach the copy path with its `MetadataConfig`; `sync_files` makes them
+//! with [`BufferPools::new`]. A pool also says how many chunks of one file
+//! are read and written at once ([`chunks_in_flight`]): every 1024 of
+//! `--queue-depth` allow one more, up to 16.
+
+use crate::adaptive_concurrency::{AdaptiveConcurrencyController, ConcurrencyOptions};
+use crate::cli::ConcurrencyConfig;
+use crate::error::Result;
+use crate::stats::SharedStats;
+use crate::supervisor::Supervisor;
+use compio::dispatcher::Dispatcher;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Smallest buffer used by the large-file pipeline
+const LARGE_BUFFER_MIN: usize = 1024 * 1024;
+
+/// `--queue-depth` per chunk of a file in flight
+const QUEUE_DEPTH_PER_CHUNK: usize = 1024;
+
+/// Most chunks of one file in flight
+const MAX_CHUNKS_IN_FLIGHT: usize = 16;
+
+/// `--queue-depth` default, for the pools of copies made outside `sync_files`
+const DEFAULT_QUEUE_DEPTH: usize = 4096;
+
+/// Reusable copy buffers of one size
+pub struct BufferPool {
+    /// Length of every buffer handed out
+    size: usize,
+    /// Most buffers kept for reuse
+    keep: usize,
+    /// Chunks of one file copied at once
+    depth: usize,
+    /// Buffers returned and not yet reused
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Pool of `size`-byte buffers keeping up to `keep` of them for reuse
+    #[must_use]
+    pub const fn new(size: usize, keep: usize) -> Self {
+        Self {
+            size,
+            keep,
+            depth: 1,
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The same pool copying `depth` chunks of a file at once (each with
+    /// its own buffer)
+    #[must_use]
+    pub const fn in_flight(mut self, depth: usize) -> Self {
+        self.depth = if depth == 0 { 1 } else { depth };
+        self
+    }
+
+    /// Length of the buffers of this pool
+    #[must_use]
+    pub const fn buffer_size(&self) -> usize {
+        self.size
+    }
+
+    /// Chunks of one file read and written at once
+    #[must_use]
+    pub const fn chunks_in_flight(&self) -> usize {
+        self.depth
+    }
+
+    /// A buffer of `buffer_size()` bytes (contents unspecified)
+    #[must_use]
+    pub fn take(&self) -> Vec<u8> {
+        let reused = self
+            .free
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut buffer = reused.unwrap_or_default();
+        buffer.resize(self.size, 0);
+        buffer
+    }
+
+    /// Return a buffer from `take()` for reuse
+    pub fn put(&self, buffer: Vec<u8>) {
+        if buffer.capacity() < self.size {
+            return;
+        }
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        if free.len() < self.keep {
+            free.push(buffer);
+        }
+    }
+}
+
+/// Buffer pools of both pipelines
+pub struct BufferPools {
+    /// Size from which files use the large-file pool (`None`: never)
+    threshold: Option<u64>,
+    /// Small-file pipeline buffers
+    small: BufferPool,
+    /// Large-file pipeline buffers
+    large: BufferPool,
+}
+
+impl BufferPools {
+    /// Buffer pools for a run with `config`
+    ///
+    /// `buffer_size` is the small-file pipeline's buffer size (from the tuning
+    /// profile or `--buffer-size-kb`), and `queue_depth` sets how many chunks of
+    /// a file are in flight.
+    #[must_use]
+    pub fn new(config: &ConcurrencyConfig, buffer_size: usize, queue_depth: usize) -> Self {
+        let depth = chunks_in_flight(queue_depth);
+        Self {
+            threshold: large_file_threshold(config),
+            small: BufferPool::new(buffer_size, config.max_files_in_flight * depth)
+                .in_flight(depth),
+            large: BufferPool::new(
+                buffer_size.max(LARGE_BUFFER_MIN),
+                config.large_files_in_flight * depth,
+            )
+            .in_flight(depth),
+        }
+    }
+
+    /// Buffer pool for copying a file of `file_size` bytes
+    #[must_use]
+    pub fn pool(&self, file_size: u64) -> &BufferPool {
+        if self
+            .threshold
+            .is_some_and(|threshold| file_size >= threshold)
+        {
+            &self.large
+        } else {
+            &self.small
+        }
+    }
+}
+
+impl Default for BufferPools {
+    /// Pools for copies made outside `sync_files`: buffers of the default
+    /// profile's size for every file, none kept for reuse
+    fn default() -> Self {
+        let depth = chunks_in_flight(DEFAULT_QUEUE_DEPTH);
+        Self {
+            threshold: None,
+            small: BufferPool::new(crate::tuning::TuningProfile::default().buffer_size, 0)
+                .in_flight(depth),
+            large: BufferPool::new(LARGE_BUFFER_MIN, 0).in_flight(depth),
+        }
+    }
+}
+
+/// Chunks of one file in flight for a `--queue-depth` of `queue_depth`
+#[must_use]
+pub fn chunks_in_flight(queue_depth: usize) -> usize {
+    (queue_depth / QUEUE_DEPTH_PER_CHUNK).clamp(1, MAX_CHUNKS_IN_FLIGHT)
+}
+
+/// Threshold in bytes, or `None` when the split is disabled
+fn large_file_threshold(config: &ConcurrencyConfig) -> Option<u64> {
+    (config.large_file_threshold_mb > 0).then(|| config.large_file_threshold_mb * 1024 * 1024)
+}
+
+/// Files and bytes copied by one pipeline, and when
+#[derive(Default)]
+struct PipelineStats {
+    /// Files copied
+    files: AtomicU64,
+    /// Bytes copied
+    bytes: AtomicU64,
+    /// Start of the first and end of the last copy
+    window: Mutex<Option<(Instant, Instant)>>,
+}
+
+impl PipelineStats {
+    /// Record a copy of `bytes` that started at `started` and just finished
+    fn record(&self, started: Instant, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        *window = Some(match *window {
+            Some((first, last)) => (first.min(started), last.max(now)),
+            None => (started, now),
+        });
+    }
+
+    /// What the pipeline did, if it copied anything
+    fn summary(&self) -> Option<PipelineSummary> {
+        let window = *self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        window.map(|(first, last)| PipelineSummary {
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            elapsed: last - first,
+        })
+    }
+}
+
+/// Files, bytes and throughput of one pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineSummary {
+    /// Files copied
+    pub files: u64,
+    /// Bytes copied
+    pub bytes: u64,
+    /// From the start of the first copy to the end of the last
+    pub elapsed: Duration,
+}
+
+impl PipelineSummary {
+    /// Bytes per second over `elapsed`
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // Only for display
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for PipelineSummary {
+    #[allow(clippy::cast_precision_loss)] // Only for display
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {:.1} MB in {:.2}s ({:.1} MB/s)",
+            self.files,
+            self.bytes as f64 / 1_048_576.0,
+            self.elapsed.as_secs_f64(),
+            self.throughput() / 1_048_576.0
+        )
+    }
+}
+
+/// Worker threads and in-flight limit of the large-file pipeline
+pub struct LargeFilePipeline {
+    /// Worker threads, separate from the traversal's
+    supervisor: Supervisor,
+    /// `--large-files-in-flight`
+    permits: AdaptiveConcurrencyController,
+}
+
+impl LargeFilePipeline {
+    /// Worker pool, for parallel chunk copies of large files
+    #[must_use]
+    pub fn dispatcher(&self) -> &'static Dispatcher {
+        self.supervisor.dispatcher()
+    }
+
+    /// Run the copy `task` for the file at `path` once a large-file slot is free
+    ///
+    /// # Errors
+    ///
+    /// Returns the task's error, or an error if it panicked.
+    #[allow(clippy::future_not_send)]
+    pub async fn run<F, Fut>(&self, path: &Path, task: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        let _permit = self.permits.acquire().await;
+        self.supervisor.run(path, task).await
+    }
+}
+
+/// Routing of files to the small-file or large-file pipeline
+pub struct Pipelines {
+    /// Size from which files are large (`None`: split disabled)
+    threshold: Option<u64>,
+    /// Large-file pipeline, if the split is enabled
+    large: Option<LargeFilePipeline>,
+    /// Small-file pipeline counters
+    small_stats: PipelineStats,
+    /// Large-file pipeline counters
+    large_stats: PipelineStats,
+}
+
+impl Pipelines {
+    /// Pipelines for one traversal; `stats` receive errors of panicked copies
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the large-file worker threads cannot be started.
+    pub fn new(config: &ConcurrencyConfig, stats: Arc<SharedStats>) -> Result<Self> {
+        let threshold = large_file_threshold(config);
+        let large = threshold
+            .map(|_| {
+                Ok::<_, crate::error::SyncError>(LargeFilePipeline {
+                    supervisor: Supervisor::new(stats)?,
+                    permits: AdaptiveConcurrencyController::new(&ConcurrencyOptions::new(
+                        config.large_files_in_flight,
+                        config.no_adaptive_concurrency,
+                    )),
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            threshold,
+            large,
+            small_stats: PipelineStats::default(),
+            large_stats: PipelineStats::default(),
+        })
+    }
+
+    /// Large-file pipeline, if a file of `size` bytes belongs in it
+    #[must_use]
+    pub fn large(&self, size: u64) -> Option<&LargeFilePipeline> {
+        self.large.as_ref().filter(|_| self.is_large(size))
+    }
+
+    /// Record a file of `size` bytes copied since `started`
+    pub fn record(&self, size: u64, started: Instant) {
+        let stats = if self.is_large(size) {
+            &self.large_stats
+        } else {
+            &self.small_stats
+        };
+        stats.record(started, size);
+    }
+
+    /// Summaries of the small-file and large-file pipelines
+    #[must_use]
+    pub fn summaries(&self) -> (Option<PipelineSummary>, Option<PipelineSummary>) {
+        (self.small_stats.summary(), self.large_stats.summary())
+    }
+
+    /// Log each pipeline's files and throughput
+    pub fn report(&self) {
+        let (small, large) = self.summaries();
+        if let Some(small) = small {
+            info!("Small-file pipeline: {small}");
+        }
+        if let Some(large) = large {
+            info!("Large-file pipeline: {large}");
+        }
+    }
+
+    fn is_large(&self, size: u64) -> bool {
+        self.threshold.is_some_and(|threshold| size >= threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_chunks_in_flight_from_queue_depth() {
+        // Requirement: Every 1024 of --queue-depth adds a chunk in flight,
+        // from 1 (read, then write) up to 16
+        assert_eq!(chunks_in_flight(1024), 1);
+        assert_eq!(chunks_in_flight(4096), 4);
+        assert_eq!(chunks_in_flight(65_536), MAX_CHUNKS_IN_FLIGHT);
+        assert_eq!(chunks_in_flight(0), 1);
+        assert_eq!(BufferPool::new(4096, 1).chunks_in_flight(), 1);
+        assert_eq!(BufferPool::new(4096, 1).in_flight(0).chunks_in_flight(), 1);
+    }
+
+    #[test]
+    fn test_buffer_pool_reuses_buffers() {
+        let pool = BufferPool::new(4096, 1);
+        let mut buffer = pool.take();
+        assert_eq!(buffer.len(), 4096);
+        buffer.truncate(10);
+        let address = buffer.as_ptr();
+        pool.put(buffer);
+        pool.put(vec![0; 4096]); // Beyond `keep`: dropped
+
+        let again = pool.take();
+        assert_eq!(again.len(), 4096);
+        assert_eq!(again.as_ptr(), address);
+        assert_eq!(pool.take().len(), 4096);
+    }
+
+    #[test]
+    fn test_pipeline_summary_display() {
+        let summary = PipelineSummary {
+            files: 3,
+            bytes: 8 * 1_048_576,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(summary.to_string(), "3 files, 8.0 MB in 2.00s (4.0 MB/s)");
+    }
+}
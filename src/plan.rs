@@ -0,0 +1,763 @@
+//! Copy mechanism plan of a tree (`arsync plan SOURCE DESTINATION...`)
+//!
+//! Before a large migration it matters whether the fast paths will apply:
+//! a reflink or an in-kernel `copy_file_range` moves no data through
+//! userspace, a read/write copy does. `arsync plan` walks SOURCE, groups its
+//! regular files by the device they are on and, for every destination, tries
+//! each mechanism once on a sample of the real data:
+//!
+//! - `reflink`: `FICLONE` of the smallest source file of the device;
+//! - `copy_file_range`, and `splice` through a pipe: the first 64 KB of it.
+//!
+//! The probes write to unnamed (`O_TMPFILE`) files in the destination, or
+//! its nearest existing ancestor, which disappear when closed; filesystems
+//! without `O_TMPFILE` get a named file that is unlinked at once. Nothing
+//! else is written and the source is only read.
+//!
+//! The files and bytes of each device are then assigned the mechanism a copy
+//! with the same options would use, following the copy path's choice:
+//! server-side copy when both ends are on the same kind of network mount,
+//! then `--copy-method` (`auto` uses `copy_file_range` within one filesystem
+//! unless `--sparse`), `splice` where `copy_file_range` is refused, and
+//! read/write when the chosen mechanism is refused.
+//!
+//! The footprint is an estimate: the copy buffers of the files read and
+//! written at once (per pipeline, as `--max-files-in-flight`,
+//! `--large-files-in-flight` and `--queue-depth` allow), and the data that
+//! passes through the page cache, which is everything but reflinks, extents
+//! shared by `copy_file_range` and server-side copies.
+
+use crate::cli::CopyMethod;
+use crate::error::{ErrorContext, Result};
+use crate::offload::MountKind;
+use crate::tuning::{TuneProfile, TuningProfile};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+/// Bytes of the sample copied by the `copy_file_range` and `splice` probes
+const PROBE_BYTES: usize = 64 * 1024;
+
+/// Smallest buffer of the large-file pipeline (as in `pipelines`)
+const LARGE_BUFFER_MIN: u64 = 1024 * 1024;
+
+/// Options of the copy being planned
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    /// `--copy-method`
+    pub copy_method: CopyMethod,
+    /// `--sparse`
+    pub sparse: bool,
+    /// `--queue-depth`
+    pub queue_depth: usize,
+    /// `--buffer-size-kb`, in bytes
+    pub buffer_size: Option<usize>,
+    /// `--tune`
+    pub tune: Option<TuneProfile>,
+    /// `--max-files-in-flight`
+    pub max_files_in_flight: usize,
+    /// `--large-file-threshold-mb`, in bytes (0: no large-file pipeline)
+    pub large_file_threshold: u64,
+    /// `--large-files-in-flight`
+    pub large_files_in_flight: usize,
+}
+
+/// How a file's data is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// The NFS or SMB server copies
+    ServerSide,
+    /// `FICLONE`: the destination shares the source's extents
+    Reflink,
+    /// In-kernel `copy_file_range(2)`
+    CopyFileRange,
+    /// `splice(2)` through a pipe, in the kernel
+    Splice,
+    /// `io_uring` reads and writes through userspace buffers
+    ReadWrite,
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ServerSide => "server-side",
+            Self::Reflink => "reflink",
+            Self::CopyFileRange => "copy_file_range",
+            Self::Splice => "splice",
+            Self::ReadWrite => "read/write",
+        })
+    }
+}
+
+/// Outcome of trying a mechanism on sample data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// It copied the sample
+    Usable,
+    /// It was refused, with the reason
+    Unusable(String),
+}
+
+impl Probe {
+    /// Whether the mechanism copied the sample
+    #[must_use]
+    pub const fn usable(&self) -> bool {
+        matches!(self, Self::Usable)
+    }
+
+    /// The outcome of a syscall returning `ret`
+    fn from_ret(ret: i64) -> Self {
+        if ret >= 0 {
+            Self::Usable
+        } else {
+            Self::Unusable(std::io::Error::last_os_error().to_string())
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usable => f.write_str("usable"),
+            Self::Unusable(reason) => write!(f, "not usable ({reason})"),
+        }
+    }
+}
+
+/// Mechanisms tried between one source device and one destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probes {
+    /// `FICLONE`
+    pub reflink: Probe,
+    /// `copy_file_range(2)`
+    pub copy_file_range: Probe,
+    /// `splice(2)` through a pipe
+    pub splice: Probe,
+    /// Both ends on the same kind of network mount
+    pub network: bool,
+    /// Both ends on the same filesystem
+    pub same_filesystem: bool,
+}
+
+/// The files of one source device, copied to one destination
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Source device (`st_dev`)
+    pub device: u64,
+    /// Source filesystem
+    pub filesystem: TuneProfile,
+    /// What was tried
+    pub probes: Probes,
+    /// What the copy would use
+    pub mechanism: Mechanism,
+    /// Why
+    pub reason: &'static str,
+    /// Whether no data passes through the page cache
+    pub shares_extents: bool,
+    /// Non-empty files
+    pub files: u64,
+    /// Their bytes
+    pub bytes: u64,
+    /// Of them, files for the large-file pipeline
+    pub large_files: u64,
+}
+
+/// The plan of the copy to one destination
+#[derive(Debug, Clone)]
+pub struct DestinationPlan {
+    /// Destination as given
+    pub destination: PathBuf,
+    /// Tuning profile of the destination (buffer size)
+    pub tuning: TuningProfile,
+    /// One route per source device with data
+    pub routes: Vec<Route>,
+    /// Empty files (no data to copy)
+    pub empty_files: u64,
+    /// Most bytes of copy buffers in use at once
+    pub buffer_memory: u64,
+    /// Bytes read and written through the page cache
+    pub page_cache: u64,
+}
+
+impl DestinationPlan {
+    /// Files and bytes copied with `mechanism`
+    #[must_use]
+    pub fn totals(&self, mechanism: Mechanism) -> (u64, u64) {
+        self.routes
+            .iter()
+            .filter(|route| route.mechanism == mechanism)
+            .fold((0, 0), |(files, bytes), route| {
+                (files + route.files, bytes + route.bytes)
+            })
+    }
+}
+
+impl fmt::Display for DestinationPlan {
+    /// Files and bytes per mechanism and the footprint; the alternate form
+    /// (`{:#}`) also explains the choice for each source filesystem
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} profile, {} KB buffers):",
+            self.destination.display(),
+            filesystem_name(self.tuning.profile),
+            self.tuning.buffer_size / 1024
+        )?;
+        for mechanism in [
+            Mechanism::Reflink,
+            Mechanism::CopyFileRange,
+            Mechanism::Splice,
+            Mechanism::ServerSide,
+            Mechanism::ReadWrite,
+        ] {
+            let (files, bytes) = self.totals(mechanism);
+            writeln!(f, "  {mechanism:<16}{files:>10} files{bytes:>18} bytes")?;
+        }
+        writeln!(f, "  {:<16}{:>10} files", "empty", self.empty_files)?;
+        if f.alternate() {
+            for route in &self.routes {
+                let (major, minor) = device_numbers(route.device);
+                writeln!(
+                    f,
+                    "  from device {major}:{minor} ({}{}):",
+                    filesystem_name(route.filesystem),
+                    if route.probes.same_filesystem {
+                        ", the destination's filesystem"
+                    } else if route.probes.network {
+                        ", the same kind of network mount as the destination"
+                    } else {
+                        ""
+                    }
+                )?;
+                writeln!(f, "    reflink: {}", route.probes.reflink)?;
+                writeln!(f, "    copy_file_range: {}", route.probes.copy_file_range)?;
+                writeln!(f, "    splice: {}", route.probes.splice)?;
+                writeln!(
+                    f,
+                    "    -> {}: {}{}",
+                    route.mechanism,
+                    route.reason,
+                    if route.shares_extents && route.mechanism == Mechanism::CopyFileRange {
+                        " (extents are shared, as with a reflink)"
+                    } else {
+                        ""
+                    }
+                )?;
+            }
+        }
+        writeln!(f, "  copy buffers: up to {} bytes", self.buffer_memory)?;
+        if f.alternate() {
+            writeln!(
+                f,
+                "    read/write files in flight x chunks in flight per file x buffer size, per pipeline"
+            )?;
+        }
+        write!(
+            f,
+            "  page cache: {} bytes read and written",
+            self.page_cache
+        )?;
+        if f.alternate() {
+            write!(
+                f,
+                "\n    source data read and destination data written, except where extents are shared or the server copies"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The plan of a copy of `source` to each of `destinations`
+#[derive(Debug, Clone)]
+pub struct CopyPlan {
+    /// Regular files found
+    pub files: u64,
+    /// Their bytes
+    pub bytes: u64,
+    /// Entries that could not be read (not planned)
+    pub unreadable: u64,
+    /// One plan per destination
+    pub destinations: Vec<DestinationPlan>,
+}
+
+/// Regular files of the source on one device
+#[derive(Debug, Default)]
+struct DeviceFiles {
+    /// Non-empty files
+    files: u64,
+    /// Their bytes
+    bytes: u64,
+    /// Empty files
+    empty: u64,
+    /// Files for the large-file pipeline
+    large_files: u64,
+    /// Smallest non-empty file, the probes' sample
+    sample: Option<(u64, PathBuf)>,
+}
+
+/// Plan the copy of `source` to each of `destinations` with `options`
+///
+/// # Errors
+///
+/// Returns an error if `source` cannot be read, or a destination has no
+/// existing ancestor directory to probe in.
+pub fn plan(source: &Path, destinations: &[PathBuf], options: &PlanOptions) -> Result<CopyPlan> {
+    std::fs::symlink_metadata(source).map_err(|e| {
+        ErrorContext::new("lstat")
+            .source(source)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let threshold = (options.large_file_threshold > 0).then_some(options.large_file_threshold);
+    let mut devices = BTreeMap::new();
+    let mut unreadable = 0;
+    walk(source, threshold, &mut devices, &mut unreadable);
+
+    let destinations = destinations
+        .iter()
+        .map(|destination| plan_destination(destination, &devices, options))
+        .collect::<Result<_>>()?;
+    Ok(CopyPlan {
+        files: devices
+            .values()
+            .map(|device| device.files + device.empty)
+            .sum(),
+        bytes: devices.values().map(|device| device.bytes).sum(),
+        unreadable,
+        destinations,
+    })
+}
+
+/// Add the regular files at and below `path` to `devices`
+fn walk(
+    path: &Path,
+    threshold: Option<u64>,
+    devices: &mut BTreeMap<u64, DeviceFiles>,
+    unreadable: &mut u64,
+) {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        *unreadable += 1;
+        return;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            *unreadable += 1;
+            return;
+        };
+        for entry in entries {
+            match entry {
+                Ok(entry) => walk(&entry.path(), threshold, devices, unreadable),
+                Err(_) => *unreadable += 1,
+            }
+        }
+    } else if metadata.is_file() {
+        let device = devices.entry(metadata.dev()).or_default();
+        let size = metadata.len();
+        if size == 0 {
+            device.empty += 1;
+            return;
+        }
+        device.files += 1;
+        device.bytes += size;
+        if threshold.is_some_and(|threshold| size >= threshold) {
+            device.large_files += 1;
+        }
+        if device
+            .sample
+            .as_ref()
+            .is_none_or(|(smallest, _)| size < *smallest)
+        {
+            device.sample = Some((size, path.to_path_buf()));
+        }
+    }
+}
+
+/// Probe the mechanisms from every source device to `destination`
+fn plan_destination(
+    destination: &Path,
+    devices: &BTreeMap<u64, DeviceFiles>,
+    options: &PlanOptions,
+) -> Result<DestinationPlan> {
+    let dir = destination
+        .ancestors()
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.is_dir())
+        .ok_or_else(|| {
+            ErrorContext::new("find destination directory")
+                .destination(destination)
+                .cause(&"no existing ancestor directory")
+                .file_system()
+        })?;
+    let dir_file = File::open(dir).map_err(|e| {
+        ErrorContext::new("open destination directory")
+            .destination(dir)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let dir_device = dir_file.metadata().map(|metadata| metadata.dev()).ok();
+
+    let mut tuning = options
+        .tune
+        .unwrap_or_else(|| crate::tuning::detect(destination))
+        .settings();
+    if let Some(buffer_size) = options.buffer_size {
+        tuning.buffer_size = buffer_size;
+    }
+
+    let mut routes = Vec::new();
+    for (&device, files) in devices {
+        let Some((_, sample)) = &files.sample else {
+            continue;
+        };
+        let probes = match File::open(sample) {
+            Ok(sample) => probe(&sample, &dir_file, dir, dir_device == Some(device)),
+            Err(e) => {
+                let refused = Probe::Unusable(format!("cannot open {}: {e}", sample.display()));
+                Probes {
+                    reflink: refused.clone(),
+                    copy_file_range: refused.clone(),
+                    splice: refused,
+                    network: false,
+                    same_filesystem: dir_device == Some(device),
+                }
+            }
+        };
+        let (mechanism, reason) = choose(&options.copy_method, options.sparse, &probes);
+        let shares_extents = match mechanism {
+            Mechanism::ServerSide | Mechanism::Reflink => true,
+            Mechanism::CopyFileRange => probes.same_filesystem && probes.reflink.usable(),
+            Mechanism::Splice | Mechanism::ReadWrite => false,
+        };
+        routes.push(Route {
+            device,
+            filesystem: crate::tuning::detect(sample),
+            probes,
+            mechanism,
+            reason,
+            shares_extents,
+            files: files.files,
+            bytes: files.bytes,
+            large_files: files.large_files,
+        });
+    }
+
+    let (buffer_memory, page_cache) = footprint(&routes, &tuning, options);
+    Ok(DestinationPlan {
+        destination: destination.to_path_buf(),
+        tuning,
+        routes,
+        empty_files: devices.values().map(|files| files.empty).sum(),
+        buffer_memory,
+        page_cache,
+    })
+}
+
+/// Try each mechanism from `sample` to a scratch file in `dir`
+fn probe(sample: &File, dir_file: &File, dir: &Path, same_filesystem: bool) -> Probes {
+    let kind = MountKind::of_fd(sample.as_raw_fd());
+    let network = kind != MountKind::Other && MountKind::of_fd(dir_file.as_raw_fd()) == kind;
+    let scratch = |copy: fn(&File, &File) -> Probe| match scratch_file(dir) {
+        Ok(scratch) => copy(sample, &scratch),
+        Err(e) => Probe::Unusable(format!("cannot create a probe file: {e}")),
+    };
+    Probes {
+        reflink: scratch(probe_reflink),
+        copy_file_range: scratch(probe_copy_file_range),
+        splice: scratch(probe_splice),
+        network,
+        same_filesystem,
+    }
+}
+
+/// Clone `src` into `dst`
+fn probe_reflink(src: &File, dst: &File) -> Probe {
+    // SAFETY: FICLONE takes the source fd as its argument; both fds are open
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), crate::copy::FICLONE as _, src.as_raw_fd()) };
+    Probe::from_ret(ret.into())
+}
+
+/// Copy the start of `src` into `dst` in the kernel
+fn probe_copy_file_range(src: &File, dst: &File) -> Probe {
+    let mut offset: libc::loff_t = 0;
+    // SAFETY: both fds are open and offset outlives the call
+    let copied = unsafe {
+        libc::copy_file_range(
+            src.as_raw_fd(),
+            &raw mut offset,
+            dst.as_raw_fd(),
+            std::ptr::null_mut(),
+            PROBE_BYTES,
+            0,
+        )
+    };
+    Probe::from_ret(copied as i64)
+}
+
+/// Splice the start of `src` through a pipe into `dst`
+fn probe_splice(src: &File, dst: &File) -> Probe {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for both ends
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Probe::from_ret(-1);
+    }
+    // SAFETY: pipe2 succeeded, so both fds are open and owned by nobody else
+    let (read_end, write_end) =
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let mut offset: libc::loff_t = 0;
+    // SAFETY: all fds are open and offset outlives the call
+    let spliced = unsafe {
+        libc::splice(
+            src.as_raw_fd(),
+            &raw mut offset,
+            write_end.as_raw_fd(),
+            std::ptr::null_mut(),
+            PROBE_BYTES,
+            0,
+        )
+    };
+    if spliced < 0 {
+        return Probe::from_ret(-1);
+    }
+    let mut offset: libc::loff_t = 0;
+    // SAFETY: all fds are open and offset outlives the call
+    Probe::from_ret(unsafe {
+        libc::splice(
+            read_end.as_raw_fd(),
+            std::ptr::null_mut(),
+            dst.as_raw_fd(),
+            &raw mut offset,
+            spliced.unsigned_abs(),
+            0,
+        )
+    } as i64)
+}
+
+/// An empty file in `dir` that is gone once closed
+fn scratch_file(dir: &Path) -> std::io::Result<File> {
+    let tmpfile = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open(dir);
+    if let Ok(file) = tmpfile {
+        return Ok(file);
+    }
+    let path = dir.join(format!(".arsync-plan-probe.{}", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// The mechanism a copy with `copy_method` uses given `probes`, and why
+///
+/// Mirrors `copy::kernel_copy` and the server-side offload tried before it.
+fn choose(copy_method: &CopyMethod, sparse: bool, probes: &Probes) -> (Mechanism, &'static str) {
+    // Network mounts copy server-side with copy_file_range (or COPYCHUNK)
+    if probes.network && probes.copy_file_range.usable() {
+        return (
+            Mechanism::ServerSide,
+            "both ends on the same kind of network mount",
+        );
+    }
+    let splice = |reason| {
+        if probes.splice.usable() {
+            (Mechanism::Splice, reason)
+        } else {
+            (Mechanism::ReadWrite, "splice is refused")
+        }
+    };
+    let copy_file_range = |reason| {
+        if probes.copy_file_range.usable() {
+            (Mechanism::CopyFileRange, reason)
+        } else {
+            splice("copy_file_range is refused")
+        }
+    };
+    match copy_method {
+        CopyMethod::CopyFileRange => copy_file_range("--copy-method copy-file-range"),
+        CopyMethod::Reflink if probes.reflink.usable() => {
+            (Mechanism::Reflink, "--copy-method reflink")
+        }
+        CopyMethod::Reflink => (Mechanism::ReadWrite, "reflink is refused"),
+        CopyMethod::Auto if sparse => (
+            Mechanism::ReadWrite,
+            "--sparse copies data segments with read/write",
+        ),
+        CopyMethod::Auto if probes.same_filesystem => {
+            copy_file_range("within one filesystem, copied in the kernel")
+        }
+        CopyMethod::Auto => (
+            Mechanism::ReadWrite,
+            "across filesystems, auto copies with read/write",
+        ),
+        CopyMethod::ReadWrite => (Mechanism::ReadWrite, "--copy-method read-write"),
+        CopyMethod::Splice => splice("--copy-method splice"),
+    }
+}
+
+/// Lowercase name of a filesystem's profile
+fn filesystem_name(profile: TuneProfile) -> String {
+    format!("{profile:?}").to_lowercase()
+}
+
+/// Major and minor numbers of the device `dev` (glibc's encoding)
+const fn device_numbers(dev: u64) -> (u64, u64) {
+    (
+        ((dev >> 32) & 0xFFFF_F000) | ((dev >> 8) & 0xFFF),
+        ((dev >> 12) & 0xFFFF_FF00) | (dev & 0xFF),
+    )
+}
+
+/// Most bytes of copy buffers in use at once, and bytes through the page
+/// cache, for `routes`
+fn footprint(routes: &[Route], tuning: &TuningProfile, options: &PlanOptions) -> (u64, u64) {
+    let (mut small, mut large, mut page_cache) = (0, 0, 0);
+    for route in routes {
+        if route.mechanism == Mechanism::ReadWrite {
+            small += route.files - route.large_files;
+            large += route.large_files;
+        }
+        if !route.shares_extents {
+            // Read from the source's cache, dirtied in the destination's
+            page_cache += 2 * route.bytes;
+        }
+    }
+    let chunks = crate::pipelines::chunks_in_flight(options.queue_depth) as u64;
+    let buffer_size = tuning.buffer_size as u64;
+    let in_flight = |files: u64, limit: usize| files.min(limit as u64);
+    let buffer_memory = in_flight(small, options.max_files_in_flight) * chunks * buffer_size
+        + in_flight(large, options.large_files_in_flight)
+            * chunks
+            * buffer_size.max(LARGE_BUFFER_MIN);
+    (buffer_memory, page_cache)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn probes(reflink: bool, copy_file_range: bool, same_filesystem: bool) -> Probes {
+        let outcome = |usable| {
+            if usable {
+                Probe::Usable
+            } else {
+                Probe::Unusable("EXDEV".to_string())
+            }
+        };
+        Probes {
+            reflink: outcome(reflink),
+            copy_file_range: outcome(copy_file_range),
+            splice: Probe::Usable,
+            network: false,
+            same_filesystem,
+        }
+    }
+
+    #[test]
+    fn test_choice_follows_copy_path() {
+        // Requirement: auto copies in the kernel only within one filesystem
+        // and not with --sparse; explicit methods fall back to read/write
+        // when refused, copy_file_range through splice first
+        let local = probes(true, true, true);
+        assert_eq!(
+            choose(&CopyMethod::Auto, false, &local).0,
+            Mechanism::CopyFileRange
+        );
+        assert_eq!(
+            choose(&CopyMethod::Auto, true, &local).0,
+            Mechanism::ReadWrite
+        );
+        let across = probes(false, true, false);
+        assert_eq!(
+            choose(&CopyMethod::Auto, false, &across).0,
+            Mechanism::ReadWrite
+        );
+        assert_eq!(
+            choose(&CopyMethod::CopyFileRange, false, &across).0,
+            Mechanism::CopyFileRange
+        );
+        assert_eq!(
+            choose(&CopyMethod::Reflink, false, &across).0,
+            Mechanism::ReadWrite
+        );
+        assert_eq!(
+            choose(&CopyMethod::Reflink, false, &local).0,
+            Mechanism::Reflink
+        );
+        assert_eq!(
+            choose(&CopyMethod::Splice, false, &local).0,
+            Mechanism::Splice
+        );
+        let refused = probes(false, false, false);
+        assert_eq!(
+            choose(&CopyMethod::CopyFileRange, false, &refused).0,
+            Mechanism::Splice
+        );
+        let no_splice = Probes {
+            splice: Probe::Unusable("EINVAL".to_string()),
+            ..refused
+        };
+        assert_eq!(
+            choose(&CopyMethod::Splice, false, &no_splice).0,
+            Mechanism::ReadWrite
+        );
+        let network = Probes {
+            network: true,
+            ..probes(false, true, false)
+        };
+        assert_eq!(
+            choose(&CopyMethod::ReadWrite, false, &network).0,
+            Mechanism::ServerSide
+        );
+    }
+
+    #[test]
+    fn test_plan_of_local_tree() {
+        // Requirement: Every file is counted once per destination, empty
+        // files apart, and the probes leave nothing in the destination
+        let temp = tempfile::TempDir::new().unwrap();
+        let source = temp.path().join("source");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("a"), vec![7; 100_000]).unwrap();
+        std::fs::write(source.join("sub/b"), b"beta").unwrap();
+        std::fs::write(source.join("sub/empty"), b"").unwrap();
+        let destination = temp.path().join("destination");
+        std::fs::create_dir(&destination).unwrap();
+        let options = PlanOptions {
+            copy_method: CopyMethod::ReadWrite,
+            sparse: false,
+            queue_depth: 4096,
+            buffer_size: Some(64 * 1024),
+            tune: None,
+            max_files_in_flight: 1,
+            large_file_threshold: 64 * 1024 * 1024,
+            large_files_in_flight: 8,
+        };
+
+        let plan = plan(&source, &[destination.clone()], &options).unwrap();
+        assert_eq!((plan.files, plan.bytes, plan.unreadable), (3, 100_004, 0));
+        let to = &plan.destinations[0];
+        assert_eq!(to.empty_files, 1);
+        assert_eq!(to.totals(Mechanism::ReadWrite), (2, 100_004));
+        assert!(to.routes[0].probes.same_filesystem);
+        assert_eq!(to.page_cache, 2 * 100_004);
+        // One file in flight, four chunks of 64 KB each
+        assert_eq!(to.buffer_memory, 4 * 64 * 1024);
+        assert_eq!(std::fs::read_dir(&destination).unwrap().count(), 0);
+    }
+}
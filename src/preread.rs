@@ -0,0 +1,311 @@
+//! Source cache warming ahead of the copy (`--preread N`)
+//!
+//! On high-latency sources (object-store FUSE mounts, NFS over a WAN) every
+//! file's first `statx` and first read wait a full round trip before the copy
+//! of that file can make progress. With `--preread N`, the traversal hands
+//! every regular file it lists to a prefetch thread, which stays up to `N`
+//! files ahead of the copy wave: it looks up each file's metadata and asks the
+//! kernel to start reading its first chunk (`POSIX_FADV_WILLNEED`), so both
+//! are already cached when the copy reaches the file.
+//!
+//! Warmed data stays accounted until the file's copy starts. The prefetcher
+//! pauses whenever `N` files or `--preread-budget-mb` of warmed data are
+//! waiting, so it never fills more page cache than the budget. Files the copy
+//! reaches before the prefetcher does are skipped.
+
+use crate::error::{Result, SyncError};
+use compio_fs_extended::DirectoryFd;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+
+/// Bytes read ahead at the start of each file
+pub const PREREAD_CHUNK: u64 = 1024 * 1024;
+
+/// Identifies a listed file: its parent's `DirectoryFd` and its name
+type EntryKey = (usize, OsString);
+
+/// File waiting to be warmed
+struct Candidate {
+    /// Parent directory (kept open until the file is warmed)
+    dir: Arc<DirectoryFd>,
+    /// File name
+    name: OsString,
+}
+
+/// Prefetch queue and accounting, guarded by `Prefetcher::state`
+#[derive(Default)]
+struct State {
+    /// Files listed by the traversal, in listing order
+    queue: VecDeque<Candidate>,
+    /// File being warmed right now
+    current: Option<EntryKey>,
+    /// Warmed files whose copy has not started, with the bytes read ahead
+    warmed: HashMap<EntryKey, u64>,
+    /// Sum of `warmed`
+    warmed_bytes: u64,
+    /// Set when the traversal is done; the thread exits
+    closed: bool,
+}
+
+/// Warms the source cache for upcoming files on a background thread
+pub struct Prefetcher {
+    /// Files warmed ahead of the copy at most
+    files_ahead: usize,
+    /// Bytes warmed ahead of the copy at most
+    budget: u64,
+    /// Queue and accounting
+    state: Mutex<State>,
+    /// Signalled when work is queued, a slot frees up or on close
+    wake: Condvar,
+    /// Prefetch thread, joined by `finish()`
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Prefetcher {
+    /// Start a prefetch thread warming up to `files_ahead` files and `budget` bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::Internal` if the thread cannot be spawned.
+    pub fn start(files_ahead: usize, budget: u64) -> Result<Arc<Self>> {
+        let prefetcher = Arc::new(Self {
+            files_ahead,
+            budget,
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+            thread: Mutex::new(None),
+        });
+        let worker = Arc::clone(&prefetcher);
+        let handle = std::thread::Builder::new()
+            .name("arsync-preread".to_string())
+            .spawn(move || worker.run())
+            .map_err(|e| SyncError::Internal(format!("Failed to start pre-read thread: {e}")))?;
+        *prefetcher
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(handle);
+        Ok(prefetcher)
+    }
+
+    /// Queue a regular file listed in `dir` for warming
+    pub fn offer(&self, dir: &Arc<DirectoryFd>, name: &OsStr) {
+        let mut state = self.lock();
+        state.queue.push_back(Candidate {
+            dir: Arc::clone(dir),
+            name: name.to_os_string(),
+        });
+        self.wake.notify_one();
+    }
+
+    /// The copy of `name` in `dir` is starting; release what was warmed for it
+    ///
+    /// If the prefetcher has not reached the file yet, files queued before it
+    /// (within the look-ahead window) are behind the copy wave and dropped.
+    pub fn consumed(&self, dir: &Arc<DirectoryFd>, name: &OsStr) {
+        let key = entry_key(dir, name);
+        let mut state = self.lock();
+        if let Some(bytes) = state.warmed.remove(&key) {
+            state.warmed_bytes -= bytes;
+        } else if state.current.as_ref() == Some(&key) {
+            state.current = None;
+        } else if let Some(position) = state
+            .queue
+            .iter()
+            .take(self.files_ahead)
+            .position(|candidate| entry_key(&candidate.dir, &candidate.name) == key)
+        {
+            state.queue.drain(..=position);
+        }
+        self.wake.notify_one();
+    }
+
+    /// Stop the prefetch thread once the traversal is done
+    pub fn finish(&self) {
+        self.lock().closed = true;
+        self.wake.notify_all();
+        let handle = self
+            .thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(handle) = handle {
+            if handle.join().is_err() {
+                tracing::warn!("Pre-read thread panicked");
+            }
+        }
+    }
+
+    /// Prefetch thread: warm queued files while within the limits
+    fn run(&self) {
+        loop {
+            let candidate = {
+                let mut state = self.lock();
+                loop {
+                    if state.closed {
+                        return;
+                    }
+                    if state.warmed.len() < self.files_ahead && state.warmed_bytes < self.budget {
+                        if let Some(candidate) = state.queue.pop_front() {
+                            state.current = Some(entry_key(&candidate.dir, &candidate.name));
+                            break candidate;
+                        }
+                    }
+                    state = self
+                        .wake
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            };
+
+            let remaining = self.budget.saturating_sub(self.lock().warmed_bytes);
+            let bytes = warm(&candidate, PREREAD_CHUNK.min(remaining));
+
+            let mut state = self.lock();
+            // `current` was cleared if the copy started in the meantime
+            if let Some(key) = state.current.take() {
+                state.warmed.insert(key, bytes);
+                state.warmed_bytes += bytes;
+            }
+        }
+    }
+
+    /// Lock the state, recovering from a poisoned lock
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Key of the file `name` in `dir`
+fn entry_key(dir: &Arc<DirectoryFd>, name: &OsStr) -> EntryKey {
+    (Arc::as_ptr(dir) as usize, name.to_os_string())
+}
+
+/// Look up the file's metadata and read ahead up to `limit` bytes of it
+///
+/// Returns the number of bytes requested; failures are ignored, the copy
+/// reports them when it reaches the file.
+fn warm(candidate: &Candidate, limit: u64) -> u64 {
+    let Ok(name) = CString::new(candidate.name.as_bytes()) else {
+        return 0;
+    };
+    let dir_fd = candidate.dir.as_raw_fd();
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: the directory fd is kept open by `candidate.dir`; stat is a
+    // valid out-pointer
+    if unsafe {
+        libc::fstatat(
+            dir_fd,
+            name.as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    } != 0
+    {
+        return 0;
+    }
+    // SAFETY: fstatat succeeded and initialized stat
+    let stat = unsafe { stat.assume_init() };
+    if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return 0;
+    }
+    let len = u64::try_from(stat.st_size).unwrap_or(0).min(limit);
+    if len == 0 {
+        return 0;
+    }
+
+    // SAFETY: openat on a valid directory fd; the result is closed below
+    let fd = unsafe {
+        libc::openat(
+            dir_fd,
+            name.as_ptr(),
+            libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOFOLLOW | libc::O_NONBLOCK,
+        )
+    };
+    if fd < 0 {
+        return 0;
+    }
+    // SAFETY: advisory call on the fd opened above, which is then closed
+    let advised = unsafe {
+        let advised = libc::posix_fadvise(
+            fd,
+            0,
+            libc::off_t::try_from(len).unwrap_or(libc::off_t::MAX),
+            libc::POSIX_FADV_WILLNEED,
+        );
+        libc::close(fd);
+        advised
+    };
+    if advised == 0 {
+        len
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    fn warmed(prefetcher: &Prefetcher) -> (usize, u64) {
+        let state = prefetcher.lock();
+        (state.warmed.len(), state.warmed_bytes)
+    }
+
+    fn wait_for(prefetcher: &Prefetcher, expected: (usize, u64)) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while warmed(prefetcher) != expected && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(warmed(prefetcher), expected);
+    }
+
+    #[compio::test]
+    async fn test_prefetcher_stays_within_limits() {
+        // Requirement: At most N files / the budget are warmed ahead of the copy
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..5 {
+            std::fs::write(temp_dir.path().join(format!("f{i}")), vec![1u8; 1000]).unwrap();
+        }
+        let dir = Arc::new(DirectoryFd::open(temp_dir.path()).await.unwrap());
+
+        let prefetcher = Prefetcher::start(2, 1 << 20).unwrap();
+        for i in 0..5 {
+            prefetcher.offer(&dir, OsStr::new(&format!("f{i}")));
+        }
+        wait_for(&prefetcher, (2, 2000));
+
+        // Starting the copy of f0 frees a slot for f2
+        prefetcher.consumed(&dir, OsStr::new("f0"));
+        wait_for(&prefetcher, (2, 2000));
+        assert_eq!(prefetcher.lock().queue.len(), 2);
+
+        // The copy overtaking the prefetcher drops what is behind it
+        prefetcher.consumed(&dir, OsStr::new("f4"));
+        assert!(prefetcher.lock().queue.is_empty());
+        prefetcher.finish();
+    }
+
+    #[compio::test]
+    async fn test_budget_limits_bytes_warmed() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..3 {
+            std::fs::write(temp_dir.path().join(format!("f{i}")), vec![1u8; 4096]).unwrap();
+        }
+        let dir = Arc::new(DirectoryFd::open(temp_dir.path()).await.unwrap());
+
+        let prefetcher = Prefetcher::start(10, 6000).unwrap();
+        for i in 0..3 {
+            prefetcher.offer(&dir, OsStr::new(&format!("f{i}")));
+        }
+        // The second file only gets what is left of the budget; the third waits
+        wait_for(&prefetcher, (2, 6000));
+        prefetcher.finish();
+    }
+}
@@ -0,0 +1,387 @@
+//! Priority classes for jobs sharing one process (`arsync serve`)
+//!
+//! Jobs of a control service draw their file copies from one budget of
+//! files in flight. Each job, and optionally each path within it, has a
+//! [`Priority`] class whose weight decides its share of that budget: a job
+//! (or path) with class weight `w` may have `budget * w / W` files in flight,
+//! where `W` sums the weights of every stream with files copying or waiting,
+//! and always at least one. An interactive restore submitted next to a
+//! background mirror therefore takes most of the budget as soon as it has
+//! files to copy; the mirror keeps its files in flight, starts no new ones
+//! beyond its share, and gets the budget back when the restore is done.
+//!
+//! Only regular files wait for the budget: directories are cheap to walk and
+//! holding a share across a directory's children could starve them.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+/// How urgent a job or path is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Bulk work that may take as long as it needs (mirrors, archives)
+    Background,
+    /// Regular syncs
+    #[default]
+    Normal,
+    /// Someone is waiting for it (restores)
+    Interactive,
+}
+
+impl Priority {
+    /// Name used on the wire
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Background => "background",
+            Self::Normal => "normal",
+            Self::Interactive => "interactive",
+        }
+    }
+
+    /// Share of the budget relative to the other classes
+    #[must_use]
+    pub const fn weight(self) -> usize {
+        match self {
+            Self::Background => 1,
+            Self::Normal => 4,
+            Self::Interactive => 16,
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "background" => Ok(Self::Background),
+            "normal" => Ok(Self::Normal),
+            "interactive" => Ok(Self::Interactive),
+            _ => Err(format!(
+                "Unknown priority {name:?} (expected interactive, normal or background)"
+            )),
+        }
+    }
+}
+
+/// The class of a job and of paths within it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriorityRules {
+    /// Class of the job's files
+    pub class: Priority,
+    /// Classes of subtrees; the deepest matching path wins
+    pub paths: Vec<(PathBuf, Priority)>,
+}
+
+impl PriorityRules {
+    /// The class of the file at `path`
+    #[must_use]
+    pub fn class_for(&self, path: &Path) -> Priority {
+        self.paths
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.components().count())
+            .map_or(self.class, |&(_, class)| class)
+    }
+}
+
+/// One job's files of one class
+type StreamKey = (u64, Priority);
+
+#[derive(Debug, Default)]
+struct Stream {
+    in_flight: usize,
+    waiting: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Streams with files in flight or waiting; idle ones are removed
+    streams: HashMap<StreamKey, Stream>,
+    in_flight: usize,
+    /// Tasks to poll again when a file finishes or a stream goes idle
+    wakers: Vec<Waker>,
+}
+
+impl State {
+    /// Files in flight the stream may have
+    fn allowance(&self, slots: usize, key: StreamKey) -> usize {
+        let total: usize = self.streams.keys().map(|&(_, class)| class.weight()).sum();
+        (slots * key.1.weight() / total.max(1)).max(1)
+    }
+
+    /// Forget the stream if nothing is left in it
+    fn prune(&mut self, key: StreamKey) {
+        if self
+            .streams
+            .get(&key)
+            .is_some_and(|stream| stream.in_flight == 0 && stream.waiting == 0)
+        {
+            self.streams.remove(&key);
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Files in flight shared by the jobs of a process, weighted by priority
+#[derive(Debug)]
+pub struct PriorityBudget {
+    slots: usize,
+    state: Mutex<State>,
+}
+
+impl PriorityBudget {
+    /// Budget of `slots` files in flight
+    #[must_use]
+    pub fn new(slots: usize) -> Arc<Self> {
+        Arc::new(Self {
+            slots: slots.max(1),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Files in flight across all jobs
+    #[must_use]
+    #[allow(dead_code)] // Library API
+    pub fn in_flight(&self) -> usize {
+        self.lock().in_flight
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A job's handle on a [`PriorityBudget`]
+#[derive(Debug, Clone)]
+pub struct JobShare {
+    budget: Arc<PriorityBudget>,
+    job: u64,
+    rules: PriorityRules,
+}
+
+impl JobShare {
+    /// Share of `budget` for job `job`, classed by `rules`
+    #[must_use]
+    pub const fn new(budget: Arc<PriorityBudget>, job: u64, rules: PriorityRules) -> Self {
+        Self { budget, job, rules }
+    }
+
+    /// Wait until the file at `path` may be copied
+    ///
+    /// The file counts against the budget until the permit is dropped.
+    #[must_use]
+    pub fn acquire(&self, path: &Path) -> Acquire {
+        Acquire {
+            budget: Arc::clone(&self.budget),
+            key: (self.job, self.rules.class_for(path)),
+            waiting: false,
+        }
+    }
+}
+
+/// Future of [`JobShare::acquire`]
+#[derive(Debug)]
+pub struct Acquire {
+    budget: Arc<PriorityBudget>,
+    key: StreamKey,
+    /// Whether the stream counts this future as waiting
+    waiting: bool,
+}
+
+impl Future for Acquire {
+    type Output = PriorityPermit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let slots = this.budget.slots;
+        let mut state = this.budget.lock();
+        let stream = state.streams.entry(this.key).or_default();
+        if !this.waiting {
+            stream.waiting += 1;
+            this.waiting = true;
+        }
+        let in_flight = stream.in_flight;
+        if in_flight < state.allowance(slots, this.key) && state.in_flight < slots {
+            let stream = state.streams.entry(this.key).or_default();
+            stream.waiting -= 1;
+            stream.in_flight += 1;
+            state.in_flight += 1;
+            this.waiting = false;
+            return Poll::Ready(PriorityPermit {
+                budget: Arc::clone(&this.budget),
+                key: this.key,
+            });
+        }
+        state.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if !self.waiting {
+            return;
+        }
+        let mut state = self.budget.lock();
+        if let Some(stream) = state.streams.get_mut(&self.key) {
+            stream.waiting -= 1;
+        }
+        state.prune(self.key);
+        // A stream that went idle frees its share for the others
+        state.wake_all();
+    }
+}
+
+/// A file counted against a [`PriorityBudget`]; dropping it frees the slot
+#[derive(Debug)]
+pub struct PriorityPermit {
+    budget: Arc<PriorityBudget>,
+    key: StreamKey,
+}
+
+impl Drop for PriorityPermit {
+    fn drop(&mut self) {
+        let mut state = self.budget.lock();
+        if let Some(stream) = state.streams.get_mut(&self.key) {
+            stream.in_flight -= 1;
+        }
+        state.in_flight -= 1;
+        state.prune(self.key);
+        state.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_priority_names_round_trip() {
+        // Requirement: Classes are named interactive, normal and background
+        // on the wire, and interactive outranks the others
+        for class in [
+            Priority::Interactive,
+            Priority::Normal,
+            Priority::Background,
+        ] {
+            assert_eq!(class.name().parse::<Priority>().unwrap(), class);
+        }
+        assert!("urgent".parse::<Priority>().is_err());
+        assert!(Priority::Interactive > Priority::Normal);
+        assert!(Priority::Normal > Priority::Background);
+    }
+
+    #[test]
+    fn test_deepest_path_rule_wins() {
+        // Requirement: A file takes the class of the deepest path rule
+        // holding it, or the job's class
+        let rules = PriorityRules {
+            class: Priority::Background,
+            paths: vec![
+                (PathBuf::from("/data/db"), Priority::Interactive),
+                (PathBuf::from("/data/db/logs"), Priority::Normal),
+            ],
+        };
+        assert_eq!(rules.class_for(Path::new("/data/x")), Priority::Background);
+        assert_eq!(
+            rules.class_for(Path::new("/data/db/table")),
+            Priority::Interactive
+        );
+        assert_eq!(
+            rules.class_for(Path::new("/data/db/logs/1")),
+            Priority::Normal
+        );
+        assert_eq!(
+            rules.class_for(Path::new("/data/dbx")),
+            Priority::Background
+        );
+    }
+
+    #[test]
+    fn test_interactive_job_preempts_background() {
+        // Requirement: A background job alone uses the whole budget; once an
+        // interactive job has files waiting, the background job starts no new
+        // files beyond its share but keeps the ones in flight, and the
+        // interactive job gets the slots they free
+        let budget = PriorityBudget::new(4);
+        let mirror = JobShare::new(
+            Arc::clone(&budget),
+            1,
+            PriorityRules {
+                class: Priority::Background,
+                paths: Vec::new(),
+            },
+        );
+        let restore = JobShare::new(
+            Arc::clone(&budget),
+            2,
+            PriorityRules {
+                class: Priority::Interactive,
+                paths: Vec::new(),
+            },
+        );
+        let path = Path::new("/f");
+
+        let mut mirror_permits: Vec<PriorityPermit> = (0..4)
+            .map(|_| mirror.acquire(path).now_or_never().unwrap())
+            .collect();
+        assert!(mirror.acquire(path).now_or_never().is_none());
+
+        let mut restore_wait = Box::pin(restore.acquire(path));
+        assert!((&mut restore_wait).now_or_never().is_none());
+        mirror_permits.pop();
+        let _restore_permit = restore_wait.now_or_never().unwrap();
+
+        // The mirror is down to its minimum of one file until the restore ends
+        mirror_permits.truncate(1);
+        assert!(mirror.acquire(path).now_or_never().is_none());
+        let _more: Vec<PriorityPermit> = (0..2)
+            .map(|_| restore.acquire(path).now_or_never().unwrap())
+            .collect();
+        assert_eq!(budget.in_flight(), 4);
+    }
+
+    #[test]
+    fn test_idle_stream_returns_its_share() {
+        // Requirement: When a job has nothing left in flight or waiting, the
+        // other jobs may grow back into the whole budget
+        let budget = PriorityBudget::new(3);
+        let normal = JobShare::new(Arc::clone(&budget), 1, PriorityRules::default());
+        let interactive = JobShare::new(
+            Arc::clone(&budget),
+            2,
+            PriorityRules {
+                class: Priority::Interactive,
+                paths: Vec::new(),
+            },
+        );
+        let path = Path::new("/f");
+
+        let busy = interactive.acquire(path).now_or_never().unwrap();
+        let first = normal.acquire(path).now_or_never().unwrap();
+        assert!(normal.acquire(path).now_or_never().is_none());
+
+        drop(busy);
+        let second = normal.acquire(path).now_or_never().unwrap();
+        let third = normal.acquire(path).now_or_never().unwrap();
+        assert_eq!(budget.in_flight(), 3);
+        drop((first, second, third));
+        assert_eq!(budget.in_flight(), 0);
+        assert!(budget.lock().streams.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Privileges for preserving ownership
+//!
+//! Giving a file to another owner needs `CAP_CHOWN`; without it a process may
+//! only change the group of files it owns, and only to a group it belongs to.
+//! `-a` as an ordinary user used to fail every `fchown` it tried, so the
+//! capabilities are detected once per process: [`ownership_hint`] describes
+//! what is missing (printed once at startup), and
+//! [`MetadataConfig::ownership`] only asks for the changes the
+//! `--unprivileged-ownership` policy allows.
+//!
+//! Capabilities come from the `CapEff` line of `/proc/self/status`; where it
+//! cannot be read, the effective user being root stands in for them.
+
+use crate::metadata::{MetadataConfig, UnprivilegedOwnership};
+use std::sync::OnceLock;
+
+/// `CAP_CHOWN`: change the owner and group of any file
+const CAP_CHOWN: u32 = 0;
+/// `CAP_FOWNER`: change the mode and times of files owned by others
+const CAP_FOWNER: u32 = 3;
+
+/// Privileges of this process
+static CURRENT: OnceLock<Privileges> = OnceLock::new();
+
+/// What this process may do to ownership
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Privileges {
+    /// Has `CAP_CHOWN`
+    pub chown: bool,
+    /// Has `CAP_FOWNER`
+    pub fowner: bool,
+    /// Effective and supplementary groups
+    pub groups: Vec<u32>,
+}
+
+impl Privileges {
+    /// Privileges of this process (detected on first use)
+    pub fn current() -> &'static Self {
+        CURRENT.get_or_init(Self::detect)
+    }
+
+    fn detect() -> Self {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        let root = unsafe { libc::geteuid() } == 0;
+        let capabilities = std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| effective_capabilities(&status));
+        let has = |capability: u32| capabilities.map_or(root, |set| set & (1 << capability) != 0);
+        Self {
+            chown: has(CAP_CHOWN),
+            fowner: has(CAP_FOWNER),
+            groups: groups(),
+        }
+    }
+
+    /// Whether the process belongs to group `gid`
+    #[must_use]
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.groups.contains(&gid)
+    }
+}
+
+/// The effective capability set from the text of `/proc/<pid>/status`
+fn effective_capabilities(status: &str) -> Option<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(line.trim(), 16).ok()
+}
+
+/// The effective group and the supplementary groups of this process
+fn groups() -> Vec<u32> {
+    // SAFETY: getegid has no preconditions and cannot fail
+    let mut groups = vec![unsafe { libc::getegid() }];
+    // SAFETY: a size of 0 only asks for the number of groups
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if let Ok(len) = usize::try_from(count) {
+        let mut supplementary = vec![0; len];
+        // SAFETY: the buffer holds `count` gid_t
+        let count = unsafe { libc::getgroups(count, supplementary.as_mut_ptr()) };
+        supplementary.truncate(usize::try_from(count).unwrap_or(0));
+        groups.extend(supplementary);
+    }
+    groups
+}
+
+/// A one-time hint when `config` preserves ownership this process may not
+/// set, saying what happens under its `--unprivileged-ownership` policy and
+/// how to avoid it; `None` if there is nothing to say
+#[must_use]
+pub fn ownership_hint(config: &MetadataConfig) -> Option<String> {
+    hint_for(config, Privileges::current())
+}
+
+fn hint_for(config: &MetadataConfig, privileges: &Privileges) -> Option<String> {
+    if !config.should_preserve_ownership() || privileges.chown {
+        return None;
+    }
+    let missing = if privileges.fowner {
+        "CAP_CHOWN"
+    } else {
+        "CAP_CHOWN and CAP_FOWNER"
+    };
+    let outcome = match config.unprivileged_ownership {
+        UnprivilegedOwnership::Permitted => {
+            "owners are not preserved, and groups only when you belong to them"
+        }
+        UnprivilegedOwnership::Attempt => "entries whose ownership cannot be changed will fail",
+        UnprivilegedOwnership::Fail => "not starting (--unprivileged-ownership=fail)",
+    };
+    Some(format!(
+        "Preserving ownership needs {missing}, which this process does not have: {outcome}. \
+         Run as root or with the capabilities (e.g. setcap cap_chown,cap_fowner+ep), or pass \
+         --no-owner --no-group to stop preserving ownership"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::metadata::SpecialFilePolicy;
+
+    fn metadata_config() -> MetadataConfig {
+        MetadataConfig {
+            archive: true,
+            recursive: false,
+            links: false,
+            perms: false,
+            times: false,
+            group: false,
+            owner: false,
+            no_owner: false,
+            no_group: false,
+            devices: false,
+            specials: false,
+            devices_and_specials: false,
+            fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
+            xattrs: false,
+            acls: false,
+            hard_links: false,
+            atimes: false,
+            crtimes: false,
+            preserve_xattr: false,
+            preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_effective_capabilities() {
+        // Requirement: CapEff is read as a hexadecimal bit set
+        let status = "Name:\tarsync\nCapInh:\t0000000000000000\n\
+                      CapEff:\t0000000000000009\nCapBnd:\t000001ffffffffff\n";
+        let capabilities = effective_capabilities(status).unwrap();
+        assert_ne!(capabilities & (1 << CAP_CHOWN), 0);
+        assert_ne!(capabilities & (1 << CAP_FOWNER), 0);
+        assert_eq!(capabilities & 2, 0);
+        assert_eq!(effective_capabilities("Name:\tarsync\n"), None);
+    }
+
+    #[test]
+    fn test_hint_only_without_chown() {
+        // Requirement: The hint appears when ownership is preserved without
+        // CAP_CHOWN, names what is missing and the way out
+        let unprivileged = Privileges {
+            chown: false,
+            fowner: false,
+            groups: vec![100],
+        };
+        let mut config = metadata_config();
+        let hint = hint_for(&config, &unprivileged).unwrap();
+        assert!(hint.contains("CAP_CHOWN and CAP_FOWNER"), "{hint}");
+        assert!(hint.contains("--no-owner --no-group"), "{hint}");
+
+        let privileged = Privileges {
+            chown: true,
+            fowner: true,
+            groups: Vec::new(),
+        };
+        assert_eq!(hint_for(&config, &privileged), None);
+        config.no_owner = true;
+        config.no_group = true;
+        assert_eq!(hint_for(&config, &unprivileged), None);
+    }
+
+    #[test]
+    fn test_no_owner_and_no_group_override_archive() {
+        // Requirement: --no-owner and --no-group leave that ID unchanged
+        // (-1) even with -a
+        let mut config = metadata_config();
+        config.unprivileged_ownership = UnprivilegedOwnership::Attempt;
+        assert_eq!(config.ownership(1000, 100), Some((1000, 100)));
+        config.no_owner = true;
+        assert_eq!(config.ownership(1000, 100), Some((u32::MAX, 100)));
+        config.no_group = true;
+        assert_eq!(config.ownership(1000, 100), None);
+    }
+
+    #[test]
+    fn test_permitted_ownership_without_chown() {
+        // Requirement: Under the default policy a process without CAP_CHOWN
+        // only sets groups it belongs to
+        let config = metadata_config();
+        let privileges = Privileges::current();
+        let foreign_gid = (0..u32::MAX)
+            .find(|gid| !privileges.in_group(*gid))
+            .unwrap();
+        if privileges.chown {
+            assert_eq!(config.ownership(1, foreign_gid), Some((1, foreign_gid)));
+        } else {
+            assert_eq!(config.ownership(1, foreign_gid), None);
+            let own_gid = privileges.groups[0];
+            assert_eq!(config.ownership(1, own_gid), Some((u32::MAX, own_gid)));
+        }
+    }
+}
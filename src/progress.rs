@@ -44,11 +44,15 @@
 //! All progress tracking operations are thread-safe and can be used concurrently
 //! across multiple threads. Statistics are updated atomically to prevent race conditions.
 
+use crate::events::{SyncEvent, EVENTS};
 use crate::i18n::TranslationKey;
 use crate::io_uring::CopyOperation;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::time::Duration;
 
+/// Events the renderer can fall behind by before it starts losing updates
+const EVENT_QUEUE_CAPACITY: usize = 64 * 1024;
+
 /// Progress tracker for file synchronization operations
 ///
 /// This structure provides real-time progress tracking and reporting for file
@@ -324,6 +328,26 @@ impl ProgressTracker {
         }
     }
 
+    /// Update the tracker from a sync pipeline event
+    ///
+    /// Discovered files grow the total, copied chunks advance the bar and
+    /// finished files are counted.
+    pub fn apply(&mut self, event: &SyncEvent) {
+        match *event {
+            SyncEvent::EntryDiscovered {
+                size,
+                is_dir: false,
+                ..
+            } => self.track_discovery(size),
+            SyncEvent::ChunkCopied { bytes, .. } => {
+                self.bytes_copied += bytes;
+                self.progress_bar.inc(bytes);
+            }
+            SyncEvent::FileDone { .. } => self.files_copied += 1,
+            _ => {}
+        }
+    }
+
     /// Get current progress statistics
     ///
     /// This function returns a snapshot of the current progress statistics,
@@ -365,6 +389,50 @@ impl ProgressTracker {
     }
 }
 
+/// Terminal progress bar driven by the sync event bus (`--progress`)
+///
+/// Subscribes to [`EVENTS`] and updates a `ProgressTracker` from a task on
+/// the current runtime as events arrive, so the bar moves with every chunk
+/// rather than once per file.
+#[derive(Debug)]
+pub struct ProgressRenderer {
+    /// Event bus subscriber id
+    subscriber: u64,
+    /// Task consuming events; yields the tracker once the stream ends
+    task: compio::runtime::JoinHandle<ProgressTracker>,
+}
+
+impl ProgressRenderer {
+    /// Subscribe to the event bus and start rendering
+    #[must_use]
+    pub fn start() -> Self {
+        let mut events = EVENTS.subscribe(EVENT_QUEUE_CAPACITY);
+        let subscriber = events.id();
+        let task = compio::runtime::spawn(async move {
+            let mut tracker = ProgressTracker::new();
+            while let Some(event) = events.next().await {
+                tracker.apply(&event);
+            }
+            tracker
+        });
+        Self { subscriber, task }
+    }
+
+    /// Render the events still queued, then show the completion message
+    pub async fn finish(self) {
+        EVENTS.unsubscribe(self.subscriber);
+        if let Ok(tracker) = self.task.await {
+            tracker.finish();
+        }
+        if EVENTS.dropped() > 0 {
+            tracing::debug!(
+                "Progress display missed {} events from a full queue",
+                EVENTS.dropped()
+            );
+        }
+    }
+}
+
 /// Progress statistics for synchronization operations
 ///
 /// This structure contains comprehensive statistics about the progress
@@ -0,0 +1,456 @@
+//! Per-module transfer accounting and quotas for daemon mode
+//!
+//! A daemon serving several modules records, for every session, how many
+//! bytes each client sent (`in`) and received (`out`) per module and per UTC
+//! day. The ledger is a small tab-separated file, rewritten atomically after
+//! each session, so it survives restarts and can be inspected with
+//! `arsync usage LEDGER` (or any text tool):
+//!
+//! ```text
+//! arsync-usage v1
+//! 2026-10-15	backups	10.0.0.7	1048576	0
+//! ```
+//!
+//! A module may carry a `Quota`: a daily byte limit per client and/or for
+//! the module as a whole. `UsageLedger::admit` is asked before a transfer
+//! (with the bytes it is about to move, once the file list is known) and
+//! refuses it if either limit would be exceeded; the session then fails
+//! with `QuotaExceeded` instead of starting a transfer it cannot finish.
+//!
+//! Nothing here knows about sockets: the daemon (`daemon`) owns one ledger
+//! behind a mutex and calls `record` and `save` after each session.
+
+#![allow(dead_code)] // Quotas are not enforced by the daemon yet
+use crate::bisync::{escape_path, unescape_path};
+use crate::clock::Clock;
+use crate::temp_files::temp_name;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// First line of the ledger file
+const LEDGER_HEADER: &str = "arsync-usage v1";
+
+/// Seconds per accounting day
+const SECS_PER_DAY: u64 = 86_400;
+
+/// A UTC calendar day, as days since 1970-01-01
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Day(pub u32);
+
+impl Day {
+    /// The day `time` falls on (UTC); times before the epoch count as day 0
+    #[must_use]
+    pub fn of(time: SystemTime) -> Self {
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self(u32::try_from(secs / SECS_PER_DAY).unwrap_or(u32::MAX))
+    }
+
+    /// Today (UTC) according to `clock`
+    #[must_use]
+    pub fn today(clock: &dyn Clock) -> Self {
+        Self::of(clock.now())
+    }
+
+    /// `(year, month, day)` in the proleptic Gregorian calendar
+    #[must_use]
+    pub const fn civil(self) -> (i64, u32, u32) {
+        // Howard Hinnant's days-to-civil algorithm
+        let z = self.0 as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    /// Parse `YYYY-MM-DD`
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut fields = text.splitn(3, '-');
+        let year: i64 = fields.next()?.parse().ok()?;
+        let month: i64 = fields.next()?.parse().ok()?;
+        let day: i64 = fields.next()?.parse().ok()?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        // Inverse of `civil`
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y.rem_euclid(400);
+        let mp = (month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let parsed = Self(u32::try_from(days).ok()?);
+        // Reject days that do not exist (2026-02-30)
+        (parsed.civil() == (year, month as u32, day as u32)).then_some(parsed)
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.civil();
+        f.pad(&format!("{year:04}-{month:02}-{day:02}"))
+    }
+}
+
+/// Bytes moved in each direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Bytes received from the client (client uploads)
+    pub bytes_in: u64,
+    /// Bytes sent to the client (client downloads)
+    pub bytes_out: u64,
+}
+
+impl Usage {
+    /// Bytes in both directions
+    #[must_use]
+    pub const fn total(self) -> u64 {
+        self.bytes_in.saturating_add(self.bytes_out)
+    }
+
+    fn add(&mut self, other: Self) {
+        self.bytes_in = self.bytes_in.saturating_add(other.bytes_in);
+        self.bytes_out = self.bytes_out.saturating_add(other.bytes_out);
+    }
+}
+
+/// Daily transfer limits of one module (both directions counted)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Bytes one client may move per day
+    pub client_daily: Option<u64>,
+    /// Bytes all clients together may move per day
+    pub module_daily: Option<u64>,
+}
+
+/// A transfer refused by a module quota
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{scope} quota of module {module} exceeded: {used} of {limit} bytes used today, transfer needs {requested} more")]
+pub struct QuotaExceeded {
+    /// Module the transfer was for
+    pub module: String,
+    /// `"per-client"` or `"module"`
+    pub scope: &'static str,
+    /// Bytes already used today within that scope
+    pub used: u64,
+    /// The daily limit
+    pub limit: u64,
+    /// Bytes the refused transfer would have moved
+    pub requested: u64,
+}
+
+/// Key of one ledger row
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    day: Day,
+    module: String,
+    client: String,
+}
+
+/// Transfer accounting by day, module and client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageLedger {
+    rows: BTreeMap<Key, Usage>,
+}
+
+impl UsageLedger {
+    /// Load the ledger; a missing file is an empty ledger
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is malformed.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(contents) => Self::decode(&contents)
+                .with_context(|| format!("Corrupt usage ledger {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read usage ledger {}", path.display()))
+            }
+        }
+    }
+
+    /// Write the ledger atomically (temporary file and rename)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let staging = path.with_file_name(temp_name(path.file_name().unwrap_or_default()));
+        std::fs::write(&staging, self.encode())
+            .and_then(|()| std::fs::rename(&staging, path))
+            .with_context(|| format!("Failed to write usage ledger {}", path.display()))
+    }
+
+    /// Add a finished (or aborted) session's traffic
+    pub fn record(&mut self, day: Day, module: &str, client: &str, usage: Usage) {
+        let key = Key {
+            day,
+            module: module.to_string(),
+            client: client.to_string(),
+        };
+        self.rows.entry(key).or_default().add(usage);
+    }
+
+    /// Check that moving `requested` more bytes keeps `client` within the
+    /// module's quota for `day`
+    ///
+    /// # Errors
+    ///
+    /// Returns `QuotaExceeded` naming the first limit that would be exceeded.
+    pub fn admit(
+        &self,
+        day: Day,
+        module: &str,
+        client: &str,
+        requested: u64,
+        quota: Quota,
+    ) -> std::result::Result<(), QuotaExceeded> {
+        let day_rows = self
+            .rows
+            .iter()
+            .filter(|(key, _)| key.day == day && key.module == module);
+        let (mut client_used, mut module_used) = (0u64, 0u64);
+        for (key, usage) in day_rows {
+            module_used = module_used.saturating_add(usage.total());
+            if key.client == client {
+                client_used = client_used.saturating_add(usage.total());
+            }
+        }
+        for (scope, used, limit) in [
+            ("per-client", client_used, quota.client_daily),
+            ("module", module_used, quota.module_daily),
+        ] {
+            if let Some(limit) = limit {
+                if used.saturating_add(requested) > limit {
+                    return Err(QuotaExceeded {
+                        module: module.to_string(),
+                        scope,
+                        used,
+                        limit,
+                        requested,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows from `since` on, optionally restricted to one module and/or
+    /// client, in day, module, client order
+    pub fn query<'a>(
+        &'a self,
+        since: Day,
+        module: Option<&'a str>,
+        client: Option<&'a str>,
+    ) -> impl Iterator<Item = (Day, &'a str, &'a str, Usage)> + 'a {
+        self.rows
+            .iter()
+            .filter(move |(key, _)| {
+                key.day >= since
+                    && module.is_none_or(|module| key.module == module)
+                    && client.is_none_or(|client| key.client == client)
+            })
+            .map(|(key, usage)| (key.day, key.module.as_str(), key.client.as_str(), *usage))
+    }
+
+    /// Drop rows older than `before` to keep the ledger small
+    pub fn prune(&mut self, before: Day) {
+        self.rows.retain(|key, _| key.day >= before);
+    }
+
+    /// One line per row: `day module client bytes_in bytes_out` (tab-separated)
+    fn encode(&self) -> Vec<u8> {
+        let mut out = format!("{LEDGER_HEADER}\n").into_bytes();
+        for (key, usage) in &self.rows {
+            out.extend_from_slice(format!("{}\t", key.day).as_bytes());
+            escape_path(OsStr::new(&key.module), &mut out);
+            out.push(b'\t');
+            escape_path(OsStr::new(&key.client), &mut out);
+            out.extend_from_slice(
+                format!("\t{}\t{}\n", usage.bytes_in, usage.bytes_out).as_bytes(),
+            );
+        }
+        out
+    }
+
+    fn decode(contents: &[u8]) -> Result<Self> {
+        let mut lines = contents.split(|&b| b == b'\n');
+        anyhow::ensure!(
+            lines.next() == Some(LEDGER_HEADER.as_bytes()),
+            "missing {LEDGER_HEADER:?} header"
+        );
+        let mut ledger = Self::default();
+        for (number, line) in lines.enumerate().filter(|(_, line)| !line.is_empty()) {
+            let (key, usage) =
+                Self::decode_row(line).with_context(|| format!("bad row {}", number + 2))?;
+            ledger.rows.entry(key).or_default().add(usage);
+        }
+        Ok(ledger)
+    }
+
+    fn decode_row(line: &[u8]) -> Option<(Key, Usage)> {
+        let mut fields = line.split(|&b| b == b'\t');
+        let day = Day::parse(std::str::from_utf8(fields.next()?).ok()?)?;
+        let mut text = || unescape_path(fields.next()?)?.into_string().ok();
+        let module = text()?;
+        let client = text()?;
+        let mut number = || std::str::from_utf8(fields.next()?).ok()?.parse().ok();
+        let usage = Usage {
+            bytes_in: number()?,
+            bytes_out: number()?,
+        };
+        fields.next().is_none().then_some((
+            Key {
+                day,
+                module,
+                client,
+            },
+            usage,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_day_calendar_round_trip() {
+        // Requirement: Days are UTC calendar dates, stored as YYYY-MM-DD
+        assert_eq!(Day(0).to_string(), "1970-01-01");
+        assert_eq!(Day(19_782).to_string(), "2024-02-29");
+        let day = Day::of(SystemTime::UNIX_EPOCH + Duration::from_secs(1_791_935_999));
+        assert_eq!(day.to_string(), "2026-10-13");
+        for n in [0, 59, 60, 365, 10_957, 19_782, 20_740, 47_482] {
+            assert_eq!(Day::parse(&Day(n).to_string()), Some(Day(n)));
+        }
+        assert_eq!(Day::parse("2026-02-30"), None);
+        assert_eq!(Day::parse("2026-13-01"), None);
+        assert_eq!(Day::parse("yesterday"), None);
+    }
+
+    #[test]
+    fn test_today_follows_clock() {
+        // Requirement: Quota days roll over at UTC midnight, unaffected by
+        // leap seconds and local DST changes; pre-epoch clocks count as day 0
+        use crate::clock::MockClock;
+
+        // 2016-12-31 ended with a leap second, which Unix time does not count
+        let clock = MockClock::at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_483_228_799));
+        assert_eq!(Day::today(&clock).to_string(), "2016-12-31");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Day::today(&clock).to_string(), "2017-01-01");
+
+        // US daylight saving time began on 2026-03-08 at 07:00 UTC
+        clock.set(SystemTime::UNIX_EPOCH + Duration::from_secs(1_772_953_200));
+        let before = Day::today(&clock);
+        clock.advance(Duration::from_secs(23 * 3600));
+        assert_eq!(Day::today(&clock).0, before.0 + 1);
+
+        clock.set(SystemTime::UNIX_EPOCH - Duration::from_secs(86_400));
+        assert_eq!(Day::today(&clock), Day(0));
+    }
+
+    #[test]
+    fn test_quota_admission() {
+        // Requirement: Transfers that would exceed a client's or the module's
+        // daily quota are refused; other days and modules do not count
+        let today = Day(20_740);
+        let mut ledger = UsageLedger::default();
+        let usage = |bytes_in, bytes_out| Usage {
+            bytes_in,
+            bytes_out,
+        };
+        ledger.record(today, "backups", "alice", usage(60, 20));
+        ledger.record(today, "backups", "bob", usage(50, 0));
+        ledger.record(Day(20_739), "backups", "alice", usage(1000, 0));
+        ledger.record(today, "media", "alice", usage(1000, 0));
+
+        let quota = Quota {
+            client_daily: Some(100),
+            module_daily: Some(150),
+        };
+        assert!(ledger.admit(today, "backups", "alice", 20, quota).is_ok());
+        let refused = ledger
+            .admit(today, "backups", "alice", 21, quota)
+            .unwrap_err();
+        assert_eq!(
+            (refused.scope, refused.used, refused.limit),
+            ("per-client", 80, 100)
+        );
+        let refused = ledger
+            .admit(today, "backups", "carol", 30, quota)
+            .unwrap_err();
+        assert_eq!(
+            (refused.scope, refused.used, refused.limit),
+            ("module", 130, 150)
+        );
+        assert!(ledger.admit(today, "backups", "carol", 20, quota).is_ok());
+        assert!(ledger
+            .admit(today, "media", "alice", 1 << 40, Quota::default())
+            .is_ok());
+        assert!(ledger
+            .admit(Day(20_741), "backups", "alice", 100, quota)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_ledger_round_trip_and_query() {
+        // Requirement: The ledger persists across restarts, accumulates
+        // repeated sessions, and can be queried by module, client and day
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("usage.ledger");
+        let mut ledger = UsageLedger::load(&path).unwrap();
+        assert_eq!(ledger, UsageLedger::default());
+
+        let usage = Usage {
+            bytes_in: 10,
+            bytes_out: 5,
+        };
+        ledger.record(Day(20_739), "backups", "10.0.0.7", usage);
+        ledger.record(Day(20_740), "backups", "10.0.0.7", usage);
+        ledger.record(Day(20_740), "backups", "10.0.0.7", usage);
+        ledger.record(Day(20_740), "odd\tname", "host\n2", usage);
+        ledger.save(&path).unwrap();
+
+        let loaded = UsageLedger::load(&path).unwrap();
+        assert_eq!(loaded, ledger);
+        let rows: Vec<_> = loaded.query(Day(20_740), Some("backups"), None).collect();
+        assert_eq!(
+            rows,
+            [(
+                Day(20_740),
+                "backups",
+                "10.0.0.7",
+                Usage {
+                    bytes_in: 20,
+                    bytes_out: 10
+                }
+            )]
+        );
+        assert_eq!(loaded.query(Day(0), None, Some("host\n2")).count(), 1);
+
+        ledger.prune(Day(20_740));
+        assert_eq!(ledger.query(Day(0), None, None).count(), 2);
+
+        std::fs::write(&path, "arsync-usage v1\n2026-10-15\tbackups\n").unwrap();
+        assert!(UsageLedger::load(&path).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! Versioned capability negotiation for the native arsync pipe protocol
+//!
+//! After the version byte exchange, both peers send a capability frame and
+//! agree on the features they both understand. The frame is designed so that
+//! older and newer arsync builds can always talk to each other:
+//!
+//! - Each capability is a `(id, value)` entry; unknown ids are skipped, so a
+//!   newer peer may advertise features this build has never heard of.
+//! - The frame body is length-prefixed and trailing bytes are ignored, so later
+//!   versions may append header fields without breaking older decoders.
+//! - Each side states the oldest capability version it can still speak
+//!   (`min_version`); the negotiated version is the lower of the two current
+//!   versions, and negotiation fails only if that falls below either minimum.
+//!
+//! # Wire Format
+//!
+//! ```text
+//! magic "ACAP" | u32 body length | body
+//! body: u16 version | u16 min_version | u16 count | count × entry | (ignored)
+//! entry: u32 id | u16 value length | value bytes
+//! ```
+//!
+//! All integers are little-endian.
+//!
+//! # Compatibility Rules
+//!
+//! When adding a feature: add a `Capability` constant, append a new entry to
+//! `HISTORY` with the bumped version, bump `CAPABILITY_VERSION`, and add a
+//! fixture for the new version under `tests/fixtures/protocol/`. Existing
+//! fixtures must never change.
+#![allow(dead_code)] // Protocol implementation not yet fully used
+
+use crate::protocol::transport::{self, Transport};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Capability version spoken by this build
+pub const CAPABILITY_VERSION: u16 = 3;
+
+/// Oldest capability version this build can still negotiate down to
+pub const MIN_CAPABILITY_VERSION: u16 = 1;
+
+/// Frame magic
+const MAGIC: &[u8; 4] = b"ACAP";
+
+/// Upper bound on a peer's frame body (guards against garbage input)
+const MAX_FRAME_BODY: usize = 64 * 1024;
+
+/// Capabilities introduced in each capability version (cumulative)
+const HISTORY: &[(u16, &[Capability])] = &[
+    (
+        1,
+        &[Capability::SEGMENTED_DELTA, Capability::RESUMABLE_SESSIONS],
+    ),
+    (2, &[Capability::XATTR_BATCH]),
+    (3, &[Capability::COMPRESSION]),
+];
+
+// ============================================================================
+// Capability
+// ============================================================================
+
+/// Identifier of one protocol feature
+///
+/// A newtype rather than an enum so ids advertised by newer peers can be
+/// represented (and ignored) without failing to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Capability(pub u32);
+
+impl Capability {
+    /// File contents are sent as length-prefixed delta segments
+    pub const SEGMENTED_DELTA: Self = Self(1);
+
+    /// Receiver checkpoints and session tokens (`protocol::session`)
+    pub const RESUMABLE_SESSIONS: Self = Self(2);
+
+    /// Per-file batched xattr frames (`protocol::xattr_batch`)
+    pub const XATTR_BATCH: Self = Self(3);
+
+    /// zstd-compressed literal data (`protocol::compress`); the value is
+    /// the level the peer asks for, empty if it did not ask
+    pub const COMPRESSION: Self = Self(4);
+
+    /// Human-readable name (for error messages and logs)
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::SEGMENTED_DELTA => "segmented-delta",
+            Self::RESUMABLE_SESSIONS => "resumable-sessions",
+            Self::XATTR_BATCH => "xattr-batch",
+            Self::COMPRESSION => "compression",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.name(), self.0)
+    }
+}
+
+// ============================================================================
+// CapabilitySet
+// ============================================================================
+
+/// Capabilities advertised by one peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilitySet {
+    /// Capability version the peer speaks
+    pub version: u16,
+    /// Oldest capability version the peer can negotiate down to
+    pub min_version: u16,
+    /// Advertised capabilities with their (capability-specific) parameters
+    pub entries: BTreeMap<Capability, Vec<u8>>,
+}
+
+impl CapabilitySet {
+    /// The capability set of this build
+    #[must_use]
+    pub fn current() -> Self {
+        Self::for_version(CAPABILITY_VERSION).unwrap_or_else(|| Self {
+            version: CAPABILITY_VERSION,
+            min_version: MIN_CAPABILITY_VERSION,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    /// The capability set a build speaking `version` advertises
+    ///
+    /// Returns `None` for versions this build does not know about.
+    #[must_use]
+    pub fn for_version(version: u16) -> Option<Self> {
+        if version == 0 || version > CAPABILITY_VERSION {
+            return None;
+        }
+        let entries = HISTORY
+            .iter()
+            .filter(|(introduced, _)| *introduced <= version)
+            .flat_map(|(_, caps)| caps.iter())
+            .map(|&cap| (cap, Vec::new()))
+            .collect();
+        Some(Self {
+            version,
+            min_version: MIN_CAPABILITY_VERSION.min(version),
+            entries,
+        })
+    }
+
+    /// Check whether a capability is advertised
+    #[must_use]
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.entries.contains_key(&capability)
+    }
+
+    /// Encode as a complete frame (magic, length, body)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the set has more than 65535 entries or a value
+    /// longer than 65535 bytes.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.version.to_le_bytes());
+        body.extend_from_slice(&self.min_version.to_le_bytes());
+        body.extend_from_slice(&u16::try_from(self.entries.len())?.to_le_bytes());
+        for (capability, value) in &self.entries {
+            body.extend_from_slice(&capability.0.to_le_bytes());
+            body.extend_from_slice(&u16::try_from(value.len())?.to_le_bytes());
+            body.extend_from_slice(value);
+        }
+
+        let mut frame = Vec::with_capacity(MAGIC.len() + 4 + body.len());
+        frame.extend_from_slice(MAGIC);
+        frame.extend_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+
+    /// Decode a complete frame
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the magic is wrong or the frame is truncated.
+    pub fn decode(frame: &[u8]) -> Result<Self> {
+        let mut reader = FrameReader(frame);
+        if reader.take(MAGIC.len())? != MAGIC {
+            anyhow::bail!("Not an arsync capability frame (bad magic)");
+        }
+        let len = u32::from_le_bytes(reader.array()?) as usize;
+        Self::decode_body(reader.take(len)?)
+    }
+
+    /// Decode a frame body, ignoring trailing bytes added by newer versions
+    fn decode_body(body: &[u8]) -> Result<Self> {
+        let mut reader = FrameReader(body);
+        let version = u16::from_le_bytes(reader.array()?);
+        let min_version = u16::from_le_bytes(reader.array()?);
+        let count = u16::from_le_bytes(reader.array()?);
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let id = u32::from_le_bytes(reader.array()?);
+            let len = u16::from_le_bytes(reader.array()?) as usize;
+            entries.insert(Capability(id), reader.take(len)?.to_vec());
+        }
+        Ok(Self {
+            version,
+            min_version,
+            entries,
+        })
+    }
+}
+
+/// Minimal bounds-checked reader over a byte slice
+struct FrameReader<'a>(&'a [u8]);
+
+impl<'a> FrameReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            anyhow::bail!("Truncated capability frame");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+}
+
+// ============================================================================
+// Negotiation
+// ============================================================================
+
+/// Result of negotiating with a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedCapabilities {
+    /// Capability version both sides use for this session
+    pub version: u16,
+    /// Capabilities both sides support, with the peer's parameters
+    pub enabled: BTreeMap<Capability, Vec<u8>>,
+}
+
+impl NegotiatedCapabilities {
+    /// Negotiate between our set and the peer's
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the versions are incompatible (one side is older
+    /// than the other's minimum).
+    pub fn negotiate(local: &CapabilitySet, remote: &CapabilitySet) -> Result<Self> {
+        if remote.version < local.min_version {
+            anyhow::bail!(
+                "Remote arsync speaks capability version {} but this build needs at least {}; upgrade the remote arsync",
+                remote.version,
+                local.min_version
+            );
+        }
+        if local.version < remote.min_version {
+            anyhow::bail!(
+                "Remote arsync needs capability version {} but this build only speaks {}; upgrade the local arsync",
+                remote.min_version,
+                local.version
+            );
+        }
+
+        let enabled = remote
+            .entries
+            .iter()
+            .filter(|(capability, _)| local.contains(**capability))
+            .map(|(capability, value)| (*capability, value.clone()))
+            .collect();
+        Ok(Self {
+            version: local.version.min(remote.version),
+            enabled,
+        })
+    }
+
+    /// Check whether a capability was negotiated
+    #[must_use]
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.enabled.contains_key(&capability)
+    }
+
+    /// Fail unless a capability was negotiated
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the capability if the peer does not support it.
+    pub fn require(&self, capability: Capability) -> Result<()> {
+        if !self.supports(capability) {
+            anyhow::bail!("Remote arsync does not support required capability {capability}");
+        }
+        Ok(())
+    }
+}
+
+/// Exchange capability frames with the peer and negotiate
+///
+/// Both sides send first and then read, so the exchange is symmetric and
+/// does not depend on the sender/receiver role.
+///
+/// # Errors
+///
+/// Returns an error if I/O fails, the peer's frame is malformed or oversized,
+/// or the versions are incompatible.
+pub async fn exchange_capabilities<T: Transport>(
+    transport: &mut T,
+    local: &CapabilitySet,
+) -> Result<NegotiatedCapabilities> {
+    transport::write_all(transport, &local.encode()?).await?;
+
+    let mut header = [0u8; 8];
+    transport::read_exact(transport, &mut header).await?;
+    if &header[..4] != MAGIC {
+        anyhow::bail!("Remote did not send a capability frame (is it an older arsync?)");
+    }
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > MAX_FRAME_BODY {
+        anyhow::bail!("Remote capability frame too large: {len} bytes");
+    }
+    let mut body = vec![0u8; len];
+    transport::read_exact(transport, &mut body).await?;
+
+    let remote = CapabilitySet::decode_body(&body)?;
+    NegotiatedCapabilities::negotiate(local, &remote)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_current_round_trip() {
+        // Test: The current set survives encode/decode unchanged
+        let current = CapabilitySet::current();
+        assert_eq!(current.version, CAPABILITY_VERSION);
+        assert!(current.contains(Capability::RESUMABLE_SESSIONS));
+        let decoded = CapabilitySet::decode(&current.encode().unwrap()).unwrap();
+        assert_eq!(decoded, current);
+    }
+
+    #[test]
+    fn test_negotiate_ignores_unknown_and_rejects_too_old() {
+        // Test: Unknown capabilities are dropped; incompatible versions fail
+        // Requirement: Newer peers must not break older builds and vice versa
+        let local = CapabilitySet::current();
+        let mut remote = CapabilitySet::current();
+        remote.version = CAPABILITY_VERSION + 3;
+        remote.entries.insert(Capability(999), vec![1, 2, 3]);
+        remote.entries.remove(&Capability::SEGMENTED_DELTA);
+
+        let negotiated = NegotiatedCapabilities::negotiate(&local, &remote).unwrap();
+        assert_eq!(negotiated.version, CAPABILITY_VERSION);
+        assert!(negotiated.supports(Capability::RESUMABLE_SESSIONS));
+        assert!(!negotiated.supports(Capability(999)));
+        assert!(negotiated.require(Capability::SEGMENTED_DELTA).is_err());
+
+        remote.min_version = CAPABILITY_VERSION + 1;
+        assert!(NegotiatedCapabilities::negotiate(&local, &remote).is_err());
+    }
+}
@@ -0,0 +1,724 @@
+//! Compression of file data on the wire (`--compress`, `--compress-level`)
+//!
+//! Only file data is compressed; file lists, block checksums and other
+//! metadata travel as they are.
+//!
+//! - Between arsync peers (the pipe protocol) the `COMPRESSION` capability
+//!   carries the level each side asks for. If either side asks, literal
+//!   data is sent as zstd frames at the lower of the two levels; a literal
+//!   that does not shrink is sent raw.
+//! - Protocol 30 has no codec negotiation, so with stock rsync (`rsync_sender`,
+//!   `rsync_receiver`) `-z` means rsync's deflated token stream, which this
+//!   module speaks on both sides.
+//!
+//! # rsync's deflated token stream (`token.c`)
+//!
+//! ```text
+//! 0x00                              end of the file
+//! 0x20 | int32 index                block (TOKEN_LONG)
+//! 0x21 | int32 index | u16 n        block and the n blocks after it
+//! 0x40 + (len >> 8) | len & 0xff    len (< 16384) bytes of deflated data
+//! 0x80 + r                          block r after the previous run's last
+//! 0xc0 + r | u16 n                  the same, and the n blocks after it
+//! ```
+//!
+//! Literal data is one raw deflate stream per file, cut with a sync flush
+//! before every block reference; the flush's closing `00 00 ff ff` is left
+//! off the wire. With `zlib` both sides also add every matched block to the
+//! compression history, so later literals can refer back to it; `zlibx`
+//! (`--new-compress`) does not. rsync adds blocks with a patched zlib's
+//! `Z_INSERT_ONLY`; here the sender compresses the block with a sync flush
+//! and discards the output, which leaves the same window behind.
+#![allow(clippy::cast_possible_truncation)] // Wire lengths are bounded
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+
+use crate::protocol::capabilities::{Capability, NegotiatedCapabilities};
+use crate::protocol::rsync_wire::{put_int, Channel};
+use crate::protocol::transport::Transport;
+use anyhow::{Context, Result};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use std::fmt;
+
+/// zstd level when none is given
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// zlib level when none is given (rsync's default)
+const DEFAULT_ZLIB_LEVEL: u32 = 6;
+
+/// Highest zlib level
+const MAX_ZLIB_LEVEL: u32 = 9;
+
+/// Token: end of the file's data
+const END_FLAG: u8 = 0x00;
+/// Token: a block by absolute index
+const TOKEN_LONG: u8 = 0x20;
+/// Token: a run of blocks from an absolute index
+const TOKENRUN_LONG: u8 = 0x21;
+/// Token: deflated data follows
+const DEFLATED_DATA: u8 = 0x40;
+/// Token: a block relative to the previous run
+const TOKEN_REL: u8 = 0x80;
+/// Token: a run of blocks relative to the previous run
+const TOKENRUN_REL: u8 = 0xc0;
+
+/// Most deflated bytes in one `DEFLATED_DATA` packet
+const MAX_DATA_COUNT: usize = 16383;
+
+/// Blocks one run token can cover
+const MAX_RUN: i32 = 65536;
+
+/// Most history added in one step (the length of a stored deflate block)
+const MAX_STORED: usize = 0xffff;
+
+/// The end of a sync flush, which the sender leaves off the wire
+const SYNC_TRAILER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+/// Output reserved per (de)compression call
+const OUT_CHUNK: usize = 32 * 1024;
+
+// ============================================================================
+// Levels
+// ============================================================================
+
+/// Compression level asked for with `--compress` / `--compress-level`
+///
+/// [`CompressLevel::DEFAULT`] leaves the level to the codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressLevel(u8);
+
+impl CompressLevel {
+    /// The codec's default level
+    pub const DEFAULT: Self = Self(0);
+
+    /// Highest level (zstd's; zlib stops at 9)
+    pub const MAX: u8 = 22;
+
+    /// Level `level`, capped at [`Self::MAX`]; 0 is the codec's default
+    #[must_use]
+    pub const fn new(level: u8) -> Self {
+        Self(if level > Self::MAX { Self::MAX } else { level })
+    }
+
+    /// The zstd level
+    #[must_use]
+    pub fn zstd(self) -> i32 {
+        match self.0 {
+            0 => DEFAULT_ZSTD_LEVEL,
+            level => i32::from(level),
+        }
+    }
+
+    /// The zlib level
+    #[must_use]
+    pub fn zlib(self) -> u32 {
+        match self.0 {
+            0 => DEFAULT_ZLIB_LEVEL,
+            level => u32::from(level).min(MAX_ZLIB_LEVEL),
+        }
+    }
+
+    /// The level of a pipe session: if either side asks for compression,
+    /// the lower of the levels asked for
+    #[must_use]
+    pub fn negotiate(local: Option<Self>, remote: Option<Self>) -> Option<Self> {
+        match (local, remote) {
+            (Some(a), Some(b)) => Some(if a.zstd() <= b.zstd() { a } else { b }),
+            (level, None) | (None, level) => level,
+        }
+    }
+
+    /// Value of the `COMPRESSION` capability: the level asked for, if any
+    #[must_use]
+    pub fn capability_value(level: Option<Self>) -> Vec<u8> {
+        level.map(|level| vec![level.0]).unwrap_or_default()
+    }
+
+    /// The level a peer asked for in its `COMPRESSION` capability
+    #[must_use]
+    pub fn from_capability_value(value: &[u8]) -> Option<Self> {
+        value.first().map(|&level| Self::new(level))
+    }
+}
+
+impl fmt::Display for CompressLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "default"),
+            level => write!(f, "{level}"),
+        }
+    }
+}
+
+/// Compression level of a pipe session, from what we asked for and what
+/// the peer advertised
+///
+/// `None` if neither side asked or the peer cannot decompress.
+#[must_use]
+pub fn session_level(
+    local: Option<CompressLevel>,
+    negotiated: &NegotiatedCapabilities,
+) -> Option<CompressLevel> {
+    let remote = negotiated.enabled.get(&Capability::COMPRESSION)?;
+    CompressLevel::negotiate(local, CompressLevel::from_capability_value(remote))
+}
+
+// ============================================================================
+// zstd literals (pipe protocol)
+// ============================================================================
+
+/// Compresses literal data for the pipe protocol
+pub struct LiteralCompressor(zstd::bulk::Compressor<'static>);
+
+impl LiteralCompressor {
+    /// Compressor at `level`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if zstd cannot allocate its context.
+    pub fn new(level: CompressLevel) -> Result<Self> {
+        let compressor = zstd::bulk::Compressor::new(level.zstd())
+            .context("Failed to set up zstd compression")?;
+        Ok(Self(compressor))
+    }
+
+    /// `data` as a zstd frame, or `None` if that is not smaller
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if compression fails.
+    pub fn compress(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let compressed = self.0.compress(data).context("zstd compression failed")?;
+        Ok((compressed.len() < data.len()).then_some(compressed))
+    }
+}
+
+/// Decompresses literal data of the pipe protocol
+pub struct LiteralDecompressor(zstd::bulk::Decompressor<'static>);
+
+impl LiteralDecompressor {
+    /// A decompressor
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if zstd cannot allocate its context.
+    pub fn new() -> Result<Self> {
+        let decompressor =
+            zstd::bulk::Decompressor::new().context("Failed to set up zstd decompression")?;
+        Ok(Self(decompressor))
+    }
+
+    /// Decompress a literal that was `len` bytes long
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame is corrupt or does not decompress to
+    /// exactly `len` bytes.
+    pub fn decompress(&mut self, data: &[u8], len: usize) -> Result<Vec<u8>> {
+        let literal = self
+            .0
+            .decompress(data, len)
+            .context("Corrupt compressed literal")?;
+        if literal.len() != len {
+            anyhow::bail!(
+                "Compressed literal decompressed to {} bytes, expected {len}",
+                literal.len()
+            );
+        }
+        Ok(literal)
+    }
+}
+
+// ============================================================================
+// rsync's deflated token stream
+// ============================================================================
+
+/// Codec of rsync's compressed token stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenCodec {
+    /// Matched blocks join the compression history too (`-z`, `--old-compress`)
+    Zlib,
+    /// Only literal data is in the history (`--new-compress`)
+    ZlibX,
+}
+
+/// Sending side of the token stream (`send_deflated_token`)
+///
+/// One per session; [`Self::end`] readies it for the next file.
+pub(super) struct TokenDeflater {
+    stream: Compress,
+    /// Add matched blocks to the history (`zlib`)
+    history: bool,
+    /// First and last block of the run not yet sent
+    run: Option<(i32, i32)>,
+    /// Last block of the previous run sent (relative tokens count from it)
+    last_run_end: i32,
+    /// Literal data was deflated since the last sync flush
+    flush_pending: bool,
+    /// Deflated bytes not yet sent
+    pending: Vec<u8>,
+    /// Output of history updates (discarded)
+    scratch: Vec<u8>,
+}
+
+impl TokenDeflater {
+    pub(super) fn new(level: CompressLevel, codec: TokenCodec) -> Self {
+        Self {
+            stream: Compress::new(Compression::new(level.zlib()), false),
+            history: codec == TokenCodec::Zlib,
+            run: None,
+            last_run_end: 0,
+            flush_pending: false,
+            pending: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Literal file data
+    pub(super) fn literal(&mut self, out: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+        self.send_run(out);
+        deflate(
+            &mut self.stream,
+            data,
+            &mut self.pending,
+            FlushCompress::None,
+        )?;
+        self.flush_pending = true;
+        // Full packets can go now; the flush trims the end of the last one
+        let full = self.pending.len() - self.pending.len() % MAX_DATA_COUNT;
+        for packet in self.pending[..full].chunks(MAX_DATA_COUNT) {
+            send_data(out, packet);
+        }
+        self.pending.drain(..full);
+        Ok(())
+    }
+
+    /// A reference to block `index`, whose data is `block`
+    pub(super) fn matched(&mut self, out: &mut Vec<u8>, index: i32, block: &[u8]) -> Result<()> {
+        self.sync(out)?;
+        self.run = match self.run {
+            Some((start, last)) if index == last + 1 && index < start + MAX_RUN => {
+                Some((start, index))
+            }
+            _ => {
+                self.send_run(out);
+                Some((index, index))
+            }
+        };
+        if self.history {
+            // Protocol 30 adds a long block's first 64K again and again
+            // (fixed in protocol 31); the receiver does the same
+            let mut left = block.len();
+            while left > 0 {
+                let n = left.min(MAX_STORED);
+                left -= n;
+                self.scratch.clear();
+                deflate(
+                    &mut self.stream,
+                    &block[..n],
+                    &mut self.scratch,
+                    FlushCompress::Sync,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// End of the file's data
+    pub(super) fn end(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        self.sync(out)?;
+        self.send_run(out);
+        out.push(END_FLAG);
+        self.stream.reset();
+        self.last_run_end = 0;
+        Ok(())
+    }
+
+    /// Finish the literal data at a sync point and send the rest of it
+    fn sync(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        if !std::mem::take(&mut self.flush_pending) {
+            return Ok(());
+        }
+        deflate(
+            &mut self.stream,
+            &[],
+            &mut self.pending,
+            FlushCompress::Sync,
+        )?;
+        if !self.pending.ends_with(&SYNC_TRAILER) {
+            anyhow::bail!("deflate did not end at a sync point");
+        }
+        self.pending
+            .truncate(self.pending.len() - SYNC_TRAILER.len());
+        for packet in self.pending.chunks(MAX_DATA_COUNT) {
+            send_data(out, packet);
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Send the pending run of blocks
+    fn send_run(&mut self, out: &mut Vec<u8>) {
+        let Some((start, last)) = self.run.take() else {
+            return;
+        };
+        let rel = start - self.last_run_end;
+        let count = last - start;
+        if (0..64).contains(&rel) {
+            let base = if count == 0 { TOKEN_REL } else { TOKENRUN_REL };
+            out.push(base + rel as u8);
+        } else {
+            out.push(if count == 0 {
+                TOKEN_LONG
+            } else {
+                TOKENRUN_LONG
+            });
+            put_int(out, start);
+        }
+        if count != 0 {
+            out.extend_from_slice(&(count as u16).to_le_bytes());
+        }
+        self.last_run_end = last;
+    }
+}
+
+/// One step of a file's data in the token stream
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Token {
+    /// Literal data
+    Literal(Vec<u8>),
+    /// A reference to the receiver's block with this index
+    Block(i32),
+    /// End of the file's data
+    End,
+}
+
+/// Receiving side of the token stream (`recv_deflated_token`)
+pub(super) struct TokenInflater {
+    stream: Decompress,
+    /// Add matched blocks to the history (`zlib`)
+    history: bool,
+    /// Index of the last block returned
+    token: i32,
+    /// Blocks left in the current run
+    run: u16,
+    /// Data was inflated since the last sync point
+    inflated: bool,
+    /// Flag read before the last of the inflated data was returned
+    saved_flag: Option<u8>,
+}
+
+impl TokenInflater {
+    pub(super) fn new(codec: TokenCodec) -> Self {
+        Self {
+            stream: Decompress::new(false),
+            history: codec == TokenCodec::Zlib,
+            token: 0,
+            run: 0,
+            inflated: false,
+            saved_flag: None,
+        }
+    }
+
+    /// Read the next step of the current file
+    pub(super) async fn read<T: Transport>(
+        &mut self,
+        channel: &mut Channel<'_, T>,
+    ) -> Result<Token> {
+        if self.run > 0 {
+            self.run -= 1;
+            self.token = self.token.wrapping_add(1);
+            return Ok(Token::Block(self.token));
+        }
+        loop {
+            let flag = match self.saved_flag.take() {
+                Some(flag) => flag,
+                None => channel.read_u8().await?,
+            };
+            if flag & 0xc0 == DEFLATED_DATA {
+                let len = (usize::from(flag & 0x3f) << 8) | usize::from(channel.read_u8().await?);
+                let data = channel.read_bytes(len).await?;
+                let literal = inflate(&mut self.stream, data, FlushDecompress::None)?;
+                self.inflated = true;
+                if literal.is_empty() {
+                    continue;
+                }
+                return Ok(Token::Literal(literal));
+            }
+            if std::mem::take(&mut self.inflated) {
+                // The data ended at a sync point: add the trailer the sender
+                // left off, and return whatever it still releases
+                let rest = inflate(&mut self.stream, &SYNC_TRAILER, FlushDecompress::Sync)?;
+                if !rest.is_empty() {
+                    self.saved_flag = Some(flag);
+                    return Ok(Token::Literal(rest));
+                }
+            }
+            let run = match flag {
+                END_FLAG => {
+                    self.stream.reset(false);
+                    self.token = 0;
+                    return Ok(Token::End);
+                }
+                TOKEN_LONG | TOKENRUN_LONG => {
+                    self.token = channel.read_int().await?;
+                    flag == TOKENRUN_LONG
+                }
+                _ if flag & TOKEN_REL != 0 => {
+                    self.token = self.token.wrapping_add(i32::from(flag & 0x3f));
+                    flag & TOKENRUN_REL == TOKENRUN_REL
+                }
+                _ => anyhow::bail!("Invalid compressed token {flag:#x} from rsync client"),
+            };
+            if run {
+                let count = channel.read_bytes(2).await?;
+                self.run = u16::from_le_bytes([count[0], count[1]]);
+            }
+            return Ok(Token::Block(self.token));
+        }
+    }
+
+    /// Add matched block `block` to the history, as the sender did
+    pub(super) fn see_block(&mut self, block: &[u8]) -> Result<()> {
+        if !self.history {
+            return Ok(());
+        }
+        // Protocol 30 adds a long block's first 64K again and again
+        let mut left = block.len();
+        while left > 0 {
+            let n = left.min(MAX_STORED);
+            left -= n;
+            // A stored deflate block: not final, length, its complement
+            let len = (n as u16).to_le_bytes();
+            let mut stored = vec![0, len[0], len[1], !len[0], !len[1]];
+            stored.extend_from_slice(&block[..n]);
+            inflate(&mut self.stream, &stored, FlushDecompress::Sync)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `DEFLATED_DATA` packet
+fn send_data(out: &mut Vec<u8>, packet: &[u8]) {
+    out.push(DEFLATED_DATA + (packet.len() >> 8) as u8);
+    out.push(packet.len() as u8);
+    out.extend_from_slice(packet);
+}
+
+/// Deflate all of `input` into `output`, then flush as `flush` asks
+fn deflate(
+    stream: &mut Compress,
+    mut input: &[u8],
+    output: &mut Vec<u8>,
+    flush: FlushCompress,
+) -> Result<()> {
+    loop {
+        output.reserve(OUT_CHUNK);
+        let before = stream.total_in();
+        stream
+            .compress_vec(input, output, flush)
+            .context("deflate failed")?;
+        input = &input[(stream.total_in() - before) as usize..];
+        // Done once the input is in and the output had room to spare
+        if input.is_empty() && output.len() < output.capacity() {
+            return Ok(());
+        }
+    }
+}
+
+/// Inflate all of `input`
+fn inflate(stream: &mut Decompress, mut input: &[u8], flush: FlushDecompress) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    loop {
+        output.reserve(OUT_CHUNK);
+        let (before_in, before_out) = (stream.total_in(), stream.total_out());
+        stream
+            .decompress_vec(input, &mut output, flush)
+            .context("Corrupt compressed data from rsync client")?;
+        input = &input[(stream.total_in() - before_in) as usize..];
+        if input.is_empty() && output.len() < output.capacity() {
+            return Ok(output);
+        }
+        if stream.total_in() == before_in && stream.total_out() == before_out {
+            anyhow::bail!("Corrupt compressed data from rsync client (trailing bytes)");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::protocol::pipe::PipeTransport;
+
+    /// A file's token stream: (literal before the block, block index)
+    /// pairs, then a literal tail
+    fn deflate_file(
+        deflater: &mut TokenDeflater,
+        steps: &[(&[u8], i32)],
+        tail: &[u8],
+        blocks: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (literal, index) in steps {
+            if !literal.is_empty() {
+                deflater.literal(&mut out, literal).unwrap();
+            }
+            deflater
+                .matched(&mut out, *index, &blocks[*index as usize])
+                .unwrap();
+        }
+        if !tail.is_empty() {
+            deflater.literal(&mut out, tail).unwrap();
+        }
+        deflater.end(&mut out).unwrap();
+        out
+    }
+
+    /// Rebuild a file from its token stream, as the receiver does
+    async fn inflate_file<T: Transport>(
+        inflater: &mut TokenInflater,
+        channel: &mut Channel<'_, T>,
+        blocks: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut file = Vec::new();
+        loop {
+            match inflater.read(channel).await.unwrap() {
+                Token::Literal(data) => file.extend_from_slice(&data),
+                Token::Block(i) => {
+                    let block = &blocks[i as usize];
+                    file.extend_from_slice(block);
+                    inflater.see_block(block).unwrap();
+                }
+                Token::End => return file,
+            }
+        }
+    }
+
+    #[compio::test]
+    async fn test_token_stream_round_trip() {
+        // Requirement: The deflated token stream rebuilds literals and
+        // block runs, across files, for both codecs; blocks longer than
+        // 64K keep the two histories in step
+        let text: Vec<u8> = b"the quick brown fox jumps over the lazy dog. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(40_000)
+            .collect();
+        let noise: Vec<u8> = (0..5_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut blocks: Vec<Vec<u8>> = (0..80u32)
+            .map(|i| (0..700).map(|j| ((i * 31 + j) % 251) as u8).collect())
+            .collect();
+        blocks.push(text.iter().copied().cycle().take(100_000).collect());
+        let long = blocks.len() as i32 - 1;
+        let steps: [(&[u8], i32); 7] = [
+            (&[], 0),
+            (&[], 1),
+            (&text[..1_000], 5),
+            (&noise[..], 6),
+            (&[], 7),
+            (&[], long),
+            (&text[..], 79),
+        ];
+        let expected = |steps: &[(&[u8], i32)], tail: &[u8]| {
+            let mut file = Vec::new();
+            for (literal, index) in steps {
+                file.extend_from_slice(literal);
+                file.extend_from_slice(&blocks[*index as usize]);
+            }
+            file.extend_from_slice(tail);
+            file
+        };
+
+        for codec in [TokenCodec::Zlib, TokenCodec::ZlibX] {
+            let mut deflater = TokenDeflater::new(CompressLevel::DEFAULT, codec);
+            let mut stream = deflate_file(&mut deflater, &steps, &text[..3_000], &blocks);
+            stream.extend(deflate_file(&mut deflater, &[], &noise, &blocks));
+            stream.extend(deflate_file(&mut deflater, &steps[..2], &[], &blocks));
+            assert!(stream.len() < 20_000, "{} bytes", stream.len());
+
+            // The whole stream fits in the pipe, so write it before reading
+            let (read_fd, write_fd) = PipeTransport::create_pipe().unwrap();
+            let read_fd_dup = unsafe { libc::dup(read_fd) };
+            let write_fd_dup = unsafe { libc::dup(write_fd) };
+            let mut reader = unsafe {
+                PipeTransport::from_fds(read_fd, read_fd_dup, "reader".to_string()).unwrap()
+            };
+            let mut writer = unsafe {
+                PipeTransport::from_fds(write_fd_dup, write_fd, "writer".to_string()).unwrap()
+            };
+            crate::protocol::transport::write_all(&mut writer, &stream)
+                .await
+                .unwrap();
+            let mut channel = Channel::new(&mut reader, "test");
+            let mut inflater = TokenInflater::new(codec);
+            assert_eq!(
+                inflate_file(&mut inflater, &mut channel, &blocks).await,
+                expected(&steps, &text[..3_000])
+            );
+            assert_eq!(
+                inflate_file(&mut inflater, &mut channel, &blocks).await,
+                noise
+            );
+            assert_eq!(
+                inflate_file(&mut inflater, &mut channel, &blocks).await,
+                expected(&steps[..2], &[])
+            );
+        }
+    }
+
+    #[test]
+    fn test_runs_use_relative_tokens() {
+        // Requirement: Consecutive blocks travel as one run token, relative
+        // to the previous run when close, absolute otherwise
+        let blocks = vec![vec![7u8; 10]; 300];
+        let mut deflater = TokenDeflater::new(CompressLevel::DEFAULT, TokenCodec::ZlibX);
+        let steps: [(&[u8], i32); 4] = [(&[], 2), (&[], 3), (&[], 4), (&[], 200)];
+        let stream = deflate_file(&mut deflater, &steps, &[], &blocks);
+        let mut expected = vec![TOKENRUN_REL + 2, 2, 0, TOKEN_LONG];
+        expected.extend_from_slice(&200i32.to_le_bytes());
+        expected.push(END_FLAG);
+        assert_eq!(stream, expected);
+    }
+
+    #[test]
+    fn test_negotiated_level() {
+        // Requirement: Compression is on if either pipe peer asks for it,
+        // at the lower of the levels asked for
+        let (fast, best) = (CompressLevel::new(1), CompressLevel::new(19));
+        assert_eq!(CompressLevel::negotiate(None, None), None);
+        assert_eq!(CompressLevel::negotiate(Some(best), None), Some(best));
+        assert_eq!(CompressLevel::negotiate(None, Some(fast)), Some(fast));
+        assert_eq!(CompressLevel::negotiate(Some(best), Some(fast)), Some(fast));
+        assert_eq!(
+            CompressLevel::negotiate(Some(CompressLevel::DEFAULT), Some(best)),
+            Some(CompressLevel::DEFAULT)
+        );
+        for level in [None, Some(fast), Some(CompressLevel::DEFAULT)] {
+            let value = CompressLevel::capability_value(level);
+            assert_eq!(CompressLevel::from_capability_value(&value), level);
+        }
+        assert_eq!(CompressLevel::new(30).zstd(), 22);
+        assert_eq!(CompressLevel::new(19).zlib(), 9);
+    }
+
+    #[test]
+    fn test_literal_compression() {
+        // Requirement: zstd literals round-trip; incompressible ones are
+        // left raw
+        let mut compressor = LiteralCompressor::new(CompressLevel::DEFAULT).unwrap();
+        let mut decompressor = LiteralDecompressor::new().unwrap();
+        let text = b"abcdefgh".repeat(1_000);
+        let compressed = compressor.compress(&text).unwrap().unwrap();
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(
+            decompressor.decompress(&compressed, text.len()).unwrap(),
+            text
+        );
+        assert!(decompressor.decompress(&compressed, 10).is_err());
+        assert_eq!(compressor.compress(&[1, 2, 3]).unwrap(), None);
+    }
+}
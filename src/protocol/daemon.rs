@@ -0,0 +1,723 @@
+//! rsync daemon: `arsync --daemon` for `rsync://` clients
+//!
+//! Machines without ssh can push to arsync over plain TCP, as they would to
+//! rsyncd. The daemon listens on a port (873 by default), speaks rsync's
+//! daemon protocol and hands each transfer to the protocol 30 receiver
+//! (`rsync_receiver`):
+//!
+//! 1. Both sides greet with `@RSYNCD: <version>`; the lower version is used.
+//! 2. The client names a module. An empty name or `#list` lists the modules
+//!    (those with `list = yes`) and ends the session.
+//! 3. After `@RSYNCD: OK` the client sends the arguments it would give
+//!    `rsync --server`, NUL-terminated and ending with an empty one. Paths
+//!    in them start with the module name, which stands for the module's
+//!    `path`.
+//! 4. The transfer runs as for `arsync --server`, minus the version exchange.
+//!
+//! Modules are defined in a subset of rsyncd.conf:
+//!
+//! ```text
+//! port = 8873
+//! usage ledger = /var/lib/arsync/usage
+//! uid = backup
+//! gid = backup
+//!
+//! [backups]
+//!     path = /srv/backups
+//!     comment = Nightly backups
+//!     read only = no
+//! ```
+//!
+//! Global parameters are `port`, `address`, `usage ledger` (bytes
+//! received per day, module and client; see `accounting`), and `uid` and
+//! `gid` (names or numbers). Module
+//! parameters are `path` (required, absolute), `comment`, `read only`
+//! (default yes, as in rsync) and `list` (default yes). Any other parameter,
+//! notably `auth users` and `hosts allow`, is refused rather than ignored:
+//! arsync does not authenticate clients, and a module must not look
+//! protected when it is not. Only expose the daemon to trusted networks.
+//!
+//! arsync only receives, so clients may list modules and push into writable
+//! ones; pulls are refused. A module's path is not a chroot, but clients
+//! cannot leave it: requested paths may not be absolute or contain `..`,
+//! the receiver reaches every path from the module's directory without
+//! following symlinks, and the symlinks clients send are munged as with
+//! rsync's `munge symlinks` (their targets get a `/rsyncd-munged/` prefix,
+//! so they lead nowhere). Set-user-ID and set-group-ID bits are dropped.
+//!
+//! Started as root (to listen on port 873), the daemon switches to `uid`
+//! and `gid` (`nobody` by default, as rsync's does) once it listens, so
+//! clients cannot set owners, create devices or write where that user
+//! cannot. Modules and the usage ledger must be writable by it.
+//!
+//! On SIGTERM the daemon stops accepting connections and gives running
+//! sessions `--drain-timeout` to finish (see `shutdown`).
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+
+use crate::clock::SystemClock;
+use crate::protocol::accounting::{Day, Usage, UsageLedger};
+use crate::protocol::rsync_receiver::{self, ReceiverStats, ServerArgs};
+use crate::protocol::rsync_wire::PROTOCOL_VERSION;
+use crate::protocol::shutdown::{
+    install_termination_handler, termination_requested, ShutdownCoordinator,
+};
+use crate::protocol::tcp::TcpTransport;
+use crate::protocol::transport::{self, Transport};
+use anyhow::{Context, Result};
+use compio::net::TcpListener;
+use std::ffi::OsString;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Port of rsync daemons
+pub const DEFAULT_PORT: u16 = 873;
+
+/// Longest greeting, module name or argument accepted
+const MAX_LINE: usize = 4096;
+
+/// Most arguments a client may send
+const MAX_ARGS: usize = 256;
+
+/// How often the accept loop checks for SIGTERM
+const ACCEPT_POLL: Duration = Duration::from_millis(200);
+
+/// A directory clients can push into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Name clients use (`rsync://host/NAME/...`)
+    pub name: String,
+    /// Directory the module stands for
+    pub path: PathBuf,
+    /// Shown next to the name in module listings
+    pub comment: String,
+    /// Refuse pushes
+    pub read_only: bool,
+    /// Show the module in listings
+    pub list: bool,
+}
+
+impl Module {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: PathBuf::new(),
+            comment: String::new(),
+            read_only: true,
+            list: true,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "path" => self.path = PathBuf::from(value),
+            "comment" => self.comment = value.to_string(),
+            "readonly" => self.read_only = parse_bool(value)?,
+            "list" => self.list = parse_bool(value)?,
+            _ => anyhow::bail!("Unsupported module parameter {key:?}"),
+        }
+        Ok(())
+    }
+
+    /// Where a path a client asked for lies in this module
+    ///
+    /// A leading module name is dropped, as rsync's daemon does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path is absolute or contains `..`.
+    pub fn resolve(&self, requested: &Path) -> Result<PathBuf> {
+        let inside = requested.strip_prefix(&self.name).unwrap_or(requested);
+        let mut resolved = self.path.clone();
+        for component in inside.components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir => {}
+                _ => anyhow::bail!(
+                    "Path {} is outside module {}",
+                    requested.display(),
+                    self.name
+                ),
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+/// Settings and modules of a daemon
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DaemonConfig {
+    /// Port to listen on
+    pub port: Option<u16>,
+    /// Address to listen on
+    pub address: Option<IpAddr>,
+    /// Where to record the bytes received per day, module and client
+    pub usage_ledger: Option<PathBuf>,
+    /// User to run as when started as root
+    pub uid: Option<u32>,
+    /// Group to run as when started as root
+    pub gid: Option<u32>,
+    /// Modules in definition order
+    pub modules: Vec<Module>,
+}
+
+impl DaemonConfig {
+    /// Read the config file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is invalid.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid daemon config {}", path.display()))
+    }
+
+    /// Parse an rsyncd.conf subset (see the module documentation)
+    ///
+    /// Parameter names ignore case, spaces and underscores, as in rsync.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the line of an unknown parameter, a malformed
+    /// line or value, a duplicate module, or a module without an absolute
+    /// path.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        let mut module: Option<Module> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            let number = index + 1;
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header
+                    .strip_suffix(']')
+                    .with_context(|| format!("Line {number}: unterminated module name"))?
+                    .trim();
+                if name.is_empty() || name.contains('/') {
+                    anyhow::bail!("Line {number}: invalid module name {name:?}");
+                }
+                if let Some(done) = module.take() {
+                    config.add_module(done)?;
+                }
+                module = Some(Module::new(name));
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Line {number}: expected NAME = VALUE"))?;
+            let key: String = key
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != '_')
+                .flat_map(char::to_lowercase)
+                .collect();
+            let value = value.trim();
+            match &mut module {
+                Some(module) => module.set(&key, value),
+                None => config.set(&key, value),
+            }
+            .with_context(|| format!("Line {number}"))?;
+        }
+        if let Some(done) = module {
+            config.add_module(done)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "port" => {
+                self.port = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid port {value:?}"))?,
+                );
+            }
+            "address" => {
+                self.address = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid address {value:?}"))?,
+                );
+            }
+            "usageledger" => self.usage_ledger = Some(PathBuf::from(value)),
+            "uid" => self.uid = Some(parse_id(value, false)?),
+            "gid" => self.gid = Some(parse_id(value, true)?),
+            _ => anyhow::bail!("Unsupported global parameter {key:?}"),
+        }
+        Ok(())
+    }
+
+    fn add_module(&mut self, module: Module) -> Result<()> {
+        if !module.path.is_absolute() {
+            anyhow::bail!("Module {} needs an absolute path", module.name);
+        }
+        if self.module(&module.name).is_some() {
+            anyhow::bail!("Module {} is defined twice", module.name);
+        }
+        self.modules.push(module);
+        Ok(())
+    }
+
+    /// The module named `name`
+    #[must_use]
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+}
+
+/// Id of the user (or group) `value`, a name or a number
+fn parse_id(value: &str, group: bool) -> Result<u32> {
+    let kind = if group { "group" } else { "user" };
+    value
+        .parse()
+        .ok()
+        .or_else(|| rsync_receiver::local_id(value.as_bytes(), group))
+        .with_context(|| format!("Unknown {kind} {value:?}"))
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "on" | "1" => Ok(true),
+        "no" | "false" | "off" | "0" => Ok(false),
+        _ => anyhow::bail!("Invalid boolean {value:?} (expected yes or no)"),
+    }
+}
+
+// ============================================================================
+// Listener
+// ============================================================================
+
+/// State shared by the sessions of a daemon
+struct Daemon {
+    config: DaemonConfig,
+    ledger: Option<Mutex<UsageLedger>>,
+    shutdown: Arc<ShutdownCoordinator>,
+}
+
+impl Daemon {
+    /// Serve one connection and log how it ended
+    async fn serve(&self, mut transport: TcpTransport) {
+        let peer = transport.peer_addr();
+        match session(&mut transport, &self.config).await {
+            Ok(None) => debug!("{peer}: listed modules"),
+            Ok(Some((module, stats))) => {
+                info!("{peer}: {module}: {stats}");
+                self.record(&module, peer.ip(), stats.literal_bytes);
+            }
+            Err(e) => warn!("{peer}: {e:#}"),
+        }
+    }
+
+    /// Add a session's bytes to the usage ledger, if there is one
+    fn record(&self, module: &str, client: IpAddr, bytes_in: u64) {
+        let (Some(path), Some(ledger)) = (&self.config.usage_ledger, &self.ledger) else {
+            return;
+        };
+        let mut ledger = ledger.lock().unwrap_or_else(PoisonError::into_inner);
+        let usage = Usage {
+            bytes_in,
+            bytes_out: 0,
+        };
+        ledger.record(Day::today(&SystemClock), module, &client.to_string(), usage);
+        if let Err(e) = ledger.save(path) {
+            warn!("Cannot save usage ledger {}: {e:#}", path.display());
+        }
+    }
+}
+
+/// Accept rsync clients on `port` of `address` until SIGTERM or SIGINT
+///
+/// `port` and `address` default to the config's, then to 873 on all IPv4
+/// addresses. After the signal, running sessions get `drain_timeout` to
+/// finish.
+///
+/// # Errors
+///
+/// Returns an error if the usage ledger cannot be loaded, the address
+/// cannot be bound, or the signal handlers cannot be installed.
+pub async fn run(
+    config: DaemonConfig,
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let addr = SocketAddr::new(
+        address
+            .or(config.address)
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        port.or(config.port).unwrap_or(DEFAULT_PORT),
+    );
+    let ledger = match &config.usage_ledger {
+        Some(path) => Some(Mutex::new(UsageLedger::load(path)?)),
+        None => None,
+    };
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Cannot listen on {addr}"))?;
+    drop_privileges(&config)?;
+    install_termination_handler().context("Cannot install signal handlers")?;
+    info!(
+        "Listening on {addr} with {} module(s)",
+        config.modules.len()
+    );
+
+    let daemon = Arc::new(Daemon {
+        config,
+        ledger,
+        shutdown: ShutdownCoordinator::new(),
+    });
+    while !termination_requested() {
+        let (stream, peer) = match compio::time::timeout(ACCEPT_POLL, listener.accept()).await {
+            Ok(Ok(accepted)) => accepted,
+            // Time to check for a signal
+            Err(_) => continue,
+            Ok(Err(e)) => {
+                // Out of file descriptors, usually: let sessions end first
+                warn!("Cannot accept a connection: {e}");
+                compio::time::sleep(ACCEPT_POLL).await;
+                continue;
+            }
+        };
+        let Some(guard) = daemon.shutdown.register(&peer.to_string()) else {
+            break;
+        };
+        let daemon = Arc::clone(&daemon);
+        compio::runtime::spawn(async move {
+            daemon.serve(TcpTransport::accepted(stream, peer)).await;
+            drop(guard);
+        })
+        .detach();
+    }
+
+    drop(listener);
+    let active = daemon.shutdown.active().len();
+    info!("Shutting down; waiting up to {drain_timeout:?} for {active} session(s)");
+    let report = daemon.shutdown.drain(drain_timeout).await;
+    for session in &report.abandoned {
+        warn!(
+            "Cut off the session of {} after {:?}",
+            session.peer,
+            session.started.elapsed()
+        );
+    }
+    Ok(())
+}
+
+/// Switch to the configured user and group (`nobody` by default) if
+/// running as root
+///
+/// As root the receiver would give files the owners clients ask for and
+/// create the devices they send; as `nobody` it can do neither.
+fn drop_privileges(config: &DaemonConfig) -> Result<()> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+    let uid = match config.uid {
+        Some(uid) => uid,
+        None => rsync_receiver::local_id(b"nobody", false)
+            .context("No user nobody: set uid in the config")?,
+    };
+    let gid = match config.gid {
+        Some(gid) => gid,
+        None => rsync_receiver::local_id(b"nobody", true)
+            .or_else(|| rsync_receiver::local_id(b"nogroup", true))
+            .context("No group nobody or nogroup: set gid in the config")?,
+    };
+    // Groups first: once the user is not root it cannot change them
+    // SAFETY: the group list holds one valid id; setgid and setuid have no
+    // memory preconditions (glibc applies them to every thread)
+    let failed = unsafe {
+        libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0
+    };
+    if failed {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Cannot switch to uid {uid} and gid {gid}"));
+    }
+    info!("Running as uid {uid}, gid {gid}");
+    Ok(())
+}
+
+// ============================================================================
+// Session
+// ============================================================================
+
+/// Serve one client: a module listing (`None`) or a push into a module
+async fn session<T: Transport>(
+    transport: &mut T,
+    config: &DaemonConfig,
+) -> Result<Option<(String, ReceiverStats)>> {
+    write_text(transport, &format!("@RSYNCD: {PROTOCOL_VERSION}.0\n")).await?;
+    let greeting = read_line(transport, b'\n').await?;
+    let Some(client_version) = parse_greeting(&greeting) else {
+        write_text(transport, "@ERROR: protocol startup error\n").await?;
+        anyhow::bail!("Not an rsync client (greeted with {greeting:?})");
+    };
+    let version = client_version.min(PROTOCOL_VERSION);
+    if version < PROTOCOL_VERSION {
+        write_text(
+            transport,
+            &format!("@ERROR: arsync needs protocol {PROTOCOL_VERSION} (rsync 3.0) or later\n"),
+        )
+        .await?;
+        anyhow::bail!("Client speaks protocol {client_version}");
+    }
+
+    let name = read_line(transport, b'\n').await?;
+    if name.is_empty() || name == "#list" {
+        for module in config.modules.iter().filter(|module| module.list) {
+            write_text(
+                transport,
+                &format!("{:<15}\t{}\n", module.name, module.comment),
+            )
+            .await?;
+        }
+        write_text(transport, "@RSYNCD: EXIT\n").await?;
+        return Ok(None);
+    }
+    let Some(module) = config.module(&name) else {
+        write_text(transport, &format!("@ERROR: Unknown module '{name}'\n")).await?;
+        anyhow::bail!("Unknown module {name:?}");
+    };
+    write_text(transport, "@RSYNCD: OK\n").await?;
+
+    let args = read_args(transport).await?;
+    let args = match server_args(module, &args) {
+        Ok(args) => args,
+        Err(e) => {
+            rsync_receiver::refuse(transport, &format!("{e:#}")).await?;
+            return Err(e);
+        }
+    };
+    info!(
+        "Receiving into {} (module {})",
+        args.destination.display(),
+        module.name
+    );
+    let stats = rsync_receiver::serve_daemon(transport, &args, version).await?;
+    Ok(Some((module.name.clone(), stats)))
+}
+
+/// Protocol version of a client greeting (`@RSYNCD: 31.0 md5 md4`)
+fn parse_greeting(line: &str) -> Option<i32> {
+    let version = line.strip_prefix("@RSYNCD: ")?;
+    let major = version.split(['.', ' ']).next()?;
+    major.parse().ok()
+}
+
+/// The receiver's options for a push into `module`
+fn server_args(module: &Module, args: &[OsString]) -> Result<ServerArgs> {
+    let Some((first, options)) = args.split_first() else {
+        anyhow::bail!("No arguments");
+    };
+    if first != ServerArgs::FLAG {
+        anyhow::bail!("Expected {} as the first argument", ServerArgs::FLAG);
+    }
+    if options
+        .iter()
+        .take_while(|arg| *arg != ".")
+        .any(|arg| arg == "--sender")
+    {
+        anyhow::bail!(
+            "arsync only receives: module {} cannot be pulled from",
+            module.name
+        );
+    }
+    if module.read_only {
+        anyhow::bail!("Module {} is read only", module.name);
+    }
+    let mut parsed = ServerArgs::parse(options)?;
+    parsed.destination = module.resolve(&parsed.destination)?;
+    parsed.module = Some(module.path.clone());
+    Ok(parsed)
+}
+
+/// Read arguments up to the empty one that ends them
+async fn read_args<T: Transport>(transport: &mut T) -> Result<Vec<OsString>> {
+    let mut args = Vec::new();
+    loop {
+        let arg = read_until(transport, 0).await?;
+        if arg.is_empty() {
+            return Ok(args);
+        }
+        if args.len() == MAX_ARGS {
+            anyhow::bail!("More than {MAX_ARGS} arguments");
+        }
+        args.push(OsString::from_vec(arg));
+    }
+}
+
+/// Read a text line, without its line ending
+async fn read_line<T: Transport>(transport: &mut T, end: u8) -> Result<String> {
+    let line = read_until(transport, end).await?;
+    let line = String::from_utf8(line).context("Line is not UTF-8")?;
+    Ok(line.trim_end_matches('\r').to_string())
+}
+
+/// Read bytes up to `end` (not included)
+///
+/// One byte at a time, so nothing of the transfer that follows is consumed.
+async fn read_until<T: Transport>(transport: &mut T, end: u8) -> Result<Vec<u8>> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        transport::read_exact(transport, &mut byte)
+            .await
+            .context("Client closed the connection")?;
+        if byte[0] == end {
+            return Ok(line);
+        }
+        if line.len() == MAX_LINE {
+            anyhow::bail!("Line longer than {MAX_LINE} bytes");
+        }
+        line.push(byte[0]);
+    }
+}
+
+async fn write_text<T: Transport>(transport: &mut T, text: &str) -> Result<()> {
+    transport::write_all(transport, text.as_bytes())
+        .await
+        .context("Failed to write to rsync client")
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::protocol::tcp::ConnectConfig;
+    use compio::buf::BufResult;
+    use compio::io::AsyncReadExt;
+
+    const CONFIG: &str = "\
+# Served to the backup hosts
+port = 8873
+
+[backups]
+    path = /srv/backups
+    comment = Nightly backups
+    read only = no
+
+[archive]
+    path = /srv/archive
+    list = no
+";
+
+    #[test]
+    fn test_parse_config() {
+        // Requirement: The rsyncd.conf subset defines the port and modules;
+        // modules are read only and listed unless configured otherwise
+        let config = DaemonConfig::parse(CONFIG).unwrap();
+        assert_eq!(config.port, Some(8873));
+        assert_eq!(config.address, None);
+        let backups = config.module("backups").unwrap();
+        assert_eq!(backups.path, PathBuf::from("/srv/backups"));
+        assert_eq!(backups.comment, "Nightly backups");
+        assert!(!backups.read_only && backups.list);
+        let archive = config.module("archive").unwrap();
+        assert!(archive.read_only && !archive.list);
+
+        let config = DaemonConfig::parse("uid = 65534\ngid = root\n").unwrap();
+        assert_eq!((config.uid, config.gid), (Some(65534), Some(0)));
+    }
+
+    #[test]
+    fn test_unsupported_parameters_are_refused() {
+        // Requirement: Parameters arsync does not implement, such as
+        // authentication, fail the config instead of being ignored
+        for text in [
+            "[m]\npath = /m\nauth users = alice\n",
+            "hosts allow = 10.0.0.0/8\n",
+            "[m]\npath = relative\n",
+            "[m]\npath = /m\n[m]\npath = /n\n",
+            "[m]\npath = /m\nread only = maybe\n",
+            "uid = no-such-user-here\n",
+        ] {
+            assert!(DaemonConfig::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_stays_inside_module() {
+        // Requirement: Requested paths resolve inside the module, with or
+        // without the leading module name, and may not leave it
+        let module = DaemonConfig::parse(CONFIG)
+            .unwrap()
+            .module("backups")
+            .cloned()
+            .unwrap();
+        let root = Path::new("/srv/backups");
+        assert_eq!(module.resolve(Path::new("backups")).unwrap(), root);
+        assert_eq!(
+            module.resolve(Path::new("backups/host1/")).unwrap(),
+            root.join("host1")
+        );
+        assert_eq!(
+            module.resolve(Path::new("./host1")).unwrap(),
+            root.join("host1")
+        );
+        assert!(module.resolve(Path::new("backups/../etc")).is_err());
+        assert!(module.resolve(Path::new("/etc")).is_err());
+    }
+
+    /// Serve one connection with `config` and return what the client got
+    /// after sending `request`
+    async fn exchange(config: &str, request: &[u8]) -> String {
+        let config = DaemonConfig::parse(config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = compio::runtime::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let mut transport = TcpTransport::accepted(stream, peer);
+            let _ = session(&mut transport, &config).await;
+        });
+        let mut client = TcpTransport::connect("127.0.0.1", addr.port(), &ConnectConfig::default())
+            .await
+            .unwrap();
+        transport::write_all(&mut client, request).await.unwrap();
+        server.await.unwrap();
+        let BufResult(result, reply) = client.read_to_end(Vec::new()).await;
+        result.unwrap();
+        String::from_utf8_lossy(&reply).into_owned()
+    }
+
+    #[compio::test]
+    async fn test_lists_listed_modules() {
+        // Requirement: "#list" lists the modules with list = yes and ends
+        // the session
+        let reply = exchange(CONFIG, b"@RSYNCD: 31.0 md5 md4\n#list\n").await;
+        assert!(reply.starts_with("@RSYNCD: 30.0\n"), "{reply}");
+        assert!(
+            reply.contains("backups        \tNightly backups\n"),
+            "{reply}"
+        );
+        assert!(!reply.contains("archive"), "{reply}");
+        assert!(reply.ends_with("@RSYNCD: EXIT\n"), "{reply}");
+    }
+
+    #[compio::test]
+    async fn test_refuses_unknown_and_read_only_modules() {
+        // Requirement: An unknown module is refused before the arguments;
+        // a push into a read-only module is refused with an error the
+        // client shows
+        let reply = exchange(CONFIG, b"@RSYNCD: 31.0\nnope\n").await;
+        assert!(
+            reply.ends_with("@ERROR: Unknown module 'nope'\n"),
+            "{reply}"
+        );
+
+        let reply = exchange(
+            CONFIG,
+            b"@RSYNCD: 31.0\narchive\n--server\0-logDtpre.iLsfxC\0.\0archive/x\0\0",
+        )
+        .await;
+        assert!(reply.contains("@RSYNCD: OK\n"), "{reply}");
+        assert!(reply.contains("Module archive is read only"), "{reply}");
+    }
+}
@@ -0,0 +1,388 @@
+//! rsync block-matching delta engine
+//!
+//! The receiver splits its copy of a file (the *basis*) into blocks and sends
+//! a [`Signature`]: a weak rolling checksum and a strong (MD5) checksum per
+//! block. The sender slides a one-block window over its version of the file,
+//! updating the weak checksum in O(1) per byte with [`RollingChecksum`], and
+//! only computes the strong checksum when the weak one matches a block.
+//! Matching windows become [`DeltaInstruction::BlockMatch`] and the bytes in
+//! between [`DeltaInstruction::Literal`], so only changed data is sent;
+//! [`apply_delta`] rebuilds the file from the basis and the delta.
+//!
+//! The weak checksum is the seeded Adler-32 of
+//! [`rolling_checksum_with_seed`](crate::protocol::checksum::rolling_checksum_with_seed),
+//! so signatures computed block by block agree with the rolling window.
+//! Near the end of the data the window shrinks, so a short final basis block
+//! can match too. Among blocks with the same checksums, the one following the
+//! previous match is preferred, which keeps the delta of an unchanged region
+//! a run of consecutive blocks.
+#![allow(dead_code)] // Used by the pipe protocol; the rest is library API
+
+use crate::protocol::checksum::{rolling_checksum_with_seed, strong_checksum};
+use anyhow::Result;
+#[allow(clippy::disallowed_types)]
+// HashMap required for O(1) weak checksum lookup
+use std::collections::HashMap;
+
+/// Modulus of the Adler-32 sums
+const MODULUS: u64 = 65521;
+
+/// Delta instruction for reconstructing files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaInstruction {
+    /// Raw data to insert (when no match found)
+    Literal(Vec<u8>),
+    /// Copy from basis file using block index
+    BlockMatch {
+        /// Index of the matching block in the basis file
+        block_index: u32,
+        /// Length of the match in bytes
+        length: u32,
+    },
+}
+
+/// Weak checksum of a sliding window, updated in O(1) per byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollingChecksum {
+    /// Sum of the bytes (plus the seed's low half), mod `MODULUS`
+    a: u64,
+    /// Sum of the running `a` values (plus the seed's high half), mod `MODULUS`
+    b: u64,
+    /// Seed's contribution to `a`
+    seed_a: u64,
+    /// Window length
+    len: u64,
+}
+
+impl RollingChecksum {
+    /// Checksum of `window`, equal to `rolling_checksum_with_seed(window, seed)`
+    #[must_use]
+    pub fn new(window: &[u8], seed: u32) -> Self {
+        let digest = rolling_checksum_with_seed(window, seed);
+        Self {
+            a: u64::from(digest & 0xffff) % MODULUS,
+            b: u64::from(digest >> 16) % MODULUS,
+            seed_a: u64::from(seed & 0xffff) % MODULUS,
+            len: window.len() as u64,
+        }
+    }
+
+    /// Slide the window one byte: `out` leaves at the front, `incoming` enters
+    pub fn roll(&mut self, out: u8, incoming: u8) {
+        let (out, incoming) = (u64::from(out), u64::from(incoming));
+        self.a = (self.a + MODULUS - out + incoming) % MODULUS;
+        // Every remaining byte gains weight one; `out` loses its weight `len`
+        let out_weight = self.len * out % MODULUS;
+        self.b = (self.b + MODULUS - out_weight + self.a + MODULUS - self.seed_a) % MODULUS;
+    }
+
+    /// Drop the byte `out` from the front of the window
+    pub fn shrink(&mut self, out: u8) {
+        let out = u64::from(out);
+        self.a = (self.a + MODULUS - out) % MODULUS;
+        let out_weight = self.len * out % MODULUS;
+        self.b = (self.b + 2 * MODULUS - out_weight - self.seed_a) % MODULUS;
+        self.len -= 1;
+    }
+
+    /// Current checksum, in the `rolling_checksum_with_seed` format
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // Both sums are below MODULUS
+    pub const fn digest(&self) -> u32 {
+        ((self.b as u32) << 16) | self.a as u32
+    }
+}
+
+/// Checksums of one basis block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    /// Rolling checksum (fast, collision-prone)
+    pub weak: u32,
+    /// MD5 checksum (slow, collision-resistant)
+    pub strong: [u8; 16],
+    /// Offset of the block in the basis
+    pub offset: u64,
+    /// Length of the block (only the last one may be shorter than the block size)
+    pub len: usize,
+}
+
+/// Block checksums of a basis file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+    /// Length of every block but the last
+    pub block_size: usize,
+    /// Seed of the weak checksums
+    pub seed: u32,
+    /// Blocks in basis order; a block's index is its position
+    pub blocks: Vec<BlockSignature>,
+}
+
+impl Signature {
+    /// Signature of `basis` in blocks of `block_size` bytes
+    #[must_use]
+    pub fn generate(basis: &[u8], block_size: usize, seed: u32) -> Self {
+        let block_size = block_size.max(1);
+        let blocks = basis
+            .chunks(block_size)
+            .enumerate()
+            .map(|(index, block)| BlockSignature {
+                weak: rolling_checksum_with_seed(block, seed),
+                strong: strong_checksum(block),
+                offset: (index * block_size) as u64,
+                len: block.len(),
+            })
+            .collect();
+        Self {
+            block_size,
+            seed,
+            blocks,
+        }
+    }
+
+    /// Block indices by weak checksum
+    #[allow(clippy::disallowed_types)] // HashMap required for O(1) lookup
+    fn by_weak(&self) -> HashMap<u32, Vec<usize>> {
+        let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (position, block) in self.blocks.iter().enumerate() {
+            index.entry(block.weak).or_default().push(position);
+        }
+        index
+    }
+}
+
+/// Delta that turns the basis of `signature` into `data` (sender side)
+///
+/// Without basis blocks the delta is `data` as a single literal; empty `data`
+/// gives an empty delta.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)] // Blocks and windows are far below 4 GB
+pub fn compute_delta(signature: &Signature, data: &[u8]) -> Vec<DeltaInstruction> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    if signature.blocks.is_empty() {
+        return vec![DeltaInstruction::Literal(data.to_vec())];
+    }
+
+    let by_weak = signature.by_weak();
+    let mut delta = Vec::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut next_block = None;
+    let mut window = signature.block_size.min(data.len());
+    let mut rolling = RollingChecksum::new(&data[..window], signature.seed);
+
+    while window > 0 {
+        let candidate = &data[pos..pos + window];
+        if let Some(block) = find_block(signature, &by_weak, &rolling, candidate, next_block) {
+            if literal_start < pos {
+                delta.push(DeltaInstruction::Literal(data[literal_start..pos].to_vec()));
+            }
+            delta.push(DeltaInstruction::BlockMatch {
+                block_index: block as u32,
+                length: window as u32,
+            });
+            pos += window;
+            literal_start = pos;
+            next_block = Some(block + 1);
+            window = signature.block_size.min(data.len() - pos);
+            if window > 0 {
+                rolling = RollingChecksum::new(&data[pos..pos + window], signature.seed);
+            }
+        } else {
+            if pos + window < data.len() {
+                rolling.roll(data[pos], data[pos + window]);
+            } else {
+                // Past the last full window: try ever shorter tails
+                rolling.shrink(data[pos]);
+                window -= 1;
+            }
+            pos += 1;
+        }
+    }
+    if literal_start < data.len() {
+        delta.push(DeltaInstruction::Literal(data[literal_start..].to_vec()));
+    }
+    delta
+}
+
+/// Basis block equal to `window`, preferring `preferred`
+#[allow(clippy::disallowed_types)] // HashMap required for O(1) lookup
+fn find_block(
+    signature: &Signature,
+    by_weak: &HashMap<u32, Vec<usize>>,
+    rolling: &RollingChecksum,
+    window: &[u8],
+    preferred: Option<usize>,
+) -> Option<usize> {
+    let candidates = by_weak.get(&rolling.digest())?;
+    let candidates: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&position| signature.blocks[position].len == window.len())
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    // Only now that the weak checksum matched is the strong one worth computing
+    let strong = strong_checksum(window);
+    let matches = |position: &usize| signature.blocks[*position].strong == strong;
+    preferred
+        .filter(|position| candidates.contains(position) && matches(position))
+        .or_else(|| candidates.into_iter().find(matches))
+}
+
+/// Rebuild the data from `basis` and a delta against its `signature` (receiver side)
+///
+/// # Errors
+///
+/// Returns an error if a block match refers to a block that is not in
+/// `signature` or not within `basis`.
+pub fn apply_delta(
+    basis: &[u8],
+    signature: &Signature,
+    delta: &[DeltaInstruction],
+) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    for instruction in delta {
+        match instruction {
+            DeltaInstruction::Literal(data) => output.extend_from_slice(data),
+            DeltaInstruction::BlockMatch {
+                block_index,
+                length,
+            } => {
+                let block = signature
+                    .blocks
+                    .get(*block_index as usize)
+                    .ok_or_else(|| anyhow::anyhow!("Block index {block_index} not in signature"))?;
+                let start = usize::try_from(block.offset)?;
+                let data = basis.get(start..start + *length as usize).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Block {block_index} ({length} bytes at {start}) is beyond the basis"
+                    )
+                })?;
+                output.extend_from_slice(data);
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Literal and matched bytes of `delta`
+#[must_use]
+pub fn delta_bytes(delta: &[DeltaInstruction]) -> (usize, usize) {
+    delta.iter().fold(
+        (0, 0),
+        |(literal, matched), instruction| match instruction {
+            DeltaInstruction::Literal(data) => (literal + data.len(), matched),
+            DeltaInstruction::BlockMatch { length, .. } => (literal, matched + *length as usize),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    /// Deterministic pseudo-random bytes
+    fn noise(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rolling_checksum_matches_recomputation() {
+        let data = noise(4096, 1);
+        for seed in [0, 0x1234_5678, 0xffff_ffff] {
+            let window = 700;
+            let mut rolling = RollingChecksum::new(&data[..window], seed);
+            for pos in 1..=data.len() - window {
+                rolling.roll(data[pos - 1], data[pos + window - 1]);
+                assert_eq!(
+                    rolling.digest(),
+                    rolling_checksum_with_seed(&data[pos..pos + window], seed),
+                    "seed {seed:#x}, offset {pos}"
+                );
+            }
+            let start = data.len() - window;
+            for pos in start + 1..data.len() {
+                rolling.shrink(data[pos - 1]);
+                assert_eq!(
+                    rolling.digest(),
+                    rolling_checksum_with_seed(&data[pos..], seed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_delta_sends_only_changed_blocks() {
+        let basis = noise(100_000, 2);
+        let mut data = basis.clone();
+        data[50_000] ^= 0xff;
+        data.splice(10..10, b"inserted".iter().copied());
+        let signature = Signature::generate(&basis, 700, 7);
+
+        let delta = compute_delta(&signature, &data);
+        assert_eq!(apply_delta(&basis, &signature, &delta).unwrap(), data);
+        let (literal, matched) = delta_bytes(&delta);
+        assert_eq!(literal + matched, data.len());
+        // The blocks holding the insertion and the flipped byte are resent
+        assert!(literal <= 2 * 700 + 8, "{literal} literal bytes");
+    }
+
+    #[test]
+    fn test_delta_matches_short_last_block() {
+        let basis = noise(1000, 3);
+        let signature = Signature::generate(&basis, 300, 0);
+        let delta = compute_delta(&signature, &basis);
+        assert_eq!(delta_bytes(&delta), (0, 1000));
+        assert_eq!(
+            delta.last(),
+            Some(&DeltaInstruction::BlockMatch {
+                block_index: 3,
+                length: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_delta_prefers_sequential_blocks() {
+        let basis = vec![0u8; 4 * 128];
+        let signature = Signature::generate(&basis, 128, 0);
+        let indices: Vec<u32> = compute_delta(&signature, &basis)
+            .iter()
+            .map(|instruction| match instruction {
+                DeltaInstruction::BlockMatch { block_index, .. } => *block_index,
+                DeltaInstruction::Literal(_) => u32::MAX,
+            })
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_delta_without_basis() {
+        let data = noise(1000, 4);
+        let signature = Signature::generate(&[], 700, 0);
+        assert_eq!(
+            compute_delta(&signature, &data),
+            [DeltaInstruction::Literal(data.clone())]
+        );
+        assert!(compute_delta(&signature, &[]).is_empty());
+        assert!(apply_delta(
+            &[],
+            &signature,
+            &[DeltaInstruction::BlockMatch {
+                block_index: 0,
+                length: 1
+            }]
+        )
+        .is_err());
+    }
+}
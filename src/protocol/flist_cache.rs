@@ -0,0 +1,510 @@
+//! Persistent file list cache for the sending side
+//!
+//! Building the file list reads every directory of the source. A server
+//! answering repeated pulls of the same tree repeats that scan each time,
+//! although the result rarely changes. With `--flist-cache DIR` the sender
+//! saves the list it built, together with a stamp of every directory scanned,
+//! and reuses it on the next run for the same source.
+//!
+//! # Invalidation
+//!
+//! Creating, removing or renaming an entry updates the mtime and ctime of its
+//! directory. A cached list is therefore reused only if every recorded
+//! directory still has the same device, inode, mtime and ctime. Any
+//! difference, a missing directory or an unreadable cache file is a miss: the
+//! source is scanned again and the cache replaced.
+//!
+//! # Safeguards
+//!
+//! - Writing to a file does not touch its directory, so on a hit every entry
+//!   is still `lstat`ed and its size, mtime, mode and owner refreshed (and
+//!   symlink targets re-read). A hit saves reading the directories, not the
+//!   per-file stats. An entry that vanished or changed type is a miss.
+//! - A directory changed within [`RACY_WINDOW`] of the scan could change again
+//!   without its timestamps moving on filesystems with coarse timestamps, so
+//!   its stamp never validates a hit.
+//! - The cache file records the canonical source path it describes; a list
+//!   is never reused for another source, even if their hashes collide.
+//! - The cache file is replaced atomically (temp file + rename). Failing to
+//!   save it is logged and the transfer continues.
+//!
+//! Only recursive scans of a directory are cached; a single file has nothing
+//! to save.
+
+use crate::cli::Args;
+use crate::clock::{unix_nanos, Clock, SystemClock};
+use crate::protocol::rsync::{scan_file_list, FileEntry};
+use crate::protocol::session::{put_bytes, ByteReader};
+use anyhow::{Context, Result};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Magic bytes (and format version) at the start of a cache file
+const CACHE_MAGIC: &[u8; 8] = b"ARSFLC01";
+
+/// Directories changed this close to the scan do not validate a cached list
+pub const RACY_WINDOW: Duration = Duration::from_secs(1);
+
+/// Identity and timestamps of a scanned directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirStamp {
+    /// Path relative to the source root ("" for the root itself)
+    path: String,
+    dev: u64,
+    ino: u64,
+    mtime_ns: i128,
+    ctime_ns: i128,
+}
+
+impl DirStamp {
+    /// Stamp for the directory at `path` (relative to the source root)
+    #[must_use]
+    pub fn new(path: String, metadata: &fs::Metadata) -> Self {
+        Self {
+            path,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mtime_ns: timestamp_ns(metadata.mtime(), metadata.mtime_nsec()),
+            ctime_ns: timestamp_ns(metadata.ctime(), metadata.ctime_nsec()),
+        }
+    }
+
+    /// Whether the directory under `root` still matches this stamp
+    fn is_current(&self, root: &Path) -> bool {
+        // The root was reached through the path given, like the scan did
+        let metadata = if self.path.is_empty() {
+            fs::metadata(root)
+        } else {
+            fs::symlink_metadata(root.join(&self.path))
+        };
+        metadata.is_ok_and(|metadata| {
+            metadata.is_dir() && Self::new(self.path.clone(), &metadata) == *self
+        })
+    }
+
+    /// Last time the directory or its entries changed
+    fn changed_ns(&self) -> i128 {
+        self.mtime_ns.max(self.ctime_ns)
+    }
+}
+
+/// Nanoseconds since the epoch
+fn timestamp_ns(secs: i64, nsecs: i64) -> i128 {
+    i128::from(secs) * 1_000_000_000 + i128::from(nsecs)
+}
+
+/// Lookups made through a [`FlistCache`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlistCacheStats {
+    /// Lists reused from the cache
+    pub hits: u64,
+    /// Lists that had to be scanned
+    pub misses: u64,
+    /// Entries of reused lists whose metadata changed since they were cached
+    pub refreshed: u64,
+}
+
+impl fmt::Display for FlistCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses, {} entries refreshed",
+            self.hits, self.misses, self.refreshed
+        )
+    }
+}
+
+/// File lists kept in a directory between runs
+#[derive(Debug)]
+pub struct FlistCache {
+    /// Directory holding one cache file per source
+    dir: PathBuf,
+    /// Lookups so far
+    stats: FlistCacheStats,
+    /// Time source for scan timestamps
+    clock: Arc<dyn Clock>,
+}
+
+impl FlistCache {
+    /// Cache kept in `dir` (created when the first list is saved)
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            stats: FlistCacheStats::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Take scan timestamps from `clock` instead of the system clock
+    #[must_use]
+    #[allow(dead_code)] // Tests pin the scan time; the sender uses the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Lookups made so far
+    #[must_use]
+    pub const fn stats(&self) -> FlistCacheStats {
+        self.stats
+    }
+
+    /// File list of `source`, reused from the cache if it is still valid
+    ///
+    /// On a miss the source is scanned and the cache updated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source cannot be scanned. Problems with the
+    /// cache itself only cause a miss.
+    pub async fn file_list(&mut self, source: &Path, args: &Args) -> Result<Vec<FileEntry>> {
+        if !source.is_dir() || !args.should_recurse() {
+            return scan_file_list(source, args, None).await;
+        }
+        let root = source
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", source.display()))?;
+        let cache_file = self.dir.join(cache_file_name(&root));
+
+        if let Some((files, refreshed)) = lookup(&cache_file, &root) {
+            debug!("Reusing cached file list for {}", root.display());
+            self.stats.hits += 1;
+            self.stats.refreshed += refreshed;
+            return Ok(files);
+        }
+        self.stats.misses += 1;
+
+        let scanned_at_ns = unix_nanos(self.clock.now());
+        let mut dirs = Vec::new();
+        let files = scan_file_list(source, args, Some(&mut dirs)).await?;
+        let cached = CachedList {
+            root,
+            scanned_at_ns,
+            dirs,
+            files,
+        };
+        if let Err(e) = cached.save(&self.dir, &cache_file) {
+            warn!("Failed to save the file list cache: {e:#}");
+        }
+        Ok(cached.files)
+    }
+}
+
+/// Cache file name for the source at `root`
+fn cache_file_name(root: &Path) -> String {
+    let digest = md5::compute(root.as_os_str().as_bytes());
+    let hash: String = digest.0.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{hash}.flist")
+}
+
+/// The cached list for `root` with refreshed metadata, if still valid
+fn lookup(cache_file: &Path, root: &Path) -> Option<(Vec<FileEntry>, u64)> {
+    let data = match fs::read(cache_file) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("No cached file list for {}", root.display());
+            return None;
+        }
+        Err(e) => {
+            warn!("Failed to read {}: {e}", cache_file.display());
+            return None;
+        }
+    };
+    let cached = match CachedList::decode(&data) {
+        Ok(cached) => cached,
+        Err(e) => {
+            warn!(
+                "Ignoring corrupt file list cache {}: {e}",
+                cache_file.display()
+            );
+            return None;
+        }
+    };
+    if cached.root != root {
+        debug!(
+            "{} holds the file list of {}",
+            cache_file.display(),
+            cached.root.display()
+        );
+        return None;
+    }
+    cached
+        .revalidate()
+        .map_err(|reason| debug!("Cached file list is stale: {reason}"))
+        .ok()
+}
+
+/// A file list together with what it was built from
+struct CachedList {
+    /// Canonical path of the source
+    root: PathBuf,
+    /// When the scan started
+    scanned_at_ns: i128,
+    /// Every directory scanned
+    dirs: Vec<DirStamp>,
+    /// The list
+    files: Vec<FileEntry>,
+}
+
+impl CachedList {
+    /// The files with current metadata and how many changed, or why the list
+    /// cannot be reused
+    fn revalidate(mut self) -> std::result::Result<(Vec<FileEntry>, u64), String> {
+        let racy_ns = i128::try_from(RACY_WINDOW.as_nanos()).unwrap_or_default();
+        for stamp in &self.dirs {
+            if stamp.changed_ns() + racy_ns >= self.scanned_at_ns {
+                return Err(format!(
+                    "{:?} changed just before it was scanned",
+                    stamp.path
+                ));
+            }
+            if !stamp.is_current(&self.root) {
+                return Err(format!("{:?} changed", stamp.path));
+            }
+        }
+
+        let mut refreshed = 0;
+        for file in &mut self.files {
+            let path = self.root.join(&file.path);
+            let metadata = fs::symlink_metadata(&path)
+                .map_err(|e| format!("cannot stat {}: {e}", path.display()))?;
+            let is_symlink = metadata.file_type().is_symlink();
+            if is_symlink != file.is_symlink || !(is_symlink || metadata.is_file()) {
+                return Err(format!("{} changed type", path.display()));
+            }
+            let symlink_target = if is_symlink {
+                let target = fs::read_link(&path)
+                    .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+                Some(target.to_string_lossy().to_string())
+            } else {
+                None
+            };
+            let current = FileEntry {
+                path: std::mem::take(&mut file.path),
+                size: if is_symlink { 0 } else { metadata.len() },
+                mtime: metadata.mtime(),
+                mode: metadata.mode(),
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                is_symlink,
+                symlink_target,
+            };
+            if !same_entry(file, &current) {
+                refreshed += 1;
+            }
+            *file = current;
+        }
+        Ok((self.files, refreshed))
+    }
+
+    /// Atomically replace `cache_file` in `dir` with this list
+    fn save(&self, dir: &Path, cache_file: &Path) -> Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let name = cache_file
+            .file_name()
+            .map(OsStr::to_string_lossy)
+            .unwrap_or_default();
+        let staged = crate::temp_files::temp_path(dir, &name);
+        let mut file = fs::File::create(&staged)
+            .with_context(|| format!("Failed to create {}", staged.display()))?;
+        file.write_all(&self.encode())?;
+        fs::rename(&staged, cache_file)
+            .with_context(|| format!("Failed to replace {}", cache_file.display()))?;
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.files.len() * 64);
+        out.extend_from_slice(CACHE_MAGIC);
+        put_bytes(&mut out, self.root.as_os_str().as_bytes());
+        out.extend_from_slice(&self.scanned_at_ns.to_le_bytes());
+
+        out.extend_from_slice(&(self.dirs.len() as u64).to_le_bytes());
+        for stamp in &self.dirs {
+            put_bytes(&mut out, stamp.path.as_bytes());
+            out.extend_from_slice(&stamp.dev.to_le_bytes());
+            out.extend_from_slice(&stamp.ino.to_le_bytes());
+            out.extend_from_slice(&stamp.mtime_ns.to_le_bytes());
+            out.extend_from_slice(&stamp.ctime_ns.to_le_bytes());
+        }
+
+        out.extend_from_slice(&(self.files.len() as u64).to_le_bytes());
+        for file in &self.files {
+            out.push(u8::from(file.is_symlink));
+            put_bytes(&mut out, file.path.as_bytes());
+            out.extend_from_slice(&file.size.to_le_bytes());
+            out.extend_from_slice(&file.mtime.to_le_bytes());
+            out.extend_from_slice(&file.mode.to_le_bytes());
+            out.extend_from_slice(&file.uid.to_le_bytes());
+            out.extend_from_slice(&file.gid.to_le_bytes());
+            let target = file.symlink_target.as_deref().unwrap_or_default();
+            put_bytes(&mut out, target.as_bytes());
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader(data);
+        if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC {
+            anyhow::bail!("bad magic");
+        }
+        let root = PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+        let scanned_at_ns = i128::from_le_bytes(reader.array()?);
+
+        let dir_count = u64::from_le_bytes(reader.array()?);
+        let mut dirs = Vec::new();
+        for _ in 0..dir_count {
+            dirs.push(DirStamp {
+                path: String::from_utf8(reader.bytes()?.to_vec())?,
+                dev: u64::from_le_bytes(reader.array()?),
+                ino: u64::from_le_bytes(reader.array()?),
+                mtime_ns: i128::from_le_bytes(reader.array()?),
+                ctime_ns: i128::from_le_bytes(reader.array()?),
+            });
+        }
+
+        let file_count = u64::from_le_bytes(reader.array()?);
+        let mut files = Vec::new();
+        for _ in 0..file_count {
+            let is_symlink = reader.array::<1>()?[0] != 0;
+            let path = String::from_utf8(reader.bytes()?.to_vec())?;
+            let size = u64::from_le_bytes(reader.array()?);
+            let mtime = i64::from_le_bytes(reader.array()?);
+            let mode = u32::from_le_bytes(reader.array()?);
+            let uid = u32::from_le_bytes(reader.array()?);
+            let gid = u32::from_le_bytes(reader.array()?);
+            let target = String::from_utf8(reader.bytes()?.to_vec())?;
+            files.push(FileEntry {
+                path,
+                size,
+                mtime,
+                mode,
+                uid,
+                gid,
+                is_symlink,
+                symlink_target: is_symlink.then_some(target),
+            });
+        }
+        if !reader.0.is_empty() {
+            anyhow::bail!("trailing data");
+        }
+        Ok(Self {
+            root,
+            scanned_at_ns,
+            dirs,
+            files,
+        })
+    }
+}
+
+/// Whether two entries describe the same file state
+fn same_entry(a: &FileEntry, b: &FileEntry) -> bool {
+    (
+        a.size,
+        a.mtime,
+        a.mode,
+        a.uid,
+        a.gid,
+        a.is_symlink,
+        &a.symlink_target,
+    ) == (
+        b.size,
+        b.mtime,
+        b.mode,
+        b.uid,
+        b.gid,
+        b.is_symlink,
+        &b.symlink_target,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::clock::MockClock;
+    use clap::Parser;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    #[compio::test]
+    async fn test_flist_cache_hit_and_invalidation() {
+        // Requirement: An unchanged tree is served from the cache with fresh
+        // file metadata; adding an entry to any directory invalidates it
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), b"a").unwrap();
+        fs::write(src.join("sub/b.txt"), b"b").unwrap();
+        // Directory timestamps this recent would not validate a hit, so
+        // the scans are dated past the racy window
+        let clock = MockClock::at(SystemTime::now() + 2 * RACY_WINDOW);
+
+        let args = Args::parse_from(["arsync", "-a", "/unused", "/unused"]);
+        let mut cache = FlistCache::new(&temp_dir.path().join("cache")).with_clock(Arc::new(clock));
+        let first = cache.file_list(&src, &args).await.unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(cache.stats().misses, 1);
+
+        // File contents change without touching the directory
+        fs::write(src.join("sub/b.txt"), b"bigger").unwrap();
+        let second = cache.file_list(&src, &args).await.unwrap();
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().refreshed, 1);
+        let b = second.iter().find(|f| f.path == "sub/b.txt").unwrap();
+        assert_eq!(b.size, 6);
+
+        // A new entry in a subdirectory is a miss
+        fs::write(src.join("sub/c.txt"), b"c").unwrap();
+        let third = cache.file_list(&src, &args).await.unwrap();
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(third.len(), 3);
+    }
+
+    #[test]
+    fn test_cached_list_round_trip() {
+        // Requirement: A saved list decodes to the same list; garbage is rejected
+        let cached = CachedList {
+            root: PathBuf::from("/data"),
+            scanned_at_ns: 42,
+            dirs: vec![DirStamp {
+                path: String::new(),
+                dev: 1,
+                ino: 2,
+                mtime_ns: 3,
+                ctime_ns: 4,
+            }],
+            files: vec![FileEntry {
+                path: "link".to_string(),
+                size: 0,
+                mtime: 5,
+                mode: 0o120_777,
+                uid: 6,
+                gid: 7,
+                is_symlink: true,
+                symlink_target: Some("target".to_string()),
+            }],
+        };
+        let decoded = CachedList::decode(&cached.encode()).unwrap();
+        assert_eq!(decoded.root, cached.root);
+        assert_eq!(decoded.scanned_at_ns, 42);
+        assert_eq!(decoded.dirs, cached.dirs);
+        assert_eq!(decoded.files[0].path, "link");
+        assert!(same_entry(&decoded.files[0], &cached.files[0]));
+
+        let mut truncated = cached.encode();
+        truncated.pop();
+        assert!(CachedList::decode(&truncated).is_err());
+        assert!(CachedList::decode(b"not a cache").is_err());
+    }
+}
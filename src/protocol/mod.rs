@@ -5,27 +5,71 @@
 //! - `PipeRole` enum for sender/receiver roles
 //! - `Transport` trait for bidirectional byte streams
 //! - `PipeTransport` for testing
+//! - `CapabilitySet` for versioned feature negotiation in the native protocol
+//! - `delta`: rsync block-matching delta engine (rolling + strong checksums)
+//! - `compress` for wire compression (zstd literals, rsync's zlib tokens)
+//! - `FlistCache` for reusing a sender's file list between runs
+//! - `TcpTransport` for direct daemon connections (Happy Eyeballs)
+//! - `daemon` for `arsync --daemon`, receiving from `rsync://` clients over TCP
+//! - `RemoteShell` for `--rsh` / `--remote-cmd` command lines
+//! - `UsageLedger` for per-module transfer accounting and quotas (daemon mode)
+//! - `ShutdownCoordinator` for draining sessions when a daemon shuts down
+//! - `probe` for `arsync probe`, reporting a remote end's capabilities
+//! - `rsync_sender` for pushing to a stock `rsync --server` (protocol 30)
+//! - `rsync_receiver` for `arsync --server`, receiving from an rsync client
+//!   (`receiver_dir` holds its destination directories open)
+//! - `rsync_wire` for the framing and encodings both of them share
 
 use anyhow::Result;
 use std::path::PathBuf;
 
+pub mod accounting;
 // Protocol implementation modules (only available with remote-sync feature)
 #[cfg(feature = "remote-sync")]
+pub mod capabilities;
+#[cfg(feature = "remote-sync")]
 pub mod checksum;
 #[cfg(feature = "remote-sync")]
+pub mod compress;
+#[cfg(feature = "remote-sync")]
+pub mod daemon;
+#[cfg(feature = "remote-sync")]
+pub mod delta;
+#[cfg(feature = "remote-sync")]
+pub mod flist_cache;
+#[cfg(feature = "remote-sync")]
 pub mod handshake;
 #[cfg(feature = "remote-sync")]
 pub mod pipe;
 #[cfg(feature = "remote-sync")]
+pub mod probe;
+#[cfg(feature = "remote-sync")]
+mod receiver_dir;
+#[cfg(feature = "remote-sync")]
 pub mod rsync;
 #[cfg(feature = "remote-sync")]
 pub mod rsync_compat;
 #[cfg(feature = "remote-sync")]
+pub mod rsync_receiver;
+#[cfg(feature = "remote-sync")]
+pub mod rsync_sender;
+#[cfg(feature = "remote-sync")]
+pub mod rsync_wire;
+#[cfg(feature = "remote-sync")]
+pub mod session;
+pub mod shell;
+#[cfg(feature = "remote-sync")]
+pub mod shutdown;
+#[cfg(feature = "remote-sync")]
 pub mod ssh;
 #[cfg(feature = "remote-sync")]
+pub mod tcp;
+#[cfg(feature = "remote-sync")]
 pub mod transport;
 #[cfg(feature = "remote-sync")]
 pub mod varint;
+#[cfg(feature = "remote-sync")]
+pub mod xattr_batch;
 
 /// Parsed location (local or remote)
 #[derive(Debug, Clone)]
@@ -65,6 +109,12 @@ impl Location {
             let host_part = &s[..colon_pos];
             let path_part = &s[colon_pos + 1..];
 
+            // Like rsync: a colon after a slash is part of a local path
+            // (./file:name, /mnt/a:b)
+            if host_part.contains('/') {
+                return Ok(Self::Local(PathBuf::from(s)));
+            }
+
             // Parse user@host or just host
             let (user, host) = host_part.find('@').map_or_else(
                 || (None, host_part.to_string()),
@@ -153,6 +203,17 @@ mod tests {
         assert_eq!(loc.path(), &PathBuf::from("C:\\Users\\user\\data"));
     }
 
+    #[test]
+    fn test_location_parse_local_path_with_colon() {
+        // Test: Parse local paths whose names contain a colon
+        // Requirement: A colon after a slash is part of a local path, as in rsync
+        for s in ["./file:name", "/mnt/backup:2024", "dir/a:b"] {
+            let loc = Location::parse(s).unwrap();
+            assert!(loc.is_local(), "{s}");
+            assert_eq!(loc.path(), &PathBuf::from(s));
+        }
+    }
+
     #[test]
     fn test_location_parse_remote_with_user() {
         // Test: Parse remote path with username (user@host:path)
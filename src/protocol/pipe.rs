@@ -29,6 +29,7 @@
 use super::transport::Transport;
 use compio::fs::AsyncFd;
 use compio::io::{AsyncRead, AsyncWrite};
+use compio_fs_extended::fd_hygiene::{dup_cloexec, pipe_cloexec};
 use std::io;
 use std::os::fd::OwnedFd;
 use std::os::unix::io::{FromRawFd, RawFd};
@@ -54,13 +55,10 @@ impl PipeTransport {
     ///
     /// Returns an error if FD duplication or `AsyncFd` creation fails.
     pub fn from_stdio() -> io::Result<Self> {
-        // Duplicate FDs so we don't close stdin/stdout
-        let stdin_fd = unsafe { libc::dup(0) };
-        let stdout_fd = unsafe { libc::dup(1) };
-
-        if stdin_fd < 0 || stdout_fd < 0 {
-            return Err(io::Error::last_os_error());
-        }
+        // Duplicate FDs so we don't close stdin/stdout; the duplicates are
+        // close-on-exec so they never leak into spawned processes
+        let stdin_fd = dup_cloexec(0)?;
+        let stdout_fd = dup_cloexec(1)?;
 
         // SAFETY: We just created these FDs via dup()
         unsafe { Self::from_fds(stdin_fd, stdout_fd, "stdio".to_string()) }
@@ -87,15 +85,13 @@ impl PipeTransport {
         })
     }
 
-    /// Create a Unix pipe pair, returns (`read_fd`, `write_fd`)
+    /// Create a close-on-exec Unix pipe pair, returns (`read_fd`, `write_fd`)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `pipe2` fails.
     pub fn create_pipe() -> io::Result<(RawFd, RawFd)> {
-        let mut fds = [0i32; 2];
-        unsafe {
-            if libc::pipe(fds.as_mut_ptr()) != 0 {
-                return Err(io::Error::last_os_error());
-            }
-        }
-        Ok(fds.into())
+        pipe_cloexec()
     }
 }
 
@@ -157,6 +153,8 @@ mod tests {
         assert!(read_fd >= 0);
         assert!(write_fd >= 0);
         assert_ne!(read_fd, write_fd);
+        assert!(compio_fs_extended::fd_hygiene::is_cloexec(read_fd).unwrap());
+        assert!(compio_fs_extended::fd_hygiene::is_cloexec(write_fd).unwrap());
 
         // Clean up
         unsafe {
@@ -0,0 +1,473 @@
+//! Remote capability probe (`arsync probe [USER@]HOST[:PATH]`)
+//!
+//! Before a big transfer it helps to know what the other end can do: which
+//! arsync and protocol versions it runs, which capabilities it would
+//! negotiate, and what the filesystem at the target path supports. The probe
+//! starts `arsync probe --server PATH` on the remote host (over `--rsh`, like
+//! a transfer), runs the same version and capability exchange a transfer
+//! would, and prints the report the server sends back. Nothing is written on
+//! either side.
+//!
+//! # Protocol
+//!
+//! ```text
+//! Client                                   Server
+//!   |-- protocol version (u8) ------------->|
+//!   |<------------- protocol version (u8) --|
+//!   |<-- capability frames (both ways) ---->|
+//!   |<-- "APRB" | u32 length | report ------|
+//! ```
+//!
+//! The report body is UTF-8 `key=value` lines. Unknown keys are ignored, so
+//! newer servers can add fields without breaking older clients.
+
+use crate::protocol::capabilities::{
+    self, CapabilitySet, NegotiatedCapabilities, CAPABILITY_VERSION, MIN_CAPABILITY_VERSION,
+};
+use crate::protocol::handshake::{MAX_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::protocol::transport::{self, Transport};
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Report frame magic
+const MAGIC: &[u8; 4] = b"APRB";
+
+/// Upper bound on a report body (guards against garbage input)
+const MAX_REPORT: usize = 64 * 1024;
+
+/// Compression codecs this build can use on the wire
+const COMPRESSION: &[&str] = &["none", "zstd", "zlib"];
+
+/// Checksums this build uses for delta transfer (rolling, strong)
+const CHECKSUMS: &[&str] = &["adler32-rolling", "md5"];
+
+/// What the filesystem at the probed path supports
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilesystemReport {
+    /// Path that was examined (the nearest existing ancestor of the target)
+    pub examined: String,
+    /// Whether the target path itself exists
+    pub exists: bool,
+    /// Filesystem type (tuning profile name, `generic` if unrecognized)
+    pub kind: String,
+    /// Mounted read-only
+    pub read_only: bool,
+    /// Total size in bytes
+    pub total_bytes: u64,
+    /// Bytes available to unprivileged users
+    pub available_bytes: u64,
+    /// Longest filename allowed
+    pub max_name_len: u64,
+    /// Extended attributes are supported
+    pub xattrs: bool,
+    /// POSIX ACLs are supported
+    pub acls: bool,
+    /// Reflinks (`FICLONE`) are supported
+    pub reflink: bool,
+}
+
+impl FilesystemReport {
+    /// Examine `path`, or its nearest existing ancestor
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no ancestor can be opened or `fstatvfs` fails.
+    pub fn examine(path: &Path) -> Result<Self> {
+        let (examined, dir) = path
+            .ancestors()
+            .map(|ancestor| {
+                if ancestor.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    ancestor
+                }
+            })
+            .find_map(|ancestor| Some((ancestor, std::fs::File::open(ancestor).ok()?)))
+            .with_context(|| format!("No existing ancestor of {}", path.display()))?;
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&dir);
+
+        let mut vfs = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: vfs is a valid out-pointer for fstatvfs on an open fd
+        if unsafe { libc::fstatvfs(fd, vfs.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("statvfs of {} failed", examined.display()));
+        }
+        // SAFETY: fstatvfs succeeded and initialized vfs
+        let vfs = unsafe { vfs.assume_init() };
+        #[allow(clippy::useless_conversion)] // field widths are platform-dependent
+        let (block_size, blocks, available, name_max) = (
+            u64::from(vfs.f_frsize),
+            u64::from(vfs.f_blocks),
+            u64::from(vfs.f_bavail),
+            u64::from(vfs.f_namemax),
+        );
+
+        let profile = crate::tuning::detect(examined);
+        Ok(Self {
+            examined: examined.display().to_string(),
+            exists: examined == path || path.as_os_str().is_empty(),
+            kind: format!("{profile:?}").to_lowercase(),
+            read_only: vfs.f_flag & libc::ST_RDONLY != 0,
+            total_bytes: blocks.saturating_mul(block_size),
+            available_bytes: available.saturating_mul(block_size),
+            max_name_len: name_max,
+            xattrs: xattr_supported(examined, None),
+            acls: xattr_supported(examined, Some(c"system.posix_acl_access")),
+            reflink: profile.settings().reflink,
+        })
+    }
+}
+
+/// Whether `path`'s filesystem supports xattrs (or the named one)
+///
+/// Listing (or reading) fails with `ENOTSUP` where unsupported; a missing
+/// attribute (`ENODATA`) or an empty list means supported.
+fn xattr_supported(path: &Path, name: Option<&std::ffi::CStr>) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: size queries with null buffers; path and name are NUL-terminated
+    let ret = unsafe {
+        match name {
+            Some(name) => libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0),
+            None => libc::listxattr(path.as_ptr(), std::ptr::null_mut(), 0),
+        }
+    };
+    ret >= 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::ENODATA)
+}
+
+/// What a probed server reports about itself and its target path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// arsync version of the server
+    pub version: String,
+    /// Protocol version range the server accepts (`min`, `current`, `max`)
+    pub protocol: (u8, u8, u8),
+    /// Capability version range (`min`, `current`)
+    pub capability_versions: (u16, u16),
+    /// Capabilities the server advertises (including ones this build lacks)
+    pub capabilities: Vec<String>,
+    /// Wire compression codecs
+    pub compression: Vec<String>,
+    /// Delta-transfer checksums (rolling, strong)
+    pub checksums: Vec<String>,
+    /// Filesystem at the target path, or why it could not be examined
+    pub filesystem: std::result::Result<FilesystemReport, String>,
+}
+
+impl ProbeReport {
+    /// Report for this build, examining `path`
+    #[must_use]
+    pub fn local(path: &Path) -> Self {
+        let strings =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol: (MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, MAX_PROTOCOL_VERSION),
+            capability_versions: (MIN_CAPABILITY_VERSION, CAPABILITY_VERSION),
+            capabilities: CapabilitySet::current()
+                .entries
+                .keys()
+                .map(ToString::to_string)
+                .collect(),
+            compression: strings(COMPRESSION),
+            checksums: strings(CHECKSUMS),
+            filesystem: FilesystemReport::examine(path).map_err(|e| format!("{e:#}")),
+        }
+    }
+
+    /// Encode as `key=value` lines
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut lines = vec![
+            format!("version={}", self.version),
+            format!(
+                "protocol={},{},{}",
+                self.protocol.0, self.protocol.1, self.protocol.2
+            ),
+            format!(
+                "capability_versions={},{}",
+                self.capability_versions.0, self.capability_versions.1
+            ),
+            format!("capabilities={}", self.capabilities.join(",")),
+            format!("compression={}", self.compression.join(",")),
+            format!("checksums={}", self.checksums.join(",")),
+        ];
+        match &self.filesystem {
+            Ok(fs) => lines.extend([
+                format!("fs.examined={}", fs.examined.replace('\n', "?")),
+                format!("fs.exists={}", fs.exists),
+                format!("fs.type={}", fs.kind),
+                format!("fs.read_only={}", fs.read_only),
+                format!("fs.total_bytes={}", fs.total_bytes),
+                format!("fs.available_bytes={}", fs.available_bytes),
+                format!("fs.max_name_len={}", fs.max_name_len),
+                format!("fs.xattrs={}", fs.xattrs),
+                format!("fs.acls={}", fs.acls),
+                format!("fs.reflink={}", fs.reflink),
+            ]),
+            Err(e) => lines.push(format!("fs.error={}", e.replace('\n', " "))),
+        }
+        let mut body = lines.join("\n").into_bytes();
+        body.push(b'\n');
+        body
+    }
+
+    /// Decode `key=value` lines, ignoring unknown keys
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is not UTF-8 or a known field is malformed.
+    pub fn decode(body: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(body).context("Probe report is not UTF-8")?;
+        let mut report = Self {
+            version: String::new(),
+            protocol: (0, 0, 0),
+            capability_versions: (0, 0),
+            capabilities: Vec::new(),
+            compression: Vec::new(),
+            checksums: Vec::new(),
+            filesystem: Ok(FilesystemReport::default()),
+        };
+        let mut fs = FilesystemReport::default();
+        let mut fs_error = None;
+        let list = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let bad = || format!("Malformed probe report field {key}={value}");
+            match key {
+                "version" => report.version = value.to_string(),
+                "protocol" => {
+                    let numbers: Vec<u8> = value
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<std::result::Result<_, _>>()
+                        .with_context(bad)?;
+                    let [min, current, max] = numbers[..] else {
+                        anyhow::bail!(bad());
+                    };
+                    report.protocol = (min, current, max);
+                }
+                "capability_versions" => {
+                    let (min, current) = value.split_once(',').with_context(bad)?;
+                    report.capability_versions = (
+                        min.parse().with_context(bad)?,
+                        current.parse().with_context(bad)?,
+                    );
+                }
+                "capabilities" => report.capabilities = list(value),
+                "compression" => report.compression = list(value),
+                "checksums" => report.checksums = list(value),
+                "fs.examined" => fs.examined = value.to_string(),
+                "fs.exists" => fs.exists = value.parse().with_context(bad)?,
+                "fs.type" => fs.kind = value.to_string(),
+                "fs.read_only" => fs.read_only = value.parse().with_context(bad)?,
+                "fs.total_bytes" => fs.total_bytes = value.parse().with_context(bad)?,
+                "fs.available_bytes" => fs.available_bytes = value.parse().with_context(bad)?,
+                "fs.max_name_len" => fs.max_name_len = value.parse().with_context(bad)?,
+                "fs.xattrs" => fs.xattrs = value.parse().with_context(bad)?,
+                "fs.acls" => fs.acls = value.parse().with_context(bad)?,
+                "fs.reflink" => fs.reflink = value.parse().with_context(bad)?,
+                "fs.error" => fs_error = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        report.filesystem = fs_error.map_or(Ok(fs), Err);
+        Ok(report)
+    }
+}
+
+/// Everything the client learned from a probe
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    /// Protocol version the server answered with
+    pub remote_protocol: u8,
+    /// Capabilities both ends would use for a transfer
+    pub negotiated: NegotiatedCapabilities,
+    /// The server's own report
+    pub report: ProbeReport,
+}
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = &self.report;
+        let (min, current, max) = report.protocol;
+        writeln!(f, "Remote arsync:        {}", report.version)?;
+        writeln!(
+            f,
+            "Protocol versions:    {min}-{max} (speaks {current}; answered {})",
+            self.remote_protocol
+        )?;
+        writeln!(
+            f,
+            "Capability versions:  {}-{} (negotiated {})",
+            report.capability_versions.0, report.capability_versions.1, self.negotiated.version
+        )?;
+        let negotiated: Vec<String> = self
+            .negotiated
+            .enabled
+            .keys()
+            .map(ToString::to_string)
+            .collect();
+        let remote_only: Vec<&str> = report
+            .capabilities
+            .iter()
+            .filter(|capability| !negotiated.contains(capability))
+            .map(String::as_str)
+            .collect();
+        writeln!(f, "Capabilities:         {}", negotiated.join(", "))?;
+        if !remote_only.is_empty() {
+            writeln!(f, "  remote only:        {}", remote_only.join(", "))?;
+        }
+        writeln!(f, "Compression:          {}", report.compression.join(", "))?;
+        writeln!(f, "Checksums:            {}", report.checksums.join(", "))?;
+        match &report.filesystem {
+            Ok(fs) => {
+                let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+                let missing = if fs.exists {
+                    ""
+                } else {
+                    " (target does not exist yet)"
+                };
+                writeln!(f, "Filesystem:           {}{missing}", fs.examined)?;
+                writeln!(f, "  type:               {}", fs.kind)?;
+                writeln!(f, "  read-only:          {}", yes_no(fs.read_only))?;
+                writeln!(
+                    f,
+                    "  space:              {} of {} bytes available",
+                    fs.available_bytes, fs.total_bytes
+                )?;
+                writeln!(f, "  max name length:    {}", fs.max_name_len)?;
+                writeln!(f, "  xattrs:             {}", yes_no(fs.xattrs))?;
+                writeln!(f, "  ACLs:               {}", yes_no(fs.acls))?;
+                write!(f, "  reflinks:           {}", yes_no(fs.reflink))
+            }
+            Err(e) => write!(f, "Filesystem:           not examined: {e}"),
+        }
+    }
+}
+
+/// Probe a server over `transport`
+///
+/// # Errors
+///
+/// Returns an error if the handshake or capability negotiation fails, or the
+/// report is malformed.
+pub async fn probe<T: Transport>(transport: &mut T) -> Result<ProbeResult> {
+    transport::write_all(transport, &[PROTOCOL_VERSION]).await?;
+    let mut version = [0u8; 1];
+    transport::read_exact(transport, &mut version).await?;
+    let negotiated =
+        capabilities::exchange_capabilities(transport, &CapabilitySet::current()).await?;
+
+    let mut header = [0u8; 8];
+    transport::read_exact(transport, &mut header).await?;
+    if &header[..4] != MAGIC {
+        anyhow::bail!("Remote did not send a probe report (is it an older arsync?)");
+    }
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > MAX_REPORT {
+        anyhow::bail!("Remote probe report too large: {len} bytes");
+    }
+    let mut body = vec![0u8; len];
+    transport::read_exact(transport, &mut body).await?;
+
+    Ok(ProbeResult {
+        remote_protocol: version[0],
+        negotiated,
+        report: ProbeReport::decode(&body)?,
+    })
+}
+
+/// Answer a probe over `transport`, reporting on `path`
+///
+/// # Errors
+///
+/// Returns an error if I/O fails or capability negotiation fails.
+pub async fn serve_probe<T: Transport>(transport: &mut T, path: &Path) -> Result<()> {
+    let mut version = [0u8; 1];
+    transport::read_exact(transport, &mut version).await?;
+    transport::write_all(transport, &[PROTOCOL_VERSION]).await?;
+    capabilities::exchange_capabilities(transport, &CapabilitySet::current()).await?;
+
+    let body = ProbeReport::local(path).encode();
+    let mut frame = Vec::with_capacity(MAGIC.len() + 4 + body.len());
+    frame.extend_from_slice(MAGIC);
+    frame.extend_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+    frame.extend_from_slice(&body);
+    transport::write_all(transport, &frame).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::protocol::capabilities::Capability;
+    use crate::protocol::pipe::PipeTransport;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_report_round_trip() {
+        // Requirement: The report survives encoding, and unknown keys from
+        // newer servers are ignored
+        let temp_dir = TempDir::new().unwrap();
+        let report = ProbeReport::local(&temp_dir.path().join("not/yet"));
+        let fs = report.filesystem.as_ref().unwrap();
+        assert!(!fs.exists);
+        assert_eq!(fs.examined, temp_dir.path().display().to_string());
+        assert!(fs.total_bytes >= fs.available_bytes);
+
+        let mut body = report.encode();
+        body.extend_from_slice(b"future.field=42\nnot a field\n");
+        assert_eq!(ProbeReport::decode(&body).unwrap(), report);
+
+        let failed = ProbeReport {
+            filesystem: Err("permission denied".to_string()),
+            ..report
+        };
+        assert_eq!(ProbeReport::decode(&failed.encode()).unwrap(), failed);
+        assert!(ProbeReport::decode(b"protocol=31,27\n").is_err());
+    }
+
+    #[compio::test]
+    async fn test_probe_over_pipe() {
+        // Requirement: A probe performs the real handshake and negotiation
+        // and returns the server's report on the target path
+        let temp_dir = TempDir::new().unwrap();
+        let (client_read, server_write) = PipeTransport::create_pipe().unwrap();
+        let (server_read, client_write) = PipeTransport::create_pipe().unwrap();
+        // SAFETY: the FDs were just created and each is owned by exactly one transport
+        let (mut client, mut server) = unsafe {
+            (
+                PipeTransport::from_fds(client_read, client_write, "client".to_string()).unwrap(),
+                PipeTransport::from_fds(server_read, server_write, "server".to_string()).unwrap(),
+            )
+        };
+
+        let path = temp_dir.path().to_path_buf();
+        let serving = compio::runtime::spawn(async move {
+            serve_probe(&mut server, &path).await.unwrap();
+        });
+        let result = probe(&mut client).await.unwrap();
+        serving.await.unwrap();
+
+        assert_eq!(result.remote_protocol, PROTOCOL_VERSION);
+        assert!(result.negotiated.supports(Capability::SEGMENTED_DELTA));
+        assert_eq!(result.report.version, env!("CARGO_PKG_VERSION"));
+        assert!(result.report.filesystem.as_ref().unwrap().exists);
+        assert!(result.to_string().contains("Filesystem:"));
+    }
+}
@@ -0,0 +1,380 @@
+//! Destination directories of the rsync receiver, held open
+//!
+//! The receiver reaches every entry as a name in an open directory rather
+//! than by path, so a symlink swapped into the destination between a check
+//! and a use cannot redirect it (in a daemon module: out of the module).
+//! Directories below the destination are opened from their parent with
+//! `O_NOFOLLOW`, as `tar_import` does, and the `*at` calls that take it get
+//! `AT_SYMLINK_NOFOLLOW`.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::path::{Path, PathBuf};
+
+/// An open directory, with its path for messages
+#[derive(Debug)]
+pub(super) struct Dir {
+    file: File,
+    path: PathBuf,
+}
+
+/// An entry's `fstatat`, of the symlink itself if it is one
+#[derive(Clone, Copy)]
+pub(super) struct Stat(libc::stat);
+
+impl Stat {
+    pub(super) fn mode(&self) -> u32 {
+        self.0.st_mode
+    }
+
+    pub(super) fn file_type(&self) -> u32 {
+        self.0.st_mode & libc::S_IFMT
+    }
+
+    pub(super) fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    pub(super) fn is_file(&self) -> bool {
+        self.file_type() == libc::S_IFREG
+    }
+
+    pub(super) fn is_symlink(&self) -> bool {
+        self.file_type() == libc::S_IFLNK
+    }
+
+    pub(super) fn len(&self) -> u64 {
+        self.0.st_size as u64
+    }
+
+    pub(super) fn mtime(&self) -> i64 {
+        self.0.st_mtime
+    }
+
+    pub(super) fn rdev(&self) -> u64 {
+        self.0.st_rdev
+    }
+}
+
+impl Dir {
+    /// Open the directory at `path`, following symlinks
+    pub(super) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(path)?;
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Path of this directory, for messages
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the subdirectory `name`, refusing a symlink
+    pub(super) fn open_dir(&self, name: &OsStr) -> io::Result<Self> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW;
+        let path = self.path.join(name);
+        match self.open_at(name, flags, 0) {
+            Ok(file) => Ok(Self { file, path }),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR)) => {
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "{} is not a directory (symlinks are not followed)",
+                        path.display()
+                    ),
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open the file `name` for reading, refusing a symlink
+    pub(super) fn open_file(&self, name: &OsStr) -> io::Result<File> {
+        self.open_at(name, libc::O_RDONLY | libc::O_NOFOLLOW, 0)
+    }
+
+    /// Create the file `name`, which must not exist, for writing
+    pub(super) fn create_file(&self, name: &OsStr, mode: u32) -> io::Result<File> {
+        let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW;
+        self.open_at(name, flags, mode)
+    }
+
+    fn open_at(&self, name: &OsStr, flags: libc::c_int, mode: u32) -> io::Result<File> {
+        let name = c_name(name)?;
+        // SAFETY: The directory is open and name is NUL-terminated
+        let fd = unsafe {
+            libc::openat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_CLOEXEC,
+                mode as libc::c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: We just opened this fd and own it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Status of the entry `name`, without following a symlink
+    pub(super) fn stat(&self, name: &OsStr) -> io::Result<Stat> {
+        let name = c_name(name)?;
+        // SAFETY: An all-zero stat is valid; fstatat overwrites it
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        // SAFETY: The directory is open, name is NUL-terminated and stat is
+        // writable
+        check(unsafe {
+            libc::fstatat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                &mut stat,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(Stat(stat))
+    }
+
+    /// Names of the entries, without `.` and `..`
+    pub(super) fn entries(&self) -> io::Result<Vec<OsString>> {
+        // A descriptor of its own, which closedir closes
+        let own = self.open_at(OsStr::new("."), libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        // SAFETY: own is an open directory
+        let stream = unsafe { libc::fdopendir(own.as_raw_fd()) };
+        if stream.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let _ = own.into_raw_fd();
+
+        let mut names = Vec::new();
+        let result = loop {
+            clear_errno();
+            // SAFETY: stream is an open directory stream
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                let err = io::Error::last_os_error();
+                break if err.raw_os_error() == Some(0) {
+                    Ok(())
+                } else {
+                    Err(err)
+                };
+            }
+            // SAFETY: readdir returned an entry whose name is NUL-terminated
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsStr::from_bytes(name).to_os_string());
+            }
+        };
+        // SAFETY: stream is open and not used after this
+        unsafe { libc::closedir(stream) };
+        result.map(|()| names)
+    }
+
+    /// Create the directory `name`
+    pub(super) fn mkdir(&self, name: &OsStr, mode: u32) -> io::Result<()> {
+        let name = c_name(name)?;
+        // SAFETY: The directory is open and name is NUL-terminated
+        check(unsafe { libc::mkdirat(self.file.as_raw_fd(), name.as_ptr(), mode as libc::mode_t) })
+    }
+
+    /// Create the symlink `name` to `target`
+    pub(super) fn symlink(&self, target: &OsStr, name: &OsStr) -> io::Result<()> {
+        let (target, name) = (c_name(target)?, c_name(name)?);
+        // SAFETY: The directory is open and both strings are NUL-terminated
+        check(unsafe { libc::symlinkat(target.as_ptr(), self.file.as_raw_fd(), name.as_ptr()) })
+    }
+
+    /// Target of the symlink `name`
+    pub(super) fn read_link(&self, name: &OsStr) -> io::Result<OsString> {
+        let name = c_name(name)?;
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        // SAFETY: The directory is open, name is NUL-terminated and buf has
+        // room for buf.len() bytes
+        let len = unsafe {
+            libc::readlinkat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len.unsigned_abs());
+        Ok(OsStr::from_bytes(&buf).to_os_string())
+    }
+
+    /// Create the fifo, socket or device file `name`
+    pub(super) fn mknod(&self, name: &OsStr, mode: u32, dev: u64) -> io::Result<()> {
+        let name = c_name(name)?;
+        // SAFETY: The directory is open and name is NUL-terminated
+        check(unsafe {
+            libc::mknodat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                dev as libc::dev_t,
+            )
+        })
+    }
+
+    /// Rename `from` to `to`, both in this directory
+    pub(super) fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+        let (from, to) = (c_name(from)?, c_name(to)?);
+        let fd = self.file.as_raw_fd();
+        // SAFETY: The directory is open and both names are NUL-terminated
+        check(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })
+    }
+
+    /// Remove the entry `name`: an empty directory if `dir`, else anything
+    /// else
+    pub(super) fn remove(&self, name: &OsStr, dir: bool) -> io::Result<()> {
+        let name = c_name(name)?;
+        let flags = if dir { libc::AT_REMOVEDIR } else { 0 };
+        // SAFETY: The directory is open and name is NUL-terminated
+        check(unsafe { libc::unlinkat(self.file.as_raw_fd(), name.as_ptr(), flags) })
+    }
+
+    /// Remove the directory `name` and everything in it, without following
+    /// symlinks
+    pub(super) fn remove_all(&self, name: &OsStr) -> io::Result<()> {
+        let dir = self.open_dir(name)?;
+        for child in dir.entries()? {
+            if dir.stat(&child)?.is_dir() {
+                dir.remove_all(&child)?;
+            } else {
+                dir.remove(&child, false)?;
+            }
+        }
+        self.remove(name, true)
+    }
+
+    /// Change the owner and group of `name` (`None` leaves one as it is),
+    /// of the symlink itself if it is one
+    pub(super) fn chown(&self, name: &OsStr, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let name = c_name(name)?;
+        // -1 leaves the id unchanged
+        let (uid, gid) = (uid.unwrap_or(u32::MAX), gid.unwrap_or(u32::MAX));
+        // SAFETY: The directory is open and name is NUL-terminated
+        check(unsafe {
+            libc::fchownat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                uid,
+                gid,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// Change the permissions of `name`, which must not be a symlink
+    pub(super) fn chmod(&self, name: &OsStr, mode: u32) -> io::Result<()> {
+        let name = c_name(name)?;
+        // SAFETY: The directory is open and name is NUL-terminated
+        check(unsafe {
+            libc::fchmodat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// Set the modification time of `name` (whole seconds), leaving its
+    /// access time
+    pub(super) fn set_mtime(&self, name: &OsStr, mtime: i64) -> io::Result<()> {
+        let name = c_name(name)?;
+        let times = [
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+            libc::timespec {
+                tv_sec: mtime as libc::time_t,
+                tv_nsec: 0,
+            },
+        ];
+        // SAFETY: The directory is open, name is NUL-terminated and times
+        // holds two timespecs
+        check(unsafe {
+            libc::utimensat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+}
+
+/// Entry name as a C string
+fn c_name(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// The error of a libc call that returned `rc`
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Reset `errno`, so the end of a directory stream tells from an error
+fn clear_errno() {
+    #[cfg(target_os = "linux")]
+    let errno = libc::__errno_location;
+    #[cfg(not(target_os = "linux"))]
+    let errno = libc::__error;
+    // SAFETY: errno is thread-local and always writable
+    unsafe { *errno() = 0 };
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_entries_are_reached_without_following_symlinks() {
+        // Requirement: Subdirectories and files are opened from the held
+        // directory and a symlink in their place is refused, not followed
+        let temp = tempfile::TempDir::new().unwrap();
+        let (root, outside) = (temp.path().join("root"), temp.path().join("outside"));
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret"), b"secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret"), root.join("link")).unwrap();
+
+        let dir = Dir::open(&root).unwrap();
+        assert!(dir.open_dir(OsStr::new("dir")).is_ok());
+        let err = dir.open_dir(OsStr::new("escape")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(dir.open_file(OsStr::new("link")).is_err());
+        assert!(dir.stat(OsStr::new("escape")).unwrap().is_symlink());
+
+        let mut names = dir.entries().unwrap();
+        names.sort();
+        assert_eq!(names, ["dir", "escape", "link"]);
+
+        // Removing a tree removes the symlinks in it, not their targets
+        std::os::unix::fs::symlink(&outside, root.join("dir/escape")).unwrap();
+        dir.remove_all(OsStr::new("dir")).unwrap();
+        assert!(!root.join("dir").exists());
+        assert_eq!(std::fs::read(outside.join("secret")).unwrap(), b"secret");
+    }
+}
@@ -18,20 +18,26 @@
 #![allow(clippy::doc_markdown)] // Protocol documentation
 
 use crate::cli::Args;
+use crate::protocol::capabilities::{self, Capability, CapabilitySet, NegotiatedCapabilities};
 use crate::protocol::checksum::{rolling_checksum, strong_checksum};
+use crate::protocol::compress::{self, CompressLevel, LiteralCompressor, LiteralDecompressor};
+pub use crate::protocol::delta::DeltaInstruction;
+use crate::protocol::delta::{self as delta_engine, BlockSignature, Signature};
+use crate::protocol::flist_cache::{DirStamp, FlistCache};
 use crate::protocol::pipe::PipeTransport;
+use crate::protocol::session::{self, ResumePoint, SessionCheckpoint, SessionToken};
+use crate::protocol::shutdown::{SessionGuard, SessionStopped};
 use crate::protocol::ssh::SshConnection;
 use crate::protocol::transport::{self, Transport};
+use crate::protocol::xattr_batch::{self, XattrBatch, XattrLimits, XattrStats};
 use crate::sync::SyncStats;
 use anyhow::Result;
 use compio::io::AsyncWrite;
-#[allow(clippy::disallowed_types)]
-// HashMap required for O(1) checksum lookup in delta algorithm
-use std::collections::HashMap;
 use std::fs;
+use std::io::{Seek, SeekFrom, Write};
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use tracing::{debug, info, warn};
 use walkdir;
 
@@ -47,6 +53,12 @@ const DEFAULT_BLOCK_SIZE: usize = 700;
 /// Minimum block size
 const MIN_BLOCK_SIZE: usize = 128;
 
+/// Bytes of source data per delta segment in the pipe protocol
+///
+/// The receiver checkpoints after every segment, so this is the most data a
+/// resumed session has to resend for the interrupted file.
+const RESUME_SEGMENT_SIZE: usize = 8 * 1024 * 1024;
+
 /// Rolling checksum constants (Adler-32 style)
 #[allow(dead_code)]
 const ROLLING_MODULUS: u32 = 65521;
@@ -212,20 +224,6 @@ pub struct BlockChecksum {
     pub block_index: u32,
 }
 
-/// Delta instruction for reconstructing files
-#[derive(Debug, Clone)]
-pub enum DeltaInstruction {
-    /// Raw data to insert (when no match found)
-    Literal(Vec<u8>),
-    /// Copy from basis file using block index
-    BlockMatch {
-        /// Index of the matching block in the basis file
-        block_index: u32,
-        /// Length of the match in bytes
-        length: u32,
-    },
-}
-
 /// Calculate optimal block size for a file
 fn calculate_block_size(file_size: u64) -> usize {
     if file_size == 0 {
@@ -245,8 +243,24 @@ fn calculate_block_size(file_size: u64) -> usize {
 pub async fn send_via_pipe(
     args: &Args,
     source_path: &Path,
-    mut transport: PipeTransport,
+    transport: PipeTransport,
 ) -> Result<SyncStats> {
+    send_via_pipe_resumable(args, source_path, transport, None)
+        .await
+        .map(|(stats, _)| stats)
+}
+
+/// Send files via pipe transport, optionally resuming an interrupted session
+///
+/// Pass the token returned (and logged) by an earlier, interrupted call as
+/// `resume` to skip what the receiver already applied. Returns the session
+/// token alongside the statistics.
+pub async fn send_via_pipe_resumable(
+    args: &Args,
+    source_path: &Path,
+    mut transport: PipeTransport,
+    resume: Option<SessionToken>,
+) -> Result<(SyncStats, SessionToken)> {
     let start = Instant::now();
 
     debug!("Sender: Starting protocol handshake");
@@ -254,22 +268,56 @@ pub async fn send_via_pipe(
     // Phase 1: Handshake
     let remote_version = handshake_sender(&mut transport).await?;
     debug!("Sender: Handshake complete, remote version: {remote_version}");
+    let compress_level = args.remote.compress_level();
+    let negotiated = negotiate_pipe_capabilities(&mut transport, compress_level).await?;
+    let xattr_batches = negotiated.supports(Capability::XATTR_BATCH);
+    let mut compressor = match compress::session_level(compress_level, &negotiated) {
+        Some(level) => {
+            info!("Sender: Compressing file data with zstd (level {level})");
+            Some(LiteralCompressor::new(level)?)
+        }
+        None => None,
+    };
 
     // Phase 2: Send file list
     let source_display = source_path.display();
     debug!("Sender: Generating file list from: {source_display}");
-    let files = generate_file_list_simple(source_path, args).await?;
+    let files = match &args.remote.flist_cache {
+        Some(cache_dir) => {
+            let mut cache = FlistCache::new(cache_dir);
+            let files = cache.file_list(source_path, args).await?;
+            info!("Sender: File list cache: {}", cache.stats());
+            files
+        }
+        None => generate_file_list_simple(source_path, args).await?,
+    };
     let file_count = files.len();
     info!("Sender: Found {file_count} files to send");
 
     send_file_list_simple(&mut transport, &files).await?;
     debug!("Sender: File list sent");
 
-    // Phase 3: Delta transfer with block checksums
+    // Phase 3: Session negotiation (where the receiver wants us to continue)
+    let (token, resume_point) = session::request_session(&mut transport, resume).await?;
+    info!("Sender: Session {token} (use this token to resume if interrupted)");
+    if resume_point != ResumePoint::START {
+        info!(
+            "Sender: Resuming at file {} offset {}",
+            resume_point.file_index, resume_point.offset
+        );
+    }
+
+    // Phase 4: Delta transfer with block checksums, in resumable segments
     let mut bytes_sent = 0u64;
     let mut bytes_matched = 0u64;
+    let xattr_limits = XattrLimits::default();
+    let mut xattr_stats = XattrStats::default();
 
-    for file in &files {
+    for (index, file) in files
+        .iter()
+        .enumerate()
+        .skip(resume_point.file_index as usize)
+    {
         if file.is_symlink {
             // Symlinks have no content, skip
             continue;
@@ -282,31 +330,40 @@ pub async fn send_via_pipe(
         // Read file content
         let content = fs::read(&file_path)
             .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", file.path))?;
+        let offset = resume_point.offset_for(index as u64);
+        let remaining = content.get(offset as usize..).ok_or_else(|| {
+            anyhow::anyhow!("{} is shorter than the resume offset {offset}", file.path)
+        })?;
 
         // Receive block checksums from receiver
         let block_checksums = receive_block_checksums(&mut transport).await?;
+        let checksum_count = block_checksums.len();
+        debug!("Sender: Received {checksum_count} block checksums");
 
-        if block_checksums.is_empty() {
-            // No basis file, send everything as literal
-            let content_len = content.len();
-            debug!("Sender: No basis file, sending {content_len} bytes as literal");
-            let delta = vec![DeltaInstruction::Literal(content.clone())];
-            send_delta(&mut transport, &delta).await?;
-            bytes_sent += content.len() as u64;
-        } else {
-            // Generate delta using block matching
-            let checksum_count = block_checksums.len();
-            debug!("Sender: Received {checksum_count} block checksums, generating delta");
-            let delta = generate_delta(&content, &block_checksums)?;
+        // Each segment is length-prefixed; a zero length ends the file
+        for segment in remaining.chunks(RESUME_SEGMENT_SIZE) {
+            transport::write_all(&mut transport, &(segment.len() as u64).to_le_bytes()).await?;
 
-            // Calculate statistics
+            // Without a basis (no checksums) the delta is a single literal
+            let delta = generate_delta(segment, &block_checksums)?;
             let (literal_bytes, matched_bytes) = count_delta_bytes(&delta);
             debug!("Sender: Delta: {literal_bytes} literal bytes, {matched_bytes} matched bytes");
 
-            send_delta(&mut transport, &delta).await?;
+            send_delta(&mut transport, &delta, compressor.as_mut()).await?;
             bytes_sent += literal_bytes as u64;
             bytes_matched += matched_bytes as u64;
         }
+        transport::write_all(&mut transport, &0u64.to_le_bytes()).await?;
+
+        // One frame carries all of the file's xattrs (empty if not preserving)
+        if xattr_batches {
+            let batch = if args.metadata.should_preserve_xattrs() {
+                XattrBatch::collect(&file_path, &xattr_limits, &mut xattr_stats).await
+            } else {
+                XattrBatch::default()
+            };
+            xattr_batch::send_xattr_batch(&mut transport, &batch).await?;
+        }
     }
 
     // Flush to ensure all data is sent
@@ -316,19 +373,54 @@ pub async fn send_via_pipe(
         .map_err(|e| anyhow::anyhow!("Flush failed: {e}"))?;
 
     info!("Sender: Transfer complete, sent {bytes_sent} bytes, matched {bytes_matched} bytes");
+    if xattr_stats.dropped > 0 || xattr_stats.truncated > 0 {
+        warn!(
+            "Sender: {} xattrs sent, {} dropped, {} truncated ({} bytes not sent)",
+            xattr_stats.sent, xattr_stats.dropped, xattr_stats.truncated, xattr_stats.bytes_dropped
+        );
+    }
 
-    Ok(SyncStats {
+    let stats = SyncStats {
         files_copied: files.len() as u64,
         bytes_copied: files.iter().map(|f| f.size).sum(),
+        errors: 0,
         duration: start.elapsed(),
-    })
+    };
+    Ok((stats, token))
 }
 
 /// Receive files via pipe transport (for testing)
+///
+/// Progress is checkpointed in `dest_path` so that an interrupted session can
+/// be resumed by the sender (see `crate::protocol::session`).
 pub async fn receive_via_pipe(
-    _args: &Args,
+    args: &Args,
+    transport: PipeTransport,
+    dest_path: &Path,
+) -> Result<SyncStats> {
+    receive_session(args, transport, dest_path, None).await
+}
+
+/// Receive files as one session of a daemon that may shut down
+///
+/// Like `receive_via_pipe`, but once the daemon's drain deadline has passed
+/// (`SessionGuard::must_stop`) the transfer stops after the next checkpoint
+/// with `SessionStopped`, leaving the checkpoint for the client to resume.
+#[allow(dead_code)] // Used by the daemon listener, which is not implemented yet
+pub async fn receive_via_pipe_guarded(
+    args: &Args,
+    transport: PipeTransport,
+    dest_path: &Path,
+    guard: &SessionGuard,
+) -> Result<SyncStats> {
+    receive_session(args, transport, dest_path, Some(guard)).await
+}
+
+async fn receive_session(
+    args: &Args,
     mut transport: PipeTransport,
     dest_path: &Path,
+    guard: Option<&SessionGuard>,
 ) -> Result<SyncStats> {
     let start = Instant::now();
 
@@ -337,6 +429,12 @@ pub async fn receive_via_pipe(
     // Phase 1: Handshake
     let remote_version = handshake_receiver(&mut transport).await?;
     debug!("Receiver: Handshake complete, remote version: {remote_version}");
+    let compress_level = args.remote.compress_level();
+    let negotiated = negotiate_pipe_capabilities(&mut transport, compress_level).await?;
+    let xattr_batches = negotiated.supports(Capability::XATTR_BATCH);
+    let mut decompressor = compress::session_level(compress_level, &negotiated)
+        .map(|_| LiteralDecompressor::new())
+        .transpose()?;
 
     // Phase 2: Receive file list
     debug!("Receiver: Receiving file list");
@@ -344,11 +442,22 @@ pub async fn receive_via_pipe(
     let file_count = files.len();
     info!("Receiver: Received {file_count} files");
 
-    // Phase 3: Delta transfer with block checksums
+    // Phase 3: Session negotiation (resume from our checkpoint if requested)
+    fs::create_dir_all(dest_path)?;
+    let durable = args.metadata.fsync;
+    let mut checkpoint = session::accept_session(&mut transport, dest_path, &files).await?;
+    let resume_point = checkpoint.resume;
+    checkpoint.save(dest_path, durable)?;
+
+    // Phase 4: Delta transfer with block checksums, in resumable segments
     let mut bytes_received = 0u64;
     let mut bytes_matched = 0u64;
 
-    for file in &files {
+    for (index, file) in files
+        .iter()
+        .enumerate()
+        .skip(resume_point.file_index as usize)
+    {
         let file_path_str = &file.path;
         debug!("Receiver: Processing file: {file_path_str}");
         let file_path = dest_path.join(&file.path);
@@ -370,56 +479,165 @@ pub async fn receive_via_pipe(
                 }
             }
         } else {
-            // Regular file - use delta transfer
-            // Check if basis file exists
-            let basis_content = if file_path.exists() {
-                fs::read(&file_path).ok()
-            } else {
-                None
-            };
-
-            // Generate and send block checksums
-            let block_checksums = if let Some(ref basis) = basis_content {
-                let block_size = calculate_block_size(basis.len() as u64);
-                let basis_len = basis.len();
-                debug!("Receiver: Basis file exists ({basis_len} bytes), block size {block_size}");
-                generate_block_checksums(basis, block_size)?
-            } else {
-                debug!("Receiver: No basis file, sending empty checksum list");
-                vec![]
-            };
-
-            send_block_checksums(&mut transport, &block_checksums).await?;
-
-            // Receive delta and apply
-            let delta = receive_delta(&mut transport).await?;
-            let (literal_bytes, matched_bytes) = count_delta_bytes(&delta);
-            debug!("Receiver: Received delta: {literal_bytes} literal bytes, {matched_bytes} matched bytes");
-
-            let reconstructed = apply_delta(basis_content.as_deref(), &delta, &block_checksums)?;
-
-            // Write reconstructed file
-            fs::write(&file_path, &reconstructed)?;
-            bytes_received += literal_bytes as u64;
-            bytes_matched += matched_bytes as u64;
-
-            let reconstructed_len = reconstructed.len();
-            debug!("Receiver: Reconstructed {reconstructed_len} bytes");
+            // Regular file - use delta transfer into a partial file
+            let (literal_bytes, matched_bytes) = receive_regular_file(
+                &mut transport,
+                dest_path,
+                file,
+                &mut checkpoint,
+                durable,
+                guard,
+                decompressor.as_mut(),
+            )
+            .await?;
+            bytes_received += literal_bytes;
+            bytes_matched += matched_bytes;
+
+            if xattr_batches {
+                let batch = xattr_batch::receive_xattr_batch(&mut transport).await?;
+                batch.apply(&file_path).await;
+            }
         }
 
         // Apply metadata (permissions, timestamps, ownership)
         apply_metadata(&file_path, file)?;
+
+        // Everything up to and including this file is now in place
+        checkpoint.advance_to(index + 1, &files);
+        checkpoint.save(dest_path, durable)?;
+        stop_if_draining(guard)?;
     }
 
+    // Session finished: nothing left to resume
+    SessionCheckpoint::remove(dest_path)?;
+
     info!("Receiver: Transfer complete, received {bytes_received} literal bytes, matched {bytes_matched} bytes");
 
     Ok(SyncStats {
         files_copied: files.len() as u64,
         bytes_copied: files.iter().map(|f| f.size).sum(),
+        errors: 0,
         duration: start.elapsed(),
     })
 }
 
+/// Receive one regular file as delta segments, checkpointing after each segment
+///
+/// Data is appended to a run-ID-stamped partial file next to the destination
+/// (continuing the checkpointed one when the checkpoint is mid-file) and
+/// renamed into place once the sender signals the end of the file. Returns the
+/// literal and matched byte counts.
+#[allow(clippy::too_many_arguments)]
+async fn receive_regular_file<T: Transport>(
+    transport: &mut T,
+    dest_path: &Path,
+    file: &FileEntry,
+    checkpoint: &mut SessionCheckpoint,
+    durable: bool,
+    guard: Option<&SessionGuard>,
+    mut decompressor: Option<&mut LiteralDecompressor>,
+) -> Result<(u64, u64)> {
+    let file_path = dest_path.join(&file.path);
+    // The checkpoint was advanced to this file, so its offset is this file's
+    let resume_offset = checkpoint.resume.offset;
+
+    // Check if basis file exists
+    let basis_content = if file_path.exists() {
+        fs::read(&file_path).ok()
+    } else {
+        None
+    };
+
+    // Generate and send block checksums
+    let block_checksums = if let Some(ref basis) = basis_content {
+        let block_size = calculate_block_size(basis.len() as u64);
+        let basis_len = basis.len();
+        debug!("Receiver: Basis file exists ({basis_len} bytes), block size {block_size}");
+        generate_block_checksums(basis, block_size)?
+    } else {
+        debug!("Receiver: No basis file, sending empty checksum list");
+        vec![]
+    };
+
+    send_block_checksums(transport, &block_checksums).await?;
+
+    // Continue the checkpointed partial file, or start a new one for this run
+    let partial_rel = match &checkpoint.partial {
+        Some(partial) if resume_offset > 0 => partial.clone(),
+        _ => {
+            let rel_path = Path::new(&file.path);
+            let file_name = rel_path
+                .file_name()
+                .ok_or_else(|| anyhow::anyhow!("No filename in {}", file.path))?;
+            rel_path.with_file_name(crate::temp_files::temp_name(file_name))
+        }
+    };
+    let partial_path = dest_path.join(&partial_rel);
+    let mut partial = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(resume_offset == 0)
+        .open(&partial_path)?;
+    partial.set_len(resume_offset)?;
+    partial.seek(SeekFrom::End(0))?;
+
+    let mut written = resume_offset;
+    let mut literal_total = 0u64;
+    let mut matched_total = 0u64;
+    loop {
+        let mut len_buf = [0u8; 8];
+        transport::read_exact(transport, &mut len_buf).await?;
+        let segment_len = u64::from_le_bytes(len_buf);
+        if segment_len == 0 {
+            break;
+        }
+
+        // Receive delta and apply
+        let delta = receive_delta(transport, decompressor.as_deref_mut()).await?;
+        let (literal_bytes, matched_bytes) = count_delta_bytes(&delta);
+        debug!("Receiver: Received delta: {literal_bytes} literal bytes, {matched_bytes} matched bytes");
+
+        let reconstructed = apply_delta(basis_content.as_deref(), &delta, &block_checksums)?;
+        if reconstructed.len() as u64 != segment_len {
+            anyhow::bail!(
+                "Segment of {} reconstructed to {} bytes, expected {segment_len}",
+                file.path,
+                reconstructed.len()
+            );
+        }
+
+        partial.write_all(&reconstructed)?;
+        if durable {
+            partial.sync_data()?;
+        }
+        written += segment_len;
+        checkpoint.record_partial(written, &partial_rel);
+        checkpoint.save(dest_path, durable)?;
+        stop_if_draining(guard)?;
+
+        literal_total += literal_bytes as u64;
+        matched_total += matched_bytes as u64;
+    }
+
+    if durable {
+        partial.sync_all()?;
+    }
+    drop(partial);
+    fs::rename(&partial_path, &file_path)?;
+    debug!("Receiver: Reconstructed {written} bytes");
+
+    Ok((literal_total, matched_total))
+}
+
+/// Stop a session (just after a checkpoint) once its daemon must shut down
+fn stop_if_draining(guard: Option<&SessionGuard>) -> Result<()> {
+    if guard.is_some_and(SessionGuard::must_stop) {
+        info!("Receiver: Stopping for daemon shutdown; session can be resumed");
+        return Err(SessionStopped.into());
+    }
+    Ok(())
+}
+
 // ============================================================================
 // Protocol Implementation (Minimal for Testing)
 // ============================================================================
@@ -462,8 +680,43 @@ async fn handshake_receiver<T: Transport>(transport: &mut T) -> Result<u8> {
     Ok(remote_version)
 }
 
+/// Negotiate capabilities, requiring everything the pipe protocol relies on
+///
+/// `compress` is the compression level this side asks for, if any.
+async fn negotiate_pipe_capabilities<T: Transport>(
+    transport: &mut T,
+    compress: Option<CompressLevel>,
+) -> Result<NegotiatedCapabilities> {
+    let mut local = CapabilitySet::current();
+    local.entries.insert(
+        Capability::COMPRESSION,
+        CompressLevel::capability_value(compress),
+    );
+    let negotiated = capabilities::exchange_capabilities(transport, &local).await?;
+    negotiated.require(Capability::SEGMENTED_DELTA)?;
+    negotiated.require(Capability::RESUMABLE_SESSIONS)?;
+    debug!(
+        "Negotiated capability version {} ({} capabilities)",
+        negotiated.version,
+        negotiated.enabled.len()
+    );
+    Ok(negotiated)
+}
+
 /// Generate simple file list (minimal implementation)
 async fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileEntry>> {
+    scan_file_list(path, args, None).await
+}
+
+/// Generate the file list, also recording every directory scanned in `dirs`
+///
+/// A directory is stamped before its entries are read, so a change made
+/// while the scan runs still shows up as a newer stamp.
+pub(super) async fn scan_file_list(
+    path: &Path,
+    args: &Args,
+    mut dirs: Option<&mut Vec<DirStamp>>,
+) -> Result<Vec<FileEntry>> {
     let mut files = Vec::new();
 
     if path.is_file() {
@@ -476,10 +729,7 @@ async fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileE
                 .to_string_lossy()
                 .to_string(),
             size: metadata.len(),
-            mtime: metadata
-                .modified()?
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_secs() as i64,
+            mtime: metadata.mtime(),
             mode: metadata.permissions().mode(),
             uid: metadata.uid(),
             gid: metadata.gid(),
@@ -500,10 +750,7 @@ async fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileE
                 files.push(FileEntry {
                     path: rel_path,
                     size: metadata.len(),
-                    mtime: metadata
-                        .modified()?
-                        .duration_since(std::time::UNIX_EPOCH)?
-                        .as_secs() as i64,
+                    mtime: metadata.mtime(),
                     mode: metadata.permissions().mode(),
                     uid: metadata.uid(),
                     gid: metadata.gid(),
@@ -520,16 +767,20 @@ async fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileE
                 files.push(FileEntry {
                     path: rel_path,
                     size: 0, // Symlinks have no size
-                    mtime: metadata
-                        .modified()?
-                        .duration_since(std::time::UNIX_EPOCH)?
-                        .as_secs() as i64,
+                    mtime: metadata.mtime(),
                     mode: metadata.permissions().mode(),
                     uid: metadata.uid(),
                     gid: metadata.gid(),
                     is_symlink: true,
                     symlink_target: Some(symlink_target),
                 });
+            } else if let (true, Some(dirs)) = (metadata.is_dir(), dirs.as_deref_mut()) {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(path)?
+                    .to_string_lossy()
+                    .to_string();
+                dirs.push(DirStamp::new(rel_path, &metadata));
             }
         }
     }
@@ -564,10 +815,9 @@ fn apply_metadata(path: &Path, file: &FileEntry) -> Result<()> {
 
     // Set modification time
     if !file.is_symlink {
-        use std::time::UNIX_EPOCH;
-        let mtime = UNIX_EPOCH + Duration::from_secs(file.mtime as u64);
-        if let Err(e) = filetime::set_file_mtime(path, filetime::FileTime::from_system_time(mtime))
-        {
+        // Signed seconds: files from before 1970 keep their dates
+        let mtime = filetime::FileTime::from_unix_time(file.mtime, 0);
+        if let Err(e) = filetime::set_file_mtime(path, mtime) {
             let path_display = path.display();
             warn!("Failed to set mtime on {path_display}: {e}");
         }
@@ -722,76 +972,53 @@ pub fn generate_block_checksums(data: &[u8], block_size: usize) -> Result<Vec<Bl
 }
 
 /// Generate delta by finding matching blocks (sender side)
+///
+/// Uses the rolling-checksum engine in `crate::protocol::delta`.
 pub fn generate_delta(data: &[u8], checksums: &[BlockChecksum]) -> Result<Vec<DeltaInstruction>> {
     if checksums.is_empty() {
         // No basis, send everything
         return Ok(vec![DeltaInstruction::Literal(data.to_vec())]);
     }
 
+    let signature = signature_of(checksums);
+    let mut delta = delta_engine::compute_delta(&signature, data);
+    // The engine numbers blocks by position; the wire uses their indices
+    for instruction in &mut delta {
+        if let DeltaInstruction::BlockMatch { block_index, .. } = instruction {
+            *block_index = checksums[*block_index as usize].block_index;
+        }
+    }
+    Ok(delta)
+}
+
+/// Engine signature of native block checksums
+///
+/// The native format carries block offsets but no lengths: a block extends to
+/// the next one, and the last is taken to be a full block (so a shorter last
+/// block is resent rather than matched).
+fn signature_of(checksums: &[BlockChecksum]) -> Signature {
     let block_size = if checksums.len() > 1 {
         (checksums[1].offset - checksums[0].offset) as usize
     } else {
         DEFAULT_BLOCK_SIZE
     };
-
-    // Build hash map for fast weak checksum lookup
-    #[allow(clippy::disallowed_types)] // HashMap required for O(1) checksum lookup
-    let mut weak_map: HashMap<u32, Vec<&BlockChecksum>> = HashMap::new();
-    for checksum in checksums {
-        weak_map.entry(checksum.weak).or_default().push(checksum);
-    }
-
-    let mut delta = Vec::new();
-    let mut pos = 0;
-    let mut literal_buffer = Vec::new();
-
-    while pos < data.len() {
-        let remaining = data.len() - pos;
-        let window_size = remaining.min(block_size);
-
-        if window_size < block_size && pos > 0 {
-            // Last partial block, send as literal
-            literal_buffer.extend_from_slice(&data[pos..]);
-            break;
-        }
-
-        let window = &data[pos..pos + window_size];
-        let weak = rolling_checksum(window);
-
-        // Check for weak match
-        if let Some(candidates) = weak_map.get(&weak) {
-            // Verify with strong checksum
-            let strong = strong_checksum(window);
-            if let Some(matched) = candidates.iter().find(|c| c.strong == strong) {
-                // Found a match!
-                // Flush any pending literal data
-                if !literal_buffer.is_empty() {
-                    delta.push(DeltaInstruction::Literal(literal_buffer.clone()));
-                    literal_buffer.clear();
-                }
-
-                // Add block match instruction
-                delta.push(DeltaInstruction::BlockMatch {
-                    block_index: matched.block_index,
-                    length: window_size as u32,
-                });
-
-                pos += window_size;
-                continue;
-            }
-        }
-
-        // No match, add byte to literal buffer
-        literal_buffer.push(data[pos]);
-        pos += 1;
-    }
-
-    // Flush any remaining literal data
-    if !literal_buffer.is_empty() {
-        delta.push(DeltaInstruction::Literal(literal_buffer));
+    let blocks = checksums
+        .iter()
+        .enumerate()
+        .map(|(position, checksum)| BlockSignature {
+            weak: checksum.weak,
+            strong: checksum.strong,
+            offset: checksum.offset,
+            len: checksums
+                .get(position + 1)
+                .map_or(block_size, |next| (next.offset - checksum.offset) as usize),
+        })
+        .collect();
+    Signature {
+        block_size,
+        seed: 0,
+        blocks,
     }
-
-    Ok(delta)
 }
 
 /// Apply delta to reconstruct file (receiver side)
@@ -890,7 +1117,13 @@ async fn receive_block_checksums<T: Transport>(transport: &mut T) -> Result<Vec<
 }
 
 /// Send delta instructions over transport
-async fn send_delta<T: Transport>(transport: &mut T, delta: &[DeltaInstruction]) -> Result<()> {
+///
+/// With a `compressor`, literals that shrink are sent zstd-compressed.
+async fn send_delta<T: Transport>(
+    transport: &mut T,
+    delta: &[DeltaInstruction],
+    mut compressor: Option<&mut LiteralCompressor>,
+) -> Result<()> {
     // Send instruction count
     let count = delta.len() as u32;
     transport::write_all(transport, &count.to_le_bytes()).await?;
@@ -899,6 +1132,22 @@ async fn send_delta<T: Transport>(transport: &mut T, delta: &[DeltaInstruction])
     for instruction in delta {
         match instruction {
             DeltaInstruction::Literal(data) => {
+                let compressed = match compressor.as_deref_mut() {
+                    Some(compressor) => compressor.compress(data)?,
+                    None => None,
+                };
+                if let Some(compressed) = compressed {
+                    // Type: 2 = zstd-compressed literal
+                    transport::write_all(transport, &[2u8]).await?;
+                    // Original length, compressed length + zstd frame
+                    let len = data.len() as u32;
+                    transport::write_all(transport, &len.to_le_bytes()).await?;
+                    let compressed_len = compressed.len() as u32;
+                    transport::write_all(transport, &compressed_len.to_le_bytes()).await?;
+                    transport::write_all(transport, &compressed).await?;
+                    continue;
+                }
+
                 // Type: 0 = Literal
                 transport::write_all(transport, &[0u8]).await?;
                 // Length + data
@@ -922,7 +1171,13 @@ async fn send_delta<T: Transport>(transport: &mut T, delta: &[DeltaInstruction])
 }
 
 /// Receive delta instructions from transport
-async fn receive_delta<T: Transport>(transport: &mut T) -> Result<Vec<DeltaInstruction>> {
+///
+/// Compressed literals are accepted only with a `decompressor` (when
+/// compression was negotiated).
+async fn receive_delta<T: Transport>(
+    transport: &mut T,
+    mut decompressor: Option<&mut LiteralDecompressor>,
+) -> Result<Vec<DeltaInstruction>> {
     // Receive instruction count
     let mut count_buf = [0u8; 4];
     transport::read_exact(transport, &mut count_buf).await?;
@@ -962,6 +1217,29 @@ async fn receive_delta<T: Transport>(transport: &mut T) -> Result<Vec<DeltaInstr
                     length,
                 });
             }
+            2 => {
+                // zstd-compressed literal
+                let decompressor = decompressor.as_deref_mut().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Compressed literal from a sender that did not negotiate compression"
+                    )
+                })?;
+                let mut len_buf = [0u8; 4];
+                transport::read_exact(transport, &mut len_buf).await?;
+                let len = u32::from_le_bytes(len_buf) as usize;
+                transport::read_exact(transport, &mut len_buf).await?;
+                let compressed_len = u32::from_le_bytes(len_buf) as usize;
+                if len > RESUME_SEGMENT_SIZE || compressed_len >= len {
+                    anyhow::bail!("Invalid compressed literal ({compressed_len} bytes for {len})");
+                }
+
+                let mut compressed = vec![0u8; compressed_len];
+                transport::read_exact(transport, &mut compressed).await?;
+
+                delta.push(DeltaInstruction::Literal(
+                    decompressor.decompress(&compressed, len)?,
+                ));
+            }
             _ => {
                 let instruction_type = type_buf[0];
                 anyhow::bail!("Unknown delta instruction type: {instruction_type}");
@@ -974,15 +1252,5 @@ async fn receive_delta<T: Transport>(transport: &mut T) -> Result<Vec<DeltaInstr
 
 /// Count literal and matched bytes in delta
 fn count_delta_bytes(delta: &[DeltaInstruction]) -> (usize, usize) {
-    let mut literal = 0;
-    let mut matched = 0;
-
-    for instruction in delta {
-        match instruction {
-            DeltaInstruction::Literal(data) => literal += data.len(),
-            DeltaInstruction::BlockMatch { length, .. } => matched += *length as usize,
-        }
-    }
-
-    (literal, matched)
+    delta_engine::delta_bytes(delta)
 }
@@ -419,13 +419,10 @@ pub async fn encode_file_list_rsync<T: Transport>(
         encode_varint_into(file.size, &mut entry);
 
         // Mtime (varint, absolute - no delta encoding yet)
-        // rsync uses unsigned for mtime, we need to convert
-        let mtime_unsigned = if file.mtime < 0 {
-            0u64 // Clamp negative times to 0
-        } else {
-            file.mtime as u64
-        };
-        encode_varint_into(mtime_unsigned, &mut entry);
+        // The varint is unsigned; pre-1970 times travel as their two's
+        // complement bit pattern and the decoder casts back to i64
+        #[allow(clippy::cast_sign_loss)]
+        encode_varint_into(file.mtime as u64, &mut entry);
 
         // Mode (varint)
         encode_varint_into(file.mode as u64, &mut entry);
@@ -965,6 +962,7 @@ pub async fn rsync_receive<T: Transport>(
     Ok(SyncStats {
         files_copied: files.len() as u64,
         bytes_copied: 0, // No actual content transferred yet
+        errors: 0,
         duration: start.elapsed(),
     })
 }
@@ -1012,6 +1010,7 @@ pub async fn rsync_send<T: Transport>(
     Ok(SyncStats {
         files_copied: files.len() as u64,
         bytes_copied: 0, // No actual content transferred yet
+        errors: 0,
         duration: start.elapsed(),
     })
 }
@@ -1029,10 +1028,7 @@ fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileEntry>>
                 .to_string_lossy()
                 .to_string(),
             size: metadata.len(),
-            mtime: metadata
-                .modified()?
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_secs() as i64,
+            mtime: compio_fs_extended::Timestamp::from(metadata.modified()?).secs,
             mode: {
                 #[cfg(unix)]
                 {
@@ -1084,10 +1080,7 @@ fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileEntry>>
                 files.push(FileEntry {
                     path: rel_path,
                     size: metadata.len(),
-                    mtime: metadata
-                        .modified()?
-                        .duration_since(std::time::UNIX_EPOCH)?
-                        .as_secs() as i64,
+                    mtime: compio_fs_extended::Timestamp::from(metadata.modified()?).secs,
                     mode: {
                         #[cfg(unix)]
                         {
@@ -1135,10 +1128,7 @@ fn generate_file_list_simple(path: &Path, args: &Args) -> Result<Vec<FileEntry>>
                 files.push(FileEntry {
                     path: rel_path,
                     size: 0,
-                    mtime: metadata
-                        .modified()?
-                        .duration_since(std::time::UNIX_EPOCH)?
-                        .as_secs() as i64,
+                    mtime: compio_fs_extended::Timestamp::from(metadata.modified()?).secs,
                     mode: {
                         #[cfg(unix)]
                         {
@@ -1241,6 +1231,26 @@ mod tests {
         assert_eq!(decoded.gid, original.gid);
     }
 
+    #[test]
+    fn test_file_entry_pre_epoch_mtime() {
+        // Requirement: Files dated before 1970 or after 2038 keep their mtime
+        // across the wire instead of being clamped to the epoch
+        for mtime in [-1, -2_208_988_800, 2_147_483_648, 253_402_300_799] {
+            let mut entry = vec![0u8];
+            encode_varint_into(1, &mut entry);
+            entry.extend(b"f");
+            encode_varint_into(0, &mut entry);
+            #[allow(clippy::cast_sign_loss)]
+            encode_varint_into(mtime as u64, &mut entry);
+            encode_varint_into(0o100644, &mut entry);
+            encode_varint_into(0, &mut entry);
+            encode_varint_into(0, &mut entry);
+
+            let decoded = decode_file_entry(&entry).expect("Should decode");
+            assert_eq!(decoded.mtime, mtime);
+        }
+    }
+
     #[test]
     fn test_symlink_entry() {
         let symlink = FileEntry {
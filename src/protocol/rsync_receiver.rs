@@ -0,0 +1,1993 @@
+//! rsync receiver: `arsync --server` for rsync clients
+//!
+//! An rsync client pushing to `HOST:DST` starts `rsync --server ... . DST`
+//! on HOST through its remote shell. With `--rsync-path=arsync` it starts
+//! `arsync --server` instead, which plays the receiving side of protocol 30
+//! (see `rsync_sender` for the sending side):
+//!
+//! 1. After the versions, the server picks the compatibility flags and the
+//!    session's checksum seed.
+//! 2. A deleting receiver reads the sender's filter rules; they protect
+//!    destination files from deletion.
+//! 3. The file list is decoded and sorted in rsync's order.
+//! 4. The generator walks the list. It creates directories, symlinks and
+//!    special files itself, and asks for every regular file that fails the
+//!    quick check (size and modification time, or `-c`), sending the block
+//!    checksums of the current copy. Requests run a bounded number of bytes
+//!    ahead of the answers.
+//! 5. Each answer is a delta against the current copy. It is applied to a
+//!    temporary file, which replaces the file once its MD5 matches; a
+//!    mismatch is requested again, with full-length block checksums, in the
+//!    redo phase.
+//! 6. Directory permissions and times are set last, then the phases end
+//!    and both sides say goodbye.
+//!
+//! Compressed deltas (`-z`, rsync's zlib and zlibx token streams) are
+//! inflated as they arrive. Other options that extend the wire format (hard
+//! links, ACLs, extended attributes, `--relative`, ...) are refused; the
+//! client shows the error.
+//!
+//! For `arsync --daemon` the receiver is confined to the module's directory
+//! (`ServerArgs::module`): every directory is opened from its parent with
+//! `O_NOFOLLOW` and kept open, and entries are created, read, renamed and
+//! changed only relative to those descriptors, so no symlink in the module,
+//! even one swapped in during the transfer, leads it out of the module; the
+//! symlinks the client sends are munged as by rsync's `munge symlinks`; and
+//! set-user-ID and set-group-ID bits are dropped.
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+#![allow(clippy::cast_possible_truncation)] // Wire integers are fixed-width
+#![allow(clippy::cast_possible_wrap)] // Wire integers are two's complement
+#![allow(clippy::cast_sign_loss)] // Wire integers are two's complement
+#![allow(clippy::doc_markdown)] // Protocol documentation
+
+use crate::cli::{FilterConfig, FilterOption};
+use crate::filter::FilterRules;
+use crate::protocol::compress::{Token, TokenCodec, TokenInflater};
+use crate::protocol::receiver_dir::{Dir, Stat};
+use crate::protocol::rsync_wire::{
+    compare_names, file_checksum, join_dev, msg, put_shortint, put_varint, strong_sum,
+    weak_checksum, weak_sums, Channel, Seed, CF_CHKSUM_SEED_FIX, CHUNK_SIZE, ITEM_IS_NEW,
+    ITEM_REPORT_SIZE, ITEM_REPORT_TIME, ITEM_TRANSFER, MAX_BLOCK_SIZE, NDX_DONE, PROTOCOL_VERSION,
+    SUM_LENGTH, XMIT_EXTENDED_FLAGS, XMIT_GROUP_NAME_FOLLOWS, XMIT_HLINKED, XMIT_LONG_NAME,
+    XMIT_NO_CONTENT_DIR, XMIT_SAME_GID, XMIT_SAME_MODE, XMIT_SAME_NAME, XMIT_SAME_RDEV_MAJOR,
+    XMIT_SAME_TIME, XMIT_SAME_UID, XMIT_USER_NAME_FOLLOWS,
+};
+use crate::protocol::transport::Transport;
+use crate::temp_files::temp_name;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Bytes of requests sent ahead of the answers
+///
+/// Kept below what a pipe or ssh channel buffers, so a request never blocks
+/// while the sender is blocked writing an answer.
+const REQUEST_WINDOW: usize = 32 * 1024;
+
+/// Longest file name, symlink target or filter rule accepted (`MAXPATHLEN`)
+const MAX_PATH_LEN: usize = 4096;
+
+/// Prefix of the symlinks received into a daemon module (`SYMLINK_PREFIX`)
+///
+/// A munged symlink leads into this directory, which does not exist, rather
+/// than out of the module; rsync's `munge-symlinks` helper strips it again.
+const SYMLINK_PREFIX: &[u8] = b"/rsyncd-munged/";
+
+/// Block length for files up to `BLOCK_SIZE²` bytes
+const BLOCK_SIZE: i64 = 700;
+
+/// Strong checksum bytes per block in the first phase (`SHORT_SUM_LENGTH`)
+const SHORT_SUM_LENGTH: usize = 2;
+
+/// Bits of collision safety the strong checksums aim for (`BLOCKSUM_BIAS`)
+const BLOCKSUM_BIAS: i32 = 10;
+
+// ============================================================================
+// Options
+// ============================================================================
+
+/// Options of `arsync --server`, as an rsync client passes them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ServerArgs {
+    /// Recurse into directories (`-r`)
+    pub recursive: bool,
+    /// Create symlinks (`-l`)
+    pub links: bool,
+    /// Preserve permissions (`-p`)
+    pub perms: bool,
+    /// Preserve modification times (`-t`)
+    pub times: bool,
+    /// Leave directory times alone (`-O`)
+    pub omit_dir_times: bool,
+    /// Preserve owners (`-o`)
+    pub owner: bool,
+    /// Preserve groups (`-g`)
+    pub group: bool,
+    /// Create device files (`--devices`)
+    pub devices: bool,
+    /// Create FIFOs and sockets (`--specials`)
+    pub specials: bool,
+    /// Compare files by checksum (`-c`)
+    pub checksum: bool,
+    /// Transfer files even if size and time match (`-I`)
+    pub ignore_times: bool,
+    /// Compare sizes only (`--size-only`)
+    pub size_only: bool,
+    /// Keep files that are newer here (`-u`)
+    pub update: bool,
+    /// Only update files that already exist (`--existing`)
+    pub existing: bool,
+    /// Leave existing files alone (`--ignore-existing`)
+    pub ignore_existing: bool,
+    /// Send whole files rather than deltas (`-W`)
+    pub whole_file: bool,
+    /// Only report what would change (`-n`)
+    pub dry_run: bool,
+    /// Delete extraneous files (`--delete` and its timing variants)
+    pub delete: bool,
+    /// Excluded files are deleted too (`--delete-excluded`)
+    pub delete_excluded: bool,
+    /// Delete even if the sender had I/O errors (`--ignore-errors`)
+    pub ignore_errors: bool,
+    /// Keep owners and groups as numbers, not names (`--numeric-ids`)
+    pub numeric_ids: bool,
+    /// Modification times this many seconds apart match (`--modify-window`)
+    pub modify_window: i64,
+    /// Checksum seed chosen by the client (`--checksum-seed`)
+    pub checksum_seed: Option<i32>,
+    /// Codec of a compressed delta (`-z`, `--compress-choice`)
+    pub compress: Option<TokenCodec>,
+    /// Capabilities the client announced after `-e` (such as `.iLsfxC`)
+    pub client_info: String,
+    /// Where to receive
+    pub destination: PathBuf,
+    /// Daemon module the receiver may not leave, not an option of the
+    /// client (see the module documentation)
+    pub module: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    /// First argument that starts the server
+    pub const FLAG: &'static str = "--server";
+
+    /// Parse what an rsync client passes after `--server`
+    ///
+    /// Options come first, then `.` and the destination.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for `--sender` (arsync only receives), for options
+    /// this receiver does not support, and for malformed arguments.
+    pub fn parse(args: &[OsString]) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut rest = args.iter();
+        let mut found_dot = false;
+        for arg in rest.by_ref() {
+            if arg == "." {
+                found_dot = true;
+                break;
+            }
+            let text = arg
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid server option {arg:?}"))?;
+            if let Some(option) = text.strip_prefix("--") {
+                parsed.long_option(option)?;
+            } else if let Some(letters) = text.strip_prefix('-') {
+                parsed.short_options(letters)?;
+            } else {
+                anyhow::bail!("Unexpected server argument {text:?}");
+            }
+        }
+        if !found_dot {
+            anyhow::bail!("Missing \".\" in server arguments");
+        }
+        parsed.destination = match (rest.next(), rest.next()) {
+            (None, _) => PathBuf::from("."),
+            (Some(destination), None) => PathBuf::from(destination),
+            (Some(_), Some(_)) => anyhow::bail!("arsync --server receives into one destination"),
+        };
+        Ok(parsed)
+    }
+
+    /// Apply a group of single-letter options (`-logDtpre.iLsfxC`)
+    fn short_options(&mut self, letters: &str) -> Result<()> {
+        for (i, letter) in letters.char_indices() {
+            match letter {
+                'e' => {
+                    // The rest of the group is the client's capability string
+                    self.client_info = letters[i + 1..].to_string();
+                    break;
+                }
+                'r' => self.recursive = true,
+                'l' => self.links = true,
+                'p' => self.perms = true,
+                't' => self.times = true,
+                'O' => self.omit_dir_times = true,
+                'o' => self.owner = true,
+                'g' => self.group = true,
+                'D' => {
+                    self.devices = true;
+                    self.specials = true;
+                }
+                'c' => self.checksum = true,
+                'I' => self.ignore_times = true,
+                'u' => self.update = true,
+                'W' => self.whole_file = true,
+                'n' => self.dry_run = true,
+                'z' => {
+                    self.compress.get_or_insert(TokenCodec::Zlib);
+                }
+                // Verbosity is the client's business; -d is the default
+                // without -r; the others only change what the sender lists
+                'v' | 'q' | 'd' | 'x' | 'L' | 'k' => {}
+                _ => anyhow::bail!("arsync --server does not support -{letter}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply one `--name[=value]` option
+    fn long_option(&mut self, option: &str) -> Result<()> {
+        let (name, value) = option
+            .split_once('=')
+            .map_or((option, None), |(name, value)| (name, Some(value)));
+        match (name, value) {
+            (
+                "delete" | "delete-before" | "delete-during" | "delete-delay" | "delete-after",
+                None,
+            ) => {
+                self.delete = true;
+            }
+            ("delete-excluded", None) => {
+                self.delete = true;
+                self.delete_excluded = true;
+            }
+            ("ignore-errors", None) => self.ignore_errors = true,
+            ("numeric-ids", None) => self.numeric_ids = true,
+            ("devices", None) => self.devices = true,
+            ("specials", None) => self.specials = true,
+            ("size-only", None) => self.size_only = true,
+            ("existing", None) => self.existing = true,
+            ("ignore-existing", None) => self.ignore_existing = true,
+            ("modify-window", Some(value)) => {
+                self.modify_window = value
+                    .parse()
+                    .with_context(|| format!("Invalid --modify-window {value:?}"))?;
+            }
+            ("compress" | "old-compress", None) => {
+                self.compress.get_or_insert(TokenCodec::Zlib);
+            }
+            ("new-compress", None) => self.compress = Some(TokenCodec::ZlibX),
+            ("compress-choice" | "zc", Some(value)) => {
+                self.compress = match value {
+                    "zlib" => Some(TokenCodec::Zlib),
+                    "zlibx" => Some(TokenCodec::ZlibX),
+                    "none" => None,
+                    _ => anyhow::bail!("arsync --server does not support compression {value:?}"),
+                };
+            }
+            // Only the sender compresses
+            ("compress-level" | "zl", Some(value)) => {
+                value
+                    .parse::<i32>()
+                    .with_context(|| format!("Invalid --compress-level {value:?}"))?;
+            }
+            ("checksum-seed", Some(value)) => {
+                self.checksum_seed = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid --checksum-seed {value:?}"))?,
+                );
+            }
+            // Directories in the way are always deleted with their contents;
+            // interrupted files are never kept
+            ("force" | "partial", None) | ("log-format" | "out-format" | "timeout", Some(_)) => {}
+            ("sender", None) => {
+                anyhow::bail!("arsync --server only receives; it cannot send to an rsync client")
+            }
+            _ => anyhow::bail!("arsync --server does not support --{option}"),
+        }
+        Ok(())
+    }
+}
+
+/// The receiver's part of a sender's filter rule, as an arsync `--filter`
+///
+/// Excludes and protect rules keep destination files from deletion; hide,
+/// show and sender-side rules only shape the file list. `None` if the rule
+/// does not apply on the receiving side.
+fn receiver_rule(rule: &str, delete_excluded: bool) -> Result<Option<String>> {
+    if rule == "!" || rule == "clear" {
+        return Ok(Some("!".to_string()));
+    }
+    let (prefix, pattern) = rule
+        .split_once([' ', '_'])
+        .ok_or_else(|| anyhow::anyhow!("Filter rule has no pattern: {rule:?}"))?;
+    let (kind, modifiers) = match prefix {
+        "exclude" => ("-", ""),
+        "include" => ("+", ""),
+        "protect" => ("P", ""),
+        "risk" => ("R", ""),
+        "hide" => ("H", ""),
+        "show" => ("S", ""),
+        _ => prefix.split_at(prefix.chars().next().map_or(0, char::len_utf8)),
+    };
+    if modifiers.contains('s') {
+        return Ok(None);
+    }
+    if modifiers.chars().any(|modifier| modifier != 'r') {
+        anyhow::bail!("arsync --server does not support the filter rule {rule:?}");
+    }
+    Ok(match kind {
+        "-" if delete_excluded => None,
+        "-" | "P" => Some(format!("- {pattern}")),
+        "+" | "R" => Some(format!("+ {pattern}")),
+        "H" | "S" => None,
+        _ => anyhow::bail!("arsync --server does not support the filter rule {rule:?}"),
+    })
+}
+
+// ============================================================================
+// Entry point
+// ============================================================================
+
+/// Totals of a receiving session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiverStats {
+    /// Entries in the file list
+    pub files: u64,
+    /// Regular files received (or that would be, with `-n`)
+    pub transferred: u64,
+    /// File data received as literal bytes
+    pub literal_bytes: u64,
+    /// File data copied from the previous version
+    pub matched_bytes: u64,
+    /// Extraneous files deleted
+    pub deleted: u64,
+    /// Files that could not be updated (reported to the client)
+    pub errors: u64,
+}
+
+impl fmt::Display for ReceiverStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} files received ({} literal, {} matched bytes), {} deleted, {} errors",
+            self.transferred,
+            self.files,
+            self.literal_bytes,
+            self.matched_bytes,
+            self.deleted,
+            self.errors
+        )
+    }
+}
+
+/// Run the receiving side of a session with an rsync client over `transport`
+///
+/// # Errors
+///
+/// Returns an error on protocol violations, if the client does not speak
+/// protocol 30 or later, if the destination cannot be created, or if the
+/// connection fails. Problems with single files are reported to the client
+/// and counted in `ReceiverStats::errors` instead.
+pub async fn serve<T: Transport>(transport: &mut T, args: &ServerArgs) -> Result<ReceiverStats> {
+    let mut channel = Channel::new(transport, "rsync client");
+
+    // Versions, compatibility flags and the checksum seed (all unframed)
+    channel.put_int(PROTOCOL_VERSION);
+    channel.flush().await?;
+    let remote_version = channel
+        .read_int()
+        .await
+        .context("rsync client closed the connection before the handshake")?;
+    receive(channel, args, remote_version).await
+}
+
+/// Run the receiving side of an rsync daemon session (`arsync --daemon`)
+///
+/// The daemon greeting has settled the protocol version already, so the
+/// session starts at the compatibility flags.
+///
+/// # Errors
+///
+/// As for [`serve`].
+pub async fn serve_daemon<T: Transport>(
+    transport: &mut T,
+    args: &ServerArgs,
+    remote_version: i32,
+) -> Result<ReceiverStats> {
+    receive(
+        Channel::new(transport, "rsync client"),
+        args,
+        remote_version,
+    )
+    .await
+}
+
+/// Refuse an rsync daemon session whose arguments were read
+///
+/// The client shows errors only once the connection is multiplexed, so the
+/// compatibility flags and checksum seed go first, as rsync's daemon does.
+///
+/// # Errors
+///
+/// Returns an error if the connection fails.
+pub async fn refuse<T: Transport>(transport: &mut T, message: &str) -> Result<()> {
+    let mut channel = Channel::new(transport, "rsync client");
+    put_varint(&mut channel.out, 0);
+    channel.put_int(default_seed());
+    channel.flush().await?;
+    channel.multiplexed = true;
+    channel
+        .send_msg(
+            msg::ERROR,
+            format!("arsync: [daemon] {message}\n").as_bytes(),
+        )
+        .await
+}
+
+/// The session after the protocol versions
+async fn receive<T: Transport>(
+    mut channel: Channel<'_, T>,
+    args: &ServerArgs,
+    remote_version: i32,
+) -> Result<ReceiverStats> {
+    if remote_version < PROTOCOL_VERSION {
+        anyhow::bail!(
+            "rsync client speaks protocol {remote_version}; arsync needs {PROTOCOL_VERSION} (rsync 3.0) or later"
+        );
+    }
+    let compat_flags = if args.client_info.contains('C') {
+        CF_CHKSUM_SEED_FIX
+    } else {
+        0
+    };
+    put_varint(&mut channel.out, compat_flags);
+    let seed = args.checksum_seed.unwrap_or_else(default_seed);
+    channel.put_int(seed);
+    channel.flush().await?;
+    channel.multiplexed = true;
+    let seed = Seed {
+        value: seed as u32,
+        first: compat_flags & CF_CHKSUM_SEED_FIX != 0,
+    };
+    debug!("rsync client: protocol {remote_version}, compat flags {compat_flags:#x}");
+
+    let filter = if args.delete {
+        read_filter_rules(&mut channel, args).await?
+    } else {
+        FilterRules::default()
+    };
+    let files = read_file_list(&mut channel, args).await?;
+    debug!("rsync receiver: {} entries", files.len());
+
+    let mut receiver = Receiver {
+        channel,
+        args,
+        stats: ReceiverStats {
+            files: files.len() as u64,
+            ..ReceiverStats::default()
+        },
+        files,
+        dest: args.destination.clone(),
+        lone_file: false,
+        dirs: HashMap::new(),
+        new_dirs: HashSet::new(),
+        seed,
+        inflater: args.compress.map(TokenInflater::new),
+        csum_length: SHORT_SUM_LENGTH,
+        phase: 0,
+        umask: current_umask(),
+        // SAFETY: geteuid has no preconditions and cannot fail
+        am_root: unsafe { libc::geteuid() } == 0,
+        pending: VecDeque::new(),
+        in_flight: 0,
+        redo: Vec::new(),
+    };
+    receiver.run(&filter).await?;
+    Ok(receiver.stats)
+}
+
+/// Checksum seed when the client did not choose one (as rsync picks it)
+fn default_seed() -> i32 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    (now as i32) ^ ((std::process::id() as i32) << 6)
+}
+
+/// The process umask, which new files get without `-p`
+fn current_umask() -> libc::mode_t {
+    // SAFETY: umask has no preconditions; the old mask is restored at once
+    let mask = unsafe { libc::umask(0o022) };
+    // SAFETY: as above
+    unsafe { libc::umask(mask) };
+    mask
+}
+
+/// Read the sender's filter rules (only sent to a deleting receiver)
+async fn read_filter_rules<T: Transport>(
+    channel: &mut Channel<'_, T>,
+    args: &ServerArgs,
+) -> Result<FilterRules> {
+    let mut rules = Vec::new();
+    loop {
+        let len = channel.read_int().await?;
+        if len == 0 {
+            break;
+        }
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len < MAX_PATH_LEN)
+            .ok_or_else(|| anyhow::anyhow!("Invalid filter rule length {len} from rsync client"))?;
+        let rule = String::from_utf8_lossy(channel.read_bytes(len).await?).into_owned();
+        if let Some(rule) = receiver_rule(&rule, args.delete_excluded)? {
+            rules.push(FilterOption::Filter(rule));
+        }
+    }
+    Ok(FilterRules::from_config(&FilterConfig { rules })?)
+}
+
+// ============================================================================
+// File list
+// ============================================================================
+
+/// One received file list entry
+#[derive(Debug, Clone, Default)]
+struct ListedFile {
+    /// Path relative to the transfer root, as sent
+    name: Vec<u8>,
+    /// `st_mode`, including the file type
+    mode: u32,
+    /// Size of a regular file
+    size: u64,
+    /// Modification time (seconds, signed)
+    mtime: i64,
+    /// Owner
+    uid: u32,
+    /// Group
+    gid: u32,
+    /// Major and minor device numbers of a device file
+    rdev: (u32, u32),
+    /// Symlink target
+    link: Option<Vec<u8>>,
+    /// MD5 of a regular file's contents (`-c`)
+    checksum: Option<[u8; SUM_LENGTH]>,
+    /// The entry's `XMIT_*` flags
+    xflags: u16,
+}
+
+impl ListedFile {
+    fn file_type(&self) -> u32 {
+        self.mode & libc::S_IFMT
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    fn is_file(&self) -> bool {
+        self.file_type() == libc::S_IFREG
+    }
+
+    fn is_link(&self) -> bool {
+        self.file_type() == libc::S_IFLNK
+    }
+
+    fn is_device(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFCHR | libc::S_IFBLK)
+    }
+
+    fn is_special(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFIFO | libc::S_IFSOCK)
+    }
+
+    /// Whether this directory's contents are in the list
+    fn has_contents(&self) -> bool {
+        self.is_dir() && self.xflags & XMIT_NO_CONTENT_DIR == 0
+    }
+
+    /// Name for messages
+    fn display(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+}
+
+/// Previous entry's values, which later entries may refer to
+#[derive(Debug, Default)]
+struct FlistDecoder {
+    last_name: Vec<u8>,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+    rdev_major: u32,
+}
+
+impl FlistDecoder {
+    /// Read the entry whose flags were just read (`recv_file_entry`)
+    async fn decode<T: Transport>(
+        &mut self,
+        channel: &mut Channel<'_, T>,
+        xflags: u16,
+        args: &ServerArgs,
+    ) -> Result<ListedFile> {
+        if xflags & XMIT_HLINKED != 0 {
+            anyhow::bail!("Hard-linked entry from rsync client (-H is not supported)");
+        }
+        let shared = if xflags & XMIT_SAME_NAME != 0 {
+            usize::from(channel.read_u8().await?)
+        } else {
+            0
+        };
+        let suffix = if xflags & XMIT_LONG_NAME != 0 {
+            usize::try_from(channel.read_varint().await?).unwrap_or(usize::MAX)
+        } else {
+            usize::from(channel.read_u8().await?)
+        };
+        if shared > self.last_name.len() || shared.saturating_add(suffix) > MAX_PATH_LEN {
+            anyhow::bail!("Invalid file name length in file list from rsync client");
+        }
+        let mut name = self.last_name[..shared].to_vec();
+        name.extend_from_slice(channel.read_bytes(suffix).await?);
+        check_name(&name)?;
+
+        let size = channel.read_varlong(3).await?;
+        if xflags & XMIT_SAME_TIME == 0 {
+            self.mtime = channel.read_varlong(4).await?;
+        }
+        if xflags & XMIT_SAME_MODE == 0 {
+            self.mode = channel.read_int().await? as u32;
+        }
+        if args.owner && xflags & XMIT_SAME_UID == 0 {
+            self.uid = channel.read_varint().await? as u32;
+            if xflags & XMIT_USER_NAME_FOLLOWS != 0 {
+                let len = usize::from(channel.read_u8().await?);
+                channel.read_bytes(len).await?;
+            }
+        }
+        if args.group && xflags & XMIT_SAME_GID == 0 {
+            self.gid = channel.read_varint().await? as u32;
+            if xflags & XMIT_GROUP_NAME_FOLLOWS != 0 {
+                let len = usize::from(channel.read_u8().await?);
+                channel.read_bytes(len).await?;
+            }
+        }
+        let mut entry = ListedFile {
+            name,
+            mode: self.mode,
+            size: u64::try_from(size).unwrap_or(0),
+            mtime: self.mtime,
+            uid: self.uid,
+            gid: self.gid,
+            xflags,
+            ..ListedFile::default()
+        };
+        if (args.devices && entry.is_device()) || (args.specials && entry.is_special()) {
+            if xflags & XMIT_SAME_RDEV_MAJOR == 0 {
+                self.rdev_major = channel.read_varint().await? as u32;
+            }
+            entry.rdev = (self.rdev_major, channel.read_varint().await? as u32);
+        }
+        if args.links && entry.is_link() {
+            let len = usize::try_from(channel.read_varint().await?)
+                .ok()
+                .filter(|&len| len <= MAX_PATH_LEN)
+                .ok_or_else(|| anyhow::anyhow!("Invalid symlink length from rsync client"))?;
+            entry.link = Some(channel.read_bytes(len).await?.to_vec());
+        }
+        if args.checksum && entry.is_file() {
+            let mut sum = [0; SUM_LENGTH];
+            sum.copy_from_slice(channel.read_bytes(SUM_LENGTH).await?);
+            entry.checksum = Some(sum);
+        }
+        self.last_name.clone_from(&entry.name);
+        Ok(entry)
+    }
+}
+
+/// Refuse names that would leave the destination
+fn check_name(name: &[u8]) -> Result<()> {
+    if name == b"." {
+        return Ok(());
+    }
+    if name.is_empty()
+        || name.starts_with(b"/")
+        || name
+            .split(|&c| c == b'/')
+            .any(|part| part.is_empty() || part == b"." || part == b"..")
+    {
+        anyhow::bail!(
+            "Unsafe file name {:?} from rsync client",
+            String::from_utf8_lossy(name)
+        );
+    }
+    Ok(())
+}
+
+/// Read the file list, map owner names, and sort it in rsync's order
+async fn read_file_list<T: Transport>(
+    channel: &mut Channel<'_, T>,
+    args: &ServerArgs,
+) -> Result<Vec<ListedFile>> {
+    let mut decoder = FlistDecoder::default();
+    let mut files = Vec::new();
+    loop {
+        let mut xflags = u16::from(channel.read_u8().await?);
+        if xflags == 0 {
+            break;
+        }
+        if xflags & XMIT_EXTENDED_FLAGS != 0 {
+            xflags |= u16::from(channel.read_u8().await?) << 8;
+        }
+        files.push(decoder.decode(channel, xflags, args).await?);
+    }
+
+    // Names for the ids, unless the client sends plain numbers
+    if !args.numeric_ids {
+        if args.owner {
+            let users = read_id_list(channel, false).await?;
+            for entry in &mut files {
+                entry.uid = users.get(&entry.uid).copied().unwrap_or(entry.uid);
+            }
+        }
+        if args.group {
+            let groups = read_id_list(channel, true).await?;
+            for entry in &mut files {
+                entry.gid = groups.get(&entry.gid).copied().unwrap_or(entry.gid);
+            }
+        }
+    }
+
+    files.sort_by(|a, b| compare_names(&a.name, a.is_dir(), &b.name, b.is_dir()));
+    Ok(files)
+}
+
+/// Read a list of ids and names (`recv_id_list`), mapping the sender's ids
+/// to local ones with the same name
+async fn read_id_list<T: Transport>(
+    channel: &mut Channel<'_, T>,
+    group: bool,
+) -> Result<HashMap<u32, u32>> {
+    let mut map = HashMap::new();
+    loop {
+        let id = channel.read_varint().await? as u32;
+        if id == 0 {
+            return Ok(map);
+        }
+        let len = usize::from(channel.read_u8().await?);
+        let name = channel.read_bytes(len).await?.to_vec();
+        if let Some(local) = local_id(&name, group) {
+            map.insert(id, local);
+        }
+    }
+}
+
+/// Local id of the user (or group) called `name`
+pub(super) fn local_id(name: &[u8], group: bool) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    if group {
+        // SAFETY: an all-zero `group` is valid (null pointers)
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call, and `buf` has the
+        // length passed
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (rc == 0 && !result.is_null()).then_some(entry.gr_gid)
+    } else {
+        // SAFETY: an all-zero `passwd` is valid (null pointers)
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call, and `buf` has the
+        // length passed
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        (rc == 0 && !result.is_null()).then_some(entry.pw_uid)
+    }
+}
+
+// ============================================================================
+// Checksums
+// ============================================================================
+
+/// Block length and strong checksum length for a basis of `len` bytes
+/// (`sum_sizes_sqroot`)
+///
+/// Blocks grow with the square root of the length, in multiples of 8. Each
+/// block's strong checksum gets enough bytes to make a false match unlikely,
+/// but at least `csum_length`.
+fn sum_sizes(len: u64, csum_length: usize) -> (i32, usize) {
+    let len = i64::try_from(len).unwrap_or(i64::MAX);
+    let blength = if len <= BLOCK_SIZE * BLOCK_SIZE {
+        BLOCK_SIZE as i32
+    } else {
+        let mut c: i32 = 1;
+        let mut l = len;
+        loop {
+            l >>= 2;
+            if l == 0 {
+                break;
+            }
+            c = c.wrapping_shl(1);
+        }
+        if c <= 0 || c >= MAX_BLOCK_SIZE {
+            MAX_BLOCK_SIZE
+        } else {
+            let mut blength = 0;
+            while c >= 8 {
+                blength |= c;
+                if len < i64::from(blength) * i64::from(blength) {
+                    blength &= !c;
+                }
+                c >>= 1;
+            }
+            blength.max(BLOCK_SIZE as i32)
+        }
+    };
+
+    let s2length = if csum_length == SUM_LENGTH {
+        SUM_LENGTH
+    } else {
+        let mut b = BLOCKSUM_BIAS;
+        let mut l = len;
+        loop {
+            l >>= 1;
+            if l == 0 {
+                break;
+            }
+            b += 2;
+        }
+        let mut c = blength;
+        loop {
+            c >>= 1;
+            if c == 0 || b == 0 {
+                break;
+            }
+            b -= 1;
+        }
+        // Add a bit, subtract the rolling checksum's 32, round up
+        let bytes = usize::try_from((b + 1 - 32 + 7) / 8).unwrap_or(0);
+        bytes.clamp(csum_length, SUM_LENGTH)
+    };
+    (blength, s2length)
+}
+
+/// The request body for a file: checksum header and block checksums of
+/// its current copy, `basis` (`generate_and_send_sums`)
+fn basis_sums(basis: Option<File>, csum_length: usize, seed: Seed) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let Some(file) = basis else {
+        out.resize(16, 0);
+        return Ok(out);
+    };
+    let len = file.metadata()?.len();
+    let (blength, s2length) = sum_sizes(len, csum_length);
+    let block_len = blength as u64;
+    let count = i32::try_from(len.div_ceil(block_len))
+        .map_err(|_| io::Error::other("file too large for block checksums"))?;
+    let remainder = (len % block_len) as i32;
+    for value in [count, blength, s2length as i32, remainder] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut reader = BufReader::with_capacity(4 * CHUNK_SIZE, file);
+    let mut block = vec![0; blength as usize];
+    for i in 0..count {
+        let n = if i == count - 1 && remainder != 0 {
+            remainder as usize
+        } else {
+            blength as usize
+        };
+        reader.read_exact(&mut block[..n])?;
+        let (s1, s2) = weak_sums(&block[..n]);
+        out.extend_from_slice(&weak_checksum(s1, s2).to_le_bytes());
+        out.extend_from_slice(&strong_sum(&block[..n], seed)[..s2length]);
+    }
+    Ok(out)
+}
+
+// ============================================================================
+// Generator and receiver
+// ============================================================================
+
+/// A file whose data was requested and not yet received
+#[derive(Debug, Clone, Copy)]
+struct Request {
+    index: usize,
+    /// Size of the request on the wire
+    bytes: usize,
+}
+
+/// State of a receiving session
+struct Receiver<'a, 'c, T> {
+    channel: Channel<'c, T>,
+    args: &'a ServerArgs,
+    /// The file list, sorted
+    files: Vec<ListedFile>,
+    /// The destination directory, or the destination of a lone file
+    dest: PathBuf,
+    /// Whether `dest` names the list's only (non-directory) entry itself
+    lone_file: bool,
+    /// Directories open so far, by name in the transfer; the empty name is
+    /// the destination (the directory holding it for a lone file)
+    dirs: HashMap<Vec<u8>, Arc<Dir>>,
+    /// Directories this session created (their permissions follow `-p` or
+    /// the umask, not what was there before)
+    new_dirs: HashSet<usize>,
+    seed: Seed,
+    /// Decoder of a compressed token stream (`-z`)
+    inflater: Option<TokenInflater>,
+    /// Minimum strong checksum length of the current phase
+    csum_length: usize,
+    /// 0, or 1 in the redo phase
+    phase: u32,
+    umask: libc::mode_t,
+    am_root: bool,
+    /// Requests awaiting answers, oldest first
+    pending: VecDeque<Request>,
+    /// Total size of `pending`
+    in_flight: usize,
+    /// Files to request again in the redo phase
+    redo: Vec<usize>,
+    stats: ReceiverStats,
+}
+
+impl<T: Transport> Receiver<'_, '_, T> {
+    /// Generate and receive through all phases, then say goodbye
+    async fn run(&mut self, filter: &FilterRules) -> Result<()> {
+        self.prepare_destination()?;
+        if self.args.delete {
+            self.delete_extraneous(filter).await?;
+        }
+
+        for index in 0..self.files.len() {
+            self.generate(index).await?;
+        }
+        self.end_phase().await?;
+
+        // Redo phase: files whose checksum failed, with full block sums
+        self.phase = 1;
+        self.csum_length = SUM_LENGTH;
+        for index in std::mem::take(&mut self.redo) {
+            debug!("rsync receiver: redoing {}", self.files[index].display());
+            self.request(index).await?;
+        }
+        self.end_phase().await?;
+
+        self.finish_directories().await?;
+
+        // End of the final phase; the sender answers with its own end
+        self.channel.put_ndx(NDX_DONE);
+        let ndx = self.channel.read_ndx().await?;
+        if ndx != NDX_DONE {
+            anyhow::bail!("Invalid packet at end of run ({ndx}) from rsync client");
+        }
+        // Goodbye
+        self.channel.put_ndx(NDX_DONE);
+        self.channel.flush().await
+    }
+
+    /// Receive what is pending, then end the phase and wait for the echo
+    async fn end_phase(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            self.receive_next().await?;
+        }
+        self.channel.put_ndx(NDX_DONE);
+        let ndx = self.channel.read_ndx().await?;
+        if ndx != NDX_DONE {
+            anyhow::bail!(
+                "Expected the end of phase {} from rsync client, got {ndx}",
+                self.phase
+            );
+        }
+        Ok(())
+    }
+
+    /// Where entry `index` goes
+    fn target(&self, index: usize) -> PathBuf {
+        let name = &self.files[index].name;
+        if self.lone_file || name == b"." {
+            self.dest.clone()
+        } else {
+            self.dest.join(OsStr::from_bytes(name))
+        }
+    }
+
+    /// The open directory named `relative` in the transfer (empty for the
+    /// destination), opened from its parent without following a symlink
+    fn dir(&mut self, relative: &[u8]) -> io::Result<Arc<Dir>> {
+        if let Some(dir) = self.dirs.get(relative) {
+            return Ok(Arc::clone(dir));
+        }
+        if relative.is_empty() {
+            // Only in a dry run, which does not create it
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let (parent, name) = split_name(relative);
+        let dir = Arc::new(self.dir(parent)?.open_dir(OsStr::from_bytes(name))?);
+        self.dirs.insert(relative.to_vec(), Arc::clone(&dir));
+        Ok(dir)
+    }
+
+    /// The open directory holding entry `index`, and the entry's name in it
+    ///
+    /// Everything done to the entry goes through that directory, so nothing
+    /// swapped into its path meanwhile redirects it.
+    fn locate(&mut self, index: usize) -> io::Result<(Arc<Dir>, OsString)> {
+        if self.lone_file {
+            let name = self.dest.file_name().unwrap_or_default().to_os_string();
+            return Ok((self.dir(b"")?, name));
+        }
+        let relative = self.files[index].name.clone();
+        let (parent, name) = split_name(&relative);
+        Ok((self.dir(parent)?, OsStr::from_bytes(name).to_os_string()))
+    }
+
+    /// Drop the open directories at or below entry `index`, which is being
+    /// removed
+    fn forget_dirs(&mut self, index: usize) {
+        let name = &self.files[index].name;
+        self.dirs.retain(|relative, _| {
+            !(relative.starts_with(name) && matches!(relative.get(name.len()), None | Some(b'/')))
+        });
+    }
+
+    /// Tell the client about a file that could not be updated
+    async fn report(&mut self, message: impl fmt::Display) -> Result<()> {
+        self.stats.errors += 1;
+        // The sender reads messages only between answers
+        while !self.pending.is_empty() {
+            self.receive_next().await?;
+        }
+        let text = format!("arsync: [receiver] {message}\n");
+        self.channel.send_msg(msg::ERROR, text.as_bytes()).await
+    }
+
+    /// Decide where the list goes (`get_local_name`): a single file may
+    /// name its destination, anything else is received into a directory,
+    /// which is created if missing
+    fn prepare_destination(&mut self) -> Result<()> {
+        let single = self.files.len() == 1 && !self.files[0].is_dir();
+        let dest = self.dest.clone();
+        let context = || format!("Cannot access destination {}", dest.display());
+        let name = match &self.args.module {
+            Some(module) if *module == dest => None,
+            _ => dest.file_name(),
+        };
+        let Some(name) = name else {
+            // A module's root, or a destination such as "/", is a directory
+            let top = Dir::open(&dest).with_context(context)?;
+            self.dirs.insert(Vec::new(), Arc::new(top));
+            return Ok(());
+        };
+        let parent = dest
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        // In a module no symlink leads to the destination, nor is one a
+        // directory to receive into
+        let (parent, is_dir) = match &self.args.module {
+            Some(module) => {
+                let parent = match open_beneath(module, parent) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && self.args.dry_run => {
+                        return Ok(())
+                    }
+                    parent => parent.with_context(context)?,
+                };
+                let is_dir = parent.stat(name).map(|stat| stat.is_dir());
+                (parent, is_dir)
+            }
+            None => {
+                let parent = match Dir::open(parent) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && self.args.dry_run => {
+                        return Ok(())
+                    }
+                    parent => parent.with_context(context)?,
+                };
+                (
+                    parent,
+                    std::fs::metadata(&dest).map(|metadata| metadata.is_dir()),
+                )
+            }
+        };
+        let top = match is_dir {
+            Ok(true) if self.args.module.is_some() => {
+                parent.open_dir(name).with_context(context)?
+            }
+            Ok(true) => Dir::open(&dest).with_context(context)?,
+            Ok(false) if single => {
+                self.lone_file = true;
+                parent
+            }
+            Ok(false) => anyhow::bail!("Destination {} is not a directory", dest.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if single {
+                    self.lone_file = true;
+                    parent
+                } else if self.args.dry_run {
+                    return Ok(());
+                } else {
+                    parent
+                        .mkdir(name, 0o777)
+                        .with_context(|| format!("Cannot create destination {}", dest.display()))?;
+                    info!("created directory {}", dest.display());
+                    parent.open_dir(name).with_context(context)?
+                }
+            }
+            Err(e) => return Err(e).with_context(context),
+        };
+        self.dirs.insert(Vec::new(), Arc::new(top));
+        Ok(())
+    }
+
+    // --- deletion ---
+
+    /// Delete what the listed directories hold beyond the list, sparing
+    /// files the filter rules protect
+    async fn delete_extraneous(&mut self, filter: &FilterRules) -> Result<()> {
+        if self.lone_file {
+            return Ok(());
+        }
+        if self.channel.remote_io_error != 0 && !self.args.ignore_errors {
+            let text = "arsync: [receiver] IO error encountered -- skipping file deletion\n";
+            return self.channel.send_msg(msg::INFO, text.as_bytes()).await;
+        }
+        let listed: HashSet<Vec<u8>> = self.files.iter().map(|f| f.name.clone()).collect();
+        for index in 0..self.files.len() {
+            if !self.files[index].has_contents() {
+                continue;
+            }
+            let path = self.target(index);
+            let (relative, prefix) = match self.files[index].name.as_slice() {
+                b"." => (Vec::new(), Vec::new()),
+                name => (name.to_vec(), [name, &b"/"[..]].concat()),
+            };
+            let mut extraneous = Vec::new();
+            let listing = self
+                .dir(&relative)
+                .and_then(|dir| dir.entries().map(|names| (dir, names)));
+            let (dir, names) = match listing {
+                Ok(listing) => listing,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    self.report(format!("opendir {} failed: {e}", path.display()))
+                        .await?;
+                    continue;
+                }
+            };
+            for child in names {
+                let name = [&prefix[..], child.as_bytes()].concat();
+                if listed.contains(&name) {
+                    continue;
+                }
+                let is_dir = dir.stat(&child).is_ok_and(|stat| stat.is_dir());
+                if filter.is_excluded(Path::new(OsStr::from_bytes(&name)), is_dir) {
+                    continue;
+                }
+                extraneous.push((name, child, is_dir));
+            }
+            extraneous.sort();
+
+            for (name, child, is_dir) in extraneous {
+                let path = dir.path().join(&child);
+                let mut deleted = Vec::new();
+                let result = delete_tree(
+                    &dir,
+                    &child,
+                    name,
+                    is_dir,
+                    filter,
+                    self.args.dry_run,
+                    &mut deleted,
+                );
+                for (name, is_dir) in deleted {
+                    self.stats.deleted += 1;
+                    let mut payload = name;
+                    if is_dir {
+                        payload.push(0);
+                    }
+                    self.channel.send_msg(msg::DELETED, &payload).await?;
+                }
+                if let Err(e) = result {
+                    self.report(format!("delete of {} failed: {e}", path.display()))
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // --- generator ---
+
+    /// Bring entry `index` up to date, requesting its data if needed
+    async fn generate(&mut self, index: usize) -> Result<()> {
+        let entry = &self.files[index];
+        let (is_dir, is_link) = (entry.is_dir(), entry.is_link());
+        if entry.is_file() {
+            return self.check_file(index).await;
+        }
+        if self.args.dry_run {
+            return Ok(());
+        }
+        let path = self.target(index);
+        let result = self.locate(index).and_then(|(dir, name)| {
+            if is_dir {
+                self.make_directory(index, &dir, &name)
+            } else if is_link {
+                self.make_symlink(index, &dir, &name)
+            } else {
+                self.make_special(index, &dir, &name)
+            }
+        });
+        if let Err(e) = result {
+            let message = format!("{}: {e}", path.display());
+            self.report(message).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove entry `index`, `name` in `dir`, so something else can be
+    /// created there
+    fn clear_path(&mut self, index: usize, dir: &Dir, name: &OsStr, stat: &Stat) -> io::Result<()> {
+        if !stat.is_dir() {
+            return dir.remove(name, false);
+        }
+        self.forget_dirs(index);
+        if self.args.delete {
+            dir.remove_all(name)
+        } else {
+            dir.remove(name, true)
+        }
+    }
+
+    fn make_directory(&mut self, index: usize, dir: &Dir, name: &OsStr) -> io::Result<()> {
+        match dir.stat(name) {
+            Ok(stat) if stat.is_dir() => return Ok(()),
+            Ok(stat) => self.clear_path(index, dir, name, &stat)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // Writable until its own metadata is applied, after its contents
+        dir.mkdir(name, 0o700)?;
+        self.new_dirs.insert(index);
+        Ok(())
+    }
+
+    fn make_symlink(&mut self, index: usize, dir: &Dir, name: &OsStr) -> io::Result<()> {
+        let Some(target) = &self.files[index].link else {
+            return Ok(());
+        };
+        let target = if self.args.module.is_some() {
+            OsStr::from_bytes(&[SYMLINK_PREFIX, target].concat()).to_os_string()
+        } else {
+            OsStr::from_bytes(target).to_os_string()
+        };
+        match dir.stat(name) {
+            Ok(stat)
+                if stat.is_symlink()
+                    && dir.read_link(name).is_ok_and(|current| current == target) =>
+            {
+                return self.set_attrs(dir, name, &self.files[index], None);
+            }
+            Ok(stat) => self.clear_path(index, dir, name, &stat)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        dir.symlink(&target, name)?;
+        self.set_attrs(dir, name, &self.files[index], None)
+    }
+
+    fn make_special(&mut self, index: usize, dir: &Dir, name: &OsStr) -> io::Result<()> {
+        let entry = &self.files[index];
+        if !(self.args.devices && entry.is_device()) && !(self.args.specials && entry.is_special())
+        {
+            return Ok(());
+        }
+        let (file_type, is_device) = (entry.file_type(), entry.is_device());
+        let dev = join_dev(entry.rdev.0, entry.rdev.1);
+        match dir.stat(name) {
+            Ok(stat) if stat.file_type() == file_type && (!is_device || stat.rdev() == dev) => {
+                let entry = &self.files[index];
+                return self.set_attrs(dir, name, entry, self.args.perms.then_some(entry.mode));
+            }
+            Ok(stat) => self.clear_path(index, dir, name, &stat)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        dir.mknod(name, file_type | 0o600, dev)?;
+        let entry = &self.files[index];
+        self.set_attrs(dir, name, entry, Some(self.new_mode(entry)))
+    }
+
+    /// Quick-check a regular file and request it if it differs
+    async fn check_file(&mut self, index: usize) -> Result<()> {
+        let path = self.target(index);
+        let located = match self.locate(index) {
+            Ok(located) => Some(located),
+            // Its directory is missing too (a dry run does not create it)
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                let message = format!("{}: {e}", path.display());
+                return self.report(message).await;
+            }
+        };
+        let mut current = match located
+            .as_ref()
+            .map(|(dir, name)| (dir, name, dir.stat(name)))
+        {
+            Some((dir, name, Ok(stat))) => Some((dir, name, stat)),
+            Some((_, _, Err(e))) if e.kind() != io::ErrorKind::NotFound => {
+                let message = format!("{}: {e}", path.display());
+                return self.report(message).await;
+            }
+            _ => None,
+        };
+        let entry = &self.files[index];
+        if (self.args.existing && current.is_none())
+            || (self.args.ignore_existing && current.is_some())
+        {
+            return Ok(());
+        }
+        if let Some((dir, name, stat)) = current.as_ref().filter(|(_, _, stat)| stat.is_file()) {
+            if self.args.update && stat.mtime() > entry.mtime {
+                debug!("{} is newer", entry.display());
+                return Ok(());
+            }
+            if self.up_to_date(entry, stat, dir, name) {
+                let mode = self.args.perms.then_some(entry.mode);
+                if let Err(e) = self.set_attrs(dir, name, entry, mode) {
+                    let message = format!("{}: {e}", path.display());
+                    self.report(message).await?;
+                }
+                return Ok(());
+            }
+        }
+
+        if self.args.dry_run {
+            info!("would receive {}", entry.display());
+            self.stats.transferred += 1;
+            return Ok(());
+        }
+        if let Some((dir, name, stat)) = current.take_if(|(_, _, stat)| stat.is_dir()) {
+            if let Err(e) = self.clear_path(index, dir, name, &stat) {
+                let message = format!("{}: {e}", path.display());
+                return self.report(message).await;
+            }
+        }
+        self.request(index).await
+    }
+
+    /// Whether the current copy, `name` in `dir`, matches entry's size and
+    /// time or checksum
+    fn up_to_date(&self, entry: &ListedFile, stat: &Stat, dir: &Dir, name: &OsStr) -> bool {
+        if stat.len() != entry.size {
+            return false;
+        }
+        if self.args.checksum {
+            return entry.checksum.is_some_and(|sum| {
+                dir.open_file(name)
+                    .and_then(file_checksum)
+                    .is_ok_and(|local| local == sum)
+            });
+        }
+        if self.args.size_only {
+            return true;
+        }
+        !self.args.ignore_times && (stat.mtime() - entry.mtime).abs() <= self.args.modify_window
+    }
+
+    /// Ask for the data of file `index`, with the checksums of its current
+    /// copy as the basis
+    async fn request(&mut self, index: usize) -> Result<()> {
+        let path = self.target(index);
+        let located = self.locate(index).ok();
+        let current = located
+            .as_ref()
+            .and_then(|(dir, name)| dir.stat(name).ok())
+            .filter(Stat::is_file);
+        let basis = match &located {
+            Some((dir, name)) if current.is_some() && !self.args.whole_file => {
+                Some(dir.open_file(name))
+            }
+            _ => None,
+        };
+        let sums = basis
+            .transpose()
+            .and_then(|basis| basis_sums(basis, self.csum_length, self.seed))
+            .unwrap_or_else(|e| {
+                debug!(
+                    "Cannot checksum {}: {e}; requesting it whole",
+                    path.display()
+                );
+                vec![0; 16]
+            });
+        let entry = &self.files[index];
+        let iflags = match &current {
+            None => ITEM_TRANSFER | ITEM_IS_NEW,
+            Some(stat) => {
+                let mut iflags = ITEM_TRANSFER;
+                if stat.len() != entry.size {
+                    iflags |= ITEM_REPORT_SIZE;
+                }
+                if stat.mtime() != entry.mtime {
+                    iflags |= ITEM_REPORT_TIME;
+                }
+                iflags
+            }
+        };
+
+        // Keep what the sender may not be reading yet within the window
+        let bytes = sums.len() + 8;
+        while !self.pending.is_empty() && self.in_flight + bytes > REQUEST_WINDOW {
+            self.receive_next().await?;
+        }
+        self.channel.put_ndx(index as i32);
+        put_shortint(&mut self.channel.out, iflags);
+        self.channel.put_bytes(&sums);
+        self.pending.push_back(Request { index, bytes });
+        self.in_flight += bytes;
+        Ok(())
+    }
+
+    // --- receiver ---
+
+    /// Receive the answer to the oldest request
+    async fn receive_next(&mut self) -> Result<()> {
+        let Some(request) = self.pending.pop_front() else {
+            return Ok(());
+        };
+        self.in_flight -= request.bytes;
+        let ndx = request.index as i32;
+        if !self.channel.wait_for_file(ndx).await? {
+            // The sender could not open it and said why
+            return Ok(());
+        }
+        let got = self.channel.read_ndx().await?;
+        if got != ndx {
+            anyhow::bail!("Expected file {ndx} from rsync client, got {got}");
+        }
+        self.channel.read_item_attrs().await?;
+        self.receive_file(request.index).await
+    }
+
+    /// Apply the delta for file `index` to a temporary file and move it
+    /// into place (`receive_data`)
+    async fn receive_file(&mut self, index: usize) -> Result<()> {
+        let path = self.target(index);
+        let mut head = [0; 4];
+        for value in &mut head {
+            *value = self.channel.read_int().await?;
+        }
+        let [count, blength, s2length, remainder] = head;
+        if count < 0
+            || !(0..=MAX_BLOCK_SIZE).contains(&blength)
+            || (count > 0 && blength == 0)
+            || !(0..=SUM_LENGTH as i32).contains(&s2length)
+            || !(0..=blength).contains(&remainder)
+        {
+            anyhow::bail!("Invalid checksum header echoed by rsync client");
+        }
+
+        let temp_leaf = temp_name(path.file_name().unwrap_or_default());
+        let temp = path.with_file_name(&temp_leaf);
+        // The delta is still read past if the file cannot be written
+        let mut failure: Option<String> = None;
+        let located = self
+            .locate(index)
+            .map_err(|e| failure = Some(format!("{}: {e}", path.display())))
+            .ok();
+        let basis = match &located {
+            Some((dir, name)) if count > 0 => dir
+                .open_file(name)
+                .map_err(|e| failure = Some(format!("cannot open basis {}: {e}", path.display())))
+                .ok(),
+            _ => None,
+        };
+        let mut output = match &located {
+            Some((dir, _)) if failure.is_none() => dir
+                .create_file(&temp_leaf, 0o600)
+                .map(|file| BufWriter::with_capacity(4 * CHUNK_SIZE, file))
+                .map_err(|e| {
+                    failure
+                        .get_or_insert_with(|| format!("mkstemp {} failed: {e}", temp.display()));
+                })
+                .ok(),
+            _ => None,
+        };
+        let mut write = |data: &[u8], failure: &mut Option<String>| {
+            if let Some(out) = output.as_mut() {
+                if let Err(e) = out.write_all(data) {
+                    failure
+                        .get_or_insert_with(|| format!("write to {} failed: {e}", temp.display()));
+                    output = None;
+                }
+            }
+        };
+
+        let mut sum = md5::Context::new();
+        let mut block = Vec::new();
+        loop {
+            let i = match self.inflater.as_mut() {
+                Some(inflater) => match inflater.read(&mut self.channel).await? {
+                    Token::End => break,
+                    Token::Literal(data) => {
+                        sum.consume(&data);
+                        write(&data, &mut failure);
+                        self.stats.literal_bytes += data.len() as u64;
+                        continue;
+                    }
+                    Token::Block(i) => i64::from(i),
+                },
+                None => {
+                    let token = self.channel.read_int().await?;
+                    if token == 0 {
+                        break;
+                    }
+                    if token > 0 {
+                        let len = token as usize;
+                        if len > MAX_BLOCK_SIZE as usize {
+                            anyhow::bail!("Invalid literal length {len} from rsync client");
+                        }
+                        let data = self.channel.read_bytes(len).await?;
+                        sum.consume(data);
+                        write(data, &mut failure);
+                        self.stats.literal_bytes += len as u64;
+                        continue;
+                    }
+                    -(i64::from(token) + 1)
+                }
+            };
+            if !(0..i64::from(count)).contains(&i) {
+                anyhow::bail!("Invalid block index {i} from rsync client");
+            }
+            let len = if i == i64::from(count) - 1 && remainder != 0 {
+                remainder as usize
+            } else {
+                blength as usize
+            };
+            block.resize(len, 0);
+            let offset = i as u64 * blength as u64;
+            if let Some(Err(e)) = basis.as_ref().map(|f| f.read_exact_at(&mut block, offset)) {
+                failure.get_or_insert_with(|| format!("read of {} failed: {e}", path.display()));
+            }
+            if let Some(inflater) = self.inflater.as_mut() {
+                inflater.see_block(&block)?;
+            }
+            sum.consume(&block);
+            write(&block, &mut failure);
+            self.stats.matched_bytes += len as u64;
+        }
+        let mut expected = [0; SUM_LENGTH];
+        expected.copy_from_slice(self.channel.read_bytes(SUM_LENGTH).await?);
+        drop(write);
+
+        let file = match output.map(BufWriter::into_inner) {
+            Some(Ok(file)) => Some(file),
+            Some(Err(e)) => {
+                failure.get_or_insert_with(|| format!("write to {} failed: {e}", temp.display()));
+                None
+            }
+            None => None,
+        };
+        drop(file);
+        let remove_temp = || {
+            if let Some((dir, _)) = &located {
+                let _ = dir.remove(&temp_leaf, false);
+            }
+        };
+        if let Some(failure) = failure {
+            remove_temp();
+            return self.report(failure).await;
+        }
+        if sum.compute().0 != expected {
+            remove_temp();
+            if self.phase == 0 {
+                self.redo.push(index);
+                return Ok(());
+            }
+            let message = format!(
+                "{} failed verification -- update discarded",
+                self.files[index].display()
+            );
+            return self.report(message).await;
+        }
+
+        // Whenever the file was not located, a failure was reported above
+        let Some((dir, name)) = located else {
+            return Ok(());
+        };
+        let entry = &self.files[index];
+        let current = dir.stat(&name).ok();
+        let mode = match current.filter(|stat| stat.is_file() && !self.args.perms) {
+            Some(stat) => stat.mode(),
+            None => self.new_mode(entry),
+        };
+        let result = self
+            .set_attrs(&dir, &temp_leaf, entry, Some(mode))
+            .and_then(|()| dir.rename(&temp_leaf, &name));
+        if let Err(e) = result {
+            let _ = dir.remove(&temp_leaf, false);
+            let message = format!("{}: {e}", path.display());
+            return self.report(message).await;
+        }
+        debug!("rsync receiver: {}", entry.display());
+        self.stats.transferred += 1;
+        Ok(())
+    }
+
+    // --- metadata ---
+
+    /// Permissions for a file this session creates (`dest_mode`): the
+    /// sender's with `-p`, otherwise the sender's less the umask
+    fn new_mode(&self, entry: &ListedFile) -> u32 {
+        if self.args.perms {
+            entry.mode
+        } else {
+            entry.mode & 0o777 & !self.umask
+        }
+    }
+
+    /// Apply owner, permissions (`mode`, unless `None`) and modification
+    /// time to `name` in `dir` as the options ask; directory times wait for
+    /// `finish_directories`
+    fn set_attrs(
+        &self,
+        dir: &Dir,
+        name: &OsStr,
+        entry: &ListedFile,
+        mode: Option<u32>,
+    ) -> io::Result<()> {
+        let uid = (self.args.owner && self.am_root).then_some(entry.uid);
+        let gid = self.args.group.then_some(entry.gid);
+        if uid.is_some() || gid.is_some() {
+            match dir.chown(name, uid, gid) {
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied && !self.am_root => {
+                    let path = dir.path().join(name);
+                    debug!("Cannot change the group of {}: {e}", path.display());
+                }
+                result => result?,
+            }
+        }
+        if entry.is_link() {
+            return Ok(());
+        }
+        if let Some(mode) = mode {
+            // A module gets no set-user-ID or set-group-ID files
+            let mask = if self.args.module.is_some() {
+                0o1777
+            } else {
+                0o7777
+            };
+            dir.chmod(name, mode & mask)?;
+        }
+        if self.args.times && !entry.is_dir() {
+            dir.set_mtime(name, entry.mtime)?;
+        }
+        Ok(())
+    }
+
+    /// Apply directory metadata, deepest first, once their contents are in
+    async fn finish_directories(&mut self) -> Result<()> {
+        if self.lone_file || self.args.dry_run {
+            return Ok(());
+        }
+        for index in (0..self.files.len()).rev() {
+            let entry = &self.files[index];
+            if !entry.is_dir() {
+                continue;
+            }
+            let path = self.target(index);
+            let mode = if self.new_dirs.contains(&index) {
+                Some(self.new_mode(entry))
+            } else {
+                self.args.perms.then_some(entry.mode)
+            };
+            let result = self.locate(index).and_then(|(dir, name)| {
+                let entry = &self.files[index];
+                self.set_attrs(&dir, &name, entry, mode)?;
+                if self.args.times && !self.args.omit_dir_times {
+                    dir.set_mtime(&name, entry.mtime)?;
+                }
+                Ok(())
+            });
+            match result {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    let message = format!("{}: {e}", path.display());
+                    self.report(message).await?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a name of the transfer into its directory's name (empty for the
+/// destination) and its last component
+fn split_name(name: &[u8]) -> (&[u8], &[u8]) {
+    match name.iter().rposition(|&byte| byte == b'/') {
+        Some(slash) => (&name[..slash], &name[slash + 1..]),
+        None => (&[], name),
+    }
+}
+
+/// Open directory `dir`, reached from `root` without following a symlink
+///
+/// Each component is opened with `O_NOFOLLOW` from the one before, as
+/// `tar_import` does.
+fn open_beneath(root: &Path, dir: &Path) -> io::Result<Dir> {
+    let outside = || {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is outside the module", dir.display()),
+        )
+    };
+    let relative = dir.strip_prefix(root).map_err(|_| outside())?;
+    let mut current = Dir::open(root)?;
+    for component in relative.components() {
+        let std::path::Component::Normal(name) = component else {
+            return Err(outside());
+        };
+        current = current.open_dir(name)?;
+    }
+    Ok(current)
+}
+
+/// Delete `leaf` in `dir` (named `name` in the transfer), leaving what
+/// `filter` protects; appends what was deleted, contents before their
+/// directory
+fn delete_tree(
+    dir: &Dir,
+    leaf: &OsStr,
+    name: Vec<u8>,
+    is_dir: bool,
+    filter: &FilterRules,
+    dry_run: bool,
+    deleted: &mut Vec<(Vec<u8>, bool)>,
+) -> io::Result<()> {
+    if is_dir {
+        let subdir = dir.open_dir(leaf)?;
+        for child in subdir.entries()? {
+            let child_name = [&name[..], &b"/"[..], child.as_bytes()].concat();
+            let child_dir = subdir.stat(&child)?.is_dir();
+            if filter.is_excluded(Path::new(OsStr::from_bytes(&child_name)), child_dir) {
+                continue;
+            }
+            delete_tree(
+                &subdir, &child, child_name, child_dir, filter, dry_run, deleted,
+            )?;
+        }
+        if !dry_run {
+            match dir.remove(leaf, true) {
+                // Protected files are left inside
+                Err(e) if e.raw_os_error() == Some(libc::ENOTEMPTY) => return Ok(()),
+                result => result?,
+            }
+        }
+    } else if !dry_run {
+        dir.remove(leaf, false)?;
+    }
+    deleted.push((name, is_dir));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<ServerArgs> {
+        let list: Vec<OsString> = list.iter().map(OsString::from).collect();
+        ServerArgs::parse(&list)
+    }
+
+    #[test]
+    fn test_parse_server_args() {
+        // Requirement: The letters and options an rsync client passes to a
+        // receiving server are understood, up to "." and the destination
+        let parsed = args(&[
+            "-vlogDtprce.iLsfxC",
+            "--delete-during",
+            "--numeric-ids",
+            "--checksum-seed=42",
+            ".",
+            "backup/dst",
+        ])
+        .unwrap();
+        assert!(parsed.recursive && parsed.links && parsed.perms && parsed.times);
+        assert!(parsed.owner && parsed.group && parsed.devices && parsed.specials);
+        assert!(parsed.checksum && parsed.delete && parsed.numeric_ids);
+        assert_eq!(parsed.client_info, ".iLsfxC");
+        assert_eq!(parsed.checksum_seed, Some(42));
+        assert_eq!(parsed.destination, PathBuf::from("backup/dst"));
+
+        assert_eq!(args(&["-d", "."]).unwrap().destination, PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_parse_compression_args() {
+        // Requirement: -z selects rsync's zlib token stream, and the
+        // compression options pick zlib, zlibx or none
+        let codec = |list: &[&str]| args(list).unwrap().compress;
+        assert_eq!(codec(&["-r", ".", "dst"]), None);
+        assert_eq!(codec(&["-rz", ".", "dst"]), Some(TokenCodec::Zlib));
+        assert_eq!(
+            codec(&["-z", "--compress-level=9", ".", "dst"]),
+            Some(TokenCodec::Zlib)
+        );
+        assert_eq!(
+            codec(&["--new-compress", ".", "dst"]),
+            Some(TokenCodec::ZlibX)
+        );
+        assert_eq!(
+            codec(&["-z", "--compress-choice=zlibx", ".", "dst"]),
+            Some(TokenCodec::ZlibX)
+        );
+        assert_eq!(codec(&["-z", "--zc=none", ".", "dst"]), None);
+        assert!(args(&["-z", "--compress-level=x", ".", "dst"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_args_refuses_unsupported() {
+        // Requirement: Roles and options the receiver cannot honour fail
+        // with a message naming them instead of corrupting the transfer
+        for list in [
+            &["--sender", "-r", ".", "src"][..],
+            &["-rz", "--compress-choice=zstd", ".", "dst"],
+            &["-rH", ".", "dst"],
+            &["--inplace", ".", "dst"],
+            &["-r", "dst"],
+        ] {
+            assert!(args(list).is_err(), "{list:?}");
+        }
+    }
+
+    #[test]
+    fn test_receiver_filter_rules() {
+        // Requirement: Only the rules that protect files from deletion
+        // apply on the receiving side
+        assert_eq!(
+            receiver_rule("- *.tmp", false).unwrap().as_deref(),
+            Some("- *.tmp")
+        );
+        assert_eq!(
+            receiver_rule("P keep/", true).unwrap().as_deref(),
+            Some("- keep/")
+        );
+        assert_eq!(receiver_rule("+ a", false).unwrap().as_deref(), Some("+ a"));
+        assert_eq!(receiver_rule("!", false).unwrap().as_deref(), Some("!"));
+        assert_eq!(receiver_rule("- *.tmp", true).unwrap(), None);
+        assert_eq!(receiver_rule("H secret", false).unwrap(), None);
+        assert_eq!(receiver_rule("-s cache/", false).unwrap(), None);
+        assert!(receiver_rule(": .rsync-filter", false).is_err());
+    }
+
+    #[test]
+    fn test_sum_sizes_match_rsync() {
+        // Requirement: Block and checksum lengths follow rsync's
+        // sum_sizes_sqroot, growing with the square root of the file size
+        assert_eq!(sum_sizes(0, SHORT_SUM_LENGTH), (700, 2));
+        assert_eq!(sum_sizes(490_000, SHORT_SUM_LENGTH), (700, 2));
+        assert_eq!(sum_sizes(1_000_000, SHORT_SUM_LENGTH).0, 1000);
+        assert_eq!(sum_sizes(1 << 30, SHORT_SUM_LENGTH), (32_768, 3));
+        assert_eq!(sum_sizes(1 << 40, SHORT_SUM_LENGTH).0, MAX_BLOCK_SIZE);
+        assert_eq!(sum_sizes(1 << 20, SUM_LENGTH).1, SUM_LENGTH);
+    }
+
+    #[test]
+    fn test_unsafe_names_are_refused() {
+        // Requirement: File list names cannot escape the destination
+        for name in ["a", "a/b", ".", "dir/.hidden"] {
+            assert!(check_name(name.as_bytes()).is_ok(), "{name}");
+        }
+        for name in ["", "/etc/passwd", "../x", "a/../../x", "a//b", "./a"] {
+            assert!(check_name(name.as_bytes()).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_module_paths_do_not_follow_symlinks() {
+        // Requirement: In a daemon module no directory is reached through a
+        // symlink, whether it leads out of the module or not
+        let temp = tempfile::TempDir::new().unwrap();
+        let (root, outside) = (temp.path().join("module"), temp.path().join("outside"));
+        std::fs::create_dir_all(root.join("dir/sub")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink("dir", root.join("inside")).unwrap();
+
+        assert!(open_beneath(&root, &root).is_ok());
+        let sub = open_beneath(&root, &root.join("dir/sub")).unwrap();
+        assert_eq!(sub.path(), root.join("dir/sub"));
+        let err = open_beneath(&root, &root.join("dir/missing/deeper")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        for dir in ["escape", "escape/x", "inside/sub", "dir/../../outside"] {
+            let err = open_beneath(&root, &root.join(dir)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{dir}");
+        }
+        assert!(open_beneath(&root, &outside).is_err());
+    }
+
+    #[test]
+    fn test_basis_sums() {
+        // Requirement: Block checksums cover the basis in blocks of the
+        // computed length, with a short last block
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("basis");
+        let data: Vec<u8> = (0..1500u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let seed = Seed {
+            value: 7,
+            first: true,
+        };
+        let sums = basis_sums(Some(File::open(&path).unwrap()), SHORT_SUM_LENGTH, seed).unwrap();
+        let int = |at: usize| i32::from_le_bytes(sums[at..at + 4].try_into().unwrap());
+        assert_eq!([int(0), int(4), int(8), int(12)], [3, 700, 2, 100]);
+        assert_eq!(sums.len(), 16 + 3 * (4 + 2));
+        let (s1, s2) = weak_sums(&data[1400..]);
+        assert_eq!(int(16 + 2 * 6) as u32, weak_checksum(s1, s2));
+        assert_eq!(
+            &sums[16 + 2 * 6 + 4..],
+            &strong_sum(&data[1400..], seed)[..2]
+        );
+
+        assert_eq!(basis_sums(None, SHORT_SUM_LENGTH, seed).unwrap(), [0; 16]);
+    }
+
+    #[test]
+    fn test_delete_tree_spares_protected_files() {
+        // Requirement: Deleting an extraneous directory keeps protected
+        // files and the directories holding them
+        let dir = tempfile::TempDir::new().unwrap();
+        let old = dir.path().join("old");
+        std::fs::create_dir_all(old.join("sub")).unwrap();
+        std::fs::write(old.join("a"), b"a").unwrap();
+        std::fs::write(old.join("sub/keep.conf"), b"k").unwrap();
+        let filter = FilterRules::from_config(&FilterConfig {
+            rules: vec![FilterOption::Filter("- *.conf".to_string())],
+        })
+        .unwrap();
+
+        let parent = Dir::open(dir.path()).unwrap();
+        let mut deleted = Vec::new();
+        delete_tree(
+            &parent,
+            OsStr::new("old"),
+            b"old".to_vec(),
+            true,
+            &filter,
+            false,
+            &mut deleted,
+        )
+        .unwrap();
+        assert_eq!(deleted, [(b"old/a".to_vec(), false)]);
+        assert!(old.join("sub/keep.conf").exists());
+        assert!(!old.join("a").exists());
+    }
+}
@@ -0,0 +1,1305 @@
+//! rsync sender: pushing to a stock rsync server
+//!
+//! `arsync SRC [USER@]HOST:DST` starts `rsync --server` on HOST through the
+//! remote shell (`--rsh`, `--remote-cmd`) and plays the client-side sender of
+//! rsync's wire protocol, version 30 (spoken by rsync 3.0 and every later
+//! release):
+//!
+//! 1. Both sides send their protocol version as a raw 4-byte integer; the
+//!    server follows with its compatibility flags and the session's checksum
+//!    seed.
+//! 2. From then on both directions are multiplexed: the byte stream travels
+//!    in frames whose 4-byte header packs a message code (data, log text,
+//!    errors, ...) and a 24-bit length.
+//! 3. The sender sends the filter rules (only when the receiver deletes) and
+//!    the file list, sorted in rsync's order; both sides refer to files by
+//!    their index in that list.
+//! 4. The receiver's generator asks for each file it needs, with the block
+//!    checksums of its current copy. The sender answers with a delta
+//!    (literal data and references to blocks it found with the rolling
+//!    checksum) followed by the MD5 of the whole file.
+//! 5. Phases end with `NDX_DONE` markers: the second phase resends files
+//!    whose whole-file checksum failed, then both sides say goodbye.
+//!
+//! Only what rsync negotiates without optional extensions is spoken: no
+//! incremental recursion, hard links, ACLs or extended attributes (those
+//! options are not passed to the server, with a warning). With `--compress`
+//! the delta travels as rsync's zlib token stream (`-z`).
+//! Owners and groups travel as numeric ids, as arsync preserves them locally.
+//!
+//! Framing, integer encodings and checksums shared with the receiving side
+//! (`arsync --server`) live in `rsync_wire`.
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+#![allow(clippy::cast_possible_truncation)] // Wire integers are fixed-width
+#![allow(clippy::cast_possible_wrap)] // Wire integers are two's complement
+#![allow(clippy::cast_sign_loss)] // Wire integers are two's complement
+#![allow(clippy::doc_markdown)] // Protocol documentation
+
+use crate::cli::FilterOption;
+use crate::config::SyncConfig;
+use crate::filter::FilterRules;
+use crate::protocol::compress::{CompressLevel, TokenCodec, TokenDeflater};
+use crate::protocol::rsync_wire::{
+    compare_names, file_checksum, msg, put_int, put_shortint, put_varint, put_varlong, split_dev,
+    strong_sum, weak_checksum, weak_sums, Channel, Seed, Sums, CF_CHKSUM_SEED_FIX, CF_INC_RECURSE,
+    CF_VARINT_FLIST_FLAGS, CHUNK_SIZE, FLUSH_SIZE, IOERR_GENERAL, IOERR_VANISHED, ITEM_TRANSFER,
+    MAX_PHASE, NDX_DONE, PROTOCOL_VERSION, READ_SIZE, SUM_LENGTH, XMIT_EXTENDED_FLAGS,
+    XMIT_LONG_NAME, XMIT_NO_CONTENT_DIR, XMIT_SAME_GID, XMIT_SAME_MODE, XMIT_SAME_NAME,
+    XMIT_SAME_TIME, XMIT_SAME_UID, XMIT_TOP_DIR,
+};
+use crate::protocol::ssh::SshConnector;
+use crate::protocol::transport::Transport;
+use crate::sync::SyncStats;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+// ============================================================================
+// Options
+// ============================================================================
+
+/// What the sender transfers, and the options the server must be started with
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct SenderOptions {
+    /// Recurse into directories (`-r`); otherwise only the top level (`-d`)
+    pub recursive: bool,
+    /// Send symlinks as symlinks (`-l`); otherwise they are skipped
+    pub links: bool,
+    /// Preserve permissions (`-p`)
+    pub perms: bool,
+    /// Preserve modification times (`-t`)
+    pub times: bool,
+    /// Leave directory times alone (`-O`)
+    pub omit_dir_times: bool,
+    /// Preserve owners (`-o`)
+    pub owner: bool,
+    /// Preserve groups (`-g`)
+    pub group: bool,
+    /// Send device files (`--devices`)
+    pub devices: bool,
+    /// Send FIFOs and sockets (`--specials`)
+    pub specials: bool,
+    /// Compare files by checksum (`-c`)
+    pub checksum: bool,
+    /// Transfer files even if size and time match (`-I`)
+    pub ignore_times: bool,
+    /// Delete extraneous files on the receiver (`--delete`)
+    pub delete: bool,
+    /// Only show what would be transferred (`-n`)
+    pub dry_run: bool,
+    /// Compress file data with zlib at this level (`-z`)
+    pub compress: Option<CompressLevel>,
+    /// Filter rules applied to the file list
+    pub filter: FilterRules,
+    /// The same rules as rsync filter strings, sent to a deleting receiver
+    pub filter_rules: Vec<String>,
+}
+
+impl SenderOptions {
+    /// Options for pushing as described by `config`
+    ///
+    /// Options the sender cannot express on the wire are dropped with a
+    /// warning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the filter rules cannot be compiled or an
+    /// `--exclude-from` file cannot be read.
+    pub fn from_config(config: &SyncConfig) -> Result<Self> {
+        let metadata = &config.metadata;
+        let traversal = &config.traversal;
+        for (enabled, option) in [
+            (metadata.should_preserve_xattrs(), "--xattrs"),
+            (metadata.should_preserve_acls(), "--acls"),
+            (metadata.should_preserve_hard_links(), "--hard-links"),
+            (metadata.atimes, "--atimes"),
+        ] {
+            if enabled {
+                warn!("{option} is not supported when pushing to an rsync server; ignored");
+            }
+        }
+        Ok(Self {
+            recursive: metadata.should_recurse(),
+            links: metadata.should_preserve_links(),
+            perms: metadata.should_preserve_permissions(),
+            times: metadata.should_preserve_timestamps(),
+            omit_dir_times: metadata.omit_dir_times,
+            owner: metadata.should_preserve_owner(),
+            group: metadata.should_preserve_group(),
+            devices: metadata.should_preserve_devices(),
+            specials: metadata.should_preserve_specials(),
+            checksum: traversal.checksum,
+            ignore_times: traversal.ignore_times,
+            delete: traversal.delete_timing().is_some(),
+            dry_run: config.output.dry_run,
+            compress: None,
+            filter: FilterRules::from_config(&traversal.filter)?,
+            filter_rules: rsync_filter_rules(&traversal.filter.rules)?,
+        })
+    }
+
+    /// Remote arguments starting `rsync --server` to receive into `destination`
+    #[must_use]
+    pub fn server_args(&self, destination: &Path) -> Vec<OsString> {
+        let mut letters = String::from("-");
+        for (enabled, letter) in [
+            (self.recursive, 'r'),
+            (!self.recursive, 'd'),
+            (self.links, 'l'),
+            (self.perms, 'p'),
+            (self.times, 't'),
+            (self.omit_dir_times, 'O'),
+            (self.owner, 'o'),
+            (self.group, 'g'),
+            (self.devices && self.specials, 'D'),
+            (self.checksum, 'c'),
+            (self.ignore_times, 'I'),
+            (self.dry_run, 'n'),
+            (self.compress.is_some(), 'z'),
+        ] {
+            if enabled {
+                letters.push(letter);
+            }
+        }
+        let mut args: Vec<OsString> = vec!["--server".into(), letters.into()];
+        for (enabled, option) in [
+            (self.devices && !self.specials, "--devices"),
+            (self.specials && !self.devices, "--specials"),
+            (self.owner || self.group, "--numeric-ids"),
+            (self.delete, "--delete"),
+        ] {
+            if enabled {
+                args.push(option.into());
+            }
+        }
+        args.push(".".into());
+        args.push(destination.as_os_str().to_os_string());
+        args
+    }
+}
+
+/// The filter options as rsync rule strings (`- PATTERN`, `+ PATTERN`, `!`)
+fn rsync_filter_rules(options: &[FilterOption]) -> Result<Vec<String>> {
+    let prefixed = |value: &str, prefix: &str| {
+        if value == "!" || value.starts_with("- ") || value.starts_with("+ ") {
+            value.to_string()
+        } else {
+            format!("{prefix}{value}")
+        }
+    };
+    let mut rules = Vec::new();
+    for option in options {
+        match option {
+            FilterOption::Exclude(value) => rules.push(prefixed(value, "- ")),
+            FilterOption::Include(value) => rules.push(prefixed(value, "+ ")),
+            FilterOption::Filter(rule) => rules.push(rule.trim_start().to_string()),
+            FilterOption::ExcludeFrom(path) => {
+                let contents = std::fs::read(path).with_context(|| {
+                    format!("Cannot read --exclude-from file {}", path.display())
+                })?;
+                for line in contents.split(|&b| b == b'\n') {
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    if line.is_empty() || line.starts_with(b"#") || line.starts_with(b";") {
+                        continue;
+                    }
+                    rules.push(prefixed(&String::from_utf8_lossy(line), "- "));
+                }
+            }
+        }
+    }
+    Ok(rules)
+}
+
+// ============================================================================
+// Entry points
+// ============================================================================
+
+/// Totals of a push, as rsync reports them at the end of a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderStats {
+    /// Entries in the file list
+    pub files: u64,
+    /// Regular files whose data was sent
+    pub transferred: u64,
+    /// Size of all regular files in the list
+    pub total_size: u64,
+    /// File data sent as literal bytes
+    pub literal_bytes: u64,
+    /// File data the receiver already had (sent as block references)
+    pub matched_bytes: u64,
+    /// Bytes written to the connection
+    pub bytes_sent: u64,
+    /// Bytes read from the connection
+    pub bytes_received: u64,
+}
+
+impl fmt::Display for SenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} files transferred ({} literal, {} matched bytes); sent {} bytes, received {} bytes, total size {}",
+            self.transferred,
+            self.files,
+            self.literal_bytes,
+            self.matched_bytes,
+            self.bytes_sent,
+            self.bytes_received,
+            self.total_size
+        )
+    }
+}
+
+/// Push `config.source` to `path` on `host` through a stock rsync server
+///
+/// `user` may be empty to let the remote shell choose. `connector` starts
+/// rsync on the host and connects again if the connection fails transiently.
+/// File data is compressed at `compress` if given.
+///
+/// # Errors
+///
+/// Returns an error if the remote shell cannot be started, the server does
+/// not speak protocol 30 or later, the transfer fails, or the server exits
+/// with an error.
+pub async fn push(
+    config: &SyncConfig,
+    connector: &SshConnector,
+    user: &str,
+    host: &str,
+    path: &Path,
+    compress: Option<CompressLevel>,
+) -> Result<SyncStats> {
+    let start = Instant::now();
+    if !config.extra_destinations.is_empty() {
+        anyhow::bail!("A remote destination cannot be combined with other destinations");
+    }
+    let options = SenderOptions {
+        compress,
+        ..SenderOptions::from_config(config)?
+    };
+    let server_args = options.server_args(path);
+    let remote_args: Vec<&OsStr> = server_args.iter().map(OsString::as_os_str).collect();
+    info!(
+        "Pushing {} to {host}:{} via rsync",
+        config.source.display(),
+        path.display()
+    );
+
+    let (options, source) = (&options, config.source.as_path());
+    let stats = connector
+        .session(user, host, &remote_args, |mut connection| async move {
+            let result = send(&mut connection, options, source).await;
+            connection.finish(result).await
+        })
+        .await?;
+    info!("{stats}");
+
+    Ok(SyncStats {
+        files_copied: stats.transferred,
+        bytes_copied: stats.literal_bytes + stats.matched_bytes,
+        errors: 0,
+        duration: start.elapsed(),
+    })
+}
+
+/// Run the sender side of a session with `rsync --server` over `transport`
+///
+/// The server must have been started with `options.server_args()`.
+///
+/// # Errors
+///
+/// Returns an error on protocol violations, if the server does not speak
+/// protocol 30 or later, or if the connection fails.
+pub async fn send<T: Transport>(
+    transport: &mut T,
+    options: &SenderOptions,
+    source: &Path,
+) -> Result<SenderStats> {
+    let mut channel = Channel::new(transport, "rsync server");
+
+    // Versions, compatibility flags and the checksum seed (all unframed)
+    channel.put_int(PROTOCOL_VERSION);
+    channel.flush().await?;
+    let remote_version = channel.read_int().await.context(
+        "rsync server closed the connection before the handshake (is rsync installed on the remote host?)",
+    )?;
+    if !(1..1000).contains(&remote_version) {
+        anyhow::bail!(
+            "Protocol version mismatch ({remote_version:#x}) -- is your shell clean? (see the rsync manpage)"
+        );
+    }
+    if remote_version < PROTOCOL_VERSION {
+        anyhow::bail!(
+            "rsync server speaks protocol {remote_version}; arsync needs {PROTOCOL_VERSION} (rsync 3.0) or later"
+        );
+    }
+    let compat_flags = channel.read_varint().await?;
+    if compat_flags & (CF_INC_RECURSE | CF_VARINT_FLIST_FLAGS) != 0 {
+        anyhow::bail!("rsync server enabled unsupported protocol extensions ({compat_flags:#x})");
+    }
+    let seed = Seed {
+        value: channel.read_int().await? as u32,
+        first: compat_flags & CF_CHKSUM_SEED_FIX != 0,
+    };
+    debug!("rsync server: protocol {remote_version}, compat flags {compat_flags:#x}");
+    channel.multiplexed = true;
+
+    // Filter rules: only a deleting receiver reads them
+    if options.delete {
+        for rule in &options.filter_rules {
+            channel.put_int(rule.len() as i32);
+            channel.put_bytes(rule.as_bytes());
+        }
+        channel.put_int(0);
+    }
+
+    let list = FileList::build(source, options)?;
+    let mut stats = SenderStats {
+        files: list.entries.len() as u64,
+        total_size: list
+            .entries
+            .iter()
+            .filter(|entry| entry.is_file())
+            .map(|entry| entry.size)
+            .sum(),
+        ..SenderStats::default()
+    };
+    let mut io_error = list.io_error;
+    io_error |= send_file_list(&mut channel, &list, options).await?;
+    if io_error != 0 {
+        channel
+            .send_msg(msg::IO_ERROR, &io_error.to_le_bytes())
+            .await?;
+    }
+    channel.flush().await?;
+
+    send_files(&mut channel, &list, options, seed, &mut stats).await?;
+
+    // The generator's goodbye
+    let ndx = channel.read_ndx().await?;
+    if ndx != NDX_DONE {
+        anyhow::bail!("Invalid packet at end of run ({ndx}) from rsync server");
+    }
+    if channel.remote_io_error != 0 {
+        warn!(
+            "rsync server reported I/O errors ({:#x})",
+            channel.remote_io_error
+        );
+    }
+
+    stats.bytes_sent = channel.bytes_sent;
+    stats.bytes_received = channel.bytes_received;
+    Ok(stats)
+}
+
+/// Answer the generator's requests until the final phase ends
+async fn send_files<T: Transport>(
+    channel: &mut Channel<'_, T>,
+    list: &FileList,
+    options: &SenderOptions,
+    seed: Seed,
+    stats: &mut SenderStats,
+) -> Result<()> {
+    let mut phase = 0;
+    let mut io_error = 0;
+    let mut deflater = options
+        .compress
+        .map(|level| TokenDeflater::new(level, TokenCodec::Zlib));
+    loop {
+        let ndx = channel.read_ndx().await?;
+        if ndx == NDX_DONE {
+            phase += 1;
+            if phase > MAX_PHASE {
+                break;
+            }
+            debug!("rsync sender: phase {phase}");
+            channel.put_ndx(NDX_DONE);
+            continue;
+        }
+        let entry = usize::try_from(ndx)
+            .ok()
+            .and_then(|i| list.entries.get(i))
+            .ok_or_else(|| anyhow::anyhow!("Invalid file index {ndx} from rsync server"))?;
+        let attrs = channel.read_item_attrs().await?;
+
+        if attrs.iflags & ITEM_TRANSFER == 0 {
+            channel.put_ndx_and_attrs(ndx, &attrs);
+            continue;
+        }
+        if !entry.is_file() {
+            anyhow::bail!("rsync server asked to transfer non-regular file {ndx}");
+        }
+        if phase == MAX_PHASE {
+            anyhow::bail!("rsync server asked for a transfer in the final phase");
+        }
+        stats.transferred += 1;
+        if options.dry_run {
+            channel.put_ndx_and_attrs(ndx, &attrs);
+            continue;
+        }
+
+        let sums = channel.read_sums().await?;
+        let file = match File::open(&entry.path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("send_files failed to open {}: {e}", entry.path.display());
+                io_error |= if e.kind() == io::ErrorKind::NotFound {
+                    IOERR_VANISHED
+                } else {
+                    IOERR_GENERAL
+                };
+                channel.send_msg(msg::NO_SEND, &ndx.to_le_bytes()).await?;
+                continue;
+            }
+        };
+        let len = file
+            .metadata()
+            .map_or(entry.size, |metadata| metadata.len());
+        debug!("rsync sender: {} ({len} bytes)", entry.path.display());
+
+        channel.put_ndx_and_attrs(ndx, &attrs);
+        channel.put_sum_head(&sums);
+        let mut matcher = Matcher::new(&sums, file, len, seed, deflater.as_mut());
+        while !matcher.run(&mut channel.out, FLUSH_SIZE)? {
+            channel.flush().await?;
+        }
+        if let Some(e) = matcher.read_error() {
+            warn!("Failed to read {}: {e}", entry.path.display());
+            io_error |= IOERR_GENERAL;
+        }
+        stats.literal_bytes += matcher.literal_bytes;
+        stats.matched_bytes += matcher.matched_bytes;
+    }
+
+    if io_error != 0 {
+        channel
+            .send_msg(msg::IO_ERROR, &io_error.to_le_bytes())
+            .await?;
+    }
+    channel.put_ndx(NDX_DONE);
+    channel.flush().await
+}
+
+// ============================================================================
+// File list
+// ============================================================================
+
+/// One file list entry
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Path relative to the transfer root, as sent
+    name: Vec<u8>,
+    /// Local path to read from
+    path: PathBuf,
+    /// `st_mode`, including the file type
+    mode: u32,
+    /// Size of a regular file, length of a symlink's target, 0 otherwise
+    size: u64,
+    /// Modification time (seconds, signed)
+    mtime: i64,
+    /// Owner
+    uid: u32,
+    /// Group
+    gid: u32,
+    /// Device number of a device file
+    rdev: u64,
+    /// Symlink target
+    link: Option<Vec<u8>>,
+    /// Root of the transfer
+    top_dir: bool,
+}
+
+impl Entry {
+    /// Entry for the file at `path` with `metadata` (from `lstat`)
+    fn new(name: Vec<u8>, path: PathBuf, metadata: &std::fs::Metadata) -> io::Result<Self> {
+        let file_type = metadata.mode() & libc::S_IFMT;
+        let link = if file_type == libc::S_IFLNK {
+            Some(std::fs::read_link(&path)?.into_os_string().into_vec())
+        } else {
+            None
+        };
+        let size = match (&link, file_type) {
+            (Some(target), _) => target.len() as u64,
+            (None, libc::S_IFREG) => metadata.len(),
+            _ => 0,
+        };
+        Ok(Self {
+            name,
+            path,
+            mode: metadata.mode(),
+            size,
+            mtime: metadata.mtime(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            rdev: metadata.rdev(),
+            link,
+            top_dir: false,
+        })
+    }
+
+    fn file_type(&self) -> u32 {
+        self.mode & libc::S_IFMT
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    fn is_file(&self) -> bool {
+        self.file_type() == libc::S_IFREG
+    }
+
+    fn is_device(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFCHR | libc::S_IFBLK)
+    }
+
+    fn is_special(&self) -> bool {
+        matches!(self.file_type(), libc::S_IFIFO | libc::S_IFSOCK)
+    }
+}
+
+/// The sorted file list
+#[derive(Debug, Default)]
+struct FileList {
+    /// Entries in rsync's order; the wire refers to them by index
+    entries: Vec<Entry>,
+    /// I/O error flags from reading the source
+    io_error: i32,
+}
+
+impl FileList {
+    /// List `source` (a directory's contents, or a single file)
+    fn build(source: &Path, options: &SenderOptions) -> Result<Self> {
+        let metadata = std::fs::metadata(source)
+            .with_context(|| format!("Cannot read source {}", source.display()))?;
+        let mut list = Self::default();
+        if !metadata.is_dir() {
+            let name = source
+                .file_name()
+                .ok_or_else(|| anyhow::anyhow!("Source has no file name: {}", source.display()))?;
+            let mut entry = Entry::new(name.as_bytes().to_vec(), source.to_path_buf(), &metadata)?;
+            entry.top_dir = true;
+            list.entries.push(entry);
+            return Ok(list);
+        }
+
+        let mut root = Entry::new(b".".to_vec(), source.to_path_buf(), &metadata)?;
+        root.top_dir = true;
+        list.entries.push(root);
+        let mut pending = vec![(source.to_path_buf(), Vec::new())];
+        while let Some((dir, prefix)) = pending.pop() {
+            let read_dir = match std::fs::read_dir(&dir) {
+                Ok(read_dir) => read_dir,
+                Err(e) => {
+                    warn!("Cannot read directory {}: {e}", dir.display());
+                    list.io_error |= IOERR_GENERAL;
+                    continue;
+                }
+            };
+            for dir_entry in read_dir {
+                let dir_entry = match dir_entry {
+                    Ok(dir_entry) => dir_entry,
+                    Err(e) => {
+                        warn!("Cannot read directory {}: {e}", dir.display());
+                        list.io_error |= IOERR_GENERAL;
+                        break;
+                    }
+                };
+                let mut name = prefix.clone();
+                name.extend_from_slice(dir_entry.file_name().as_bytes());
+                let path = dir_entry.path();
+                let entry = match dir_entry
+                    .metadata()
+                    .and_then(|metadata| Entry::new(name, path.clone(), &metadata))
+                {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn!("Cannot read {}: {e}", path.display());
+                        list.io_error |= IOERR_GENERAL;
+                        continue;
+                    }
+                };
+                if (entry.link.is_some() && !options.links)
+                    || (entry.is_device() && !options.devices)
+                    || (entry.is_special() && !options.specials)
+                {
+                    info!("skipping non-regular file \"{}\"", path.display());
+                    continue;
+                }
+                let relative = Path::new(OsStr::from_bytes(&entry.name));
+                if options.filter.is_excluded(relative, entry.is_dir()) {
+                    continue;
+                }
+                if entry.is_dir() && options.recursive {
+                    let mut prefix = entry.name.clone();
+                    prefix.push(b'/');
+                    pending.push((path, prefix));
+                }
+                list.entries.push(entry);
+            }
+        }
+        list.entries
+            .sort_by(|a, b| compare_names(&a.name, a.is_dir(), &b.name, b.is_dir()));
+        Ok(list)
+    }
+}
+
+/// Previous entry's values, which later entries may refer to
+#[derive(Debug, Default)]
+struct FlistEncoder {
+    last_name: Vec<u8>,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+}
+
+impl FlistEncoder {
+    /// Append `entry` in protocol 30 format (`send_file_entry` in flist.c)
+    fn encode(
+        &mut self,
+        entry: &Entry,
+        options: &SenderOptions,
+        checksum: Option<&[u8; SUM_LENGTH]>,
+        out: &mut Vec<u8>,
+    ) {
+        let mut flags = 0u16;
+        if entry.top_dir && entry.is_dir() {
+            flags |= XMIT_TOP_DIR;
+        }
+        if entry.is_dir() && !entry.top_dir && !options.recursive {
+            flags |= XMIT_NO_CONTENT_DIR;
+        }
+        if entry.mode == self.mode {
+            flags |= XMIT_SAME_MODE;
+        }
+        let first = self.last_name.is_empty();
+        if !options.owner || (!first && entry.uid == self.uid) {
+            flags |= XMIT_SAME_UID;
+        }
+        if !options.group || (!first && entry.gid == self.gid) {
+            flags |= XMIT_SAME_GID;
+        }
+        if entry.mtime == self.mtime {
+            flags |= XMIT_SAME_TIME;
+        }
+        let shared = entry
+            .name
+            .iter()
+            .zip(&self.last_name)
+            .take(255)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = &entry.name[shared..];
+        if shared > 0 {
+            flags |= XMIT_SAME_NAME;
+        }
+        if suffix.len() > 255 {
+            flags |= XMIT_LONG_NAME;
+        }
+
+        // A zero flag byte would end the list; the top-dir bit means
+        // nothing on a non-directory
+        if flags == 0 && !entry.is_dir() {
+            flags |= XMIT_TOP_DIR;
+        }
+        if flags & 0xFF00 != 0 || flags == 0 {
+            put_shortint(out, flags | XMIT_EXTENDED_FLAGS);
+        } else {
+            out.push(flags as u8);
+        }
+        if flags & XMIT_SAME_NAME != 0 {
+            out.push(shared as u8);
+        }
+        if flags & XMIT_LONG_NAME != 0 {
+            put_varint(out, suffix.len() as i32);
+        } else {
+            out.push(suffix.len() as u8);
+        }
+        out.extend_from_slice(suffix);
+
+        put_varlong(out, entry.size as i64, 3);
+        if flags & XMIT_SAME_TIME == 0 {
+            put_varlong(out, entry.mtime, 4);
+        }
+        if flags & XMIT_SAME_MODE == 0 {
+            put_int(out, entry.mode as i32);
+        }
+        if options.owner && flags & XMIT_SAME_UID == 0 {
+            put_varint(out, entry.uid as i32);
+        }
+        if options.group && flags & XMIT_SAME_GID == 0 {
+            put_varint(out, entry.gid as i32);
+        }
+        if (options.devices && entry.is_device()) || (options.specials && entry.is_special()) {
+            let (major, minor) = if entry.is_device() {
+                split_dev(entry.rdev)
+            } else {
+                (0, 0)
+            };
+            put_varint(out, major as i32);
+            put_varint(out, minor as i32);
+        }
+        if let Some(target) = &entry.link {
+            put_varint(out, target.len() as i32);
+            out.extend_from_slice(target);
+        }
+        if options.checksum && entry.is_file() {
+            out.extend_from_slice(checksum.unwrap_or(&[0; SUM_LENGTH]));
+        }
+
+        self.last_name.clone_from(&entry.name);
+        self.mode = entry.mode;
+        self.uid = entry.uid;
+        self.gid = entry.gid;
+        self.mtime = entry.mtime;
+    }
+}
+
+/// Send the file list; returns I/O error flags from checksumming files
+async fn send_file_list<T: Transport>(
+    channel: &mut Channel<'_, T>,
+    list: &FileList,
+    options: &SenderOptions,
+) -> Result<i32> {
+    let mut encoder = FlistEncoder::default();
+    let mut io_error = 0;
+    for entry in &list.entries {
+        let checksum = if options.checksum && entry.is_file() {
+            match File::open(&entry.path).and_then(file_checksum) {
+                Ok(sum) => Some(sum),
+                Err(e) => {
+                    warn!("Cannot checksum {}: {e}", entry.path.display());
+                    io_error |= IOERR_GENERAL;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        encoder.encode(entry, options, checksum.as_ref(), &mut channel.out);
+        channel.flush_if_full().await?;
+    }
+    // End of list
+    channel.put_bytes(&[0]);
+    Ok(io_error)
+}
+
+// ============================================================================
+// Delta
+// ============================================================================
+
+/// Sliding view of a file, reading ahead and dropping what was sent
+struct Window<R> {
+    reader: R,
+    /// File data from `start`
+    buf: Vec<u8>,
+    /// File offset of `buf[0]`
+    start: u64,
+    /// First read error; data past it reads as zeros
+    error: Option<io::Error>,
+}
+
+/// Sent data kept before the window moves it out
+const WINDOW_DISCARD: usize = 1024 * 1024;
+
+impl<R: Read> Window<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            start: 0,
+            error: None,
+        }
+    }
+
+    /// File bytes `from..to`; `from` must not go backwards between calls
+    fn get(&mut self, from: u64, to: u64) -> &[u8] {
+        let consumed = (from - self.start) as usize;
+        if consumed >= WINDOW_DISCARD {
+            self.buf.drain(..consumed);
+            self.start = from;
+        }
+        while self.start + (self.buf.len() as u64) < to {
+            let filled = self.buf.len();
+            let wanted = (to - self.start) as usize - filled;
+            self.buf.resize(filled + wanted.max(READ_SIZE), 0);
+            let read = if self.error.is_some() {
+                Ok(0)
+            } else {
+                self.reader.read(&mut self.buf[filled..])
+            };
+            match read {
+                Ok(0) => {
+                    // Shrunk or unreadable: pad with zeros, like rsync's
+                    // map_ptr, and fail the whole-file checksum
+                    self.error.get_or_insert_with(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while sending")
+                    });
+                    self.buf.truncate(filled + wanted);
+                }
+                Ok(n) => self.buf.truncate(filled + n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(filled),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.buf.truncate(filled + wanted);
+                }
+            }
+        }
+        let begin = (from - self.start) as usize;
+        let end = (to - self.start) as usize;
+        &self.buf[begin..end]
+    }
+}
+
+/// Delta of a file against the receiver's block checksums (`match_sums`)
+///
+/// Emits rsync's uncompressed token stream: literal runs as a length and
+/// the bytes, matched blocks as `-(index + 1)`, then `0` and the file's MD5.
+/// With a deflater the tokens are compressed instead (`-z`).
+struct Matcher<'a, R> {
+    sums: &'a Sums,
+    deflater: Option<&'a mut TokenDeflater>,
+    /// Block indexes by rolling checksum
+    table: HashMap<u32, Vec<usize>>,
+    window: Window<R>,
+    seed: Seed,
+    /// File length
+    len: u64,
+    /// Block length
+    blength: u64,
+    /// Current search offset
+    offset: u64,
+    /// End of the data already sent
+    last_match: u64,
+    /// Search stops here (a full last block no longer fits)
+    end: u64,
+    /// Length of the rolling window at `offset`
+    k: u64,
+    s1: u32,
+    s2: u32,
+    /// MD5 of everything sent so far
+    file_sum: md5::Context,
+    /// Bytes sent as literals
+    literal_bytes: u64,
+    /// Bytes sent as block references
+    matched_bytes: u64,
+    finished: bool,
+}
+
+impl<'a, R: Read> Matcher<'a, R> {
+    fn new(
+        sums: &'a Sums,
+        reader: R,
+        len: u64,
+        seed: Seed,
+        deflater: Option<&'a mut TokenDeflater>,
+    ) -> Self {
+        let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, block) in sums.blocks.iter().enumerate() {
+            table.entry(block.sum1).or_default().push(i);
+        }
+        let blength = u64::try_from(sums.blength).unwrap_or(0);
+        let mut matcher = Self {
+            sums,
+            deflater,
+            table,
+            window: Window::new(reader),
+            seed,
+            len,
+            blength,
+            offset: 0,
+            last_match: 0,
+            end: 0,
+            k: 0,
+            s1: 0,
+            s2: 0,
+            file_sum: md5::Context::new(),
+            literal_bytes: 0,
+            matched_bytes: 0,
+            finished: false,
+        };
+        if let Some(last) = sums.blocks.last() {
+            if len > 0 && blength > 0 {
+                matcher.end = (len + 1).saturating_sub(last.len);
+                matcher.reset_window();
+            }
+        }
+        matcher
+    }
+
+    /// Read error hit while sending (the receiver will reject the file)
+    fn read_error(&self) -> Option<&io::Error> {
+        self.window.error.as_ref()
+    }
+
+    /// Append tokens to `out` until it holds `limit` bytes or the file is
+    /// done; returns whether it is done
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if compressing the data fails.
+    fn run(&mut self, out: &mut Vec<u8>, limit: usize) -> Result<bool> {
+        while self.offset < self.end {
+            if out.len() >= limit {
+                return Ok(false);
+            }
+            let sum = weak_checksum(self.s1, self.s2);
+            if let Some(i) = self.find_block(sum) {
+                self.send_match(out, i)?;
+            } else {
+                self.roll(out)?;
+            }
+        }
+        self.end = 0;
+
+        while self.last_match < self.len {
+            if out.len() >= limit {
+                return Ok(false);
+            }
+            let to = self.len.min(self.last_match + CHUNK_SIZE as u64);
+            self.send_literal(out, to)?;
+        }
+        if !self.finished {
+            match self.deflater.as_deref_mut() {
+                Some(deflater) => deflater.end(out)?,
+                None => put_int(out, 0),
+            }
+            let mut digest: [u8; SUM_LENGTH] =
+                std::mem::replace(&mut self.file_sum, md5::Context::new())
+                    .compute()
+                    .0;
+            if self.window.error.is_some() {
+                digest[0] = digest[0].wrapping_add(1);
+            }
+            out.extend_from_slice(&digest);
+            self.finished = true;
+        }
+        Ok(true)
+    }
+
+    /// Block matching the data at `offset`, if any
+    fn find_block(&mut self, sum: u32) -> Option<usize> {
+        let candidates = self.table.get(&sum)?.clone();
+        let len = self.blength.min(self.len - self.offset);
+        let s2length = usize::try_from(self.sums.s2length).unwrap_or(0);
+        let mut strong = None;
+        for i in candidates {
+            let block = &self.sums.blocks[i];
+            if block.len != len {
+                continue;
+            }
+            let sum2 = *strong.get_or_insert_with(|| {
+                let data = self.window.get(self.last_match, self.offset + len);
+                strong_sum(&data[(self.offset - self.last_match) as usize..], self.seed)
+            });
+            if sum2[..s2length] == block.sum2[..s2length] {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Send the pending literal and a reference to block `i` at `offset`
+    fn send_match(&mut self, out: &mut Vec<u8>, i: usize) -> Result<()> {
+        let len = self.sums.blocks[i].len;
+        self.send_literal(out, self.offset)?;
+        let data = self.window.get(self.offset, self.offset + len);
+        match self.deflater.as_deref_mut() {
+            Some(deflater) => deflater.matched(out, i as i32, data)?,
+            None => put_int(out, -(i as i32 + 1)),
+        }
+        self.file_sum.consume(data);
+        self.matched_bytes += len;
+        self.offset += len;
+        self.last_match = self.offset;
+        if self.offset < self.end {
+            self.reset_window();
+        }
+        Ok(())
+    }
+
+    /// Send `last_match..to` as literal runs
+    fn send_literal(&mut self, out: &mut Vec<u8>, to: u64) -> Result<()> {
+        while self.last_match < to {
+            let n = (to - self.last_match).min(CHUNK_SIZE as u64);
+            let data = self.window.get(self.last_match, self.last_match + n);
+            self.file_sum.consume(data);
+            match self.deflater.as_deref_mut() {
+                Some(deflater) => deflater.literal(out, data)?,
+                None => {
+                    put_int(out, n as i32);
+                    out.extend_from_slice(data);
+                }
+            }
+            self.literal_bytes += n;
+            self.last_match += n;
+        }
+        Ok(())
+    }
+
+    /// Checksum the block-sized window at `offset` from scratch
+    fn reset_window(&mut self) {
+        self.k = self.blength.min(self.len - self.offset);
+        let data = self.window.get(self.last_match, self.offset + self.k);
+        let (s1, s2) = weak_sums(&data[(self.offset - self.last_match) as usize..]);
+        self.s1 = s1;
+        self.s2 = s2;
+    }
+
+    /// Slide the window one byte forward
+    fn roll(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let more = self.offset + self.k < self.len;
+        let data = self
+            .window
+            .get(self.last_match, self.offset + self.k + u64::from(more));
+        let base = (self.offset - self.last_match) as usize;
+        let old = i32::from(data[base] as i8);
+        self.s1 = self.s1.wrapping_sub(old as u32);
+        self.s2 = self
+            .s2
+            .wrapping_sub((self.k as i32).wrapping_mul(old) as u32);
+        if more {
+            let new = i32::from(data[base + self.k as usize] as i8);
+            self.s1 = self.s1.wrapping_add(new as u32);
+            self.s2 = self.s2.wrapping_add(self.s1);
+        } else {
+            self.k -= 1;
+        }
+        // Send long unmatched stretches early so the window stays small
+        let backlog = self.offset - self.last_match;
+        if backlog >= self.blength + CHUNK_SIZE as u64 && self.end - self.offset > CHUNK_SIZE as u64
+        {
+            self.send_literal(out, self.offset - self.blength)?;
+        }
+        self.offset += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::protocol::rsync_wire::Block;
+
+    const NO_SEED: Seed = Seed {
+        value: 0,
+        first: false,
+    };
+
+    /// Block checksums of `basis` as a receiver would send them
+    fn sums_of(basis: &[u8], blength: usize, s2length: usize, seed: Seed) -> Sums {
+        let blocks: Vec<Block> = basis
+            .chunks(blength)
+            .map(|block| {
+                let (s1, s2) = weak_sums(block);
+                Block {
+                    sum1: weak_checksum(s1, s2),
+                    sum2: strong_sum(block, seed)[..s2length].to_vec(),
+                    len: block.len() as u64,
+                }
+            })
+            .collect();
+        Sums {
+            count: blocks.len() as i32,
+            blength: blength as i32,
+            s2length: s2length as i32,
+            remainder: (basis.len() % blength) as i32,
+            blocks,
+        }
+    }
+
+    /// Rebuild the file from `tokens`, as rsync's receive_data does
+    fn apply_tokens(tokens: &[u8], basis: &[u8], sums: &Sums) -> Vec<u8> {
+        let mut rest = tokens;
+        let mut file = Vec::new();
+        loop {
+            let token = i32::from_le_bytes(rest[..4].try_into().unwrap());
+            rest = &rest[4..];
+            match token {
+                0 => break,
+                n if n > 0 => {
+                    file.extend_from_slice(&rest[..n as usize]);
+                    rest = &rest[n as usize..];
+                }
+                n => {
+                    let i = (-n - 1) as usize;
+                    let start = i * sums.blength as usize;
+                    file.extend_from_slice(&basis[start..start + sums.blocks[i].len as usize]);
+                }
+            }
+        }
+        assert_eq!(rest, &md5::compute(&file).0[..], "whole-file checksum");
+        file
+    }
+
+    fn delta(source: &[u8], sums: &Sums, seed: Seed, limit: usize) -> (Vec<u8>, u64, u64) {
+        let mut matcher = Matcher::new(sums, source, source.len() as u64, seed, None);
+        let mut tokens = Vec::new();
+        let mut out = Vec::new();
+        while !matcher.run(&mut out, limit).unwrap() {
+            tokens.append(&mut out);
+        }
+        tokens.append(&mut out);
+        (tokens, matcher.literal_bytes, matcher.matched_bytes)
+    }
+
+    #[test]
+    fn test_delta_reconstructs_file() {
+        // Requirement: The token stream rebuilds the source from the
+        // receiver's blocks plus literals, reusing blocks that moved
+        let basis: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut source = b"inserted at the front".to_vec();
+        source.extend_from_slice(&basis[..9_000]);
+        source.extend_from_slice(&[0xAB; 3_333]);
+        source.extend_from_slice(&basis[9_000..]);
+        let seed = Seed {
+            value: 0x1234_5678,
+            first: false,
+        };
+        let sums = sums_of(&basis, 700, 2, seed);
+
+        let (tokens, literal, matched) = delta(&source, &sums, seed, FLUSH_SIZE);
+        assert_eq!(apply_tokens(&tokens, &basis, &sums), source);
+        assert_eq!(literal + matched, source.len() as u64);
+        assert!(matched >= 18_000, "matched only {matched} bytes");
+
+        // Small output limits only change how the stream is cut
+        let (chunked, _, _) = delta(&source, &sums, seed, 16);
+        assert_eq!(chunked, tokens);
+    }
+
+    #[test]
+    fn test_delta_without_basis() {
+        // Requirement: With no blocks (a new file) or an empty file, the
+        // data is sent as literal runs of at most CHUNK_SIZE bytes
+        let source: Vec<u8> = (0..100_000u32).map(|i| (i % 256) as u8).collect();
+        let sums = Sums::default();
+        let (tokens, literal, matched) = delta(&source, &sums, NO_SEED, FLUSH_SIZE);
+        assert_eq!(apply_tokens(&tokens, &[], &sums), source);
+        assert_eq!((literal, matched), (100_000, 0));
+        assert_eq!(
+            i32::from_le_bytes(tokens[..4].try_into().unwrap()),
+            CHUNK_SIZE as i32
+        );
+
+        let (tokens, _, _) = delta(&[], &sums, NO_SEED, FLUSH_SIZE);
+        assert_eq!(apply_tokens(&tokens, &[], &sums), b"");
+    }
+
+    #[test]
+    fn test_server_args() {
+        // Requirement: The server is started as a receiver with the letters
+        // rsync's client would pass for the same options
+        let options = SenderOptions {
+            recursive: true,
+            links: true,
+            perms: true,
+            times: true,
+            owner: true,
+            group: true,
+            devices: true,
+            specials: true,
+            delete: true,
+            ..SenderOptions::default()
+        };
+        assert_eq!(
+            options.server_args(Path::new("/backup/dst")),
+            [
+                "--server",
+                "-rlptogD",
+                "--numeric-ids",
+                "--delete",
+                ".",
+                "/backup/dst"
+            ]
+        );
+        let options = SenderOptions {
+            devices: true,
+            ..SenderOptions::default()
+        };
+        assert_eq!(
+            options.server_args(Path::new("dst")),
+            ["--server", "-d", "--devices", ".", "dst"]
+        );
+        let options = SenderOptions {
+            recursive: true,
+            compress: Some(CompressLevel::DEFAULT),
+            ..SenderOptions::default()
+        };
+        assert_eq!(
+            options.server_args(Path::new("dst")),
+            ["--server", "-rz", ".", "dst"]
+        );
+    }
+
+    #[test]
+    fn test_filter_rules_for_receiver() {
+        // Requirement: A deleting receiver gets the filter options as rsync
+        // rule strings
+        let rules = rsync_filter_rules(&[
+            FilterOption::Exclude("*.tmp".to_string()),
+            FilterOption::Include("+ keep/".to_string()),
+            FilterOption::Include("docs/**".to_string()),
+            FilterOption::Filter("- cache/".to_string()),
+            FilterOption::Exclude("!".to_string()),
+        ])
+        .unwrap();
+        assert_eq!(rules, ["- *.tmp", "+ keep/", "+ docs/**", "- cache/", "!"]);
+    }
+
+    #[test]
+    fn test_file_list_encoding() {
+        // Requirement: Entries are encoded like rsync's send_file_entry,
+        // sharing name prefixes, modes and times with the previous entry
+        let entry = |name: &str, mode: u32, mtime: i64| Entry {
+            name: name.as_bytes().to_vec(),
+            path: PathBuf::from(name),
+            mode,
+            size: 5,
+            mtime,
+            uid: 1000,
+            gid: 1000,
+            rdev: 0,
+            link: None,
+            top_dir: false,
+        };
+        let options = SenderOptions {
+            recursive: true,
+            ..SenderOptions::default()
+        };
+        let mut encoder = FlistEncoder::default();
+        let mut out = Vec::new();
+        encoder.encode(&entry("dir/a", 0o100_644, 100), &options, None, &mut out);
+        assert_eq!(
+            out,
+            [
+                &[XMIT_SAME_UID as u8 | XMIT_SAME_GID as u8, 5][..],
+                &b"dir/a"[..],
+                &[5, 0, 0][..],      // size
+                &[100, 0, 0, 0][..], // mtime
+                &0o100_644u32.to_le_bytes()[..],
+            ]
+            .concat()
+        );
+
+        out.clear();
+        encoder.encode(&entry("dir/b", 0o100_644, 100), &options, None, &mut out);
+        let flags =
+            XMIT_SAME_UID | XMIT_SAME_GID | XMIT_SAME_MODE | XMIT_SAME_TIME | XMIT_SAME_NAME;
+        assert_eq!(
+            out,
+            [&[flags as u8, 4, 1][..], &b"b"[..], &[5, 0, 0][..]].concat()
+        );
+    }
+}
@@ -0,0 +1,960 @@
+//! rsync wire protocol 30: framing, integers and checksums
+//!
+//! Shared by the sender (`rsync_sender`, pushing to `rsync --server`) and
+//! the receiver (`rsync_receiver`, run as `arsync --server` by an rsync
+//! client): the multiplexed connection, rsync's integer and file index
+//! encodings, its file list order, and the block checksums of the delta
+//! algorithm.
+//!
+//! The native protocol's `delta` and `varint` modules use a different weak
+//! checksum and integer encoding, so this module carries rsync's own.
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+#![allow(clippy::cast_possible_truncation)] // Wire integers are fixed-width
+#![allow(clippy::cast_possible_wrap)] // Wire integers are two's complement
+#![allow(clippy::cast_sign_loss)] // Wire integers are two's complement
+#![allow(clippy::doc_markdown)] // Protocol documentation
+
+use crate::protocol::transport::{self, Transport};
+use anyhow::{Context, Result};
+use compio::io::AsyncRead;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, Read};
+use tracing::{debug, error, info, warn};
+
+/// Protocol version we speak (rsync 3.0)
+pub(super) const PROTOCOL_VERSION: i32 = 30;
+
+/// Added to message codes in frame headers
+const MPLEX_BASE: u8 = 7;
+
+/// Largest frame payload we send (rsync's own I/O buffer size)
+const MAX_FRAME: usize = 32 * 1024;
+
+/// Pending output that triggers a flush to the transport
+pub(super) const FLUSH_SIZE: usize = 256 * 1024;
+
+/// Bytes requested from the transport per read
+pub(super) const READ_SIZE: usize = 64 * 1024;
+
+/// Message codes (`enum msgcode` in rsync.h)
+pub(super) mod msg {
+    /// Part of the data stream
+    pub const DATA: u8 = 0;
+    /// Error about one file
+    pub const ERROR_XFER: u8 = 1;
+    /// Informational text
+    pub const INFO: u8 = 2;
+    /// Error text
+    pub const ERROR: u8 = 3;
+    /// Warning text
+    pub const WARNING: u8 = 4;
+    /// Error relayed from a socket
+    pub const ERROR_SOCKET: u8 = 5;
+    /// Log text
+    pub const LOG: u8 = 6;
+    /// Text for the client only
+    pub const CLIENT: u8 = 7;
+    /// Error text that is not valid in the local charset
+    pub const ERROR_UTF8: u8 = 8;
+    /// The sending side had I/O errors (4-byte flags)
+    pub const IO_ERROR: u8 = 22;
+    /// Keep-alive
+    pub const NOOP: u8 = 42;
+    /// The peer is exiting with an error
+    pub const ERROR_EXIT: u8 = 86;
+    /// A file was updated (4-byte index)
+    pub const SUCCESS: u8 = 100;
+    /// The receiver deleted a file (its name)
+    pub const DELETED: u8 = 101;
+    /// The sender could not open a file (4-byte index)
+    pub const NO_SEND: u8 = 102;
+}
+
+/// Server compatibility flag: incremental recursion
+pub(super) const CF_INC_RECURSE: i32 = 1 << 0;
+/// Server compatibility flag: the checksum seed goes before the block data
+pub(super) const CF_CHKSUM_SEED_FIX: i32 = 1 << 5;
+/// Server compatibility flag: file list flags are varints
+pub(super) const CF_VARINT_FLIST_FLAGS: i32 = 1 << 7;
+
+/// File list flag: root of the transfer
+pub(super) const XMIT_TOP_DIR: u16 = 1 << 0;
+/// File list flag: same mode as the previous entry
+pub(super) const XMIT_SAME_MODE: u16 = 1 << 1;
+/// File list flag: flags are two bytes
+pub(super) const XMIT_EXTENDED_FLAGS: u16 = 1 << 2;
+/// File list flag: same uid as the previous entry
+pub(super) const XMIT_SAME_UID: u16 = 1 << 3;
+/// File list flag: same gid as the previous entry
+pub(super) const XMIT_SAME_GID: u16 = 1 << 4;
+/// File list flag: name shares a prefix with the previous entry's
+pub(super) const XMIT_SAME_NAME: u16 = 1 << 5;
+/// File list flag: name suffix is longer than 255 bytes
+pub(super) const XMIT_LONG_NAME: u16 = 1 << 6;
+/// File list flag: same mtime as the previous entry
+pub(super) const XMIT_SAME_TIME: u16 = 1 << 7;
+/// File list flag: a directory whose contents are not sent (`-d`)
+pub(super) const XMIT_NO_CONTENT_DIR: u16 = 1 << 8;
+/// File list flag: same device major number as the previous device
+pub(super) const XMIT_SAME_RDEV_MAJOR: u16 = 1 << 8;
+/// File list flag: hard-linked to another entry (`-H`)
+pub(super) const XMIT_HLINKED: u16 = 1 << 9;
+/// File list flag: the owner's name follows (incremental recursion)
+pub(super) const XMIT_USER_NAME_FOLLOWS: u16 = 1 << 10;
+/// File list flag: the group's name follows (incremental recursion)
+pub(super) const XMIT_GROUP_NAME_FOLLOWS: u16 = 1 << 11;
+
+/// Item flag: the size differs (regular files)
+pub(super) const ITEM_REPORT_SIZE: u16 = 1 << 2;
+/// Item flag: the modification time differs
+pub(super) const ITEM_REPORT_TIME: u16 = 1 << 3;
+/// Item flag: the basis file type follows
+pub(super) const ITEM_BASIS_TYPE_FOLLOWS: u16 = 1 << 11;
+/// Item flag: an alternate name follows
+pub(super) const ITEM_XNAME_FOLLOWS: u16 = 1 << 12;
+/// Item flag: the file does not exist on the receiver
+pub(super) const ITEM_IS_NEW: u16 = 1 << 13;
+/// Item flag: the file's data is wanted
+pub(super) const ITEM_TRANSFER: u16 = 1 << 15;
+
+/// File index marking the end of a phase
+pub(super) const NDX_DONE: i32 = -1;
+
+/// Transfer phases after the first (the redo phase, then the final one)
+pub(super) const MAX_PHASE: u32 = 2;
+
+/// Largest literal run per token, and the granularity of early literals
+pub(super) const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Largest block size a protocol 30 receiver may ask for
+pub(super) const MAX_BLOCK_SIZE: i32 = 1 << 17;
+
+/// Length of an MD5 checksum
+pub(super) const SUM_LENGTH: usize = 16;
+
+/// I/O error flag: some file could not be read
+pub(super) const IOERR_GENERAL: i32 = 1 << 0;
+/// I/O error flag: some file vanished
+pub(super) const IOERR_VANISHED: i32 = 1 << 1;
+
+// ============================================================================
+// File list order
+// ============================================================================
+
+/// rsync's file list order (`f_name_cmp`, protocol 29 and later)
+///
+/// Names are compared component by component. Within a directory, files
+/// sort before subdirectories, and a directory's name compares as if it
+/// ended in `/`; a directory comes right before its contents. The root `.`
+/// comes first.
+pub(super) fn compare_names(a: &[u8], a_dir: bool, b: &[u8], b_dir: bool) -> Ordering {
+    /// Whether a component is the last one of a non-directory
+    fn is_item(index: usize, count: usize, dir: bool) -> bool {
+        index + 1 == count && !dir
+    }
+    let root = |name: &[u8]| name == b".";
+    match (root(a), root(b)) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        (false, false) => {}
+    }
+    let a_parts: Vec<&[u8]> = a.split(|&c| c == b'/').collect();
+    let b_parts: Vec<&[u8]> = b.split(|&c| c == b'/').collect();
+    for i in 0..a_parts.len().max(b_parts.len()) {
+        let (Some(x), Some(y)) = (a_parts.get(i), b_parts.get(i)) else {
+            return a_parts.len().cmp(&b_parts.len());
+        };
+        let x_item = is_item(i, a_parts.len(), a_dir);
+        let y_item = is_item(i, b_parts.len(), b_dir);
+        if x_item != y_item {
+            return if x_item {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+        }
+        let order = if x_item {
+            x.cmp(y)
+        } else {
+            x.iter().chain(b"/").cmp(y.iter().chain(b"/"))
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Major and minor numbers of a Linux `dev_t`
+pub(super) const fn split_dev(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 32) & 0xFFFF_F000) | ((dev >> 8) & 0xFFF);
+    let minor = ((dev >> 12) & 0xFFFF_FF00) | (dev & 0xFF);
+    (major as u32, minor as u32)
+}
+
+/// Linux `dev_t` from major and minor numbers (the inverse of `split_dev`)
+pub(super) const fn join_dev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xFFFF_F000) << 32)
+        | ((major & 0xFFF) << 8)
+        | ((minor & 0xFFFF_FF00) << 12)
+        | (minor & 0xFF)
+}
+
+// ============================================================================
+// Checksums
+// ============================================================================
+
+/// The session's checksum seed and where it goes in block checksums
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Seed {
+    pub(super) value: u32,
+    /// Hash the seed before the data (`CF_CHKSUM_SEED_FIX`), not after
+    pub(super) first: bool,
+}
+
+/// Block checksums of the receiver's copy of a file
+#[derive(Debug, Default)]
+pub(super) struct Sums {
+    /// Number of blocks
+    pub(super) count: i32,
+    /// Block length (all but the last block)
+    pub(super) blength: i32,
+    /// Bytes of each strong checksum sent
+    pub(super) s2length: i32,
+    /// Length of the last block, if shorter
+    pub(super) remainder: i32,
+    /// The blocks, in file order
+    pub(super) blocks: Vec<Block>,
+}
+
+/// Checksums of one block
+#[derive(Debug, Clone)]
+pub(super) struct Block {
+    /// Rolling checksum
+    pub(super) sum1: u32,
+    /// Leading `s2length` bytes of the strong checksum
+    pub(super) sum2: Vec<u8>,
+    /// Block length
+    pub(super) len: u64,
+}
+
+/// rsync's rolling checksum of `data` (`get_checksum1`) as `(s1, s2)`
+///
+/// Bytes are signed (`schar`), as in rsync.
+pub(super) fn weak_sums(data: &[u8]) -> (u32, u32) {
+    let mut s1 = 0u32;
+    let mut s2 = 0u32;
+    for &byte in data {
+        s1 = s1.wrapping_add(i32::from(byte as i8) as u32);
+        s2 = s2.wrapping_add(s1);
+    }
+    (s1, s2)
+}
+
+/// Combined 32-bit rolling checksum from its halves
+pub(super) const fn weak_checksum(s1: u32, s2: u32) -> u32 {
+    (s1 & 0xFFFF) | (s2 << 16)
+}
+
+/// MD5 block checksum with the session seed (`get_checksum2`)
+pub(super) fn strong_sum(data: &[u8], seed: Seed) -> [u8; SUM_LENGTH] {
+    let mut context = md5::Context::new();
+    let seed_bytes = seed.value.to_le_bytes();
+    if seed.first && seed.value != 0 {
+        context.consume(seed_bytes);
+    }
+    context.consume(data);
+    if !seed.first && seed.value != 0 {
+        context.consume(seed_bytes);
+    }
+    context.compute().0
+}
+
+/// MD5 of a file's contents, as sent in the file list with `-c`
+pub(super) fn file_checksum(mut file: File) -> io::Result<[u8; SUM_LENGTH]> {
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(context.compute().0);
+        }
+        context.consume(&buffer[..n]);
+    }
+}
+
+// ============================================================================
+// Wire encoding
+// ============================================================================
+
+/// Append a 4-byte little-endian integer
+pub(super) fn put_int(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append a 2-byte little-endian integer
+pub(super) fn put_shortint(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Append rsync's variable-length integer (`write_varint`)
+///
+/// The leading byte's high bits count the extra bytes that follow; its
+/// remaining bits hold the most significant part of the value.
+pub(super) fn put_varint(out: &mut Vec<u8>, value: i32) {
+    put_var(out, &value.to_le_bytes(), 1);
+}
+
+/// Append rsync's variable-length 64-bit integer (`write_varlong`), at least
+/// `min_bytes` long
+pub(super) fn put_varlong(out: &mut Vec<u8>, value: i64, min_bytes: usize) {
+    put_var(out, &value.to_le_bytes(), min_bytes);
+}
+
+/// Shared encoding of `write_varint` and `write_varlong`
+fn put_var(out: &mut Vec<u8>, bytes: &[u8], min_bytes: usize) {
+    // Significant bytes, at least min_bytes
+    let mut cnt = bytes.len();
+    while cnt > min_bytes && bytes[cnt - 1] == 0 {
+        cnt -= 1;
+    }
+    let bit = 1u8 << (7 + min_bytes - cnt);
+    let top = bytes[cnt - 1];
+    if top >= bit {
+        // No room for the top byte: a full lead byte of count bits
+        out.push(!(bit - 1));
+        out.extend_from_slice(&bytes[..cnt]);
+    } else if cnt > min_bytes {
+        out.push(top | !(bit * 2 - 1));
+        out.extend_from_slice(&bytes[..cnt - 1]);
+    } else {
+        out.push(top);
+        out.extend_from_slice(&bytes[..cnt - 1]);
+    }
+}
+
+/// Number of bytes following a varint's lead byte (`int_byte_extra`)
+const fn var_extra(lead: u8) -> usize {
+    let ones = lead.leading_ones() as usize;
+    if ones > 6 {
+        6
+    } else {
+        ones
+    }
+}
+
+/// Decode a varint or varlong from its bytes (lead byte first), given the
+/// encoding's `min_bytes`
+fn decode_var(bytes: &[u8], min_bytes: usize) -> i64 {
+    let lead = bytes[0];
+    let extra = var_extra(lead);
+    let mut value = [0u8; 9];
+    value[..min_bytes - 1].copy_from_slice(&bytes[1..min_bytes]);
+    if extra > 0 {
+        let bit = 1u8 << (8 - extra);
+        value[min_bytes - 1..min_bytes - 1 + extra]
+            .copy_from_slice(&bytes[min_bytes..min_bytes + extra]);
+        value[min_bytes - 1 + extra] = lead & (bit - 1);
+    } else {
+        value[min_bytes - 1] = lead;
+    }
+    i64::from_le_bytes([
+        value[0], value[1], value[2], value[3], value[4], value[5], value[6], value[7],
+    ])
+}
+
+/// State of rsync's file index compression (`write_ndx`/`read_ndx`)
+///
+/// Indexes travel as the difference from the previous one of the same
+/// sign, usually a single byte.
+#[derive(Debug, Clone, Copy)]
+struct NdxState {
+    prev_positive: i32,
+    prev_negative: i32,
+}
+
+impl Default for NdxState {
+    fn default() -> Self {
+        Self {
+            prev_positive: -1,
+            prev_negative: 1,
+        }
+    }
+}
+
+impl NdxState {
+    /// Append `ndx`
+    fn encode(&mut self, ndx: i32, out: &mut Vec<u8>) {
+        if ndx == NDX_DONE {
+            out.push(0);
+            return;
+        }
+        let (ndx, diff) = if ndx >= 0 {
+            let diff = ndx.wrapping_sub(self.prev_positive);
+            self.prev_positive = ndx;
+            (ndx, diff)
+        } else {
+            out.push(0xFF);
+            let ndx = ndx.wrapping_neg();
+            let diff = ndx.wrapping_sub(self.prev_negative);
+            self.prev_negative = ndx;
+            (ndx, diff)
+        };
+        if diff > 0 && diff < 0xFE {
+            out.push(diff as u8);
+        } else if !(0..=0x7FFF).contains(&diff) {
+            out.extend_from_slice(&[
+                0xFE,
+                ((ndx >> 24) as u8) | 0x80,
+                ndx as u8,
+                (ndx >> 8) as u8,
+                (ndx >> 16) as u8,
+            ]);
+        } else {
+            out.extend_from_slice(&[0xFE, (diff >> 8) as u8, diff as u8]);
+        }
+    }
+
+    /// Length of the encoded index starting `bytes`, if enough are there
+    fn wire_len(bytes: &[u8]) -> Option<usize> {
+        let lead = *bytes.first()?;
+        if lead == 0 {
+            return Some(1);
+        }
+        let base = usize::from(lead == 0xFF);
+        if *bytes.get(base)? != 0xFE {
+            return Some(base + 1);
+        }
+        let high = *bytes.get(base + 1)?;
+        Some(if high & 0x80 != 0 { base + 5 } else { base + 3 })
+    }
+
+    /// Decode an index of `wire_len` bytes
+    fn decode(&mut self, bytes: &[u8]) -> i32 {
+        if bytes[0] == 0 {
+            return NDX_DONE;
+        }
+        let negative = bytes[0] == 0xFF;
+        let bytes = if negative { &bytes[1..] } else { bytes };
+        let prev = if negative {
+            &mut self.prev_negative
+        } else {
+            &mut self.prev_positive
+        };
+        let num = if bytes[0] != 0xFE {
+            i32::from(bytes[0]).wrapping_add(*prev)
+        } else if bytes[1] & 0x80 != 0 {
+            i32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[1] & 0x7F])
+        } else {
+            (i32::from(bytes[1]) << 8 | i32::from(bytes[2])).wrapping_add(*prev)
+        };
+        *prev = num;
+        if negative {
+            -num
+        } else {
+            num
+        }
+    }
+}
+
+// ============================================================================
+// Connection
+// ============================================================================
+
+/// Item flags and extras that accompany a file index
+#[derive(Debug, Clone, Default)]
+pub(super) struct ItemAttrs {
+    pub(super) iflags: u16,
+    pub(super) basis_type: u8,
+    pub(super) xname: Vec<u8>,
+}
+
+/// Buffered, multiplexed connection to the peer
+pub(super) struct Channel<'a, T> {
+    transport: &'a mut T,
+    /// The other end, for error messages ("rsync server", "rsync client")
+    peer: &'static str,
+    /// Whether both directions are framed (after the handshake)
+    pub(super) multiplexed: bool,
+    /// Bytes read from the transport and not yet parsed
+    raw: Vec<u8>,
+    /// Data stream bytes not yet consumed
+    data: Vec<u8>,
+    /// Consumed prefix of `data`
+    data_pos: usize,
+    /// Data stream bytes waiting to be sent
+    pub(super) out: Vec<u8>,
+    /// Reusable read buffer
+    scratch: Vec<u8>,
+    read_ndx: NdxState,
+    write_ndx: NdxState,
+    /// I/O error flags reported by the peer
+    pub(super) remote_io_error: i32,
+    /// Indexes of files the sender could not open (`MSG_NO_SEND`)
+    no_send: Vec<i32>,
+    pub(super) bytes_sent: u64,
+    pub(super) bytes_received: u64,
+}
+
+impl<'a, T: Transport> Channel<'a, T> {
+    pub(super) fn new(transport: &'a mut T, peer: &'static str) -> Self {
+        Self {
+            transport,
+            peer,
+            multiplexed: false,
+            raw: Vec::new(),
+            data: Vec::new(),
+            data_pos: 0,
+            out: Vec::new(),
+            scratch: Vec::with_capacity(READ_SIZE),
+            read_ndx: NdxState::default(),
+            write_ndx: NdxState::default(),
+            remote_io_error: 0,
+            no_send: Vec::new(),
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    // --- writing ---
+
+    pub(super) fn put_int(&mut self, value: i32) {
+        put_int(&mut self.out, value);
+    }
+
+    pub(super) fn put_bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub(super) fn put_ndx(&mut self, ndx: i32) {
+        self.write_ndx.encode(ndx, &mut self.out);
+    }
+
+    /// Echo an index with its item flags (`write_ndx_and_attrs`)
+    pub(super) fn put_ndx_and_attrs(&mut self, ndx: i32, attrs: &ItemAttrs) {
+        self.put_ndx(ndx);
+        put_shortint(&mut self.out, attrs.iflags);
+        if attrs.iflags & ITEM_BASIS_TYPE_FOLLOWS != 0 {
+            self.out.push(attrs.basis_type);
+        }
+        if attrs.iflags & ITEM_XNAME_FOLLOWS != 0 {
+            let len = attrs.xname.len();
+            if len > 0x7F {
+                self.out.push((len / 0x100 + 0x80) as u8);
+            }
+            self.out.push(len as u8);
+            self.out.extend_from_slice(&attrs.xname);
+        }
+    }
+
+    /// Echo the receiver's block checksum header
+    pub(super) fn put_sum_head(&mut self, sums: &Sums) {
+        for value in [sums.count, sums.blength, sums.s2length, sums.remainder] {
+            self.put_int(value);
+        }
+    }
+
+    /// Send pending data
+    pub(super) async fn flush(&mut self) -> Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let framed = if self.multiplexed {
+            let mut framed =
+                Vec::with_capacity(self.out.len() + 4 * self.out.len().div_ceil(MAX_FRAME));
+            for chunk in self.out.chunks(MAX_FRAME) {
+                framed.extend_from_slice(&frame_header(msg::DATA, chunk.len()));
+                framed.extend_from_slice(chunk);
+            }
+            self.out.clear();
+            framed
+        } else {
+            std::mem::take(&mut self.out)
+        };
+        self.bytes_sent += framed.len() as u64;
+        let peer = self.peer;
+        transport::write_all(&mut *self.transport, &framed)
+            .await
+            .with_context(|| format!("Failed to write to {peer}"))
+    }
+
+    /// Flush once enough data is pending
+    pub(super) async fn flush_if_full(&mut self) -> Result<()> {
+        if self.out.len() >= FLUSH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Send a message other than data, after the pending data
+    pub(super) async fn send_msg(&mut self, code: u8, payload: &[u8]) -> Result<()> {
+        self.flush().await?;
+        let mut frame = frame_header(code, payload.len()).to_vec();
+        frame.extend_from_slice(payload);
+        self.bytes_sent += frame.len() as u64;
+        let peer = self.peer;
+        transport::write_all(&mut *self.transport, &frame)
+            .await
+            .with_context(|| format!("Failed to write to {peer}"))
+    }
+
+    // --- reading ---
+
+    /// Read more bytes from the transport, sending pending output first so
+    /// the peer is never left waiting for it
+    async fn fill(&mut self) -> Result<()> {
+        self.flush().await?;
+        let mut buf = std::mem::take(&mut self.scratch);
+        buf.clear();
+        let compio::buf::BufResult(result, buf) = self.transport.read(buf).await;
+        let n = result.with_context(|| format!("Failed to read from {}", self.peer))?;
+        if n == 0 {
+            anyhow::bail!("Connection closed by {}", self.peer);
+        }
+        self.raw.extend_from_slice(&buf[..n]);
+        self.scratch = buf;
+        self.bytes_received += n as u64;
+        Ok(())
+    }
+
+    /// Make at least `n` data bytes available
+    async fn need(&mut self, n: usize) -> Result<()> {
+        if self.data_pos > 0 && self.data_pos == self.data.len() {
+            self.data.clear();
+            self.data_pos = 0;
+        }
+        while self.data.len() - self.data_pos < n {
+            if self.multiplexed {
+                self.read_frame().await?;
+            } else {
+                if self.raw.is_empty() {
+                    self.fill().await?;
+                }
+                // Only what was asked for: framed data may follow
+                let take = self.raw.len().min(n - (self.data.len() - self.data_pos));
+                self.data.extend(self.raw.drain(..take));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read one frame and handle it
+    async fn read_frame(&mut self) -> Result<()> {
+        while self.raw.len() < 4 {
+            self.fill().await?;
+        }
+        let header = u32::from_le_bytes([self.raw[0], self.raw[1], self.raw[2], self.raw[3]]);
+        let tag = (header >> 24) as u8;
+        let len = (header & 0x00FF_FFFF) as usize;
+        if tag < MPLEX_BASE {
+            anyhow::bail!(
+                "Unexpected data from {} (tag {tag}) -- is your shell clean?",
+                self.peer
+            );
+        }
+        while self.raw.len() < 4 + len {
+            self.fill().await?;
+        }
+        let payload: Vec<u8> = self.raw.drain(..4 + len).skip(4).collect();
+        self.handle_message(tag - MPLEX_BASE, payload)
+    }
+
+    /// Act on a message from the peer
+    fn handle_message(&mut self, code: u8, payload: Vec<u8>) -> Result<()> {
+        let text = |payload: &[u8]| String::from_utf8_lossy(payload).trim_end().to_string();
+        match code {
+            msg::DATA => {
+                if self.data_pos == self.data.len() {
+                    self.data = payload;
+                    self.data_pos = 0;
+                } else {
+                    self.data.extend_from_slice(&payload);
+                }
+            }
+            msg::INFO | msg::LOG | msg::CLIENT => info!("rsync: {}", text(&payload)),
+            msg::WARNING => warn!("rsync: {}", text(&payload)),
+            msg::ERROR | msg::ERROR_XFER | msg::ERROR_SOCKET | msg::ERROR_UTF8 => {
+                error!("rsync: {}", text(&payload));
+            }
+            msg::ERROR_EXIT => anyhow::bail!("{} exited with an error", self.peer),
+            msg::IO_ERROR => {
+                let flags = payload.get(..4).map_or(IOERR_GENERAL, |b| {
+                    i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+                });
+                self.remote_io_error |= flags;
+            }
+            msg::DELETED => {
+                // Directories carry a trailing NUL
+                let name = String::from_utf8_lossy(&payload);
+                match name.strip_suffix('\0') {
+                    Some(dir) => info!("deleting {dir}/"),
+                    None => info!("deleting {name}"),
+                }
+            }
+            msg::NO_SEND => {
+                let ndx = payload.get(..4).ok_or_else(|| {
+                    anyhow::anyhow!("Truncated NO_SEND message from {}", self.peer)
+                })?;
+                self.no_send
+                    .push(i32::from_le_bytes([ndx[0], ndx[1], ndx[2], ndx[3]]));
+            }
+            msg::NOOP | msg::SUCCESS => debug!("rsync message {code}"),
+            _ => anyhow::bail!("Unknown message {code} from {}", self.peer),
+        }
+        Ok(())
+    }
+
+    /// Take `n` data bytes
+    pub(super) async fn read_bytes(&mut self, n: usize) -> Result<&[u8]> {
+        self.need(n).await?;
+        let start = self.data_pos;
+        self.data_pos += n;
+        Ok(&self.data[start..start + n])
+    }
+
+    pub(super) async fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1).await?[0])
+    }
+
+    pub(super) async fn read_shortint(&mut self) -> Result<u16> {
+        let b = self.read_bytes(2).await?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(super) async fn read_int(&mut self) -> Result<i32> {
+        let b = self.read_bytes(4).await?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(super) async fn read_varint(&mut self) -> Result<i32> {
+        self.need(1).await?;
+        let extra = var_extra(self.data[self.data_pos]);
+        if extra > 4 {
+            anyhow::bail!("Overflow in varint from {}", self.peer);
+        }
+        let bytes = self.read_bytes(1 + extra).await?;
+        Ok(decode_var(bytes, 1) as i32)
+    }
+
+    /// Read a varlong of at least `min_bytes` (`read_varlong`)
+    pub(super) async fn read_varlong(&mut self, min_bytes: usize) -> Result<i64> {
+        self.need(1).await?;
+        let extra = var_extra(self.data[self.data_pos]);
+        if min_bytes + extra > 8 {
+            anyhow::bail!("Overflow in varlong from {}", self.peer);
+        }
+        let bytes = self.read_bytes(min_bytes + extra).await?;
+        Ok(decode_var(bytes, min_bytes))
+    }
+
+    /// Wait for the sender's answer to a request for file `ndx`: `true` once
+    /// data arrives, `false` if it reported that it cannot send the file
+    pub(super) async fn wait_for_file(&mut self, ndx: i32) -> Result<bool> {
+        loop {
+            if let Some(pos) = self.no_send.iter().position(|&n| n == ndx) {
+                self.no_send.swap_remove(pos);
+                return Ok(false);
+            }
+            if self.data_pos < self.data.len() {
+                return Ok(true);
+            }
+            self.read_frame().await?;
+        }
+    }
+
+    pub(super) async fn read_ndx(&mut self) -> Result<i32> {
+        let mut available = 1;
+        let len = loop {
+            self.need(available).await?;
+            match NdxState::wire_len(&self.data[self.data_pos..]) {
+                Some(len) => break len,
+                None => available = self.data.len() - self.data_pos + 1,
+            }
+        };
+        self.need(len).await?;
+        let start = self.data_pos;
+        self.data_pos += len;
+        Ok(self.read_ndx.decode(&self.data[start..start + len]))
+    }
+
+    /// Item flags and extras after a file index (`read_ndx_and_attrs`)
+    pub(super) async fn read_item_attrs(&mut self) -> Result<ItemAttrs> {
+        let iflags = self.read_shortint().await?;
+        let basis_type = if iflags & ITEM_BASIS_TYPE_FOLLOWS != 0 {
+            self.read_u8().await?
+        } else {
+            0
+        };
+        let xname = if iflags & ITEM_XNAME_FOLLOWS != 0 {
+            let mut len = usize::from(self.read_u8().await?);
+            if len & 0x80 != 0 {
+                len = (len & 0x7F) * 0x100 + usize::from(self.read_u8().await?);
+            }
+            self.read_bytes(len).await?.to_vec()
+        } else {
+            Vec::new()
+        };
+        Ok(ItemAttrs {
+            iflags,
+            basis_type,
+            xname,
+        })
+    }
+
+    /// The receiver's block checksums (`receive_sums`)
+    pub(super) async fn read_sums(&mut self) -> Result<Sums> {
+        let count = self.read_int().await?;
+        let blength = self.read_int().await?;
+        let s2length = self.read_int().await?;
+        let remainder = self.read_int().await?;
+        if count < 0
+            || !(0..=MAX_BLOCK_SIZE).contains(&blength)
+            || !(0..=SUM_LENGTH as i32).contains(&s2length)
+            || !(0..=blength).contains(&remainder)
+        {
+            anyhow::bail!(
+                "Invalid checksum header from {} (count {count}, block {blength}, sum {s2length}, remainder {remainder})",
+                self.peer
+            );
+        }
+        let mut blocks = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let sum1 = self.read_int().await? as u32;
+            let sum2 = self.read_bytes(s2length as usize).await?.to_vec();
+            let len = if i == count - 1 && remainder != 0 {
+                remainder
+            } else {
+                blength
+            };
+            blocks.push(Block {
+                sum1,
+                sum2,
+                len: len as u64,
+            });
+        }
+        Ok(Sums {
+            count,
+            blength,
+            s2length,
+            remainder,
+            blocks,
+        })
+    }
+}
+
+/// Frame header: length in the low 24 bits, code plus `MPLEX_BASE` above
+fn frame_header(code: u8, len: usize) -> [u8; 4] {
+    ((u32::from(MPLEX_BASE + code) << 24) | len as u32).to_le_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_varint_encoding_matches_rsync() {
+        // Requirement: Integers are encoded byte for byte like rsync's
+        // write_varint/write_varlong, and decode back
+        let varint = |value| {
+            let mut out = Vec::new();
+            put_varint(&mut out, value);
+            out
+        };
+        assert_eq!(varint(0), [0x00]);
+        assert_eq!(varint(0x7F), [0x7F]);
+        assert_eq!(varint(0x80), [0x80, 0x80]);
+        assert_eq!(varint(0x3FFF), [0xBF, 0xFF]);
+        assert_eq!(varint(0x4000), [0xC0, 0x00, 0x40]);
+        assert_eq!(varint(-1), [0xF0, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let varlong = |value, min_bytes| {
+            let mut out = Vec::new();
+            put_varlong(&mut out, value, min_bytes);
+            out
+        };
+        // 2023-11-14T22:13:20Z as an mtime
+        assert_eq!(varlong(1_700_000_000, 4), [0x65, 0x00, 0xF1, 0x53]);
+        assert_eq!(varlong(0, 3), [0x00, 0x00, 0x00]);
+        assert_eq!(varlong(1 << 24, 3), [0x81, 0x00, 0x00]);
+
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, i32::MAX, -1, i32::MIN] {
+            let bytes = varint(value);
+            assert_eq!(bytes.len(), 1 + var_extra(bytes[0]), "{value}");
+            assert_eq!(decode_var(&bytes, 1) as i32, value, "{value}");
+        }
+        for min_bytes in [3, 4] {
+            for value in [0, 1 << 24, 1 << 40, -1, -2_208_988_800, i64::MAX, i64::MIN] {
+                let bytes = varlong(value, min_bytes);
+                assert_eq!(bytes.len(), min_bytes + var_extra(bytes[0]), "{value}");
+                assert_eq!(decode_var(&bytes, min_bytes), value, "{value}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_ndx_round_trip() {
+        // Requirement: File indexes use rsync's delta encoding (one byte for
+        // the next index, 0 for NDX_DONE) and decode back
+        let mut writer = NdxState::default();
+        let mut out = Vec::new();
+        writer.encode(0, &mut out);
+        writer.encode(1, &mut out);
+        writer.encode(NDX_DONE, &mut out);
+        assert_eq!(out, [0x01, 0x01, 0x00]);
+
+        let indexes = [0, 1, 5, 300, 40_000, 2, 1 << 30, NDX_DONE, -2, -3, -500];
+        let mut writer = NdxState::default();
+        let mut out = Vec::new();
+        for ndx in indexes {
+            writer.encode(ndx, &mut out);
+        }
+        let mut reader = NdxState::default();
+        let mut rest = &out[..];
+        for ndx in indexes {
+            let len = NdxState::wire_len(rest).unwrap();
+            assert_eq!(reader.decode(&rest[..len]), ndx);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(NdxState::wire_len(&[0xFE]), None);
+    }
+
+    #[test]
+    fn test_file_list_order_matches_rsync() {
+        // Requirement: Entries sort like rsync's f_name_cmp, so both sides
+        // agree on file indexes: the root first, files before subdirectories,
+        // a directory right before its contents
+        let mut names = vec![
+            ("b/y", false),
+            ("b-c", true),
+            ("a.txt", false),
+            ("b", true),
+            ("b/x", true),
+            ("b/x/z", false),
+            ("c", false),
+            (".", true),
+            ("b/y2", false),
+        ];
+        names.sort_by(|a, b| compare_names(a.0.as_bytes(), a.1, b.0.as_bytes(), b.1));
+        let sorted: Vec<&str> = names.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            sorted,
+            [".", "a.txt", "c", "b-c", "b", "b/y", "b/y2", "b/x", "b/x/z"]
+        );
+    }
+
+    #[test]
+    fn test_weak_checksum_uses_signed_bytes() {
+        // Requirement: The rolling checksum treats bytes as signed, like
+        // rsync's get_checksum1
+        let (s1, s2) = weak_sums(b"abc");
+        assert_eq!(weak_checksum(s1, s2), 294 | (586 << 16));
+        let (s1, s2) = weak_sums(&[0x01, 0xFF]);
+        assert_eq!(weak_checksum(s1, s2), 1 << 16);
+    }
+}
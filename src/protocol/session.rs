@@ -0,0 +1,569 @@
+//! Resumable transfer sessions for the native arsync protocol
+//!
+//! A dropped connection used to restart the whole transfer. With sessions, the
+//! receiver checkpoints its progress in the destination and the sender can
+//! reconnect with the session token to continue where the receiver left off.
+//!
+//! # Protocol
+//!
+//! After the file list has been exchanged:
+//!
+//! ```text
+//! Sender                                 Receiver
+//!   |-- 0x00 (new) | 0x01 + token[16] --->|
+//!   |<-- token[16] + file_index + offset --|
+//! ```
+//!
+//! Both sides then skip `file_index` entries of the file list. The file at
+//! `file_index` continues from `offset`; all later files start from zero.
+//!
+//! # Checkpoints
+//!
+//! The receiver writes file contents to a `.arsync.tmp.<runid>.<name>` partial
+//! file (see `crate::temp_files`), renames it into place when complete, and
+//! records `(token, file_index, offset, partial)` in `DEST/.arsync-session`.
+//! The checkpoint is replaced atomically (temp file + rename) and only ever
+//! advances past data that has been written, so it never claims more progress
+//! than the destination holds. With `--fsync` the data is synced to disk
+//! before each checkpoint.
+//!
+//! Files completed before the interruption are not re-checked on resume. The
+//! file at the resume point is validated by path, size and mtime against the
+//! new file list; on mismatch (or a missing partial file, e.g. after
+//! `arsync cleanup`) the session restarts from the beginning.
+#![allow(dead_code)] // Protocol implementation not yet fully used
+
+use crate::protocol::rsync::FileEntry;
+use crate::protocol::transport::{self, Transport};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Checkpoint filename in the destination root
+pub const SESSION_FILE: &str = ".arsync-session";
+
+/// Magic bytes identifying a checkpoint file (format version 1)
+const CHECKPOINT_MAGIC: &[u8; 8] = b"ARSYNCS1";
+
+/// Session request byte: start a new session
+const REQUEST_NEW: u8 = 0;
+
+/// Session request byte: resume the session whose token follows
+const REQUEST_RESUME: u8 = 1;
+
+// ============================================================================
+// SESSION TOKEN
+// ============================================================================
+
+/// Opaque identifier of a transfer session (128 random bits)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken([u8; 16]);
+
+impl SessionToken {
+    /// Generate a new random session token
+    #[must_use]
+    pub fn generate() -> Self {
+        use rand::Rng;
+        Self(rand::rng().random())
+    }
+
+    /// Create a token from its raw bytes
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Raw token bytes (wire and checkpoint format)
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SessionToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 32 || !s.is_ascii() {
+            anyhow::bail!("Invalid session token '{s}': expected 32 hex digits");
+        }
+        let mut bytes = [0u8; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .with_context(|| format!("Invalid session token '{s}'"))?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+// ============================================================================
+// RESUME POINT
+// ============================================================================
+
+/// Position in the file list where a transfer continues
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResumePoint {
+    /// Index of the first file that is not fully applied
+    pub file_index: u64,
+    /// Bytes of that file already written to its partial file
+    pub offset: u64,
+}
+
+impl ResumePoint {
+    /// Start of the transfer (nothing applied yet)
+    pub const START: Self = Self {
+        file_index: 0,
+        offset: 0,
+    };
+
+    /// Offset to resume the file at `index` from (zero for all but the resume file)
+    #[must_use]
+    pub const fn offset_for(&self, index: u64) -> u64 {
+        if index == self.file_index {
+            self.offset
+        } else {
+            0
+        }
+    }
+}
+
+// ============================================================================
+// CHECKPOINT (receiver side)
+// ============================================================================
+
+/// Receiver progress persisted in `DEST/.arsync-session`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionCheckpoint {
+    /// Session this checkpoint belongs to
+    pub token: SessionToken,
+    /// How far the receiver got
+    pub resume: ResumePoint,
+    /// Path, size and mtime of the file at `resume.file_index` (for validation)
+    pub current: Option<(String, u64, i64)>,
+    /// Partial file holding `resume.offset` bytes, relative to the destination
+    pub partial: Option<PathBuf>,
+}
+
+impl SessionCheckpoint {
+    /// Checkpoint at the start of a new session
+    #[must_use]
+    pub fn new(token: SessionToken, files: &[FileEntry]) -> Self {
+        let mut checkpoint = Self {
+            token,
+            resume: ResumePoint::START,
+            current: None,
+            partial: None,
+        };
+        checkpoint.advance_to(0, files);
+        checkpoint
+    }
+
+    /// Record that every file before `file_index` has been fully applied
+    pub fn advance_to(&mut self, file_index: usize, files: &[FileEntry]) {
+        self.resume = ResumePoint {
+            file_index: file_index as u64,
+            offset: 0,
+        };
+        self.current = files
+            .get(file_index)
+            .map(|f| (f.path.clone(), f.size, f.mtime));
+        self.partial = None;
+    }
+
+    /// Record `offset` bytes of the current file written to `partial`
+    pub fn record_partial(&mut self, offset: u64, partial: &Path) {
+        self.resume.offset = offset;
+        self.partial = Some(partial.to_path_buf());
+    }
+
+    /// Where a resumed transfer of `files` into `dest` may safely continue
+    ///
+    /// Falls back to `ResumePoint::START` when the file list no longer matches
+    /// the checkpoint, and to offset zero when the partial file is gone or
+    /// shorter than recorded.
+    #[must_use]
+    pub fn resume_point(&self, dest: &Path, files: &[FileEntry]) -> ResumePoint {
+        let Ok(index) = usize::try_from(self.resume.file_index) else {
+            return ResumePoint::START;
+        };
+        let current = files.get(index).map(|f| (f.path.clone(), f.size, f.mtime));
+        if index > files.len() || current != self.current {
+            return ResumePoint::START;
+        }
+
+        let partial_len = self
+            .partial
+            .as_ref()
+            .and_then(|p| fs::symlink_metadata(dest.join(p)).ok())
+            .filter(fs::Metadata::is_file)
+            .map_or(0, |m| m.len());
+        ResumePoint {
+            file_index: self.resume.file_index,
+            offset: if partial_len >= self.resume.offset {
+                self.resume.offset
+            } else {
+                0
+            },
+        }
+    }
+
+    /// Load the checkpoint for `dest`, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint exists but cannot be read or is corrupt.
+    pub fn load(dest: &Path) -> Result<Option<Self>> {
+        let path = dest.join(SESSION_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::decode(&data)
+            .map(Some)
+            .with_context(|| format!("Corrupt session checkpoint {}", path.display()))
+    }
+
+    /// Atomically replace the checkpoint for `dest`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint cannot be written or renamed into place.
+    pub fn save(&self, dest: &Path, durable: bool) -> Result<()> {
+        let path = dest.join(SESSION_FILE);
+        let staged = crate::temp_files::temp_path(dest, SESSION_FILE);
+        let mut file = fs::File::create(&staged)
+            .with_context(|| format!("Failed to create {}", staged.display()))?;
+        file.write_all(&self.encode())?;
+        if durable {
+            file.sync_all()?;
+        }
+        fs::rename(&staged, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Remove the checkpoint for `dest` (session finished)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint exists but cannot be removed.
+    pub fn remove(dest: &Path) -> Result<()> {
+        match fs::remove_file(dest.join(SESSION_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(128);
+        out.extend_from_slice(CHECKPOINT_MAGIC);
+        out.extend_from_slice(self.token.as_bytes());
+        out.extend_from_slice(&self.resume.file_index.to_le_bytes());
+        out.extend_from_slice(&self.resume.offset.to_le_bytes());
+        let (path, size, mtime) = self.current.clone().unwrap_or_default();
+        out.push(u8::from(self.current.is_some()));
+        put_bytes(&mut out, path.as_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&mtime.to_le_bytes());
+        let partial = self
+            .partial
+            .as_ref()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        put_bytes(&mut out, partial.as_bytes());
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader(data);
+        if reader.take(CHECKPOINT_MAGIC.len())? != CHECKPOINT_MAGIC {
+            anyhow::bail!("bad magic");
+        }
+        let token = SessionToken(reader.array()?);
+        let resume = ResumePoint {
+            file_index: u64::from_le_bytes(reader.array()?),
+            offset: u64::from_le_bytes(reader.array()?),
+        };
+        let has_current = reader.array::<1>()?[0] != 0;
+        let path = String::from_utf8(reader.bytes()?.to_vec())?;
+        let size = u64::from_le_bytes(reader.array()?);
+        let mtime = i64::from_le_bytes(reader.array()?);
+        let partial = String::from_utf8(reader.bytes()?.to_vec())?;
+        Ok(Self {
+            token,
+            resume,
+            current: has_current.then_some((path, size, mtime)),
+            partial: (!partial.is_empty()).then(|| PathBuf::from(partial)),
+        })
+    }
+}
+
+/// Append a u32-length-prefixed byte string (paths are far below 4 GiB)
+pub(super) fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Minimal cursor over checkpoint bytes
+pub(super) struct ByteReader<'a>(pub(super) &'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            anyhow::bail!("truncated");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(super) fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    pub(super) fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        self.take(len)
+    }
+}
+
+// ============================================================================
+// WIRE EXCHANGE
+// ============================================================================
+
+/// Sender: request a new session (or resume `token`) and learn the resume point
+///
+/// # Errors
+///
+/// Returns an error if the transport fails.
+pub async fn request_session<T: Transport>(
+    transport: &mut T,
+    resume: Option<SessionToken>,
+) -> Result<(SessionToken, ResumePoint)> {
+    match resume {
+        Some(token) => {
+            transport::write_all(transport, &[REQUEST_RESUME]).await?;
+            transport::write_all(transport, token.as_bytes()).await?;
+        }
+        None => transport::write_all(transport, &[REQUEST_NEW]).await?,
+    }
+
+    let mut token = [0u8; 16];
+    transport::read_exact(transport, &mut token).await?;
+    let mut index_buf = [0u8; 8];
+    transport::read_exact(transport, &mut index_buf).await?;
+    let mut offset_buf = [0u8; 8];
+    transport::read_exact(transport, &mut offset_buf).await?;
+
+    Ok((
+        SessionToken(token),
+        ResumePoint {
+            file_index: u64::from_le_bytes(index_buf),
+            offset: u64::from_le_bytes(offset_buf),
+        },
+    ))
+}
+
+/// Receiver: answer the sender's session request from the checkpoint in `dest`
+///
+/// Returns the checkpoint to keep updating during the transfer.
+///
+/// # Errors
+///
+/// Returns an error if the transport fails or the checkpoint cannot be read.
+pub async fn accept_session<T: Transport>(
+    transport: &mut T,
+    dest: &Path,
+    files: &[FileEntry],
+) -> Result<SessionCheckpoint> {
+    let mut request = [0u8; 1];
+    transport::read_exact(transport, &mut request).await?;
+    let requested = match request[0] {
+        REQUEST_NEW => None,
+        REQUEST_RESUME => {
+            let mut token = [0u8; 16];
+            transport::read_exact(transport, &mut token).await?;
+            Some(SessionToken(token))
+        }
+        other => anyhow::bail!("Unknown session request type: {other}"),
+    };
+
+    let checkpoint = match (requested, SessionCheckpoint::load(dest)?) {
+        (Some(token), Some(existing)) if existing.token == token => {
+            let resume = existing.resume_point(dest, files);
+            if resume == ResumePoint::START {
+                tracing::warn!("Session {token}: source changed since interruption, restarting");
+                SessionCheckpoint::new(token, files)
+            } else {
+                tracing::info!(
+                    "Session {token}: resuming at file {} offset {}",
+                    resume.file_index,
+                    resume.offset
+                );
+                SessionCheckpoint { resume, ..existing }
+            }
+        }
+        (requested, _) => {
+            if let Some(token) = requested {
+                tracing::warn!("Unknown session {token}, starting a new session");
+            }
+            SessionCheckpoint::new(SessionToken::generate(), files)
+        }
+    };
+
+    transport::write_all(transport, checkpoint.token.as_bytes()).await?;
+    transport::write_all(transport, &checkpoint.resume.file_index.to_le_bytes()).await?;
+    transport::write_all(transport, &checkpoint.resume.offset.to_le_bytes()).await?;
+    Ok(checkpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::pipe::PipeTransport;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            size,
+            mtime: 1_700_000_000,
+            mode: 0o100_644,
+            uid: 0,
+            gid: 0,
+            is_symlink: false,
+            symlink_target: None,
+        }
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = SessionToken::generate();
+        let parsed: SessionToken = token.to_string().parse().unwrap();
+        assert_eq!(parsed, token);
+        assert!("not-a-token".parse::<SessionToken>().is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_save_load() {
+        let dest = TempDir::new().unwrap();
+        let files = vec![entry("a.txt", 10), entry("b.txt", 20)];
+        let mut checkpoint = SessionCheckpoint::new(SessionToken::generate(), &files);
+        checkpoint.advance_to(1, &files);
+        checkpoint.record_partial(8, Path::new(".arsync.tmp.1-2.b.txt"));
+        checkpoint.save(dest.path(), false).unwrap();
+
+        let loaded = SessionCheckpoint::load(dest.path()).unwrap().unwrap();
+        assert_eq!(loaded, checkpoint);
+
+        SessionCheckpoint::remove(dest.path()).unwrap();
+        assert!(SessionCheckpoint::load(dest.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resume_point_validation() {
+        let dest = TempDir::new().unwrap();
+        let files = vec![entry("a.txt", 10), entry("b.txt", 20)];
+        let partial = Path::new(".arsync.tmp.1-2.b.txt");
+        fs::write(dest.path().join(partial), [0u8; 8]).unwrap();
+
+        let mut checkpoint = SessionCheckpoint::new(SessionToken::generate(), &files);
+        checkpoint.advance_to(1, &files);
+        checkpoint.record_partial(8, partial);
+        assert_eq!(
+            checkpoint.resume_point(dest.path(), &files),
+            ResumePoint {
+                file_index: 1,
+                offset: 8
+            }
+        );
+
+        // Partial file lost: restart that file
+        fs::remove_file(dest.path().join(partial)).unwrap();
+        assert_eq!(checkpoint.resume_point(dest.path(), &files).offset, 0);
+
+        // File at the resume point changed: restart the session
+        let changed = vec![entry("a.txt", 10), entry("b.txt", 21)];
+        assert_eq!(
+            checkpoint.resume_point(dest.path(), &changed),
+            ResumePoint::START
+        );
+    }
+
+    /// Connected (sender, receiver) pipe transports
+    fn transport_pair() -> (PipeTransport, PipeTransport) {
+        let (to_receiver_read, to_receiver_write) = PipeTransport::create_pipe().unwrap();
+        let (to_sender_read, to_sender_write) = PipeTransport::create_pipe().unwrap();
+        // SAFETY: the FDs were just created and each is owned by exactly one transport
+        unsafe {
+            (
+                PipeTransport::from_fds(to_sender_read, to_receiver_write, "sender".to_string())
+                    .unwrap(),
+                PipeTransport::from_fds(to_receiver_read, to_sender_write, "receiver".to_string())
+                    .unwrap(),
+            )
+        }
+    }
+
+    #[compio::test]
+    async fn test_pipe_transfer_resumes_from_checkpoint() {
+        use crate::protocol::rsync::{receive_via_pipe, send_via_pipe_resumable};
+        use clap::Parser;
+
+        let src_dir = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        let src_file = src_dir.path().join("data.bin");
+        fs::write(&src_file, b"0123456789abcdef").unwrap();
+        let args = crate::cli::Args::parse_from(["arsync", "src", "dst"]);
+
+        // Simulate an interrupted session: 4 bytes already applied to a partial
+        // file. The partial holds different bytes so we can tell it was reused.
+        let metadata = fs::metadata(&src_file).unwrap();
+        let mtime = metadata
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        let files = vec![FileEntry {
+            mtime: i64::try_from(mtime.as_secs()).unwrap(),
+            ..entry("data.bin", metadata.len())
+        }];
+        let token = SessionToken::generate();
+        let partial = Path::new(".arsync.tmp.1-2.data.bin");
+        fs::write(dest.path().join(partial), b"WXYZ").unwrap();
+        let mut checkpoint = SessionCheckpoint::new(token, &files);
+        checkpoint.record_partial(4, partial);
+        checkpoint.save(dest.path(), false).unwrap();
+
+        let (sender, receiver) = transport_pair();
+        let (sent, received) = futures::join!(
+            send_via_pipe_resumable(&args, &src_file, sender, Some(token)),
+            receive_via_pipe(&args, receiver, dest.path())
+        );
+        let (_, session) = sent.unwrap();
+        received.unwrap();
+
+        assert_eq!(session, token);
+        assert_eq!(
+            fs::read(dest.path().join("data.bin")).unwrap(),
+            b"WXYZ456789abcdef"
+        );
+        assert!(!dest.path().join(partial).exists());
+        assert!(SessionCheckpoint::load(dest.path()).unwrap().is_none());
+    }
+}
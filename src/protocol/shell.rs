@@ -0,0 +1,243 @@
+//! Remote shell command lines (`--rsh`, `--remote-cmd`)
+//!
+//! Two conversions are needed to start arsync on a remote host:
+//!
+//! - The `--rsh` value (e.g. `ssh -p 2222 -i "my key"`) is split into an argv
+//!   locally, using POSIX shell quoting rules, and executed directly (no local
+//!   shell is involved).
+//! - The remote command line is sent to the remote shell as a single string,
+//!   which the remote shell word-splits again. Every argument we add (flags,
+//!   paths) is therefore single-quoted so that filenames containing spaces,
+//!   quotes, or shell metacharacters arrive intact.
+//!
+//! Like rsync's `--rsync-path`, the `--remote-cmd` value is inserted verbatim,
+//! so it may itself be a short command such as `sudo /opt/arsync/bin/arsync`.
+
+use anyhow::Result;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// Default remote shell
+pub const DEFAULT_RSH: &str = "ssh";
+
+/// Default remote arsync command
+pub const DEFAULT_REMOTE_CMD: &str = "arsync";
+
+/// Default remote command when pushing to a stock rsync server
+pub const DEFAULT_RSYNC_CMD: &str = "rsync";
+
+/// Parsed remote shell invocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteShell {
+    /// Program to execute (e.g. "ssh")
+    program: String,
+    /// Options given to the program before the destination
+    options: Vec<String>,
+    /// Remote arsync command, inserted verbatim into the remote command line
+    remote_cmd: String,
+}
+
+impl Default for RemoteShell {
+    fn default() -> Self {
+        Self {
+            program: DEFAULT_RSH.to_string(),
+            options: Vec::new(),
+            remote_cmd: DEFAULT_REMOTE_CMD.to_string(),
+        }
+    }
+}
+
+impl RemoteShell {
+    /// Build from `--rsh` and `--remote-cmd` values
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `rsh` is empty or has unbalanced quotes, or if
+    /// `remote_cmd` is empty.
+    pub fn parse(rsh: &str, remote_cmd: &str) -> Result<Self> {
+        let mut argv = split_command_line(rsh)?.into_iter();
+        let program = argv
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Remote shell command is empty"))?;
+        if remote_cmd.trim().is_empty() {
+            anyhow::bail!("Remote command is empty");
+        }
+        Ok(Self {
+            program,
+            options: argv.collect(),
+            remote_cmd: remote_cmd.to_string(),
+        })
+    }
+
+    /// Program to execute
+    #[must_use]
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Options passed to the program before the destination
+    #[must_use]
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    /// Remote command line running the remote arsync with `args`
+    ///
+    /// Each argument is quoted for the remote shell; the remote command itself
+    /// is not.
+    #[must_use]
+    pub fn remote_command(&self, args: &[&OsStr]) -> OsString {
+        let mut line = OsString::from(&self.remote_cmd);
+        for arg in args {
+            line.push(" ");
+            line.push(quote(arg));
+        }
+        line
+    }
+}
+
+/// Split a command line into words using POSIX shell quoting rules
+///
+/// Supports single quotes, double quotes (where `\` escapes `"`, `\`, `$` and
+/// `` ` ``), and backslash escapes outside quotes. No expansion is performed.
+///
+/// # Errors
+///
+/// Returns an error on an unterminated quote or a trailing backslash.
+pub fn split_command_line(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("Unterminated single quote in: {line}"),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => anyhow::bail!("Unterminated double quote in: {line}"),
+                        },
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("Unterminated double quote in: {line}"),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => anyhow::bail!("Trailing backslash in: {line}"),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+/// Quote one argument for a POSIX shell
+///
+/// Arguments made only of safe characters are returned unchanged; everything
+/// else is wrapped in single quotes (with embedded `'` written as `'\''`).
+/// Works on raw bytes, so non-UTF-8 filenames are preserved.
+#[must_use]
+pub fn quote(arg: &OsStr) -> OsString {
+    let bytes = arg.as_bytes();
+    let is_safe = |b: &u8| b.is_ascii_alphanumeric() || b"-_./=:,+@%".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(is_safe) {
+        return arg.to_os_string();
+    }
+
+    let mut quoted = Vec::with_capacity(bytes.len() + 2);
+    quoted.push(b'\'');
+    for &b in bytes {
+        if b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    OsString::from_vec(quoted)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_split_command_line() {
+        // Test: --rsh values split like a POSIX shell would
+        // Requirement: Options with spaces (key paths) must survive as one word
+        assert_eq!(
+            split_command_line(r#"ssh -p 2222 -i "/keys/my key" -o 'User=a b'"#).unwrap(),
+            vec!["ssh", "-p", "2222", "-i", "/keys/my key", "-o", "User=a b"]
+        );
+        assert_eq!(
+            split_command_line(r#"  a\ b "c\"d" 'e\f' ''  "#).unwrap(),
+            vec!["a b", "c\"d", "e\\f", ""]
+        );
+        assert!(split_command_line("ssh 'oops").is_err());
+        assert!(split_command_line("ssh \"oops").is_err());
+        assert!(split_command_line("ssh oops\\").is_err());
+    }
+
+    #[test]
+    fn test_quote_round_trips_through_split() {
+        // Test: Quoted arguments come back unchanged after remote word splitting
+        // Requirement: Filenames with spaces and quotes reach the remote intact
+        for arg in ["plain", "with space", "it's", "semi;colon $HOME", "", "*"] {
+            let quoted = quote(OsStr::new(arg));
+            let split = split_command_line(quoted.to_str().unwrap()).unwrap();
+            assert_eq!(split, vec![arg.to_string()], "{arg:?} -> {quoted:?}");
+        }
+        assert_eq!(quote(OsStr::new("/srv/data-1")), "/srv/data-1");
+    }
+
+    #[test]
+    fn test_remote_shell_parse() {
+        // Test: --rsh and --remote-cmd combine into program, options, remote line
+        let shell = RemoteShell::parse("ssh -p 2222", "/opt/arsync/bin/arsync").unwrap();
+        assert_eq!(shell.program(), "ssh");
+        assert_eq!(shell.options(), ["-p", "2222"]);
+        assert_eq!(
+            shell.remote_command(&[OsStr::new("--server"), OsStr::new("/srv/my files")]),
+            "/opt/arsync/bin/arsync --server '/srv/my files'"
+        );
+        assert!(RemoteShell::parse("  ", "arsync").is_err());
+        assert!(RemoteShell::parse("ssh", "").is_err());
+    }
+}
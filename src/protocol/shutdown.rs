@@ -0,0 +1,280 @@
+//! Graceful draining of protocol sessions on daemon shutdown
+//!
+//! Killing a daemon outright drops every connection mid-file. Instead, a
+//! `ShutdownCoordinator` sits between the listener and the sessions it
+//! spawns:
+//!
+//! 1. Every accepted connection registers and holds a `SessionGuard` for
+//!    the lifetime of its session. Once draining has begun, registration
+//!    fails and the listener closes the connection instead of serving it.
+//! 2. On SIGTERM (see `install_termination_handler`) the daemon calls
+//!    `drain(deadline)`: new sessions are refused and in-flight ones keep
+//!    going, so transfers that finish within `deadline` complete normally.
+//! 3. At the deadline, sessions still running are told to stop: the
+//!    receiver checks `SessionGuard::must_stop` after each checkpoint, so it
+//!    stops with its checkpoint (`crate::protocol::session`) on disk and the
+//!    client can resume from there against the restarted daemon.
+//! 4. `drain` returns once every session is gone, or after a short grace
+//!    period past the deadline, reporting the sessions that were cut off.
+
+#![allow(dead_code)] // must_stop is for native-protocol sessions, not served by the daemon yet
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How often `drain` checks whether all sessions have ended
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time sessions get after the deadline to reach a checkpoint and exit
+pub const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// Set by the SIGTERM/SIGINT handler
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// A session currently being served
+#[derive(Debug, Clone)]
+pub struct ActiveSession {
+    /// Peer address (or other client description) for logs
+    pub peer: String,
+    /// When the session was registered
+    pub started: Instant,
+}
+
+/// Outcome of `ShutdownCoordinator::drain`
+#[derive(Debug, Default)]
+pub struct DrainReport {
+    /// Sessions that ended on their own during the drain
+    pub finished: usize,
+    /// Sessions told to stop at the deadline (resumable from their checkpoint)
+    pub stopped: usize,
+    /// Sessions still registered when `drain` gave up waiting
+    pub abandoned: Vec<ActiveSession>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Set once draining begins; new sessions are refused
+    draining: bool,
+    /// Sessions must stop at their next checkpoint
+    stopping: bool,
+    sessions: BTreeMap<u64, ActiveSession>,
+}
+
+/// Registry of active sessions with the shutdown state machine
+#[derive(Debug, Default)]
+pub struct ShutdownCoordinator {
+    state: Mutex<State>,
+    /// Mirrors `State::stopping` for the receivers' hot path
+    stopping: AtomicBool,
+    next_id: AtomicU64,
+}
+
+impl ShutdownCoordinator {
+    /// Coordinator accepting sessions
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Register a newly accepted session from `peer`
+    ///
+    /// Returns `None` once draining has begun; the listener should then
+    /// close the connection without serving it.
+    #[must_use]
+    pub fn register(self: &Arc<Self>, peer: &str) -> Option<SessionGuard> {
+        let mut state = self.lock();
+        if state.draining {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        state.sessions.insert(
+            id,
+            ActiveSession {
+                peer: peer.to_string(),
+                started: Instant::now(),
+            },
+        );
+        Some(SessionGuard {
+            coordinator: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Whether draining has begun (the listener should stop accepting)
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.lock().draining
+    }
+
+    /// Sessions currently registered
+    #[must_use]
+    pub fn active(&self) -> Vec<ActiveSession> {
+        self.lock().sessions.values().cloned().collect()
+    }
+
+    /// Stop accepting sessions, without waiting for active ones
+    pub fn begin_drain(&self) {
+        self.lock().draining = true;
+    }
+
+    /// Drain: refuse new sessions, let active ones run until `deadline`
+    /// from now, then tell the remaining ones to stop at their next
+    /// checkpoint and wait up to `STOP_GRACE` for them to exit
+    #[allow(clippy::future_not_send)]
+    pub async fn drain(&self, deadline: Duration) -> DrainReport {
+        let before = {
+            let mut state = self.lock();
+            state.draining = true;
+            state.sessions.len()
+        };
+        let mut report = DrainReport::default();
+
+        let cutoff = Instant::now() + deadline;
+        while !self.lock().sessions.is_empty() && Instant::now() < cutoff {
+            compio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        let remaining = {
+            let mut state = self.lock();
+            state.stopping = true;
+            state.sessions.len()
+        };
+        self.stopping.store(true, Ordering::Release);
+        report.finished = before - remaining;
+        report.stopped = remaining;
+
+        let grace_end = Instant::now() + STOP_GRACE;
+        while !self.lock().sessions.is_empty() && Instant::now() < grace_end {
+            compio::time::sleep(POLL_INTERVAL).await;
+        }
+        report.abandoned = self.active();
+        report
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Registration of one active session; dropping it ends the session
+#[derive(Debug)]
+pub struct SessionGuard {
+    coordinator: Arc<ShutdownCoordinator>,
+    id: u64,
+}
+
+impl SessionGuard {
+    /// Whether the session must stop at its next checkpoint (the drain
+    /// deadline has passed)
+    #[must_use]
+    pub fn must_stop(&self) -> bool {
+        self.coordinator.stopping.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.coordinator.lock().sessions.remove(&self.id);
+    }
+}
+
+/// Error of a session stopped by a draining daemon
+///
+/// The receiver's checkpoint is on disk; the client resumes by reconnecting
+/// with its session token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Daemon is shutting down; session stopped at its last checkpoint and can be resumed")]
+pub struct SessionStopped;
+
+/// Make SIGTERM and SIGINT request a drain instead of killing the process
+///
+/// The daemon's accept loop polls `termination_requested` and calls
+/// `ShutdownCoordinator::drain` when it turns true. A second signal after
+/// that falls back to the default action, so an impatient operator can
+/// still kill the daemon.
+///
+/// # Errors
+///
+/// Returns an error if a handler cannot be installed.
+pub fn install_termination_handler() -> std::io::Result<()> {
+    extern "C" fn on_signal(signal: libc::c_int) {
+        // Only async-signal-safe calls here: an atomic store and signal()
+        TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+        // SAFETY: restoring the default disposition is async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: `on_signal` only performs async-signal-safe operations
+        let previous = unsafe { libc::signal(signal, on_signal as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether SIGTERM or SIGINT was received since
+/// `install_termination_handler`
+#[must_use]
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[compio::test]
+    async fn test_drain_refuses_new_sessions_and_waits() {
+        // Requirement: Draining refuses new sessions and returns once active
+        // sessions that finish before the deadline have ended
+        let coordinator = ShutdownCoordinator::new();
+        let session = coordinator.register("10.0.0.7:40000").unwrap();
+        assert_eq!(coordinator.active().len(), 1);
+
+        let finisher = compio::runtime::spawn(async move {
+            compio::time::sleep(Duration::from_millis(100)).await;
+            assert!(!session.must_stop());
+            drop(session);
+        });
+        let report = coordinator.drain(Duration::from_secs(10)).await;
+        finisher.await.unwrap();
+
+        assert!(coordinator.is_draining());
+        assert!(coordinator.register("10.0.0.8:40000").is_none());
+        assert_eq!(report.finished, 1);
+        assert_eq!(report.stopped, 0);
+        assert!(report.abandoned.is_empty());
+    }
+
+    #[compio::test]
+    async fn test_drain_stops_sessions_at_deadline() {
+        // Requirement: Sessions still running at the deadline are told to
+        // stop at their next checkpoint, and drain waits for them to exit
+        let coordinator = ShutdownCoordinator::new();
+        let session = coordinator.register("slow client").unwrap();
+
+        let transfer = compio::runtime::spawn(async move {
+            // Keep "transferring" until told to stop, like the receive loop
+            while !session.must_stop() {
+                compio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        let start = Instant::now();
+        let report = coordinator.drain(Duration::from_millis(100)).await;
+        transfer.await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < STOP_GRACE);
+        assert_eq!(report.finished, 0);
+        assert_eq!(report.stopped, 1);
+        assert!(report.abandoned.is_empty());
+        assert!(coordinator.active().is_empty());
+    }
+}
@@ -16,16 +16,85 @@
 //!     ↓
 //! io_uring operations
 //! ```
+//!
+//! # Connection Pooling
+//!
+//! Repeated remote invocations (watch mode, per-module syncs) would otherwise
+//! pay for a full SSH handshake and authentication each time. `SshPool` keeps
+//! one OpenSSH `ControlMaster` per `(user, host, port)` and opens every
+//! session as a multiplexed channel over it. The master exits on its own after
+//! the configured idle timeout (`ControlPersist`), so an idle pool holds no
+//! connections; its control sockets live in a private (0700) directory.
+//!
+//! # Diagnostics and Reconnection
+//!
+//! The remote shell's stderr is captured rather than inherited: every line
+//! is logged as a warning prefixed with the host, and the last lines are
+//! kept so a failed session can say why ([`SshConnection::finish`]). ssh
+//! exits with 255 when the connection itself fails; if that happens before
+//! the remote command sent anything and ssh reported a transient cause
+//! (refused, timed out, unreachable, failed name lookup), the failure is a
+//! retryable [`ConnectionFailed`]. [`SshConnector`] opens sessions, directly
+//! or multiplexed over a pooled master, and retries those failures with
+//! exponential backoff ([`RetryPolicy`]). Nothing was transferred by then,
+//! so starting the session again is safe.
 #![allow(dead_code)] // Protocol implementation not yet fully used
 #![allow(clippy::future_not_send)] // compio buffers are not Send by design
 #![allow(clippy::unused_async)] // Async signatures for future protocol work
 
+use super::shell::RemoteShell;
 use super::transport::Transport;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use compio::buf::BufResult;
 use compio::io::{AsyncRead, AsyncWrite};
-use compio::process::{Child, ChildStdin, ChildStdout, Command};
-use std::path::Path;
-use std::process::Stdio;
+use compio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use futures::channel::oneshot;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Exit code of ssh when the connection itself failed
+const SSH_CONNECTION_FAILED: i32 = 255;
+
+/// Lines of the remote shell's stderr kept for error messages
+const STDERR_TAIL_LINES: usize = 20;
+
+/// How long to wait for the rest of stderr once the remote shell exited
+const STDERR_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What ssh says when a connection failed for a reason worth retrying
+const TRANSIENT_ERRORS: &[&str] = &[
+    "Connection refused",
+    "Connection timed out",
+    "Operation timed out",
+    "Connection reset",
+    "Connection closed by",
+    "No route to host",
+    "Network is unreachable",
+    "Temporary failure in name resolution",
+    "kex_exchange_identification",
+    "ssh_exchange_identification",
+];
+
+/// Last lines the remote shell wrote to stderr
+type StderrTail = Arc<Mutex<VecDeque<String>>>;
+
+/// The SSH connection to `host` failed before the remote command started
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("SSH connection to {host} failed: {reason}")]
+pub struct ConnectionFailed {
+    /// Remote host
+    pub host: String,
+    /// What ssh reported (its last line on stderr)
+    pub reason: String,
+    /// Whether connecting again may succeed (refused, timed out, ...)
+    pub transient: bool,
+}
 
 /// SSH connection to remote host
 ///
@@ -44,6 +113,12 @@ pub struct SshConnection {
     /// Remote user
     #[allow(dead_code)]
     user: String,
+    /// Captured stderr of the remote shell
+    stderr: StderrTail,
+    /// Resolves once all of stderr has been read
+    stderr_closed: Option<oneshot::Receiver<()>>,
+    /// Bytes received from the remote command
+    received: u64,
 }
 
 impl SshConnection {
@@ -54,8 +129,9 @@ impl SshConnection {
     /// # Arguments
     ///
     /// * `host` - Remote hostname or IP
-    /// * `user` - Remote username  
-    /// * `remote_shell` - Shell command to use (typically "ssh")
+    /// * `user` - Remote username (empty: let ssh choose)
+    /// * `shell` - Remote shell and remote arsync command (`--rsh`, `--remote-cmd`)
+    /// * `server_args` - Extra arguments for the remote server (quoted for the remote shell)
     ///
     /// # Errors
     ///
@@ -66,31 +142,77 @@ impl SshConnection {
     /// # Example
     ///
     /// ```rust,no_run
+    /// use arsync::protocol::shell::RemoteShell;
     /// use arsync::protocol::ssh::SshConnection;
     ///
     /// # async fn example() -> anyhow::Result<()> {
-    /// let conn = SshConnection::connect("example.com", "user", "ssh").await?;
+    /// let shell = RemoteShell::parse("ssh -p 2222", "/opt/arsync/bin/arsync")?;
+    /// let conn = SshConnection::connect("example.com", "user", &shell, &[]).await?;
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn connect(host: &str, user: &str, remote_shell: &str) -> Result<Self> {
-        // Build SSH command
-        let mut cmd = Command::new(remote_shell);
-        cmd.arg(format!("{user}@{host}"))
+    pub async fn connect(
+        host: &str,
+        user: &str,
+        shell: &RemoteShell,
+        server_args: &[&OsStr],
+    ) -> Result<Self> {
+        Self::spawn(host, user, shell, &[], &server_command(server_args))
+    }
+
+    /// Connect to remote host via SSH running `arsync REMOTE_ARGS...`
+    ///
+    /// Like `connect`, but for remote modes other than the transfer server
+    /// (such as `arsync probe --server`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SSH process fails to spawn or its stdin/stdout
+    /// cannot be captured.
+    pub async fn connect_command(
+        host: &str,
+        user: &str,
+        shell: &RemoteShell,
+        remote_args: &[&OsStr],
+    ) -> Result<Self> {
+        Self::spawn(host, user, shell, &[], remote_args)
+    }
+
+    /// Spawn SSH (with extra options before the destination) running remote arsync
+    fn spawn(
+        host: &str,
+        user: &str,
+        shell: &RemoteShell,
+        ssh_options: &[String],
+        remote_args: &[&OsStr],
+    ) -> Result<Self> {
+        // Build SSH command; the remote command is one pre-quoted string because
+        // the remote shell word-splits it again
+        // An empty user leaves the choice to ssh (its config or the local user)
+        let destination = if user.is_empty() {
+            host.to_string()
+        } else {
+            format!("{user}@{host}")
+        };
+        let mut cmd = Command::new(shell.program());
+        cmd.args(shell.options())
+            .args(ssh_options)
+            .arg(destination)
             .arg("--") // Separator for SSH args vs remote command
-            .arg("arsync")
-            .arg("--server");
+            .arg(shell.remote_command(remote_args));
 
         // Configure stdio (compio methods return Result)
         cmd.stdin(Stdio::piped())
             .map_err(|_| anyhow::anyhow!("Failed to configure stdin"))?;
         cmd.stdout(Stdio::piped())
             .map_err(|_| anyhow::anyhow!("Failed to configure stdout"))?;
-        cmd.stderr(Stdio::inherit())
+        cmd.stderr(Stdio::piped())
             .map_err(|_| anyhow::anyhow!("Failed to configure stderr"))?;
 
         // Spawn SSH process (uses compio, will use io_uring for I/O)
-        let mut process = cmd.spawn()?;
+        let mut process = cmd
+            .spawn()
+            .with_context(|| format!("Failed to start remote shell {}", shell.program()))?;
 
         let stdin = process
             .stdin
@@ -100,6 +222,11 @@ impl SshConnection {
             .stdout
             .take()
             .ok_or_else(|| anyhow::anyhow!("Failed to get stdout from SSH process"))?;
+        let stderr = process
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get stderr from SSH process"))?;
+        let (tail, closed) = capture_stderr(stderr, host.to_string());
 
         Ok(Self {
             process,
@@ -107,6 +234,9 @@ impl SshConnection {
             stdout,
             host: host.to_string(),
             user: user.to_string(),
+            stderr: tail,
+            stderr_closed: Some(closed),
+            received: 0,
         })
     }
 
@@ -120,6 +250,641 @@ impl SshConnection {
         // TODO: Implement protocol negotiation
         Ok(())
     }
+
+    /// Last lines the remote shell wrote to stderr (also logged as warnings)
+    #[must_use]
+    pub fn diagnostics(&self) -> Vec<String> {
+        lock(&self.stderr).iter().cloned().collect()
+    }
+
+    /// Close stdin and wait for the remote command (and the shell) to exit
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if waiting for the process fails.
+    pub async fn wait(self) -> Result<ExitStatus> {
+        Ok(self.close().await?.status)
+    }
+
+    /// End a session that produced `result`: wait for the remote command and
+    /// explain a failure with what the remote shell reported
+    ///
+    /// # Errors
+    ///
+    /// Returns the session's error, as [`ConnectionFailed`] context if ssh
+    /// could not connect, or an error if the session succeeded but the
+    /// remote command failed.
+    pub async fn finish<T>(self, result: Result<T>) -> Result<T> {
+        let closed = match self.close().await {
+            Ok(closed) => closed,
+            Err(e) => return result.and(Err(e)),
+        };
+        let reason = closed
+            .diagnostics
+            .last()
+            .cloned()
+            .unwrap_or_else(|| closed.status.to_string());
+        match result {
+            Ok(value) if closed.status.success() => Ok(value),
+            Ok(_) => Err(anyhow::anyhow!(
+                "Remote command on {} failed ({}): {reason}",
+                closed.host,
+                closed.status
+            )),
+            Err(e) if closed.status.code() == Some(SSH_CONNECTION_FAILED) => {
+                let transient = closed.received == 0 && is_transient(&closed.diagnostics);
+                Err(e.context(ConnectionFailed {
+                    host: closed.host,
+                    reason,
+                    transient,
+                }))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Close the pipes, wait for the remote shell and collect its stderr
+    async fn close(self) -> Result<Closed> {
+        let Self {
+            process,
+            stdin,
+            stdout,
+            host,
+            stderr,
+            stderr_closed,
+            received,
+            ..
+        } = self;
+        drop(stdin);
+        drop(stdout);
+        let status = process
+            .wait()
+            .await
+            .context("Failed to wait for the remote shell")?;
+        if let Some(closed) = stderr_closed {
+            // Something the shell started may still hold stderr open
+            let _ = compio::time::timeout(STDERR_DRAIN_TIMEOUT, closed).await;
+        }
+        let diagnostics = lock(&stderr).iter().cloned().collect();
+        Ok(Closed {
+            host,
+            status,
+            diagnostics,
+            received,
+        })
+    }
+}
+
+/// How a remote shell ended
+struct Closed {
+    host: String,
+    status: ExitStatus,
+    diagnostics: Vec<String>,
+    received: u64,
+}
+
+/// Log the remote shell's stderr line by line, keeping the last lines
+///
+/// Returns the kept lines and a receiver resolving at end of stream.
+fn capture_stderr(mut stderr: ChildStderr, host: String) -> (StderrTail, oneshot::Receiver<()>) {
+    let tail = StderrTail::default();
+    let (closed, closed_rx) = oneshot::channel();
+    let lines = Arc::clone(&tail);
+    compio::runtime::spawn(async move {
+        let mut pending = Vec::new();
+        let mut buf = Vec::with_capacity(4096);
+        loop {
+            buf.clear();
+            let BufResult(result, returned) = stderr.read(buf).await;
+            buf = returned;
+            let done = !matches!(result, Ok(n) if n > 0);
+            pending.extend_from_slice(&buf);
+            if done && !pending.is_empty() {
+                pending.push(b'\n');
+            }
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                warn!("{host}: {line}");
+                let mut lines = lock(&lines);
+                if lines.len() == STDERR_TAIL_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            if done {
+                break;
+            }
+        }
+        let _ = closed.send(());
+    })
+    .detach();
+    (tail, closed_rx)
+}
+
+fn lock(tail: &StderrTail) -> std::sync::MutexGuard<'_, VecDeque<String>> {
+    tail.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether ssh's messages name a cause worth connecting again for
+fn is_transient(diagnostics: &[String]) -> bool {
+    diagnostics
+        .iter()
+        .any(|line| TRANSIENT_ERRORS.iter().any(|error| line.contains(error)))
+}
+
+// ============================================================================
+// Reconnection
+// ============================================================================
+
+/// How often and how patiently to retry transient connection failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry; doubled for each further one
+    pub initial_delay: Duration,
+    /// Longest delay between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (counting from 0)
+    #[must_use]
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(1_u32.checked_shl(retry).unwrap_or(u32::MAX))
+            .min(self.max_delay)
+    }
+
+    /// Delay before retrying after `error`, if it is a transient connection
+    /// failure and `retries` retries have not used up the policy
+    #[must_use]
+    pub fn retry_after(&self, retries: u32, error: &anyhow::Error) -> Option<Duration> {
+        let transient = error
+            .downcast_ref::<ConnectionFailed>()
+            .is_some_and(|failure| failure.transient);
+        (transient && retries < self.retries).then(|| self.delay(retries))
+    }
+}
+
+/// Opens SSH sessions to remote hosts
+///
+/// Sessions run one ssh process each, or are multiplexed over a pooled
+/// master connection; connections that fail transiently are retried.
+pub struct SshConnector {
+    shell: RemoteShell,
+    pool: Option<SshPool>,
+    retry: RetryPolicy,
+}
+
+impl SshConnector {
+    /// Connector starting `shell` for every session
+    #[must_use]
+    pub fn new(shell: RemoteShell) -> Self {
+        Self {
+            shell,
+            pool: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Retry transient connection failures according to `retry`
+    #[must_use]
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Multiplex sessions over one master per host, kept open for
+    /// `idle_timeout` after its last session
+    #[must_use]
+    pub fn multiplexed(mut self, idle_timeout: Duration) -> Self {
+        self.pool = Some(SshPool::new(SshPoolConfig {
+            shell: self.shell.clone(),
+            idle_timeout,
+            ..SshPoolConfig::default()
+        }));
+        self
+    }
+
+    /// Start `remote_args` on `host` (an empty `user` lets ssh choose)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the remote shell cannot be started or, when
+    /// multiplexing, the master connection cannot be established.
+    pub async fn connect(
+        &self,
+        user: &str,
+        host: &str,
+        remote_args: &[&OsStr],
+    ) -> Result<SshConnection> {
+        match &self.pool {
+            Some(pool) => {
+                let target = SshTarget {
+                    user: user.to_string(),
+                    host: host.to_string(),
+                    port: None,
+                };
+                pool.connect_command(&target, remote_args).await
+            }
+            None => SshConnection::connect_command(host, user, &self.shell, remote_args).await,
+        }
+    }
+
+    /// Run a session with `remote_args` on `host`, connecting again after
+    /// transient connection failures
+    ///
+    /// `run` gets each new connection and should end with
+    /// [`SshConnection::finish`], which tells connection failures apart.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last attempt's error.
+    pub async fn session<T, F, Fut>(
+        &self,
+        user: &str,
+        host: &str,
+        remote_args: &[&OsStr],
+        mut run: F,
+    ) -> Result<T>
+    where
+        F: FnMut(SshConnection) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retries = 0;
+        loop {
+            let result = match self.connect(user, host, remote_args).await {
+                Ok(connection) => run(connection).await,
+                Err(e) => Err(e),
+            };
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let Some(delay) = self.retry.retry_after(retries, &error) else {
+                return Err(error);
+            };
+            retries += 1;
+            warn!(
+                "{error:#}; reconnecting in {delay:?} (retry {retries} of {})",
+                self.retry.retries
+            );
+            compio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Remote arguments starting the transfer server
+fn server_command<'a>(server_args: &[&'a OsStr]) -> Vec<&'a OsStr> {
+    let mut remote_args = vec![OsStr::new("--server")];
+    remote_args.extend_from_slice(server_args);
+    remote_args
+}
+
+// ============================================================================
+// Connection Pool (ControlMaster multiplexing)
+// ============================================================================
+
+/// Key identifying a pooled SSH connection
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SshTarget {
+    /// Remote username
+    pub user: String,
+    /// Remote hostname or IP
+    pub host: String,
+    /// Remote port (`None` = ssh default / `~/.ssh/config`)
+    pub port: Option<u16>,
+}
+
+impl SshTarget {
+    /// Stable 64-bit FNV-1a hash, so later arsync runs find the same master
+    fn stable_hash(&self) -> u64 {
+        let port = self.port.map_or_else(String::new, |p| p.to_string());
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in [self.user.as_str(), "@", self.host.as_str(), ":", &port]
+            .iter()
+            .flat_map(|part| part.bytes())
+        {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    /// `user@host`, or just `host` when no user is set (ssh chooses)
+    fn destination(&self) -> String {
+        if self.user.is_empty() {
+            self.host.clone()
+        } else {
+            format!("{}@{}", self.user, self.host)
+        }
+    }
+
+    /// SSH options selecting this target's port, if one is set
+    fn port_options(&self) -> Vec<String> {
+        self.port
+            .map(|port| vec!["-p".to_string(), port.to_string()])
+            .unwrap_or_default()
+    }
+}
+
+/// Configuration for `SshPool`
+#[derive(Debug, Clone)]
+pub struct SshPoolConfig {
+    /// Remote shell and remote arsync command
+    pub shell: RemoteShell,
+    /// How long an unused master connection stays open (`ControlPersist`)
+    pub idle_timeout: Duration,
+    /// Directory for control sockets (created with mode 0700)
+    pub control_dir: PathBuf,
+}
+
+impl Default for SshPoolConfig {
+    fn default() -> Self {
+        Self {
+            shell: RemoteShell::default(),
+            idle_timeout: Duration::from_secs(60),
+            control_dir: default_control_dir(),
+        }
+    }
+}
+
+/// Default control socket directory: `$XDG_RUNTIME_DIR/arsync-ssh`, else per-user in /tmp
+///
+/// Kept short because Unix socket paths are limited to ~104 bytes.
+fn default_control_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR").map_or_else(
+        || {
+            // SAFETY: getuid has no preconditions and cannot fail
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/arsync-ssh-{uid}"))
+        },
+        |dir| PathBuf::from(dir).join("arsync-ssh"),
+    )
+}
+
+/// Pool of multiplexed SSH connections keyed by `(user, host, port)`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use arsync::protocol::ssh::{SshPool, SshPoolConfig, SshTarget};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let pool = SshPool::new(SshPoolConfig::default());
+/// let target = SshTarget { user: "alice".into(), host: "backup".into(), port: None };
+/// let first = pool.connect(&target, &[]).await?; // authenticates once
+/// let second = pool.connect(&target, &[]).await?; // reuses the master connection
+/// # Ok(())
+/// # }
+/// ```
+pub struct SshPool {
+    /// Pool configuration
+    config: SshPoolConfig,
+    /// Targets with a master started by this pool (serializes master startup)
+    masters: futures::lock::Mutex<HashSet<SshTarget>>,
+}
+
+impl SshPool {
+    /// Create an empty pool
+    #[must_use]
+    pub fn new(config: SshPoolConfig) -> Self {
+        Self {
+            config,
+            masters: futures::lock::Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Open a session to `target`, multiplexed over its master connection
+    ///
+    /// Starts (and authenticates) the master on first use or after it exited
+    /// because it was idle. `server_args` are passed to the remote server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the control directory cannot be created, the master
+    /// connection cannot be established, or the session process fails to spawn.
+    pub async fn connect(
+        &self,
+        target: &SshTarget,
+        server_args: &[&OsStr],
+    ) -> Result<SshConnection> {
+        self.connect_command(target, &server_command(server_args))
+            .await
+    }
+
+    /// Run `arsync REMOTE_ARGS...` on `target` over its master connection
+    ///
+    /// Like `connect`, but for remote commands other than the transfer server.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Self::connect`].
+    pub async fn connect_command(
+        &self,
+        target: &SshTarget,
+        remote_args: &[&OsStr],
+    ) -> Result<SshConnection> {
+        let control_path = self.ensure_master(target).await?;
+        let mut options = target.port_options();
+        options.extend(session_options(&control_path));
+        SshConnection::spawn(
+            &target.host,
+            &target.user,
+            &self.config.shell,
+            &options,
+            remote_args,
+        )
+    }
+
+    /// Close the master connection for `target`, if it is running
+    ///
+    /// Sessions already multiplexed over it are terminated as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the control command cannot be run.
+    pub async fn close(&self, target: &SshTarget) -> Result<()> {
+        let mut masters = self.masters.lock().await;
+        let control_path = self.control_path(target);
+        if self.control_command(target, &control_path, "check").await? {
+            self.control_command(target, &control_path, "exit").await?;
+        }
+        masters.remove(target);
+        Ok(())
+    }
+
+    /// Close every master connection started by this pool
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a control command cannot be run.
+    pub async fn close_all(&self) -> Result<()> {
+        let targets: Vec<SshTarget> = self.masters.lock().await.iter().cloned().collect();
+        for target in &targets {
+            self.close(target).await?;
+        }
+        Ok(())
+    }
+
+    /// Control socket path for `target`
+    #[must_use]
+    pub fn control_path(&self, target: &SshTarget) -> PathBuf {
+        self.config
+            .control_dir
+            .join(format!("{:016x}", target.stable_hash()))
+    }
+
+    /// Make sure a live master exists for `target`, returning its control path
+    async fn ensure_master(&self, target: &SshTarget) -> Result<PathBuf> {
+        let mut masters = self.masters.lock().await;
+        let control_path = self.control_path(target);
+
+        if self.control_command(target, &control_path, "check").await? {
+            debug!("Reusing SSH master for {}", target.destination());
+            masters.insert(target.clone());
+            return Ok(control_path);
+        }
+
+        create_private_dir(&self.config.control_dir)?;
+        debug!(
+            "Starting SSH master for {} (idle timeout {:?})",
+            target.destination(),
+            self.config.idle_timeout
+        );
+
+        // -f: ssh backgrounds itself once authenticated, so exit means "ready".
+        // The backgrounded master would keep a stderr pipe open for as long as
+        // it lives, so ssh logs to a file instead (-E)
+        let log = master_log(&control_path);
+        std::fs::write(&log, b"")
+            .with_context(|| format!("Failed to create SSH log {}", log.display()))?;
+        let mut cmd = Command::new(self.config.shell.program());
+        cmd.args(self.config.shell.options())
+            .args(target.port_options())
+            .args(master_options(&control_path, self.config.idle_timeout))
+            .arg("-E")
+            .arg(&log)
+            .arg(target.destination());
+        cmd.stdin(Stdio::inherit())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stdin"))?;
+        cmd.stdout(Stdio::null())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stdout"))?;
+        cmd.stderr(Stdio::inherit())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stderr"))?;
+        let status = cmd
+            .status()
+            .await
+            .context("Failed to spawn SSH master process")?;
+        let diagnostics: Vec<String> = std::fs::read_to_string(&log)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+        for line in &diagnostics {
+            warn!("{}: {line}", target.host);
+        }
+        if !status.success() {
+            return Err(ConnectionFailed {
+                host: target.host.clone(),
+                reason: diagnostics
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| status.to_string()),
+                transient: status.code() == Some(SSH_CONNECTION_FAILED)
+                    && is_transient(&diagnostics),
+            }
+            .into());
+        }
+
+        masters.insert(target.clone());
+        Ok(control_path)
+    }
+
+    /// Run `ssh -O <command>` against a control socket; returns whether it succeeded
+    async fn control_command(
+        &self,
+        target: &SshTarget,
+        control_path: &Path,
+        command: &str,
+    ) -> Result<bool> {
+        if !control_path.exists() {
+            return Ok(false);
+        }
+        let mut cmd = Command::new(self.config.shell.program());
+        cmd.args(self.config.shell.options())
+            .args(target.port_options())
+            .arg("-O")
+            .arg(command)
+            .arg("-o")
+            .arg(format!("ControlPath={}", control_path.display()))
+            .arg(target.destination());
+        cmd.stdin(Stdio::null())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stdin"))?;
+        cmd.stdout(Stdio::null())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stdout"))?;
+        cmd.stderr(Stdio::null())
+            .map_err(|_| anyhow::anyhow!("Failed to configure stderr"))?;
+        let status = cmd
+            .status()
+            .await
+            .with_context(|| format!("Failed to run ssh -O {command}"))?;
+        Ok(status.success())
+    }
+}
+
+/// SSH options that start a backgrounded master with an idle timeout
+fn master_options(control_path: &Path, idle_timeout: Duration) -> Vec<String> {
+    vec![
+        "-M".to_string(),
+        "-N".to_string(),
+        "-f".to_string(),
+        "-o".to_string(),
+        format!("ControlPath={}", control_path.display()),
+        "-o".to_string(),
+        format!("ControlPersist={}s", idle_timeout.as_secs().max(1)),
+    ]
+}
+
+/// Where the master for `control_path` logs (`ssh -E`)
+fn master_log(control_path: &Path) -> PathBuf {
+    control_path.with_extension("log")
+}
+
+/// SSH options that multiplex a session over an existing master
+fn session_options(control_path: &Path) -> Vec<String> {
+    vec![
+        "-o".to_string(),
+        "ControlMaster=no".to_string(),
+        "-o".to_string(),
+        format!("ControlPath={}", control_path.display()),
+    ]
+}
+
+/// Create `dir` (and parents) and restrict it to the current user
+fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create SSH control directory {}", dir.display()))?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to secure SSH control directory {}", dir.display()))?;
+    Ok(())
 }
 
 // ============================================================================
@@ -128,8 +893,13 @@ impl SshConnection {
 
 impl AsyncRead for SshConnection {
     async fn read<B: compio::buf::IoBufMut>(&mut self, buf: B) -> compio::buf::BufResult<usize, B> {
-        // Delegate to stdout
-        self.stdout.read(buf).await
+        // Delegate to stdout, counting what arrives: a connection that
+        // failed after the remote command spoke is not retried
+        let BufResult(result, buf) = self.stdout.read(buf).await;
+        if let Ok(n) = result {
+            self.received += n as u64;
+        }
+        BufResult(result, buf)
     }
 }
 
@@ -168,6 +938,8 @@ impl Transport for SshConnection {
 
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::unwrap_used)]
+
     use super::*;
 
     #[test]
@@ -235,6 +1007,189 @@ mod tests {
         assert_unpin::<SshConnection>();
     }
 
+    fn target(port: Option<u16>) -> SshTarget {
+        SshTarget {
+            user: "alice".to_string(),
+            host: "backup.example.com".to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_pool_control_path_is_stable_and_per_target() {
+        // Test: Control paths are deterministic and distinct per (user, host, port)
+        // Requirement: Later runs must find the same master; targets must not collide
+        let pool = SshPool::new(SshPoolConfig {
+            control_dir: PathBuf::from("/run/user/1000/arsync-ssh"),
+            ..SshPoolConfig::default()
+        });
+        assert_eq!(
+            pool.control_path(&target(None)),
+            pool.control_path(&target(None))
+        );
+        assert_ne!(
+            pool.control_path(&target(None)),
+            pool.control_path(&target(Some(2222)))
+        );
+        // Unix socket paths must stay well below the ~104 byte limit
+        assert!(pool.control_path(&target(None)).as_os_str().len() < 100);
+    }
+
+    #[test]
+    fn test_pool_ssh_options() {
+        // Test: Master and session commands use the same control socket
+        // Requirement: Sessions must multiplex over the master, never start their own
+        let control_path = Path::new("/tmp/arsync-ssh-1000/0123456789abcdef");
+        let master = master_options(control_path, Duration::from_secs(90));
+        assert!(master.contains(&"-M".to_string()));
+        assert!(master.contains(&"ControlPersist=90s".to_string()));
+
+        let session = session_options(control_path);
+        assert!(session.contains(&"ControlMaster=no".to_string()));
+        let control_option = format!("ControlPath={}", control_path.display());
+        assert!(master.contains(&control_option));
+        assert!(session.contains(&control_option));
+
+        assert_eq!(target(Some(2222)).port_options(), vec!["-p", "2222"]);
+        assert!(target(None).port_options().is_empty());
+    }
+
+    #[test]
+    fn test_transient_failures_are_recognized() {
+        // Requirement: Refused, timed-out and unreachable connections are
+        // retried; authentication and host key failures are not
+        let lines = |line: &str| vec![line.to_string()];
+        for line in [
+            "ssh: connect to host backup port 22: Connection refused",
+            "ssh: connect to host backup port 22: Connection timed out",
+            "ssh: connect to host backup port 22: No route to host",
+            "ssh: Could not resolve hostname backup: Temporary failure in name resolution",
+            "kex_exchange_identification: read: Connection reset by peer",
+        ] {
+            assert!(is_transient(&lines(line)), "{line}");
+        }
+        for line in [
+            "alice@backup: Permission denied (publickey).",
+            "Host key verification failed.",
+            "ssh: Could not resolve hostname nosuchhost: Name or service not known",
+        ] {
+            assert!(!is_transient(&lines(line)), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_retry_policy_backs_off() {
+        // Requirement: Retries wait 1s, 2s, 4s, ... up to the maximum, and
+        // only transient connection failures are retried
+        let policy = RetryPolicy {
+            retries: 3,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(10), Duration::from_secs(30));
+        assert_eq!(policy.delay(40), Duration::from_secs(30));
+
+        let failure = |transient| {
+            anyhow::anyhow!("rsync server closed the connection").context(ConnectionFailed {
+                host: "backup".to_string(),
+                reason: "Connection refused".to_string(),
+                transient,
+            })
+        };
+        assert_eq!(
+            policy.retry_after(1, &failure(true)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.retry_after(3, &failure(true)), None);
+        assert_eq!(policy.retry_after(0, &failure(false)), None);
+        assert_eq!(policy.retry_after(0, &anyhow::anyhow!("EIO")), None);
+    }
+
+    /// Remote shell that fails like ssh for its first `failures` runs, then
+    /// prints "hello"; returns the connector and the file counting runs
+    fn flaky_shell(dir: &Path, failures: u32, message: &str) -> (SshConnector, PathBuf) {
+        use std::os::unix::fs::PermissionsExt;
+        let counter = dir.join("runs");
+        let script = dir.join("fake-ssh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 n=$(($(cat '{counter}' 2>/dev/null || echo 0) + 1))\n\
+                 echo $n > '{counter}'\n\
+                 if [ $n -le {failures} ]; then echo '{message}' >&2; exit 255; fi\n\
+                 echo 'remote warning' >&2\n\
+                 echo hello\n",
+                counter = counter.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let shell = RemoteShell::parse(&script.to_string_lossy(), "arsync").unwrap();
+        let connector = SshConnector::new(shell).with_retry(RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        });
+        (connector, counter)
+    }
+
+    /// Read everything the remote command sends; nothing at all is an error
+    async fn read_all(connector: &SshConnector) -> Result<Vec<u8>> {
+        use compio::io::AsyncReadExt;
+        connector
+            .session("", "backup", &[], |mut connection| async move {
+                let BufResult(result, data) = connection.read_to_end(Vec::new()).await;
+                let result = result.map_err(anyhow::Error::from).and_then(|_| {
+                    anyhow::ensure!(!data.is_empty(), "Connection closed");
+                    Ok(data)
+                });
+                connection.finish(result).await
+            })
+            .await
+    }
+
+    fn runs(counter: &Path) -> String {
+        std::fs::read_to_string(counter).unwrap().trim().to_string()
+    }
+
+    #[compio::test]
+    async fn test_session_reconnects_after_transient_failures() {
+        // Requirement: A session whose connection is refused is started again
+        // with backoff, and the remote shell's stderr is captured
+        let temp = tempfile::TempDir::new().unwrap();
+        let message = "ssh: connect to host backup port 22: Connection refused";
+        let (connector, counter) = flaky_shell(temp.path(), 2, message);
+        let data = read_all(&connector).await.unwrap();
+        assert_eq!(data, b"hello\n");
+        assert_eq!(runs(&counter), "3");
+
+        let (connector, counter) = flaky_shell(temp.path(), 9, message);
+        std::fs::remove_file(&counter).unwrap();
+        let error = read_all(&connector).await.unwrap_err();
+        let failure = error.downcast_ref::<ConnectionFailed>().unwrap();
+        assert!(failure.transient);
+        assert_eq!(failure.reason, message);
+        assert_eq!(runs(&counter), "3");
+    }
+
+    #[compio::test]
+    async fn test_session_does_not_retry_permanent_failures() {
+        // Requirement: Authentication failures are reported at once, with
+        // what ssh said
+        let temp = tempfile::TempDir::new().unwrap();
+        let message = "alice@backup: Permission denied (publickey).";
+        let (connector, counter) = flaky_shell(temp.path(), 1, message);
+        let error = read_all(&connector).await.unwrap_err();
+        assert!(!error.downcast_ref::<ConnectionFailed>().unwrap().transient);
+        assert!(
+            format!("{error:#}").contains("Permission denied"),
+            "{error:#}"
+        );
+        assert_eq!(runs(&counter), "1");
+    }
+
     // Note: Full integration tests for SSH connections would require:
     // - SSH server setup (sshd)
     // - Authentication configuration (keys or passwords)
@@ -0,0 +1,438 @@
+//! TCP transport with async DNS resolution and Happy Eyeballs connection racing
+//!
+//! Used by the native daemon client, which connects directly instead of going
+//! through ssh(1), and by `arsync --daemon` for the connections it accepts.
+//! Connection setup follows Happy Eyeballs v2 (RFC 8305) so that dual-stack
+//! hosts connect quickly even when one address family is broken:
+//!
+//! 1. **Resolution**: AAAA and A lookups run concurrently on the blocking pool
+//!    (`getaddrinfo` has no async interface). Whichever answers first, the
+//!    other gets a short grace period (`resolution_delay`); a lookup that
+//!    answers later still contributes addresses to the race.
+//! 2. **Ordering**: addresses are interleaved by family, IPv6 first.
+//! 3. **Racing**: a new connection attempt starts every `attempt_delay` (or as
+//!    soon as the previous one fails); the first to succeed wins and the
+//!    others are dropped.
+//!
+//! Every lookup and attempt is bounded by a timeout, and failures report each
+//! address tried with its error.
+//!
+//! # Architecture
+//!
+//! ```text
+//! TcpTransport
+//!     ↓
+//! compio::net::TcpStream
+//!     ↓
+//! compio AsyncRead/AsyncWrite
+//!     ↓
+//! io_uring operations
+//! ```
+#![allow(dead_code)] // Protocol implementation not yet fully used
+#![allow(clippy::future_not_send)] // compio buffers are not Send by design
+
+use super::transport::Transport;
+use anyhow::Result;
+use compio::io::{AsyncRead, AsyncWrite};
+use compio::net::TcpStream;
+use futures::future::FusedFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use std::ffi::{CStr, CString};
+use std::fmt::Write as _;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+use tracing::debug;
+
+/// Timeouts and delays for connection setup
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectConfig {
+    /// Maximum time for each DNS lookup (AAAA and A separately)
+    pub resolve_timeout: Duration,
+    /// How long to wait for the other address family after the first answers
+    pub resolution_delay: Duration,
+    /// Delay before starting the next connection attempt in parallel
+    pub attempt_delay: Duration,
+    /// Maximum time for each individual connection attempt
+    pub connect_timeout: Duration,
+}
+
+impl Default for ConnectConfig {
+    /// RFC 8305 recommended delays with conservative timeouts
+    fn default() -> Self {
+        Self {
+            resolve_timeout: Duration::from_secs(5),
+            resolution_delay: Duration::from_millis(50),
+            attempt_delay: Duration::from_millis(250),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// TCP connection to a remote arsync daemon
+pub struct TcpTransport {
+    /// Connected stream
+    stream: TcpStream,
+    /// Address that won the connection race
+    peer: SocketAddr,
+}
+
+impl TcpTransport {
+    /// Resolve `host` and connect to `port`, racing addresses (Happy Eyeballs)
+    ///
+    /// `host` may be a hostname or an IP literal (IPv6 with or without brackets).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - Neither the AAAA nor the A lookup yields an address (both errors reported)
+    /// - Every connection attempt fails or times out (each address reported)
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use arsync::protocol::tcp::{ConnectConfig, TcpTransport};
+    ///
+    /// # async fn example() -> anyhow::Result<()> {
+    /// let transport = TcpTransport::connect("backup.example.com", 873, &ConnectConfig::default()).await?;
+    /// println!("Connected to {}", transport.peer_addr());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(host: &str, port: u16, config: &ConnectConfig) -> Result<Self> {
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return race_connect(
+                host,
+                vec![SocketAddr::new(ip, port)],
+                None,
+                Vec::new(),
+                config,
+            )
+            .await;
+        }
+
+        let v6 = resolve_family(host, port, libc::AF_INET6, config.resolve_timeout).boxed_local();
+        let v4 = resolve_family(host, port, libc::AF_INET, config.resolve_timeout).boxed_local();
+
+        // Wait for the first answer, then give the other family a grace period
+        let (first, other) = match futures::future::select(v6, v4).await {
+            futures::future::Either::Left((v6_result, v4)) => (v6_result, v4),
+            futures::future::Either::Right((v4_result, v6)) => (v4_result, v6),
+        };
+        let mut other = other.fuse();
+        let grace = compio::time::sleep(config.resolution_delay).fuse();
+        futures::pin_mut!(grace);
+        let second = futures::select! {
+            result = other => Some(result),
+            () = grace => None,
+        };
+
+        let mut addrs = Vec::new();
+        let mut errors = Vec::new();
+        for result in std::iter::once(first).chain(second) {
+            match result {
+                Ok(found) => addrs.extend(found),
+                Err(e) => errors.push(e),
+            }
+        }
+        // The slower lookup keeps running and can still join the race
+        let late = (!other.is_terminated()).then_some(other);
+
+        if addrs.is_empty() && late.is_none() {
+            let mut message = format!("Failed to resolve {host}");
+            for e in &errors {
+                let _ = write!(message, "; {e}");
+            }
+            anyhow::bail!(message);
+        }
+
+        race_connect(host, interleave(addrs), late, errors, config).await
+    }
+
+    /// Wrap a connection accepted by a listener (the daemon's side)
+    #[must_use]
+    pub const fn accepted(stream: TcpStream, peer: SocketAddr) -> Self {
+        Self { stream, peer }
+    }
+
+    /// Address of the remote end
+    #[must_use]
+    pub const fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+/// A pending DNS lookup whose addresses may still join the race
+type LateLookup<'a> =
+    futures::future::Fuse<futures::future::LocalBoxFuture<'a, io::Result<Vec<SocketAddr>>>>;
+
+/// Race connection attempts to `addrs`, staggered by `attempt_delay`
+///
+/// `resolve_errors` are lookup failures so far; they are included in the
+/// error message if no connection can be made.
+async fn race_connect(
+    host: &str,
+    addrs: Vec<SocketAddr>,
+    late: Option<LateLookup<'_>>,
+    mut resolve_errors: Vec<io::Error>,
+    config: &ConnectConfig,
+) -> Result<TcpTransport> {
+    let mut queue = std::collections::VecDeque::from(addrs);
+    let mut late = late;
+    let mut attempts = FuturesUnordered::new();
+    let mut failures: Vec<(SocketAddr, io::Error)> = Vec::new();
+    let connect_timeout = config.connect_timeout;
+
+    loop {
+        if let Some(addr) = queue.pop_front() {
+            debug!("Connecting to {host} via {addr}");
+            attempts.push(async move {
+                let result = compio::time::timeout(connect_timeout, TcpStream::connect(addr))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("timed out after {connect_timeout:?}"),
+                        ))
+                    });
+                (addr, result)
+            });
+        }
+
+        if attempts.is_empty() && late.is_none() {
+            break;
+        }
+
+        // Wake up for the next staggered attempt only if there is one to start
+        let stagger = if queue.is_empty() {
+            futures::future::pending().left_future()
+        } else {
+            compio::time::sleep(config.attempt_delay).right_future()
+        }
+        .fuse();
+        futures::pin_mut!(stagger);
+        let mut late_lookup = match late.as_mut() {
+            Some(lookup) => lookup.left_future(),
+            None => futures::future::pending().right_future(),
+        };
+
+        futures::select! {
+            finished = attempts.select_next_some() => {
+                let (addr, result) = finished;
+                match result {
+                    Ok(stream) => {
+                        debug!("Connected to {host} via {addr}");
+                        return Ok(TcpTransport { stream, peer: addr });
+                    }
+                    // Start the next attempt immediately
+                    Err(e) => failures.push((addr, e)),
+                }
+            }
+            result = late_lookup => {
+                late = None;
+                match result {
+                    Ok(found) => {
+                        queue.extend(found);
+                        queue = interleave(queue.into()).into();
+                    }
+                    Err(e) => resolve_errors.push(e),
+                }
+            }
+            () = stagger => {}
+        }
+    }
+
+    let mut message = format!("Failed to connect to {host}");
+    if failures.is_empty() {
+        message.push_str(": no addresses found");
+    }
+    for (addr, e) in &failures {
+        let _ = write!(message, "; {addr}: {e}");
+    }
+    for e in &resolve_errors {
+        let _ = write!(message, "; {e}");
+    }
+    anyhow::bail!(message)
+}
+
+/// Interleave addresses by family, IPv6 first (RFC 8305 section 4)
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    v6.dedup();
+    v4.dedup();
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Resolve `host` for one address family on the blocking pool, with a timeout
+async fn resolve_family(
+    host: &str,
+    port: u16,
+    family: libc::c_int,
+    timeout: Duration,
+) -> io::Result<Vec<SocketAddr>> {
+    let label = if family == libc::AF_INET6 {
+        "AAAA"
+    } else {
+        "A"
+    };
+    let owned_host = host.to_string();
+    let lookup =
+        compio::runtime::spawn_blocking(move || getaddrinfo_family(&owned_host, port, family));
+    match compio::time::timeout(timeout, lookup).await {
+        Ok(Ok(result)) => result.map_err(|e| io::Error::new(e.kind(), format!("{label}: {e}"))),
+        Ok(Err(e)) => Err(io::Error::other(format!(
+            "{label}: spawn_blocking failed: {e:?}"
+        ))),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{label}: lookup timed out after {timeout:?}"),
+        )),
+    }
+}
+
+/// `getaddrinfo(3)` restricted to one address family
+fn getaddrinfo_family(host: &str, port: u16, family: libc::c_int) -> io::Result<Vec<SocketAddr>> {
+    let c_host = CString::new(host)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name contains NUL byte"))?;
+
+    // SAFETY: addrinfo is a plain C struct; all-zero is a valid "no hints" value
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_family = family;
+    hints.ai_socktype = libc::SOCK_STREAM;
+
+    let mut result: *mut libc::addrinfo = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call
+    let rc = unsafe { libc::getaddrinfo(c_host.as_ptr(), std::ptr::null(), &hints, &mut result) };
+    if rc != 0 {
+        // SAFETY: gai_strerror returns a static NUL-terminated string
+        let message = unsafe { CStr::from_ptr(libc::gai_strerror(rc)) };
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            message.to_string_lossy().into_owned(),
+        ));
+    }
+
+    let mut addrs = Vec::new();
+    let mut current = result;
+    while !current.is_null() {
+        // SAFETY: current is a node of the list returned by getaddrinfo
+        let info = unsafe { &*current };
+        match info.ai_family {
+            libc::AF_INET6 => {
+                // SAFETY: ai_family guarantees ai_addr points to a sockaddr_in6
+                let sa = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in6>() };
+                addrs.push(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sa.sin6_addr.s6_addr),
+                    port,
+                    sa.sin6_flowinfo,
+                    sa.sin6_scope_id,
+                )));
+            }
+            libc::AF_INET => {
+                // SAFETY: ai_family guarantees ai_addr points to a sockaddr_in
+                let sa = unsafe { &*info.ai_addr.cast::<libc::sockaddr_in>() };
+                addrs.push(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr)),
+                    port,
+                )));
+            }
+            _ => {}
+        }
+        current = info.ai_next;
+    }
+    // SAFETY: result came from a successful getaddrinfo call and is freed once
+    unsafe { libc::freeaddrinfo(result) };
+
+    Ok(addrs)
+}
+
+// ============================================================================
+// compio AsyncRead/AsyncWrite Implementation (delegates to stream)
+// ============================================================================
+
+impl AsyncRead for TcpTransport {
+    async fn read<B: compio::buf::IoBufMut>(&mut self, buf: B) -> compio::buf::BufResult<usize, B> {
+        self.stream.read(buf).await
+    }
+}
+
+impl AsyncWrite for TcpTransport {
+    async fn write<B: compio::buf::IoBuf>(&mut self, buf: B) -> compio::buf::BufResult<usize, B> {
+        self.stream.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown().await
+    }
+}
+
+impl Transport for TcpTransport {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave_prefers_ipv6_and_alternates() {
+        let v6a: SocketAddr = "[2001:db8::1]:873".parse().unwrap();
+        let v6b: SocketAddr = "[2001:db8::2]:873".parse().unwrap();
+        let v4a: SocketAddr = "192.0.2.1:873".parse().unwrap();
+        let v4b: SocketAddr = "192.0.2.2:873".parse().unwrap();
+        let v4c: SocketAddr = "192.0.2.3:873".parse().unwrap();
+
+        let ordered = interleave(vec![v4a, v4b, v6a, v4c, v6b]);
+        assert_eq!(ordered, vec![v6a, v4a, v6b, v4b, v4c]);
+    }
+
+    #[compio::test]
+    async fn test_connect_falls_back_across_families() {
+        // Listen on IPv4 only: "localhost" usually resolves to ::1 as well, and
+        // that attempt must fail over to 127.0.0.1
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let transport = TcpTransport::connect("localhost", port, &ConnectConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.peer_addr(),
+            SocketAddr::from(([127, 0, 0, 1], port))
+        );
+    }
+
+    #[compio::test]
+    async fn test_connect_reports_each_failed_address() {
+        // Bind then drop to get a port with nothing listening
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let error = TcpTransport::connect("127.0.0.1", port, &ConnectConfig::default())
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains(&format!("127.0.0.1:{port}")), "{error}");
+    }
+}
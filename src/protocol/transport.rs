@@ -51,7 +51,7 @@ use std::io;
 ///
 /// - `PipeTransport` - For testing via stdin/stdout or Unix pipes
 /// - `SshConnection` - For production remote sync over SSH
-/// - `TcpStream` - For direct network connections (future)
+/// - `TcpTransport` - For direct daemon connections (Happy Eyeballs connect)
 /// - `QuicConnection` - For QUIC-based transport (future)
 ///
 /// # Example Implementation
@@ -0,0 +1,331 @@
+//! Batched extended attribute transfer for the native pipe protocol
+//!
+//! Files can carry hundreds of xattrs (Samba stores DOS attributes, security
+//! labels, user metadata), so the pipe protocol sends all of a file's xattrs in
+//! one frame instead of one message per attribute. Batches are built under
+//! `XattrLimits`:
+//!
+//! - Values larger than `max_value_bytes` are skipped or truncated according
+//!   to `OversizePolicy`.
+//! - Once a file's batch reaches `max_batch_bytes`, further attributes are
+//!   dropped (never truncated), so one pathological file cannot stall the
+//!   stream.
+//!
+//! Every dropped or truncated attribute is counted in `XattrStats` and logged.
+//!
+//! # Wire Format
+//!
+//! ```text
+//! u32 frame length | u32 count | count × (u16 name length | name | u32 value length | value)
+//! ```
+//!
+//! All integers are little-endian. Only sent when both peers negotiated
+//! `Capability::XATTR_BATCH`.
+#![allow(dead_code)] // Protocol implementation not yet fully used
+
+use crate::protocol::transport::{self, Transport};
+use anyhow::Result;
+use std::path::Path;
+use tracing::warn;
+
+/// Largest value the Linux VFS accepts (`XATTR_SIZE_MAX`)
+pub const DEFAULT_MAX_VALUE_BYTES: usize = 64 * 1024;
+
+/// Default cap on one file's batch (names plus values)
+pub const DEFAULT_MAX_BATCH_BYTES: usize = 1024 * 1024;
+
+/// Hard upper bound on a received frame, independent of local limits
+const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// What to do with a value larger than `max_value_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizePolicy {
+    /// Leave the attribute out of the batch
+    #[default]
+    Skip,
+    /// Send only the first `max_value_bytes` bytes of the value
+    Truncate,
+}
+
+/// Size limits applied when building a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XattrLimits {
+    /// Largest single value sent as-is
+    pub max_value_bytes: usize,
+    /// Largest total size (names plus values) of one file's batch
+    pub max_batch_bytes: usize,
+    /// Handling of values over `max_value_bytes`
+    pub oversize: OversizePolicy,
+}
+
+impl Default for XattrLimits {
+    fn default() -> Self {
+        Self {
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            oversize: OversizePolicy::Skip,
+        }
+    }
+}
+
+/// Counters for batched xattr transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XattrStats {
+    /// Attributes included in batches
+    pub sent: u64,
+    /// Attributes left out because of size limits
+    pub dropped: u64,
+    /// Attributes sent with a truncated value
+    pub truncated: u64,
+    /// Value bytes not sent (dropped attributes and truncated tails)
+    pub bytes_dropped: u64,
+}
+
+/// All extended attributes of one file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XattrBatch {
+    /// `(name, value)` pairs
+    pub entries: Vec<(String, Vec<u8>)>,
+}
+
+impl XattrBatch {
+    /// Build a batch from attributes, enforcing `limits`
+    pub fn build(
+        attrs: impl IntoIterator<Item = (String, Vec<u8>)>,
+        limits: &XattrLimits,
+        stats: &mut XattrStats,
+    ) -> Self {
+        let mut entries = Vec::new();
+        let mut batch_bytes = 0usize;
+
+        for (name, mut value) in attrs {
+            if value.len() > limits.max_value_bytes {
+                match limits.oversize {
+                    OversizePolicy::Skip => {
+                        warn!(
+                            "Skipping xattr '{name}': {} bytes exceeds limit of {}",
+                            value.len(),
+                            limits.max_value_bytes
+                        );
+                        stats.dropped += 1;
+                        stats.bytes_dropped += value.len() as u64;
+                        continue;
+                    }
+                    OversizePolicy::Truncate => {
+                        warn!(
+                            "Truncating xattr '{name}' from {} to {} bytes",
+                            value.len(),
+                            limits.max_value_bytes
+                        );
+                        stats.truncated += 1;
+                        stats.bytes_dropped += (value.len() - limits.max_value_bytes) as u64;
+                        value.truncate(limits.max_value_bytes);
+                    }
+                }
+            }
+
+            let entry_bytes = name.len() + value.len();
+            if batch_bytes + entry_bytes > limits.max_batch_bytes {
+                warn!(
+                    "Dropping xattr '{name}': file exceeds xattr batch limit of {} bytes",
+                    limits.max_batch_bytes
+                );
+                stats.dropped += 1;
+                stats.bytes_dropped += value.len() as u64;
+                continue;
+            }
+
+            batch_bytes += entry_bytes;
+            stats.sent += 1;
+            entries.push((name, value));
+        }
+
+        Self { entries }
+    }
+
+    /// Read the xattrs of `path` (without following symlinks) into a batch
+    ///
+    /// Attributes that cannot be read are logged and left out, matching local
+    /// copies. A filesystem without xattr support yields an empty batch.
+    pub async fn collect(path: &Path, limits: &XattrLimits, stats: &mut XattrStats) -> Self {
+        use compio_fs_extended::xattr::{lget_xattr_at_path, llist_xattr_at_path};
+
+        let Ok(names) = llist_xattr_at_path(path).await else {
+            return Self::default();
+        };
+        let mut attrs = Vec::with_capacity(names.len());
+        for name in names {
+            match lget_xattr_at_path(path, &name).await {
+                Ok(value) => attrs.push((name, value)),
+                Err(e) => warn!("Failed to read extended attribute '{name}': {e}"),
+            }
+        }
+        Self::build(attrs, limits, stats)
+    }
+
+    /// Set every attribute in the batch on `path` (without following symlinks)
+    ///
+    /// Failures are logged and skipped, matching local copies. Returns the
+    /// number of attributes applied.
+    pub async fn apply(&self, path: &Path) -> usize {
+        use compio_fs_extended::xattr::lset_xattr_at_path;
+
+        let mut applied = 0;
+        for (name, value) in &self.entries {
+            match lset_xattr_at_path(path, name, value).await {
+                Ok(()) => applied += 1,
+                Err(e) => warn!("Failed to preserve extended attribute '{name}': {e}"),
+            }
+        }
+        applied
+    }
+
+    /// Encode the batch body (without the frame length)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a name or value exceeds its length field.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&u32::try_from(self.entries.len())?.to_le_bytes());
+        for (name, value) in &self.entries {
+            out.extend_from_slice(&u16::try_from(name.len())?.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&u32::try_from(value.len())?.to_le_bytes());
+            out.extend_from_slice(value);
+        }
+        Ok(out)
+    }
+
+    /// Decode a batch body
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is truncated or a name is not UTF-8.
+    pub fn decode(mut body: &[u8]) -> Result<Self> {
+        let count = u32::from_le_bytes(take_array(&mut body)?);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take_array(&mut body)?) as usize;
+            let name = String::from_utf8(take(&mut body, name_len)?.to_vec())?;
+            let value_len = u32::from_le_bytes(take_array(&mut body)?) as usize;
+            entries.push((name, take(&mut body, value_len)?.to_vec()));
+        }
+        if !body.is_empty() {
+            anyhow::bail!("Trailing bytes after xattr batch");
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn take<'a>(body: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if body.len() < len {
+        anyhow::bail!("Truncated xattr batch");
+    }
+    let (head, tail) = body.split_at(len);
+    *body = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(body: &mut &[u8]) -> Result<[u8; N]> {
+    let mut out = [0u8; N];
+    out.copy_from_slice(take(body, N)?);
+    Ok(out)
+}
+
+/// Send one file's xattr batch
+///
+/// # Errors
+///
+/// Returns an error if encoding or writing fails.
+pub async fn send_xattr_batch<T: Transport>(transport: &mut T, batch: &XattrBatch) -> Result<()> {
+    let body = batch.encode()?;
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&u32::try_from(body.len())?.to_le_bytes());
+    frame.extend_from_slice(&body);
+    transport::write_all(transport, &frame).await?;
+    Ok(())
+}
+
+/// Receive one file's xattr batch
+///
+/// # Errors
+///
+/// Returns an error if reading fails or the frame is oversized or malformed.
+pub async fn receive_xattr_batch<T: Transport>(transport: &mut T) -> Result<XattrBatch> {
+    let mut len_buf = [0u8; 4];
+    transport::read_exact(transport, &mut len_buf).await?;
+    let len = u32::from_le_bytes(len_buf) as usize;
+    if len > MAX_FRAME_BYTES {
+        anyhow::bail!("xattr batch too large: {len} bytes");
+    }
+    let mut body = vec![0u8; len];
+    transport::read_exact(transport, &mut body).await?;
+    XattrBatch::decode(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn attrs() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("user.DOSATTRIB".to_string(), vec![0x20; 16]),
+            ("user.big".to_string(), vec![7; 100]),
+            ("user.small".to_string(), vec![1, 2, 3]),
+        ]
+    }
+
+    #[test]
+    fn test_batch_round_trip() {
+        // Test: Many attributes survive encode/decode in a single frame
+        // Requirement: Hundreds of xattrs must travel as one message
+        let many: Vec<_> = (0..300)
+            .map(|i| (format!("user.attr{i}"), vec![i as u8; i % 17]))
+            .collect();
+        let mut stats = XattrStats::default();
+        let batch = XattrBatch::build(many.clone(), &XattrLimits::default(), &mut stats);
+        assert_eq!(stats.sent, 300);
+        let decoded = XattrBatch::decode(&batch.encode().unwrap()).unwrap();
+        assert_eq!(decoded.entries, many);
+        assert!(XattrBatch::decode(&batch.encode().unwrap()[..10]).is_err());
+    }
+
+    #[test]
+    fn test_oversize_policies() {
+        // Test: Oversized values are skipped or truncated, and counted
+        let mut limits = XattrLimits {
+            max_value_bytes: 50,
+            ..XattrLimits::default()
+        };
+        let mut stats = XattrStats::default();
+        let batch = XattrBatch::build(attrs(), &limits, &mut stats);
+        assert_eq!(batch.entries.len(), 2);
+        assert_eq!(
+            (stats.sent, stats.dropped, stats.bytes_dropped),
+            (2, 1, 100)
+        );
+
+        limits.oversize = OversizePolicy::Truncate;
+        let mut stats = XattrStats::default();
+        let batch = XattrBatch::build(attrs(), &limits, &mut stats);
+        assert_eq!(batch.entries[1].1.len(), 50);
+        assert_eq!((stats.truncated, stats.bytes_dropped), (1, 50));
+    }
+
+    #[test]
+    fn test_batch_size_cap_drops_remaining() {
+        // Test: Attributes beyond the per-file cap are dropped, not truncated
+        let limits = XattrLimits {
+            max_batch_bytes: 64,
+            ..XattrLimits::default()
+        };
+        let mut stats = XattrStats::default();
+        let batch = XattrBatch::build(attrs(), &limits, &mut stats);
+        let names: Vec<_> = batch.entries.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["user.DOSATTRIB", "user.small"]);
+        assert_eq!((stats.dropped, stats.truncated), (1, 0));
+    }
+}
@@ -0,0 +1,96 @@
+//! What one run decides and collects on the way
+//!
+//! The options of a run are its `SyncConfig`. What it works out from them
+//! (the destination's tuning profile, the copy buffer pools, the timings of
+//! its device pairs) and what it collects until its end (the
+//! `--delay-updates` staged files, the `--emit-checksums` list, its metrics,
+//! server-side copy counts and held-back warnings) belong to the run too,
+//! not to the process: under `arsync serve` several jobs sync at once, each
+//! to its own destination with its own options. [`RunState`] holds it;
+//! `sync` makes one per run and puts it in the run's `MetadataConfig`,
+//! which every copy of the run is handed.
+//!
+//! A caller that needs to tell its runs apart, or to read a run's metrics
+//! while it goes, hands `sync` a config whose state carries the run's
+//! [`RunState::job`] and [`RunState::metrics`]; the state made for the run
+//! keeps them. The job is on every event the run publishes.
+//!
+//! Copies made outside `sync_files` (`copy_file`, `CopyTask`, tar imports)
+//! get the default state of the config they are given: the generic profile
+//! and pools that keep no buffers.
+
+use crate::checksum_list::ChecksumList;
+use crate::config::SyncConfig;
+use crate::delay_updates::DelayedUpdates;
+use crate::device::DeviceTimings;
+use crate::metrics::Metrics;
+use crate::offload::OffloadStats;
+use crate::pipelines::BufferPools;
+use crate::tuning::TuningProfile;
+use crate::warnings::WarningLog;
+use std::fmt;
+use std::sync::Arc;
+
+/// State of one run, shared by its copies
+#[derive(Default)]
+pub struct RunState {
+    /// Job the run belongs to, on its events (0 outside `arsync serve`)
+    pub job: u64,
+    /// Tuning profile of the destination
+    pub tuning: TuningProfile,
+    /// Copy buffers of the small-file and large-file pipelines
+    pub buffers: BufferPools,
+    /// Files staged until the end of the run (`--delay-updates`)
+    pub delayed: DelayedUpdates,
+    /// Hashes of the copied files (`--emit-checksums`)
+    pub checksums: ChecksumList,
+    /// Counters served by `--metrics-listen` and written by `--metrics-textfile`
+    pub metrics: Arc<Metrics>,
+    /// Files copied server-side on network mounts
+    pub offload: OffloadStats,
+    /// What `--parallel-adaptive` learned about each device pair
+    pub devices: DeviceTimings,
+    /// Per-entry warnings, grouped and summarized at the end
+    pub warnings: WarningLog,
+}
+
+impl RunState {
+    /// State of a run with `config` copying with `tuning`, keeping the job
+    /// and metrics of the state `config` came with
+    #[must_use]
+    pub fn new(config: &SyncConfig, tuning: TuningProfile) -> Self {
+        let metrics = Arc::clone(&config.metadata.run.metrics);
+        metrics.set_queue_depth(config.io.queue_depth);
+        metrics.start();
+        Self {
+            job: config.metadata.run.job,
+            tuning,
+            buffers: BufferPools::new(
+                &config.concurrency,
+                tuning.buffer_size,
+                config.io.queue_depth,
+            ),
+            delayed: DelayedUpdates::default(),
+            checksums: ChecksumList::new(
+                config
+                    .output
+                    .emit_checksums
+                    .as_ref()
+                    .map(|_| config.output.checksum_algorithm),
+            ),
+            metrics,
+            offload: OffloadStats::default(),
+            devices: DeviceTimings::default(),
+            warnings: WarningLog::default(),
+        }
+    }
+}
+
+impl fmt::Debug for RunState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunState")
+            .field("job", &self.job)
+            .field("tuning", &self.tuning.profile)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,376 @@
+//! Validation against rsync after a run (`--shadow-rsync`)
+//!
+//! For users migrating from rsync: once arsync has finished, rsync is run in
+//! dry-run itemize mode (`rsync -n -i`) from the same source to each
+//! destination, with the rsync options equivalent to arsync's. Whatever rsync
+//! would still change is a difference between arsync's result and rsync's,
+//! and is reported with the attributes it concerns (contents, times,
+//! permissions, ownership, ACLs, xattrs, hard links, missing or extraneous
+//! entries), so semantic gaps show up without comparing trees by hand.
+//!
+//! arsync's own files at the destination (`.arsync*`: lock, long-name
+//! manifest, deletion journal, temporary and partial files) are protected from
+//! rsync's `--delete`. `--crtimes` has no rsync equivalent on Linux and is not
+//! passed on.
+
+use crate::cli::FilterOption;
+use crate::config::SyncConfig;
+use crate::error::{ErrorContext, Result, SyncError};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+/// Program run to validate the destination
+pub const RSYNC_PROGRAM: &str = "rsync";
+
+/// rsync exit codes for a run that skipped some files (23: partial transfer
+/// due to an error, 24: source files vanished); its output is still valid
+const RSYNC_PARTIAL_EXIT_CODES: [i32; 2] = [23, 24];
+
+/// What a change rsync would make concerns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Gap {
+    /// The entry is missing from the destination
+    Missing,
+    /// The destination has an entry the source does not (with `--delete`)
+    Extraneous,
+    /// File contents, size or symlink target
+    Contents,
+    /// Modification time
+    ModTime,
+    /// Permissions
+    Permissions,
+    /// Owner
+    Owner,
+    /// Group
+    Group,
+    /// Access or creation time
+    OtherTimes,
+    /// POSIX ACL
+    Acl,
+    /// Extended attributes
+    Xattrs,
+    /// Hard link to another destination file
+    HardLink,
+}
+
+impl Gap {
+    /// Description for the report
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Missing => "missing from the destination",
+            Self::Extraneous => "not deleted from the destination",
+            Self::Contents => "contents or size differ",
+            Self::ModTime => "modification time differs",
+            Self::Permissions => "permissions differ",
+            Self::Owner => "owner differs",
+            Self::Group => "group differs",
+            Self::OtherTimes => "access or creation time differs",
+            Self::Acl => "ACL differs",
+            Self::Xattrs => "extended attributes differ",
+            Self::HardLink => "hard link not preserved",
+        }
+    }
+}
+
+/// One change rsync would still make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// rsync's itemized change code (`YXcstpoguax`, or `*deleting`)
+    pub code: String,
+    /// Path relative to the destination, as rsync prints it
+    pub path: String,
+}
+
+impl Difference {
+    /// What the change concerns (at least one entry)
+    #[must_use]
+    pub fn gaps(&self) -> Vec<Gap> {
+        let code = self.code.as_bytes();
+        match code.first() {
+            Some(b'*') => return vec![Gap::Extraneous],
+            // Linked (or to be linked) to an earlier file of the same inode
+            Some(b'h') => return vec![Gap::HardLink],
+            _ => {}
+        }
+        let attributes = code.get(2..).unwrap_or_default();
+        if !attributes.is_empty() && attributes.iter().all(|&flag| flag == b'+') {
+            return vec![Gap::Missing];
+        }
+
+        let mut gaps = Vec::new();
+        for &flag in attributes {
+            let gap = match flag {
+                b'c' | b's' => Gap::Contents,
+                b't' | b'T' => Gap::ModTime,
+                b'p' => Gap::Permissions,
+                b'o' => Gap::Owner,
+                b'g' => Gap::Group,
+                b'u' | b'n' | b'b' => Gap::OtherTimes,
+                b'a' => Gap::Acl,
+                b'x' => Gap::Xattrs,
+                _ => continue,
+            };
+            if !gaps.contains(&gap) {
+                gaps.push(gap);
+            }
+        }
+        // A transfer without a changed attribute still rewrites the data
+        if gaps.is_empty() {
+            gaps.push(Gap::Contents);
+        }
+        gaps
+    }
+}
+
+/// Changes rsync would still make to one destination
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShadowReport {
+    /// Itemized changes, in rsync's order
+    pub differences: Vec<Difference>,
+}
+
+impl ShadowReport {
+    /// Whether rsync would leave the destination as it is
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// Number of differences concerning each gap
+    #[must_use]
+    pub fn gap_counts(&self) -> BTreeMap<Gap, usize> {
+        let mut counts = BTreeMap::new();
+        for gap in self.differences.iter().flat_map(Difference::gaps) {
+            *counts.entry(gap).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+impl fmt::Display for ShadowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "rsync would make no changes");
+        }
+        writeln!(
+            f,
+            "rsync would still make {} change(s):",
+            self.differences.len()
+        )?;
+        for difference in &self.differences {
+            writeln!(f, "  {} {}", difference.code, difference.path)?;
+        }
+        write!(f, "Gaps:")?;
+        for (gap, count) in self.gap_counts() {
+            write!(f, "\n  {count:>6}  {}", gap.description())?;
+        }
+        Ok(())
+    }
+}
+
+/// Width of rsync's itemized change code (`YXcstpoguax`)
+const CODE_WIDTH: usize = 11;
+
+/// Parse the output of `rsync -i`
+///
+/// Each change is an 11-character code (padded with spaces when the
+/// attributes are unchanged), a space and the path. Lines that are not
+/// itemized changes (rsync's own messages) are skipped.
+#[must_use]
+pub fn parse_itemized(output: &str) -> ShadowReport {
+    let differences = output
+        .lines()
+        .filter_map(|line| {
+            let code = line.get(..CODE_WIDTH)?;
+            let path = line.get(CODE_WIDTH..)?.strip_prefix(' ')?;
+            let itemized = code.starts_with("*deleting")
+                || matches!(
+                    code.as_bytes(),
+                    [
+                        b'<' | b'>' | b'c' | b'h' | b'.',
+                        b'f' | b'd' | b'L' | b'D' | b'S',
+                        ..
+                    ]
+                );
+            itemized.then(|| Difference {
+                code: code.trim_end().to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect();
+    ShadowReport { differences }
+}
+
+/// rsync options equivalent to `config`, ending with source and destination
+#[must_use]
+pub fn rsync_args(config: &SyncConfig, destination: &Path) -> Vec<OsString> {
+    let metadata = &config.metadata;
+    let traversal = &config.traversal;
+    let mut args: Vec<OsString> = vec!["-n".into(), "-i".into(), "--numeric-ids".into()];
+    let flags = [
+        (metadata.should_recurse(), "-r"),
+        (metadata.should_preserve_links(), "-l"),
+        (metadata.should_preserve_permissions(), "-p"),
+        (metadata.should_preserve_timestamps(), "-t"),
+        (metadata.omit_dir_times, "-O"),
+        (metadata.omit_link_times, "-J"),
+        (metadata.should_preserve_group(), "-g"),
+        (metadata.should_preserve_owner(), "-o"),
+        (metadata.should_preserve_devices(), "--devices"),
+        (metadata.should_preserve_specials(), "--specials"),
+        (metadata.should_preserve_xattrs(), "-X"),
+        (metadata.should_preserve_acls(), "-A"),
+        (metadata.should_preserve_hard_links(), "-H"),
+        (metadata.atimes, "-U"),
+        (traversal.checksum, "-c"),
+        (traversal.ignore_times, "-I"),
+        (traversal.delete_timing().is_some(), "--delete"),
+    ];
+    args.extend(
+        flags
+            .into_iter()
+            .filter(|&(enabled, _)| enabled)
+            .map(|(_, flag)| flag.into()),
+    );
+    for rule in &traversal.filter.rules {
+        args.push(match rule {
+            FilterOption::Exclude(pattern) => format!("--exclude={pattern}").into(),
+            FilterOption::Include(pattern) => format!("--include={pattern}").into(),
+            FilterOption::ExcludeFrom(path) => {
+                let mut arg = OsString::from("--exclude-from=");
+                arg.push(path);
+                arg
+            }
+            FilterOption::Filter(rule) => format!("--filter={rule}").into(),
+        });
+    }
+    // arsync's own files are not the source's business
+    args.push("--filter=P .arsync*".into());
+
+    if config.is_directory_copy() {
+        // Copy the directory's contents, as arsync does
+        args.push(with_trailing_slash(&config.source));
+        args.push(with_trailing_slash(destination));
+    } else {
+        args.push(config.source.clone().into_os_string());
+        args.push(destination.as_os_str().to_os_string());
+    }
+    args
+}
+
+/// `path` with a trailing `/`
+fn with_trailing_slash(path: &Path) -> OsString {
+    let mut path = path.as_os_str().to_os_string();
+    path.push("/");
+    path
+}
+
+/// Run rsync in dry-run itemize mode against `destination`
+///
+/// # Errors
+///
+/// Returns `SyncError::FileSystem` if rsync cannot be run (for example, it is
+/// not installed) or fails.
+pub fn check(config: &SyncConfig, destination: &Path) -> Result<ShadowReport> {
+    let output = Command::new(RSYNC_PROGRAM)
+        .args(rsync_args(config, destination))
+        .output()
+        .map_err(|e| {
+            ErrorContext::new("run rsync for --shadow-rsync")
+                .source(&config.source)
+                .destination(destination)
+                .io_cause(&e)
+                .file_system()
+        })?;
+
+    let exit_code = output.status.code();
+    if !output.status.success()
+        && !exit_code.is_some_and(|code| RSYNC_PARTIAL_EXIT_CODES.contains(&code))
+    {
+        return Err(SyncError::FileSystem(format!(
+            "rsync for --shadow-rsync failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_itemized(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_itemized_output() {
+        // Requirement: Itemized lines are parsed and classified; rsync's
+        // other messages are ignored
+        let report = parse_itemized(
+            "sending incremental file list\n\
+             >f.st...... data/changed.txt\n\
+             .d..t...... data/\n\
+             >f+++++++++ data/new file.txt\n\
+             .f...po.... data/mode\n\
+             hf          data/link => data/changed.txt\n\
+             *deleting   data/extra\n\
+             .L..T...... data/symlink -> target\n\
+             \n\
+             sent 123 bytes  received 45 bytes\n",
+        );
+
+        let gaps: Vec<Vec<Gap>> = report.differences.iter().map(Difference::gaps).collect();
+        assert_eq!(
+            gaps,
+            [
+                vec![Gap::Contents, Gap::ModTime],
+                vec![Gap::ModTime],
+                vec![Gap::Missing],
+                vec![Gap::Permissions, Gap::Owner],
+                vec![Gap::HardLink],
+                vec![Gap::Extraneous],
+                vec![Gap::ModTime],
+            ]
+        );
+        assert_eq!(report.differences[2].path, "data/new file.txt");
+        assert_eq!(report.gap_counts()[&Gap::ModTime], 3);
+        assert!(report.to_string().contains("hard link not preserved"));
+        assert!(parse_itemized("sent 1 bytes\n").is_clean());
+    }
+
+    #[test]
+    fn test_rsync_args_mirror_options() {
+        // Requirement: rsync gets the options matching arsync's, and copies a
+        // directory's contents like arsync does
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let source = temp_dir.path().to_str().unwrap();
+        let args = crate::cli::Args::parse_from([
+            "arsync",
+            "-a",
+            "-X",
+            "--delete",
+            "--exclude=*.tmp",
+            source,
+            "/dst",
+        ]);
+        let config = SyncConfig::from(&args);
+        let rsync = rsync_args(&config, Path::new("/dst"));
+        let rsync: Vec<&str> = rsync.iter().map(|arg| arg.to_str().unwrap()).collect();
+
+        for flag in ["-n", "-i", "-r", "-l", "-p", "-t", "-g", "-o", "-X"] {
+            assert!(rsync.contains(&flag), "{flag} in {rsync:?}");
+        }
+        assert!(rsync.contains(&"--delete"));
+        assert!(rsync.contains(&"--exclude=*.tmp"));
+        assert!(!rsync.contains(&"-A"));
+        assert_eq!(
+            rsync[rsync.len() - 2..],
+            [format!("{source}/").as_str(), "/dst/"]
+        );
+    }
+}
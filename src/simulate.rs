@@ -0,0 +1,407 @@
+//! Scheduler simulation on synthetic trees (`arsync simulate MANIFEST`)
+//!
+//! Tuning concurrency and scheduling policies against real disks is noisy:
+//! caches, device queues and background activity swamp the differences being
+//! measured. Simulation mode runs the same scheduling machinery as a real copy
+//! (dispatcher worker threads, the adaptive concurrency controller's permits,
+//! per-directory fan-out and the parallel large-file split) over a synthetic
+//! tree, with every I/O operation replaced by a sleep:
+//!
+//! - each directory costs `--dir-latency-us`;
+//! - each file costs `--file-latency-us` plus its size at `--throughput-mb`
+//!   MB/s, split across regions when `--parallel-max-depth` applies.
+//!
+//! Nothing is read or written besides the manifest. The report compares wall
+//! time with the total simulated work, so scheduling overhead and achieved
+//! parallelism can be compared between policies.
+//!
+//! # Manifest format
+//!
+//! One entry per line: size in bytes, a tab or space, then the path relative
+//! to the tree root. A path ending in `/` is a (possibly empty) directory and
+//! its size is ignored; parent directories are implied. Blank lines and lines
+//! starting with `#` are skipped. A manifest of an existing tree can be made
+//! with `find SRC -type f -printf '%s\t%P\n'`.
+
+use crate::adaptive_concurrency::AdaptiveConcurrencyController;
+use crate::cli::{ConcurrencyConfig, ParallelCopyConfig};
+use crate::error::{ErrorContext, Result, SyncError};
+use compio::dispatcher::Dispatcher;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Directory of a synthetic tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimDir {
+    /// Subdirectories by name
+    pub dirs: BTreeMap<OsString, SimDir>,
+    /// Sizes of the regular files directly in this directory
+    pub files: Vec<u64>,
+}
+
+impl SimDir {
+    /// Read a manifest file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or a line is malformed.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ErrorContext::new("read simulation manifest")
+                .source(path)
+                .io_cause(&e)
+                .file_system()
+        })?;
+        Self::parse(&contents)
+    }
+
+    /// Build a tree from manifest text
+    ///
+    /// # Errors
+    ///
+    /// Returns `SyncError::InvalidConfig` naming the line if a size is not a
+    /// number or a path is missing, absolute or contains `..`.
+    pub fn parse(manifest: &str) -> Result<Self> {
+        let mut root = Self::default();
+        for (index, line) in manifest.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                SyncError::InvalidConfig(format!(
+                    "Simulation manifest line {}: {reason}: {line}",
+                    index + 1
+                ))
+            };
+            let (size, path) = line
+                .split_once(['\t', ' '])
+                .ok_or_else(|| invalid("expected `SIZE PATH`"))?;
+            let size: u64 = size.parse().map_err(|_| invalid("size is not a number"))?;
+
+            let mut names = Vec::new();
+            for component in Path::new(path).components() {
+                match component {
+                    Component::Normal(name) => names.push(name.to_os_string()),
+                    Component::CurDir => {}
+                    _ => return Err(invalid("path must be relative and must not contain `..`")),
+                }
+            }
+            if path.ends_with('/') {
+                root.dir_mut(&names);
+                continue;
+            }
+            if names.pop().is_none() {
+                return Err(invalid("path is empty"));
+            }
+            root.dir_mut(&names).files.push(size);
+        }
+        Ok(root)
+    }
+
+    /// Directory at `names` below this one, created if needed
+    fn dir_mut(&mut self, names: &[OsString]) -> &mut Self {
+        names
+            .iter()
+            .fold(self, |dir, name| dir.dirs.entry(name.clone()).or_default())
+    }
+}
+
+/// Simulated cost of each operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationCost {
+    /// Copy throughput of one file (or one parallel region), in bytes/second
+    pub bytes_per_sec: u64,
+    /// Fixed cost per file (open, preallocate, metadata)
+    pub file_latency: Duration,
+    /// Cost per directory (statx, mkdir, directory read)
+    pub dir_latency: Duration,
+}
+
+impl SimulationCost {
+    /// Time to transfer `bytes` at this throughput
+    #[must_use]
+    pub fn transfer_time(&self, bytes: u64) -> Duration {
+        let nanos = u128::from(bytes) * 1_000_000_000 / u128::from(self.bytes_per_sec.max(1));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+}
+
+/// Result of a simulation run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    /// Files simulated
+    pub files: u64,
+    /// Directories simulated (including the root)
+    pub directories: u64,
+    /// Total size of the simulated files
+    pub bytes: u64,
+    /// Wall time of the run
+    pub elapsed: Duration,
+    /// Sum of all simulated operation times (the run time with one worker)
+    pub work: Duration,
+}
+
+impl SimulationReport {
+    /// Average number of simulated operations in flight
+    #[must_use]
+    pub fn parallelism(&self) -> f64 {
+        self.work.as_secs_f64() / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Simulated {} files ({} bytes) in {} directories: {:.3?} elapsed for {:.3?} of \
+             simulated work ({:.1} operations in flight on average)",
+            self.files,
+            self.bytes,
+            self.directories,
+            self.elapsed,
+            self.work,
+            self.parallelism()
+        )
+    }
+}
+
+/// Totals accumulated by the simulation tasks
+#[derive(Debug, Default)]
+struct Counters {
+    /// Files simulated
+    files: AtomicU64,
+    /// Directories simulated
+    directories: AtomicU64,
+    /// Bytes of the simulated files
+    bytes: AtomicU64,
+    /// Simulated work in nanoseconds
+    work_nanos: AtomicU64,
+}
+
+/// State shared by all simulation tasks (mirrors `TraversalContext`)
+#[derive(Clone)]
+struct SimContext {
+    /// Operation costs
+    cost: SimulationCost,
+    /// Large-file split policy
+    parallel: Arc<ParallelCopyConfig>,
+    /// Permits for entries in flight
+    controller: Arc<AdaptiveConcurrencyController>,
+    /// Worker threads
+    dispatcher: &'static Dispatcher,
+    /// Run totals
+    counters: Arc<Counters>,
+}
+
+impl SimContext {
+    /// Simulate an operation taking `duration`
+    async fn spend(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.counters.work_nanos.fetch_add(nanos, Ordering::Relaxed);
+        compio::time::sleep(duration).await;
+    }
+}
+
+/// Run the scheduler over `tree` with simulated I/O
+///
+/// # Errors
+///
+/// Returns an error if the dispatcher cannot be created or a simulated
+/// operation cannot be dispatched.
+pub async fn simulate(
+    tree: SimDir,
+    cost: SimulationCost,
+    concurrency: &ConcurrencyConfig,
+    parallel: &ParallelCopyConfig,
+) -> Result<SimulationReport> {
+    // Leaked like the copy's dispatcher: worker threads live for the program
+    let dispatcher = Box::leak(Box::new(Dispatcher::new()?));
+    let counters = Arc::new(Counters::default());
+    let ctx = SimContext {
+        cost,
+        parallel: Arc::new(parallel.clone()),
+        controller: Arc::new(AdaptiveConcurrencyController::new(
+            &concurrency.to_options(),
+        )),
+        dispatcher,
+        counters: Arc::clone(&counters),
+    };
+
+    let start = Instant::now();
+    simulate_dir(tree, ctx).await?;
+    let elapsed = start.elapsed();
+
+    Ok(SimulationReport {
+        files: counters.files.load(Ordering::Relaxed),
+        directories: counters.directories.load(Ordering::Relaxed),
+        bytes: counters.bytes.load(Ordering::Relaxed),
+        elapsed,
+        work: Duration::from_nanos(counters.work_nanos.load(Ordering::Relaxed)),
+    })
+}
+
+/// Simulate a directory and, dispatched to the workers, everything below it
+///
+/// Like the real traversal, the directory's permit is held until all of its
+/// children are done.
+async fn simulate_dir(dir: SimDir, ctx: SimContext) -> Result<()> {
+    let controller = Arc::clone(&ctx.controller);
+    let _permit = controller.acquire().await;
+    ctx.spend(ctx.cost.dir_latency).await;
+    ctx.counters.directories.fetch_add(1, Ordering::Relaxed);
+
+    let mut receivers = Vec::with_capacity(dir.dirs.len() + dir.files.len());
+    for child in dir.dirs.into_values() {
+        let child_ctx = ctx.clone();
+        receivers.push(
+            ctx.dispatcher
+                .dispatch(move || simulate_dir(child, child_ctx))
+                .map_err(|e| dispatch_error(&e))?,
+        );
+    }
+    for size in dir.files {
+        let file_ctx = ctx.clone();
+        receivers.push(
+            ctx.dispatcher
+                .dispatch(move || simulate_file(size, file_ctx))
+                .map_err(|e| dispatch_error(&e))?,
+        );
+    }
+    futures::future::try_join_all(receivers.into_iter().map(|receiver| async move {
+        receiver.await.map_err(|e| {
+            SyncError::Internal(format!("Simulated operation did not complete: {e:?}"))
+        })?
+    }))
+    .await?;
+    Ok(())
+}
+
+/// Simulate copying one file of `size` bytes
+async fn simulate_file(size: u64, ctx: SimContext) -> Result<()> {
+    let controller = Arc::clone(&ctx.controller);
+    let _permit = controller.acquire().await;
+    ctx.spend(ctx.cost.file_latency).await;
+
+    if ctx.parallel.should_use_parallel(size) {
+        // Same split as `copy_read_write_parallel`: 2^depth regions, the last
+        // one taking the remainder
+        let tasks = 1u64 << ctx.parallel.max_depth;
+        let region = size / tasks;
+        let mut receivers = Vec::new();
+        for task in 0..tasks {
+            let bytes = if task == tasks - 1 {
+                size - region * (tasks - 1)
+            } else {
+                region
+            };
+            let region_ctx = ctx.clone();
+            receivers.push(
+                ctx.dispatcher
+                    .dispatch(move || async move {
+                        region_ctx.spend(region_ctx.cost.transfer_time(bytes)).await;
+                    })
+                    .map_err(|e| dispatch_error(&e))?,
+            );
+        }
+        for receiver in receivers {
+            receiver.await.map_err(|e| {
+                SyncError::Internal(format!("Simulated region copy did not complete: {e:?}"))
+            })?;
+        }
+    } else {
+        ctx.spend(ctx.cost.transfer_time(size)).await;
+    }
+
+    ctx.counters.files.fetch_add(1, Ordering::Relaxed);
+    ctx.counters.bytes.fetch_add(size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Error for a failed dispatch to the worker threads
+fn dispatch_error(error: &dyn fmt::Debug) -> SyncError {
+    SyncError::Internal(format!("Failed to dispatch simulated operation: {error:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn concurrency(max_files_in_flight: usize) -> ConcurrencyConfig {
+        ConcurrencyConfig {
+            max_files_in_flight,
+            no_adaptive_concurrency: false,
+            large_file_threshold_mb: 64,
+            large_files_in_flight: 8,
+            hardlink_spill_dir: None,
+            hardlink_memory_entries: 0,
+            no_lock: false,
+        }
+    }
+
+    fn sequential() -> ParallelCopyConfig {
+        ParallelCopyConfig {
+            max_depth: 0,
+            min_file_size_mb: 128,
+            chunk_size_mb: 2,
+            adaptive: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let tree = SimDir::parse(
+            "# synthetic tree\n\
+             10\ta.txt\n\
+             20 photos/2024/img 1.jpg\n\
+             0\tempty/\n\
+             30\t./photos/b\n",
+        )
+        .unwrap();
+
+        assert_eq!(tree.files, vec![10]);
+        assert_eq!(tree.dirs.len(), 2);
+        let photos = &tree.dirs[&OsString::from("photos")];
+        assert_eq!(photos.files, vec![30]);
+        assert_eq!(photos.dirs[&OsString::from("2024")].files, vec![20]);
+        assert!(tree.dirs[&OsString::from("empty")].files.is_empty());
+
+        let err = SimDir::parse("1\ta\nbig\tb\n").unwrap_err().to_string();
+        assert!(err.contains("line 2"), "{err}");
+        assert!(SimDir::parse("1\t../escape\n").is_err());
+    }
+
+    #[compio::test]
+    async fn test_simulation_runs_files_concurrently() {
+        // Requirement: Sleeps stand in for I/O and are scheduled concurrently
+        let manifest: String = (0..8).map(|i| format!("1000\tdir/f{i}\n")).collect();
+        let cost = SimulationCost {
+            bytes_per_sec: 1_000_000_000,
+            file_latency: Duration::from_millis(25),
+            dir_latency: Duration::ZERO,
+        };
+
+        let report = simulate(
+            SimDir::parse(&manifest).unwrap(),
+            cost,
+            &concurrency(64),
+            &sequential(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            (report.files, report.directories, report.bytes),
+            (8, 2, 8000)
+        );
+        assert!(report.work >= Duration::from_millis(200), "{report}");
+        assert!(report.elapsed < Duration::from_millis(150), "{report}");
+    }
+}
@@ -3,12 +3,20 @@
 //! This module provides lock-free atomic statistics tracking using `SharedStats`.
 //! Statistics can be safely shared across async tasks without requiring mutexes.
 
-use crate::directory::DirectoryStats;
+use crate::directory::{
+    CrtimeCounts, CrtimeStatus, DirectoryStats, FileAllocation, SpecialFileCounts, SpecialKind,
+    WriteAmplification,
+};
+use crate::symlink_rewrite::SymlinkRewrites;
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
 
 /// Statistics tracking with interior mutability via atomics
 ///
-/// This struct uses `AtomicU64` fields for lock-free statistics tracking.
+/// This struct uses `AtomicU64` fields for lock-free statistics tracking
+/// (except for the write amplification report and the rewritten symlinks,
+/// which keep short lists).
 /// The struct should be wrapped in `Arc<SharedStats>` when shared across tasks.
 ///
 /// # Thread Safety
@@ -36,6 +44,28 @@ pub struct SharedStats {
     symlinks_processed: AtomicU64,
     /// Errors counter using atomics
     errors: AtomicU64,
+    /// Special files recreated at the destination, indexed by `SpecialKind`
+    specials_created: [AtomicU64; 4],
+    /// Skipped special files, indexed by `SpecialKind`
+    specials_skipped: [AtomicU64; 4],
+    /// Special files replaced by placeholders, indexed by `SpecialKind`
+    specials_placeholders: [AtomicU64; 4],
+    /// Recoveries from stale directory handles
+    stale_recoveries: AtomicU64,
+    /// Files skipped because the destination was already up to date
+    files_unchanged: AtomicU64,
+    /// Unchanged files hard linked from a `--link-dest` directory
+    files_linked: AtomicU64,
+    /// Zero-byte files copied
+    empty_files: AtomicU64,
+    /// Entries not started because the run was interrupted
+    not_started: AtomicU64,
+    /// Copied files by creation time outcome, indexed by `CrtimeStatus`
+    crtimes: [AtomicU64; 3],
+    /// Space allocated for copied files (verbose runs only; one lock per file)
+    write_amplification: Mutex<WriteAmplification>,
+    /// Symlinks whose target was rewritten (one lock per rewritten link)
+    symlink_rewrites: Mutex<SymlinkRewrites>,
 }
 
 impl SharedStats {
@@ -52,6 +82,22 @@ impl SharedStats {
             bytes_copied: AtomicU64::new(stats.bytes_copied),
             symlinks_processed: AtomicU64::new(stats.symlinks_processed),
             errors: AtomicU64::new(stats.errors),
+            specials_created: special_counters(&stats.specials_created),
+            specials_skipped: special_counters(&stats.specials_skipped),
+            specials_placeholders: special_counters(&stats.specials_placeholders),
+            stale_recoveries: AtomicU64::new(stats.stale_recoveries),
+            files_unchanged: AtomicU64::new(stats.files_unchanged),
+            files_linked: AtomicU64::new(stats.files_linked),
+            empty_files: AtomicU64::new(stats.empty_files),
+            not_started: AtomicU64::new(stats.not_started),
+            crtimes: [
+                AtomicU64::new(stats.crtimes.preserved),
+                AtomicU64::new(stats.crtimes.not_preserved),
+                AtomicU64::new(stats.crtimes.unavailable),
+            ],
+            // Measurements are per run: a resumed run starts over
+            write_amplification: Mutex::new(WriteAmplification::new()),
+            symlink_rewrites: Mutex::new(SymlinkRewrites::new()),
         }
     }
 
@@ -119,18 +165,137 @@ impl SharedStats {
         self.errors.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Count a special file recreated at the destination (lock-free atomic operation)
+    pub fn increment_special_created(&self, kind: SpecialKind) {
+        self.specials_created[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a special file left out of the destination (lock-free atomic operation)
+    pub fn increment_special_skipped(&self, kind: SpecialKind) {
+        self.specials_skipped[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a special file replaced by a placeholder (lock-free atomic operation)
+    pub fn increment_special_placeholder(&self, kind: SpecialKind) {
+        self.specials_placeholders[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an operation that succeeded after reopening a stale directory
+    pub fn increment_stale_recoveries(&self) {
+        self.stale_recoveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a file left alone because the destination was up to date
+    pub fn increment_files_unchanged(&self) {
+        self.files_unchanged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a file hard linked from a `--link-dest` directory
+    pub fn increment_files_linked(&self) {
+        self.files_linked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an entry not started because the run was interrupted
+    pub fn increment_not_started(&self) {
+        self.not_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a zero-byte file copied
+    pub fn increment_empty_files(&self) {
+        self.empty_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count how a copied file's creation time compares with its source's
+    pub fn record_crtime(&self, status: CrtimeStatus) {
+        self.crtimes[status as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the space a copied file takes at the destination
+    pub fn record_allocation(&self, file: FileAllocation) {
+        self.write_amplification
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(file);
+    }
+
+    /// Record the symlink `link`, created pointing at the rewritten `target`
+    pub fn record_symlink_rewrite(&self, link: &Path, target: &Path) {
+        self.symlink_rewrites
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(link, target);
+    }
+
     /// Convert atomic statistics back to `DirectoryStats`
     ///
     /// This consumes the `SharedStats` and returns a `DirectoryStats` with the final values.
     /// All atomic loads use `Ordering::Relaxed` since we're reading the final values.
     #[must_use]
     pub fn into_inner(self) -> DirectoryStats {
+        self.snapshot()
+    }
+
+    /// Current values as `DirectoryStats`, while tasks may still be updating them
+    ///
+    /// Each counter is read individually, so the snapshot is not atomic as a whole.
+    #[must_use]
+    pub fn snapshot(&self) -> DirectoryStats {
         DirectoryStats {
             files_copied: self.files_copied.load(Ordering::Relaxed),
             directories_created: self.directories_created.load(Ordering::Relaxed),
             bytes_copied: self.bytes_copied.load(Ordering::Relaxed),
             symlinks_processed: self.symlinks_processed.load(Ordering::Relaxed),
             errors: self.errors.load(Ordering::Relaxed),
+            specials_created: special_counts(&self.specials_created),
+            specials_skipped: special_counts(&self.specials_skipped),
+            specials_placeholders: special_counts(&self.specials_placeholders),
+            stale_recoveries: self.stale_recoveries.load(Ordering::Relaxed),
+            files_unchanged: self.files_unchanged.load(Ordering::Relaxed),
+            files_linked: self.files_linked.load(Ordering::Relaxed),
+            empty_files: self.empty_files.load(Ordering::Relaxed),
+            not_started: self.not_started.load(Ordering::Relaxed),
+            crtimes: crtime_counts(&self.crtimes),
+            write_amplification: self
+                .write_amplification
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            symlink_rewrites: self
+                .symlink_rewrites
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
         }
     }
 }
+
+/// Atomic counters indexed by `SpecialKind`, starting from `counts`
+const fn special_counters(counts: &SpecialFileCounts) -> [AtomicU64; 4] {
+    [
+        AtomicU64::new(counts.fifos),
+        AtomicU64::new(counts.sockets),
+        AtomicU64::new(counts.char_devices),
+        AtomicU64::new(counts.block_devices),
+    ]
+}
+
+/// Read counters indexed by `SpecialKind`
+fn special_counts(counters: &[AtomicU64; 4]) -> SpecialFileCounts {
+    let [fifos, sockets, char_devices, block_devices] = counters;
+    SpecialFileCounts {
+        fifos: fifos.load(Ordering::Relaxed),
+        sockets: sockets.load(Ordering::Relaxed),
+        char_devices: char_devices.load(Ordering::Relaxed),
+        block_devices: block_devices.load(Ordering::Relaxed),
+    }
+}
+
+/// Read counters indexed by `CrtimeStatus`
+fn crtime_counts(counters: &[AtomicU64; 3]) -> CrtimeCounts {
+    let [preserved, not_preserved, unavailable] = counters;
+    CrtimeCounts {
+        preserved: preserved.load(Ordering::Relaxed),
+        not_preserved: not_preserved.load(Ordering::Relaxed),
+        unavailable: unavailable.load(Ordering::Relaxed),
+    }
+}
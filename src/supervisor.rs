@@ -0,0 +1,226 @@
+//! Panic isolation and restart for the traversal's dispatcher workers
+//!
+//! compio's dispatcher runs each task on one of a fixed set of worker threads;
+//! a panic in a task unwinds that worker, and once every worker is gone the
+//! dispatcher refuses new work. On a multi-day sync one bad file (or bug)
+//! would then fail its whole directory and eventually the run.
+//!
+//! [`Supervisor::run`] dispatches a task with its panic captured: the panic
+//! becomes an error for that entry (counted in the run's error stats and
+//! logged by the caller, like any other per-entry failure) and the worker
+//! thread keeps serving other tasks. If a worker is lost anyway (a task
+//! vanished without a result, or dispatching fails because no worker is
+//! left), the supervisor starts a fresh worker pool and later tasks go to it. The dispatcher does not
+//! expose its threads individually, so the whole pool is replaced; tasks
+//! already queued on the old pool still run on its remaining workers.
+//!
+//! Capturing a panic needs it to unwind, so every profile that ships a
+//! binary (`release` and those inheriting it) keeps `panic = "unwind"`. With
+//! `ARSYNC_TEST_PANIC_ON=NAME` the task of every entry named NAME panics, so
+//! tests can check the isolation in the binary as built.
+
+use crate::error::{Result, SyncError};
+use crate::stats::SharedStats;
+use compio::dispatcher::Dispatcher;
+use futures::FutureExt;
+use std::any::Any;
+use std::ffi::OsString;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use tracing::warn;
+
+/// Outcome of a dispatched task: its result, or the payload of its panic
+type Caught<T> = std::result::Result<T, Box<dyn Any + Send>>;
+
+/// Environment variable naming the entries whose tasks panic on purpose
+const PANIC_ON_VAR: &str = "ARSYNC_TEST_PANIC_ON";
+
+/// Value of [`PANIC_ON_VAR`], read once
+static PANIC_ON: OnceLock<Option<OsString>> = OnceLock::new();
+
+/// Dispatches tasks with panic capture and replaces lost workers
+pub struct Supervisor {
+    /// Current worker pool
+    ///
+    /// Leaked like every dispatcher in arsync: worker threads live for the
+    /// program, and a replaced pool may still be finishing queued tasks.
+    dispatcher: RwLock<&'static Dispatcher>,
+    /// Statistics whose error count includes panicked entries
+    stats: Arc<SharedStats>,
+    /// Tasks that panicked
+    panics: AtomicU64,
+    /// Worker pools started to replace lost workers
+    restarts: AtomicU64,
+}
+
+impl Supervisor {
+    /// Supervisor with a fresh worker pool
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worker threads cannot be started.
+    pub fn new(stats: Arc<SharedStats>) -> Result<Self> {
+        Ok(Self {
+            dispatcher: RwLock::new(Box::leak(Box::new(Dispatcher::new()?))),
+            stats,
+            panics: AtomicU64::new(0),
+            restarts: AtomicU64::new(0),
+        })
+    }
+
+    /// Current worker pool, for work that is dispatched without supervision
+    #[must_use]
+    pub fn dispatcher(&self) -> &'static Dispatcher {
+        *self
+            .dispatcher
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Number of tasks that panicked
+    #[must_use]
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Number of worker pools started to replace lost workers
+    #[must_use]
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Run `task` for the entry at `path` on a worker, isolating panics
+    ///
+    /// # Errors
+    ///
+    /// Returns the task's own error, or `SyncError::Internal` if the task
+    /// panicked, its worker was lost or no worker pool could be started.
+    #[allow(clippy::future_not_send)]
+    pub async fn run<F, Fut>(&self, path: &Path, task: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        let inject_panic = injected_panic(path);
+        let guarded = move || {
+            AssertUnwindSafe(async move {
+                #[allow(clippy::panic)] // the point of ARSYNC_TEST_PANIC_ON
+                if inject_panic {
+                    panic!("injected by {PANIC_ON_VAR}");
+                }
+                task().await
+            })
+            .catch_unwind()
+        };
+        let dispatcher = self.dispatcher();
+        let receiver = match dispatcher.dispatch(guarded) {
+            Ok(receiver) => receiver,
+            // Every worker of this pool is gone: start a new one and retry there
+            Err(e) => self
+                .restart(dispatcher, path)?
+                .dispatch(e.into_inner())
+                .map_err(|e| {
+                    SyncError::Internal(format!(
+                        "Failed to dispatch {} after restarting workers: {e:?}",
+                        path.display()
+                    ))
+                })?,
+        };
+        let outcome: Caught<Result<()>> = match receiver.await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                // The task was dropped without a result: its worker died
+                self.restart(dispatcher, path)?;
+                self.stats.increment_errors();
+                return Err(SyncError::Internal(format!(
+                    "Worker lost while processing {}",
+                    path.display()
+                )));
+            }
+        };
+        outcome.unwrap_or_else(|payload| {
+            self.panics.fetch_add(1, Ordering::Relaxed);
+            self.stats.increment_errors();
+            let message = panic_message(payload.as_ref());
+            Err(SyncError::Internal(format!(
+                "Panic while processing {}: {message}",
+                path.display()
+            )))
+        })
+    }
+
+    /// Replace the pool `lost` (unless another task already did) and return
+    /// the current one
+    fn restart(&self, lost: &'static Dispatcher, path: &Path) -> Result<&'static Dispatcher> {
+        let mut current = self
+            .dispatcher
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if std::ptr::eq(*current, lost) {
+            let fresh = Dispatcher::new().map_err(|e| {
+                SyncError::Internal(format!("Failed to restart worker threads: {e}"))
+            })?;
+            *current = Box::leak(Box::new(fresh));
+            self.restarts.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Restarted worker threads after losing a worker while processing {}",
+                path.display()
+            );
+        }
+        Ok(*current)
+    }
+}
+
+/// Whether the task of the entry at `path` is to panic ([`PANIC_ON_VAR`])
+fn injected_panic(path: &Path) -> bool {
+    PANIC_ON
+        .get_or_init(|| std::env::var_os(PANIC_ON_VAR))
+        .as_deref()
+        .is_some_and(|name| path.file_name() == Some(name))
+}
+
+/// Text of a panic payload (`panic!` with a literal or a formatted message)
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(non-string panic payload)")
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::panic)]
+
+    use super::*;
+    use crate::directory::DirectoryStats;
+
+    #[compio::test]
+    async fn test_panic_becomes_entry_error() {
+        // Requirement: A panicking task fails only its own entry; the workers
+        // keep serving later tasks
+        let stats = Arc::new(SharedStats::new(&DirectoryStats::default()));
+        let supervisor = Supervisor::new(Arc::clone(&stats)).unwrap();
+
+        let result = supervisor
+            .run(Path::new("/src/bad"), || async { panic!("corrupt entry") })
+            .await;
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("/src/bad"), "{message}");
+        assert!(message.contains("corrupt entry"), "{message}");
+
+        for _ in 0..16 {
+            supervisor
+                .run(Path::new("/src/good"), || async { Ok(()) })
+                .await
+                .unwrap();
+        }
+        assert_eq!(supervisor.panics(), 1);
+        assert_eq!(supervisor.restarts(), 0);
+        assert_eq!(stats.snapshot().errors, 1);
+    }
+}
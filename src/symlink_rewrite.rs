@@ -0,0 +1,175 @@
+//! Symlink target rewriting (`--symlink-rewrite=FROM:TO`)
+//!
+//! Relocating a tree (say from `/data/old` to `/data/new`) breaks the
+//! absolute symlinks that point into its old location. Each rule replaces a
+//! leading `FROM` in a symlink's target with `TO` when the link is recreated
+//! at the destination; the first rule that matches wins.
+//!
+//! Rewritten links are counted, and those whose new target does not exist
+//! are remembered. Their targets may still be copied later in the run, so
+//! they are checked again at the end and only the ones still dangling are
+//! reported.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// One `--symlink-rewrite` rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteRule {
+    /// Leading components of the targets to rewrite
+    pub from: PathBuf,
+    /// What replaces them
+    pub to: PathBuf,
+}
+
+impl RewriteRule {
+    /// Parse `FROM:TO` (split at the first colon)
+    ///
+    /// # Errors
+    ///
+    /// Returns a message for the command line if there is no colon or either
+    /// side is empty.
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        match text.split_once(':') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(Self {
+                from: PathBuf::from(from),
+                to: PathBuf::from(to),
+            }),
+            _ => Err(format!("invalid rewrite rule {text:?} (expected FROM:TO)")),
+        }
+    }
+
+    /// `target` with `from` replaced by `to`, if it starts with `from`
+    ///
+    /// Whole components are compared: `/data/old` matches `/data/old/lib`
+    /// but not `/data/older`.
+    #[must_use]
+    pub fn apply(&self, target: &Path) -> Option<PathBuf> {
+        let rest = target.strip_prefix(&self.from).ok()?;
+        Some(if rest.as_os_str().is_empty() {
+            self.to.clone()
+        } else {
+            self.to.join(rest)
+        })
+    }
+}
+
+impl fmt::Display for RewriteRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.from.display(), self.to.display())
+    }
+}
+
+/// `target` rewritten by the first matching rule, if any
+#[must_use]
+pub fn rewrite(rules: &[RewriteRule], target: &Path) -> Option<PathBuf> {
+    rules.iter().find_map(|rule| rule.apply(target))
+}
+
+/// Rewritten symlinks of a run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymlinkRewrites {
+    /// Symlinks whose target was rewritten
+    pub rewritten: u64,
+    /// Rewritten symlinks (and their new targets) that did not resolve
+    /// when they were created
+    pub dangling: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SymlinkRewrites {
+    /// No symlinks rewritten yet
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            rewritten: 0,
+            dangling: Vec::new(),
+        }
+    }
+
+    /// Count the symlink `link`, created pointing at the rewritten `target`
+    pub fn record(&mut self, link: &Path, target: &Path) {
+        self.rewritten += 1;
+        if !resolves(link, target) {
+            self.dangling
+                .push((link.to_path_buf(), target.to_path_buf()));
+        }
+    }
+
+    /// Rewritten symlinks whose target still does not exist
+    pub fn still_dangling(&self) -> impl Iterator<Item = &(PathBuf, PathBuf)> {
+        self.dangling
+            .iter()
+            .filter(|(link, target)| !resolves(link, target))
+    }
+}
+
+/// Whether the symlink `link` pointing at `target` resolves to something
+fn resolves(link: &Path, target: &Path) -> bool {
+    let resolved = link
+        .parent()
+        .map_or_else(|| target.to_path_buf(), |dir| dir.join(target));
+    std::fs::metadata(resolved).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_rule() {
+        // Requirement: Rules are FROM:TO, split at the first colon, and
+        // neither side may be empty
+        let rule = RewriteRule::parse("/data/old:/data/new").unwrap();
+        assert_eq!(rule.from, PathBuf::from("/data/old"));
+        assert_eq!(rule.to, PathBuf::from("/data/new"));
+        assert_eq!(rule.to_string(), "/data/old:/data/new");
+        for text in ["/data/old", ":/data/new", "/data/old:", ""] {
+            assert!(RewriteRule::parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_rewrite_whole_components() {
+        // Requirement: The first rule whose FROM is a leading part of the
+        // target (in whole components) replaces it
+        let rules = [
+            RewriteRule::parse("/data/old/special:/mnt/special").unwrap(),
+            RewriteRule::parse("/data/old:/data/new").unwrap(),
+        ];
+        let rewritten = |target: &str| rewrite(&rules, Path::new(target));
+        assert_eq!(
+            rewritten("/data/old/lib/libfoo.so"),
+            Some(PathBuf::from("/data/new/lib/libfoo.so"))
+        );
+        assert_eq!(
+            rewritten("/data/old/special/x"),
+            Some(PathBuf::from("/mnt/special/x"))
+        );
+        assert_eq!(rewritten("/data/old"), Some(PathBuf::from("/data/new")));
+        assert_eq!(rewritten("/data/older/x"), None);
+        assert_eq!(rewritten("../old/x"), None);
+    }
+
+    #[test]
+    fn test_dangling_rewrites_are_checked_again() {
+        // Requirement: A rewritten link whose target appears later in the
+        // run is not reported as dangling
+        let temp = TempDir::new().unwrap();
+        let link = temp.path().join("link");
+        let later = temp.path().join("later");
+        let never = temp.path().join("never");
+        let mut rewrites = SymlinkRewrites::new();
+        rewrites.record(&link, &later);
+        rewrites.record(&link, &never);
+        rewrites.record(&link, temp.path());
+        assert_eq!(rewrites.rewritten, 3);
+        assert_eq!(rewrites.dangling.len(), 2);
+
+        std::fs::write(&later, b"copied").unwrap();
+        let dangling: Vec<_> = rewrites.still_dangling().collect();
+        assert_eq!(dangling, [&(link, never)]);
+    }
+}
@@ -30,13 +30,24 @@
 //! #[compio::main]
 //! async fn main() -> arsync::Result<()> {
 //!     let args = Args::parse();
-//!     let stats = sync_files(&args).await?;
+//!     let stats = sync_files(&args, Default::default()).await?;
 //!     println!("Copied {} files, {} bytes in {:?}",
 //!              stats.files_copied, stats.bytes_copied, stats.duration);
 //!     Ok(())
 //! }
 //! ```
 //!
+//! Without a command line, build a [`SyncConfig`] instead:
+//!
+//! ```rust,ignore
+//! use arsync::config::SyncConfig;
+//!
+//! let mut config = SyncConfig::new("/data", "/backup/data");
+//! config.metadata.archive = true;
+//! config.validate()?;
+//! let stats = arsync::sync::sync(&config).await?;
+//! ```
+//!
 //! # Performance Considerations
 //!
 //! - Uses async I/O for non-blocking operations
@@ -55,11 +66,21 @@
 //! - Configuration validation failures
 
 use crate::cli::Args;
-use crate::directory::copy_directory;
-use crate::error::Result;
+use crate::config::SyncConfig;
+use crate::dest_lock::DestinationLock;
+use crate::directory::{copy_directory, metadata_from_path, CrtimeStatus};
+use crate::error::{Result, SyncError};
 use crate::io_uring::FileOperations;
+use crate::itemize::Itemizer;
+use crate::metrics::Metrics;
+use crate::progress::ProgressRenderer;
+use crate::run_state::RunState;
+use compio_fs_extended::fd_hygiene::FdSnapshot;
+use compio_fs_extended::DirectoryFd;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
 /// Statistics for a synchronization operation
 ///
@@ -70,6 +91,7 @@ use tracing::{error, info};
 ///
 /// * `files_copied` - Number of files successfully copied
 /// * `bytes_copied` - Total number of bytes copied
+/// * `errors` - Number of entries that failed and were skipped
 /// * `duration` - Total time taken for the synchronization operation
 ///
 /// # Examples
@@ -81,6 +103,7 @@ use tracing::{error, info};
 /// let stats = SyncStats {
 ///     files_copied: 150,
 ///     bytes_copied: 1_048_576,
+///     errors: 0,
 ///     duration: Duration::from_secs(5),
 /// };
 /// println!("Copied {} files ({} bytes) in {:?}",
@@ -102,6 +125,12 @@ pub struct SyncStats {
     /// Total number of bytes copied during the operation
     pub bytes_copied: u64,
 
+    /// Number of entries that failed and were skipped
+    ///
+    /// A run with failed entries still returns `Ok`; the CLI then exits
+    /// with status 23 unless `--ignore-errors` was given.
+    pub errors: u64,
+
     /// Total duration of the synchronization operation
     pub duration: Duration,
 }
@@ -116,6 +145,8 @@ pub struct SyncStats {
 ///
 /// * `args` - Command-line arguments containing source/destination paths,
 ///   configuration options, and operation parameters
+/// * `metrics` - Where the run counts its work (`--metrics-listen`,
+///   `--metrics-textfile`)
 ///
 /// # Returns
 ///
@@ -142,7 +173,7 @@ pub struct SyncStats {
 /// #[compio::main]
 /// async fn main() -> arsync::Result<()> {
 ///     let args = Args::parse();
-///     let stats = sync_files(&args).await?;
+///     let stats = sync_files(&args, Default::default()).await?;
 ///     println!("Operation completed: {} files, {} bytes, {:?}",
 ///              stats.files_copied, stats.bytes_copied, stats.duration);
 ///     Ok(())
@@ -158,7 +189,7 @@ pub struct SyncStats {
 /// #[compio::main]
 /// async fn main() -> arsync::Result<()> {
 ///     let args = Args::parse();
-///     match sync_files(&args).await {
+///     match sync_files(&args, Default::default()).await {
 ///         Ok(stats) => {
 ///             println!("Success: {} files copied", stats.files_copied);
 ///         }
@@ -194,76 +225,214 @@ pub struct SyncStats {
 /// 5. Tracks statistics and handles errors
 /// 6. Returns comprehensive operation results
 #[allow(clippy::future_not_send)]
-pub async fn sync_files(args: &Args) -> Result<SyncStats> {
+pub async fn sync_files(args: &Args, metrics: Arc<Metrics>) -> Result<SyncStats> {
+    let mut config = SyncConfig::from(args);
+    config.metadata.run = Arc::new(RunState {
+        metrics,
+        ..RunState::default()
+    });
+    sync(&config).await
+}
+
+/// Synchronize files as described by `config`
+///
+/// This is `sync_files` for callers that build a [`SyncConfig`] themselves
+/// rather than parsing a command line.
+///
+/// # Errors
+///
+/// Same as [`sync_files`].
+#[allow(clippy::future_not_send)]
+pub async fn sync(config: &SyncConfig) -> Result<SyncStats> {
     let start_time = Instant::now();
+    let fd_baseline = capture_fd_baseline(config)?;
+    let progress = (config.output.progress && !config.output.quiet).then(ProgressRenderer::start);
+    if config.output.interactive {
+        crate::interactive::install();
+    }
 
     info!(
         "Starting synchronization from {} to {}",
-        args.source().display(),
-        args.destination().display()
+        config.source.display(),
+        config.destination.display()
     );
 
     let mut stats = SyncStats {
         files_copied: 0,
         bytes_copied: 0,
+        errors: 0,
         duration: Duration::from_secs(0),
     };
 
     // Initialize file operations with configured parameters
     // Queue depth and buffer size are validated by the CLI module
-    // Use effective_buffer_size() to handle None (auto-detect) case
-    let file_ops = FileOperations::new(args.queue_depth(), args.effective_buffer_size())?;
+    // Pick the destination filesystem's profile; --buffer-size-kb overrides it
+    let tuning = crate::tuning::select(config);
+    info!(
+        "Tuning profile: {:?} ({} KB buffers)",
+        tuning.profile,
+        tuning.buffer_size / 1024
+    );
+    if config.metadata.fsync && !config.metadata.syncfs && tuning.expensive_fsync {
+        warn!(
+            "--fsync is expensive on {:?} destinations; consider --syncfs for one flush at the end",
+            tuning.profile
+        );
+    }
+    if config.io.parallel.max_depth > 0 && !tuning.parallel_writes {
+        warn!(
+            "Parallel in-file writes are disabled on {:?} destinations; large files are written sequentially (--tune=generic to override)",
+            tuning.profile
+        );
+    }
+    // The profile and buffer pools are this run's own, even beside other
+    // runs in the process (`arsync serve`)
+    let mut run_config = config.clone();
+    run_config.metadata.run = Arc::new(RunState::new(config, tuning));
+    let config = &run_config;
+    let file_ops = FileOperations::new(config.io.queue_depth, tuning.buffer_size)?
+        .with_run(Arc::clone(&config.metadata.run));
+
+    // Held until the end of the run, including the final syncfs
+    let mut locks = Vec::new();
 
+    // Handle fan-out to several destinations
+    if !config.extra_destinations.is_empty() {
+        info!(
+            "Fanning out {} to {} destinations",
+            config.source.display(),
+            config.extra_destinations.len() + 1
+        );
+        for destination in config.destinations() {
+            let root = if config.is_directory_copy() {
+                destination.as_path()
+            } else {
+                destination
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."))
+            };
+            file_ops.create_dir(root).await?;
+            locks.extend(lock_destination(config, root)?);
+        }
+
+        let report = crate::fanout::fan_out(config).await?;
+        for destination in &report.destinations {
+            info!("Fan-out destination {destination}");
+        }
+        stats.files_copied = report.files_read;
+        stats.bytes_copied = report.bytes_read;
+
+        let failed: Vec<String> = report.failed().map(ToString::to_string).collect();
+        if !failed.is_empty() {
+            return Err(SyncError::CopyFailed(format!(
+                "{} of {} destinations failed: {}",
+                failed.len(),
+                report.destinations.len(),
+                failed.join("; ")
+            )));
+        }
+    }
+    // --dry-run of a single file: report the copy, touching nothing
+    else if config.is_file_copy() && config.output.dry_run {
+        let root = config
+            .destination
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let src_metadata = metadata_from_path(&config.source).await?;
+        let existing = metadata_from_path(&config.destination).await.ok();
+        Itemizer::stdout(root)
+            .with_crtimes(config.metadata.crtimes)
+            .with_json(config.output.json)
+            .file(&config.destination, &src_metadata, existing.as_ref(), false);
+        stats.files_copied = 1;
+        stats.bytes_copied = src_metadata.size;
+    }
     // Handle single file copy
-    if args.is_file_copy() {
-        info!("Copying single file: {}", args.source().display());
+    else if config.is_file_copy() {
+        info!("Copying single file: {}", config.source.display());
 
         // Ensure destination directory exists
-        if let Some(parent) = args.destination().parent() {
+        if let Some(parent) = config.destination.parent() {
             file_ops.create_dir(parent).await?;
         }
+        locks.extend(lock_destination(
+            config,
+            config
+                .destination
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(".")),
+        )?);
+
+        // With --interactive, an existing destination that differs is only
+        // replaced once confirmed
+        let confirmed = match crate::interactive::prompter() {
+            Some(prompter) => match metadata_from_path(&config.destination).await {
+                Ok(dst_metadata) => prompter.confirm_overwrite(
+                    &config.source,
+                    &metadata_from_path(&config.source).await?,
+                    &config.destination,
+                    &dst_metadata,
+                ),
+                Err(_) => true,
+            },
+            None => true,
+        };
 
         // Note: file size is now obtained within copy_file_with_metadata
 
         // Copy the file with metadata preservation
-        match file_ops
-            .copy_file_with_metadata(args.source(), args.destination(), &args.io.parallel)
-            .await
-        {
-            Ok(bytes_copied) => {
-                stats.files_copied = 1;
-                stats.bytes_copied = bytes_copied;
-                info!(
-                    "Successfully copied file with metadata: {} bytes",
-                    bytes_copied
-                );
-            }
-            Err(e) => {
-                error!("Failed to copy file {}: {}", args.source().display(), e);
-                return Err(e);
+        if confirmed {
+            let copied = file_ops
+                .copy_file_with_metadata(&config.source, &config.destination, &config.io.parallel)
+                .await;
+            match config.metadata.run.delayed.finish(copied).await {
+                Ok(bytes_copied) => {
+                    stats.files_copied = 1;
+                    stats.bytes_copied = bytes_copied;
+                    info!(
+                        "Successfully copied file with metadata: {} bytes",
+                        bytes_copied
+                    );
+                    if config.metadata.crtimes {
+                        report_crtime(&config.source, &config.destination).await;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to copy file {}: {}", config.source.display(), e);
+                    return Err(e);
+                }
             }
+        } else {
+            info!("Not overwriting {}", config.destination.display());
         }
     }
     // Handle directory copy
-    else if args.is_directory_copy() {
-        info!("Copying directory: {}", args.source().display());
+    else if config.is_directory_copy() {
+        info!("Copying directory: {}", config.source.display());
 
-        // Ensure destination directory exists
-        file_ops.create_dir(args.destination()).await?;
+        // Ensure destination directory exists (a dry run creates nothing)
+        if !config.output.dry_run {
+            file_ops.create_dir(&config.destination).await?;
+            locks.extend(lock_destination(config, &config.destination)?);
+        }
 
         // Copy directory recursively
         let dir_stats = copy_directory(
-            args.source(),
-            args.destination(),
+            &config.source,
+            &config.destination,
             &file_ops,
-            args.copy_method().clone(),
-            args,
+            config.io.copy_method.clone(),
+            config,
         )
         .await?;
 
         // Update statistics
         stats.files_copied = dir_stats.files_copied;
         stats.bytes_copied = dir_stats.bytes_copied;
+        stats.errors = dir_stats.errors;
 
         info!(
             "Directory copy completed: {} files, {} directories, {} bytes, {} errors",
@@ -275,13 +444,42 @@ pub async fn sync_files(args: &Args) -> Result<SyncStats> {
     } else {
         error!(
             "Source path is neither a file nor a directory: {}",
-            args.source().display()
+            config.source.display()
         );
-        return Err(crate::error::SyncError::InvalidConfig(
+        return Err(SyncError::InvalidConfig(
             "Source must be a file or directory".to_string(),
         ));
     }
 
+    // Final durability barrier: one syncfs for the whole destination filesystem
+    if config.metadata.syncfs && !config.output.dry_run {
+        for destination in config.destinations() {
+            sync_destination_filesystem(destination).await?;
+        }
+    }
+
+    // --emit-checksums: list what this run copied
+    if let Some(file) = &config.output.emit_checksums {
+        let root = if config.is_directory_copy() {
+            config.destination.as_path()
+        } else {
+            config
+                .destination
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+        };
+        let listed = config.metadata.run.checksums.finish(file, root)?;
+        info!("Checksums of {listed} files written to {}", file.display());
+    }
+
+    drop(locks);
+    if let Some(progress) = progress {
+        progress.finish().await;
+    }
+    if let Some(baseline) = fd_baseline {
+        audit_fds(&baseline)?;
+    }
     stats.duration = start_time.elapsed();
 
     info!("Synchronization completed in {:?}", stats.duration);
@@ -289,6 +487,139 @@ pub async fn sync_files(args: &Args) -> Result<SyncStats> {
         "Files copied: {}, Bytes copied: {}",
         stats.files_copied, stats.bytes_copied
     );
+    if config.metadata.run.offload.any() {
+        info!("{}", config.metadata.run.offload.summary());
+    }
 
     Ok(stats)
 }
+
+/// Compare the creation time of a single copied file with its source's
+async fn report_crtime(source: &Path, destination: &Path) {
+    let source = metadata_from_path(source)
+        .await
+        .ok()
+        .and_then(|m| m.created);
+    let copy = metadata_from_path(destination)
+        .await
+        .ok()
+        .and_then(|m| m.created);
+    match CrtimeStatus::of(source, copy) {
+        CrtimeStatus::Preserved => info!("Creation time preserved"),
+        CrtimeStatus::NotPreserved => {
+            warn!("Creation time not preserved (Linux cannot set a file's creation time)");
+        }
+        CrtimeStatus::Unavailable => {
+            warn!("Creation time unavailable (the filesystem does not report it)");
+        }
+    }
+}
+
+/// Take the destination lock for the run, unless `--no-lock` was given
+///
+/// # Errors
+///
+/// Returns an error if another sync holds the lock or it cannot be taken.
+fn lock_destination(
+    config: &SyncConfig,
+    destination_dir: &Path,
+) -> Result<Option<DestinationLock>> {
+    if config.concurrency.no_lock {
+        return Ok(None);
+    }
+    let lock = DestinationLock::acquire(destination_dir, &config.source)?;
+    info!("Locked destination: {}", lock.path().display());
+    Ok(Some(lock))
+}
+
+/// Snapshot open descriptors for `--debug-fd-audit`; `None` if not enabled
+///
+/// # Errors
+///
+/// Returns an error if `/proc/self/fd` cannot be read.
+fn capture_fd_baseline(config: &SyncConfig) -> Result<Option<FdSnapshot>> {
+    if !config.output.debug_fd_audit {
+        return Ok(None);
+    }
+    FdSnapshot::capture()
+        .map(Some)
+        .map_err(|e| SyncError::Internal(format!("FD audit: cannot read /proc/self/fd: {e}")))
+}
+
+/// Compare open descriptors against the start of the run
+///
+/// Descriptors opened without close-on-exec are reported as warnings; any
+/// descriptor still open that was not open at the start fails the run.
+///
+/// # Errors
+///
+/// Returns `SyncError::Internal` listing the leaked descriptors.
+fn audit_fds(baseline: &FdSnapshot) -> Result<()> {
+    let now = FdSnapshot::capture()
+        .map_err(|e| SyncError::Internal(format!("FD audit: cannot read /proc/self/fd: {e}")))?;
+    for info in now.inheritable() {
+        warn!(
+            "FD audit: fd {} ({}) is open without close-on-exec",
+            info.fd,
+            info.target.display()
+        );
+    }
+    let leaked = now.leaked_since(baseline);
+    if leaked.is_empty() {
+        debug!("FD audit: no descriptors leaked");
+        return Ok(());
+    }
+    let list = leaked
+        .iter()
+        .map(|info| format!("{} ({})", info.fd, info.target.display()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(SyncError::Internal(format!(
+        "FD audit: {} descriptor(s) leaked by the run: {list}",
+        leaked.len()
+    )))
+}
+
+/// Flush the destination filesystem to stable storage with `syncfs(2)`
+///
+/// Used for `--syncfs`: instead of fsyncing every file, issue a single
+/// filesystem-wide barrier after all data has been written. For a single
+/// file copy the destination's parent directory is used to identify the
+/// filesystem. A fan-out calls this once per destination.
+///
+/// # Errors
+///
+/// Returns an error if the destination directory cannot be opened or the
+/// `syncfs` call fails (e.g., writeback I/O error).
+#[allow(clippy::future_not_send)]
+async fn sync_destination_filesystem(destination: &Path) -> Result<()> {
+    let sync_dir = if destination.is_dir() {
+        destination
+    } else {
+        match destination.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    };
+
+    info!(
+        "Flushing destination filesystem (syncfs): {}",
+        sync_dir.display()
+    );
+
+    let dir = DirectoryFd::open(sync_dir).await.map_err(|e| {
+        SyncError::FileSystem(format!(
+            "Failed to open destination {} for syncfs: {}",
+            sync_dir.display(),
+            e
+        ))
+    })?;
+
+    compio_fs_extended::syncfs::syncfs(&dir).await.map_err(|e| {
+        SyncError::FileSystem(format!(
+            "syncfs failed on destination {}: {}",
+            sync_dir.display(),
+            e
+        ))
+    })
+}
@@ -0,0 +1,811 @@
+//! Tar export: `arsync SOURCE --to-tar ARCHIVE` (`-` for stdout)
+//!
+//! Instead of copying the tree, stream it as a POSIX (pax) tar archive for
+//! piping into other tools. The walk is the one a copy makes (filter rules
+//! apply, directories come before their contents) and the metadata options
+//! decide what the archive carries, as they decide what a copy preserves:
+//!
+//! - Mode, ownership (numeric IDs) and modification time are always stored;
+//!   tar headers have no way to leave them out. Sub-second times and values
+//!   too large for the ustar fields go into pax records.
+//! - `--atimes` adds access times, `--xattrs` the extended attributes
+//!   (`SCHILY.xattr.*`) and `--acls` the POSIX ACLs (`SCHILY.acl.access` and
+//!   `SCHILY.acl.default`), in the records GNU tar and bsdtar read back.
+//! - With `--links` symlinks are stored as links (after `--symlink-rewrite`),
+//!   otherwise symlinks to files are stored as the file. `--hard-links`
+//!   stores later names of a hardlinked file as links to the first, and
+//!   `--devices`/`--specials` store device nodes and FIFOs. Anything else is
+//!   skipped with a warning.
+//!
+//! Member names are relative to the source (directories end in `/`); a
+//! single-file source becomes one member named after the file. Entries that
+//! cannot be read are skipped and counted; a failed write ends the export.
+
+use crate::config::SyncConfig;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::filter::FilterRules;
+use crate::symlink_rewrite::rewrite;
+use compio_fs_extended::acl::is_acl_xattr;
+use compio_fs_extended::xattr::{lget_xattr_at_path, llist_xattr_at_path};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::Metadata;
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Tar block size; headers and padded file data are multiples of it
+pub(crate) const BLOCK: usize = 512;
+
+/// Archive path meaning standard output
+pub const STDOUT: &str = "-";
+
+/// What an export wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TarReport {
+    /// Members written (directories, files, links, ...)
+    pub entries: u64,
+    /// Regular files among them
+    pub files: u64,
+    /// File data written, without headers and padding
+    pub bytes: u64,
+    /// Source entries that could not be read and were left out
+    pub source_errors: u64,
+}
+
+impl fmt::Display for TarReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries ({} files, {} bytes)",
+            self.entries, self.files, self.bytes
+        )?;
+        if self.source_errors > 0 {
+            write!(f, ", {} unreadable entries skipped", self.source_errors)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `config.source` as a tar archive to `config.destination`
+///
+/// A destination of `-` writes to standard output.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be created or written, or the
+/// source root cannot be read.
+#[allow(clippy::future_not_send)]
+pub async fn export(config: &SyncConfig) -> Result<TarReport> {
+    if config.destination.as_os_str() == STDOUT {
+        let stdout = std::io::stdout();
+        return write_archive(config, stdout.lock()).await;
+    }
+    let file = std::fs::File::create(&config.destination).map_err(|e| {
+        ErrorContext::new("create archive")
+            .destination(&config.destination)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    write_archive(config, file).await
+}
+
+/// Write `config.source` as a tar archive to `out`
+///
+/// # Errors
+///
+/// Returns an error if writing to `out` fails or the source root cannot be
+/// read.
+#[allow(clippy::future_not_send)]
+pub async fn write_archive<W: Write>(config: &SyncConfig, out: W) -> Result<TarReport> {
+    let mut export = Export {
+        config,
+        filter: FilterRules::from_config(&config.traversal.filter)?,
+        out: std::io::BufWriter::with_capacity(1024 * 1024, out),
+        linked: HashMap::new(),
+        report: TarReport::default(),
+    };
+
+    if config.is_file_copy() {
+        let name = config
+            .source
+            .file_name()
+            .map_or_else(|| PathBuf::from("file"), PathBuf::from);
+        let metadata = std::fs::symlink_metadata(&config.source)
+            .map_err(|e| read_error("lstat", &config.source, &e))?;
+        export.entry(&config.source, &name, &metadata).await?;
+    } else {
+        export.tree(&config.source).await?;
+    }
+
+    // End of archive: two zero blocks
+    export.write(&[0; 2 * BLOCK])?;
+    export
+        .out
+        .flush()
+        .map_err(|e| write_error(&config.destination, &e))?;
+    Ok(export.report)
+}
+
+/// State of an export (single-threaded, members are written in order)
+struct Export<'a, W: Write> {
+    /// Options of the run
+    config: &'a SyncConfig,
+    /// Include/exclude rules
+    filter: FilterRules,
+    /// The archive
+    out: std::io::BufWriter<W>,
+    /// Member name of each hardlinked file already written, by (dev, inode)
+    linked: HashMap<(u64, u64), Vec<u8>>,
+    /// Counters
+    report: TarReport,
+}
+
+impl<W: Write> Export<'_, W> {
+    /// Write every entry under `src_root`, parents before children and
+    /// siblings in name order
+    #[allow(clippy::future_not_send)]
+    async fn tree(&mut self, src_root: &Path) -> Result<()> {
+        let mut pending =
+            children(src_root, Path::new("")).map_err(|e| read_error("read_dir", src_root, &e))?;
+        while let Some(relative) = pending.pop() {
+            let src_path = src_root.join(&relative);
+            let metadata = match std::fs::symlink_metadata(&src_path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    self.source_failed(&src_path, &read_error("lstat", &src_path, &e));
+                    continue;
+                }
+            };
+            if self.filter.is_excluded(&relative, metadata.is_dir()) {
+                continue;
+            }
+            self.entry(&src_path, &relative, &metadata).await?;
+            if metadata.is_dir() {
+                match children(&src_path, &relative) {
+                    Ok(children) => pending.extend(children),
+                    Err(e) => {
+                        self.source_failed(&src_path, &read_error("read_dir", &src_path, &e));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn source_failed(&mut self, path: &Path, error: &SyncError) {
+        self.report.source_errors += 1;
+        self.config.metadata.run.warnings.entry_failed(path, error);
+    }
+
+    /// Write the member for `src_path`, archived as `relative`
+    ///
+    /// Only errors writing the archive are returned; unreadable and
+    /// unsupported entries are skipped.
+    #[allow(clippy::future_not_send)]
+    async fn entry(&mut self, src_path: &Path, relative: &Path, metadata: &Metadata) -> Result<()> {
+        let config = self.config;
+        let metadata_config = &config.metadata;
+        let mut name = relative.as_os_str().as_bytes().to_vec();
+        let file_type = metadata.file_type();
+
+        let member = if file_type.is_dir() {
+            name.push(b'/');
+            Member::new(name, b'5', metadata)
+        } else if file_type.is_file() {
+            return self.file(src_path, name, metadata).await;
+        } else if file_type.is_symlink() && metadata_config.should_preserve_links() {
+            let target = match std::fs::read_link(src_path) {
+                Ok(target) => target,
+                Err(e) => {
+                    self.source_failed(src_path, &read_error("readlink", src_path, &e));
+                    return Ok(());
+                }
+            };
+            let target = rewrite(&metadata_config.symlink_rewrite, &target).unwrap_or(target);
+            let mut member = Member::new(name, b'2', metadata);
+            member.link = target.into_os_string().into_vec();
+            member
+        } else if file_type.is_symlink() && src_path.is_file() {
+            // Stored as the file it points to
+            return match std::fs::metadata(src_path) {
+                Ok(target_metadata) => self.file(src_path, name, &target_metadata).await,
+                Err(e) => {
+                    self.source_failed(src_path, &read_error("stat", src_path, &e));
+                    Ok(())
+                }
+            };
+        } else if (file_type.is_block_device() || file_type.is_char_device())
+            && metadata_config.should_preserve_devices()
+        {
+            let kind = if file_type.is_block_device() {
+                b'4'
+            } else {
+                b'3'
+            };
+            let mut member = Member::new(name, kind, metadata);
+            member.device = split_dev(metadata.rdev());
+            member
+        } else if file_type.is_fifo() && metadata_config.should_preserve_specials() {
+            Member::new(name, b'6', metadata)
+        } else {
+            self.config.metadata.run.warnings.warn(
+                "entry not archived",
+                src_path,
+                format_args!(
+                    "Skipping {}: tar cannot store it with the given options",
+                    src_path.display()
+                ),
+            );
+            return Ok(());
+        };
+        self.member(src_path, member, metadata).await
+    }
+
+    /// Write a regular file (or a link to an earlier name of it)
+    #[allow(clippy::future_not_send)]
+    async fn file(&mut self, src_path: &Path, name: Vec<u8>, metadata: &Metadata) -> Result<()> {
+        let hardlinked = metadata.nlink() > 1 && self.config.metadata.should_preserve_hard_links();
+        let key = (metadata.dev(), metadata.ino());
+        if let Some(first) = self.linked.get(&key).filter(|_| hardlinked) {
+            let mut member = Member::new(name, b'1', metadata);
+            member.link = first.clone();
+            return self.member(src_path, member, metadata).await;
+        }
+
+        let mut file = match std::fs::File::open(src_path) {
+            Ok(file) => file,
+            Err(e) => {
+                self.source_failed(src_path, &read_error("open", src_path, &e));
+                return Ok(());
+            }
+        };
+        if hardlinked {
+            self.linked.insert(key, name.clone());
+        }
+        let mut member = Member::new(name, b'0', metadata);
+        member.size = metadata.len();
+        self.member(src_path, member, metadata).await?;
+        self.report.files += 1;
+        self.file_data(src_path, &mut file, metadata.len())
+    }
+
+    /// Write `size` bytes of `file` and the padding after them
+    ///
+    /// The header already promised `size` bytes: a file that shrank while it
+    /// was read is padded with zeros, one that grew is cut off.
+    fn file_data(&mut self, src_path: &Path, file: &mut std::fs::File, size: u64) -> Result<()> {
+        let buffer_size = self
+            .config
+            .io
+            .buffer_size_kb
+            .map_or(64 * 1024, |kb| kb.get() * 1024);
+        let mut buffer = vec![0u8; buffer_size];
+        let mut remaining = size;
+        while remaining > 0 {
+            let want = usize::try_from(remaining).map_or(buffer.len(), |r| r.min(buffer.len()));
+            let read = match file.read(&mut buffer[..want]) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.source_failed(src_path, &read_error("read", src_path, &e));
+                    break;
+                }
+            };
+            self.write(&buffer[..read])?;
+            remaining -= read as u64;
+            self.report.bytes += read as u64;
+        }
+        if remaining > 0 {
+            self.config.metadata.run.warnings.warn(
+                "file changed while archived",
+                src_path,
+                format_args!(
+                    "{}: file shrank while it was read; padded with zeros",
+                    src_path.display()
+                ),
+            );
+            buffer.fill(0);
+            while remaining > 0 {
+                let zeros =
+                    usize::try_from(remaining).map_or(buffer.len(), |r| r.min(buffer.len()));
+                self.write(&buffer[..zeros])?;
+                remaining -= zeros as u64;
+            }
+        }
+        self.write(&[0; BLOCK][..padding(size)])
+    }
+
+    /// Add the optional metadata of `src_path` to `member` and write its
+    /// header(s)
+    #[allow(clippy::future_not_send)]
+    async fn member(
+        &mut self,
+        src_path: &Path,
+        mut member: Member,
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let config = self.config;
+        let metadata_config = &config.metadata;
+        if metadata_config.atimes {
+            member.atime = Some((metadata.atime(), nanos(metadata.atime_nsec())));
+        }
+        if metadata_config.should_preserve_xattrs() || metadata_config.should_preserve_acls() {
+            for (name, value) in extended_attributes(src_path).await {
+                if !is_acl_xattr(&name) {
+                    if metadata_config.should_preserve_xattrs() {
+                        member.pax.push((format!("SCHILY.xattr.{name}"), value));
+                    }
+                    continue;
+                }
+                if !metadata_config.should_preserve_acls() {
+                    continue;
+                }
+                let key = match name.as_str() {
+                    "system.posix_acl_access" => "SCHILY.acl.access",
+                    "system.posix_acl_default" => "SCHILY.acl.default",
+                    _ => continue,
+                };
+                match acl_text(&value) {
+                    Some(text) => member.pax.push((key.to_string(), text.into_bytes())),
+                    None => debug!("{}: unrecognized ACL in {name}", src_path.display()),
+                }
+            }
+        }
+        let headers = member.encode();
+        self.write(&headers)?;
+        self.report.entries += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.out
+            .write_all(data)
+            .map_err(|e| write_error(&self.config.destination, &e))
+    }
+}
+
+/// Entries of the directory `src_dir` (at `relative`), in reverse name order
+/// so that popping them yields name order
+fn children(src_dir: &Path, relative: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut names = std::fs::read_dir(src_dir)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort_unstable_by(|a, b| b.as_bytes().cmp(a.as_bytes()));
+    Ok(names.into_iter().map(|name| relative.join(name)).collect())
+}
+
+/// Extended attributes of `path` (not following symlinks), sorted by name
+///
+/// Filesystems without xattr support have none.
+async fn extended_attributes(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let Ok(mut names) = llist_xattr_at_path(path).await else {
+        return Vec::new();
+    };
+    names.sort_unstable();
+    let mut attributes = Vec::with_capacity(names.len());
+    for name in names {
+        match lget_xattr_at_path(path, &name).await {
+            Ok(value) => attributes.push((name, value)),
+            Err(e) => debug!("{}: cannot read xattr {name}: {e}", path.display()),
+        }
+    }
+    attributes
+}
+
+/// One archive member: what goes into its ustar header, plus the pax
+/// records for what does not fit there
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Member {
+    /// Path within the archive
+    name: Vec<u8>,
+    /// ustar type flag (`0` file, `1` hard link, `2` symlink, `5` directory, ...)
+    kind: u8,
+    /// Permission bits (including setuid/setgid/sticky)
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    /// Size of the data following the header
+    size: u64,
+    /// Modification time (seconds, nanoseconds)
+    mtime: (i64, u32),
+    /// Access time to record, if any
+    atime: Option<(i64, u32)>,
+    /// Link target of hard links and symlinks
+    link: Vec<u8>,
+    /// Device major and minor number
+    device: (u32, u32),
+    /// Extra pax records (xattrs, ACLs)
+    pax: Vec<(String, Vec<u8>)>,
+}
+
+impl Member {
+    fn new(name: Vec<u8>, kind: u8, metadata: &Metadata) -> Self {
+        Self {
+            name,
+            kind,
+            mode: metadata.mode() & 0o7777,
+            uid: u64::from(metadata.uid()),
+            gid: u64::from(metadata.gid()),
+            size: 0,
+            mtime: (metadata.mtime(), nanos(metadata.mtime_nsec())),
+            atime: None,
+            link: Vec::new(),
+            device: (0, 0),
+            pax: Vec::new(),
+        }
+    }
+
+    /// The member's header blocks: a pax extended header (with its data)
+    /// when needed, then the ustar header
+    fn encode(&self) -> Vec<u8> {
+        let mut header = [0u8; BLOCK];
+        let mut records = Vec::new();
+
+        let utf8 =
+            std::str::from_utf8(&self.name).is_ok() && std::str::from_utf8(&self.link).is_ok();
+        if !utf8 && (self.name.len() > 100 || self.link.len() > 100) {
+            records.extend(pax_record("hdrcharset", b"BINARY"));
+        }
+        if self.name.len() > 100 {
+            records.extend(pax_record("path", &self.name));
+        }
+        if self.link.len() > 100 {
+            records.extend(pax_record("linkpath", &self.link));
+        }
+        put_bytes(&mut header[0..100], &self.name);
+        put_bytes(&mut header[157..257], &self.link);
+
+        put_octal(&mut header[100..108], u64::from(self.mode));
+        for (key, value, field) in [
+            ("uid", self.uid, 108..116),
+            ("gid", self.gid, 116..124),
+            ("size", self.size, 124..136),
+        ] {
+            if !put_octal(&mut header[field], value) {
+                records.extend(pax_record(key, value.to_string().as_bytes()));
+            }
+        }
+        let (seconds, nanos) = self.mtime;
+        let whole = put_octal(&mut header[136..148], u64::try_from(seconds).unwrap_or(0));
+        if !whole || seconds < 0 || nanos != 0 {
+            records.extend(pax_record("mtime", pax_time(seconds, nanos).as_bytes()));
+        }
+        if let Some((seconds, nanos)) = self.atime {
+            records.extend(pax_record("atime", pax_time(seconds, nanos).as_bytes()));
+        }
+        for (key, value) in &self.pax {
+            records.extend(pax_record(key, value));
+        }
+
+        header[156] = self.kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        put_octal(&mut header[329..337], u64::from(self.device.0));
+        put_octal(&mut header[337..345], u64::from(self.device.1));
+        put_checksum(&mut header);
+
+        let mut blocks = Vec::new();
+        if !records.is_empty() {
+            let size = records.len() as u64;
+            blocks.extend(self.pax_header(size));
+            blocks.extend(records);
+            blocks.resize(blocks.len() + padding(size), 0);
+        }
+        blocks.extend(header);
+        blocks
+    }
+
+    /// ustar header of the pax extended header holding `size` bytes of
+    /// records for this member
+    fn pax_header(&self, size: u64) -> [u8; BLOCK] {
+        let mut header = [0u8; BLOCK];
+        let base = self
+            .name
+            .strip_suffix(b"/")
+            .unwrap_or(&self.name)
+            .rsplit(|&byte| byte == b'/')
+            .next()
+            .unwrap_or_default();
+        let mut name = b"PaxHeaders/".to_vec();
+        name.extend(base);
+        put_bytes(&mut header[0..100], &name);
+        put_octal(&mut header[100..108], 0o644);
+        put_octal(&mut header[108..116], 0);
+        put_octal(&mut header[116..124], 0);
+        put_octal(&mut header[124..136], size);
+        put_octal(
+            &mut header[136..148],
+            u64::try_from(self.mtime.0).unwrap_or(0),
+        );
+        header[156] = b'x';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        put_checksum(&mut header);
+        header
+    }
+}
+
+/// Nanoseconds as reported by `MetadataExt` (always 0..1e9)
+fn nanos(nsec: i64) -> u32 {
+    u32::try_from(nsec).unwrap_or(0)
+}
+
+/// Zero bytes that pad `len` bytes to a whole number of blocks
+pub(crate) fn padding(len: u64) -> usize {
+    (BLOCK - (len % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Copy `value` into a header field, cut off at the field's width
+fn put_bytes(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Write `value` as zero-padded octal followed by a NUL
+///
+/// Returns false (writing 0 instead) if it does not fit.
+fn put_octal(field: &mut [u8], value: u64) -> bool {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    if digits.len() >= field.len() {
+        put_octal(field, 0);
+        return false;
+    }
+    put_bytes(field, digits.as_bytes());
+    field[field.len() - 1] = 0;
+    true
+}
+
+/// Fill in the checksum: the sum of the header's bytes with the checksum
+/// field counted as spaces
+fn put_checksum(header: &mut [u8; BLOCK]) {
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+}
+
+/// One pax record: `<length> <key>=<value>\n`, the length counting itself
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend(value);
+    record.push(b'\n');
+    record
+}
+
+/// A time as a pax decimal: seconds with up to nine fraction digits
+fn pax_time(seconds: i64, nanos: u32) -> String {
+    if nanos == 0 {
+        return seconds.to_string();
+    }
+    // -1.25 is a quarter of a second before -1
+    let (sign, whole, fraction) = if seconds < 0 {
+        ("-", -(seconds + 1), 1_000_000_000 - nanos)
+    } else {
+        ("", seconds, nanos)
+    };
+    let fraction = format!("{fraction:09}");
+    format!("{sign}{whole}.{}", fraction.trim_end_matches('0'))
+}
+
+/// A binary `system.posix_acl_*` xattr in the text form tar stores
+/// (`user::rwx,user:1000:r-x,group::r-x,mask::r-x,other::---`)
+///
+/// Named entries use numeric IDs, as the ACL itself does. Returns `None` if
+/// the value is not a version 2 ACL.
+fn acl_text(value: &[u8]) -> Option<String> {
+    let (version, entries) = value.split_first_chunk::<4>()?;
+    if u32::from_le_bytes(*version) != 2 || entries.len() % 8 != 0 {
+        return None;
+    }
+    let mut text = Vec::new();
+    for entry in entries.chunks_exact(8) {
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let perm = u16::from_le_bytes([entry[2], entry[3]]);
+        let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+        let qualifier = match tag {
+            0x01 => "user:".to_string(),
+            0x02 => format!("user:{id}"),
+            0x04 => "group:".to_string(),
+            0x08 => format!("group:{id}"),
+            0x10 => "mask:".to_string(),
+            0x20 => "other:".to_string(),
+            _ => return None,
+        };
+        let bit = |mask: u16, c: char| if perm & mask != 0 { c } else { '-' };
+        text.push(format!(
+            "{qualifier}:{}{}{}",
+            bit(4, 'r'),
+            bit(2, 'w'),
+            bit(1, 'x')
+        ));
+    }
+    Some(text.join(","))
+}
+
+/// Major and minor numbers of a Linux `dev_t`
+const fn split_dev(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 32) & 0xFFFF_F000) | ((dev >> 8) & 0xFFF);
+    let minor = ((dev >> 12) & 0xFFFF_FF00) | (dev & 0xFF);
+    (major as u32, minor as u32)
+}
+
+fn read_error(operation: &'static str, path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new(operation)
+        .source(path)
+        .io_cause(e)
+        .file_system()
+}
+
+fn write_error(path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new("write archive")
+        .destination(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::cli::FilterOption;
+    use tempfile::TempDir;
+
+    /// Names of the members of `archive`, with the pax records preceding each
+    fn members(archive: &[u8]) -> Vec<(Vec<u8>, u8, Vec<u8>)> {
+        let mut members = Vec::new();
+        let mut pax = Vec::new();
+        let mut offset = 0;
+        while archive[offset..offset + BLOCK]
+            .iter()
+            .any(|&byte| byte != 0)
+        {
+            let header = &archive[offset..offset + BLOCK];
+            let size = std::str::from_utf8(&header[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            let data = archive[offset + BLOCK..offset + BLOCK + size].to_vec();
+            let name = header[..100].split(|&byte| byte == 0).next().unwrap();
+            if header[156] == b'x' {
+                pax = data;
+            } else {
+                members.push((name.to_vec(), header[156], std::mem::take(&mut pax)));
+            }
+            offset += BLOCK + size + padding(size as u64);
+        }
+        members
+    }
+
+    #[test]
+    fn test_pax_record_length_counts_itself() {
+        // Requirement: A pax record starts with its own length in bytes,
+        // including the digits of the length
+        for value in ["", "x", &"y".repeat(94), &"z".repeat(95), &"w".repeat(995)] {
+            let record = pax_record("path", value.as_bytes());
+            let text = String::from_utf8(record.clone()).unwrap();
+            let (len, rest) = text.split_once(' ').unwrap();
+            assert_eq!(len.parse::<usize>().unwrap(), record.len(), "{text:?}");
+            assert_eq!(rest, format!("path={value}\n"));
+        }
+    }
+
+    #[test]
+    fn test_header_fields_and_overflow() {
+        // Requirement: Values that do not fit the ustar fields (long names,
+        // large sizes, sub-second times) are carried in pax records, and the
+        // header checksum is correct
+        let metadata = std::fs::metadata(".").unwrap();
+        let mut member = Member::new("d/".repeat(60).into_bytes(), b'0', &metadata);
+        member.size = 1 << 40;
+        member.mtime = (1_700_000_000, 250_000_000);
+        let blocks = member.encode();
+        assert_eq!(blocks.len() % BLOCK, 0);
+
+        let records = String::from_utf8_lossy(&blocks[BLOCK..blocks.len() - BLOCK]);
+        assert!(records.contains(&format!(" path={}\n", "d/".repeat(60))));
+        assert!(records.contains(" size=1099511627776\n"));
+        assert!(records.contains(" mtime=1700000000.25\n"));
+
+        let header = &blocks[blocks.len() - BLOCK..];
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        assert_eq!(&header[124..136], b"00000000000\0");
+        let checksum = std::str::from_utf8(&header[148..154]).unwrap();
+        let sum: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    u32::from(byte)
+                }
+            })
+            .sum();
+        assert_eq!(u32::from_str_radix(checksum, 8).unwrap(), sum);
+    }
+
+    #[test]
+    fn test_pax_time_before_epoch() {
+        // Requirement: Fractions of negative times count towards the epoch
+        assert_eq!(pax_time(5, 0), "5");
+        assert_eq!(pax_time(5, 500_000_000), "5.5");
+        assert_eq!(pax_time(-2, 750_000_000), "-1.25");
+        assert_eq!(pax_time(0, 1), "0.000000001");
+    }
+
+    #[test]
+    fn test_acl_text() {
+        // Requirement: Binary POSIX ACL xattrs become the text form tar
+        // stores, with numeric IDs for named entries
+        let mut acl = 2u32.to_le_bytes().to_vec();
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 5, 1000),
+            (0x04, 4, u32::MAX),
+            (0x08, 7, 100),
+            (0x10, 7, u32::MAX),
+            (0x20, 0, u32::MAX),
+        ] {
+            acl.extend(tag.to_le_bytes());
+            acl.extend(perm.to_le_bytes());
+            acl.extend(id.to_le_bytes());
+        }
+        assert_eq!(
+            acl_text(&acl).unwrap(),
+            "user::rw-,user:1000:r-x,group::r--,group:100:rwx,mask::rwx,other::---"
+        );
+        assert_eq!(acl_text(&acl[..7]), None);
+        assert_eq!(acl_text(&[1, 0, 0, 0]), None);
+    }
+
+    #[compio::test]
+    async fn test_archive_order_filters_and_links() {
+        // Requirement: Members are written parents first in name order,
+        // excluded entries are left out, symlinks are links with --links and
+        // later names of a hardlinked file link to the first with
+        // --hard-links
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        std::fs::create_dir_all(src.join("b/c")).unwrap();
+        std::fs::write(src.join("a.txt"), b"alpha").unwrap();
+        std::fs::write(src.join("b/c/d.txt"), b"delta").unwrap();
+        std::fs::write(src.join("skip.log"), b"skipped").unwrap();
+        std::fs::hard_link(src.join("a.txt"), src.join("z.txt")).unwrap();
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+
+        let mut config = SyncConfig::new(&src, temp.path().join("out.tar"));
+        config.metadata.links = true;
+        config.metadata.hard_links = true;
+        config.traversal.filter.rules = vec![FilterOption::Exclude("*.log".to_string())];
+        let mut archive = Vec::new();
+        let report = write_archive(&config, &mut archive).await.unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+        assert_eq!(report.files, 2);
+        assert_eq!(report.bytes, 10);
+
+        let listed: Vec<_> = members(&archive)
+            .into_iter()
+            .map(|(name, kind, _)| (String::from_utf8(name).unwrap(), kind))
+            .collect();
+        let expected = [
+            ("a.txt", b'0'),
+            ("b/", b'5'),
+            ("b/c/", b'5'),
+            ("b/c/d.txt", b'0'),
+            ("link", b'2'),
+            ("z.txt", b'1'),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(name, kind)| (name.to_string(), kind))
+            .collect();
+        assert_eq!(listed, expected);
+    }
+}
@@ -0,0 +1,1235 @@
+//! Tar import: `arsync --from-tar ARCHIVE DESTINATION` (`-` for stdin)
+//!
+//! The reverse of `tar_export`: the members of a tar stream are written into
+//! the destination the way a copy writes it. Parent directories are opened
+//! relative to the destination root with `O_NOFOLLOW`, one `DirectoryFd` at a
+//! time, so a symlink in the archive (or planted in the destination) cannot
+//! redirect later members outside it; files are created through
+//! [`StagedFile`], so `--atomic-create` applies; and metadata is applied
+//! through the file descriptors, as configured by the metadata options:
+//!
+//! - `--perms`, `--owner`/`--group` and `--times` apply the header's mode,
+//!   numeric IDs and modification time (`--atimes`-style access times from
+//!   pax records are used when present).
+//! - `--xattrs` applies `SCHILY.xattr.*` records and `--acls` the
+//!   `SCHILY.acl.access`/`SCHILY.acl.default` text ACLs (named entries need
+//!   numeric IDs).
+//! - Symlinks (after `--symlink-rewrite`) and hard links are always created;
+//!   device nodes need `--devices` and FIFOs `--specials`.
+//!
+//! ustar, pax (local and global extended headers) and the GNU long name and
+//! long link members are understood. Member names are taken relative to the
+//! destination: leading `/` and `./` are dropped and names containing `..`
+//! are skipped. Filter rules apply to member names.
+//!
+//! Members that cannot be written are reported and counted (their data is
+//! still read past); a corrupt or truncated archive ends the import. Directory
+//! metadata is applied last, children before parents, so that filling a
+//! directory does not change its times and read-only directories can still
+//! be filled.
+
+use crate::atomic_create::StagedFile;
+use crate::config::SyncConfig;
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::filter::FilterRules;
+use crate::metadata::AclKind;
+use crate::symlink_rewrite::rewrite;
+use crate::tar_export::{padding, BLOCK};
+use crate::warnings::WarningLog;
+use compio::fs::File;
+use compio::io::AsyncWriteAtExt;
+use compio_fs_extended::{DirectoryFd, ExtendedFile, OwnershipOps, XattrOps};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Source name that reads the archive from standard input
+pub const STDIN: &str = "-";
+
+/// Largest extended header (pax records, GNU long names) accepted
+const MAX_EXTENDED_HEADER: u64 = 64 * 1024 * 1024;
+
+/// What an import wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    /// Members written (directories, files, links, ...)
+    pub entries: u64,
+    /// Regular files among them
+    pub files: u64,
+    /// File data written
+    pub bytes: u64,
+    /// Members not extracted: unsupported types, unsafe names, and devices
+    /// or FIFOs that were not asked for
+    pub skipped: u64,
+    /// Members that could not be written
+    pub failed: u64,
+}
+
+impl fmt::Display for ExtractReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries ({} files, {} bytes)",
+            self.entries, self.files, self.bytes
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Extract the tar archive `config.source` into `config.destination`
+///
+/// A source of `-` reads the archive from standard input. The destination
+/// is created if it does not exist.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read or is corrupt, or the
+/// destination cannot be created or opened.
+#[allow(clippy::future_not_send)]
+pub async fn import(config: &SyncConfig) -> Result<ExtractReport> {
+    if config.source.as_os_str() == STDIN {
+        let stdin = std::io::stdin();
+        return read_archive(config, stdin.lock()).await;
+    }
+    let file =
+        std::fs::File::open(&config.source).map_err(|e| archive_error(&config.source, &e))?;
+    read_archive(config, file).await
+}
+
+/// Extract the tar archive read from `input` into `config.destination`
+///
+/// # Errors
+///
+/// Returns an error if `input` cannot be read or is not a valid archive, or
+/// the destination cannot be created or opened.
+#[allow(clippy::future_not_send)]
+pub async fn read_archive<R: Read>(config: &SyncConfig, input: R) -> Result<ExtractReport> {
+    let dst_root = &config.destination;
+    compio::fs::create_dir_all(dst_root).await.map_err(|e| {
+        ErrorContext::new("create destination")
+            .destination(dst_root)
+            .io_cause(&e)
+            .file_system()
+    })?;
+    let root = DirectoryFd::open(dst_root).await.map_err(|e| {
+        ErrorContext::new("open destination directory")
+            .destination(dst_root)
+            .cause(&e)
+            .file_system()
+    })?;
+
+    let mut import = Import {
+        config,
+        archive: TarReader::new(input, &config.source),
+        filter: FilterRules::from_config(&config.traversal.filter)?,
+        dirs: HashMap::from([(PathBuf::new(), root)]),
+        excluded_dirs: Vec::new(),
+        dir_metadata: Vec::new(),
+        report: ExtractReport::default(),
+    };
+    while let Some(member) = import.archive.next_member()? {
+        import.extract(member).await?;
+    }
+
+    // Directory metadata last, children before parents
+    for (relative, member) in std::mem::take(&mut import.dir_metadata).into_iter().rev() {
+        let dst = dst_root.join(&relative);
+        let result = match import.dirs.get(&relative) {
+            Some(dir) => apply_metadata(dir.as_file(), &member, config, &dst, true).await,
+            None => continue,
+        };
+        if let Err(e) = result {
+            import.failed(&dst, &e);
+        }
+    }
+    Ok(import.report)
+}
+
+/// State of an import (single-threaded, members are extracted in order)
+struct Import<'a, R: Read> {
+    /// Options of the run
+    config: &'a SyncConfig,
+    /// The archive
+    archive: TarReader<R>,
+    /// Include/exclude rules
+    filter: FilterRules,
+    /// Destination directories opened so far, by path relative to the root
+    dirs: HashMap<PathBuf, DirectoryFd>,
+    /// Excluded directories, whose members are skipped too
+    excluded_dirs: Vec<PathBuf>,
+    /// Directory members, for applying their metadata at the end
+    dir_metadata: Vec<(PathBuf, Member)>,
+    /// Counters
+    report: ExtractReport,
+}
+
+impl<R: Read> Import<'_, R> {
+    /// Write one member (and consume its data)
+    ///
+    /// Only errors reading the archive are returned; members that cannot be
+    /// written are skipped.
+    #[allow(clippy::future_not_send)]
+    async fn extract(&mut self, member: Member) -> Result<()> {
+        let config = self.config;
+        let Some(relative) = member_path(&member.path) else {
+            config.metadata.run.warnings.warn(
+                "unsafe archive member",
+                &config.destination,
+                format_args!(
+                    "Skipping {:?}: member names may not contain ..",
+                    String::from_utf8_lossy(&member.path)
+                ),
+            );
+            self.report.skipped += 1;
+            return self.archive.skip(member.size);
+        };
+        let is_dir = member.kind == b'5';
+        let dst = config.destination.join(&relative);
+        if self.is_excluded(&relative, is_dir) {
+            debug!("Excluded: {}", dst.display());
+            return self.archive.skip(member.size);
+        }
+
+        let result = match member.kind {
+            b'0' | b'\0' | b'7' => return self.file(&relative, &dst, &member).await,
+            b'5' => self.directory(&relative, member.clone()).await,
+            b'1' => self.hard_link(&relative, &dst, &member).await,
+            b'2' => self.symlink(&relative, &dst, &member).await,
+            b'3' | b'4' | b'6' => self.special(&relative, &dst, &member).await,
+            kind => {
+                config.metadata.run.warnings.warn(
+                    "archive member not extracted",
+                    &dst,
+                    format_args!(
+                        "Skipping {}: unsupported tar member type {:?}",
+                        dst.display(),
+                        char::from(kind)
+                    ),
+                );
+                self.report.skipped += 1;
+                return self.archive.skip(member.size);
+            }
+        };
+        match result {
+            Ok(true) => self.report.entries += 1,
+            Ok(false) => self.report.skipped += 1,
+            Err(e) => self.failed(&dst, &e),
+        }
+        self.archive.skip(member.size)
+    }
+
+    /// Whether `relative` is excluded by a filter rule or lies in an
+    /// excluded directory
+    fn is_excluded(&mut self, relative: &Path, is_dir: bool) -> bool {
+        if self
+            .excluded_dirs
+            .iter()
+            .any(|dir| relative.starts_with(dir))
+        {
+            return true;
+        }
+        if relative.as_os_str().is_empty() || !self.filter.is_excluded(relative, is_dir) {
+            return false;
+        }
+        if is_dir {
+            self.excluded_dirs.push(relative.to_path_buf());
+        }
+        true
+    }
+
+    fn failed(&mut self, path: &Path, error: &SyncError) {
+        self.report.failed += 1;
+        self.config.metadata.run.warnings.entry_failed(path, error);
+    }
+
+    /// The open destination directory `relative`, creating it (and its
+    /// parents) if missing
+    #[allow(clippy::future_not_send)]
+    async fn dir(&mut self, relative: &Path) -> Result<DirectoryFd> {
+        if let Some(dir) = self.dirs.get(relative) {
+            return Ok(dir.clone());
+        }
+        let mut path = PathBuf::new();
+        let mut dir = self.dirs[&path].clone();
+        for name in relative.iter() {
+            path.push(name);
+            if let Some(child) = self.dirs.get(&path) {
+                dir = child.clone();
+                continue;
+            }
+            let dst = self.config.destination.join(&path);
+            // O_NOFOLLOW: a symlink where a directory is expected fails
+            // instead of leading out of the destination
+            let child = match dir.open_directory_at(name).await {
+                Ok(child) => child,
+                Err(_) => {
+                    if let Err(e) = dir.create_directory(name, 0o755).await {
+                        debug!("mkdirat {}: {e}", dst.display());
+                    }
+                    dir.open_directory_at(name).await.map_err(|e| {
+                        ErrorContext::new("open destination directory")
+                            .destination(&dst)
+                            .dirfd(dir.path())
+                            .cause(&e)
+                            .file_system()
+                    })?
+                }
+            };
+            self.dirs.insert(path.clone(), child.clone());
+            dir = child;
+        }
+        Ok(dir)
+    }
+
+    /// Parent directory and name of `relative`
+    #[allow(clippy::future_not_send)]
+    async fn parent(&mut self, relative: &Path) -> Result<(DirectoryFd, OsString)> {
+        let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+        let name = relative.file_name().unwrap_or_default().to_os_string();
+        Ok((self.dir(parent).await?, name))
+    }
+
+    /// Create a directory; its metadata is applied at the end, except for
+    /// the default ACL, which its children should inherit
+    #[allow(clippy::future_not_send)]
+    async fn directory(&mut self, relative: &Path, member: Member) -> Result<bool> {
+        let dir = self.dir(relative).await?;
+        if self.config.metadata.should_preserve_acls() {
+            if let Some(acl) = &member.acl_default {
+                set_acl(
+                    dir.as_file(),
+                    AclKind::Default,
+                    acl,
+                    dir.path(),
+                    &self.config.metadata.run.warnings,
+                )?;
+            }
+        }
+        self.dir_metadata.push((relative.to_path_buf(), member));
+        Ok(true)
+    }
+
+    /// Write a regular file from the archive
+    #[allow(clippy::future_not_send)]
+    async fn file(&mut self, relative: &Path, dst: &Path, member: &Member) -> Result<()> {
+        let created = match self.parent(relative).await {
+            Ok((dir, name)) => {
+                StagedFile::create_for(&dir, &name, &self.config.metadata, dst).await
+            }
+            Err(e) => Err(e),
+        };
+        let (mut file, staged) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                self.failed(dst, &e);
+                return self.archive.skip(member.size);
+            }
+        };
+
+        // The data is read to the end even if writing it fails
+        let buffer_size = self
+            .config
+            .io
+            .buffer_size_kb
+            .map_or(1024 * 1024, |kb| kb.get() * 1024);
+        let mut buffer = Vec::with_capacity(buffer_size);
+        let mut written = Ok(());
+        let mut offset = 0;
+        while offset < member.size {
+            let chunk = usize::try_from(member.size - offset)
+                .map_or(buffer_size, |left| left.min(buffer_size));
+            buffer.resize(chunk, 0);
+            self.archive.read_data(&mut buffer)?;
+            if written.is_ok() {
+                let write_result = file.write_all_at(buffer, offset).await;
+                buffer = write_result.1;
+                written = write_result.0.map_err(|e| {
+                    ErrorContext::new("write destination")
+                        .destination(dst)
+                        .io_cause(&e)
+                        .file_system()
+                });
+            }
+            offset += chunk as u64;
+        }
+        self.archive.skip_padding(member.size)?;
+
+        let result = match written {
+            Ok(()) => match apply_metadata(&file, member, self.config, dst, false).await {
+                Ok(()) => staged.commit(&file, dst).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                self.report.entries += 1;
+                self.report.files += 1;
+                self.report.bytes += member.size;
+            }
+            Err(e) => self.failed(dst, &e),
+        }
+        Ok(())
+    }
+
+    /// Link `relative` to an earlier member
+    #[allow(clippy::future_not_send)]
+    async fn hard_link(&mut self, relative: &Path, dst: &Path, member: &Member) -> Result<bool> {
+        let Some(target) = member_path(&member.link) else {
+            return Err(ErrorContext::new("link destination")
+                .destination(dst)
+                .cause(&"hard link target contains ..")
+                .file_system());
+        };
+        let original = self.config.destination.join(&target);
+        // Through the same O_NOFOLLOW directories, so a symlink member
+        // cannot lead the target out of the destination
+        let (original_dir, original_name) = self.parent(&target).await?;
+        let (dir, name) = self.parent(relative).await?;
+        remove_existing(&dir, &name, dst).await?;
+        compio_fs_extended::hardlink::linkat_at(&original_dir, &original_name, &dir, &name)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("linkat")
+                    .source(&original)
+                    .destination(dst)
+                    .io_cause(&e)
+                    .file_system()
+            })?;
+        Ok(true)
+    }
+
+    /// Create a symlink (with `--symlink-rewrite` applied to its target)
+    #[allow(clippy::future_not_send)]
+    async fn symlink(&mut self, relative: &Path, dst: &Path, member: &Member) -> Result<bool> {
+        let config = self.config;
+        let metadata_config = &config.metadata;
+        let target = PathBuf::from(OsStr::from_bytes(&member.link));
+        let target = rewrite(&metadata_config.symlink_rewrite, &target).unwrap_or(target);
+        let (dir, name) = self.parent(relative).await?;
+        remove_existing(&dir, &name, dst).await?;
+        let name = name.to_string_lossy();
+        dir.symlinkat(&target.to_string_lossy(), &name)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("symlinkat")
+                    .destination(dst)
+                    .dirfd(dir.path())
+                    .cause(&e)
+                    .file_system()
+            })?;
+        if let Some((uid, gid)) = metadata_config.ownership(member.uid, member.gid) {
+            if let Err(e) = dir.lfchownat(&name, uid, gid).await {
+                debug!(
+                    "Could not preserve symlink ownership of {} (may need root): {e}",
+                    dst.display()
+                );
+            }
+        }
+        if metadata_config.should_preserve_link_timestamps() {
+            let accessed = member.atime.unwrap_or(member.mtime);
+            dir.lutimensat(&name, accessed, member.mtime)
+                .await
+                .map_err(|e| {
+                    ErrorContext::new("set symlink timestamps")
+                        .destination(dst)
+                        .cause(&e)
+                        .file_system()
+                })?;
+        }
+        Ok(true)
+    }
+
+    /// Create a device node or FIFO, if the options allow it
+    #[allow(clippy::future_not_send)]
+    async fn special(&mut self, relative: &Path, dst: &Path, member: &Member) -> Result<bool> {
+        let config = self.config;
+        let metadata_config = &config.metadata;
+        let (file_type, allowed) = match member.kind {
+            b'3' => (libc::S_IFCHR, metadata_config.should_preserve_devices()),
+            b'4' => (libc::S_IFBLK, metadata_config.should_preserve_devices()),
+            _ => (libc::S_IFIFO, metadata_config.should_preserve_specials()),
+        };
+        if !allowed {
+            debug!("Skipping special file {} (not requested)", dst.display());
+            return Ok(false);
+        }
+        let (dir, name) = self.parent(relative).await?;
+        remove_existing(&dir, &name, dst).await?;
+        let rdev = join_dev(member.device.0, member.device.1);
+        dir.mknodat(&name, file_type | (member.mode & 0o777), rdev)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("mknodat special file")
+                    .destination(dst)
+                    .dirfd(dir.path())
+                    .cause(&e)
+                    .file_system()
+            })?;
+
+        // Ownership before permissions: chown clears the setuid and setgid bits
+        let name_str = name.to_string_lossy();
+        if let Some((uid, gid)) = metadata_config.ownership(member.uid, member.gid) {
+            if let Err(e) = dir.lfchownat(&name_str, uid, gid).await {
+                debug!(
+                    "Could not preserve ownership of {} (may need root): {e}",
+                    dst.display()
+                );
+            }
+        }
+        if metadata_config.should_preserve_permissions() {
+            dir.fchmodat(&name, member.mode & 0o7777)
+                .await
+                .map_err(|e| {
+                    ErrorContext::new("fchmodat special file")
+                        .destination(dst)
+                        .cause(&e)
+                        .file_system()
+                })?;
+        }
+        if metadata_config.should_preserve_timestamps() {
+            let accessed = member.atime.unwrap_or(member.mtime);
+            dir.lutimensat(&name_str, accessed, member.mtime)
+                .await
+                .map_err(|e| {
+                    ErrorContext::new("set special file timestamps")
+                        .destination(dst)
+                        .cause(&e)
+                        .file_system()
+                })?;
+        }
+        Ok(true)
+    }
+}
+
+/// Remove a non-directory `name` in `dir` that is in the way
+#[allow(clippy::future_not_send)]
+async fn remove_existing(dir: &DirectoryFd, name: &OsStr, dst: &Path) -> Result<()> {
+    match crate::atomic_create::unlink_at(dir.as_raw_fd(), name) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(ErrorContext::new("remove existing destination")
+                .destination(dst)
+                .io_cause(&e)
+                .file_system())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Apply the configured metadata of `member` to the open `file`
+///
+/// Ownership comes first (chown clears the setuid and setgid bits) and the
+/// access ACL after the mode (fchmod rewrites its mask entry). Ownership that
+/// cannot be set without privileges is not an error, as in a copy.
+#[allow(clippy::future_not_send)]
+async fn apply_metadata(
+    file: &File,
+    member: &Member,
+    config: &SyncConfig,
+    dst: &Path,
+    is_dir: bool,
+) -> Result<()> {
+    let metadata_config = &config.metadata;
+    if let Some((uid, gid)) = metadata_config.ownership(member.uid, member.gid) {
+        if let Err(e) = file.fchown(uid, gid).await {
+            debug!(
+                "Could not preserve ownership of {} (may need root): {e}",
+                dst.display()
+            );
+        }
+    }
+    if metadata_config.should_preserve_permissions() {
+        file.set_permissions(compio::fs::Permissions::from_mode(member.mode & 0o7777))
+            .await
+            .map_err(|e| {
+                ErrorContext::new("fchmod")
+                    .destination(dst)
+                    .io_cause(&e)
+                    .file_system()
+            })?;
+    }
+    if metadata_config.should_preserve_xattrs() {
+        let extended = ExtendedFile::from_ref(file);
+        for (name, value) in &member.xattrs {
+            if let Err(e) = extended.set_xattr(name, value).await {
+                tracing::warn!(
+                    "Failed to restore extended attribute '{name}' of {}: {e}",
+                    dst.display()
+                );
+            }
+        }
+    }
+    if metadata_config.should_preserve_acls() {
+        if let Some(acl) = &member.acl_access {
+            set_acl(
+                file,
+                AclKind::Access,
+                acl,
+                dst,
+                &metadata_config.run.warnings,
+            )?;
+        }
+    }
+    let times = if is_dir {
+        metadata_config.should_preserve_dir_timestamps()
+    } else {
+        metadata_config.should_preserve_timestamps()
+    };
+    if times {
+        let accessed = member.atime.unwrap_or(member.mtime);
+        compio_fs_extended::metadata::futimens_fd(file, accessed, member.mtime)
+            .await
+            .map_err(|e| {
+                ErrorContext::new("futimens")
+                    .destination(dst)
+                    .cause(&e)
+                    .file_system()
+            })?;
+    }
+    Ok(())
+}
+
+/// Set an ACL given in the text form tar stores; one that cannot be restored
+/// is warned about in `warnings`
+fn set_acl(
+    file: &File,
+    kind: AclKind,
+    text: &str,
+    dst: &Path,
+    warnings: &WarningLog,
+) -> Result<()> {
+    let Some(acl) = acl_from_text(text) else {
+        warnings.warn(
+            "ACL not restored",
+            dst,
+            format_args!(
+                "{}: cannot restore ACL {text:?} (named entries need numeric IDs)",
+                dst.display()
+            ),
+        );
+        return Ok(());
+    };
+    compio_fs_extended::acl::set_acl_fd(file.as_raw_fd(), kind, Some(&acl)).map_err(|e| {
+        ErrorContext::new("set ACL")
+            .destination(dst)
+            .cause(&e)
+            .file_system()
+    })
+}
+
+/// Path of a member relative to the destination, or `None` if it would
+/// leave it
+///
+/// Leading `/` and `.` components are dropped; `""` is the destination itself.
+fn member_path(name: &[u8]) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(OsStr::from_bytes(name)).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Linux `dev_t` from major and minor numbers
+const fn join_dev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    ((major & 0xFFFF_F000) << 32)
+        | ((major & 0xFFF) << 8)
+        | ((minor & 0xFFFF_FF00) << 12)
+        | (minor & 0xFF)
+}
+
+/// A POSIX ACL in tar's text form (`user::rwx,user:1000:r-x,...`, entries
+/// separated by commas or newlines) as a binary `system.posix_acl_*` xattr
+///
+/// Qualifiers must be numeric IDs, or be followed by one (`user:bob:r--:1000`,
+/// as star writes them). Returns `None` for anything else.
+fn acl_from_text(text: &str) -> Option<Vec<u8>> {
+    let mut entries = Vec::new();
+    for entry in text
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let mut fields = entry.split(':');
+        let (tag, qualifier, perms) = (fields.next()?, fields.next()?, fields.next()?);
+        let tag: u16 = match (tag, qualifier.is_empty()) {
+            ("user" | "u", true) => 0x01,
+            ("user" | "u", false) => 0x02,
+            ("group" | "g", true) => 0x04,
+            ("group" | "g", false) => 0x08,
+            ("mask" | "m", _) => 0x10,
+            ("other" | "o", _) => 0x20,
+            _ => return None,
+        };
+        let id: u32 = if tag == 0x02 || tag == 0x08 {
+            qualifier
+                .parse()
+                .ok()
+                .or_else(|| fields.next()?.parse().ok())?
+        } else {
+            u32::MAX
+        };
+        let mut perm = 0u16;
+        for c in perms.chars() {
+            perm |= match c {
+                'r' => 4,
+                'w' => 2,
+                'x' => 1,
+                '-' => 0,
+                _ => return None,
+            };
+        }
+        entries.push((tag, perm, id));
+    }
+    // The kernel wants the entries sorted by tag, then ID
+    entries.sort_unstable_by_key(|&(tag, _, id)| (tag, id));
+    let mut acl = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in entries {
+        acl.extend(tag.to_le_bytes());
+        acl.extend(perm.to_le_bytes());
+        acl.extend(id.to_le_bytes());
+    }
+    Some(acl)
+}
+
+/// One member of the archive, with its extended headers applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Member {
+    /// Name within the archive
+    path: Vec<u8>,
+    /// ustar type flag
+    kind: u8,
+    /// Permission bits (including setuid/setgid/sticky)
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Size of the data following the header
+    size: u64,
+    mtime: SystemTime,
+    /// Access time, if the archive records one
+    atime: Option<SystemTime>,
+    /// Link target of hard links and symlinks
+    link: Vec<u8>,
+    /// Device major and minor number
+    device: (u32, u32),
+    /// Extended attributes (`SCHILY.xattr.*`)
+    xattrs: Vec<(String, Vec<u8>)>,
+    /// Access ACL, in text form
+    acl_access: Option<String>,
+    /// Default ACL, in text form
+    acl_default: Option<String>,
+}
+
+/// Reads members of a tar stream one after the other
+struct TarReader<R: Read> {
+    input: R,
+    /// Archive path, for error messages
+    path: PathBuf,
+    /// Records of global pax headers, applied to every later member
+    globals: Vec<(String, Vec<u8>)>,
+}
+
+impl<R: Read> TarReader<R> {
+    fn new(input: R, path: &Path) -> Self {
+        Self {
+            input,
+            path: path.to_path_buf(),
+            globals: Vec::new(),
+        }
+    }
+
+    /// The next member, positioned at its data; `None` at the end of the
+    /// archive
+    fn next_member(&mut self) -> Result<Option<Member>> {
+        let mut records = Vec::new();
+        let mut long_name = None;
+        let mut long_link = None;
+        loop {
+            let mut header = [0u8; BLOCK];
+            if !self.read_block(&mut header)? || header.iter().all(|&byte| byte == 0) {
+                return Ok(None);
+            }
+            if !checksum_matches(&header) {
+                return Err(self.corrupt("header checksum mismatch (not a tar archive?)"));
+            }
+            let size =
+                numeric(&header[124..136]).ok_or_else(|| self.corrupt("invalid size field"))?;
+            match header[156] {
+                b'x' => records.extend(parse_pax(&self.extended_header(size)?)),
+                b'g' => {
+                    let globals = parse_pax(&self.extended_header(size)?);
+                    self.globals.extend(globals);
+                }
+                b'L' => long_name = Some(until_nul(&self.extended_header(size)?).to_vec()),
+                b'K' => long_link = Some(until_nul(&self.extended_header(size)?).to_vec()),
+                _ => {
+                    let mut member = parse_header(&header, size);
+                    for (key, value) in self.globals.iter().chain(&records) {
+                        member.apply_record(key, value);
+                    }
+                    if let Some(name) = long_name {
+                        member.path = name;
+                    }
+                    if let Some(link) = long_link {
+                        member.link = link;
+                    }
+                    return Ok(Some(member));
+                }
+            }
+        }
+    }
+
+    /// Read the next block; false at the end of the input
+    fn read_block(&mut self, block: &mut [u8; BLOCK]) -> Result<bool> {
+        let mut filled = 0;
+        while filled < BLOCK {
+            match self.input.read(&mut block[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(self.corrupt("truncated header")),
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(archive_error(&self.path, &e)),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Data of an extended header member (including its padding)
+    fn extended_header(&mut self, size: u64) -> Result<Vec<u8>> {
+        if size > MAX_EXTENDED_HEADER {
+            return Err(self.corrupt("extended header too large"));
+        }
+        let mut data = vec![0u8; size as usize];
+        self.read_data(&mut data)?;
+        self.skip_padding(size)?;
+        Ok(data)
+    }
+
+    /// Fill `data` from the current member's data
+    fn read_data(&mut self, data: &mut [u8]) -> Result<()> {
+        self.input.read_exact(data).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                self.corrupt("truncated member data")
+            } else {
+                archive_error(&self.path, &e)
+            }
+        })
+    }
+
+    /// Skip `size` bytes of member data and the padding after them
+    fn skip(&mut self, size: u64) -> Result<()> {
+        let total = size + padding(size) as u64;
+        let skipped = std::io::copy(&mut (&mut self.input).take(total), &mut std::io::sink())
+            .map_err(|e| archive_error(&self.path, &e))?;
+        if skipped < total {
+            return Err(self.corrupt("truncated member data"));
+        }
+        Ok(())
+    }
+
+    /// Skip the padding after `size` bytes of member data
+    fn skip_padding(&mut self, size: u64) -> Result<()> {
+        let mut padding_bytes = [0u8; BLOCK];
+        self.read_data(&mut padding_bytes[..padding(size)])
+    }
+
+    fn corrupt(&self, problem: &str) -> SyncError {
+        ErrorContext::new("read archive")
+            .source(&self.path)
+            .cause(&problem)
+            .file_system()
+    }
+}
+
+impl Member {
+    /// Apply one pax record (unknown keys are ignored)
+    fn apply_record(&mut self, key: &str, value: &[u8]) {
+        let text = std::str::from_utf8(value).unwrap_or_default();
+        match key {
+            "path" => self.path = value.to_vec(),
+            "linkpath" => self.link = value.to_vec(),
+            "size" => self.size = text.parse().unwrap_or(self.size),
+            "uid" => self.uid = text.parse().unwrap_or(self.uid),
+            "gid" => self.gid = text.parse().unwrap_or(self.gid),
+            "mtime" => self.mtime = pax_time(text).unwrap_or(self.mtime),
+            "atime" => self.atime = pax_time(text).or(self.atime),
+            "SCHILY.acl.access" => self.acl_access = Some(text.to_string()),
+            "SCHILY.acl.default" => self.acl_default = Some(text.to_string()),
+            _ => {
+                if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                    self.xattrs.push((name.to_string(), value.to_vec()));
+                }
+            }
+        }
+    }
+}
+
+/// The ustar fields of `header`
+fn parse_header(header: &[u8; BLOCK], size: u64) -> Member {
+    let mut path = until_nul(&header[0..100]).to_vec();
+    let prefix = until_nul(&header[345..500]);
+    if header[257..262] == *b"ustar" && !prefix.is_empty() {
+        path = [prefix, b"/".as_slice(), &path].concat();
+    }
+    let field = |range: std::ops::Range<usize>| numeric(&header[range]).unwrap_or(0);
+    Member {
+        path,
+        kind: header[156],
+        mode: u32::try_from(field(100..108)).unwrap_or(0),
+        uid: u32::try_from(field(108..116)).unwrap_or(0),
+        gid: u32::try_from(field(116..124)).unwrap_or(0),
+        size,
+        mtime: UNIX_EPOCH
+            .checked_add(Duration::from_secs(field(136..148)))
+            .unwrap_or(UNIX_EPOCH),
+        atime: None,
+        link: until_nul(&header[157..257]).to_vec(),
+        device: (
+            u32::try_from(field(329..337)).unwrap_or(0),
+            u32::try_from(field(337..345)).unwrap_or(0),
+        ),
+        xattrs: Vec::new(),
+        acl_access: None,
+        acl_default: None,
+    }
+}
+
+/// Whether the checksum field of `header` matches its contents
+fn checksum_matches(header: &[u8; BLOCK]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            if (148..156).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(byte)
+            }
+        })
+        .sum();
+    numeric(&header[148..156]) == Some(sum)
+}
+
+/// A numeric header field: octal digits (padded with spaces or NULs), or
+/// GNU base-256 when the high bit of the first byte is set
+fn numeric(field: &[u8]) -> Option<u64> {
+    if field.first()? & 0x80 != 0 {
+        let mut value = u64::from(field[0] & 0x7F);
+        for &byte in &field[1..] {
+            value = value.checked_mul(256)? | u64::from(byte);
+        }
+        return Some(value);
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// The bytes of `field` before its first NUL
+fn until_nul(field: &[u8]) -> &[u8] {
+    field
+        .iter()
+        .position(|&byte| byte == 0)
+        .map_or(field, |end| &field[..end])
+}
+
+/// The records of a pax extended header (malformed ones are dropped)
+fn parse_pax(mut data: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut records = Vec::new();
+    while let Some(space) = data.iter().position(|&byte| byte == b' ') {
+        let Some(len) = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > space + 1 && len <= data.len())
+        else {
+            break;
+        };
+        let record = &data[space + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(equals) = record.iter().position(|&byte| byte == b'=') {
+            if let Ok(key) = std::str::from_utf8(&record[..equals]) {
+                records.push((key.to_string(), record[equals + 1..].to_vec()));
+            }
+        }
+        data = &data[len..];
+    }
+    records
+}
+
+/// A pax time (`seconds[.fraction]`, possibly negative)
+fn pax_time(text: &str) -> Option<SystemTime> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let seconds: u64 = seconds.parse().ok()?;
+    let digits: String = fraction.chars().take(9).collect();
+    let nanos = if digits.is_empty() {
+        0
+    } else {
+        format!("{digits:0<9}").parse().ok()?
+    };
+    let offset = Duration::new(seconds, nanos);
+    if negative {
+        UNIX_EPOCH.checked_sub(offset)
+    } else {
+        UNIX_EPOCH.checked_add(offset)
+    }
+}
+
+fn archive_error(path: &Path, e: &std::io::Error) -> SyncError {
+    ErrorContext::new("read archive")
+        .source(path)
+        .io_cause(e)
+        .file_system()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::cli::FilterOption;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    /// A ustar header block for `name` followed by `data` and its padding
+    fn member(name: &str, kind: u8, link: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"14000000000");
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        let mut blocks = header.to_vec();
+        blocks.extend(data);
+        blocks.resize(blocks.len() + padding(data.len() as u64), 0);
+        blocks
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        // Requirement: Numeric fields are octal padded with spaces or NULs,
+        // or GNU base-256 when the high bit is set
+        assert_eq!(numeric(b"0000644\0"), Some(0o644));
+        assert_eq!(numeric(b"  755 \0\0"), Some(0o755));
+        assert_eq!(numeric(b"\0\0\0\0"), Some(0));
+        assert_eq!(
+            numeric(&[0x80, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]),
+            Some(1 << 32)
+        );
+        assert_eq!(numeric(b"0009"), None);
+    }
+
+    #[test]
+    fn test_pax_records() {
+        // Requirement: pax records are "LEN KEY=VALUE\n" with LEN counting
+        // the whole record; values may hold any bytes
+        let data = b"18 path=a/b=c.txt\n22 SCHILY.xattr.k=\x00\x01\n\n99 truncated";
+        let records = parse_pax(data);
+        assert_eq!(
+            records,
+            [
+                ("path".to_string(), b"a/b=c.txt".to_vec()),
+                ("SCHILY.xattr.k".to_string(), b"\x00\x01\n".to_vec()),
+            ]
+        );
+        assert_eq!(
+            pax_time("1700000000.25"),
+            Some(UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000))
+        );
+        assert_eq!(
+            pax_time("-1.25"),
+            UNIX_EPOCH.checked_sub(Duration::new(1, 250_000_000))
+        );
+        assert_eq!(pax_time("x"), None);
+    }
+
+    #[test]
+    fn test_member_paths_stay_in_destination() {
+        // Requirement: Absolute and ./ names are taken relative to the
+        // destination; names with .. are refused
+        let path = |name: &str| member_path(name.as_bytes());
+        assert_eq!(path("/etc/passwd"), Some(PathBuf::from("etc/passwd")));
+        assert_eq!(path("./a/./b/"), Some(PathBuf::from("a/b")));
+        assert_eq!(path("./"), Some(PathBuf::new()));
+        assert_eq!(path("a/../../b"), None);
+        assert_eq!(path(".."), None);
+    }
+
+    #[test]
+    fn test_acl_from_text() {
+        // Requirement: Text ACLs become the binary xattr with entries sorted
+        // by tag and ID; named entries need a numeric ID
+        let mut expected = 2u32.to_le_bytes().to_vec();
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 5, 1000),
+            (0x04, 4, u32::MAX),
+            (0x08, 7, 100),
+            (0x10, 7, u32::MAX),
+            (0x20, 0, u32::MAX),
+        ] {
+            expected.extend(tag.to_le_bytes());
+            expected.extend(perm.to_le_bytes());
+            expected.extend(id.to_le_bytes());
+        }
+        let text = "user::rw-,group::r--,user:1000:r-x,group:100:rwx,mask::rwx,other::---";
+        assert_eq!(acl_from_text(text).unwrap(), expected);
+        let star = "user::rw-\nuser:bob:r-x:1000\ngroup::r--\ngroup:100:rwx\nmask::rwx\nother::---";
+        assert_eq!(acl_from_text(star).unwrap(), expected);
+        assert_eq!(acl_from_text("user:bob:r-x"), None);
+        assert_eq!(acl_from_text("user::rwz"), None);
+    }
+
+    #[compio::test]
+    async fn test_export_then_import_round_trip() {
+        // Requirement: A tree archived with --to-tar is recreated by
+        // --from-tar with its data, links, modes and times; filter rules
+        // apply to member names
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        std::fs::create_dir_all(src.join("b/c")).unwrap();
+        std::fs::write(src.join("a.txt"), b"alpha").unwrap();
+        std::fs::write(src.join("b/c/d.txt"), vec![7u8; 100_000]).unwrap();
+        std::fs::write(src.join("b/skip.log"), b"skipped").unwrap();
+        std::fs::hard_link(src.join("a.txt"), src.join("z.txt")).unwrap();
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+        std::fs::set_permissions(src.join("a.txt"), std::fs::Permissions::from_mode(0o640))
+            .unwrap();
+        let mtime = UNIX_EPOCH + Duration::new(1_600_000_000, 500_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(src.join("b/c/d.txt"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let mut config = SyncConfig::new(&src, temp.path().join("unused.tar"));
+        config.metadata.archive = true;
+        config.metadata.hard_links = true;
+        let mut archive = Vec::new();
+        crate::tar_export::write_archive(&config, &mut archive)
+            .await
+            .unwrap();
+
+        let dst = temp.path().join("dst");
+        let mut config = SyncConfig::new(temp.path().join("in.tar"), &dst);
+        config.metadata.archive = true;
+        config.traversal.filter.rules = vec![FilterOption::Exclude("*.log".to_string())];
+        let report = read_archive(&config, archive.as_slice()).await.unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(report.bytes, 100_005);
+        assert_eq!(report.failed, 0);
+
+        assert_eq!(std::fs::read(dst.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(
+            std::fs::read(dst.join("b/c/d.txt")).unwrap(),
+            vec![7u8; 100_000]
+        );
+        assert!(!dst.join("b/skip.log").exists());
+        assert_eq!(
+            std::fs::read_link(dst.join("link")).unwrap(),
+            PathBuf::from("a.txt")
+        );
+        let a = std::fs::metadata(dst.join("a.txt")).unwrap();
+        assert_eq!(a.ino(), std::fs::metadata(dst.join("z.txt")).unwrap().ino());
+        assert_eq!(a.mode() & 0o7777, 0o640);
+        let d = std::fs::metadata(dst.join("b/c/d.txt")).unwrap();
+        assert_eq!(d.modified().unwrap(), mtime);
+    }
+
+    #[compio::test]
+    async fn test_unsafe_and_corrupt_members() {
+        // Requirement: Members that would leave the destination are skipped,
+        // and a header with a bad checksum ends the import with an error
+        let temp = TempDir::new().unwrap();
+        let dst = temp.path().join("dst");
+        let config = SyncConfig::new(temp.path().join("in.tar"), &dst);
+
+        let mut archive = member("../escape.txt", b'0', "", b"out");
+        archive.extend(member("/abs.txt", b'0', "", b"abs"));
+        archive.extend(member("ok.txt", b'0', "", b"ok"));
+        archive.extend([0u8; 2 * BLOCK]);
+        let report = read_archive(&config, archive.as_slice()).await.unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(report.skipped, 1);
+        assert!(!temp.path().join("escape.txt").exists());
+        assert_eq!(std::fs::read(dst.join("abs.txt")).unwrap(), b"abs");
+        assert_eq!(std::fs::read(dst.join("ok.txt")).unwrap(), b"ok");
+
+        let mut corrupt = member("bad.txt", b'0', "", b"bad");
+        corrupt[0] = b'c';
+        assert!(read_archive(&config, corrupt.as_slice()).await.is_err());
+        let truncated = &member("short.txt", b'0', "", b"short")[..BLOCK + 2];
+        assert!(read_archive(&config, truncated).await.is_err());
+    }
+
+    #[compio::test]
+    async fn test_symlinked_directory_is_not_followed() {
+        // Requirement: A symlink member cannot redirect later members out of
+        // the destination
+        let temp = TempDir::new().unwrap();
+        let outside = temp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let dst = temp.path().join("dst");
+        let mut config = SyncConfig::new(temp.path().join("in.tar"), &dst);
+        config.metadata.links = true;
+
+        let mut archive = member("d", b'2', &outside.to_string_lossy(), b"");
+        archive.extend(member("d/planted.txt", b'0', "", b"planted"));
+        archive.extend([0u8; 2 * BLOCK]);
+        let report = read_archive(&config, archive.as_slice()).await.unwrap();
+        assert_eq!(report.failed, 1);
+        assert!(!outside.join("planted.txt").exists());
+    }
+
+    #[compio::test]
+    async fn test_hard_link_through_symlink_is_refused() {
+        // Requirement: A hard link member cannot reach a file outside the
+        // destination through a symlink member, nor let a later member
+        // write to it
+        let temp = TempDir::new().unwrap();
+        let outside = temp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret"), b"outside").unwrap();
+        let dst = temp.path().join("dst");
+        let mut config = SyncConfig::new(temp.path().join("in.tar"), &dst);
+        config.metadata.links = true;
+
+        let mut archive = member("evil", b'2', &outside.to_string_lossy(), b"");
+        archive.extend(member("x", b'1', "evil/secret", b""));
+        archive.extend(member("x", b'0', "", b"overwritten"));
+        archive.extend([0u8; 2 * BLOCK]);
+        let report = read_archive(&config, archive.as_slice()).await.unwrap();
+        assert_eq!(report.failed, 1);
+        assert_eq!(std::fs::read(outside.join("secret")).unwrap(), b"outside");
+        assert_eq!(std::fs::read(dst.join("x")).unwrap(), b"overwritten");
+    }
+}
@@ -0,0 +1,351 @@
+//! Run-ID-stamped naming for temporary artifacts and crash cleanup
+//!
+//! Every temporary file or directory arsync creates is named
+//! `.arsync.tmp.<runid>.<name>`, where `<runid>` identifies the process that
+//! created it. A normal run removes its own artifacts; a crashed run leaves them
+//! behind, and `arsync cleanup DST` finds and removes them later.
+//!
+//! The run ID is `<pid>-<start time in hex>`. The PID lets cleanup tell whether
+//! the owning process is still alive (artifacts of live runs are never removed
+//! without `--force`); the start time keeps names unique across PID reuse.
+//!
+//! A name too long to fit `NAME_MAX` (255 bytes) behind the prefix is embedded
+//! shortened, with a hash of the full name (`long_names::shorten_name`), so
+//! the same name still always gets the same temporary or partial name.
+
+use crate::error::{ErrorContext, Result};
+use crate::long_names::{shorten_name, DEFAULT_NAME_MAX};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Filename prefix shared by all temporary artifacts
+pub const TEMP_PREFIX: &str = ".arsync.tmp.";
+
+/// Filename prefix of partially copied files kept by `--partial`
+///
+/// These are deliberately not run-stamped: the next run looks them up by name
+/// to resume them, and cleanup leaves them alone.
+pub const PARTIAL_PREFIX: &str = ".arsync.partial.";
+
+/// Longest prefix `temp_name` puts before a name: `TEMP_PREFIX`, the widest
+/// run ID (a 10-digit PID, a dash and 16 hex digits) and a dot
+pub const TEMP_NAME_RESERVE: usize = TEMP_PREFIX.len() + 10 + 1 + 16 + 1;
+
+// ============================================================================
+// RUN ID
+// ============================================================================
+
+/// Identifier of the arsync process that created a temporary artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RunId {
+    /// Process ID of the owning run
+    pub pid: u32,
+    /// Start time of the owning run (seconds since the Unix epoch)
+    pub started: u64,
+}
+
+impl RunId {
+    /// Run ID of the current process (fixed for the lifetime of the process)
+    #[must_use]
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<RunId> = OnceLock::new();
+        *CURRENT.get_or_init(|| Self {
+            pid: std::process::id(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        })
+    }
+
+    /// Parse a run ID in `<pid>-<hex start time>` form
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        let (pid, started) = s.split_once('-')?;
+        Some(Self {
+            pid: pid.parse().ok()?,
+            started: u64::from_str_radix(started, 16).ok()?,
+        })
+    }
+
+    /// Whether the owning process still appears to be running
+    ///
+    /// PIDs can be reused, so `true` only means "possibly alive"; callers must
+    /// treat that as "in use" unless the user forces removal.
+    #[must_use]
+    pub fn is_alive(&self) -> bool {
+        if *self == Self::current() {
+            return true;
+        }
+        process_exists(self.pid)
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:x}", self.pid, self.started)
+    }
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 performs only the existence/permission check
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    // No cheap liveness check: assume alive so cleanup stays conservative
+    true
+}
+
+// ============================================================================
+// NAMING
+// ============================================================================
+
+/// Temporary filename for `name` owned by the current run
+///
+/// Returns `.arsync.tmp.<runid>.<name>`, with `name` shortened if the whole
+/// would exceed `NAME_MAX`.
+#[must_use]
+pub fn temp_name(name: &OsStr) -> OsString {
+    prefixed(format!("{TEMP_PREFIX}{}.", RunId::current()), name)
+}
+
+/// Name of the partial file kept for `name` with `--partial`
+///
+/// Returns `.arsync.partial.<name>`, with `name` shortened if the whole would
+/// exceed `NAME_MAX`.
+#[must_use]
+pub fn partial_name(name: &OsStr) -> OsString {
+    prefixed(PARTIAL_PREFIX.to_string(), name)
+}
+
+/// `prefix` followed by `name`, shortened to fit `DEFAULT_NAME_MAX` in all
+fn prefixed(prefix: String, name: &OsStr) -> OsString {
+    let room = DEFAULT_NAME_MAX - prefix.len();
+    let mut prefixed = OsString::from(prefix);
+    if name.len() > room {
+        prefixed.push(shorten_name(name, room));
+    } else {
+        prefixed.push(name);
+    }
+    prefixed
+}
+
+/// Temporary path in `dir` for `name` owned by the current run
+#[must_use]
+pub fn temp_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(temp_name(OsStr::new(name)))
+}
+
+/// Split a temporary filename into its run ID and original name (shortened,
+/// if it was too long to embed whole)
+///
+/// Returns `None` for names that do not follow the scheme, so unrelated files
+/// (even ones starting with `.arsync`) are never mistaken for artifacts.
+#[must_use]
+pub fn parse_temp_name(file_name: &OsStr) -> Option<(RunId, &str)> {
+    let rest = file_name.to_str()?.strip_prefix(TEMP_PREFIX)?;
+    let (run_id, name) = rest.split_once('.')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((RunId::parse(run_id)?, name))
+}
+
+// ============================================================================
+// CLEANUP
+// ============================================================================
+
+/// Options for `cleanup_stale_artifacts`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CleanupOptions {
+    /// Only report what would be removed
+    pub dry_run: bool,
+    /// Also remove artifacts whose owning process appears to be alive
+    pub force: bool,
+}
+
+/// Result of a cleanup scan
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    /// Artifacts removed (or that would be removed in dry-run mode)
+    pub removed: Vec<PathBuf>,
+    /// Artifacts skipped because their run may still be in progress
+    pub in_use: Vec<PathBuf>,
+    /// Bytes of regular files removed
+    pub bytes_freed: u64,
+}
+
+/// Find and remove temporary artifacts left behind by previous runs
+///
+/// Scans `root` recursively without following symlinks or crossing filesystem
+/// boundaries. Only names matching the temporary scheme are considered;
+/// artifacts of the current run, and of runs whose process is still alive
+/// (unless `force` is set), are left in place.
+///
+/// # Errors
+///
+/// Returns an error if the tree cannot be scanned or an artifact cannot be removed.
+pub fn cleanup_stale_artifacts(root: &Path, options: CleanupOptions) -> Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+    let mut entries = walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(true)
+        .into_iter();
+
+    while let Some(entry) = entries.next() {
+        let entry = entry.map_err(|e| {
+            ErrorContext::new("scan for temporary artifacts")
+                .source(e.path().unwrap_or(root))
+                .cause(&e)
+                .file_system()
+        })?;
+        let Some((run_id, _)) = parse_temp_name(entry.file_name()) else {
+            continue;
+        };
+
+        let is_dir = entry.file_type().is_dir();
+        if is_dir {
+            // The artifact is removed (or kept) as a whole
+            entries.skip_current_dir();
+        }
+
+        if run_id == RunId::current() || (!options.force && run_id.is_alive()) {
+            report.in_use.push(entry.into_path());
+            continue;
+        }
+
+        let path = entry.path();
+        let bytes = if is_dir {
+            0
+        } else {
+            entry.metadata().map_or(0, |m| m.len())
+        };
+        if !options.dry_run {
+            let removed = if is_dir {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+            removed.map_err(|e| {
+                ErrorContext::new("remove temporary artifact")
+                    .destination(path)
+                    .io_cause(&e)
+                    .file_system()
+            })?;
+        }
+        report.bytes_freed += bytes;
+        report.removed.push(entry.into_path());
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A run ID whose process cannot exist (PIDs never reach `u32::MAX`)
+    const DEAD_RUN: RunId = RunId {
+        pid: u32::MAX,
+        started: 0x1234,
+    };
+
+    fn stale_name(name: &str) -> String {
+        format!("{TEMP_PREFIX}{DEAD_RUN}.{name}")
+    }
+
+    #[test]
+    fn test_temp_name_round_trip() {
+        let name = temp_name(OsStr::new("data.file.txt"));
+        let (run_id, original) = parse_temp_name(&name).unwrap();
+        assert_eq!(run_id, RunId::current());
+        assert_eq!(original, "data.file.txt");
+    }
+
+    #[test]
+    fn test_long_names_fit_name_max() {
+        // Requirement: Temporary and partial names of a 250-byte name stay
+        // within NAME_MAX, can be created, and are the same on every call
+        let temp_dir = TempDir::new().unwrap();
+        let long = OsString::from(format!("{}.txt", "n".repeat(246)));
+        std::fs::write(temp_dir.path().join(&long), b"").unwrap();
+
+        let temp = temp_name(&long);
+        assert!(temp.len() <= DEFAULT_NAME_MAX, "{}", temp.len());
+        assert_eq!(temp, temp_name(&long));
+        let (run_id, embedded) = parse_temp_name(&temp).unwrap();
+        assert_eq!(run_id, RunId::current());
+        assert!(embedded.starts_with("nnn") && embedded.ends_with(".txt"));
+        std::fs::write(temp_dir.path().join(&temp), b"").unwrap();
+
+        let partial = partial_name(&long);
+        assert!(partial.len() <= DEFAULT_NAME_MAX, "{}", partial.len());
+        assert_eq!(partial, partial_name(&long));
+        std::fs::write(temp_dir.path().join(&partial), b"").unwrap();
+
+        assert!(format!("{TEMP_PREFIX}{}.", RunId::current()).len() <= TEMP_NAME_RESERVE);
+    }
+
+    #[test]
+    fn test_parse_rejects_foreign_names() {
+        for name in [
+            "file.txt",
+            ".arsync.tmp.",
+            ".arsync.tmp.notarunid.file",
+            ".arsync.tmp.12-ab.",
+            ".arsync-hardlinks.1.slots",
+            ".arsync.partial.file",
+        ] {
+            assert!(parse_temp_name(OsStr::new(name)).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_cleanup_removes_only_stale_artifacts() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+
+        let stale_file = root.join("sub").join(stale_name("big.bin"));
+        std::fs::write(&stale_file, b"partial").unwrap();
+        let stale_dir = root.join(stale_name("staging"));
+        std::fs::create_dir(&stale_dir).unwrap();
+        std::fs::write(stale_dir.join("inner"), b"x").unwrap();
+        let own = temp_path(root, "mine");
+        std::fs::write(&own, b"in progress").unwrap();
+        let regular = root.join("sub").join("keep.txt");
+        std::fs::write(&regular, b"keep").unwrap();
+
+        let dry = cleanup_stale_artifacts(
+            root,
+            CleanupOptions {
+                dry_run: true,
+                force: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(dry.removed.len(), 2);
+        assert!(stale_file.exists() && stale_dir.exists());
+
+        let report = cleanup_stale_artifacts(root, CleanupOptions::default()).unwrap();
+        assert_eq!(report.removed.len(), 2);
+        assert_eq!(report.in_use, vec![own.clone()]);
+        assert_eq!(report.bytes_freed, 7);
+        assert!(!stale_file.exists());
+        assert!(!stale_dir.exists());
+        assert!(own.exists(), "current run's artifacts must be kept");
+        assert!(regular.exists(), "regular files must be kept");
+    }
+}
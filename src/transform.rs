@@ -0,0 +1,326 @@
+//! Content transforms during the copy (`--transform GLOB:CMD`)
+//!
+//! A regular file whose path matches GLOB is not copied: it is piped through
+//! CMD, run with `/bin/sh -c`, and what CMD writes becomes the destination's
+//! contents. GLOB is an rsync pattern as in `--exclude` (see `filter`),
+//! matched against the path relative to the transfer root, and the first
+//! matching rule wins. CMD reads the source on its standard input and
+//! writes to the destination file on its standard output; it also finds
+//! both paths in `ARSYNC_SOURCE` and `ARSYNC_DESTINATION`, and its standard
+//! error is arsync's. For example `--transform '*.txt:sed s/secret/XXX/'` or
+//! `--transform '*.bat:unix2dos'`.
+//!
+//! Since the destination differs from the source by design, a transformed
+//! file follows its own rules:
+//!
+//! - Update check: the destination is up to date when its modification time
+//!   matches the source's (preserved with `--times`), whatever its size;
+//!   `--checksum` compares nothing for it, and `--ignore-times` always runs
+//!   CMD again.
+//! - Sizes: the byte counts and progress report the source's size, not the
+//!   size CMD wrote.
+//! - Data path: no `copy_file_range`, reflink, parallel copy, `--sparse`,
+//!   `--partial` or `--verify-direct`; `--link-dest` directories are not
+//!   looked into.
+//! - Metadata is preserved as for a copy, after CMD has finished.
+//! - A CMD that fails (non-zero exit status or signal) fails the file, and
+//!   the destination is left as it was: the output is written under a
+//!   temporary name (`--atomic-create=rename` unless another staging was
+//!   asked for) and only put in place once CMD succeeds.
+//!
+//! Transforms apply to the files of a directory copy.
+
+use crate::atomic_create::{AtomicCreate, StagedFile};
+use crate::error::{ErrorContext, Result, SyncError};
+use crate::events::EVENTS;
+use crate::filter::Pattern;
+use crate::metadata::{preserve_file_metadata, MetadataConfig};
+use compio_fs_extended::{DirectoryFd, FileMetadata};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+use std::path::Path;
+use std::process::Command;
+
+/// One `--transform` rule
+#[derive(Debug, Clone)]
+pub struct TransformRule {
+    /// Pattern as given
+    glob: String,
+    /// Compiled pattern
+    pattern: Pattern,
+    /// Shell command the matching files are piped through
+    pub command: String,
+}
+
+impl TransformRule {
+    /// Parse `GLOB:CMD` (split at the first colon)
+    ///
+    /// # Errors
+    ///
+    /// Returns a message for the command line if there is no colon or either
+    /// side is empty.
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        match text.split_once(':') {
+            Some((glob, command)) if !glob.is_empty() && !command.trim().is_empty() => Ok(Self {
+                glob: glob.to_string(),
+                pattern: Pattern::new(glob),
+                command: command.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid transform rule {text:?} (expected GLOB:CMD)"
+            )),
+        }
+    }
+
+    /// Whether the file at `relative` (from the transfer root) matches
+    #[must_use]
+    pub fn matches(&self, relative: &Path) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+
+        self.pattern.matches(relative.as_os_str().as_bytes(), false)
+    }
+}
+
+impl fmt::Display for TransformRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.glob, self.command)
+    }
+}
+
+/// The first rule matching the file at `relative`, if any
+#[must_use]
+pub fn find<'a>(rules: &'a [TransformRule], relative: &Path) -> Option<&'a TransformRule> {
+    rules.iter().find(|rule| rule.matches(relative))
+}
+
+/// Write `dst` by piping the source through `rule`'s command
+///
+/// Takes the same directory descriptors and pre-fetched metadata as
+/// `copy_file_internal`.
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be opened, the destination cannot
+/// be created, the command cannot be run or fails, or the metadata cannot
+/// be preserved.
+#[allow(clippy::future_not_send, clippy::too_many_arguments)]
+pub async fn copy_transformed(
+    rule: &TransformRule,
+    src: &Path,
+    dst: &Path,
+    metadata_config: &MetadataConfig,
+    src_metadata: &FileMetadata,
+    src_parent_dir: &DirectoryFd,
+    src_filename: &OsStr,
+    dst_parent_dir: &DirectoryFd,
+    dst_filename: &OsStr,
+) -> Result<()> {
+    let (command, source, destination) =
+        (rule.command.clone(), src.to_path_buf(), dst.to_path_buf());
+    let run = move |input: File, output: File| {
+        let status = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&command)
+            .env("ARSYNC_SOURCE", &source)
+            .env("ARSYNC_DESTINATION", &destination)
+            .stdin(input)
+            .stdout(output)
+            .status()
+            .map_err(|e| {
+                SyncError::CopyFailed(format!(
+                    "Cannot run --transform command {command:?} for {}: {e}",
+                    source.display()
+                ))
+            })?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(SyncError::CopyFailed(format!(
+                "--transform command {command:?} failed for {} ({status})",
+                source.display()
+            )))
+        }
+    };
+    copy_filtered(
+        src,
+        dst,
+        metadata_config,
+        src_metadata,
+        src_parent_dir,
+        src_filename,
+        dst_parent_dir,
+        dst_filename,
+        run,
+    )
+    .await
+}
+
+/// Write `dst` with what `filter` makes of the source
+///
+/// `filter` runs on a blocking thread, given the source open for reading
+/// and the staged destination open for writing. The destination is put in
+/// place, with the source's metadata, only if it succeeds; progress events
+/// count the source's size.
+///
+/// # Errors
+///
+/// Returns an error if the source cannot be opened, the destination cannot
+/// be created, `filter` fails, or the metadata cannot be preserved.
+#[allow(clippy::future_not_send, clippy::too_many_arguments)]
+pub(crate) async fn copy_filtered<F>(
+    src: &Path,
+    dst: &Path,
+    metadata_config: &MetadataConfig,
+    src_metadata: &FileMetadata,
+    src_parent_dir: &DirectoryFd,
+    src_filename: &OsStr,
+    dst_parent_dir: &DirectoryFd,
+    dst_filename: &OsStr,
+    filter: F,
+) -> Result<()>
+where
+    F: FnOnce(File, File) -> Result<()> + Send + 'static,
+{
+    let events = EVENTS.file_started(&metadata_config.run, src, src_metadata.size);
+    let result = filter_file(
+        src,
+        dst,
+        metadata_config,
+        src_metadata,
+        src_parent_dir,
+        src_filename,
+        dst_parent_dir,
+        dst_filename,
+        filter,
+    )
+    .await;
+    match &result {
+        Ok(()) => events.done(src_metadata.size),
+        Err(_) => events.failed(),
+    }
+    result
+}
+
+/// See [`copy_filtered`]
+#[allow(clippy::future_not_send, clippy::too_many_arguments)]
+async fn filter_file<F>(
+    src: &Path,
+    dst: &Path,
+    metadata_config: &MetadataConfig,
+    src_metadata: &FileMetadata,
+    src_parent_dir: &DirectoryFd,
+    src_filename: &OsStr,
+    dst_parent_dir: &DirectoryFd,
+    dst_filename: &OsStr,
+    filter: F,
+) -> Result<()>
+where
+    F: FnOnce(File, File) -> Result<()> + Send + 'static,
+{
+    let src_file = src_parent_dir
+        .open_file_at(src_filename, true, false, false, false)
+        .await
+        .map_err(|e| {
+            ErrorContext::new("openat source")
+                .source(src)
+                .destination(dst)
+                .dirfd(src_parent_dir.path())
+                .cause(&e)
+                .file_system()
+        })?;
+    // A failed filter must not leave its partial output at the destination
+    let (dst_file, staged) =
+        if metadata_config.delay_updates || metadata_config.atomic_create.is_some() {
+            StagedFile::create_for(dst_parent_dir, dst_filename, metadata_config, dst).await?
+        } else {
+            StagedFile::create(
+                dst_parent_dir,
+                dst_filename,
+                Some(AtomicCreate::Rename),
+                dst,
+            )
+            .await?
+        };
+
+    let dup = |file: &compio::fs::File| {
+        // SAFETY: the file stays open while its descriptor is duplicated
+        unsafe { BorrowedFd::borrow_raw(file.as_raw_fd()) }
+            .try_clone_to_owned()
+            .map(File::from)
+            .map_err(|e| {
+                ErrorContext::new("dup for content filter")
+                    .source(src)
+                    .destination(dst)
+                    .io_cause(&e)
+                    .file_system()
+            })
+    };
+    let (input, output) = (dup(&src_file)?, dup(&dst_file)?);
+    compio::runtime::spawn_blocking(move || filter(input, output))
+        .await
+        .map_err(|_| SyncError::Internal("Content filter worker panicked".to_string()))??;
+
+    if metadata_config.fsync {
+        dst_file
+            .sync_all()
+            .await
+            .map_err(|e| SyncError::FileSystem(format!("Failed to sync destination file: {e}")))?;
+    }
+    // --emit-checksums: what the filter wrote is read back to be hashed
+    let checksums = &metadata_config.run.checksums;
+    let digester = match checksums.digester() {
+        Some(digester) => {
+            let reader = staged.reopen_for_read(&dst_file, dst).await?;
+            Some(crate::checksum_list::read_back(&reader, None, digester, dst).await?)
+        }
+        None => None,
+    };
+    preserve_file_metadata(
+        &src_file,
+        &dst_file,
+        dst,
+        src_metadata.accessed,
+        src_metadata.modified,
+        metadata_config,
+    )
+    .await?;
+    staged.commit(&dst_file, dst).await?;
+    if let Some(digester) = digester {
+        checksums.record(dst, digester);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn test_parse_transform_rule() {
+        // Requirement: GLOB:CMD splits at the first colon, so commands may
+        // contain colons, and round-trips through Display
+        let rule = TransformRule::parse("*.txt:sed s/a:b/c/").unwrap();
+        assert_eq!(rule.command, "sed s/a:b/c/");
+        assert_eq!(rule.to_string(), "*.txt:sed s/a:b/c/");
+        for text in ["", "*.txt", ":cat", "*.txt:", "*.txt:  "] {
+            assert!(TransformRule::parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        // Requirement: Rules match like --exclude patterns, in order
+        let rules = [
+            TransformRule::parse("secrets/*.env:redact").unwrap(),
+            TransformRule::parse("*.env:cat").unwrap(),
+        ];
+        let command = |path: &str| find(&rules, Path::new(path)).map(|rule| rule.command.as_str());
+        assert_eq!(command("app/secrets/prod.env"), Some("redact"));
+        assert_eq!(command("app/prod.env"), Some("cat"));
+        assert_eq!(command("app/prod.envx"), None);
+    }
+}
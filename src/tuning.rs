@@ -0,0 +1,218 @@
+//! Per-filesystem tuning profiles (`--tune`, `--show-tuning`)
+//!
+//! Filesystems differ in what makes copies fast: the I/O size that keeps them
+//! busy, whether preallocating with `fallocate` helps or is wasted work on a
+//! copy-on-write or network filesystem, whether reflinks are available and how
+//! much an `fsync` costs. arsync ships a profile per filesystem and picks the
+//! one matching the destination's `statfs` `f_type`; `--tune=PROFILE`
+//! overrides the detection and `--show-tuning` prints the chosen profile.
+//!
+//! FUSE destinations get the `fuse` profile. Object-store mounts (s3fs,
+//! gcsfuse) upload a file as a whole when it is closed or synced, so they
+//! handle preallocation, out-of-order writes and `fsync` poorly: the profile
+//! turns off `fallocate` and parallel in-file writes (large files are written
+//! sequentially from start to end) and uses large buffers. `--tune=generic`
+//! restores the usual behavior on FUSE filesystems that do not need this.
+//!
+//! The selected profile belongs to the run (see `run_state`): `sync_files`
+//! picks it with [`select`] and the copy path reads it from the run's
+//! `MetadataConfig`, so runs in one process (`arsync serve` jobs) each use
+//! their own destination's. An explicit `--buffer-size-kb` still wins over
+//! the profile.
+
+use crate::config::SyncConfig;
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// `EXT4_SUPER_MAGIC` (also ext2/ext3)
+const EXT4_SUPER_MAGIC: i64 = 0xEF53;
+/// `XFS_SUPER_MAGIC`
+const XFS_SUPER_MAGIC: i64 = 0x5846_5342;
+/// `BTRFS_SUPER_MAGIC`
+const BTRFS_SUPER_MAGIC: i64 = 0x9123_683E;
+/// ZFS on Linux
+const ZFS_SUPER_MAGIC: i64 = 0x2FC1_2FC1;
+/// `NFS_SUPER_MAGIC`
+const NFS_SUPER_MAGIC: i64 = 0x6969;
+/// `SMB2_SUPER_MAGIC` (SMB2/3 via the cifs client)
+const SMB2_SUPER_MAGIC: i64 = 0xFE53_4D42;
+/// `CIFS_SUPER_MAGIC`
+const CIFS_SUPER_MAGIC: i64 = 0xFF53_4D42;
+/// `FUSE_SUPER_MAGIC` (s3fs, gcsfuse, sshfs, ...)
+const FUSE_SUPER_MAGIC: i64 = 0x6573_5546;
+
+/// Built-in tuning profile names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TuneProfile {
+    /// ext2/ext3/ext4
+    Ext4,
+    /// XFS
+    Xfs,
+    /// Btrfs
+    Btrfs,
+    /// ZFS
+    Zfs,
+    /// NFS
+    Nfs,
+    /// SMB/CIFS
+    Cifs,
+    /// FUSE, notably object-store mounts (s3fs, gcsfuse)
+    Fuse,
+    /// Any other filesystem
+    Generic,
+}
+
+impl TuneProfile {
+    /// Profile for a `statfs.f_type` magic number
+    #[must_use]
+    pub const fn from_magic(magic: i64) -> Self {
+        match magic & 0xFFFF_FFFF {
+            EXT4_SUPER_MAGIC => Self::Ext4,
+            XFS_SUPER_MAGIC => Self::Xfs,
+            BTRFS_SUPER_MAGIC => Self::Btrfs,
+            ZFS_SUPER_MAGIC => Self::Zfs,
+            NFS_SUPER_MAGIC => Self::Nfs,
+            SMB2_SUPER_MAGIC | CIFS_SUPER_MAGIC => Self::Cifs,
+            FUSE_SUPER_MAGIC => Self::Fuse,
+            _ => Self::Generic,
+        }
+    }
+
+    /// Settings of this profile
+    #[must_use]
+    pub const fn settings(self) -> TuningProfile {
+        const KB: usize = 1024;
+        let (buffer_size, fallocate, reflink, expensive_fsync) = match self {
+            Self::Ext4 => (128 * KB, true, false, false),
+            Self::Xfs => (256 * KB, true, true, false),
+            // Preallocated extents are rewritten anyway on copy-on-write
+            Self::Btrfs => (256 * KB, false, true, true),
+            // ZFS cannot preallocate; 128 KB matches the default recordsize
+            Self::Zfs => (128 * KB, false, false, true),
+            // Large I/O amortizes round trips; fallocate is emulated or remote
+            Self::Nfs | Self::Cifs => (1024 * KB, false, false, true),
+            // Object stores upload whole files; an fsync uploads it again
+            Self::Fuse => (8 * 1024 * KB, false, false, true),
+            Self::Generic => (64 * KB, true, false, false),
+        };
+        TuningProfile {
+            profile: self,
+            buffer_size,
+            fallocate,
+            reflink,
+            expensive_fsync,
+            // Object-store FUSE mounts buffer or reject out-of-order writes
+            parallel_writes: !matches!(self, Self::Fuse),
+        }
+    }
+}
+
+/// Copy settings tuned for one filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TuningProfile {
+    /// Profile these settings come from
+    pub profile: TuneProfile,
+    /// Read/write buffer size in bytes
+    pub buffer_size: usize,
+    /// Preallocate destination files with `fallocate`
+    pub fallocate: bool,
+    /// Filesystem supports reflinks (`FICLONE`)
+    pub reflink: bool,
+    /// Per-file `fsync` is costly; `--syncfs` is preferable to `--fsync`
+    pub expensive_fsync: bool,
+    /// Large files may be written by several tasks at once (`--parallel-max-depth`)
+    pub parallel_writes: bool,
+}
+
+impl Default for TuningProfile {
+    fn default() -> Self {
+        TuneProfile::Generic.settings()
+    }
+}
+
+impl fmt::Display for TuningProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "profile: {:?}", self.profile)?;
+        writeln!(f, "buffer_size_kb: {}", self.buffer_size / 1024)?;
+        writeln!(f, "fallocate: {}", self.fallocate)?;
+        writeln!(f, "reflink: {}", self.reflink)?;
+        writeln!(f, "expensive_fsync: {}", self.expensive_fsync)?;
+        write!(f, "parallel_writes: {}", self.parallel_writes)
+    }
+}
+
+/// Detect the profile for `path`, or its nearest existing ancestor
+///
+/// Falls back to `TuneProfile::Generic` if no ancestor can be examined.
+#[must_use]
+pub fn detect(path: &Path) -> TuneProfile {
+    let Some(dir) = path.ancestors().find_map(|ancestor| {
+        let ancestor = if ancestor.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            ancestor
+        };
+        std::fs::File::open(ancestor).ok()
+    }) else {
+        return TuneProfile::Generic;
+    };
+    let mut buf = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: buf is a valid out-pointer for fstatfs on an open fd
+    if unsafe { libc::fstatfs(dir.as_raw_fd(), buf.as_mut_ptr()) } != 0 {
+        return TuneProfile::Generic;
+    }
+    // SAFETY: fstatfs succeeded and initialized buf
+    #[allow(clippy::useless_conversion)] // f_type width is platform-dependent
+    let fs_type = i64::from(unsafe { buf.assume_init() }.f_type);
+    TuneProfile::from_magic(fs_type)
+}
+
+/// Profile for this run: `--tune`, or detected from the destination
+///
+/// An explicit `--buffer-size-kb` overrides the profile's buffer size.
+#[must_use]
+pub fn select(config: &SyncConfig) -> TuningProfile {
+    let profile = config
+        .io
+        .tune
+        .unwrap_or_else(|| detect(&config.destination));
+    let mut settings = profile.settings();
+    if let Some(kb) = config.io.buffer_size_kb {
+        settings.buffer_size = kb.get() * 1024;
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_by_magic() {
+        assert_eq!(TuneProfile::from_magic(0xEF53), TuneProfile::Ext4);
+        // f_type is sign-extended on some platforms
+        assert_eq!(
+            TuneProfile::from_magic(0xFFFF_FFFF_9123_683E_u64 as i64),
+            TuneProfile::Btrfs
+        );
+        assert_eq!(TuneProfile::from_magic(0x6969), TuneProfile::Nfs);
+        assert_eq!(TuneProfile::from_magic(0x1234), TuneProfile::Generic);
+
+        let btrfs = TuneProfile::Btrfs.settings();
+        assert!(btrfs.reflink && !btrfs.fallocate);
+        assert_eq!(TuningProfile::default().buffer_size, 64 * 1024);
+        assert!(btrfs.to_string().starts_with("profile: Btrfs\n"));
+    }
+
+    #[test]
+    fn test_fuse_profile_writes_sequentially() {
+        // Requirement: Object-store FUSE mounts get no fallocate, no parallel
+        // in-file writes and large buffers
+        let fuse = TuneProfile::from_magic(0x6573_5546).settings();
+        assert_eq!(fuse.profile, TuneProfile::Fuse);
+        assert!(!fuse.fallocate && !fuse.parallel_writes && fuse.expensive_fsync);
+        assert!(fuse.buffer_size >= 1024 * 1024);
+        assert!(TuneProfile::Generic.settings().parallel_writes);
+    }
+}
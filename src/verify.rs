@@ -0,0 +1,554 @@
+//! Checksum verification of a copy against its source (`arsync verify`)
+//!
+//! `arsync verify SOURCE DESTINATION` checks that DESTINATION holds the tree
+//! of SOURCE: every entry present with the same type, symlinks with the same
+//! target, regular files with the same size and BLAKE3 hash. Entries only in
+//! DESTINATION are differences too; metadata is not compared.
+//!
+//! Hashing is what takes the time on large trees, so it is done by a pool of
+//! threads (`--parallel[=N]`, one per CPU by default; one without it). Files
+//! larger than a region (`--region-size-mb`) are split with the parallel
+//! copy's region planner, and every region of every file, on either side,
+//! is one task for the pool: a single huge file keeps every thread busy, and
+//! the source and destination are read at the same time. A file's hash is the
+//! BLAKE3 hash of its region hashes in order (a one-level Merkle tree), so it
+//! is the same whichever threads hashed the regions and in what order; when
+//! two files' hashes differ, the regions whose hashes differ say where.
+
+use crate::copy::plan_regions;
+use crate::error::{ErrorContext, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// Bytes read at a time by a hashing thread
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Mismatching regions listed individually in a difference
+const MAX_REPORTED_REGIONS: usize = 10;
+
+/// How `verify` works
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyOptions {
+    /// Hashing threads
+    pub threads: usize,
+    /// Bytes per region of a large file
+    pub region_size: u64,
+    /// Show a progress bar of the bytes hashed
+    pub progress: bool,
+}
+
+/// How an entry of the destination differs from the source's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The entry is not in the destination
+    Missing,
+    /// The entry is only in the destination
+    Extra,
+    /// The entries are of different types
+    Type {
+        /// Type in the source
+        source: &'static str,
+        /// Type in the destination
+        destination: &'static str,
+    },
+    /// The files have different sizes
+    Size {
+        /// Size in the source
+        source: u64,
+        /// Size in the destination
+        destination: u64,
+    },
+    /// The symlinks point to different targets
+    Target,
+    /// The files have different contents in these byte ranges (regions)
+    Contents(Vec<Range<u64>>),
+    /// The entry could not be read on one side
+    Unreadable(String),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing from the destination"),
+            Self::Extra => write!(f, "only in the destination"),
+            Self::Type {
+                source,
+                destination,
+            } => write!(
+                f,
+                "{source} in the source, {destination} in the destination"
+            ),
+            Self::Size {
+                source,
+                destination,
+            } => write!(
+                f,
+                "{source} bytes in the source, {destination} in the destination"
+            ),
+            Self::Target => write!(f, "symlink target differs"),
+            Self::Contents(regions) => {
+                write!(f, "contents differ in bytes")?;
+                for region in regions.iter().take(MAX_REPORTED_REGIONS) {
+                    write!(f, " {}..{}", region.start, region.end)?;
+                }
+                if regions.len() > MAX_REPORTED_REGIONS {
+                    write!(
+                        f,
+                        " and {} more regions",
+                        regions.len() - MAX_REPORTED_REGIONS
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Unreadable(message) => write!(f, "cannot be read: {message}"),
+        }
+    }
+}
+
+/// One entry that differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Path relative to the two roots (empty for the roots themselves)
+    pub path: PathBuf,
+    /// What differs
+    pub difference: Difference,
+}
+
+/// Outcome of `verify`
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Entries of the source compared
+    pub entries: u64,
+    /// Regular files whose contents were hashed
+    pub files_hashed: u64,
+    /// Bytes hashed, on each side
+    pub bytes_hashed: u64,
+    /// Entries that differ, sorted by path
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Compare the tree (or file) `destination` with `source`
+///
+/// # Errors
+///
+/// Returns an error if either root cannot be read. Entries below them that
+/// cannot be read are reported as `Difference::Unreadable`.
+pub fn verify(source: &Path, destination: &Path, options: VerifyOptions) -> Result<VerifyReport> {
+    for root in [source, destination] {
+        std::fs::symlink_metadata(root).map_err(|e| {
+            ErrorContext::new("lstat")
+                .source(root)
+                .io_cause(&e)
+                .file_system()
+        })?;
+    }
+    let mut plan = Plan {
+        source,
+        destination,
+        region_size: options.region_size.max(1),
+        files: Vec::new(),
+        report: VerifyReport::default(),
+    };
+    plan.compare(Path::new(""));
+
+    let Plan {
+        files, mut report, ..
+    } = plan;
+    report.files_hashed = files.len() as u64;
+    report.bytes_hashed = files.iter().map(|file| file.size).sum();
+    let progress = if options.progress {
+        let bar = ProgressBar::new(2 * report.bytes_hashed);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} ({eta})")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        );
+        bar
+    } else {
+        ProgressBar::hidden()
+    };
+    report.mismatches.extend(hash_and_compare(
+        source,
+        destination,
+        &files,
+        options.threads.max(1),
+        &progress,
+    ));
+    progress.finish_and_clear();
+    report.mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
+
+/// A regular file present on both sides with the same size
+struct FileJob {
+    /// Path relative to the roots
+    relative: PathBuf,
+    /// Size in bytes
+    size: u64,
+    /// Regions hashed separately, in file order
+    regions: Vec<Range<u64>>,
+}
+
+/// The tree walk: differences found by metadata alone, files left to hash
+struct Plan<'a> {
+    /// Source root
+    source: &'a Path,
+    /// Destination root
+    destination: &'a Path,
+    /// Bytes per region of a large file
+    region_size: u64,
+    /// Files to hash
+    files: Vec<FileJob>,
+    /// What was found so far
+    report: VerifyReport,
+}
+
+impl Plan<'_> {
+    /// Compare the entry at `relative`, and below it for a directory
+    fn compare(&mut self, relative: &Path) {
+        let (src_path, dst_path) = (self.source.join(relative), self.destination.join(relative));
+        self.report.entries += 1;
+        let src_metadata = match std::fs::symlink_metadata(&src_path) {
+            Ok(metadata) => metadata,
+            Err(e) => return self.differs(relative, Difference::Unreadable(e.to_string())),
+        };
+        let dst_metadata = match std::fs::symlink_metadata(&dst_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return self.differs(relative, Difference::Missing)
+            }
+            Err(e) => return self.differs(relative, Difference::Unreadable(e.to_string())),
+        };
+        let (src_type, dst_type) = (src_metadata.file_type(), dst_metadata.file_type());
+        if type_name(src_type) != type_name(dst_type) {
+            return self.differs(
+                relative,
+                Difference::Type {
+                    source: type_name(src_type),
+                    destination: type_name(dst_type),
+                },
+            );
+        }
+
+        if src_type.is_dir() {
+            let (src_names, dst_names) = match (list(&src_path), list(&dst_path)) {
+                (Ok(src_names), Ok(dst_names)) => (src_names, dst_names),
+                (Err(e), _) | (_, Err(e)) => {
+                    return self.differs(relative, Difference::Unreadable(e.to_string()))
+                }
+            };
+            for name in dst_names.difference(&src_names) {
+                self.differs(&relative.join(name), Difference::Extra);
+            }
+            for name in &src_names {
+                self.compare(&relative.join(name));
+            }
+        } else if src_type.is_symlink() {
+            match (std::fs::read_link(&src_path), std::fs::read_link(&dst_path)) {
+                (Ok(src_target), Ok(dst_target)) if src_target == dst_target => {}
+                (Ok(_), Ok(_)) => self.differs(relative, Difference::Target),
+                (Err(e), _) | (_, Err(e)) => {
+                    self.differs(relative, Difference::Unreadable(e.to_string()));
+                }
+            }
+        } else if src_type.is_file() {
+            let (size, dst_size) = (src_metadata.len(), dst_metadata.len());
+            if size != dst_size {
+                return self.differs(
+                    relative,
+                    Difference::Size {
+                        source: size,
+                        destination: dst_size,
+                    },
+                );
+            }
+            if size > 0 {
+                #[allow(clippy::cast_possible_truncation)] // Regions of a file fit in memory
+                let regions = size.div_ceil(self.region_size) as usize;
+                self.files.push(FileJob {
+                    relative: relative.to_path_buf(),
+                    size,
+                    regions: plan_regions(&[0..size], regions)
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    fn differs(&mut self, relative: &Path, difference: Difference) {
+        self.report.mismatches.push(Mismatch {
+            path: relative.to_path_buf(),
+            difference,
+        });
+    }
+}
+
+/// Name of an entry type, for the report
+fn type_name(file_type: std::fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_char_device() {
+        "character device"
+    } else {
+        "block device"
+    }
+}
+
+/// Names in the directory `path`
+fn list(path: &Path) -> std::io::Result<BTreeSet<OsString>> {
+    std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect()
+}
+
+/// One region of one file on one side, hashed by one pool thread
+struct Task {
+    /// Index of the file in the job list
+    file: usize,
+    /// Index of the region in the file
+    region: usize,
+    /// Whether this is the destination's copy
+    destination: bool,
+}
+
+/// Hash every region of `files` on both sides with `threads` threads and
+/// compare the files' hashes
+fn hash_and_compare(
+    source: &Path,
+    destination: &Path,
+    files: &[FileJob],
+    threads: usize,
+    progress: &ProgressBar,
+) -> Vec<Mismatch> {
+    // Both sides of a region are hashed one after the other, so the two
+    // trees are read at the same time
+    let tasks: Vec<Task> = files
+        .iter()
+        .enumerate()
+        .flat_map(|(file, job)| {
+            (0..job.regions.len()).flat_map(move |region| {
+                [false, true].map(|destination| Task {
+                    file,
+                    region,
+                    destination,
+                })
+            })
+        })
+        .collect();
+    let hashes: Vec<OnceLock<std::result::Result<blake3::Hash, String>>> =
+        tasks.iter().map(|_| OnceLock::new()).collect();
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(tasks.len()) {
+            scope.spawn(|| {
+                let mut buffer = vec![0; READ_SIZE];
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(task) = tasks.get(index) else {
+                        break;
+                    };
+                    let job = &files[task.file];
+                    let root = if task.destination {
+                        destination
+                    } else {
+                        source
+                    };
+                    let hash = hash_region(
+                        &root.join(&job.relative),
+                        job.regions[task.region].clone(),
+                        &mut buffer,
+                        progress,
+                    );
+                    let _ = hashes[index].set(hash);
+                }
+            });
+        }
+    });
+
+    // The tasks of a file are consecutive: each region, source then destination
+    let mut hashes = hashes.into_iter().map(|hash| {
+        hash.into_inner()
+            .unwrap_or_else(|| Err("not hashed".to_string()))
+    });
+    let mut mismatches = Vec::new();
+    for job in files {
+        let mut regions = Vec::with_capacity(job.regions.len());
+        let mut unreadable = None;
+        for _ in &job.regions {
+            match (hashes.next(), hashes.next()) {
+                (Some(Ok(src)), Some(Ok(dst))) => regions.push((src, dst)),
+                (Some(Err(e)), _) | (_, Some(Err(e))) => unreadable = Some(e),
+                _ => unreadable = Some("not hashed".to_string()),
+            }
+        }
+        let difference = if let Some(message) = unreadable {
+            Difference::Unreadable(message)
+        } else if merkle_root(regions.iter().map(|(src, _)| src))
+            == merkle_root(regions.iter().map(|(_, dst)| dst))
+        {
+            continue;
+        } else {
+            Difference::Contents(
+                job.regions
+                    .iter()
+                    .zip(&regions)
+                    .filter(|(_, (src, dst))| src != dst)
+                    .map(|(range, _)| range.clone())
+                    .collect(),
+            )
+        };
+        mismatches.push(Mismatch {
+            path: job.relative.clone(),
+            difference,
+        });
+    }
+    mismatches
+}
+
+/// BLAKE3 hash of `range` of the file at `path`
+fn hash_region(
+    path: &Path,
+    range: Range<u64>,
+    buffer: &mut [u8],
+    progress: &ProgressBar,
+) -> std::result::Result<blake3::Hash, String> {
+    let error = |e: std::io::Error| format!("{}: {e}", path.display());
+    let file = File::open(path).map_err(error)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut offset = range.start;
+    while offset < range.end {
+        #[allow(clippy::cast_possible_truncation)] // At most the buffer's length
+        let want = (range.end - offset).min(buffer.len() as u64) as usize;
+        let read = file.read_at(&mut buffer[..want], offset).map_err(error)?;
+        if read == 0 {
+            return Err(format!("{}: file shrank while verifying", path.display()));
+        }
+        hasher.update(&buffer[..read]);
+        offset += read as u64;
+        progress.inc(read as u64);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Hash of a file from its region hashes, in file order
+fn merkle_root<'a>(regions: impl Iterator<Item = &'a blake3::Hash>) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    for region in regions {
+        hasher.update(region.as_bytes());
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use std::fs;
+
+    const MB: u64 = 1 << 20;
+
+    fn options(threads: usize) -> VerifyOptions {
+        VerifyOptions {
+            threads,
+            region_size: MB,
+            progress: false,
+        }
+    }
+
+    /// A source tree with a file of several regions, and its exact copy
+    fn trees() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (src, dst) = (temp.path().join("src"), temp.path().join("dst"));
+        for root in [&src, &dst] {
+            fs::create_dir_all(root.join("sub")).unwrap();
+            #[allow(clippy::cast_possible_truncation)]
+            let big: Vec<u8> = (0..5 * MB + 123).map(|i| (i % 251) as u8).collect();
+            fs::write(root.join("big"), big).unwrap();
+            fs::write(root.join("sub/small"), "small").unwrap();
+            fs::write(root.join("empty"), "").unwrap();
+            std::os::unix::fs::symlink("sub/small", root.join("link")).unwrap();
+        }
+        (temp, src, dst)
+    }
+
+    #[test]
+    fn test_identical_trees_verify() {
+        // Requirement: A faithful copy has no differences, however many
+        // threads hash it
+        let (_temp, src, dst) = trees();
+        for threads in [1, 4] {
+            let report = verify(&src, &dst, options(threads)).unwrap();
+            assert_eq!(report.mismatches, []);
+            assert_eq!(report.files_hashed, 2);
+            assert_eq!(report.bytes_hashed, 5 * MB + 123 + 5);
+        }
+    }
+
+    #[test]
+    fn test_differences_are_reported() {
+        // Requirement: Changed regions of a large file are located; missing,
+        // extra, retyped and retargeted entries are reported by path
+        let (_temp, src, dst) = trees();
+        let big = fs::OpenOptions::new()
+            .write(true)
+            .open(dst.join("big"))
+            .unwrap();
+        big.write_at(b"corrupt", 3 * MB + 10).unwrap();
+        fs::remove_file(dst.join("sub/small")).unwrap();
+        fs::write(dst.join("stray"), "x").unwrap();
+        fs::remove_file(dst.join("link")).unwrap();
+        std::os::unix::fs::symlink("elsewhere", dst.join("link")).unwrap();
+        fs::remove_file(dst.join("empty")).unwrap();
+        fs::create_dir(dst.join("empty")).unwrap();
+
+        let report = verify(&src, &dst, options(3)).unwrap();
+        let differences: Vec<(&Path, &Difference)> = report
+            .mismatches
+            .iter()
+            .map(|mismatch| (mismatch.path.as_path(), &mismatch.difference))
+            .collect();
+        assert_eq!(differences.len(), 5, "{differences:?}");
+        assert!(matches!(
+            differences[0],
+            (path, Difference::Contents(regions))
+                if path == Path::new("big") && regions.len() == 1 && regions[0].contains(&(3 * MB + 10))
+        ));
+        assert_eq!(
+            differences[1..],
+            [
+                (
+                    Path::new("empty"),
+                    &Difference::Type {
+                        source: "file",
+                        destination: "directory"
+                    }
+                ),
+                (Path::new("link"), &Difference::Target),
+                (Path::new("stray"), &Difference::Extra),
+                (Path::new("sub/small"), &Difference::Missing),
+            ]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Deduplicated per-entry warnings
+//!
+//! A tree where thousands of entries fail the same way (every `chown` refused,
+//! every xattr rejected by the destination) would otherwise print thousands of
+//! identical lines and bury anything else. Per-entry warnings therefore go
+//! through the run's [`WarningLog`] (`RunState::warnings`), which groups them
+//! by (category, directory): the first [`SHOWN_PER_GROUP`] of each group are
+//! logged as usual, later ones are only counted, and [`WarningLog::report`]
+//! prints a table of every group at the end of the run when any warning was
+//! held back.
+//!
+//! The category of a failed entry is its error message with the entry's own
+//! path and name removed, so the same failure on sibling entries falls into
+//! one group while different failures stay apart.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tracing::warn;
+
+/// Warnings logged per (category, directory) before the rest are only counted
+pub const SHOWN_PER_GROUP: u64 = 3;
+
+/// Most groups listed in the end-of-run table
+const REPORT_ROWS: usize = 20;
+
+/// Per-entry warnings grouped by (category, directory)
+pub struct WarningLog {
+    /// Warnings seen per group
+    groups: Mutex<BTreeMap<(String, PathBuf), u64>>,
+}
+
+/// One group of identical warnings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarningGroup {
+    /// What went wrong
+    pub category: String,
+    /// Directory of the entries
+    pub directory: PathBuf,
+    /// Warnings in the group, logged or not
+    pub count: u64,
+}
+
+impl WarningLog {
+    /// Empty log
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            groups: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Warn `message` about the entry at `path`, unless its group is full
+    ///
+    /// Returns whether the warning was logged.
+    pub fn warn(&self, category: &str, path: &Path, message: impl fmt::Display) -> bool {
+        let directory = path.parent().unwrap_or(path).to_path_buf();
+        let count = {
+            let mut groups = self.groups.lock().unwrap_or_else(PoisonError::into_inner);
+            let count = groups.entry((category.to_string(), directory)).or_insert(0);
+            *count += 1;
+            *count
+        };
+        match count {
+            n if n < SHOWN_PER_GROUP => warn!("{message}"),
+            SHOWN_PER_GROUP => warn!(
+                "{message} (further \"{category}\" warnings in {} are counted and summarized at the end)",
+                path.parent().unwrap_or(path).display()
+            ),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Warn that copying the entry at `path` failed with `error`
+    pub fn entry_failed(&self, path: &Path, error: &dyn fmt::Display) -> bool {
+        let message = error.to_string();
+        let category = failure_category(path, &message);
+        self.warn(
+            &category,
+            path,
+            format_args!("Failed to copy {}: {message}", path.display()),
+        )
+    }
+
+    /// Every group, most frequent first
+    #[must_use]
+    pub fn groups(&self) -> Vec<WarningGroup> {
+        let mut groups: Vec<WarningGroup> = self
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|((category, directory), &count)| WarningGroup {
+                category: category.clone(),
+                directory: directory.clone(),
+                count,
+            })
+            .collect();
+        groups.sort_by(|a, b| b.count.cmp(&a.count));
+        groups
+    }
+
+    /// Warnings counted but not logged
+    #[must_use]
+    pub fn suppressed(&self) -> u64 {
+        self.groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|count| count.saturating_sub(SHOWN_PER_GROUP))
+            .sum()
+    }
+
+    /// Log a table of the warning groups, if any warning was held back
+    pub fn report(&self) {
+        let suppressed = self.suppressed();
+        if suppressed == 0 {
+            return;
+        }
+        let groups = self.groups();
+        let total: u64 = groups.iter().map(|group| group.count).sum();
+        warn!("{total} warnings ({suppressed} not shown above), by category and directory:");
+        warn!("{:>8}  {:<40}  DIRECTORY", "COUNT", "CATEGORY");
+        for group in groups.iter().take(REPORT_ROWS) {
+            warn!(
+                "{:>8}  {:<40}  {}",
+                group.count,
+                group.category,
+                group.directory.display()
+            );
+        }
+        if groups.len() > REPORT_ROWS {
+            warn!("... and {} more groups", groups.len() - REPORT_ROWS);
+        }
+    }
+}
+
+impl Default for WarningLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Category of a failure: its message without the entry's path and name
+fn failure_category(path: &Path, message: &str) -> String {
+    let mut category = message.replace(&*path.to_string_lossy(), "…");
+    if let Some(name) = path.file_name() {
+        let name = name.to_string_lossy();
+        // Very short names would also match inside unrelated words
+        if name.len() > 2 {
+            category = category.replace(&*name, "…");
+        }
+    }
+    category
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_failures_are_grouped_per_directory() {
+        // Requirement: Thousands of identical failures log a few lines, and
+        // different failures or directories stay separate
+        let log = WarningLog::new();
+        let error = |path: &str| format!("open {path}: Operation not permitted (os error 1)");
+        let logged = (0..1000)
+            .filter(|i| {
+                let path = PathBuf::from(format!("/dst/a/file{i}"));
+                log.entry_failed(&path, &error(&path.display().to_string()))
+            })
+            .count();
+        assert_eq!(logged as u64, SHOWN_PER_GROUP);
+        assert!(log.entry_failed(Path::new("/dst/b/file1"), &error("/dst/b/file1")));
+        assert!(log.entry_failed(Path::new("/dst/a/other"), &"No space left on device"));
+
+        let groups = log.groups();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].count, 1000);
+        assert_eq!(groups[0].directory, Path::new("/dst/a"));
+        assert_eq!(
+            groups[0].category,
+            "open …: Operation not permitted (os error 1)"
+        );
+        assert_eq!(log.suppressed(), 1000 - SHOWN_PER_GROUP);
+    }
+}
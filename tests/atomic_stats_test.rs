@@ -3,7 +3,7 @@
 //! This module tests that the atomic statistics counters work correctly
 //! under concurrent access without requiring locks.
 
-use arsync::directory::DirectoryStats;
+use arsync::directory::{DirectoryStats, SpecialFileCounts, SpecialKind};
 use arsync::stats::SharedStats;
 use std::sync::Arc;
 
@@ -167,3 +167,26 @@ async fn test_atomic_stats_infallible_operations() {
     assert_eq!(final_stats.symlinks_processed, 1);
     assert_eq!(final_stats.errors, 1);
 }
+
+/// Test per-type special file counters
+#[compio::test]
+async fn test_special_file_counters() {
+    let stats = SharedStats::new(&DirectoryStats::default());
+    stats.increment_special_skipped(SpecialKind::Fifo);
+    stats.increment_special_skipped(SpecialKind::Fifo);
+    stats.increment_special_skipped(SpecialKind::Socket);
+    stats.increment_special_placeholder(SpecialKind::BlockDevice);
+
+    let final_stats = stats.into_inner();
+    assert_eq!(
+        final_stats.specials_skipped,
+        SpecialFileCounts {
+            fifos: 2,
+            sockets: 1,
+            char_devices: 0,
+            block_devices: 0,
+        }
+    );
+    assert_eq!(final_stats.specials_placeholders.block_devices, 1);
+    assert_eq!(final_stats.specials_placeholders.total(), 1);
+}
@@ -0,0 +1,53 @@
+#![cfg(unix)]
+//! Tests for the checksum list of copied files (`--emit-checksums`)
+
+mod common;
+
+use arsync::checksum_list::ChecksumAlgorithm;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Requirement: Every copied file, hard links included, is listed once in
+/// `sha256sum` format, sorted, relative to the destination; a run that copies
+/// nothing, or links everything from `--link-dest`, still lists every file
+#[compio::test]
+async fn test_emit_checksums_lists_copied_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    fs::create_dir_all(src_dir.join("sub")).unwrap();
+    fs::write(src_dir.join("abc.txt"), "abc").unwrap();
+    fs::write(src_dir.join("sub/empty"), "").unwrap();
+    fs::hard_link(src_dir.join("abc.txt"), src_dir.join("sub/link")).unwrap();
+    let list = temp_dir.path().join("SHA256SUMS");
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir;
+    args.paths.destination = dst_dir;
+    args.metadata.hard_links = true;
+    args.output.emit_checksums = Some(list.clone());
+    args.output.checksum_algorithm = ChecksumAlgorithm::Sha256;
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let expected = format!("{abc}  abc.txt\n{empty}  sub/empty\n{abc}  sub/link\n");
+    assert_eq!(fs::read_to_string(&list).unwrap(), expected);
+
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes_copied, 0, "the second run copies nothing");
+    assert_eq!(fs::read_to_string(&list).unwrap(), expected);
+
+    args.paths.destination = temp_dir.path().join("snapshot");
+    args.traversal.link_dest = vec![PathBuf::from("../dst")];
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes_copied, 0, "every file is linked");
+    assert_eq!(fs::read_to_string(&list).unwrap(), expected);
+}
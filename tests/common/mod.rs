@@ -5,6 +5,7 @@ use std::time::Duration;
 
 pub mod container_helpers;
 pub mod copy_helpers;
+pub mod tar_helpers;
 pub mod test_args;
 
 /// Helper to create a disabled parallel copy config for tests
@@ -14,6 +15,7 @@ pub fn disabled_parallel_config() -> ParallelCopyConfig {
         max_depth: 0, // 0 = disabled
         min_file_size_mb: 128,
         chunk_size_mb: 2,
+        adaptive: false,
     }
 }
 
@@ -0,0 +1,26 @@
+//! Fixtures shared by the `--to-tar` and `--from-tar` tests
+
+#![allow(clippy::unwrap_used)]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Whether the system tar is installed
+#[allow(dead_code)] // Not all test files use this
+pub fn tar_available() -> bool {
+    Command::new("tar").arg("--version").output().is_ok()
+}
+
+/// A small tree with a nested directory, a long name, a symlink and a hard
+/// link; returns the long name
+#[allow(dead_code)] // Not all test files use this
+pub fn make_source(source: &Path) -> String {
+    let long_name = "n".repeat(120);
+    fs::create_dir_all(source.join("sub/deeper")).unwrap();
+    fs::write(source.join("file1.txt"), b"Hello, World!").unwrap();
+    fs::write(source.join("sub/deeper").join(&long_name), b"long name").unwrap();
+    fs::hard_link(source.join("file1.txt"), source.join("sub/hard.txt")).unwrap();
+    std::os::unix::fs::symlink("file1.txt", source.join("link")).unwrap();
+    long_name
+}
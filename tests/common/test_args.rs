@@ -2,7 +2,9 @@
 
 use arsync::cli::{
     Args, ConcurrencyConfig, CopyMethod, IoConfig, MetadataConfig, OutputConfig, PathConfig,
+    RemoteConfig, TraversalConfig,
 };
+use arsync::metadata::{SpecialFilePolicy, UnprivilegedOwnership};
 use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
@@ -12,17 +14,25 @@ pub fn create_minimal_test_args() -> Args {
         paths: PathConfig {
             source: PathBuf::from("/test/source"),
             destination: PathBuf::from("/test/dest"),
+            extra_destinations: Vec::new(),
         },
         io: IoConfig {
             queue_depth: 4096,
             buffer_size_kb: NonZeroUsize::new(64),
             copy_method: CopyMethod::Auto,
+            tune: None,
+            show_tuning: false,
             cpu_count: 1,
             parallel: super::disabled_parallel_config(),
         },
         concurrency: ConcurrencyConfig {
             max_files_in_flight: 1024,
             no_adaptive_concurrency: false,
+            large_file_threshold_mb: 64,
+            large_files_in_flight: 8,
+            hardlink_spill_dir: None,
+            hardlink_memory_entries: 4_000_000,
+            no_lock: false,
         },
         metadata: MetadataConfig {
             archive: false,
@@ -32,22 +42,53 @@ pub fn create_minimal_test_args() -> Args {
             times: false,
             group: false,
             owner: false,
+            no_owner: false,
+            no_group: false,
             devices: false,
+            specials: false,
+            devices_and_specials: false,
             xattrs: false,
             acls: false,
             fsync: false,
+            syncfs: false,
+            verify_direct: None,
+            atomic_create: None,
+            partial: false,
+            delay_updates: false,
+            special_files: SpecialFilePolicy::Skip,
+            unprivileged_ownership: UnprivilegedOwnership::Permitted,
             hard_links: false,
             atimes: false,
             crtimes: false,
             preserve_xattr: false,
             preserve_acl: false,
+            omit_dir_times: false,
+            omit_link_times: false,
+            symlink_rewrite: Vec::new(),
+            sparse: false,
+            preserve_flags: false,
+            update_immutable: false,
+            overlay_layer: false,
+            run: Default::default(),
         },
+        traversal: TraversalConfig::default(),
+        remote: RemoteConfig::default(),
         output: OutputConfig {
             dry_run: false,
             progress: false,
+            interactive: false,
             verbose: 0,
             quiet: false,
+            json: false,
+            shadow_rsync: false,
+            to_tar: false,
+            from_tar: false,
             pirate: false,
+            emit_checksums: None,
+            checksum_algorithm: arsync::checksum_list::ChecksumAlgorithm::Blake3,
+            metrics_listen: None,
+            metrics_textfile: None,
+            debug_fd_audit: false,
         },
     }
 }
@@ -124,6 +165,11 @@ impl ArgsBuilder {
         self
     }
 
+    pub fn syncfs(mut self, enabled: bool) -> Self {
+        self.args.metadata.syncfs = enabled;
+        self
+    }
+
     pub fn verbose(mut self, level: u8) -> Self {
         self.args.output.verbose = level;
         self
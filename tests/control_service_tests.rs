@@ -0,0 +1,117 @@
+//! End-to-end tests of `arsync serve` over its control socket
+
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// A running `arsync serve`, killed on drop
+struct Service(Child);
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn start(socket: &Path) -> (Service, UnixStream) {
+    let child = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg("serve")
+        .arg("--control-socket")
+        .arg(socket)
+        .spawn()
+        .expect("Failed to start arsync serve");
+    let service = Service(child);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Ok(stream) = UnixStream::connect(socket) {
+            return (service, stream);
+        }
+        assert!(Instant::now() < deadline, "arsync serve did not listen");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Send one request and read its answer
+fn call(stream: &mut UnixStream, request: &str) -> String {
+    writeln!(stream, "{request}").unwrap();
+    let mut line = String::new();
+    BufReader::new(stream.try_clone().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    line
+}
+
+#[test]
+fn test_submit_and_poll_job() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("src");
+    std::fs::create_dir_all(source.join("sub")).unwrap();
+    std::fs::write(source.join("a.txt"), "alpha").unwrap();
+    std::fs::write(source.join("sub/b.txt"), "beta").unwrap();
+    let dest = temp.path().join("dst");
+    let socket = temp.path().join("control.sock");
+    let (_service, mut stream) = start(&socket);
+
+    let response = call(
+        &mut stream,
+        &format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"submit","params":{{"args":["-a","{}","{}"]}}}}"#,
+            source.display(),
+            dest.display()
+        ),
+    );
+    assert!(response.contains(r#""result":{"job":1}"#), "{response}");
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        let status = call(
+            &mut stream,
+            r#"{"jsonrpc":"2.0","id":2,"method":"status","params":{"job":1}}"#,
+        );
+        if !status.contains(r#""state":"running""#) && !status.contains(r#""state":"queued""#) {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "job did not finish: {status}");
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert!(status.contains(r#""state":"completed""#), "{status}");
+    assert_eq!(
+        std::fs::read_to_string(dest.join("a.txt")).unwrap(),
+        "alpha"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dest.join("sub/b.txt")).unwrap(),
+        "beta"
+    );
+
+    let list = call(&mut stream, r#"{"jsonrpc":"2.0","id":3,"method":"list"}"#);
+    assert!(list.contains(r#""job":1"#), "{list}");
+    let cancel = call(
+        &mut stream,
+        r#"{"jsonrpc":"2.0","id":4,"method":"cancel","params":{"job":1}}"#,
+    );
+    assert!(cancel.contains(r#""cancelled":false"#), "{cancel}");
+}
+
+#[test]
+fn test_second_service_refuses_the_socket() {
+    let temp = TempDir::new().unwrap();
+    let socket = temp.path().join("control.sock");
+    let (_service, _stream) = start(&socket);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg("serve")
+        .arg("--control-socket")
+        .arg(&socket)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Another arsync is serving"));
+}
@@ -356,3 +356,152 @@ async fn test_directory_metadata_umask_interaction() {
         "Directory permissions should be preserved regardless of umask"
     );
 }
+
+/// Encode a minimal POSIX ACL (owner, group, other) in the kernel xattr format
+fn minimal_acl_xattr(owner: u16, group: u16, other: u16) -> Vec<u8> {
+    const ACL_UNDEFINED_ID: u32 = u32::MAX;
+    let mut value = 2u32.to_le_bytes().to_vec(); // POSIX_ACL_XATTR_VERSION
+    for (tag, perm) in [(0x01u16, owner), (0x04, group), (0x20, other)] {
+        value.extend_from_slice(&tag.to_le_bytes());
+        value.extend_from_slice(&perm.to_le_bytes());
+        value.extend_from_slice(&ACL_UNDEFINED_ID.to_le_bytes());
+    }
+    value
+}
+
+fn set_xattr(path: &std::path::Path, name: &std::ffi::CStr, value: &[u8]) -> std::io::Result<()> {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    let ret = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+fn get_xattr(path: &std::path::Path, name: &std::ffi::CStr) -> Option<Vec<u8>> {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    let mut buf = vec![0u8; 4096];
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    usize::try_from(len).ok().map(|len| buf[..len].to_vec())
+}
+
+/// Test directory default ACLs are recreated (and inherited ones removed) with --acls
+#[compio::test]
+async fn test_directory_default_acl_preservation() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src_dir");
+    let dst_dir = temp_dir.path().join("dst_dir");
+    fs::create_dir(&src_dir).unwrap();
+    fs::create_dir(&dst_dir).unwrap();
+
+    let default_acl = minimal_acl_xattr(7, 5, 0);
+    if let Err(e) = set_xattr(&src_dir, c"system.posix_acl_default", &default_acl) {
+        eprintln!("Skipping: filesystem does not support POSIX ACLs ({e})");
+        return;
+    }
+
+    let mut args = common::test_args::create_minimal_test_args();
+    args.metadata.acls = true;
+    let extended_metadata = metadata_from_path(&src_dir).await.unwrap();
+    preserve_directory_metadata(&src_dir, &dst_dir, &extended_metadata, &args.metadata)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_xattr(&dst_dir, c"system.posix_acl_default"),
+        Some(default_acl),
+        "Default ACL should be recreated on the destination directory"
+    );
+
+    // Children created afterwards inherit it: "other" gets no access
+    let child = dst_dir.join("child");
+    fs::create_dir(&child).unwrap();
+    assert_eq!(
+        fs::metadata(&child).unwrap().permissions().mode() & 0o007,
+        0
+    );
+
+    // A source without a default ACL removes the one the child inherited
+    let plain_src = temp_dir.path().join("plain_src");
+    fs::create_dir(&plain_src).unwrap();
+    let plain_metadata = metadata_from_path(&plain_src).await.unwrap();
+    preserve_directory_metadata(&plain_src, &child, &plain_metadata, &args.metadata)
+        .await
+        .unwrap();
+    assert_eq!(get_xattr(&child, c"system.posix_acl_default"), None);
+}
+
+/// Test file access ACLs survive --acls together with --perms
+#[compio::test]
+async fn test_file_access_acl_preservation() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_path = temp_dir.path().join("src.txt");
+    let dst_path = temp_dir.path().join("dst.txt");
+    fs::write(&src_path, "acl").unwrap();
+    fs::write(&dst_path, "acl").unwrap();
+
+    // owner rw, user 4242 r, group r, mask r, other none
+    const ACL_UNDEFINED_ID: u32 = u32::MAX;
+    let mut access_acl = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in [
+        (0x01u16, 6u16, ACL_UNDEFINED_ID),
+        (0x02, 4, 4242),
+        (0x04, 4, ACL_UNDEFINED_ID),
+        (0x10, 4, ACL_UNDEFINED_ID),
+        (0x20, 0, ACL_UNDEFINED_ID),
+    ] {
+        access_acl.extend_from_slice(&tag.to_le_bytes());
+        access_acl.extend_from_slice(&perm.to_le_bytes());
+        access_acl.extend_from_slice(&id.to_le_bytes());
+    }
+    if let Err(e) = set_xattr(&src_path, c"system.posix_acl_access", &access_acl) {
+        eprintln!("Skipping: filesystem does not support POSIX ACLs ({e})");
+        return;
+    }
+
+    let mut args = common::test_args::create_minimal_test_args();
+    args.metadata.acls = true;
+    let src_file = compio::fs::File::open(&src_path).await.unwrap();
+    let dst_file = compio::fs::OpenOptions::new()
+        .write(true)
+        .open(&dst_path)
+        .await
+        .unwrap();
+    let now = SystemTime::now();
+    arsync::metadata::preserve_file_metadata(
+        &src_file,
+        &dst_file,
+        &dst_path,
+        now,
+        now,
+        &args.metadata,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_xattr(&dst_path, c"system.posix_acl_access"),
+        Some(access_acl),
+        "Access ACL (with its mask) should be copied after the permissions"
+    );
+    assert_eq!(
+        fs::metadata(&dst_path).unwrap().permissions().mode() & 0o777,
+        fs::metadata(&src_path).unwrap().permissions().mode() & 0o777
+    );
+}
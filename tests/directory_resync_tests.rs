@@ -39,7 +39,10 @@ async fn test_resync_updates_directory_metadata() {
     args.paths.source = src_dir.clone();
     args.paths.destination = dst_dir.clone();
 
-    arsync::sync::sync_files(&args).await.map(|_| ()).unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .map(|_| ())
+        .unwrap();
 
     // Verify metadata was UPDATED to match source
     let dst_after = fs::metadata(&dst_dir).unwrap().permissions().mode() & 0o777;
@@ -80,7 +83,9 @@ async fn test_type_conflict_file_to_directory_fails() {
     args.paths.source = src_dir.clone();
     args.paths.destination = dst_base.clone();
 
-    let result = arsync::sync::sync_files(&args).await.map(|_| ());
+    let result = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .map(|_| ());
 
     assert!(
         result.is_err(),
@@ -120,7 +125,10 @@ async fn test_file_into_existing_directory_creates_nested() {
     args.paths.destination = dst_dir.clone();
 
     // This should succeed - file goes inside directory
-    arsync::sync::sync_files(&args).await.map(|_| ()).unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .map(|_| ())
+        .unwrap();
 
     // Verify file was created inside directory
     let nested_file = dst_dir.join("file.txt");
@@ -169,7 +177,10 @@ async fn test_resync_preserves_directory_timestamps() {
     args.paths.source = src_dir.clone();
     args.paths.destination = dst_dir.clone();
 
-    arsync::sync::sync_files(&args).await.map(|_| ()).unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .map(|_| ())
+        .unwrap();
 
     // Verify destination timestamps match source
     use std::os::unix::fs::MetadataExt;
@@ -1,6 +1,7 @@
 //! Tests for directory extended attributes (xattr) preservation
 
 use arsync::directory::preserve_directory_xattr;
+use arsync::warnings::WarningLog;
 use compio::fs;
 use compio_fs_extended::{ExtendedFile, XattrOps};
 use tempfile::TempDir;
@@ -33,7 +34,7 @@ async fn test_directory_xattr_preservation() {
     fs::create_dir(&dst_path).await.unwrap();
 
     // Test xattr preservation
-    preserve_directory_xattr(&src_path, &dst_path)
+    preserve_directory_xattr(&src_path, &dst_path, &WarningLog::default())
         .await
         .unwrap();
 
@@ -61,7 +62,7 @@ async fn test_directory_xattr_preservation_no_xattrs() {
     fs::create_dir(&dst_path).await.unwrap();
 
     // Test xattr preservation (should not fail)
-    preserve_directory_xattr(&src_path, &dst_path)
+    preserve_directory_xattr(&src_path, &dst_path, &WarningLog::default())
         .await
         .unwrap();
 
@@ -101,7 +102,7 @@ async fn test_directory_xattr_preservation_multiple() {
     fs::create_dir(&dst_path).await.unwrap();
 
     // Test xattr preservation
-    preserve_directory_xattr(&src_path, &dst_path)
+    preserve_directory_xattr(&src_path, &dst_path, &WarningLog::default())
         .await
         .unwrap();
 
@@ -138,7 +139,7 @@ async fn test_directory_xattr_preservation_binary_data() {
     fs::create_dir(&dst_path).await.unwrap();
 
     // Test xattr preservation
-    preserve_directory_xattr(&src_path, &dst_path)
+    preserve_directory_xattr(&src_path, &dst_path, &WarningLog::default())
         .await
         .unwrap();
 
@@ -171,7 +172,7 @@ async fn test_directory_xattr_preservation_error_handling() {
     fs::create_dir(&dst_path).await.unwrap();
 
     // Test xattr preservation (should not fail even if some xattrs can't be set)
-    let result = preserve_directory_xattr(&src_path, &dst_path).await;
+    let result = preserve_directory_xattr(&src_path, &dst_path, &WarningLog::default()).await;
 
     // Should succeed (warnings are logged but don't fail the operation)
     assert!(result.is_ok());
@@ -540,3 +540,39 @@ async fn test_metadata_preservation_unicode_filenames() {
         );
     }
 }
+
+/// Requirement: A zero-byte file replaces an existing destination and keeps
+/// its permissions and nanosecond modification time
+#[compio::test]
+async fn test_metadata_preservation_zero_byte_file() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let src_path = temp_dir.path().join("app.lock");
+    let dst_path = temp_dir.path().join("app_copy.lock");
+    fs::write(&src_path, "").unwrap();
+    fs::write(&dst_path, "stale destination contents").unwrap();
+    fs::set_permissions(&src_path, fs::Permissions::from_mode(0o640)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::new(1_609_459_200, 123_456_789);
+    fs::File::options()
+        .write(true)
+        .open(&src_path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    let args = create_test_args_with_archive();
+    copy_file_test(
+        &src_path,
+        &dst_path,
+        &args.metadata,
+        &common::disabled_parallel_config(),
+    )
+    .await
+    .unwrap();
+
+    let dst_metadata = fs::metadata(&dst_path).unwrap();
+    assert_eq!(dst_metadata.len(), 0);
+    assert_eq!(dst_metadata.mode() & 0o7777, 0o640);
+    assert_eq!(dst_metadata.modified().unwrap(), modified);
+}
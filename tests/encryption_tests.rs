@@ -0,0 +1,92 @@
+#![cfg(unix)]
+//! Tests for encrypted destinations (`--encrypt-to`, `arsync restore`)
+
+mod common;
+
+use age::secrecy::ExposeSecret;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Every path under `dir`, recursively
+fn paths_under(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            paths.extend(paths_under(&entry.path()));
+        }
+        paths.push(entry.path());
+    }
+    paths
+}
+
+/// Requirement: With --encrypt-names the destination holds neither the
+/// contents nor the names of the source, a second run copies nothing, and
+/// `arsync restore` gives the tree back
+#[compio::test]
+async fn test_encrypted_backup_restores() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    fs::create_dir_all(src_dir.join("payroll")).unwrap();
+    fs::write(src_dir.join("payroll/salaries.csv"), "alice,100\n").unwrap();
+    fs::write(src_dir.join("readme.txt"), "public-ish\n").unwrap();
+
+    let identity = age::x25519::Identity::generate();
+    let key_file = temp_dir.path().join("key.txt");
+    fs::write(
+        &key_file,
+        format!("{}\n", identity.to_string().expose_secret()),
+    )
+    .unwrap();
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    args.traversal.encrypt_identity = Some(key_file.clone());
+    args.traversal.encrypt_names = true;
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.files_copied, 2);
+
+    assert!(dst_dir.join(".arsync-encryption").is_file());
+    for path in paths_under(&dst_dir) {
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(
+            !["payroll", "salaries.csv", "readme.txt"].contains(&name.as_ref()),
+            "{name} leaked"
+        );
+        if path.is_file() {
+            assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("alice"));
+        }
+    }
+
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.files_copied, 0, "unchanged files keep their names");
+
+    let restored = temp_dir.path().join("restored");
+    let report = arsync::encryption::restore(&dst_dir, &restored, &key_file).unwrap();
+    assert_eq!(report.files, 2);
+    assert_eq!(
+        fs::read_to_string(restored.join("payroll/salaries.csv")).unwrap(),
+        "alice,100\n"
+    );
+    assert_eq!(
+        fs::read_to_string(restored.join("readme.txt")).unwrap(),
+        "public-ish\n"
+    );
+    assert_eq!(
+        fs::metadata(restored.join("readme.txt"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        fs::metadata(src_dir.join("readme.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+}
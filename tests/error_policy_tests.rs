@@ -0,0 +1,83 @@
+#![cfg(unix)]
+//! Tests for failed entries (`--ignore-errors`, `--max-errors`)
+//!
+//! A `--transform` command that exits non-zero fails its file the same way
+//! on every machine, whoever runs the tests.
+
+mod common;
+
+use arsync::transform::TransformRule;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Source tree with three files whose copy fails and one that copies
+fn create_source(src_dir: &Path) {
+    fs::create_dir_all(src_dir.join("logs")).unwrap();
+    for name in ["a.bad", "b.bad", "logs/c.bad"] {
+        fs::write(src_dir.join(name), "unused").unwrap();
+    }
+    fs::write(src_dir.join("good.txt"), "copied").unwrap();
+}
+
+/// Arguments copying `src_dir` to `dst_dir`, failing every `*.bad` file
+fn failing_args(src_dir: &Path, dst_dir: &Path) -> arsync::cli::Args {
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.to_path_buf();
+    args.paths.destination = dst_dir.to_path_buf();
+    args.traversal.transform = vec![TransformRule::parse("*.bad:exit 5").unwrap()];
+    args
+}
+
+/// Requirement: Failed entries are skipped and counted, the rest is copied,
+/// and --delete-after deletes nothing unless --ignore-errors
+#[compio::test]
+async fn test_failed_entries_are_counted() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_source(&src_dir);
+    fs::create_dir_all(&dst_dir).unwrap();
+    fs::write(dst_dir.join("extraneous"), "old").unwrap();
+
+    let mut args = failing_args(&src_dir, &dst_dir);
+    args.traversal.delete_after = true;
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.errors, 3);
+    assert_eq!(
+        fs::read_to_string(dst_dir.join("good.txt")).unwrap(),
+        "copied"
+    );
+    assert!(dst_dir.join("extraneous").exists());
+
+    args.traversal.ignore_errors = true;
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.errors, 3);
+    assert!(!dst_dir.join("extraneous").exists());
+}
+
+/// Requirement: --max-errors N fails the run once N entries have failed
+#[compio::test]
+async fn test_max_errors_fails_the_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_source(&src_dir);
+
+    let mut args = failing_args(&src_dir, &dst_dir);
+    args.traversal.max_errors = std::num::NonZeroU64::new(2);
+    let error = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("--max-errors"), "{error}");
+
+    args.traversal.max_errors = std::num::NonZeroU64::new(4);
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.errors, 3);
+}
@@ -0,0 +1,128 @@
+#![cfg(unix)]
+//! Tests for hardlink farms (rsnapshot-style snapshot trees)
+//!
+//! Every snapshot directory holds hardlinks to the same inodes, so once the
+//! first snapshot is copied the others are resolved from the hardlink
+//! tracker and only linked.
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+const SNAPSHOTS: usize = 4;
+const FILES: [(&str, &str); 3] = [
+    ("a.txt", "alpha"),
+    ("b.txt", "beta"),
+    ("sub/c.txt", "gamma"),
+];
+
+/// Create `daily.0` .. `daily.N` under `root`, all linking the same files
+fn create_snapshots(root: &Path) {
+    for (name, content) in FILES {
+        let path = root.join("daily.0").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+    }
+    for snapshot in 1..SNAPSHOTS {
+        for (name, _) in FILES {
+            let link = root.join(format!("daily.{snapshot}")).join(name);
+            fs::create_dir_all(link.parent().unwrap()).unwrap();
+            fs::hard_link(root.join("daily.0").join(name), link).unwrap();
+        }
+    }
+}
+
+/// Requirement: Every snapshot of a hardlink farm is linked to one copy of
+/// each inode, and the content is copied once
+#[compio::test]
+async fn test_snapshots_link_to_one_copy() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_snapshots(&src_dir);
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    let content_bytes: usize = FILES.iter().map(|(_, content)| content.len()).sum();
+    assert_eq!(stats.bytes_copied, content_bytes as u64);
+    for (name, content) in FILES {
+        let first = fs::metadata(dst_dir.join("daily.0").join(name)).unwrap();
+        assert_eq!(first.nlink(), SNAPSHOTS as u64, "{name}");
+        for snapshot in 1..SNAPSHOTS {
+            let link = dst_dir.join(format!("daily.{snapshot}")).join(name);
+            assert_eq!(fs::metadata(&link).unwrap().ino(), first.ino(), "{name}");
+            assert_eq!(fs::read_to_string(&link).unwrap(), content);
+        }
+    }
+}
+
+/// Requirement: Syncing a hardlink farm again copies nothing and keeps the
+/// links in place
+#[compio::test]
+async fn test_resync_keeps_snapshot_links() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_snapshots(&src_dir);
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    let before = fs::metadata(dst_dir.join("daily.0/a.txt")).unwrap().ino();
+
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes_copied, 0);
+    for snapshot in 0..SNAPSHOTS {
+        let link = dst_dir.join(format!("daily.{snapshot}/a.txt"));
+        assert_eq!(fs::metadata(link).unwrap().ino(), before);
+    }
+}
+
+/// Requirement: With --delay-updates the links are made to the staged copy
+/// and everything is in place, with no staged files left, once the run ends
+#[compio::test]
+async fn test_delayed_snapshots_link_to_one_copy() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_snapshots(&src_dir);
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    args.metadata.delay_updates = true;
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    for (name, content) in FILES {
+        let first = fs::metadata(dst_dir.join("daily.0").join(name)).unwrap();
+        assert_eq!(first.nlink(), SNAPSHOTS as u64, "{name}");
+        for snapshot in 1..SNAPSHOTS {
+            let link = dst_dir.join(format!("daily.{snapshot}")).join(name);
+            assert_eq!(fs::metadata(&link).unwrap().ino(), first.ino(), "{name}");
+            assert_eq!(fs::read_to_string(&link).unwrap(), content);
+        }
+    }
+    let staged = fs::read_dir(dst_dir.join("daily.0"))
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            arsync::temp_files::parse_temp_name(&name).is_some()
+        })
+        .count();
+    assert_eq!(staged, 0);
+}
@@ -0,0 +1,102 @@
+//! Benchmarks for the compact hardlink inode store on synthetic trees
+//!
+//! These are ignored by default because the full-size runs take minutes and
+//! several GB of memory/disk. Run them explicitly in release mode:
+//!
+//! ```bash
+//! cargo test --release --test hardlink_store_bench -- --ignored --nocapture
+//! ```
+//!
+//! The number of synthetic inodes defaults to 100M and can be changed with
+//! `ARSYNC_BENCH_ENTRIES` (e.g. `ARSYNC_BENCH_ENTRIES=1000000` for a quick run).
+//! Spill files go to `ARSYNC_BENCH_SPILL_DIR` (default: a temp directory).
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use arsync::hardlink_store::{InodePathStore, InodeStoreConfig};
+use arsync::hardlink_tracker::InodeInfo;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tempfile::TempDir;
+
+/// Number of synthetic inodes to insert
+fn bench_entries() -> u64 {
+    std::env::var("ARSYNC_BENCH_ENTRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100_000_000)
+}
+
+/// Synthetic destination path, shaped like a deep tree (dir fan-out of 1000)
+fn synthetic_path(ino: u64) -> PathBuf {
+    PathBuf::from(format!(
+        "/dst/d{:03}/d{:03}/file{ino}",
+        (ino / 1_000_000) % 1000,
+        (ino / 1000) % 1000
+    ))
+}
+
+/// Insert `entries` inodes, then look up a sample and report timings
+fn run_bench(name: &str, mut store: InodePathStore, entries: u64) {
+    let start = Instant::now();
+    for ino in 0..entries {
+        let key = InodeInfo { dev: 1, ino };
+        store.insert(key, &synthetic_path(ino), 2).unwrap();
+    }
+    let insert_elapsed = start.elapsed();
+
+    let samples = entries.min(1_000_000);
+    let stride = (entries / samples).max(1);
+    let start = Instant::now();
+    for i in 0..samples {
+        let ino = i * stride;
+        let path = store.get_dst_path(InodeInfo { dev: 1, ino }).unwrap();
+        assert_eq!(path.as_deref(), Some(synthetic_path(ino).as_path()));
+    }
+    let lookup_elapsed = start.elapsed();
+
+    #[allow(clippy::cast_precision_loss)]
+    let per_entry = store.memory_bytes() as f64 / entries as f64;
+    println!(
+        "{name}: {entries} inserts in {insert_elapsed:?} ({:.0}/s), \
+         {samples} lookups in {lookup_elapsed:?}, \
+         {} in memory, {} spilled, {} MB RAM ({per_entry:.1} B/entry)",
+        entries as f64 / insert_elapsed.as_secs_f64(),
+        store.len() - store.spilled_len(),
+        store.spilled_len(),
+        store.memory_bytes() / (1024 * 1024),
+    );
+    assert_eq!(store.len() as u64, entries);
+}
+
+#[test]
+#[ignore] // Large benchmark: run with --ignored in release mode
+fn bench_compact_store_in_memory() {
+    let entries = bench_entries();
+    run_bench(
+        "in-memory",
+        InodePathStore::new(InodeStoreConfig::default()),
+        entries,
+    );
+}
+
+#[test]
+#[ignore] // Large benchmark: run with --ignored in release mode
+fn bench_compact_store_with_spill() {
+    let entries = bench_entries();
+    let temp_dir = TempDir::new().unwrap();
+    let spill_dir = std::env::var("ARSYNC_BENCH_SPILL_DIR")
+        .map_or_else(|_| temp_dir.path().to_path_buf(), PathBuf::from);
+
+    // Keep 10% in memory, spill the rest
+    #[allow(clippy::cast_possible_truncation)]
+    let memory_entries = (entries / 10) as usize;
+    run_bench(
+        "spill",
+        InodePathStore::new(InodeStoreConfig::with_spill(
+            memory_entries,
+            Path::new(&spill_dir),
+        )),
+        entries,
+    );
+}
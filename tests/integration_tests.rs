@@ -18,6 +18,17 @@ fn test_help_output() {
         ));
 }
 
+#[test]
+fn test_help_lists_subcommands() {
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Subcommands:"))
+        .stdout(predicate::str::contains("  verify SOURCE DESTINATION"))
+        .stdout(predicate::str::contains("  serve "));
+}
+
 #[test]
 fn test_version_output() {
     let mut cmd = Command::cargo_bin("arsync").unwrap();
@@ -69,3 +80,837 @@ fn test_dry_run() {
     .assert()
     .success();
 }
+
+#[test]
+fn test_dry_run_itemizes_without_changes() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let src = src_dir.path();
+    std::fs::create_dir(src.join("sub")).unwrap();
+    std::fs::write(src.join("a.txt"), "new contents").unwrap();
+    std::fs::write(src.join("sub/b.txt"), "b").unwrap();
+    std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+    let dst = dst_dir.path().join("existing");
+    std::fs::create_dir(&dst).unwrap();
+    std::fs::write(dst.join("a.txt"), "old").unwrap();
+    std::fs::write(dst.join("extra.txt"), "extra").unwrap();
+
+    let output = Command::cargo_bin("arsync")
+        .unwrap()
+        .args([
+            src.to_str().unwrap(),
+            dst.to_str().unwrap(),
+            "-a",
+            "--delete",
+            "-n",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(">f.s"), "{stdout}");
+    for line in [
+        "cd+++++++++ sub/",
+        ">f+++++++++ sub/b.txt",
+        "cL+++++++++ link -> a.txt",
+        "*deleting   extra.txt",
+    ] {
+        assert!(
+            stdout.lines().any(|l| l == line),
+            "missing {line:?} in {stdout}"
+        );
+    }
+    assert_eq!(std::fs::read_to_string(dst.join("a.txt")).unwrap(), "old");
+    assert!(dst.join("extra.txt").exists());
+    assert!(!dst.join("sub").exists());
+    assert!(!dst.join("link").is_symlink());
+
+    // A missing destination (and parent) is reported, not created
+    let missing = dst_dir.path().join("new/deeper");
+    let output = Command::cargo_bin("arsync")
+        .unwrap()
+        .args([src.to_str().unwrap(), missing.to_str().unwrap(), "-a", "-n"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|l| l == "cd+++++++++ ./"), "{stdout}");
+    assert!(
+        stdout.lines().any(|l| l == ">f+++++++++ sub/b.txt"),
+        "{stdout}"
+    );
+    assert!(!dst_dir.path().join("new").exists());
+}
+
+#[test]
+fn test_syncfs_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::write(src_dir.path().join("file.txt"), "durable").unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--syncfs",
+    ])
+    .assert()
+    .success();
+
+    assert_eq!(
+        std::fs::read_to_string(dst.join("file.txt")).unwrap(),
+        "durable"
+    );
+}
+
+#[test]
+fn test_verify_direct_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(src_dir.path().join("data.bin"), &data).unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--verify-direct",
+    ])
+    .assert()
+    .success();
+
+    assert_eq!(std::fs::read(dst.join("data.bin")).unwrap(), data);
+}
+
+#[test]
+fn test_progress_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), "a").unwrap();
+    std::fs::write(src_dir.path().join("sub/b.txt"), vec![1u8; 200_000]).unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--progress",
+    ])
+    .assert()
+    .success();
+
+    assert_eq!(std::fs::read(dst.join("sub/b.txt")).unwrap().len(), 200_000);
+}
+
+#[test]
+fn test_preread_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+    for i in 0..20u8 {
+        std::fs::write(src_dir.path().join(format!("sub/f{i}")), vec![i; 3000]).unwrap();
+    }
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--preread",
+        "4",
+        "--preread-budget-mb",
+        "1",
+    ])
+    .assert()
+    .success();
+
+    for i in 0..20u8 {
+        assert_eq!(
+            std::fs::read(dst.join(format!("sub/f{i}"))).unwrap(),
+            vec![i; 3000]
+        );
+    }
+}
+
+#[test]
+fn test_simulate_subcommand() {
+    let dir = TempDir::new().unwrap();
+    let manifest = dir.path().join("tree.manifest");
+    std::fs::write(&manifest, "4096\ta.txt\n1048576\tsub/b.bin\n0\tempty/\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        "simulate",
+        manifest.to_str().unwrap(),
+        "--throughput-mb",
+        "1000",
+        "--max-files-in-flight",
+        "4",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(
+        "Simulated 2 files (1052672 bytes) in 3 directories",
+    ));
+
+    // Nothing but the manifest was touched
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_atomic_create_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"new contents").unwrap();
+    std::fs::write(src_dir.path().join("b.bin"), vec![3u8; 100_000]).unwrap();
+
+    for mode in ["tmpfile", "rename"] {
+        let dst = dst_dir.path().join(mode);
+        std::fs::create_dir(&dst).unwrap();
+        // An existing destination is replaced
+        std::fs::write(dst.join("a.txt"), b"old").unwrap();
+
+        let mut cmd = Command::cargo_bin("arsync").unwrap();
+        cmd.args([
+            src_dir.path().to_str().unwrap(),
+            dst.to_str().unwrap(),
+            "-r",
+            &format!("--atomic-create={mode}"),
+        ])
+        .assert()
+        .success();
+
+        assert_eq!(std::fs::read(dst.join("a.txt")).unwrap(), b"new contents");
+        assert_eq!(
+            std::fs::read(dst.join("b.bin")).unwrap(),
+            vec![3u8; 100_000]
+        );
+        let leftovers: Vec<_> = std::fs::read_dir(&dst)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with(".arsync.tmp."))
+            .collect();
+        assert!(leftovers.is_empty(), "{mode}: {leftovers:?}");
+    }
+}
+
+#[test]
+fn test_shadow_rsync_flag() {
+    if std::process::Command::new("rsync")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        eprintln!("Skipping: rsync is not installed");
+        return;
+    }
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"hello").unwrap();
+    std::fs::write(src_dir.path().join("sub/b.txt"), b"world").unwrap();
+
+    // rsync finds nothing left to do after an archive copy
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst_dir.path().to_str().unwrap(),
+        "-a",
+        "--shadow-rsync",
+    ])
+    .assert()
+    .success();
+}
+
+#[test]
+fn test_special_files_policy() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::write(src_dir.path().join("regular.txt"), b"data").unwrap();
+    let _socket = std::os::unix::net::UnixListener::bind(src_dir.path().join("sock")).unwrap();
+    let status = std::process::Command::new("mkfifo")
+        .arg(src_dir.path().join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Default: skipped, each with a warning and counted per type
+    let skipped = dst_dir.path().join("skipped");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        skipped.to_str().unwrap(),
+        "-r",
+        "-t",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Skipping fifo"))
+    .stdout(predicate::str::contains("1 fifos, 1 sockets"));
+    assert!(skipped.join("regular.txt").exists());
+    assert!(!skipped.join("pipe").exists());
+    assert!(!skipped.join("sock").exists());
+
+    // Placeholder: empty regular files with the source timestamps
+    let placeholders = dst_dir.path().join("placeholders");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        placeholders.to_str().unwrap(),
+        "-r",
+        "-t",
+        "--special-files=placeholder",
+    ])
+    .assert()
+    .success();
+    for name in ["pipe", "sock"] {
+        let metadata = std::fs::symlink_metadata(placeholders.join(name)).unwrap();
+        assert!(metadata.is_file(), "{name}");
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            std::fs::symlink_metadata(src_dir.path().join(name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_specials_recreated() {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let _socket = std::os::unix::net::UnixListener::bind(src_dir.path().join("sock")).unwrap();
+    let pipe = src_dir.path().join("pipe");
+    let status = std::process::Command::new("mkfifo")
+        .args(["-m", "0640"])
+        .arg(&pipe)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // --specials: fifos and sockets are recreated with their metadata
+    let dst = dst_dir.path().join("dst");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "-t",
+        "-p",
+        "--specials",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Skipping").not());
+
+    let copied = std::fs::symlink_metadata(dst.join("pipe")).unwrap();
+    assert!(copied.file_type().is_fifo());
+    assert_eq!(copied.permissions().mode() & 0o7777, 0o640);
+    assert_eq!(
+        copied.modified().unwrap(),
+        std::fs::symlink_metadata(&pipe)
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+    assert!(std::fs::symlink_metadata(dst.join("sock"))
+        .unwrap()
+        .file_type()
+        .is_socket());
+
+    // A second run keeps them and reports nothing to do in a dry run
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--specials",
+        "--dry-run",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("cS+++++++++").not());
+}
+
+#[test]
+fn test_show_tuning() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let dst = dst_dir.path().join("not-created-yet");
+
+    // Detected from the nearest existing ancestor of the destination
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "--show-tuning",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("profile: "));
+    assert!(!dst.exists());
+
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "--tune=nfs",
+        "--show-tuning",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(
+        "profile: Nfs\nbuffer_size_kb: 1024\nfallocate: false",
+    ));
+
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "--tune=xfs",
+        "--buffer-size-kb",
+        "32",
+        "--show-tuning",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("buffer_size_kb: 32\n"));
+}
+
+#[test]
+fn test_interactive_requires_terminal() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"new contents").unwrap();
+    std::fs::write(dst_dir.path().join("a.txt"), b"old").unwrap();
+
+    // Without a terminal on stdin nothing is asked and the copy proceeds
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst_dir.path().to_str().unwrap(),
+        "-r",
+        "--interactive",
+    ])
+    .write_stdin("n\n")
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("--interactive ignored"));
+    assert_eq!(
+        std::fs::read(dst_dir.path().join("a.txt")).unwrap(),
+        b"new contents"
+    );
+}
+
+#[test]
+fn test_state_journal_flag() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+    std::fs::write(src_dir.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(src_dir.path().join("sub/b.txt"), b"bb").unwrap();
+
+    let journal = state_dir.path().join("state.journal");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst_dir.path().join("out").to_str().unwrap(),
+        "-r",
+        "--state-journal",
+        journal.to_str().unwrap(),
+        "--checkpoint-files",
+        "1",
+    ])
+    .assert()
+    .success();
+
+    let contents = std::fs::read_to_string(&journal).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines[0], "arsync-state-journal v1");
+    assert!(lines.contains(&"+ a.txt"), "{contents}");
+    assert!(lines.contains(&"+ sub/b.txt"), "{contents}");
+    // The last snapshot has the final totals: 2 files, 3 bytes
+    let last_stats = lines
+        .iter()
+        .rev()
+        .find(|line| line.starts_with("S "))
+        .unwrap();
+    let fields: Vec<&str> = last_stats.split(' ').collect();
+    assert_eq!((fields[2], fields[4]), ("2", "3"), "{contents}");
+    assert!(lines.last().unwrap().starts_with("E "), "{contents}");
+}
+
+#[test]
+fn test_large_file_pipeline() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let large: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    std::fs::write(src_dir.path().join("large.bin"), &large).unwrap();
+    std::fs::write(src_dir.path().join("small.txt"), b"small").unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "-v",
+        "--large-file-threshold-mb",
+        "1",
+        "--large-files-in-flight",
+        "1",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Small-file pipeline: 1 files"))
+    .stdout(predicate::str::contains(
+        "Large-file pipeline: 1 files, 3.0 MB",
+    ));
+
+    assert_eq!(std::fs::read(dst.join("large.bin")).unwrap(), large);
+    assert_eq!(std::fs::read(dst.join("small.txt")).unwrap(), b"small");
+}
+
+#[test]
+fn test_omit_dir_times_keeps_file_times() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let sub = src_dir.path().join("sub");
+    std::fs::create_dir(&sub).unwrap();
+    std::fs::write(sub.join("file.txt"), "content").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(sub.join("file.txt"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    std::fs::File::open(&sub)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--times",
+        "--omit-dir-times",
+    ])
+    .assert()
+    .success();
+
+    let mtime = |path: &std::path::Path| std::fs::metadata(path).unwrap().modified().unwrap();
+    assert_eq!(mtime(&dst.join("sub/file.txt")), old);
+    assert_ne!(mtime(&dst.join("sub")), old);
+}
+
+#[test]
+fn test_copy_methods_produce_identical_files() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..5 * 1024 * 1024 + 17).map(|i| (i % 253) as u8).collect();
+    std::fs::write(src_dir.path().join("data.bin"), &data).unwrap();
+    std::fs::write(src_dir.path().join("empty"), b"").unwrap();
+
+    for method in ["auto", "copy-file-range", "reflink", "splice", "read-write"] {
+        let dst = dst_dir.path().join(method);
+        let mut cmd = Command::cargo_bin("arsync").unwrap();
+        cmd.args([
+            src_dir.path().to_str().unwrap(),
+            dst.to_str().unwrap(),
+            "-r",
+            "--copy-method",
+            method,
+        ])
+        .assert()
+        .success();
+
+        assert_eq!(
+            std::fs::read(dst.join("data.bin")).unwrap(),
+            data,
+            "{method}"
+        );
+        assert!(std::fs::read(dst.join("empty")).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_repeated_warnings_are_summarized() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let pipes: Vec<_> = (0..10)
+        .map(|i| src_dir.path().join(format!("pipe{i}")))
+        .collect();
+    let status = std::process::Command::new("mkfifo")
+        .args(&pipes)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::cargo_bin("arsync")
+        .unwrap()
+        .args([
+            src_dir.path().to_str().unwrap(),
+            dst_dir.path().join("out").to_str().unwrap(),
+            "-r",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The first three are logged, the rest only counted in the final table
+    assert_eq!(stdout.matches("Skipping fifo").count(), 3, "{stdout}");
+    assert!(
+        stdout.contains("10 warnings (7 not shown above)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("special file skipped"), "{stdout}");
+}
+
+#[test]
+fn test_sparse_preserves_holes() {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::os::unix::fs::MetadataExt;
+
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    let size = 64 * 1024 * 1024;
+    let path = src_dir.path().join("disk.img");
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(b"header").unwrap();
+    // Explicitly written zeros become a hole too
+    file.write_all(&vec![0u8; 2 * 1024 * 1024]).unwrap();
+    file.seek(SeekFrom::Start(32 * 1024 * 1024)).unwrap();
+    file.write_all(b"middle").unwrap();
+    file.set_len(size).unwrap();
+    drop(file);
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--sparse",
+    ])
+    .assert()
+    .success();
+
+    let copied = dst.join("disk.img");
+    assert_eq!(
+        std::fs::read(&copied).unwrap(),
+        std::fs::read(&path).unwrap()
+    );
+    let metadata = std::fs::metadata(&copied).unwrap();
+    assert_eq!(metadata.len(), size);
+    assert!(
+        metadata.blocks() * 512 < 4 * 1024 * 1024,
+        "{} bytes allocated",
+        metadata.blocks() * 512
+    );
+}
+
+#[test]
+fn test_delete_removes_extraneous_entries() {
+    for timing in ["--delete", "--delete-before", "--delete-after"] {
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+        std::fs::write(src_dir.path().join("sub/keep.txt"), b"keep").unwrap();
+        std::fs::create_dir_all(dst_dir.path().join("sub/stale/deeper")).unwrap();
+        std::fs::write(dst_dir.path().join("sub/stale/deeper/f"), b"x").unwrap();
+        std::fs::write(dst_dir.path().join("extra.txt"), b"x").unwrap();
+
+        let mut cmd = Command::cargo_bin("arsync").unwrap();
+        cmd.args([
+            src_dir.path().to_str().unwrap(),
+            dst_dir.path().to_str().unwrap(),
+            "-r",
+            timing,
+        ])
+        .assert()
+        .success();
+
+        let dst = dst_dir.path();
+        assert_eq!(std::fs::read(dst.join("sub/keep.txt")).unwrap(), b"keep");
+        assert!(!dst.join("extra.txt").exists(), "{timing}");
+        assert!(!dst.join("sub/stale").exists(), "{timing}");
+        assert!(!dst.join(".arsync-delete.journal").exists(), "{timing}");
+    }
+}
+
+#[test]
+fn test_fan_out_to_several_destinations() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::create_dir(src_dir.path().join("sub")).unwrap();
+    std::fs::write(src_dir.path().join("top.txt"), b"top").unwrap();
+    std::fs::write(src_dir.path().join("sub/nested.bin"), vec![3u8; 1 << 20]).unwrap();
+
+    let destinations: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|name| dst_dir.path().join(name))
+        .collect();
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.arg(src_dir.path())
+        .args(&destinations)
+        .args(["-r", "-t"])
+        .assert()
+        .success();
+
+    for destination in &destinations {
+        assert_eq!(std::fs::read(destination.join("top.txt")).unwrap(), b"top");
+        assert_eq!(
+            std::fs::read(destination.join("sub/nested.bin")).unwrap(),
+            vec![3u8; 1 << 20]
+        );
+        assert_eq!(
+            std::fs::metadata(destination.join("top.txt"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            std::fs::metadata(src_dir.path().join("top.txt"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_rerun_skips_unchanged_files() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    std::fs::write(src_dir.path().join("file.txt"), "original").unwrap();
+    let dst = dst_dir.path().join("out");
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::cargo_bin("arsync").unwrap();
+        cmd.args([
+            src_dir.path().to_str().unwrap(),
+            dst.to_str().unwrap(),
+            "-a",
+        ])
+        .args(extra)
+        .assert()
+        .success();
+    };
+    run(&[]);
+
+    // Same size and mtime, different contents: only --checksum notices
+    let copied = dst.join("file.txt");
+    let mtime = std::fs::metadata(&copied).unwrap().modified().unwrap();
+    std::fs::write(&copied, "tampered").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&copied)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    run(&[]);
+    assert_eq!(std::fs::read_to_string(&copied).unwrap(), "tampered");
+    run(&["--checksum"]);
+    assert_eq!(std::fs::read_to_string(&copied).unwrap(), "original");
+}
+
+#[test]
+fn test_filter_rules() {
+    let src_dir = TempDir::new().unwrap();
+    let dst_dir = TempDir::new().unwrap();
+    for dir in ["src", "target/debug", "docs"] {
+        std::fs::create_dir_all(src_dir.path().join(dir)).unwrap();
+    }
+    for file in [
+        "src/main.rs",
+        "src/notes.txt",
+        "target/debug/app",
+        "docs/keep.txt",
+    ] {
+        std::fs::write(src_dir.path().join(file), file).unwrap();
+    }
+    let list = dst_dir.path().join("excludes");
+    std::fs::write(&list, "# build output\n/target/\n").unwrap();
+
+    let dst = dst_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        src_dir.path().to_str().unwrap(),
+        dst.to_str().unwrap(),
+        "-r",
+        "--exclude-from",
+        list.to_str().unwrap(),
+        "--include",
+        "keep.txt",
+        "--exclude",
+        "*.txt",
+    ])
+    .assert()
+    .success();
+
+    assert!(dst.join("src/main.rs").exists());
+    assert!(dst.join("docs/keep.txt").exists());
+    assert!(!dst.join("src/notes.txt").exists());
+    assert!(!dst.join("target").exists());
+}
+
+#[test]
+fn test_cleanup_removes_stale_artifacts() {
+    let dst_dir = TempDir::new().unwrap();
+    // PID u32::MAX can never be running, so this looks like a crashed run
+    let stale = dst_dir.path().join(".arsync.tmp.4294967295-1.file.txt");
+    let regular = dst_dir.path().join("file.txt");
+    std::fs::write(&stale, "partial").unwrap();
+    std::fs::write(&regular, "complete").unwrap();
+
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args(["cleanup", dst_dir.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed 1 stale artifact(s)"));
+
+    assert!(!stale.exists());
+    assert!(regular.exists());
+}
+
+#[test]
+fn test_bisync_copies_both_ways() {
+    let a = TempDir::new().unwrap();
+    let b = TempDir::new().unwrap();
+    std::fs::write(a.path().join("from_a.txt"), "a").unwrap();
+    std::fs::write(b.path().join("from_b.txt"), "b").unwrap();
+
+    let mut cmd = Command::cargo_bin("arsync").unwrap();
+    cmd.args([
+        "bisync",
+        a.path().to_str().unwrap(),
+        b.path().to_str().unwrap(),
+        "--conflict",
+        "keep-both",
+    ])
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("1 copied A -> B, 1 copied B -> A"));
+
+    assert_eq!(
+        std::fs::read_to_string(b.path().join("from_a.txt")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        std::fs::read_to_string(a.path().join("from_b.txt")).unwrap(),
+        "b"
+    );
+}
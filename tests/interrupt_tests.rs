@@ -0,0 +1,39 @@
+#![cfg(unix)]
+//! Tests for stopping a directory copy on Ctrl-C or SIGTERM
+//!
+//! The cancellation token is process-wide, so these tests get a test binary
+//! of their own.
+
+mod common;
+
+use arsync::error::SyncError;
+use std::fs;
+use tempfile::TempDir;
+
+/// Requirement: Once interrupted, no entry is started and the run fails
+/// with a summary of what was and wasn't copied
+#[compio::test]
+async fn test_interrupted_copy_starts_nothing() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    fs::create_dir_all(src_dir.join("sub")).unwrap();
+    fs::write(src_dir.join("a.txt"), "a").unwrap();
+    fs::write(src_dir.join("sub/b.txt"), "b").unwrap();
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir;
+    args.paths.destination = dst_dir.clone();
+    arsync::interrupt::token().cancel();
+    let error = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, SyncError::Interrupted(_)), "{error}");
+    assert!(
+        error.to_string().contains("2 entries were not started"),
+        "{error}"
+    );
+    assert!(!dst_dir.join("a.txt").exists());
+    assert!(!dst_dir.join("sub").exists());
+}
@@ -0,0 +1,105 @@
+#![cfg(unix)]
+//! End-to-end tests of the line-delimited JSON output (`--json`)
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Run arsync with `args` and parse every line of its stdout as JSON
+fn run_json(args: &[&Path], options: &[&str]) -> (std::process::ExitStatus, Vec<Value>) {
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .args(args)
+        .args(options)
+        .arg("--json")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let events = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect(line))
+        .collect();
+    (output.status, events)
+}
+
+fn named<'a>(events: &'a [Value], name: &str) -> Vec<&'a Value> {
+    events
+        .iter()
+        .filter(|event| event["event"] == name)
+        .collect()
+}
+
+/// Requirement: Every stdout line is a JSON event: each file is started
+/// and done, and the run ends with its summary
+#[test]
+fn test_copy_reports_json_events() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("a.txt"), b"alpha").unwrap();
+    fs::write(source.join("sub/b.txt"), b"beta").unwrap();
+    let destination = temp.path().join("destination");
+
+    let (status, events) = run_json(&[&source, &destination], &["-a", "-v"]);
+    assert!(status.success());
+    assert_eq!(named(&events, "file_start").len(), 2);
+    let mut done: Vec<_> = named(&events, "file_done")
+        .iter()
+        .map(|event| event["path"].as_str().unwrap().to_string())
+        .collect();
+    done.sort();
+    assert_eq!(
+        done,
+        [
+            source.join("a.txt").to_string_lossy(),
+            source.join("sub/b.txt").to_string_lossy()
+        ]
+    );
+    let summary = events.last().unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["files_copied"], 2);
+    assert_eq!(summary["bytes_copied"], 9);
+    assert_eq!(summary["errors"], 0);
+}
+
+/// Requirement: A dry run itemizes its changes as `itemize` events
+#[test]
+fn test_dry_run_itemizes_as_json() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("a.txt"), b"alpha").unwrap();
+    let destination = temp.path().join("destination");
+
+    let (status, events) = run_json(&[&source, &destination], &["-a", "-n"]);
+    assert!(status.success());
+    let items: Vec<_> = named(&events, "itemize")
+        .iter()
+        .map(|event| {
+            (
+                event["item"].as_str().unwrap(),
+                event["path"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert!(items.contains(&(">f+++++++++", "a.txt")), "{items:?}");
+    assert!(!destination.join("a.txt").exists());
+}
+
+/// Requirement: --shadow-rsync prints its report as text, so it is refused
+/// with --json rather than mixed into the JSON lines
+#[test]
+fn test_shadow_rsync_conflicts_with_json() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    let destination = temp.path().join("destination");
+
+    let (status, events) = run_json(&[&source, &destination], &["--shadow-rsync"]);
+    assert_eq!(status.code(), Some(2));
+    assert!(events.is_empty());
+    assert!(!destination.exists());
+}
@@ -0,0 +1,118 @@
+#![cfg(unix)]
+//! Tests for `--link-dest` snapshot backups
+//!
+//! Each backup is copied into a new directory next to the previous one,
+//! with `--link-dest=../previous`: unchanged files become hard links to the
+//! previous snapshot, and only changed files are copied.
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Requirement: Unchanged files are hard linked from the `--link-dest`
+/// directory and changed files are copied
+#[compio::test]
+async fn test_link_dest_links_unchanged_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    fs::create_dir_all(src_dir.join("sub")).unwrap();
+    fs::write(src_dir.join("same.txt"), "unchanged").unwrap();
+    fs::write(src_dir.join("sub/changed.txt"), "first").unwrap();
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = temp_dir.path().join("backup.1");
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    fs::write(src_dir.join("sub/changed.txt"), "second version").unwrap();
+    args.paths.destination = temp_dir.path().join("backup.2");
+    args.traversal.link_dest = vec![PathBuf::from("../backup.1")];
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    assert_eq!(stats.bytes_copied, "second version".len() as u64);
+    let old = temp_dir.path().join("backup.1");
+    let new = temp_dir.path().join("backup.2");
+    assert_eq!(
+        fs::metadata(new.join("same.txt")).unwrap().ino(),
+        fs::metadata(old.join("same.txt")).unwrap().ino(),
+        "unchanged file is a hard link"
+    );
+    assert_ne!(
+        fs::metadata(new.join("sub/changed.txt")).unwrap().ino(),
+        fs::metadata(old.join("sub/changed.txt")).unwrap().ino(),
+    );
+    assert_eq!(
+        fs::read_to_string(new.join("sub/changed.txt")).unwrap(),
+        "second version"
+    );
+    assert_eq!(
+        fs::read_to_string(old.join("sub/changed.txt")).unwrap(),
+        "first",
+        "the previous snapshot is untouched"
+    );
+}
+
+/// Requirement: With `-X`, a `--link-dest` file whose extended attributes
+/// differ from the source's is copied, not linked; neither is a symlink
+#[compio::test]
+async fn test_link_dest_compares_xattrs_and_skips_symlinks() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    fs::create_dir_all(&src_dir).unwrap();
+    for name in ["same.txt", "tagged.txt", "linked.txt"] {
+        fs::write(src_dir.join(name), "unchanged").unwrap();
+    }
+    if let Err(e) = xattr::set(src_dir.join("tagged.txt"), "user.tag", b"new") {
+        println!("⚠️  no user xattrs here ({e}), skipping");
+        return;
+    }
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.metadata.xattrs = true;
+    args.paths.source = src_dir.clone();
+    args.paths.destination = temp_dir.path().join("backup.1");
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    let old = temp_dir.path().join("backup.1");
+    xattr::set(old.join("tagged.txt"), "user.tag", b"old").unwrap();
+    // A symlink in the previous snapshot is not followed to a matching file
+    fs::rename(old.join("linked.txt"), temp_dir.path().join("outside.txt")).unwrap();
+    std::os::unix::fs::symlink(temp_dir.path().join("outside.txt"), old.join("linked.txt"))
+        .unwrap();
+
+    args.paths.destination = temp_dir.path().join("backup.2");
+    args.traversal.link_dest = vec![PathBuf::from("../backup.1")];
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    let new = temp_dir.path().join("backup.2");
+    let ino = |path: PathBuf| fs::symlink_metadata(path).unwrap().ino();
+    assert_eq!(ino(new.join("same.txt")), ino(old.join("same.txt")));
+    assert_ne!(ino(new.join("tagged.txt")), ino(old.join("tagged.txt")));
+    assert_eq!(
+        xattr::get(new.join("tagged.txt"), "user.tag").unwrap(),
+        Some(b"new".to_vec())
+    );
+    assert_eq!(
+        xattr::get(old.join("tagged.txt"), "user.tag").unwrap(),
+        Some(b"old".to_vec()),
+        "the previous snapshot is untouched"
+    );
+    assert!(fs::symlink_metadata(new.join("linked.txt"))
+        .unwrap()
+        .file_type()
+        .is_file());
+    assert_ne!(
+        ino(new.join("linked.txt")),
+        ino(temp_dir.path().join("outside.txt"))
+    );
+}
@@ -0,0 +1,48 @@
+#![cfg(unix)]
+//! End-to-end tests of the Prometheus metrics export (`--metrics-textfile`)
+
+#![allow(clippy::unwrap_used)]
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Value of the unlabelled sample `name` in a Prometheus text exposition
+fn sample(text: &str, name: &str) -> Option<f64> {
+    text.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+/// Requirement: At exit the textfile holds the run's counts and its success,
+/// and nothing else is left in the collector's directory
+#[test]
+fn test_metrics_textfile_written_at_exit() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("a.txt"), vec![b'a'; 5000]).unwrap();
+    fs::write(source.join("b.txt"), b"beta").unwrap();
+    let destination = temp.path().join("destination");
+    let collector = temp.path().join("textfile");
+    fs::create_dir(&collector).unwrap();
+    let textfile = collector.join("arsync.prom");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .arg(&destination)
+        .args(["-a", "--copy-method", "read-write", "--metrics-textfile"])
+        .arg(&textfile)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let text = fs::read_to_string(&textfile).unwrap();
+    assert_eq!(sample(&text, "arsync_files_copied_total"), Some(2.0));
+    assert_eq!(sample(&text, "arsync_bytes_copied_total"), Some(5004.0));
+    assert_eq!(sample(&text, "arsync_files_in_flight"), Some(0.0));
+    assert_eq!(sample(&text, "arsync_io_in_flight"), Some(0.0));
+    assert_eq!(sample(&text, "arsync_last_run_success"), Some(1.0));
+    assert!(text.contains("# TYPE arsync_io_uring_submissions_total counter\n"));
+    assert_eq!(fs::read_dir(&collector).unwrap().count(), 1);
+}
@@ -0,0 +1,70 @@
+#![cfg(target_os = "linux")]
+//! Tests for copying overlayfs layers (`--overlay-layer`)
+//!
+//! Uses the `user.overlay.*` markers of rootless mounts, which need no
+//! privileges; skipped where the filesystem has no user xattrs or the kernel
+//! does not let unprivileged users create whiteouts (before 5.8).
+
+mod common;
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Create a whiteout (0:0 character device) at `path`
+fn mknod_whiteout(path: &Path) -> std::io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    // SAFETY: c_path is a valid NUL-terminated path
+    if unsafe { libc::mknod(c_path.as_ptr(), libc::S_IFCHR | 0o600, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Requirement: Whiteouts are recreated and opaque markers copied, without
+/// -D, -X or root; a marker the source lost is removed on the next run
+#[compio::test]
+async fn test_overlay_layer_keeps_markers() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("upper");
+    let dst_dir = temp_dir.path().join("replica");
+    fs::create_dir_all(src_dir.join("etc")).unwrap();
+    fs::write(src_dir.join("etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+    if let Err(e) = mknod_whiteout(&src_dir.join("deleted")) {
+        println!("⚠️  cannot create whiteouts here ({e}), skipping");
+        return;
+    }
+    if let Err(e) = xattr::set(src_dir.join("etc"), "user.overlay.opaque", b"y") {
+        println!("⚠️  no user xattrs here ({e}), skipping");
+        return;
+    }
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    args.metadata.overlay_layer = true;
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    let whiteout = fs::symlink_metadata(dst_dir.join("deleted")).unwrap();
+    assert!(whiteout.file_type().is_char_device());
+    assert_eq!(whiteout.rdev(), 0);
+    assert_eq!(
+        xattr::get(dst_dir.join("etc"), "user.overlay.opaque").unwrap(),
+        Some(b"y".to_vec())
+    );
+
+    xattr::remove(src_dir.join("etc"), "user.overlay.opaque").unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        xattr::get(dst_dir.join("etc"), "user.overlay.opaque").unwrap(),
+        None
+    );
+}
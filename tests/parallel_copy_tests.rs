@@ -7,7 +7,7 @@
 #![allow(clippy::panic)] // panic!() is acceptable in tests for failure messages
 
 use arsync::cli::ParallelCopyConfig;
-use arsync::metadata::MetadataConfig;
+use arsync::metadata::{MetadataConfig, SpecialFilePolicy, UnprivilegedOwnership};
 use std::fs;
 use std::path::Path;
 use tempfile::TempDir;
@@ -33,6 +33,7 @@ fn enabled_parallel_config(max_depth: usize) -> ParallelCopyConfig {
         max_depth,
         min_file_size_mb: 1, // 1MB threshold for testing
         chunk_size_mb: 2,
+        adaptive: false,
     }
 }
 
@@ -46,15 +47,34 @@ fn minimal_metadata_config() -> MetadataConfig {
         times: false,
         group: false,
         owner: false,
+        no_owner: false,
+        no_group: false,
         devices: false,
+        specials: false,
+        devices_and_specials: false,
         xattrs: false,
         acls: false,
         fsync: false,
+        syncfs: false,
+        verify_direct: None,
+        atomic_create: None,
+        partial: false,
+        delay_updates: false,
+        special_files: SpecialFilePolicy::Skip,
+        unprivileged_ownership: UnprivilegedOwnership::Permitted,
         hard_links: false,
         atimes: false,
         crtimes: false,
         preserve_xattr: false,
         preserve_acl: false,
+        omit_dir_times: false,
+        omit_link_times: false,
+        symlink_rewrite: Vec::new(),
+        sparse: false,
+        preserve_flags: false,
+        update_immutable: false,
+        overlay_layer: false,
+        run: Default::default(),
     }
 }
 
@@ -204,6 +224,51 @@ async fn test_no_region_overlap() {
     }
 }
 
+/// Test parallel copy of a sparse file: holes are skipped and stay holes
+#[compio::test]
+async fn test_parallel_copy_sparse_source() {
+    use std::os::unix::fs::{FileExt, MetadataExt};
+
+    let temp_dir = TempDir::new().unwrap();
+    let src_path = temp_dir.path().join("sparse_source.bin");
+    let dst_path = temp_dir.path().join("sparse_dest.bin");
+
+    // 4MB of data, a 56MB hole, 4MB of data, and a 16MB hole at the end
+    let mb = 1024 * 1024;
+    let chunk: Vec<u8> = (0..4 * mb).map(|i| (i % 251) as u8).collect();
+    let file = fs::File::create(&src_path).expect("Failed to create source");
+    file.write_all_at(&chunk, 0)
+        .expect("Failed to write source");
+    file.write_all_at(&chunk, 60 * mb as u64)
+        .expect("Failed to write source");
+    file.set_len(80 * mb as u64)
+        .expect("Failed to extend source");
+    drop(file);
+
+    let parallel_config = enabled_parallel_config(3);
+    let metadata_config = minimal_metadata_config();
+    copy_file_test(&src_path, &dst_path, &metadata_config, &parallel_config)
+        .await
+        .expect("Copy failed");
+
+    let copied_data = fs::read(&dst_path).expect("Failed to read copied file");
+    assert_eq!(copied_data.len(), 80 * mb);
+    assert_eq!(&copied_data[..4 * mb], &chunk[..]);
+    assert!(copied_data[4 * mb..60 * mb].iter().all(|&byte| byte == 0));
+    assert_eq!(&copied_data[60 * mb..64 * mb], &chunk[..]);
+    assert!(copied_data[64 * mb..].iter().all(|&byte| byte == 0));
+
+    // Where the filesystem maps extents the holes are not allocated
+    let source_blocks = fs::metadata(&src_path).expect("stat source").blocks();
+    let copied_blocks = fs::metadata(&dst_path).expect("stat copy").blocks();
+    if source_blocks * 512 < 16 * mb as u64 {
+        assert!(
+            copied_blocks * 512 < 40 * mb as u64,
+            "holes were filled: {copied_blocks} blocks"
+        );
+    }
+}
+
 /// Benchmark helper: Test page alignment function
 #[test]
 fn test_align_to_page() {
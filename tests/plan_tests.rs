@@ -0,0 +1,75 @@
+#![cfg(unix)]
+//! End-to-end tests of the copy mechanism plan (`arsync plan`)
+
+#![allow(clippy::unwrap_used)]
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Requirement: The plan counts every file under one mechanism, explains the
+/// probes per source filesystem with --explain, and writes nothing to the
+/// destination
+#[test]
+fn test_plan_explains_mechanisms() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("a.txt"), vec![b'a'; 3000]).unwrap();
+    fs::write(source.join("sub/b.txt"), b"beta").unwrap();
+    let destination = temp.path().join("destination");
+    fs::create_dir(&destination).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg("plan")
+        .arg("--explain")
+        .args(["--copy-method", "read-write"])
+        .arg(&source)
+        .arg(&destination)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(": 2 files (3004 bytes)\n"), "{stdout}");
+    let read_write = stdout
+        .lines()
+        .find(|line| line.trim_start().starts_with("read/write"))
+        .unwrap();
+    assert_eq!(
+        read_write.split_whitespace().collect::<Vec<_>>(),
+        ["read/write", "2", "files", "3004", "bytes"]
+    );
+    assert!(stdout.contains("    reflink: "));
+    assert!(stdout.contains("    -> read/write: --copy-method read-write\n"));
+    assert_eq!(fs::read_dir(&destination).unwrap().count(), 0);
+}
+
+/// Requirement: A `plan` directory in the working directory is not silently
+/// taken for the subcommand; `./plan` copies it
+#[test]
+fn test_subcommand_name_shadowing_a_directory_is_refused() {
+    let temp = TempDir::new().unwrap();
+    fs::create_dir(temp.path().join("plan")).unwrap();
+    fs::write(temp.path().join("plan/a.txt"), b"alpha").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .current_dir(temp.path())
+        .args(["plan", "backup"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`./plan`"), "{stderr}");
+    assert!(!temp.path().join("backup").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .current_dir(temp.path())
+        .args(["./plan", "backup"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(temp.path().join("backup/a.txt")).unwrap(),
+        b"alpha"
+    );
+}
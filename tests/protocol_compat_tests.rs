@@ -0,0 +1,128 @@
+//! Capability negotiation compatibility tests
+//!
+//! Runs the current negotiation code against capability frames recorded from
+//! older (and hypothetical newer) arsync builds, stored as annotated hex under
+//! `tests/fixtures/protocol/`. Fixtures for released versions must never
+//! change: if one of these tests fails, the wire format broke compatibility.
+
+#![cfg(feature = "remote-sync")]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use arsync::protocol::capabilities::{
+    exchange_capabilities, Capability, CapabilitySet, NegotiatedCapabilities, CAPABILITY_VERSION,
+};
+use arsync::protocol::pipe::PipeTransport;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+
+/// Recorded frames of every released capability version: (version, fixture)
+const RELEASED: &[(u16, &str)] = &[
+    (1, include_str!("fixtures/protocol/capabilities-v1.hex")),
+    (2, include_str!("fixtures/protocol/capabilities-v2.hex")),
+    (3, include_str!("fixtures/protocol/capabilities-v3.hex")),
+];
+
+const FUTURE: &str = include_str!("fixtures/protocol/capabilities-future.hex");
+const FUTURE_INCOMPATIBLE: &str =
+    include_str!("fixtures/protocol/capabilities-future-incompatible.hex");
+
+/// Parse an annotated hex fixture (`#` starts a comment)
+fn parse_hex(fixture: &str) -> Vec<u8> {
+    fixture
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .flat_map(str::split_whitespace)
+        .map(|byte| u8::from_str_radix(byte, 16).expect("invalid hex byte in fixture"))
+        .collect()
+}
+
+/// Play `peer_frame` as the remote side and run our exchange against it
+///
+/// Returns the negotiation result and the frame we sent.
+async fn exchange_with_recorded_peer(
+    peer_frame: &[u8],
+) -> (anyhow::Result<NegotiatedCapabilities>, Vec<u8>) {
+    let (our_read, peer_write) = PipeTransport::create_pipe().unwrap();
+    let (peer_read, our_write) = PipeTransport::create_pipe().unwrap();
+    let mut peer_out = unsafe { File::from_raw_fd(peer_write) };
+    let mut peer_in = unsafe { File::from_raw_fd(peer_read) };
+    peer_out.write_all(peer_frame).unwrap();
+    drop(peer_out);
+
+    let mut transport =
+        unsafe { PipeTransport::from_fds(our_read, our_write, "local".to_string()).unwrap() };
+    let result = exchange_capabilities(&mut transport, &CapabilitySet::current()).await;
+    drop(transport);
+
+    let mut sent = Vec::new();
+    peer_in.read_to_end(&mut sent).unwrap();
+    (result, sent)
+}
+
+#[test]
+fn test_released_fixtures_match_historical_sets() {
+    // Test: Each released version's set still encodes to its recorded bytes
+    // Requirement: The encoding of released versions must never change
+    for (version, fixture) in RELEASED {
+        let historical = CapabilitySet::for_version(*version).unwrap();
+        assert_eq!(
+            historical.encode().unwrap(),
+            parse_hex(fixture),
+            "encoding of capability version {version} changed"
+        );
+    }
+    assert!(
+        RELEASED.iter().any(|(v, _)| *v == CAPABILITY_VERSION),
+        "add a fixture for capability version {CAPABILITY_VERSION}"
+    );
+}
+
+#[compio::test]
+async fn test_current_negotiates_with_every_released_version() {
+    // Test: Current code negotiates with each recorded older peer
+    // Requirement: Backward compatibility with every released version
+    for (version, fixture) in RELEASED {
+        let (result, sent) = exchange_with_recorded_peer(&parse_hex(fixture)).await;
+        let negotiated = result.unwrap();
+        assert_eq!(negotiated.version, *version);
+
+        let historical = CapabilitySet::for_version(*version).unwrap();
+        assert_eq!(
+            negotiated.enabled.keys().collect::<Vec<_>>(),
+            historical.entries.keys().collect::<Vec<_>>()
+        );
+
+        // What we sent must be understood by the recorded peer's decoder
+        let ours = CapabilitySet::decode(&sent).unwrap();
+        assert!(ours.min_version <= *version);
+    }
+}
+
+#[compio::test]
+async fn test_current_negotiates_with_future_version() {
+    // Test: A newer peer with unknown capabilities and header fields is accepted
+    // Requirement: Forward compatibility (older builds keep working)
+    let (result, _) = exchange_with_recorded_peer(&parse_hex(FUTURE)).await;
+    let negotiated = result.unwrap();
+    assert_eq!(negotiated.version, CAPABILITY_VERSION);
+    assert!(negotiated.supports(Capability::SEGMENTED_DELTA));
+    assert!(negotiated.supports(Capability::RESUMABLE_SESSIONS));
+    assert!(!negotiated.supports(Capability(1000)));
+}
+
+#[compio::test]
+async fn test_incompatible_future_version_fails_cleanly() {
+    // Test: A newer peer that dropped our version is rejected with a clear error
+    let (result, _) = exchange_with_recorded_peer(&parse_hex(FUTURE_INCOMPATIBLE)).await;
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("upgrade the local arsync"), "{error}");
+}
+
+#[compio::test]
+async fn test_non_capability_peer_is_reported() {
+    // Test: A peer that skips the capability frame gets a helpful error
+    let (result, _) = exchange_with_recorded_peer(b"\x00\x00\x00\x00\x00\x00\x00\x00").await;
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("capability frame"), "{error}");
+}
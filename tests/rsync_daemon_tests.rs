@@ -0,0 +1,200 @@
+//! End-to-end tests of `arsync --daemon` with a real rsync client
+//!
+//! Skipped when rsync is not installed.
+
+#![cfg(feature = "remote-sync")]
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Child, Command, Output};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn rsync_available() -> bool {
+    Command::new("rsync").arg("--version").output().is_ok()
+}
+
+/// A running `arsync --daemon`, killed on drop
+struct Daemon {
+    child: Child,
+    port: u16,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Start a daemon serving `backups` (writable) and `archive` (read only)
+///
+/// It runs as the owner of `root`, who can write there even when the tests
+/// run as root.
+fn start(root: &Path) -> Daemon {
+    fs::create_dir_all(root.join("backups")).unwrap();
+    fs::create_dir_all(root.join("archive")).unwrap();
+    let owner = fs::metadata(root).unwrap();
+    let config = root.join("arsyncd.conf");
+    fs::write(
+        &config,
+        format!(
+            "uid = {}\ngid = {}\n\
+             [backups]\n    path = {}\n    comment = Nightly backups\n    read only = no\n\
+             [archive]\n    path = {}\n",
+            owner.uid(),
+            owner.gid(),
+            root.join("backups").display(),
+            root.join("archive").display()
+        ),
+    )
+    .unwrap();
+    // A port that was free a moment ago
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let child = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg("--daemon")
+        .arg("--config")
+        .arg(&config)
+        .arg("--address")
+        .arg("127.0.0.1")
+        .arg("--port")
+        .arg(port.to_string())
+        .spawn()
+        .expect("Failed to start arsync --daemon");
+    let daemon = Daemon { child, port };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "arsync --daemon did not listen");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    daemon
+}
+
+fn rsync(args: &[String]) -> Output {
+    Command::new("rsync")
+        .args(args)
+        .output()
+        .expect("Failed to run rsync")
+}
+
+#[test]
+fn test_rsync_pushes_into_module() {
+    if !rsync_available() {
+        println!("⚠️  rsync not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(source.join("subdir")).unwrap();
+    fs::write(source.join("file1.txt"), b"Hello, World!").unwrap();
+    fs::write(source.join("subdir/file2.txt"), b"Nested file").unwrap();
+    let daemon = start(&temp.path().join("daemon"));
+
+    let output = rsync(&[
+        "-a".to_string(),
+        format!("{}/", source.display()),
+        format!("rsync://127.0.0.1:{}/backups/host1", daemon.port),
+    ]);
+    assert!(
+        output.status.success(),
+        "rsync push to arsync --daemon failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let dest = temp.path().join("daemon/backups/host1");
+    assert_eq!(fs::read(dest.join("file1.txt")).unwrap(), b"Hello, World!");
+    assert_eq!(
+        fs::read(dest.join("subdir/file2.txt")).unwrap(),
+        b"Nested file"
+    );
+}
+
+#[test]
+fn test_rsync_lists_modules_and_is_refused_read_only() {
+    if !rsync_available() {
+        println!("⚠️  rsync not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("file1.txt"), b"Hello, World!").unwrap();
+    let daemon = start(&temp.path().join("daemon"));
+
+    let output = rsync(&[format!("rsync://127.0.0.1:{}/", daemon.port)]);
+    assert!(output.status.success());
+    let listing = String::from_utf8_lossy(&output.stdout);
+    assert!(listing.contains("backups") && listing.contains("Nightly backups"));
+    assert!(listing.contains("archive"));
+
+    let output = rsync(&[
+        "-a".to_string(),
+        format!("{}/", source.display()),
+        format!("rsync://127.0.0.1:{}/archive/", daemon.port),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("read only"));
+    assert!(!temp.path().join("daemon/archive/file1.txt").exists());
+}
+
+#[test]
+fn test_symlinks_cannot_lead_out_of_the_module() {
+    // Requirement: A client cannot reach outside the module through
+    // symlinks, neither those it sends (they are munged) nor those already
+    // in the module (they are not followed, even by --delete)
+    if !rsync_available() {
+        println!("⚠️  rsync not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let outside = temp.path().join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("victim.txt"), b"keep me").unwrap();
+    let daemon = start(&temp.path().join("daemon"));
+    let url = format!("rsync://127.0.0.1:{}/backups/host1", daemon.port);
+    let dest = temp.path().join("daemon/backups/host1");
+
+    // A symlink the client sends is stored munged
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    std::os::unix::fs::symlink(&outside, source.join("escape")).unwrap();
+    let output = rsync(&[
+        "-a".to_string(),
+        format!("{}/", source.display()),
+        url.clone(),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stored = fs::read_link(dest.join("escape")).unwrap();
+    assert_eq!(
+        stored.to_str().unwrap(),
+        format!("/rsyncd-munged/{}", outside.display())
+    );
+
+    // A symlink planted in the module is replaced, not followed, by a
+    // directory of the same name
+    fs::remove_file(dest.join("escape")).unwrap();
+    std::os::unix::fs::symlink(&outside, dest.join("escape")).unwrap();
+    let source = temp.path().join("source2");
+    fs::create_dir_all(source.join("escape")).unwrap();
+    fs::write(source.join("escape/pushed.txt"), b"pushed").unwrap();
+    rsync(&[
+        "-a".to_string(),
+        "--delete".to_string(),
+        format!("{}/", source.display()),
+        url,
+    ]);
+    assert_eq!(fs::read(outside.join("victim.txt")).unwrap(), b"keep me");
+    assert!(!outside.join("pushed.txt").exists());
+    assert!(fs::symlink_metadata(dest.join("escape")).unwrap().is_dir());
+    assert_eq!(fs::read(dest.join("escape/pushed.txt")).unwrap(), b"pushed");
+}
@@ -423,11 +423,10 @@ fn test_rsync_protocol_version() {
 }
 
 // ============================================================================
-// Level 5: Full Roundtrip Tests (Aspirational)
+// Level 5: Full Roundtrip Tests
 // ============================================================================
 
 #[test]
-#[ignore] // Will enable when full protocol is implemented
 fn test_full_rsync_to_arsync_transfer() {
     if !rsync_available() {
         println!("⚠️  rsync not available, skipping");
@@ -439,35 +438,58 @@ fn test_full_rsync_to_arsync_transfer() {
     let temp = TempDir::new().unwrap();
     let source = temp.path().join("source");
     let dest = temp.path().join("dest");
-
     create_test_files(&source);
-    fs::create_dir(&dest).unwrap();
-
-    // TODO: Full bidirectional transfer
-    // This will be the ultimate test when protocol is complete
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i * 17 % 251) as u8).collect();
+    fs::write(source.join("subdir/big.bin"), &big).unwrap();
+
+    // rsync starts `arsync --server ...` through a "remote shell" that runs
+    // the command locally: sh -c SCRIPT rsh HOST COMMAND...
+    let push = || {
+        let output = Command::new("rsync")
+            .arg("-a")
+            .arg("--delete")
+            .arg("-e")
+            .arg("sh -c 'shift; exec \"$@\"' rsh")
+            .arg(format!("--rsync-path={}", env!("CARGO_BIN_EXE_arsync")))
+            .arg(format!("{}/", source.display()))
+            .arg(format!("localhost:{}", dest.display()))
+            .output()
+            .expect("Failed to run rsync");
+        assert!(
+            output.status.success(),
+            "rsync push to arsync failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
 
-    // Verify files were transferred
-    assert!(
-        dest.join("file1.txt").exists(),
-        "file1.txt should be transferred"
-    );
-    assert!(
-        dest.join("file2.txt").exists(),
-        "file2.txt should be transferred"
+    push();
+    assert_eq!(fs::read(dest.join("file1.txt")).unwrap(), b"Hello, World!");
+    assert_eq!(
+        fs::read(dest.join("file2.txt")).unwrap(),
+        b"Rust is awesome!"
     );
-    assert!(
-        dest.join("subdir/file3.txt").exists(),
-        "subdir/file3.txt should be transferred"
+    assert_eq!(
+        fs::read(dest.join("subdir/file3.txt")).unwrap(),
+        b"Nested file"
     );
-
-    // Verify content
-    assert_eq!(fs::read(dest.join("file1.txt")).unwrap(), b"Hello, World!");
+    assert_eq!(fs::read(dest.join("subdir/big.bin")).unwrap(), big);
+    println!("✓ Initial transfer");
+
+    // Second run: arsync sends block checksums and rsync answers with a
+    // delta; files gone from the source are deleted
+    let mut changed = big.clone();
+    changed.splice(1000..1000, b"inserted".iter().copied());
+    changed[200_000] ^= 0xFF;
+    fs::write(source.join("subdir/big.bin"), &changed).unwrap();
+    fs::remove_file(source.join("file2.txt")).unwrap();
+    push();
+    assert_eq!(fs::read(dest.join("subdir/big.bin")).unwrap(), changed);
+    assert!(!dest.join("file2.txt").exists());
 
     println!("✓ FULL TRANSFER SUCCESSFUL!");
 }
 
 #[test]
-#[ignore] // Will enable when full protocol is implemented
 fn test_full_arsync_to_rsync_transfer() {
     if !rsync_available() {
         println!("⚠️  rsync not available, skipping");
@@ -476,8 +498,53 @@ fn test_full_arsync_to_rsync_transfer() {
 
     println!("🧪 FULL TEST: arsync → rsync complete file transfer");
 
-    // Reverse direction test
-    // TODO: Implement when sender mode is ready
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    let dest = temp.path().join("dest");
+    create_test_files(&source);
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    fs::write(source.join("subdir/big.bin"), &big).unwrap();
+
+    // A "remote shell" that runs the remote command locally:
+    // sh -c SCRIPT rsh HOST -- COMMAND
+    let push = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+            .arg("-a")
+            .arg("--rsh")
+            .arg("sh -c 'exec sh -c \"$3\"' rsh")
+            .arg(&source)
+            .arg(format!("localhost:{}", dest.display()))
+            .output()
+            .expect("Failed to run arsync");
+        assert!(
+            output.status.success(),
+            "arsync push failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    push();
+    assert_eq!(fs::read(dest.join("file1.txt")).unwrap(), b"Hello, World!");
+    assert_eq!(
+        fs::read(dest.join("file2.txt")).unwrap(),
+        b"Rust is awesome!"
+    );
+    assert_eq!(
+        fs::read(dest.join("subdir/file3.txt")).unwrap(),
+        b"Nested file"
+    );
+    assert_eq!(fs::read(dest.join("subdir/big.bin")).unwrap(), big);
+    println!("✓ Initial push");
+
+    // Second run: rsync sends block checksums and arsync answers with a delta
+    let mut changed = big.clone();
+    changed.splice(1000..1000, b"inserted".iter().copied());
+    changed[200_000] ^= 0xFF;
+    fs::write(source.join("subdir/big.bin"), &changed).unwrap();
+    fs::write(source.join("file1.txt"), b"Hello again!").unwrap();
+    push();
+    assert_eq!(fs::read(dest.join("subdir/big.bin")).unwrap(), changed);
+    assert_eq!(fs::read(dest.join("file1.txt")).unwrap(), b"Hello again!");
 
     println!("✓ Reverse direction test (arsync → rsync)");
 }
@@ -503,7 +570,7 @@ fn test_suite_summary() {
     println!("  ✓ Level 2: File list exchange    (PASSING)");
     println!("  ✓ Level 3: Bidirectional pipes   (PASSING)");
     println!("  ✓ Level 4: Protocol handshake    (PASSING)");
-    println!("  ⏳ Level 5: Full transfer         (IN PROGRESS)");
+    println!("  ✓ Level 5: Full transfer         (PASSING)");
     println!();
     println!("Current Status:");
     println!("  • Can spawn rsync --server");
@@ -0,0 +1,43 @@
+#![cfg(unix)]
+//! End-to-end tests of panic isolation in the traversal's workers
+//!
+//! The panic strategy is the profile's: run these against the shipped
+//! binary with `cargo test --release --test supervisor_tests`.
+
+#![allow(clippy::unwrap_used)]
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Requirement: A panicking entry fails on its own; the process is not
+/// aborted and its siblings are still copied
+#[test]
+fn test_panic_fails_only_its_entry() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("a.txt"), b"alpha").unwrap();
+    fs::write(source.join("bad.txt"), b"bad").unwrap();
+    fs::write(source.join("sub/b.txt"), b"beta").unwrap();
+    let destination = temp.path().join("destination");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .env("ARSYNC_TEST_PANIC_ON", "bad.txt")
+        .arg(&source)
+        .arg(&destination)
+        .arg("-a")
+        .output()
+        .unwrap();
+    let log = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Aborting would end the process by SIGABRT, without an exit code
+    assert!(output.status.code().is_some(), "{log}");
+    assert!(log.contains("Panic while processing"), "{log}");
+    assert_eq!(fs::read(destination.join("a.txt")).unwrap(), b"alpha");
+    assert_eq!(fs::read(destination.join("sub/b.txt")).unwrap(), b"beta");
+    assert!(!destination.join("bad.txt").exists());
+}
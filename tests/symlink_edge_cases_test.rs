@@ -269,6 +269,34 @@ fn test_directory_symlink_dereferenced() {
     println!("✓ Directory symlink (link→dir/) dereferenced correctly");
 }
 
+#[test]
+#[cfg(unix)]
+fn test_directory_symlink_loop_dereferenced() {
+    let temp = TempDir::new().unwrap();
+    let src = temp.path().join("src");
+    let dst = temp.path().join("dst");
+
+    // Create: sub/file.txt and sub/back → .. (points at an ancestor)
+    let subdir = src.join("sub");
+    fs::create_dir_all(&subdir).unwrap();
+    fs::write(subdir.join("file.txt"), "content").unwrap();
+    std::os::unix::fs::symlink("..", subdir.join("back")).unwrap();
+
+    // Dereferencing would recurse forever without loop detection
+    run_arsync(&src, &dst, &["-r"]).expect("Sync failed");
+
+    assert_eq!(
+        fs::read_to_string(dst.join("sub/file.txt")).unwrap(),
+        "content"
+    );
+    assert!(
+        !dst.join("sub/back/sub").exists(),
+        "Loop through ancestor symlink should be skipped"
+    );
+
+    println!("✓ Directory symlink loop (link→ancestor) skipped correctly");
+}
+
 // ============================================================================
 // TEST: Metadata Preservation Across Types
 // ============================================================================
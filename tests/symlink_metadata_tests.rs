@@ -277,7 +277,9 @@ async fn test_symlink_ownership_preservation() {
     args.paths.source = src_dir.clone();
     args.paths.destination = dst_dir.clone();
 
-    arsync::sync::sync_files(&args).await.unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
 
     // Check that the SYMLINK's ownership was preserved (not just the target)
     let dst_link = dst_dir.join("link");
@@ -365,7 +367,9 @@ async fn test_symlink_timestamp_preservation() {
     args.paths.source = src_dir.clone();
     args.paths.destination = dst_dir.clone();
 
-    arsync::sync::sync_files(&args).await.unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
 
     // Check that the SYMLINK's timestamps were preserved (not just the target)
     let dst_link = dst_dir.join("link");
@@ -441,7 +445,9 @@ async fn test_symlink_xattr_preservation() {
     args.metadata.xattrs = true;
     args.metadata.links = true;
 
-    arsync::sync::sync_files(&args).await.unwrap();
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
 
     // Check that the SYMLINK's xattr was preserved (not just the target)
     let dst_link = dst_dir.join("link");
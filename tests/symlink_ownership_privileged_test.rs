@@ -174,7 +174,7 @@ async fn test_symlink_copy_works_without_root() {
     args.paths.destination = dst.clone();
 
     // This should succeed even without root (ownership preservation fails gracefully)
-    let result = arsync::sync::sync_files(&args).await;
+    let result = arsync::sync::sync_files(&args, Default::default()).await;
     assert!(
         result.is_ok(),
         "Symlink copy should succeed even without root privileges"
@@ -0,0 +1,47 @@
+#![cfg(unix)]
+//! Tests for `--symlink-rewrite=FROM:TO`
+//!
+//! A tree moved from `old` to `new` keeps working when its absolute symlinks
+//! into `old` are rewritten to point into `new`.
+
+mod common;
+
+use arsync::symlink_rewrite::RewriteRule;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Requirement: Absolute symlinks into FROM point into TO at the
+/// destination; other symlinks keep their targets
+#[compio::test]
+async fn test_relocated_tree_links_point_to_new_location() {
+    let temp_dir = TempDir::new().unwrap();
+    let old = temp_dir.path().join("old");
+    let new = temp_dir.path().join("new");
+    fs::create_dir_all(old.join("lib")).unwrap();
+    fs::create_dir_all(old.join("bin")).unwrap();
+    fs::write(old.join("lib/libfoo.so"), b"library").unwrap();
+    std::os::unix::fs::symlink(old.join("lib/libfoo.so"), old.join("bin/libfoo.so")).unwrap();
+    std::os::unix::fs::symlink(old.join("lib/gone.so"), old.join("bin/gone.so")).unwrap();
+    std::os::unix::fs::symlink("../lib/libfoo.so", old.join("bin/relative.so")).unwrap();
+    std::os::unix::fs::symlink("/etc/hostname", old.join("bin/outside")).unwrap();
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = old.clone();
+    args.paths.destination = new.clone();
+    args.metadata.symlink_rewrite = vec![RewriteRule {
+        from: old.clone(),
+        to: new.clone(),
+    }];
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    let link = |name: &str| fs::read_link(new.join("bin").join(name)).unwrap();
+    assert_eq!(link("libfoo.so"), new.join("lib/libfoo.so"));
+    assert_eq!(fs::read(new.join("bin/libfoo.so")).unwrap(), b"library");
+    // Rewritten even though it still dangles (it is reported)
+    assert_eq!(link("gone.so"), new.join("lib/gone.so"));
+    assert_eq!(link("relative.so"), PathBuf::from("../lib/libfoo.so"));
+    assert_eq!(link("outside"), PathBuf::from("/etc/hostname"));
+}
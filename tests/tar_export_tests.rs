@@ -0,0 +1,106 @@
+#![cfg(unix)]
+//! End-to-end tests of `arsync SOURCE --to-tar ARCHIVE`
+//!
+//! The archive is unpacked with the system tar; skipped when it is not
+//! installed.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+mod common;
+use common::tar_helpers::{make_source, tar_available};
+
+#[test]
+fn test_archive_file_unpacks_to_source() {
+    if !tar_available() {
+        println!("⚠️  tar not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    let long_name = make_source(&source);
+    let archive = temp.path().join("source.tar");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .arg(&archive)
+        .args(["--to-tar", "-a"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let unpacked = temp.path().join("unpacked");
+    fs::create_dir_all(&unpacked).unwrap();
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&unpacked)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        fs::read(unpacked.join("file1.txt")).unwrap(),
+        b"Hello, World!"
+    );
+    assert_eq!(
+        fs::read(unpacked.join("sub/deeper").join(long_name)).unwrap(),
+        b"long name"
+    );
+    assert_eq!(
+        fs::read_link(unpacked.join("link")).unwrap(),
+        Path::new("file1.txt")
+    );
+    assert_eq!(
+        fs::metadata(unpacked.join("file1.txt"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        fs::metadata(source.join("file1.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_archive_streams_to_stdout() {
+    if !tar_available() {
+        println!("⚠️  tar not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    make_source(&source);
+    fs::write(source.join("debug.log"), b"excluded").unwrap();
+
+    // Logging goes to stderr, so -v must not corrupt the stream
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .args(["--to-tar", "-", "-v", "--exclude", "*.log"])
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len() % 512, 0);
+
+    let mut tar = Command::new("tar")
+        .arg("-tf")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    tar.stdin.take().unwrap().write_all(&output.stdout).unwrap();
+    let listing = tar.wait_with_output().unwrap();
+    assert!(listing.status.success());
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    let names: Vec<&str> = listing.lines().collect();
+    assert_eq!(names[..3], ["file1.txt", "link", "sub/"]);
+    assert!(!listing.contains("debug.log"));
+}
@@ -0,0 +1,128 @@
+#![cfg(unix)]
+//! End-to-end tests of `arsync ARCHIVE DESTINATION --from-tar`
+//!
+//! Archives are made with the system tar (skipped when it is not installed)
+//! and with `arsync --to-tar`.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+mod common;
+use common::tar_helpers::{make_source, tar_available};
+
+fn assert_extracted(source: &Path, extracted: &Path, long_name: &str) {
+    assert_eq!(
+        fs::read(extracted.join("file1.txt")).unwrap(),
+        b"Hello, World!"
+    );
+    assert_eq!(
+        fs::read(extracted.join("sub/deeper").join(long_name)).unwrap(),
+        b"long name"
+    );
+    assert_eq!(
+        fs::read_link(extracted.join("link")).unwrap(),
+        Path::new("file1.txt")
+    );
+    let file = fs::metadata(extracted.join("file1.txt")).unwrap();
+    assert_eq!(
+        file.ino(),
+        fs::metadata(extracted.join("sub/hard.txt")).unwrap().ino()
+    );
+    assert_eq!(
+        file.modified().unwrap(),
+        fs::metadata(source.join("file1.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_extract_tar_formats_from_stdin() {
+    if !tar_available() {
+        println!("⚠️  tar not available, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    let long_name = make_source(&source);
+
+    for format in ["pax", "gnu"] {
+        let archive = Command::new("tar")
+            .arg(format!("--format={format}"))
+            .arg("-cf")
+            .arg("-")
+            .arg("-C")
+            .arg(&source)
+            .arg(".")
+            .output()
+            .unwrap();
+        assert!(archive.status.success());
+
+        let extracted = temp.path().join(format);
+        let mut arsync = Command::new(env!("CARGO_BIN_EXE_arsync"))
+            .arg("-")
+            .arg(&extracted)
+            .args(["--from-tar", "-a"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        arsync
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(&archive.stdout)
+            .unwrap();
+        assert!(arsync.wait().unwrap().success(), "{format}");
+        assert_extracted(&source, &extracted, &long_name);
+    }
+}
+
+#[test]
+fn test_to_tar_piped_into_from_tar() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    let long_name = make_source(&source);
+    fs::write(source.join("debug.log"), b"excluded").unwrap();
+
+    let archive = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .args(["-", "--to-tar", "-aH"])
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(archive.status.success());
+    let archive_path = temp.path().join("source.tar");
+    fs::write(&archive_path, &archive.stdout).unwrap();
+
+    let extracted = temp.path().join("extracted");
+    let status = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&archive_path)
+        .arg(&extracted)
+        .args(["--from-tar", "-a", "--exclude", "*.log"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_extracted(&source, &extracted, &long_name);
+    assert!(!extracted.join("debug.log").exists());
+}
+
+#[test]
+fn test_missing_archive_is_rejected() {
+    let temp = TempDir::new().unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(temp.path().join("missing.tar"))
+        .arg(temp.path().join("dst"))
+        .arg("--from-tar")
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(!temp.path().join("dst").exists());
+}
@@ -21,6 +21,8 @@ fn to_file_metadata(m: compio::fs::Metadata) -> FileMetadata {
         nlink: m.nlink(),
         ino: m.ino(),
         dev: m.dev(),
+        rdev: m.rdev(),
+        blocks: m.blocks(),
         accessed: m.accessed().unwrap_or(SystemTime::UNIX_EPOCH),
         modified: m.modified().unwrap_or(SystemTime::UNIX_EPOCH),
         created: m.created().ok(),
@@ -0,0 +1,86 @@
+#![cfg(unix)]
+//! Tests for `--transform` content filters
+
+mod common;
+
+use arsync::transform::TransformRule;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Source tree with one file to transform and one to copy
+fn create_source(src_dir: &Path) {
+    fs::create_dir_all(src_dir.join("docs")).unwrap();
+    fs::write(src_dir.join("docs/readme.txt"), "hello world\n").unwrap();
+    fs::write(src_dir.join("data.bin"), "hello world\n").unwrap();
+}
+
+/// Requirement: Matching files are piped through the command and keep the
+/// source's modification time; other files are copied unchanged
+#[compio::test]
+async fn test_transform_pipes_matching_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_source(&src_dir);
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir.clone();
+    args.paths.destination = dst_dir.clone();
+    args.traversal.transform = vec![TransformRule::parse("*.txt:tr a-z A-Z").unwrap()];
+    arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dst_dir.join("docs/readme.txt")).unwrap(),
+        "HELLO WORLD\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dst_dir.join("data.bin")).unwrap(),
+        "hello world\n"
+    );
+    assert_eq!(
+        fs::metadata(dst_dir.join("docs/readme.txt"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        fs::metadata(src_dir.join("docs/readme.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    );
+
+    // Up to date by modification time, even though the contents differ
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.files_copied, 0);
+}
+
+/// Requirement: A failing command fails the file (reported like any failed
+/// file, the run goes on) and leaves the existing destination untouched
+#[compio::test]
+async fn test_failed_transform_keeps_destination() {
+    let temp_dir = TempDir::new().unwrap();
+    let src_dir = temp_dir.path().join("src");
+    let dst_dir = temp_dir.path().join("dst");
+    create_source(&src_dir);
+    fs::create_dir_all(dst_dir.join("docs")).unwrap();
+    fs::write(dst_dir.join("docs/readme.txt"), "previous").unwrap();
+
+    let mut args = common::test_args::create_archive_test_args();
+    args.paths.source = src_dir;
+    args.paths.destination = dst_dir.clone();
+    args.traversal.transform = vec![TransformRule::parse("*.txt:cat; exit 3").unwrap()];
+    let stats = arsync::sync::sync_files(&args, Default::default())
+        .await
+        .unwrap();
+
+    assert_eq!(stats.files_copied, 1, "only data.bin is copied");
+    assert_eq!(
+        fs::read_to_string(dst_dir.join("docs/readme.txt")).unwrap(),
+        "previous"
+    );
+    assert_eq!(fs::read_dir(dst_dir.join("docs")).unwrap().count(), 1);
+}
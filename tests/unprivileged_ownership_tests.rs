@@ -0,0 +1,79 @@
+#![cfg(unix)]
+//! End-to-end tests of `-a` without the privileges to change ownership
+//!
+//! Without `CAP_CHOWN` a run prints one hint and copies what it may; with
+//! `--unprivileged-ownership=fail` it does not start. Skipped when the tests
+//! run with `CAP_CHOWN` (as root).
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use arsync::privileges::Privileges;
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+const HINT: &str = "Preserving ownership needs CAP_CHOWN";
+
+/// Requirement: One hint per run, not an error per file, and the copy
+/// succeeds
+#[test]
+fn test_archive_without_chown_hints_once() {
+    if Privileges::current().chown {
+        println!("⚠️  running with CAP_CHOWN, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    for i in 0..20 {
+        fs::write(source.join(format!("sub/file{i}.txt")), b"data").unwrap();
+    }
+    let destination = temp.path().join("destination");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .arg(&destination)
+        .arg("-a")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(log.matches(HINT).count(), 1, "{log}");
+    assert_eq!(
+        fs::read(destination.join("sub/file7.txt")).unwrap(),
+        b"data"
+    );
+
+    let quiet = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .arg(temp.path().join("quiet"))
+        .args(["-a", "--no-owner", "--no-group"])
+        .output()
+        .unwrap();
+    assert!(quiet.status.success());
+    assert!(!String::from_utf8_lossy(&quiet.stdout).contains(HINT));
+}
+
+/// Requirement: --unprivileged-ownership=fail refuses to start
+#[test]
+fn test_fail_policy_refuses_to_start() {
+    if Privileges::current().chown {
+        println!("⚠️  running with CAP_CHOWN, skipping");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let source = temp.path().join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("file.txt"), b"data").unwrap();
+    let destination = temp.path().join("destination");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_arsync"))
+        .arg(&source)
+        .arg(&destination)
+        .args(["-a", "--unprivileged-ownership", "fail"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(HINT));
+    assert!(!destination.exists());
+}